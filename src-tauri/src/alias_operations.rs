@@ -0,0 +1,256 @@
+// alias_operations.rs
+
+use crate::{ link_operations, search_index, local_operations::CONNECTION, models::AppError, models::Note };
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+
+/// The maximum number of aliases of a note.
+const MAX_ALIASES: usize = 20;
+
+/// The maximum length of an alias, the same as a title.
+const MAX_ALIAS_LENGTH: usize = 100;
+
+/// The maximum size of the aliases of a note once encoded, so they fit in the metadata of its object in a bucket,
+/// which is limited to 2 KB with the other fields.
+const MAX_ALIASES_BYTES: usize = 768;
+
+
+/// Retrieves the aliases of a local note, the other names it is found and linked by.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the aliases, sorted alphabetically.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_note_aliases(note_id: i64) -> Result<Vec<String>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    note_aliases(&conn, note_id)
+}
+
+
+/// Replaces the aliases of a local note, e.g. an abbreviation or a former title.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `aliases` - The aliases, up to 20 of up to 100 characters each (see `check_aliases`).
+///
+/// # Operation
+///
+/// The aliases are stored in the "note_aliases" table, and the note is reindexed so a search finds it by them. They
+/// are uploaded with the note the next time it is uploaded to a bucket.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the aliases are stored successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if the aliases are invalid, or if there is an
+/// issue with the database connection.
+pub fn set_note_aliases(note_id: i64, aliases: Vec<String>) -> Result<(), AppError> {
+    let aliases = check_aliases(&aliases)?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let note_exists: Option<i64> = conn.query_row("SELECT id FROM notes WHERE id = ?1", params![note_id], |row| row.get(0))
+        .optional()?;
+    if note_exists.is_none() {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+
+    let tx = conn.transaction()?;
+    store_aliases(&tx, note_id, &aliases)?;
+    tx.commit()?;
+
+    search_index::schedule_reindex(note_id);
+    Ok(())
+}
+
+
+/// Finds the local note a wikilink points to, e.g. "[[Meeting notes]]", by its title or one of its aliases.
+///
+/// # Arguments
+///
+/// * `name` - The name in the wikilink, without the brackets. A heading or a label after "#" or "|" is ignored,
+///   e.g. "Meeting notes#Actions" or "Meeting notes|the meeting".
+///
+/// # Operation
+///
+/// Case is ignored. A note whose title is the name comes before a note with the name as an alias, and among several,
+/// the oldest note comes first.
+///
+/// # Returns
+///
+/// Returns the ID of the note, or `None` if no note has the name.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn resolve_wikilink(name: &str) -> Result<Option<i64>, String> {
+    let name = link_operations::link_target(name);
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    resolve_name(&conn, name)
+}
+
+
+/// Finds the local note with a name, its title or one of its aliases, like `resolve_wikilink`.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `name` - The name, whatever its case.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn resolve_name(conn: &Connection, name: &str) -> Result<Option<i64>, String> {
+    conn.query_row(
+        "SELECT id FROM (
+            SELECT id, 0 AS by_alias FROM notes WHERE title = ?1 COLLATE NOCASE AND deleted_at IS NULL
+            UNION ALL
+            SELECT notes.id, 1 FROM note_aliases JOIN notes ON notes.id = note_aliases.note_id WHERE note_aliases.alias = ?1 COLLATE NOCASE AND notes.deleted_at IS NULL
+        ) ORDER BY by_alias, id LIMIT 1",
+        params![name.trim()],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Checks the aliases of a note and cleans them up.
+///
+/// # Arguments
+///
+/// * `aliases` - The aliases. They are trimmed, and the empty ones and the duplicates, whatever their case, are dropped.
+///
+/// # Returns
+///
+/// Returns the cleaned aliases, in their order.
+///
+/// # Errors
+///
+/// This function will return an error if there are more than 20 aliases, if one is longer than 100 characters, or
+/// if they are too long together to be stored in the metadata of an object.
+pub fn check_aliases(aliases: &[String]) -> Result<Vec<String>, String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for alias in aliases.iter().map(|alias| alias.trim()).filter(|alias| !alias.is_empty()) {
+        if alias.chars().count() > MAX_ALIAS_LENGTH {
+            return Err(format!("Alias '{}' is too long", alias));
+        }
+        if !cleaned.iter().any(|existing| existing.to_lowercase() == alias.to_lowercase()) {
+            cleaned.push(alias.to_string());
+        }
+    }
+    if cleaned.len() > MAX_ALIASES {
+        return Err(format!("A note can have at most {} aliases", MAX_ALIASES));
+    }
+    if serde_json::to_string(&cleaned).map_err(|e| e.to_string())?.len() > MAX_ALIASES_BYTES {
+        return Err("The aliases are too long together".to_string());
+    }
+    Ok(cleaned)
+}
+
+
+/// Replaces the aliases of a note in the database, e.g. when it is created.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `aliases` - The aliases, checked with `check_aliases`.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn store_aliases(conn: &Connection, note_id: i64, aliases: &[String]) -> Result<(), String> {
+    conn.execute("DELETE FROM note_aliases WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    for alias in aliases {
+        conn.execute("INSERT INTO note_aliases (note_id, alias) VALUES (?1, ?2)", params![note_id, alias])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+
+/// Reads the aliases of every local note, by note ID.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn read_aliases(conn: &Connection) -> Result<HashMap<i64, Vec<String>>, String> {
+    let mut stmt = conn.prepare("SELECT note_id, alias FROM note_aliases ORDER BY alias").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))).map_err(|e| e.to_string())?;
+
+    let mut aliases: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in rows {
+        let (note_id, alias) = row.map_err(|e| e.to_string())?;
+        aliases.entry(note_id).or_default().push(alias);
+    }
+    Ok(aliases)
+}
+
+
+/// Fills in the aliases of notes read from the database.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `notes` - The notes, built with `local_operations::note_from_row`.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn fill_aliases(conn: &Connection, notes: &mut [Note]) -> Result<(), String> {
+    // A single note is read often, e.g. when it is opened, so its aliases are read alone
+    if let [note] = notes {
+        note.aliases = note.id.map(|id| note_aliases(conn, id)).transpose()?.unwrap_or_default();
+        return Ok(());
+    }
+
+    let mut aliases = read_aliases(conn)?;
+    for note in notes {
+        note.aliases = note.id.and_then(|id| aliases.remove(&id)).unwrap_or_default();
+    }
+    Ok(())
+}
+
+
+/// Removes the aliases of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_aliases(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_aliases WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Reads the aliases of a note, sorted alphabetically.
+fn note_aliases(conn: &Connection, note_id: i64) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare("SELECT alias FROM note_aliases WHERE note_id = ?1 ORDER BY alias").map_err(|e| e.to_string())?;
+    let aliases = stmt.query_map(params![note_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(aliases)
+}
@@ -0,0 +1,190 @@
+// api_tokens.rs
+
+use crate::{ commands, local_operations::CONNECTION, models::ApiScope, models::ApiToken, models::AppError, models::CreatedApiToken };
+use base64::{Engine as _, engine::general_purpose};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use rusqlite::{params, OptionalExtension, Row};
+
+
+/// The prefix of the secrets of the tokens, so they are recognized when they leak, e.g. in a log.
+const SECRET_PREFIX: &str = "cnt_";
+
+/// The number of random bytes of a secret.
+const SECRET_BYTES: usize = 32;
+
+/// The columns of the "api_tokens" table read into an `ApiToken`, in order.
+const TOKEN_COLUMNS: &str = "id, name, scopes, created_at, expires_at, last_used_at, revoked_at";
+
+
+/// Creates a token for the local HTTP API or a plugin, which can only run the commands its scopes allow.
+///
+/// # Arguments
+///
+/// * `name` - The name of the integration the token is given to, e.g. "Zapier".
+/// * `scopes` - The scopes of the token: read-only, read-write, which also allows reading, or admin, which allows
+///   every command (see `commands::required_scope`).
+/// * `expires_at` - When the token expires, as a UNIX timestamp, or `None` for a token that expires only when it is revoked.
+///
+/// # Operation
+///
+/// Only a hash of the secret is stored, so the secret cannot be shown again.
+///
+/// # Returns
+///
+/// Returns a `CreatedApiToken` with the token and its secret.
+///
+/// # Errors
+///
+/// This function will return an error if the name is blank, if there is no scope, if the expiry is in the past,
+/// or if there is an issue with the database connection.
+pub fn create_api_token(name: &str, scopes: Vec<ApiScope>, expires_at: Option<i64>) -> Result<CreatedApiToken, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The name of a token should not be empty".to_string());
+    }
+    if scopes.is_empty() {
+        return Err("A token should have at least one scope".to_string());
+    }
+    let now = chrono::Utc::now().timestamp();
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err("The expiry of a token should be in the future".to_string());
+    }
+    let mut scopes = scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    let mut bytes = [0u8; SECRET_BYTES];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| "Failed to generate the token")?;
+    let secret = format!("{}{}", SECRET_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(bytes));
+
+    let json = serde_json::to_string(&scopes).map_err(|e| e.to_string())?;
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO api_tokens (name, secret_hash, scopes, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, hash(&secret), json, now, expires_at],
+    ).map_err(|e| e.to_string())?;
+
+    let token = ApiToken {
+        id: conn.last_insert_rowid(),
+        name: name.to_string(),
+        scopes,
+        created_at: now,
+        expires_at,
+        last_used_at: None,
+        revoked_at: None,
+    };
+    Ok(CreatedApiToken { token, secret })
+}
+
+
+/// Lists the API tokens, without their secrets.
+///
+/// # Returns
+///
+/// Returns a vector of `ApiToken`, the most recent first, including the revoked and expired ones.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_api_tokens() -> Result<Vec<ApiToken>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM api_tokens ORDER BY created_at DESC, id DESC", TOKEN_COLUMNS))
+        .map_err(|e| e.to_string())?;
+    let tokens = stmt.query_map([], token_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(tokens)
+}
+
+
+/// Revokes an API token, which can no longer run any command.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the token.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the token is revoked, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the token does not exist or is already revoked, or if there is an issue
+/// with the database connection.
+pub fn revoke_api_token(id: i64) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let revoked = conn.execute(
+        "UPDATE api_tokens SET revoked_at = ?1 WHERE id = ?2 AND revoked_at IS NULL",
+        params![chrono::Utc::now().timestamp(), id],
+    )?;
+    if revoked == 0 {
+        return Err(AppError::NotFound(format!("API token {} not found or already revoked", id)));
+    }
+    Ok(())
+}
+
+
+/// Checks that a token may run a command, and records its use.
+///
+/// # Arguments
+///
+/// * `secret` - The secret of the token.
+/// * `command` - The ID of the command.
+///
+/// # Returns
+///
+/// Returns the `ApiToken` if it may run the command.
+///
+/// # Errors
+///
+/// This function will return an error if the token is unknown, revoked, or expired, if the command cannot be run
+/// with a token, if the scopes of the token do not allow it, or if there is an issue with the database connection.
+pub fn authorize(secret: &str, command: &str) -> Result<ApiToken, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let token = conn.query_row(
+        &format!("SELECT {} FROM api_tokens WHERE secret_hash = ?1", TOKEN_COLUMNS),
+        params![hash(secret.trim())],
+        token_from_row,
+    ).optional().map_err(|e| e.to_string())?
+        .ok_or("Invalid API token".to_string())?;
+
+    let now = chrono::Utc::now().timestamp();
+    if token.revoked_at.is_some() {
+        return Err(format!("The API token '{}' was revoked", token.name));
+    }
+    if token.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(format!("The API token '{}' expired", token.name));
+    }
+    let required = commands::required_scope(command).ok_or(format!("The command '{}' cannot be run with an API token", command))?;
+    if !token.scopes.iter().any(|scope| *scope >= required) {
+        return Err(format!("The API token '{}' is not allowed to run '{}', which needs the {:?} scope", token.name, command, required));
+    }
+
+    conn.execute("UPDATE api_tokens SET last_used_at = ?1 WHERE id = ?2", params![now, token.id])
+        .map_err(|e| e.to_string())?;
+    Ok(token)
+}
+
+
+/// Reads an `ApiToken` from a row of `TOKEN_COLUMNS`.
+fn token_from_row(row: &Row) -> rusqlite::Result<ApiToken> {
+    let scopes: String = row.get(2)?;
+    Ok(ApiToken {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        // A token with unreadable scopes is allowed nothing
+        scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+        created_at: row.get(3)?,
+        expires_at: row.get(4)?,
+        last_used_at: row.get(5)?,
+        revoked_at: row.get(6)?,
+    })
+}
+
+
+/// Hashes the secret of a token, as stored.
+fn hash(secret: &str) -> String {
+    digest::digest(&digest::SHA256, secret.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,278 @@
+// app_paths.rs
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::PathResolver;
+
+
+/// The identifier of the application, as in "tauri.conf.json". It names the directories of the application the way
+/// the path resolver of Tauri does, when they are needed before the application is set up.
+const APP_IDENTIFIER: &str = "com.customnotes.rust";
+
+/// The name of the database file.
+const DATABASE_FILE: &str = "notes.db";
+
+/// The suffixes of the files SQLite keeps next to the database, which are moved along with it.
+const DATABASE_SIDECARS: [&str; 3] = ["-journal", "-wal", "-shm"];
+
+/// The name of the directory of the attachment files, in the data directory.
+const ATTACHMENTS_DIR: &str = "attachments";
+
+/// The name of the directory of the local search index, in the data directory.
+const SEARCH_INDEX_DIR: &str = "search_index";
+
+/// The name of the directory of the images of the notes exported as cards, in the cache directory.
+const CARDS_DIR: &str = "cards";
+
+/// The name of the directory where the attachment files were stored in the home directory.
+const LEGACY_ATTACHMENTS_DIR: &str = "notes_attachments";
+
+/// The name of the directory of the benchmark measures, in the cache directory.
+#[cfg(feature = "benchmarks")]
+const BENCHMARKS_DIR: &str = "benchmarks";
+
+/// The name of the directory where the benchmark measures were stored in the home directory.
+#[cfg(feature = "benchmarks")]
+const LEGACY_BENCHMARKS_DIR: &str = "notes_benchmarks";
+
+
+/// The directories of the application.
+struct AppDirs {
+    /// The data of the user: the database and the attachment files.
+    data: PathBuf,
+    /// The configuration of this installation, such as the identifier of the device.
+    config: PathBuf,
+    /// The data that can be regenerated, such as the cards of the notes and the benchmark measures.
+    cache: PathBuf,
+}
+
+
+/// The directories of the application, resolved once.
+static APP_DIRS: OnceLock<AppDirs> = OnceLock::new();
+
+
+/// Resolves the directories of the application with the path resolver of Tauri. It is called once the application
+/// is set up, before the database is opened.
+///
+/// # Arguments
+///
+/// * `resolver` - The path resolver of the application.
+pub fn init(resolver: &PathResolver) {
+    let default_dirs = default_dirs();
+    let app_dirs = AppDirs {
+        data: resolver.app_data_dir().unwrap_or(default_dirs.data),
+        config: resolver.app_config_dir().unwrap_or(default_dirs.config),
+        cache: resolver.app_cache_dir().unwrap_or(default_dirs.cache),
+    };
+    if APP_DIRS.set(app_dirs).is_err() {
+        eprintln!("The directories of the application were resolved before the application was set up");
+    }
+}
+
+
+/// Returns the path of the database, in the data directory of the application, e.g. "%APPDATA%\com.customnotes.rust"
+/// on Windows, "~/Library/Application Support/com.customnotes.rust" on macOS, or "~/.local/share/com.customnotes.rust"
+/// on Linux.
+///
+/// # Operation
+///
+/// * The data directory is created if needed.
+/// * A database left in the home directory by a previous version is moved there with its journal, unless the data
+///   directory already has one. If it cannot be moved, it is used where it is and the move is tried again on the next start.
+///
+/// # Errors
+///
+/// This function will return an error if the data directory cannot be created.
+pub fn database_path() -> Result<PathBuf, String> {
+    let path = data_dir()?.join(DATABASE_FILE);
+    let Some(legacy_path) = legacy_path(DATABASE_FILE) else {
+        return Ok(path);
+    };
+    if path.exists() || !legacy_path.exists() {
+        return Ok(path);
+    }
+
+    // The journal is moved first, so the database is never opened without it
+    let sidecar = |path: &Path, suffix: &str| PathBuf::from(format!("{}{}", path.display(), suffix));
+    let mut moved_sidecars = Vec::new();
+    let mut moved = true;
+    for suffix in DATABASE_SIDECARS.into_iter().filter(|suffix| sidecar(&legacy_path, suffix).exists()) {
+        if let Err(e) = move_path(&sidecar(&legacy_path, suffix), &sidecar(&path, suffix)) {
+            eprintln!("Failed to move {}: {}", sidecar(&legacy_path, suffix).display(), e);
+            moved = false;
+            break;
+        }
+        moved_sidecars.push(suffix);
+    }
+    if moved {
+        if let Err(e) = move_path(&legacy_path, &path) {
+            eprintln!("Failed to move {}: {}", legacy_path.display(), e);
+            moved = false;
+        }
+    }
+    if moved {
+        eprintln!("Moved the database from {} to {}", legacy_path.display(), path.display());
+        return Ok(path);
+    }
+
+    // Put the journal back next to the database left where it was
+    for suffix in moved_sidecars {
+        if let Err(e) = move_path(&sidecar(&path, suffix), &sidecar(&legacy_path, suffix)) {
+            eprintln!("Failed to move back {}: {}", sidecar(&path, suffix).display(), e);
+        }
+    }
+    Ok(legacy_path)
+}
+
+
+/// Returns the directory of the attachment files, in the data directory of the application.
+///
+/// The directory left in the home directory by a previous version is moved there, unless the data directory already
+/// has one. If it cannot be moved, it is used where it is.
+///
+/// # Errors
+///
+/// This function will return an error if the data directory cannot be created.
+pub fn attachments_dir() -> Result<PathBuf, String> {
+    Ok(relocate(LEGACY_ATTACHMENTS_DIR, data_dir()?.join(ATTACHMENTS_DIR)))
+}
+
+
+/// Returns the directory of the local search index, in the data directory of the application. It is not created,
+/// since the index is kept in memory while a passphrase is set (see `search_index`).
+///
+/// # Errors
+///
+/// This function will return an error if the data directory cannot be created.
+pub fn search_index_dir() -> Result<PathBuf, String> {
+    Ok(data_dir()?.join(SEARCH_INDEX_DIR))
+}
+
+
+/// Returns the configuration directory of the application, which belongs to the user of the operating system even
+/// when the data directory is shared, creating it if needed.
+///
+/// # Errors
+///
+/// This function will return an error if the configuration directory cannot be created.
+pub fn config_dir() -> Result<PathBuf, String> {
+    let config_dir = &app_dirs().config;
+    std::fs::create_dir_all(config_dir).map_err(|e| format!("Failed to create {}: {}", config_dir.display(), e))?;
+    Ok(config_dir.clone())
+}
+
+
+/// Returns the directory of the images of the notes exported as cards (see `card_operations`), in the cache
+/// directory of the application, creating it if needed.
+///
+/// # Errors
+///
+/// This function will return an error if the directory cannot be created.
+pub fn cards_dir() -> Result<PathBuf, String> {
+    let cards_dir = app_dirs().cache.join(CARDS_DIR);
+    std::fs::create_dir_all(&cards_dir).map_err(|e| format!("Failed to create {}: {}", cards_dir.display(), e))?;
+    Ok(cards_dir)
+}
+
+
+/// Returns the directory of the benchmark measures, in the cache directory of the application.
+///
+/// The directory left in the home directory by a previous version is moved there, unless the cache directory already has one.
+///
+/// # Errors
+///
+/// This function will return an error if the cache directory cannot be created.
+#[cfg(feature = "benchmarks")]
+pub fn benchmarks_dir() -> Result<PathBuf, String> {
+    let cache_dir = &app_dirs().cache;
+    std::fs::create_dir_all(cache_dir).map_err(|e| format!("Failed to create {}: {}", cache_dir.display(), e))?;
+    Ok(relocate(LEGACY_BENCHMARKS_DIR, cache_dir.join(BENCHMARKS_DIR)))
+}
+
+
+/// Returns the data directory of the application, creating it if needed.
+fn data_dir() -> Result<PathBuf, String> {
+    let data_dir = &app_dirs().data;
+    std::fs::create_dir_all(data_dir).map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+    Ok(data_dir.clone())
+}
+
+
+/// Returns the directories of the application, resolving them like the path resolver of Tauri if it was not given yet.
+fn app_dirs() -> &'static AppDirs {
+    APP_DIRS.get_or_init(default_dirs)
+}
+
+
+/// Returns the directories the path resolver of Tauri resolves: the data, configuration, and cache directories of the platform,
+/// followed by the identifier of the application.
+fn default_dirs() -> AppDirs {
+    let platform_dir = |dir: Option<PathBuf>| dir.or_else(dirs::home_dir).unwrap_or_default().join(APP_IDENTIFIER);
+    AppDirs {
+        data: platform_dir(dirs::data_dir()),
+        config: platform_dir(dirs::config_dir()),
+        cache: platform_dir(dirs::cache_dir()),
+    }
+}
+
+
+/// Returns the path of a file or directory in the home directory, where a previous version stored it.
+fn legacy_path(name: &str) -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(name))
+}
+
+
+/// Moves a file or directory left in the home directory by a previous version to its path, unless something is
+/// already there, and returns where it is.
+fn relocate(legacy_name: &str, path: PathBuf) -> PathBuf {
+    let Some(legacy_path) = legacy_path(legacy_name) else {
+        return path;
+    };
+    if path.exists() || !legacy_path.exists() {
+        return path;
+    }
+
+    match move_path(&legacy_path, &path) {
+        Ok(()) => {
+            eprintln!("Moved {} to {}", legacy_path.display(), path.display());
+            path
+        },
+        Err(e) => {
+            eprintln!("Failed to move {}: {}", legacy_path.display(), e);
+            legacy_path
+        },
+    }
+}
+
+
+/// Moves a file or directory, copying it when it cannot be renamed, e.g. to another drive.
+///
+/// A copy left unfinished is removed, so the source stays the only complete one.
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    let copied = if from.is_dir() { copy_dir(from, to) } else { std::fs::copy(from, to).map(|_| ()) };
+    if let Err(e) = copied {
+        let _ = if from.is_dir() { std::fs::remove_dir_all(to) } else { std::fs::remove_file(to) };
+        return Err(e);
+    }
+    if from.is_dir() { std::fs::remove_dir_all(from) } else { std::fs::remove_file(from) }
+}
+
+
+/// Copies a directory with its content.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
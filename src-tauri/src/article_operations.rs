@@ -0,0 +1,317 @@
+// article_operations.rs
+
+use crate::{ attachment_operations, delta, local_operations, note_events, notifier, proxy };
+use crate::local_operations::CONNECTION;
+use crate::models::{Note, NoteSource};
+use reqwest::Url;
+use rusqlite::{params, Connection, OptionalExtension};
+use scraper::{ElementRef, Html, Node, Selector};
+use serde_json::json;
+use std::time::Duration;
+
+
+/// The elements left out of an article, since they hold navigation, scripts, or forms rather than its content.
+const SKIPPED_ELEMENTS: [&str; 14] = [
+    "script", "style", "noscript", "nav", "aside", "footer", "header", "form", "button", "iframe", "svg", "figcaption", "template", "object",
+];
+
+/// The maximum number of images of an article saved as attachments. The other images keep their web address.
+const MAX_IMAGES: usize = 50;
+
+/// The user agent sent with the requests, since some sites reject requests without one.
+const USER_AGENT: &str = concat!("CustomNotes/", env!("CARGO_PKG_VERSION"));
+
+
+/// Saves a web article as a local note, to read it later.
+///
+/// # Arguments
+///
+/// * `url` - The address of the page.
+///
+/// # Operation
+///
+/// * The page is downloaded and its main content is extracted: the `article` element if there is one, otherwise
+///   the element holding the most paragraph text. Navigation, scripts, and forms are left out.
+/// * The content is converted to Markdown. Its images are downloaded and saved as attachments, the Markdown
+///   referring to them by file name. An image that cannot be downloaded keeps its web address.
+/// * The title of the note is the title of the article. The address of the page, its author, and the name of the
+///   site are stored in the "note_sources" table (see `get_note_source`).
+///
+/// It blocks until the page and its images are downloaded, so it must not be called from the async runtime directly.
+///
+/// # Returns
+///
+/// Returns the ID of the new note, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the address is invalid, if the page cannot be downloaded, if no content
+/// is found, if the article is too long for a note, or if there is an issue with the database connection.
+pub fn save_url_as_note(url: &str) -> Result<i64, String> {
+    let page_url = Url::parse(url.trim()).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    if page_url.scheme() != "http" && page_url.scheme() != "https" {
+        return Err(format!("Invalid URL {}: only web pages can be saved", url));
+    }
+
+    let client = proxy::blocking_client_builder()?
+        .user_agent(USER_AGENT)
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let page = client.get(page_url.clone())
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| format!("Failed to download {}: {}", page_url, e))?;
+
+    // Extract the metadata and the content of the article, whose images are renamed after their attachments
+    let (title, author, site_name, mut html, images) = {
+        let document = Html::parse_document(&page);
+        let title = meta_content(&document, &["meta[property='og:title']", "meta[name='twitter:title']"])
+            .or_else(|| first_text(&document, "title"))
+            .unwrap_or_else(|| page_url.host_str().unwrap_or_default().to_string());
+        let author = meta_content(&document, &["meta[name='author']", "meta[property='article:author']"])
+            .or_else(|| first_text(&document, "[rel='author'], .byline, .author"));
+        let site_name = meta_content(&document, &["meta[property='og:site_name']"]);
+
+        let content = main_content(&document).ok_or("No content found".to_string())?;
+        let mut html = String::new();
+        let mut images = Vec::new();
+        clean_html(content, &page_url, &mut images, &mut html);
+        (title, author, site_name, html, images)
+    };
+
+    // Download the images first, so the note is only created once everything is ready
+    let mut media = Vec::new();
+    for (image_url, file_name) in images {
+        let result = client.get(image_url.clone())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.bytes());
+        match result {
+            Ok(data) => media.push((file_name, data.to_vec())),
+            Err(e) => {
+                // The image keeps its web address
+                eprintln!("Failed to download image {}: {}", image_url, e);
+                html = html.replace(&format!("src=\"{}\"", escape(&file_name)), &format!("src=\"{}\"", escape(image_url.as_str())));
+            },
+        }
+    }
+
+    let markdown = html2md::parse_html(&html);
+    if markdown.trim().is_empty() {
+        return Err("No content found".to_string());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(title.trim()),
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", markdown.trim_end()) })]),
+        nonce: None,
+        created_at: now,
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status: None,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: Vec::new(),
+    };
+    local_operations::validate_params(note.clone())?;
+
+    // Create the note, its images, and its source in a single transaction
+    let note_id = {
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let note_id = local_operations::insert_note(&tx, &note)?;
+        note_events::note_saved(&tx, note_id, &note.content)?;
+        for (file_name, data) in &media {
+            attachment_operations::store_attachment(&tx, note_id, file_name, data)?;
+        }
+        tx.execute(
+            "INSERT INTO note_sources (note_id, url, author, site_name, saved_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![note_id, page_url.as_str(), author, site_name, now],
+        ).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        note_id
+    };
+
+    // Send a desktop notification
+    notifier::notify("Article saved", &format!("Article '{}' was saved as a note.", note.title));
+
+    Ok(note_id)
+}
+
+
+/// Retrieves the web page a local note was saved from.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the `NoteSource` of the note, or `None` if it was not saved from a web page.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_note_source(note_id: i64) -> Result<Option<NoteSource>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row(
+        "SELECT note_id, url, author, site_name, saved_at FROM note_sources WHERE note_id = ?1",
+        params![note_id],
+        |row| Ok(NoteSource {
+            note_id: row.get(0)?,
+            url: row.get(1)?,
+            author: row.get(2)?,
+            site_name: row.get(3)?,
+            saved_at: row.get(4)?,
+        }),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Removes the source of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_source(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_sources WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Finds the main content of a page: its `article` element, or else the element with the most paragraph text.
+fn main_content(document: &Html) -> Option<ElementRef<'_>> {
+    let article = Selector::parse("article").unwrap();
+    let longest_article = document.select(&article).max_by_key(|element| text_length(*element));
+    if longest_article.is_some() {
+        return longest_article;
+    }
+
+    // Score each container by the text of the paragraphs directly inside it
+    let containers = Selector::parse("main, [role='main'], section, div, td").unwrap();
+    let best = document.select(&containers)
+        .map(|element| {
+            let paragraph_text: usize = element.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|child| child.value().name() == "p")
+                .map(text_length)
+                .sum();
+            (paragraph_text, element)
+        })
+        .filter(|(paragraph_text, _)| *paragraph_text > 0)
+        .max_by_key(|(paragraph_text, _)| *paragraph_text)
+        .map(|(_, element)| element);
+
+    best.or_else(|| document.select(&Selector::parse("body").unwrap()).next())
+}
+
+
+/// Writes the HTML of an element without the skipped elements and attributes, resolving its links against the
+/// address of the page. Its images are renamed after the attachments they are saved as, and listed with their address.
+fn clean_html(element: ElementRef, page_url: &Url, images: &mut Vec<(Url, String)>, html: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => html.push_str(&escape(text)),
+            Node::Element(child_element) => {
+                let name = child_element.name();
+                if SKIPPED_ELEMENTS.contains(&name) {
+                    continue;
+                }
+                let Some(child_ref) = ElementRef::wrap(child) else {
+                    continue;
+                };
+
+                if name == "img" {
+                    let source = child_element.attr("src").or(child_element.attr("data-src"));
+                    let Some(image_url) = source.and_then(|source| page_url.join(source).ok()) else {
+                        continue;
+                    };
+                    let alt = escape(child_element.attr("alt").unwrap_or_default());
+                    if images.len() < MAX_IMAGES && image_url.scheme().starts_with("http") {
+                        let file_name = image_file_name(&image_url, images.len() + 1);
+                        html.push_str(&format!("<img src=\"{}\" alt=\"{}\">", escape(&file_name), alt));
+                        images.push((image_url, file_name));
+                    } else {
+                        html.push_str(&format!("<img src=\"{}\" alt=\"{}\">", escape(image_url.as_str()), alt));
+                    }
+                    continue;
+                }
+
+                html.push('<');
+                html.push_str(name);
+                if name == "a" {
+                    if let Some(href) = child_element.attr("href").and_then(|href| page_url.join(href).ok()) {
+                        html.push_str(&format!(" href=\"{}\"", escape(href.as_str())));
+                    }
+                }
+                html.push('>');
+                clean_html(child_ref, page_url, images, html);
+                html.push_str(&format!("</{}>", name));
+            },
+            _ => (),
+        }
+    }
+}
+
+
+/// Names the attachment of an image after its position in the article, keeping the extension of its address.
+fn image_file_name(image_url: &Url, position: usize) -> String {
+    let extension = image_url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|file_name| file_name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase())
+        .filter(|extension| !extension.is_empty() && extension.len() <= 4 && extension.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    match extension {
+        Some(extension) => format!("image-{}.{}", position, extension),
+        None => format!("image-{}", position),
+    }
+}
+
+
+/// Returns the content of the first of the meta tags found, if not empty.
+fn meta_content(document: &Html, selectors: &[&str]) -> Option<String> {
+    selectors.iter()
+        .filter_map(|selector| Selector::parse(selector).ok())
+        .filter_map(|selector| document.select(&selector).next()?.attr("content").map(|content| content.trim().to_string()))
+        .find(|content| !content.is_empty())
+}
+
+
+/// Returns the text of the first element matching a selector, if not empty.
+fn first_text(document: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).ok()?;
+    let text = document.select(&selector).next()?.text().collect::<String>();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+
+/// Returns the length of the text of an element.
+fn text_length(element: ElementRef) -> usize {
+    element.text().map(|text| text.trim().len()).sum()
+}
+
+
+/// Escapes the characters with a special meaning in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,594 @@
+// attachment_operations.rs
+
+use crate::{ app_paths, search_index, text_extraction, local_operations::CONNECTION, models::AppError, models::Attachment, models::AttachmentData };
+use base64::{Engine as _, engine::general_purpose};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+
+/// The maximum size of an attachment, in bytes.
+const MAX_ATTACHMENT_SIZE: u64 = 50 * 1024 * 1024;
+
+/// The maximum size of the annotation of an attachment, in bytes.
+const MAX_ANNOTATION_SIZE: usize = 10 * 1024 * 1024;
+
+/// The extension added to the name of the file of an attachment to name the sidecar file of its annotation.
+const ANNOTATION_EXTENSION: &str = "annotation.json";
+
+/// The columns selected to build an `Attachment` with `attachment_from_row`, in order.
+const ATTACHMENT_COLUMNS: &str = "id, note_id, file_name, mime_type, size, created_at, annotated_at";
+
+
+/// Returns the directory where the attachment files are stored, in the data directory of the application, creating it if needed.
+pub fn attachments_dir() -> Result<PathBuf, String> {
+    let dir = app_paths::attachments_dir()?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+
+/// Attaches a file to a local note.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `path` - The path of the file to attach.
+///
+/// # Operation
+///
+/// * The file is copied into the attachments directory under a random name, so the original can be moved or deleted.
+/// * Its MIME type is guessed from its extension. The text of a PDF is extracted in the background (see `text_extraction`).
+///
+/// # Returns
+///
+/// Returns the new `Attachment`, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if the file cannot be read or is larger than 50 MB,
+/// or if there is an issue with the database connection.
+pub fn add_attachment(note_id: i64, path: &str) -> Result<Attachment, AppError> {
+    let source = Path::new(path);
+    let file_name = source.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or(format!("{} is not a file", path))?;
+    let size = std::fs::metadata(source).map_err(|e| format!("Failed to read {}: {}", path, e))?.len();
+    if size > MAX_ATTACHMENT_SIZE {
+        return Err(AppError::Validation(format!("{} is larger than 50 MB", file_name)));
+    }
+
+    let extension = source.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let stored_name = if extension.is_empty() {
+        Uuid::new_v4().to_string()
+    } else {
+        format!("{}.{}", Uuid::new_v4(), extension)
+    };
+
+    let conn = CONNECTION.lock().unwrap();
+    let note_exists: Option<i64> = conn.query_row("SELECT id FROM notes WHERE id = ?1", params![note_id], |row| row.get(0))
+        .optional()?;
+    if note_exists.is_none() {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+
+    let stored_path = attachments_dir()?.join(&stored_name);
+    std::fs::copy(source, &stored_path).map_err(|e| format!("Failed to copy {}: {}", path, e))?;
+
+    let created_at = chrono::Utc::now().timestamp();
+    let mime_type = mime_type(&extension);
+    let result = conn.execute(
+        "INSERT INTO attachments (note_id, file_name, mime_type, size, stored_name, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![note_id, file_name, mime_type, size as i64, stored_name, created_at],
+    );
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&stored_path);
+        return Err(e.into());
+    }
+    let id = conn.last_insert_rowid();
+    text_extraction::queue_extraction(&conn, id, mime_type)?;
+
+    Ok(Attachment {
+        id,
+        note_id,
+        file_name,
+        mime_type: mime_type.to_string(),
+        size: size as i64,
+        created_at,
+        annotated_at: None,
+    })
+}
+
+
+/// Attaches data received from elsewhere, e.g. the attachment of an email, to a local note.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `file_name` - The name of the file shown to the user. Its MIME type is guessed from its extension, and the text
+///   of a PDF is extracted in the background (see `text_extraction`).
+/// * `data` - The content of the file.
+///
+/// # Returns
+///
+/// Returns the new `Attachment`, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the data is larger than 50 MB, if the file cannot be written,
+/// or if there is an issue with the database connection.
+pub fn store_attachment(conn: &Connection, note_id: i64, file_name: &str, data: &[u8]) -> Result<Attachment, String> {
+    if data.len() as u64 > MAX_ATTACHMENT_SIZE {
+        return Err(format!("{} is larger than 50 MB", file_name));
+    }
+
+    let extension = Path::new(file_name).extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let stored_name = if extension.is_empty() {
+        Uuid::new_v4().to_string()
+    } else {
+        format!("{}.{}", Uuid::new_v4(), extension)
+    };
+
+    let stored_path = attachments_dir()?.join(&stored_name);
+    std::fs::write(&stored_path, data).map_err(|e| format!("Failed to write {}: {}", file_name, e))?;
+
+    let created_at = chrono::Utc::now().timestamp();
+    let mime_type = mime_type(&extension);
+    let result = conn.execute(
+        "INSERT INTO attachments (note_id, file_name, mime_type, size, stored_name, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![note_id, file_name, mime_type, data.len() as i64, stored_name, created_at],
+    );
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&stored_path);
+        return Err(e.to_string());
+    }
+    let id = conn.last_insert_rowid();
+    text_extraction::queue_extraction(conn, id, mime_type)?;
+
+    Ok(Attachment {
+        id,
+        note_id,
+        file_name: file_name.to_string(),
+        mime_type: mime_type.to_string(),
+        size: data.len() as i64,
+        created_at,
+        annotated_at: None,
+    })
+}
+
+
+/// Retrieves the attachments of a local note.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the attachments of the note, oldest first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_note_attachments(note_id: i64) -> Result<Vec<Attachment>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM attachments WHERE note_id = ?1 ORDER BY id", ATTACHMENT_COLUMNS))
+        .map_err(|e| e.to_string())?;
+    let attachment_iter = stmt.query_map(params![note_id], attachment_from_row).map_err(|e| e.to_string())?;
+
+    attachment_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Retrieves an attachment with its content, e.g. to show a cover image.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment.
+///
+/// # Returns
+///
+/// Returns an `AttachmentData` with the attachment and its content encoded in base64.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, if its file cannot be read,
+/// or if there is an issue with the database connection.
+pub fn get_attachment_data(id: i64) -> Result<AttachmentData, String> {
+    let (attachment, data) = read_attachment(id)?;
+
+    Ok(AttachmentData {
+        attachment,
+        data: general_purpose::STANDARD.encode(data),
+    })
+}
+
+
+/// Reads an attachment with the raw content of its file.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment.
+///
+/// # Returns
+///
+/// Returns the `Attachment` and its content.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, if its file cannot be read,
+/// or if there is an issue with the database connection.
+pub fn read_attachment(id: i64) -> Result<(Attachment, Vec<u8>), AppError> {
+    let (attachment, stored_name) = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {}, stored_name FROM attachments WHERE id = ?1", ATTACHMENT_COLUMNS),
+            params![id],
+            |row| Ok((attachment_from_row(row)?, row.get::<_, String>(7)?)),
+        ).optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?
+    };
+
+    let data = std::fs::read(attachments_dir()?.join(&stored_name))
+        .map_err(|e| format!("Failed to read {}: {}", attachment.file_name, e))?;
+
+    Ok((attachment, data))
+}
+
+
+/// Deletes an attachment and its file.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment.
+///
+/// # Operation
+///
+/// The notes using the attachment as their cover lose their cover.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the attachment is deleted successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist or if there is an issue with the database connection.
+pub fn delete_attachment(id: i64) -> Result<(), AppError> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction()?;
+
+    let (note_id, stored_name): (i64, String) = tx.query_row(
+        "SELECT note_id, stored_name FROM attachments WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?;
+    tx.execute("DELETE FROM attachment_texts WHERE attachment_id = ?1", params![id])?;
+    tx.execute("DELETE FROM attachments WHERE id = ?1", params![id])?;
+    tx.execute("UPDATE notes SET cover_attachment_id = NULL WHERE cover_attachment_id = ?1", params![id])?;
+    tx.commit()?;
+
+    remove_stored_file(&stored_name);
+    // The extracted text of the attachment leaves the index with it
+    search_index::schedule_reindex(note_id);
+    Ok(())
+}
+
+
+/// Saves the annotation of an attachment, e.g. the pen strokes drawn over a PDF or an image.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment.
+/// * `annotation` - The annotation, as JSON whose format is up to the frontend. An empty annotation removes it.
+///
+/// # Operation
+///
+/// * The annotation is written to a sidecar file next to the file of the attachment, replacing the previous one.
+///   The file is written under a temporary name first, so a crash never leaves a partial annotation.
+/// * The sidecar file follows the attachment: it moves with it when its note is merged, and it is deleted with it.
+/// * The time of the change is stored in the "annotated_at" column of the attachment.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the annotation is saved successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, if the annotation is not valid JSON or is
+/// larger than 10 MB, if the sidecar file cannot be written, or if there is an issue with the database connection.
+pub fn save_annotation(id: i64, annotation: &str) -> Result<(), AppError> {
+    let annotation = annotation.trim();
+    if annotation.len() > MAX_ANNOTATION_SIZE {
+        return Err(AppError::Validation("The annotation is larger than 10 MB".to_string()));
+    }
+    if !annotation.is_empty() {
+        serde_json::from_str::<serde_json::Value>(annotation).map_err(|e| format!("Invalid annotation: {}", e))?;
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    let stored_name: String = conn.query_row("SELECT stored_name FROM attachments WHERE id = ?1", params![id], |row| row.get(0))
+        .optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?;
+    let annotation_path = attachments_dir()?.join(annotation_name(&stored_name));
+
+    if annotation.is_empty() {
+        match std::fs::remove_file(&annotation_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(AppError::Failed(format!("Failed to remove the annotation: {}", e))),
+            _ => (),
+        }
+        conn.execute("UPDATE attachments SET annotated_at = NULL WHERE id = ?1", params![id])?;
+        return Ok(());
+    }
+
+    let temp_path = annotation_path.with_extension("json.tmp");
+    std::fs::write(&temp_path, annotation).map_err(|e| format!("Failed to write the annotation: {}", e))?;
+    if let Err(e) = std::fs::rename(&temp_path, &annotation_path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(AppError::Failed(format!("Failed to write the annotation: {}", e)));
+    }
+    conn.execute("UPDATE attachments SET annotated_at = ?1 WHERE id = ?2", params![chrono::Utc::now().timestamp(), id])?;
+
+    Ok(())
+}
+
+
+/// Retrieves the annotation of an attachment.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment.
+///
+/// # Returns
+///
+/// Returns the annotation saved with `save_annotation`, or `None` if the attachment has no annotation.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, if the sidecar file cannot be read,
+/// or if there is an issue with the database connection.
+pub fn get_annotation(id: i64) -> Result<Option<String>, AppError> {
+    let (stored_name, annotated_at): (String, Option<i64>) = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            "SELECT stored_name, annotated_at FROM attachments WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?
+    };
+    if annotated_at.is_none() {
+        return Ok(None);
+    }
+
+    match std::fs::read_to_string(attachments_dir()?.join(annotation_name(&stored_name))) {
+        Ok(annotation) => Ok(Some(annotation)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AppError::Failed(format!("Failed to read the annotation: {}", e))),
+    }
+}
+
+
+/// Checks that an attachment can be the cover of a note: it must be an image attached to the note.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `note_id` - The ID of the note.
+/// * `attachment_id` - The ID of the attachment.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, belongs to another note, or is not an image.
+pub fn check_cover(conn: &Connection, note_id: i64, attachment_id: i64) -> Result<(), AppError> {
+    let attachment = conn.query_row(
+        &format!("SELECT {} FROM attachments WHERE id = ?1", ATTACHMENT_COLUMNS),
+        params![attachment_id],
+        attachment_from_row,
+    ).optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?;
+
+    if attachment.note_id != note_id {
+        return Err(AppError::Validation("The cover should be attached to the note".to_string()));
+    }
+    if !attachment.mime_type.starts_with("image/") {
+        return Err(AppError::Validation(format!("{} is not an image", attachment.file_name)));
+    }
+
+    Ok(())
+}
+
+
+/// Deletes the attachments of a note and their files after the note was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_attachments(conn: &Connection, note_id: i64) -> Result<(), String> {
+    let mut stmt = conn.prepare("SELECT stored_name FROM attachments WHERE note_id = ?1").map_err(|e| e.to_string())?;
+    let stored_names = stmt.query_map(params![note_id], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "DELETE FROM attachment_texts WHERE attachment_id IN (SELECT id FROM attachments WHERE note_id = ?1)",
+        params![note_id],
+    ).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM attachments WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    for stored_name in stored_names {
+        remove_stored_file(&stored_name);
+    }
+
+    Ok(())
+}
+
+
+/// Deletes every attachment and its file, after every local note was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_all_attachments(conn: &Connection) -> Result<(), String> {
+    conn.execute("DELETE FROM attachment_texts", []).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM attachments", []).map_err(|e| e.to_string())?;
+    if let Ok(dir) = attachments_dir() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            eprintln!("Failed to remove {}: {}", dir.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Finds the attachments left behind by a crash or an older version, and removes them if asked to (see `doctor`).
+///
+/// # Arguments
+///
+/// * `fix` - Whether to remove what was found.
+///
+/// # Operation
+///
+/// * The attachments of deleted notes and the attachments whose file is missing are found, and removed with
+///   their extracted text when fixing. The notes using them as their cover lose their cover.
+/// * The files of the attachments directory that belong to no attachment are found, and removed when fixing.
+///
+/// # Returns
+///
+/// Returns a description of each problem found, and the number of problems fixed.
+///
+/// # Errors
+///
+/// This function will return an error if the attachments directory cannot be read or if there is an issue with
+/// the database connection.
+pub fn repair_attachments(fix: bool) -> Result<(Vec<String>, usize), String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let rows: Vec<(i64, String, String, bool)> = {
+        let mut stmt = conn.prepare(
+            "SELECT attachments.id, attachments.file_name, attachments.stored_name, notes.id IS NULL FROM attachments
+            LEFT JOIN notes ON notes.id = attachments.note_id ORDER BY attachments.id"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+    };
+    let dir = attachments_dir()?;
+
+    let mut problems = Vec::new();
+    let mut orphans = Vec::new();
+    for (id, file_name, stored_name, note_deleted) in &rows {
+        if *note_deleted {
+            problems.push(format!("Attachment {} ({}) belongs to a deleted note", id, file_name));
+        } else if !dir.join(stored_name).is_file() {
+            problems.push(format!("Attachment {} ({}) has no file", id, file_name));
+        } else {
+            continue;
+        }
+        orphans.push((*id, stored_name.clone()));
+    }
+
+    // The files of the attachments and of their annotations are known, anything else is left over
+    let known: std::collections::HashSet<String> = rows.iter()
+        .flat_map(|(_, _, stored_name, _)| [stored_name.clone(), annotation_name(stored_name)])
+        .collect();
+    let mut stray_files = Vec::new();
+    for entry in std::fs::read_dir(&dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_file() && !known.contains(&name) {
+            problems.push(format!("File {} belongs to no attachment", name));
+            stray_files.push(name);
+        }
+    }
+
+    if !fix {
+        return Ok((problems, 0));
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for (id, _) in &orphans {
+        tx.execute("DELETE FROM attachment_texts WHERE attachment_id = ?1", params![id]).map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM attachments WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+        tx.execute("UPDATE notes SET cover_attachment_id = NULL WHERE cover_attachment_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    let mut fixed = orphans.len();
+    for (_, stored_name) in &orphans {
+        remove_stored_file(stored_name);
+    }
+    for name in &stray_files {
+        match std::fs::remove_file(dir.join(name)) {
+            Ok(_) => fixed += 1,
+            Err(e) => eprintln!("Failed to remove {}: {}", name, e),
+        }
+    }
+
+    Ok((problems, fixed))
+}
+
+
+/// Builds an `Attachment` from a row selected with `ATTACHMENT_COLUMNS`.
+fn attachment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        note_id: row.get(1)?,
+        file_name: row.get(2)?,
+        mime_type: row.get(3)?,
+        size: row.get(4)?,
+        created_at: row.get(5)?,
+        annotated_at: row.get(6)?,
+    })
+}
+
+
+/// Returns the name of the sidecar file holding the annotation of an attachment, next to the file of the attachment.
+fn annotation_name(stored_name: &str) -> String {
+    format!("{}.{}", stored_name, ANNOTATION_EXTENSION)
+}
+
+
+/// Removes the file of an attachment and the sidecar file of its annotation.
+/// Failing to remove them only leaves orphan files, so errors are only printed.
+fn remove_stored_file(stored_name: &str) {
+    if let Ok(dir) = attachments_dir() {
+        if let Err(e) = std::fs::remove_file(dir.join(stored_name)) {
+            eprintln!("Failed to remove attachment file {}: {}", stored_name, e);
+        }
+        match std::fs::remove_file(dir.join(annotation_name(stored_name))) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => eprintln!("Failed to remove the annotation of {}: {}", stored_name, e),
+            _ => (),
+        }
+    }
+}
+
+
+/// Guesses the MIME type of a file from its lowercase extension.
+fn mime_type(extension: &str) -> &'static str {
+    match extension {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
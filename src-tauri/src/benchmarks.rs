@@ -0,0 +1,169 @@
+// benchmarks.rs
+
+use crate::{ app_paths, crypto, local_operations, search_index, test_data, models::BenchmarkReport, models::BenchmarkResult, models::Note };
+use criterion::{black_box, BatchSize, Criterion};
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+
+/// The number of notes of the scratch vault the operations on notes are measured on.
+const NOTE_COUNT: usize = 10_000;
+
+/// The average size of the notes of the scratch vault, in bytes.
+const NOTE_SIZE: usize = 2_000;
+
+/// The number of distinct tags of the notes of the scratch vault.
+const TAG_COUNT: usize = 50;
+
+/// The size of the content encrypted and decrypted, in bytes.
+const CONTENT_SIZE: usize = 10_000;
+
+/// The number of notes replaced by an incremental update of the index.
+const REINDEXED_NOTES: usize = 100;
+
+/// The benchmarks, by criterion group and function, in the order they run.
+const BENCHMARKS: [(&str, &str); 9] = [
+    ("crypto", "encrypt_10kb"),
+    ("crypto", "decrypt_10kb"),
+    ("notes", "insert_10k"),
+    ("notes", "list_10k"),
+    ("index", "build_10k"),
+    ("index", "update_100"),
+    ("serialization", "serialize_10k"),
+    ("serialization", "deserialize_10k"),
+    ("serialization", "serialize_one"),
+];
+
+
+/// Measures the core operations and writes the results to a report file, to catch performance regressions.
+///
+/// # Arguments
+///
+/// * `path` - The path of the JSON report.
+///
+/// # Operation
+///
+/// * The operations are measured with criterion on scratch data generated like `test_data::generate_test_data`:
+///   10,000 notes of 2 KB on average, kept in memory. The vault of the user is neither read nor changed.
+/// * "crypto": the encryption and decryption of 10 KB of content.
+/// * "notes": the insertion of the notes into an empty database, and the listing of the notes with their content decrypted.
+/// * "index": the build of a search index of the notes, and the incremental update of 100 of them.
+/// * "serialization": the JSON serialization of the notes, as sent to the window, and back, and of a single note.
+/// * Criterion keeps the measures of each run in the "benchmarks" directory of the cache directory, so each
+///   result is compared with the previous run.
+/// * The run takes a few minutes, and the timings are only meaningful in release builds.
+///
+/// # Returns
+///
+/// Returns a `BenchmarkReport` with the mean and standard deviation of each operation, and its change since the
+/// previous run, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the scratch data cannot be prepared or if the report cannot be written.
+pub fn run_benchmarks(path: &str) -> Result<BenchmarkReport, String> {
+    let output_directory = app_paths::benchmarks_dir()?;
+    let mut criterion = Criterion::default()
+        .output_directory(&output_directory)
+        .without_plots()
+        .sample_size(10)
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(5));
+
+    let notes = test_data::generate_notes(NOTE_COUNT, NOTE_SIZE, TAG_COUNT);
+    let content = test_data::generate_notes(1, CONTENT_SIZE, 0).remove(0).content;
+    let (encrypted_content, nonce) = crypto::encrypt_content(&content)?;
+    let vault = scratch_database(&notes)?;
+    let mut note_index = search_index::build_scratch_index(&notes)?;
+    let reindexed_notes: Vec<_> = notes.iter().step_by(NOTE_COUNT / REINDEXED_NOTES).cloned().collect();
+    let serialized_notes = serde_json::to_string(&notes).map_err(|e| e.to_string())?;
+
+    let mut group = criterion.benchmark_group("crypto");
+    group.bench_function("encrypt_10kb", |b| b.iter(|| crypto::encrypt_content(black_box(&content))));
+    group.bench_function("decrypt_10kb", |b| b.iter(|| crypto::decrypt_content(black_box(&encrypted_content), black_box(&nonce))));
+    group.finish();
+
+    let mut group = criterion.benchmark_group("notes");
+    group.bench_function("insert_10k", |b| b.iter_batched(
+        || scratch_database(&[]).unwrap(),
+        |conn| insert_notes(&conn, &notes).unwrap(),
+        BatchSize::PerIteration,
+    ));
+    group.bench_function("list_10k", |b| b.iter(|| list_notes(&vault).unwrap()));
+    group.finish();
+
+    let mut group = criterion.benchmark_group("index");
+    group.bench_function("build_10k", |b| b.iter(|| search_index::build_scratch_index(black_box(&notes)).unwrap()));
+    group.bench_function("update_100", |b| b.iter(|| search_index::reindex_scratch_notes(&mut note_index, black_box(&reindexed_notes)).unwrap()));
+    group.finish();
+
+    let mut group = criterion.benchmark_group("serialization");
+    group.bench_function("serialize_10k", |b| b.iter(|| serde_json::to_string(black_box(&notes)).unwrap()));
+    group.bench_function("deserialize_10k", |b| b.iter(|| serde_json::from_str::<Vec<Note>>(black_box(&serialized_notes)).unwrap()));
+    group.bench_function("serialize_one", |b| b.iter(|| serde_json::to_string(black_box(&notes[0])).unwrap()));
+    group.finish();
+
+    criterion.final_summary();
+
+    let report = BenchmarkReport {
+        ran_at: chrono::Utc::now().timestamp(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        results: BENCHMARKS.iter()
+            .map(|(group, function)| read_result(&output_directory, group, function))
+            .collect::<Result<_, _>>()?,
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("Failed to write the report to {}: {}", path, e))?;
+
+    Ok(report)
+}
+
+
+/// Creates a database in memory with the "notes" table, holding some notes.
+fn scratch_database(notes: &[Note]) -> Result<Connection, String> {
+    let conn = Connection::open_in_memory().map_err(|e| e.to_string())?;
+    local_operations::create_notes_table(&conn).map_err(|e| e.to_string())?;
+    insert_notes(&conn, notes)?;
+    Ok(conn)
+}
+
+
+/// Inserts notes in a single transaction, the way notes are imported.
+fn insert_notes(conn: &Connection, notes: &[Note]) -> Result<(), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for note in notes {
+        local_operations::insert_note(&tx, note)?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+
+/// Lists the notes of a database with their content decrypted, the way the notes are listed.
+fn list_notes(conn: &Connection) -> Result<Vec<Note>, String> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM notes", local_operations::NOTE_COLUMNS)).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], local_operations::note_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(notes)
+}
+
+
+/// Reads the estimates criterion saved for a benchmark, and their change since the previous run.
+fn read_result(output_directory: &Path, group: &str, function: &str) -> Result<BenchmarkResult, String> {
+    let directory: PathBuf = output_directory.join(group).join(function);
+    let read_estimates = |kind: &str| -> Option<serde_json::Value> {
+        let estimates = std::fs::read_to_string(directory.join(kind).join("estimates.json")).ok()?;
+        serde_json::from_str(&estimates).ok()
+    };
+    let point_estimate = |estimates: &serde_json::Value, statistic: &str| estimates[statistic]["point_estimate"].as_f64();
+
+    let estimates = read_estimates("new").ok_or(format!("The estimates of {}/{} were not saved", group, function))?;
+    Ok(BenchmarkResult {
+        name: format!("{}/{}", group, function),
+        mean_ns: point_estimate(&estimates, "mean").unwrap_or_default(),
+        std_dev_ns: point_estimate(&estimates, "std_dev").unwrap_or_default(),
+        change: read_estimates("change").and_then(|change| point_estimate(&change, "mean")),
+    })
+}
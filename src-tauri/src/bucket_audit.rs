@@ -0,0 +1,212 @@
+// bucket_audit.rs
+
+use crate::{ envelope, notebook_csv, notifier, presence_operations, s3_operations, models::InventoryEntry, models::OrphanedObject };
+use aws_sdk_s3 as s3;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+
+/// The columns of an inventory exported as CSV. The metadata is written as a JSON object.
+const INVENTORY_COLUMNS: [&str; 6] = ["key", "uuid", "size", "last_modified", "etag", "metadata"];
+
+
+/// Finds the objects of a note bucket that are not notes, e.g. written by another tool sharing the bucket or by an
+/// old version of the application.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Operation
+///
+/// * The objects of the bucket are listed, leaving out the presence markers (see `presence_operations`).
+/// * An object is orphaned when its metadata does not describe a note envelope: its UUID or its nonce is missing
+///   or malformed (see `envelope::metadata_issues`).
+///
+/// # Returns
+///
+/// Returns the orphaned objects with their size, their last modification, and what is wrong with their metadata.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error.
+pub async fn find_orphaned_bucket_objects(bucket_name: &str) -> Result<Vec<OrphanedObject>, String> {
+    let client = s3_operations::s3_client().await;
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| e.to_string())?;
+    let mut orphans = Vec::new();
+    for (key, _) in objects {
+        if let Some(orphan) = orphaned_object(&client, bucket_name, &key).await? {
+            orphans.push(orphan);
+        }
+    }
+
+    Ok(orphans)
+}
+
+
+/// Deletes orphaned objects of a note bucket, once the user confirmed them.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `keys` - The keys of the objects to delete, as returned by `find_orphaned_bucket_objects` and confirmed by the user.
+///
+/// # Operation
+///
+/// Each object is checked again before it is deleted, so an object that became a note since it was found, or that
+/// no longer exists, is skipped, as are presence markers. A note is never deleted by this function.
+///
+/// # Returns
+///
+/// Returns the keys of the deleted objects.
+///
+/// # Errors
+///
+/// This function will return an error if no key is given or if the AWS SDK encounters an error.
+pub async fn clean_orphaned_objects(bucket_name: &str, keys: &[String]) -> Result<Vec<String>, String> {
+    if keys.is_empty() {
+        return Err("No object to delete".to_string());
+    }
+
+    let client = s3_operations::s3_client().await;
+
+    let mut deleted = Vec::new();
+    for key in keys {
+        // Presence markers are not notes either, but they expire on their own
+        if key.starts_with(presence_operations::PRESENCE_PREFIX) || orphaned_object(&client, bucket_name, key).await?.is_none() {
+            continue;
+        }
+
+        client.delete_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete {}: {}", key, e))?;
+        deleted.push(key.clone());
+    }
+
+    Ok(deleted)
+}
+
+
+/// Exports the inventory of the objects of a note bucket, for auditing or external tooling.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `path` - The file to write. Its extension chooses the format: ".json" for an array of objects, ".csv" for one
+///   row per object.
+///
+/// # Operation
+///
+/// * The objects are listed page by page, leaving out the presence markers (see `presence_operations`).
+/// * For each object, its key, size, last modification, and ETag come from the listing, and its metadata, including
+///   the UUID of the note, from its headers. The objects are not downloaded, so the content of the notes never
+///   reaches the inventory.
+///
+/// # Returns
+///
+/// Returns the number of objects in the inventory, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the extension of the file is not supported, if the AWS SDK encounters an
+/// error, or if the file cannot be written.
+pub async fn export_bucket_inventory(bucket_name: &str, path: &str) -> Result<usize, String> {
+    let extension = Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if extension != "json" && extension != "csv" {
+        return Err(format!("{} should be a .json or .csv file", path));
+    }
+
+    let client = s3_operations::s3_client().await;
+
+    let mut response = client.list_objects_v2()
+        .bucket(bucket_name)
+        .into_paginator()
+        .send();
+    let mut entries = Vec::new();
+    while let Some(result) = response.next().await {
+        let output = result.map_err(|e| e.to_string())?;
+        for object in output.contents() {
+            let Some(key) = object.key().filter(|key| !key.starts_with(presence_operations::PRESENCE_PREFIX)) else {
+                continue;
+            };
+            entries.push(InventoryEntry {
+                key: key.to_string(),
+                uuid: None,
+                size: object.size().unwrap_or(0),
+                last_modified: object.last_modified().map(|dt| dt.to_string()),
+                etag: object.e_tag().map(|etag| etag.trim_matches('"').to_string()),
+                metadata: BTreeMap::new(),
+            });
+        }
+    }
+
+    for entry in &mut entries {
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
+            .key(&entry.key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", entry.key, e))?;
+
+        entry.metadata = head_object_output.metadata.unwrap_or_default().into_iter().collect();
+        entry.uuid = entry.metadata.get("uuid").cloned();
+    }
+
+    let contents = if extension == "json" {
+        serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?
+    } else {
+        let mut csv = format!("{}\n", INVENTORY_COLUMNS.join(","));
+        for entry in &entries {
+            let metadata = serde_json::to_string(&entry.metadata).map_err(|e| e.to_string())?;
+            let row = [
+                notebook_csv::csv_field(&entry.key),
+                notebook_csv::csv_field(entry.uuid.as_deref().unwrap_or_default()),
+                entry.size.to_string(),
+                notebook_csv::csv_field(entry.last_modified.as_deref().unwrap_or_default()),
+                notebook_csv::csv_field(entry.etag.as_deref().unwrap_or_default()),
+                notebook_csv::csv_field(&metadata),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    };
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    // Send a desktop notification
+    notifier::notify("Inventory exported", &format!("The inventory of {} objects of '{}' was exported to {}.", entries.len(), bucket_name, path));
+
+    Ok(entries.len())
+}
+
+
+/// Reads the metadata of an object and returns it as orphaned if it does not describe a note envelope.
+/// An object that no longer exists is not orphaned.
+async fn orphaned_object(client: &s3::Client, bucket_name: &str, key: &str) -> Result<Option<OrphanedObject>, String> {
+    let result = client.head_object()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await;
+    let head_object_output = match result {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().map(|e| e.is_not_found()).unwrap_or(false) => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", key, e)),
+    };
+
+    let issues = envelope::metadata_issues(&head_object_output.metadata.unwrap_or_default());
+    if issues.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(OrphanedObject {
+        key: key.to_string(),
+        size: head_object_output.content_length.unwrap_or(0),
+        last_modified: head_object_output.last_modified.map(|dt| dt.to_string()),
+        issues,
+    }))
+}
@@ -0,0 +1,184 @@
+// bucket_format.rs
+
+use crate::{ notifier, s3_operations, local_operations::CONNECTION, models::AppError, models::BucketFormatMigration, models::MigratedObject, models::Note, models::SkippedObject };
+use aws_sdk_s3 as s3;
+use rusqlite::{params, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+
+/// Rewrites the notes uploaded by the previous versions of the application into the current format of the buckets.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket to migrate.
+///
+/// # Operation
+///
+/// * The objects of the bucket outside of the "notes/" prefix, named after the title of their note by the previous
+///   versions, are downloaded and decrypted with the key they were encrypted with.
+/// * Each note is written to the object named after its UUID (see `s3_operations::note_key`) like an uploaded note:
+///   encrypted with the current key, with its title, its flags, its tags, and its statistics in the metadata. A note
+///   without a UUID, from the versions that did not store it, is given a new one.
+/// * The new object is downloaded and decrypted again, and the object named after the title is deleted only if it
+///   holds the same note.
+/// * If the object named after the UUID already exists, e.g. uploaded since by another device, the more recently
+///   changed of the two is kept, like `s3_operations::migrate_bucket_keys` does.
+/// * The objects that cannot be decrypted are not notes of the application, or were encrypted with a key this device
+///   does not have, and are left as they are.
+///
+/// A migration stopped halfway is resumed by running it again: the objects already migrated are gone, and the UUID
+/// given to a note is kept in the "bucket_format_migrations" table until its previous object is deleted, so the note
+/// is not written twice under two UUIDs.
+///
+/// # Returns
+///
+/// Returns a `BucketFormatMigration` with the previous and new keys of the migrated notes, the previous keys of the
+/// removed duplicates, and the skipped objects with the reason.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when reading, writing, or deleting an
+/// object, if a migrated note does not read back the same, or if there is an issue with the database connection.
+pub async fn migrate_bucket_format(bucket_name: &str) -> Result<BucketFormatMigration, AppError> {
+    let bucket_name = bucket_name.trim_matches('"');
+    let client = s3_operations::s3_client().await;
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))?;
+    let mut keys: HashSet<String> = objects.iter().map(|(key, _)| key.clone()).collect();
+    let mut migration = BucketFormatMigration::default();
+
+    for (key, _) in &objects {
+        if key.starts_with(s3_operations::NOTES_PREFIX) {
+            continue;
+        }
+
+        let (metadata, content) = match s3_operations::fetch_bucket_object(&client, bucket_name, key).await {
+            Ok((_, metadata, content)) => (metadata.unwrap_or_default(), content),
+            Err(e) => {
+                migration.skipped.push(SkippedObject { key: key.clone(), reason: format!("Cannot be decrypted: {}", e) });
+                continue;
+            },
+        };
+        let uuid = match metadata.get("uuid").filter(|uuid| !uuid.is_empty()) {
+            Some(uuid) => uuid.clone(),
+            None => assigned_uuid(bucket_name, key)?,
+        };
+        let new_key = s3_operations::note_key(&uuid, None);
+
+        // Keep the object named after the UUID if it is the more recent copy of the note
+        let existing = match keys.contains(&new_key) {
+            true => head_metadata(&client, bucket_name, &new_key).await?,
+            false => None,
+        };
+        let is_duplicate = existing.is_some_and(|existing| s3_operations::changed_at(&existing) >= s3_operations::changed_at(&metadata));
+
+        if !is_duplicate {
+            let note = note_from_object(key, &uuid, &metadata, content);
+            s3_operations::put_note_object(&client, bucket_name, &new_key, &note)
+                .await
+                .map_err(|e| AppError::s3_with(e.as_ref(), &format!("Failed to write {}", new_key)))?;
+            keys.insert(new_key.clone());
+
+            // The previous object is only deleted once the new one reads back the same
+            let (_, written_metadata, written_content) = s3_operations::fetch_bucket_object(&client, bucket_name, &new_key)
+                .await
+                .map_err(|e| AppError::s3_with(e.as_ref(), &format!("Failed to read {}", new_key)))?;
+            let written_uuid = written_metadata.as_ref().and_then(|metadata| metadata.get("uuid"));
+            if written_content != note.content || written_uuid != Some(&uuid) {
+                return Err(AppError::Failed(format!("{} does not hold the same note as {}, which is kept", new_key, key)));
+            }
+        }
+
+        client.delete_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3_with(&e, &format!("Failed to delete {}", key)))?;
+        forget_uuid(bucket_name, key)?;
+
+        match is_duplicate {
+            true => migration.duplicates_removed.push(key.clone()),
+            false => migration.migrated.push(MigratedObject { old_key: key.clone(), new_key }),
+        }
+    }
+
+    if !migration.migrated.is_empty() {
+        notifier::notify("Bucket migrated", &format!("{} notes of bucket {} were migrated.", migration.migrated.len(), bucket_name));
+    }
+
+    Ok(migration)
+}
+
+
+/// Returns the UUID given to an object without one, giving it a new one the first time.
+fn assigned_uuid(bucket_name: &str, key: &str) -> Result<String, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let assigned: Option<String> = conn.query_row(
+        "SELECT note_uuid FROM bucket_format_migrations WHERE bucket_name = ?1 AND object_key = ?2",
+        params![bucket_name, key],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())?;
+    if let Some(uuid) = assigned {
+        return Ok(uuid);
+    }
+
+    let uuid = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO bucket_format_migrations (bucket_name, object_key, note_uuid, started_at) VALUES (?1, ?2, ?3, ?4)",
+        params![bucket_name, key, uuid, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+    Ok(uuid)
+}
+
+
+/// Forgets the UUID given to an object once it is migrated.
+fn forget_uuid(bucket_name: &str, key: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "DELETE FROM bucket_format_migrations WHERE bucket_name = ?1 AND object_key = ?2",
+        params![bucket_name, key],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Reads the metadata of an object, or `None` if it does not exist anymore.
+async fn head_metadata(client: &s3::Client, bucket_name: &str, key: &str) -> Result<Option<HashMap<String, String>>, AppError> {
+    let result = client.head_object()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await;
+    match result {
+        Ok(head_object) => Ok(Some(head_object.metadata.unwrap_or_default())),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+        Err(e) => Err(AppError::s3_with(&e, &format!("Failed to read {}", key))),
+    }
+}
+
+
+/// Builds a note from a decrypted object of the previous versions and its metadata, whatever fields it has.
+fn note_from_object(key: &str, uuid: &str, metadata: &HashMap<String, String>, content: String) -> Note {
+    let time = |field: &str| metadata.get(field).and_then(|value| value.parse::<i64>().ok());
+    Note {
+        id: None,
+        uuid: Some(uuid.to_string()),
+        title: s3_operations::title_from_metadata(key, metadata),
+        content,
+        nonce: None,
+        created_at: time("created_at").unwrap_or_else(|| chrono::Utc::now().timestamp()),
+        updated_at: time("updated_at").filter(|updated_at| *updated_at > 0),
+        timestamp: metadata.get("timestamp").cloned(),
+        secure: metadata.get("secure").is_some_and(|secure| secure == "true"),
+        status: metadata.get("status").cloned(),
+        icon: s3_operations::icon_from_metadata(metadata),
+        cover_attachment_id: metadata.get("cover_attachment_id").and_then(|id| id.parse::<i64>().ok()),
+        locked: s3_operations::is_locked(metadata),
+        pinned: s3_operations::flag_from_metadata(metadata, "pinned"),
+        archived: s3_operations::flag_from_metadata(metadata, "archived"),
+        notebook_id: None,
+        aliases: s3_operations::aliases_from_metadata(metadata),
+        tags: s3_operations::tags_from_metadata(metadata),
+    }
+}
@@ -0,0 +1,155 @@
+// bucket_mirror.rs
+
+use crate::{ crypto, s3_operations, local_operations::CONNECTION, s3_operations::BucketNote };
+use rusqlite::params;
+use std::collections::HashMap;
+
+
+/// The objects of a bucket that changed since the last synchronization of its mirror.
+pub struct MirrorChanges {
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+
+/// Synchronizes the local mirror of the notes of a bucket with the bucket.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Operation
+///
+/// * The objects of the bucket are listed, which returns their ETag without downloading them.
+/// * Only the objects that are new or whose ETag changed since the last synchronization are downloaded.
+///   Their content is stored encrypted in the "bucket_mirror" table.
+/// * The objects that are no longer in the bucket are removed from the mirror.
+///
+/// # Returns
+///
+/// Returns the keys of the objects that were downloaded and of the objects that were removed.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when listing or fetching the objects,
+/// or if there is an issue with the database connection.
+pub async fn sync_bucket_mirror(bucket_name: &str) -> Result<MirrorChanges, Box<dyn std::error::Error>> {
+    let client = s3_operations::s3_client().await;
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await?;
+    let mirrored_etags = get_mirrored_etags(bucket_name)?;
+
+    // Download the new and modified objects
+    let mut changed = Vec::new();
+    for (key, etag) in &objects {
+        if etag.is_some() && mirrored_etags.get(key) == Some(etag) {
+            continue;
+        }
+
+        let (last_modified, metadata, content) = s3_operations::fetch_bucket_object(&client, bucket_name, key).await?;
+        let metadata = metadata.map(|map| serde_json::to_string(&map)).transpose()?;
+        let (encrypted_content, nonce_str) = crypto::encrypt_content(&content)?;
+
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO bucket_mirror (bucket, key, etag, last_modified, metadata, content, nonce) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![bucket_name, key, etag, last_modified, metadata, encrypted_content, nonce_str],
+        )?;
+        changed.push(key.clone());
+    }
+
+    // Forget the objects that were deleted from the bucket
+    let removed: Vec<String> = mirrored_etags.into_keys()
+        .filter(|key| !objects.iter().any(|(object_key, _)| object_key == key))
+        .collect();
+    {
+        let conn = CONNECTION.lock().unwrap();
+        for key in &removed {
+            conn.execute(
+                "DELETE FROM bucket_mirror WHERE bucket = ?1 AND key = ?2",
+                params![bucket_name, key],
+            )?;
+        }
+    }
+
+    Ok(MirrorChanges { changed, removed })
+}
+
+
+/// Retrieves the mirrored notes of a bucket.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `keys` - The keys of the notes to retrieve, or `None` to retrieve every note of the bucket.
+///
+/// # Returns
+///
+/// Returns a vector of the notes with their key, last modified timestamp, metadata, and decrypted content,
+/// like `s3_operations::fetch_bucket_notes`.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or if a note cannot be decrypted.
+pub fn get_mirrored_notes(bucket_name: &str, keys: Option<&[String]>) -> Result<Vec<BucketNote>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT key, last_modified, metadata, content, nonce FROM bucket_mirror WHERE bucket = ?1"
+    ).map_err(|e| e.to_string())?;
+    let row_iter = stmt.query_map(params![bucket_name], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    }).map_err(|e| e.to_string())?;
+
+    let mut notes = Vec::new();
+    for row in row_iter {
+        let (key, last_modified, metadata, encrypted_content, nonce_str) = row.map_err(|e| e.to_string())?;
+        if keys.is_some_and(|keys| !keys.contains(&key)) {
+            continue;
+        }
+
+        let metadata = metadata.map(|value| serde_json::from_str(&value)).transpose().map_err(|e| e.to_string())?;
+        let content = crypto::decrypt_content(&encrypted_content, &nonce_str)?;
+        notes.push((key, last_modified, metadata, content));
+    }
+
+    Ok(notes)
+}
+
+
+/// Removes every mirrored note of a bucket, e.g. after the bucket was deleted.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn clear_bucket_mirror(bucket_name: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "DELETE FROM bucket_mirror WHERE bucket = ?1",
+        params![bucket_name],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Retrieves the ETag of every mirrored note of a bucket, by key.
+fn get_mirrored_etags(bucket_name: &str) -> Result<HashMap<String, Option<String>>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT key, etag FROM bucket_mirror WHERE bucket = ?1"
+    ).map_err(|e| e.to_string())?;
+    let etag_iter = stmt.query_map(params![bucket_name], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    etag_iter.collect::<Result<HashMap<_, _>, _>>().map_err(|e| e.to_string())
+}
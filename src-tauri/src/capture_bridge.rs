@@ -0,0 +1,380 @@
+// capture_bridge.rs
+
+use crate::{ attachment_operations, delta, local_operations, note_events, notifier, proxy, settings };
+use crate::local_operations::CONNECTION;
+use crate::models::{BridgeKind, CaptureBridgeConfig, CaptureBridgeSettings, Note};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+
+/// The settings key under which the bridge configuration is stored, as JSON.
+const CONFIG_SETTING: &str = "capture_bridge";
+
+/// The settings key under which the bot token or the Matrix access token is stored encrypted (see `settings::set_secret_setting`).
+const TOKEN_SETTING: &str = "capture_bridge_token";
+
+/// The settings key under which the position reached in the messages is stored: the next Telegram update ID,
+/// or the Matrix sync token.
+const CURSOR_SETTING: &str = "capture_bridge_cursor";
+
+/// The base URL of the Telegram Bot API.
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// The maximum number of Matrix events fetched per poll.
+const MATRIX_SYNC_LIMIT: u32 = 100;
+
+/// How often, in seconds, the configuration is read again while the bridge is disabled.
+const DISABLED_CHECK_SECONDS: u32 = 60;
+
+/// The title given to captured media without text.
+const UNTITLED_CAPTURE: &str = "Quick capture";
+
+
+/// A message received through the bridge, ready to become a note.
+struct CapturedMessage {
+    text: String,
+    created_at: i64,
+    media: Vec<(String, Vec<u8>)>,
+}
+
+
+/// Starts polling the configured Telegram bot or Matrix room for new messages.
+///
+/// # Operation
+///
+/// A thread polls the bridge every poll interval while it is enabled. It runs on its own rather than as background
+/// tasks, which would flood the list of tasks at this rate. The configuration is read again before each poll,
+/// so changes apply without restarting the application.
+pub fn start_capture_bridge() {
+    std::thread::spawn(|| loop {
+        let interval = match get_config() {
+            Ok(Some(config)) if config.enabled => {
+                if let Err(e) = poll_bridge(&config) {
+                    eprintln!("Failed to poll the capture bridge: {}", e);
+                }
+                config.poll_interval_seconds
+            },
+            Ok(_) => DISABLED_CHECK_SECONDS,
+            Err(e) => {
+                eprintln!("Failed to read the capture bridge settings: {}", e);
+                DISABLED_CHECK_SECONDS
+            },
+        };
+        std::thread::sleep(Duration::from_secs(interval.max(1) as u64));
+    });
+}
+
+
+/// Retrieves the capture bridge settings. The token is never returned.
+///
+/// # Returns
+///
+/// Returns a `CaptureBridgeSettings` with the configuration of the bridge, or `None` if it is not configured,
+/// and whether its token is set.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_capture_bridge() -> Result<CaptureBridgeSettings, String> {
+    Ok(CaptureBridgeSettings {
+        config: get_config()?,
+        has_token: settings::get_setting(TOKEN_SETTING)?.is_some(),
+    })
+}
+
+
+/// Configures the bridge turning the messages sent to a Telegram bot or posted in a Matrix room into notes.
+///
+/// # Arguments
+///
+/// * `config` - The service, the homeserver and the room for Matrix, the senders whose messages are captured
+///   (every sender if empty), how often to poll, and whether the bridge is enabled.
+/// * `token` - The Telegram bot token or the Matrix access token, stored encrypted. `None` keeps the current token.
+///
+/// # Operation
+///
+/// Switching to another service, homeserver, or room starts again from its latest messages.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the homeserver or the room is missing for Matrix, if the poll interval
+/// is zero, or if there is an issue with the database connection.
+pub fn set_capture_bridge(config: CaptureBridgeConfig, token: Option<String>) -> Result<(), String> {
+    if config.kind == BridgeKind::Matrix {
+        if config.homeserver.as_deref().unwrap_or_default().trim().is_empty() {
+            return Err("Homeserver cannot be empty".to_string());
+        }
+        if config.room_id.as_deref().unwrap_or_default().trim().is_empty() {
+            return Err("Room cannot be empty".to_string());
+        }
+    }
+    if config.poll_interval_seconds == 0 {
+        return Err("Poll interval should be at least 1 second".to_string());
+    }
+
+    let source_changed = match get_config()? {
+        Some(previous) => previous.kind != config.kind || previous.homeserver != config.homeserver || previous.room_id != config.room_id,
+        None => true,
+    };
+    if source_changed {
+        settings::delete_setting(CURSOR_SETTING)?;
+    }
+    if let Some(token) = token {
+        settings::set_secret_setting(TOKEN_SETTING, &token)?;
+    }
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)
+}
+
+
+/// Fetches the new messages of the bridge and turns them into notes.
+///
+/// # Operation
+///
+/// * The text of a message becomes the content of the note, and its first line the title.
+///   Its photos, files, voice messages, and videos are attached to the note.
+/// * The messages of senders not listed in `allowed_senders` are ignored, unless the list is empty.
+/// * A message that cannot be captured, e.g. because its media cannot be downloaded, is skipped, so it does not
+///   block the ones after it.
+///
+/// # Errors
+///
+/// This function will return an error if the token is not set, if the service cannot be reached or rejects the
+/// token, or if there is an issue with the database connection.
+fn poll_bridge(config: &CaptureBridgeConfig) -> Result<(), String> {
+    let token = settings::get_secret_setting(TOKEN_SETTING)?.ok_or("The token of the capture bridge is not set".to_string())?;
+    let client = proxy::blocking_client_builder()?.timeout(Duration::from_secs(60)).build().map_err(|e| e.to_string())?;
+
+    let captured = match config.kind {
+        BridgeKind::Telegram => poll_telegram(&client, &token, config)?,
+        BridgeKind::Matrix => poll_matrix(&client, &token, config)?,
+    };
+    if captured > 0 {
+        // Send a desktop notification
+        notifier::notify("Notes captured", &format!("{} message(s) were turned into notes.", captured));
+    }
+
+    Ok(())
+}
+
+
+/// Captures the messages sent to the Telegram bot since the last poll.
+///
+/// # Returns
+///
+/// Returns the number of new notes.
+fn poll_telegram(client: &Client, token: &str, config: &CaptureBridgeConfig) -> Result<usize, String> {
+    let mut request = client.get(format!("{}/bot{}/getUpdates", TELEGRAM_API, token)).query(&[("timeout", "0")]);
+    if let Some(offset) = settings::get_setting(CURSOR_SETTING)? {
+        // Asking from an offset also confirms the updates before it, so Telegram stops sending them
+        request = request.query(&[("offset", offset)]);
+    }
+    let response: Value = request.send().and_then(|response| response.json()).map_err(|e| format!("Failed to reach Telegram: {}", e))?;
+    if response["ok"] != json!(true) {
+        return Err(format!("Telegram error: {}", response["description"].as_str().unwrap_or("unknown error")));
+    }
+
+    let mut captured = 0;
+    for update in response["result"].as_array().cloned().unwrap_or_default() {
+        let Some(update_id) = update["update_id"].as_i64() else {
+            continue;
+        };
+        let message = &update["message"];
+        let senders = [message["from"]["id"].as_i64().map(|id| id.to_string()), message["from"]["username"].as_str().map(str::to_string)];
+        if message.is_object() && is_allowed(config, &senders) {
+            match telegram_message(client, token, message).and_then(save_capture) {
+                Ok(_) => captured += 1,
+                Err(e) => eprintln!("Failed to capture Telegram message {}: {}", update_id, e),
+            }
+        }
+        settings::set_setting(CURSOR_SETTING, &(update_id + 1).to_string())?;
+    }
+
+    Ok(captured)
+}
+
+
+/// Converts a Telegram message, downloading its media.
+fn telegram_message(client: &Client, token: &str, message: &Value) -> Result<CapturedMessage, String> {
+    let message_id = message["message_id"].as_i64().unwrap_or_default();
+    let text = message["text"].as_str().or(message["caption"].as_str()).unwrap_or_default().to_string();
+
+    // Photos come in several sizes, the largest last
+    let mut files = Vec::new();
+    if let Some(photo) = message["photo"].as_array().and_then(|sizes| sizes.last()) {
+        files.push((photo["file_id"].clone(), format!("photo-{}.jpg", message_id)));
+    }
+    for (kind, default_name) in [("document", format!("document-{}", message_id)), ("voice", format!("voice-{}.ogg", message_id)),
+        ("audio", format!("audio-{}.mp3", message_id)), ("video", format!("video-{}.mp4", message_id))] {
+        let file = &message[kind];
+        if file.is_object() {
+            let file_name = file["file_name"].as_str().map(str::to_string).unwrap_or(default_name);
+            files.push((file["file_id"].clone(), file_name));
+        }
+    }
+
+    let mut media = Vec::new();
+    for (file_id, file_name) in files {
+        let file_id = file_id.as_str().ok_or("Missing file ID".to_string())?;
+        let file: Value = client.get(format!("{}/bot{}/getFile", TELEGRAM_API, token))
+            .query(&[("file_id", file_id)])
+            .send()
+            .and_then(|response| response.json())
+            .map_err(|e| format!("Failed to get {}: {}", file_name, e))?;
+        let file_path = file["result"]["file_path"].as_str().ok_or(format!("Telegram cannot send {}", file_name))?;
+        let data = download(client.get(format!("{}/file/bot{}/{}", TELEGRAM_API, token, file_path)), &file_name)?;
+        media.push((file_name, data));
+    }
+
+    Ok(CapturedMessage {
+        text,
+        created_at: message["date"].as_i64().unwrap_or_else(|| chrono::Utc::now().timestamp()),
+        media,
+    })
+}
+
+
+/// Captures the messages posted in the Matrix room since the last poll. The first poll only records the position
+/// of the room, so the messages posted before the bridge was set up are not captured.
+///
+/// # Returns
+///
+/// Returns the number of new notes.
+fn poll_matrix(client: &Client, token: &str, config: &CaptureBridgeConfig) -> Result<usize, String> {
+    let homeserver = config.homeserver.as_deref().unwrap_or_default().trim_end_matches('/');
+    let room_id = config.room_id.as_deref().unwrap_or_default();
+
+    let filter = json!({
+        "room": { "rooms": [room_id], "timeline": { "limit": MATRIX_SYNC_LIMIT, "types": ["m.room.message"] } },
+        "presence": { "types": [] },
+        "account_data": { "types": [] },
+    }).to_string();
+    let since = settings::get_setting(CURSOR_SETTING)?;
+    let mut request = client.get(format!("{}/_matrix/client/v3/sync", homeserver))
+        .bearer_auth(token)
+        .query(&[("timeout", "0"), ("filter", filter.as_str())]);
+    if let Some(since) = &since {
+        request = request.query(&[("since", since)]);
+    }
+    let response: Value = request.send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .map_err(|e| format!("Failed to reach {}: {}", homeserver, e))?;
+    let next_batch = response["next_batch"].as_str().ok_or("Missing sync token".to_string())?;
+
+    let mut captured = 0;
+    if since.is_some() {
+        for event in response["rooms"]["join"][room_id]["timeline"]["events"].as_array().cloned().unwrap_or_default() {
+            let senders = [event["sender"].as_str().map(str::to_string)];
+            if event["type"] != json!("m.room.message") || !is_allowed(config, &senders) {
+                continue;
+            }
+            match matrix_message(client, token, homeserver, &event).and_then(save_capture) {
+                Ok(_) => captured += 1,
+                Err(e) => eprintln!("Failed to capture Matrix event {}: {}", event["event_id"], e),
+            }
+        }
+    }
+    settings::set_setting(CURSOR_SETTING, next_batch)?;
+
+    Ok(captured)
+}
+
+
+/// Converts a Matrix message event, downloading its media.
+fn matrix_message(client: &Client, token: &str, homeserver: &str, event: &Value) -> Result<CapturedMessage, String> {
+    let content = &event["content"];
+    let body = content["body"].as_str().unwrap_or_default().to_string();
+    let created_at = event["origin_server_ts"].as_i64().map(|ms| ms / 1000).unwrap_or_else(|| chrono::Utc::now().timestamp());
+
+    match content["msgtype"].as_str() {
+        Some("m.image") | Some("m.file") | Some("m.audio") | Some("m.video") => {
+            let url = content["url"].as_str().ok_or("Missing media URL".to_string())?;
+            let (server, media_id) = url.strip_prefix("mxc://").and_then(|path| path.split_once('/'))
+                .ok_or(format!("Invalid media URL {}", url))?;
+            // The body is the file name, unless a caption was given with it
+            let (file_name, text) = match content["filename"].as_str() {
+                Some(file_name) if file_name != body => (file_name.to_string(), body),
+                _ => (body, String::new()),
+            };
+            let request = client.get(format!("{}/_matrix/client/v1/media/download/{}/{}", homeserver, server, media_id)).bearer_auth(token);
+            let data = download(request, &file_name)?;
+            Ok(CapturedMessage { text, created_at, media: vec![(file_name, data)] })
+        },
+        _ => Ok(CapturedMessage { text: body, created_at, media: Vec::new() }),
+    }
+}
+
+
+/// Downloads a media file.
+fn download(request: reqwest::blocking::RequestBuilder, file_name: &str) -> Result<Vec<u8>, String> {
+    request.send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| format!("Failed to download {}: {}", file_name, e))
+}
+
+
+/// Turns a captured message into a note with its media attached.
+fn save_capture(message: CapturedMessage) -> Result<(), String> {
+    let first_line = message.text.lines().map(str::trim).find(|line| !line.is_empty());
+    let title = match (first_line, message.media.first()) {
+        (Some(line), _) => line,
+        (None, Some((file_name, _))) => file_name.as_str(),
+        (None, None) => UNTITLED_CAPTURE,
+    };
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(title),
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", message.text.trim_end()) })]),
+        nonce: None,
+        created_at: message.created_at,
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status: None,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: Vec::new(),
+    };
+    local_operations::validate_params(note.clone())?;
+
+    // Create the note and its attachments in a single transaction
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let note_id = local_operations::insert_note(&tx, &note)?;
+    note_events::note_saved(&tx, note_id, &note.content)?;
+    for (file_name, data) in &message.media {
+        attachment_operations::store_attachment(&tx, note_id, file_name, data)?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())
+}
+
+
+/// Tells whether a message comes from one of the allowed senders, given the identifiers of its sender.
+fn is_allowed(config: &CaptureBridgeConfig, senders: &[Option<String>]) -> bool {
+    config.allowed_senders.is_empty()
+        || senders.iter().flatten().any(|sender| config.allowed_senders.iter().any(|allowed| allowed.trim_start_matches('@') == sender.trim_start_matches('@')))
+}
+
+
+/// Reads the bridge configuration from the settings.
+fn get_config() -> Result<Option<CaptureBridgeConfig>, String> {
+    settings::get_setting(CONFIG_SETTING)?
+        .map(|json| serde_json::from_str(&json).map_err(|e| format!("Invalid capture bridge settings: {}", e)))
+        .transpose()
+}
@@ -0,0 +1,150 @@
+// card_operations.rs
+
+use crate::{ app_paths, local_operations, preview, vault, models::AppError, models::CardTheme };
+use resvg::{tiny_skia, usvg};
+
+
+/// The width of a card, in pixels, which is the size of the link previews of the social networks.
+const CARD_WIDTH: u32 = 1200;
+
+/// The height of a card, in pixels.
+const CARD_HEIGHT: u32 = 630;
+
+/// The margin around the text of a card, in pixels.
+const CARD_MARGIN: u32 = 72;
+
+/// The maximum number of lines of text on a card, after the title.
+const CARD_MAX_LINES: usize = 8;
+
+/// The number of characters a line of text is wrapped at, for the font size of the text.
+const CARD_LINE_LENGTH: usize = 56;
+
+/// The number of characters the title is cut at, for the font size of the title.
+const CARD_TITLE_LENGTH: usize = 40;
+
+/// The text written at the bottom of every card.
+const CARD_FOOTER: &str = "Custom Notes";
+
+
+/// Exports a note as a PNG image, e.g. to share a quote or a snippet.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `theme` - The colors of the card: light or dark.
+///
+/// # Operation
+///
+/// The card holds the title of the note and the first lines of its plain text, wrapped to the width of the card, with
+/// an ellipsis if the note is longer. It is drawn from an SVG with the fonts of the system, so it looks the same
+/// whatever the window shows.
+/// The image is written to the cache directory of the application, replacing the previous card of the note.
+///
+/// # Returns
+///
+/// Returns the path of the written image, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it is secure while the vault is locked, if the
+/// image cannot be drawn or written, or if there is an issue with the database connection.
+pub fn export_note_card(note_id: i64, theme: CardTheme) -> Result<String, AppError> {
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if note.secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to export a secure note".to_string()));
+    }
+
+    let svg = card_svg(&note.title, &preview::plain_text(&note.content), theme);
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_str(&svg, &options).map_err(|e| format!("Failed to draw the card: {}", e))?;
+    let mut pixmap = tiny_skia::Pixmap::new(CARD_WIDTH, CARD_HEIGHT).ok_or("Failed to draw the card".to_string())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    let path = app_paths::cards_dir()?.join(format!("note-{}.png", note_id));
+    pixmap.save_png(&path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(path.display().to_string())
+}
+
+
+/// Builds the SVG of a card.
+fn card_svg(title: &str, text: &str, theme: CardTheme) -> String {
+    let (background, foreground, muted, accent) = match theme {
+        CardTheme::Light => ("#ffffff", "#1f2328", "#656d76", "#0969da"),
+        CardTheme::Dark => ("#0d1117", "#e6edf3", "#8d96a0", "#4493f8"),
+    };
+
+    let mut lines = wrap(text, CARD_LINE_LENGTH);
+    if lines.len() > CARD_MAX_LINES {
+        lines.truncate(CARD_MAX_LINES);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    let text_lines: String = lines.iter().enumerate().map(|(index, line)| format!(
+        r#"<tspan x="{}" dy="{}">{}</tspan>"#,
+        CARD_MARGIN,
+        if index == 0 { 0 } else { 44 },
+        escape(line),
+    )).collect();
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">
+<rect width="100%" height="100%" fill="{background}"/>
+<rect width="{width}" height="12" fill="{accent}"/>
+<text x="{margin}" y="{title_y}" font-family="sans-serif" font-size="52" font-weight="bold" fill="{foreground}">{title}</text>
+<text x="{margin}" y="{text_y}" font-family="sans-serif" font-size="32" fill="{foreground}">{text_lines}</text>
+<text x="{margin}" y="{footer_y}" font-family="sans-serif" font-size="24" fill="{muted}">{footer}</text>
+</svg>"##,
+        width = CARD_WIDTH,
+        height = CARD_HEIGHT,
+        margin = CARD_MARGIN,
+        title_y = CARD_MARGIN + 52,
+        text_y = CARD_MARGIN + 130,
+        footer_y = CARD_HEIGHT - CARD_MARGIN / 2,
+        title = escape(&shorten(title, CARD_TITLE_LENGTH)),
+        footer = CARD_FOOTER,
+    )
+}
+
+
+/// Wraps a text into lines of at most a number of characters, between words, without the blank lines. A word longer
+/// than a line is cut.
+fn wrap(text: &str, length: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines().map(str::trim).filter(|paragraph| !paragraph.is_empty()) {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let word = shorten(word, length);
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > length {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+
+/// Cuts a text at a number of characters, with an ellipsis if it is longer.
+fn shorten(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+    let mut shortened: String = text.chars().take(length - 1).collect();
+    shortened.push('…');
+    shortened
+}
+
+
+/// Escapes a text for the content of an SVG element.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
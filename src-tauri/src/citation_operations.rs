@@ -0,0 +1,244 @@
+// citation_operations.rs
+
+use crate::{ preview, local_operations::CONNECTION, models::Citation };
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use std::collections::BTreeSet;
+
+
+lazy_static! {
+    /// Matches a Pandoc citation group, e.g. `[@smith2020]` or `[see @smith2020, p. 4; @doe2019]`.
+    static ref CITATION_GROUP: Regex = Regex::new(r"\[[^\[\]]*@[^\[\]]*\]").unwrap();
+
+    /// Matches a citation key inside a citation group.
+    static ref CITATION_KEY: Regex = Regex::new(r"@([A-Za-z0-9_][A-Za-z0-9_:.#$%&+?<>~/-]*)").unwrap();
+}
+
+
+/// Records the citation keys of a note in the "note_citations" table, replacing the previous ones.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `content` - The plaintext content of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn index_citations(conn: &Connection, note_id: i64, content: &str) -> Result<(), String> {
+    forget_citations(conn, note_id)?;
+    for key in citation_keys(&preview::plain_text(content)) {
+        conn.execute(
+            "INSERT INTO note_citations (note_id, citation_key) VALUES (?1, ?2)",
+            params![note_id, key],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+
+/// Removes the citation keys of a deleted note from the "note_citations" table.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_citations(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM note_citations WHERE note_id = ?1",
+        params![note_id],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Retrieves the citations of a note.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the citation keys of the note, sorted, with whether the attached BibTeX library has an entry for each.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_note_citations(note_id: i64) -> Result<Vec<Citation>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT c.citation_key, e.citation_key IS NOT NULL FROM note_citations c
+        LEFT JOIN bib_entries e ON e.citation_key = c.citation_key
+        WHERE c.note_id = ?1 ORDER BY c.citation_key"
+    ).map_err(|e| e.to_string())?;
+    let citation_iter = stmt.query_map(params![note_id], |row| {
+        Ok(Citation {
+            key: row.get(0)?,
+            in_library: row.get(1)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    citation_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Attaches a BibTeX library, replacing the previously attached one.
+///
+/// # Arguments
+///
+/// * `path` - The path of the .bib file.
+///
+/// # Operation
+///
+/// * The file is parsed into entries, skipping the `@comment`, `@string`, and `@preamble` blocks.
+/// * The entries are stored in the "bib_entries" table, so the file is no longer needed afterwards.
+///
+/// # Returns
+///
+/// Returns the number of entries in the library, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read, if it has no entries, or if there is an issue
+/// with the database connection.
+pub fn attach_bibliography(path: &str) -> Result<usize, String> {
+    let library = std::fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let entries = parse_bibtex(&library);
+    if entries.is_empty() {
+        return Err(format!("No BibTeX entries found in '{}'", path));
+    }
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM bib_entries", []).map_err(|e| e.to_string())?;
+    for (key, entry) in &entries {
+        tx.execute(
+            "INSERT OR REPLACE INTO bib_entries (citation_key, entry) VALUES (?1, ?2)",
+            params![key, entry],
+        ).map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(entries.len())
+}
+
+
+/// Builds the BibTeX bibliography of the entries cited by some notes.
+///
+/// # Arguments
+///
+/// * `note_ids` - The IDs of the notes.
+///
+/// # Returns
+///
+/// Returns the content of a .bib file with the entries of the attached library cited by the notes, sorted by key.
+/// Keys missing from the library are listed in a comment at the end.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn export_bibliography(note_ids: Vec<i64>) -> Result<String, String> {
+    if note_ids.is_empty() {
+        return Ok(String::new());
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    let placeholders = vec!["?"; note_ids.len()].join(", ");
+    let params: Vec<&dyn rusqlite::ToSql> = note_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT c.citation_key, e.entry FROM note_citations c
+        LEFT JOIN bib_entries e ON e.citation_key = c.citation_key
+        WHERE c.note_id IN ({}) ORDER BY c.citation_key",
+        placeholders
+    )).map_err(|e| e.to_string())?;
+    let entry_iter = stmt.query_map(params.as_slice(), |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut bibliography = String::new();
+    let mut missing_keys = Vec::new();
+    for entry in entry_iter {
+        match entry.map_err(|e| e.to_string())? {
+            (_, Some(entry)) => {
+                bibliography.push_str(&entry);
+                bibliography.push_str("\n\n");
+            },
+            (key, None) => missing_keys.push(key),
+        }
+    }
+    if !missing_keys.is_empty() {
+        bibliography.push_str(&format!("% Missing from the library: {}\n", missing_keys.join(", ")));
+    }
+
+    Ok(bibliography)
+}
+
+
+/// Extracts the distinct citation keys of a text.
+fn citation_keys(text: &str) -> BTreeSet<String> {
+    CITATION_GROUP.find_iter(text)
+        .flat_map(|group| CITATION_KEY.captures_iter(group.as_str()))
+        // Keys end with a letter or a digit, e.g. in "[@smith2020.]" the dot is punctuation
+        .map(|captures| captures[1].trim_end_matches(|c: char| !c.is_alphanumeric()).to_string())
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+
+/// Parses the entries of a BibTeX library.
+///
+/// # Returns
+///
+/// Returns the key and the full text of each entry.
+fn parse_bibtex(library: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut rest = library;
+
+    while let Some(start) = rest.find('@') {
+        let entry = &rest[start..];
+        let open = match entry.find(['{', '(']) {
+            Some(open) => open,
+            None => break,
+        };
+        let entry_type = entry[1..open].trim().to_lowercase();
+
+        // Find the closing delimiter by counting the nested braces
+        let close_char = if entry.as_bytes()[open] == b'{' { '}' } else { ')' };
+        let mut depth = 0;
+        let mut end = None;
+        for (offset, c) in entry[open..].char_indices() {
+            if c == '{' || offset == 0 {
+                depth += 1;
+            } else if c == '}' || (c == close_char && depth == 1) {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(open + offset);
+                    break;
+                }
+            }
+        }
+        let end = match end {
+            Some(end) => end,
+            None => break,
+        };
+
+        if !matches!(entry_type.as_str(), "comment" | "string" | "preamble") {
+            let key = entry[open + 1..end].split(',').next().unwrap_or_default().trim();
+            if !key.is_empty() {
+                entries.push((key.to_string(), entry[..=end].trim().to_string()));
+            }
+        }
+        rest = &entry[end + 1..];
+    }
+
+    entries
+}
+
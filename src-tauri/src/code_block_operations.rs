@@ -0,0 +1,384 @@
+// code_block_operations.rs
+
+use crate::{ crypto, delta, vault, local_operations::CONNECTION, models::AppError, models::CodeBlock, models::Snippet };
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+
+
+/// The minimum score of a language for it to be detected, so a few common keywords do not label a block.
+const DETECTION_THRESHOLD: u32 = 3;
+
+
+lazy_static! {
+    /// The patterns giving away the language of a piece of code, with their weight, by language.
+    /// On a tie, the language listed first wins, which is why TypeScript comes before JavaScript and C++ before C.
+    static ref LANGUAGE_HINTS: Vec<(&'static str, Vec<(Regex, u32)>)> = [
+        ("rust", vec![
+            (r"\bfn\s+\w+\s*[<(]", 3),
+            (r"\blet\s+mut\b", 3),
+            (r"\bpub\s+(fn|struct|enum|mod|trait)\b", 3),
+            (r"&(mut\s+)?self\b", 3),
+            (r"\b(println|vec|format)!", 3),
+            (r"\bimpl\b", 2),
+            (r"::", 1),
+        ]),
+        ("python", vec![
+            (r"(?m)^\s*def\s+\w+\s*\(.*\)\s*(->.*)?:\s*$", 3),
+            (r"(?m)^\s*class\s+\w+(\(.*\))?:\s*$", 3),
+            (r"(?m)^\s*(if|elif|else|for|while|try|except|with)\b.*:\s*$", 2),
+            (r"(?m)^\s*(from\s+[\w.]+\s+)?import\s+\w", 1),
+            (r"\bself\.", 1),
+            (r"\b(None|True|False)\b", 1),
+            (r"\bprint\(", 1),
+        ]),
+        ("typescript", vec![
+            (r":\s*(string|number|boolean|any|void|unknown)\b", 3),
+            (r"\binterface\s+\w+\s*\{", 2),
+            (r"\btype\s+\w+\s*=", 2),
+            (r"\b(const|let)\s+\w+\s*:", 2),
+        ]),
+        ("javascript", vec![
+            (r"\bconsole\.log\(", 3),
+            (r"\bfunction\b", 2),
+            (r"\brequire\(", 2),
+            (r"\b(document|window)\.", 2),
+            (r"===|!==", 2),
+            (r"\b(const|let|var)\s+\w+\s*=", 1),
+            (r"=>", 1),
+        ]),
+        ("go", vec![
+            (r"(?m)^\s*package\s+\w+\s*$", 3),
+            (r"\bfunc\s+(\(\w+\s+\*?\w+\)\s*)?\w+\(", 3),
+            (r"\bfmt\.\w+", 3),
+            (r":=", 2),
+        ]),
+        ("java", vec![
+            (r"\bpublic\s+(static\s+)?(class|void|final)\b", 3),
+            (r"\bSystem\.out\.print", 3),
+            (r"\bString\[\]", 2),
+            (r"\b(private|protected)\s+\w+", 1),
+        ]),
+        ("cpp", vec![
+            (r"#include\s*<\w+>", 3),
+            (r"\bstd::", 3),
+            (r"\bcout\s*<<", 3),
+            (r"\btemplate\s*<", 3),
+        ]),
+        ("c", vec![
+            (r#"#include\s*[<"]\w+\.h[>"]"#, 3),
+            (r"\bint\s+main\s*\(", 2),
+            (r"\bprintf\(", 2),
+            (r"\bmalloc\(", 2),
+        ]),
+        ("shell", vec![
+            (r"^#!/(usr/)?bin/(env\s+)?(ba|z)?sh", 5),
+            (r"\|\s*(grep|awk|sed|xargs)\b", 3),
+            (r"(?m)^\s*\$\s+\w", 2),
+            (r"(?m)^\s*(sudo|apt|brew|npm|cargo|git|cd|ls|echo|export|curl|pip)\s", 2),
+            (r"(?m)^\s*(fi|done|esac)\s*$", 2),
+        ]),
+        ("sql", vec![
+            (r"(?is)\bselect\b.+\bfrom\b", 3),
+            (r"(?i)\b(insert\s+into|create\s+table|update\s+\w+\s+set|delete\s+from)\b", 3),
+            (r"(?i)\bwhere\b", 1),
+        ]),
+        ("html", vec![
+            (r"(?i)<(!doctype|html|head|body|div|span|p|a|ul|li|table)\b", 3),
+            (r"</\w+>", 2),
+        ]),
+        ("css", vec![
+            (r"(?m)^\s*[\w-]+\s*:\s*[^;{}]+;\s*$", 2),
+            (r"(?m)^\s*[.#]?[\w-]+([\s,>+~]+[.#]?[\w-]+)*\s*\{", 1),
+            (r"@media\b", 3),
+        ]),
+        ("yaml", vec![
+            (r"(?m)^---\s*$", 2),
+            (r"(?m)^\s*-\s+[\w-]+:\s", 2),
+            (r"(?m)^[\w-]+:(\s+\S.*)?$", 1),
+        ]),
+    ].into_iter()
+        .map(|(language, hints)| (language, hints.into_iter().map(|(pattern, weight)| (Regex::new(pattern).unwrap(), weight)).collect()))
+        .collect();
+}
+
+
+/// Detects the language of a piece of code.
+///
+/// # Arguments
+///
+/// * `code` - The code to label.
+///
+/// # Operation
+///
+/// * JSON is recognized by parsing it.
+/// * Other languages are scored by the weight of the patterns they match, e.g. `fn main(` for Rust.
+///
+/// # Returns
+///
+/// Returns the language with the best score, or `None` if no language scores at least 3.
+pub fn detect_language(code: &str) -> Option<String> {
+    let trimmed = code.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('[')) && serde_json::from_str::<serde_json::Value>(trimmed).is_ok() {
+        return Some("json".to_string());
+    }
+
+    let mut best: Option<(&str, u32)> = None;
+    for (language, hints) in LANGUAGE_HINTS.iter() {
+        let score = hints.iter()
+            .filter(|(pattern, _)| pattern.is_match(code))
+            .map(|(_, weight)| weight)
+            .sum();
+        if score >= DETECTION_THRESHOLD && best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((language, score));
+        }
+    }
+
+    best.map(|(language, _)| language.to_string())
+}
+
+
+/// Normalizes the language label of a code block, e.g. "rs" or "Rust" into "rust".
+///
+/// # Arguments
+///
+/// * `label` - The label of the block, e.g. the info string of a Markdown fence.
+///
+/// # Returns
+///
+/// Returns the label in lowercase, with the common aliases replaced by the name used by `detect_language`.
+pub fn normalize_language(label: &str) -> String {
+    let label = label.trim().to_lowercase();
+    match label.as_str() {
+        "rs" => "rust",
+        "py" | "python3" => "python",
+        "js" | "jsx" | "node" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "golang" => "go",
+        "c++" | "cc" | "cxx" | "hpp" => "cpp",
+        "h" => "c",
+        "sh" | "bash" | "zsh" | "console" | "shell-session" => "shell",
+        "yml" => "yaml",
+        "htm" | "xhtml" => "html",
+        _ => return label,
+    }.to_string()
+}
+
+
+/// Labels a code block with its language.
+///
+/// # Arguments
+///
+/// * `label` - The label of the block, if any.
+/// * `code` - The code of the block.
+///
+/// # Returns
+///
+/// Returns the language of the block, if known, and whether it was detected rather than labeled.
+pub fn block_language(label: Option<&str>, code: &str) -> (Option<String>, bool) {
+    match label {
+        Some(label) => (Some(normalize_language(label)), false),
+        None => {
+            let language = detect_language(code);
+            let detected = language.is_some();
+            (language, detected)
+        },
+    }
+}
+
+
+/// Records the code blocks of a note in the "code_blocks" table, replacing the previous ones.
+///
+/// The code is encrypted like the content of the note, since code blocks of secure notes are recorded too.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `content` - The plaintext content of the note.
+///
+/// # Errors
+///
+/// This function will return an error if the code cannot be encrypted or if there is an issue with the database connection.
+pub fn index_code_blocks(conn: &Connection, note_id: i64, content: &str) -> Result<(), String> {
+    forget_code_blocks(conn, note_id)?;
+    for (position, (label, code)) in delta::code_blocks(content).into_iter().enumerate() {
+        if code.trim().is_empty() {
+            continue;
+        }
+
+        let (language, detected) = block_language(label.as_deref(), &code);
+        let (encrypted_code, nonce_str) = crypto::encrypt_content(&code)?;
+        conn.execute(
+            "INSERT INTO code_blocks (note_id, position, language, detected, code, nonce) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![note_id, position as i64, language, detected, encrypted_code, nonce_str],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+
+/// Removes the code blocks of a deleted note from the "code_blocks" table.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_code_blocks(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM code_blocks WHERE note_id = ?1",
+        params![note_id],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Retrieves the code blocks of a note with their language.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the code blocks in the order of the note, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it is secure while the vault is locked,
+/// or if there is an issue with the database connection.
+pub fn get_note_code_blocks(note_id: i64) -> Result<Vec<CodeBlock>, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let secure: bool = conn.query_row(
+        "SELECT secure FROM notes WHERE id = ?1",
+        params![note_id],
+        |row| row.get(0),
+    ).optional()?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to read the code blocks of secure notes".to_string()));
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT id, note_id, position, language, detected, code, nonce FROM code_blocks WHERE note_id = ?1 ORDER BY position"
+    )?;
+    let rows = stmt.query_map(params![note_id], |row| {
+        Ok((
+            CodeBlock {
+                id: row.get(0)?,
+                note_id: row.get(1)?,
+                position: row.get(2)?,
+                language: row.get(3)?,
+                detected: row.get(4)?,
+                code: String::new(),
+            },
+            row.get::<_, String>(5)?,
+            row.get::<_, String>(6)?,
+        ))
+    })?;
+
+    let mut code_blocks = Vec::new();
+    for row in rows {
+        let (mut code_block, encrypted_code, nonce_str) = row?;
+        code_block.code = crypto::decrypt_content(&encrypted_code, &nonce_str)?;
+        code_blocks.push(code_block);
+    }
+
+    Ok(code_blocks)
+}
+
+
+/// Lists the code blocks of the notes as a snippet library.
+///
+/// # Arguments
+///
+/// * `language` - An optional language, e.g. "rust" or "rs". Only the snippets in the language are listed.
+/// * `query` - An optional text. Only the snippets whose code or note title contains it, ignoring case, are listed.
+///
+/// # Operation
+///
+/// * The snippets are read from the "snippets" view, newest notes first.
+/// * Snippets of secure notes are left out while the vault is locked.
+///
+/// # Returns
+///
+/// Returns the matching snippets, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if a snippet cannot be decrypted or if there is an issue with the database connection.
+pub fn list_snippets(language: Option<&str>, query: Option<&str>) -> Result<Vec<Snippet>, String> {
+    let language = language.map(normalize_language);
+    let query = query.map(|query| query.trim().to_lowercase()).filter(|query| !query.is_empty());
+
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, note_id, note_title, language, detected, code, nonce FROM snippets
+        WHERE (?1 IS NULL OR language = ?1) AND (secure = 0 OR ?2)
+        ORDER BY note_id DESC, id"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![language, vault::is_unlocked()], |row| {
+        Ok((
+            Snippet {
+                id: row.get(0)?,
+                note_id: row.get(1)?,
+                note_title: row.get(2)?,
+                language: row.get(3)?,
+                detected: row.get(4)?,
+                code: String::new(),
+            },
+            row.get::<_, String>(5)?,
+            row.get::<_, String>(6)?,
+        ))
+    }).map_err(|e| e.to_string())?;
+
+    let mut snippets = Vec::new();
+    for row in rows {
+        let (mut snippet, encrypted_code, nonce_str) = row.map_err(|e| e.to_string())?;
+        snippet.code = crypto::decrypt_content(&encrypted_code, &nonce_str)?;
+
+        // The code is encrypted, so the text is matched after decrypting it
+        let matches = query.as_ref().is_none_or(|query| {
+            snippet.code.to_lowercase().contains(query) || snippet.note_title.to_lowercase().contains(query)
+        });
+        if matches {
+            snippets.push(snippet);
+        }
+    }
+
+    Ok(snippets)
+}
+
+
+/// Retrieves the code of a snippet, for the frontend to put on the clipboard.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the snippet, as returned by `list_snippets`.
+///
+/// # Returns
+///
+/// Returns the code of the snippet, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the snippet does not exist, if its note is secure while the vault is locked,
+/// or if there is an issue with the database connection.
+pub fn copy_snippet(id: i64) -> Result<String, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let (secure, encrypted_code, nonce_str): (bool, String, String) = conn.query_row(
+        "SELECT secure, code, nonce FROM snippets WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).optional()?.ok_or(AppError::NotFound("Snippet not found".to_string()))?;
+    if secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to copy snippets of secure notes".to_string()));
+    }
+
+    crypto::decrypt_content(&encrypted_code, &nonce_str)
+}
@@ -0,0 +1,215 @@
+// collation.rs
+
+use crate::settings;
+use std::cmp::Ordering;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+
+/// The settings key under which the locale used to sort notes is stored.
+const LOCALE_SETTING: &str = "locale";
+
+/// The locale used when none is configured.
+const DEFAULT_LOCALE: &str = "en";
+
+
+/// A collation element at the primary level, i.e. ignoring accents and case.
+///
+/// The variants are declared in sort order: punctuation and spaces sort before numbers, which sort before letters.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Primary {
+    Symbol(char),
+    // A run of digits compared by value: the number of significant digits, then the digits themselves
+    Number(usize, String),
+    Letter(u32),
+}
+
+
+/// The collation key of a title, compared level by level.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    primary: Vec<Primary>,
+    secondary: Vec<u32>,
+    tertiary: Vec<bool>,
+}
+
+
+/// Retrieves the locale used to sort notes.
+///
+/// # Returns
+///
+/// Returns the configured locale, "en" if none is configured, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_locale() -> Result<String, String> {
+    Ok(settings::get_setting(LOCALE_SETTING)?.unwrap_or_else(|| DEFAULT_LOCALE.to_string()))
+}
+
+
+/// Stores the locale used to sort notes.
+///
+/// # Arguments
+///
+/// * `locale` - A BCP 47 language tag, e.g. "en", "sv" or "es-ES".
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the locale is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the locale is not a language tag or if there is an issue with the database connection.
+pub fn set_locale(locale: &str) -> Result<(), String> {
+    let is_tag = locale.split('-').all(|part| !part.is_empty() && part.len() <= 8 && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !is_tag {
+        return Err(format!("Invalid locale '{}'", locale));
+    }
+
+    settings::set_setting(LOCALE_SETTING, locale)
+}
+
+
+/// Compares two titles in natural, locale-aware order.
+///
+/// # Arguments
+///
+/// * `a` - The first title.
+/// * `b` - The second title.
+/// * `locale` - The locale whose alphabet is used, e.g. "sv" sorts "å", "ä" and "ö" after "z".
+///
+/// # Operation
+///
+/// * Runs of digits are compared by value, so "Note 2" sorts before "Note 10".
+/// * Letters are first compared without accents and case, so "école" sorts between "eclair" and "ecrire".
+/// * Ties are broken by accents, then by case, then by the raw titles.
+pub fn compare_titles(a: &str, b: &str, locale: &str) -> Ordering {
+    sort_key(a, locale).cmp(&sort_key(b, locale)).then_with(|| a.cmp(b))
+}
+
+
+/// Builds the collation key of a title.
+fn sort_key(title: &str, locale: &str) -> SortKey {
+    let language = locale.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+    let mut key = SortKey { primary: Vec::new(), secondary: Vec::new(), tertiary: Vec::new() };
+    let mut digits = String::new();
+
+    for c in title.nfc() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        push_number(&mut key, &mut digits);
+
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        key.tertiary.push(c != lower);
+
+        // Letters of the alphabet of the locale that sort after "z" or after their base letter
+        if let Some(weight) = tailored_weight(&language, lower) {
+            key.primary.push(Primary::Letter(weight));
+            key.secondary.push(0);
+            continue;
+        }
+
+        let mut accents = 0;
+        for d in expand(lower).nfd() {
+            if is_combining_mark(d) {
+                accents = accents * 31 + d as u32 % 31 + 1;
+            } else if d.is_alphabetic() {
+                key.primary.push(Primary::Letter(d as u32 * 2));
+            } else {
+                key.primary.push(Primary::Symbol(d));
+            }
+        }
+        key.secondary.push(accents);
+    }
+    push_number(&mut key, &mut digits);
+
+    key
+}
+
+
+/// Adds the pending run of digits to a collation key as a single number.
+fn push_number(key: &mut SortKey, digits: &mut String) {
+    if digits.is_empty() {
+        return;
+    }
+    let significant = digits.trim_start_matches('0').to_string();
+    key.primary.push(Primary::Number(significant.len(), significant));
+    // Leading zeros only matter at the secondary level, so "07" and "7" are equal at the primary level
+    key.secondary.push(digits.len() as u32);
+    digits.clear();
+}
+
+
+/// Expands the ligatures and letters that sort as several letters.
+fn expand(c: char) -> String {
+    match c {
+        'æ' => "ae".to_string(),
+        'œ' => "oe".to_string(),
+        'ß' => "ss".to_string(),
+        'ø' => "o".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+
+/// Returns the primary weight of the letters that have their own place in the alphabet of a language.
+///
+/// Weights are twice the code point of the letters, so a letter can be placed right after another one.
+fn tailored_weight(language: &str, c: char) -> Option<u32> {
+    let after_z = 'z' as u32 * 2 + 1;
+    let alphabet: &[char] = match language {
+        "sv" | "fi" => &['å', 'ä', 'ö'],
+        "da" | "nb" | "nn" | "no" => &['æ', 'ø', 'å'],
+        "es" => return (c == 'ñ').then_some('n' as u32 * 2 + 1),
+        _ => &[],
+    };
+
+    alphabet.iter().position(|&letter| letter == c).map(|position| after_z + position as u32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(titles: &[&str], locale: &str) -> Vec<String> {
+        let mut titles: Vec<String> = titles.iter().map(|title| title.to_string()).collect();
+        titles.sort_by(|a, b| compare_titles(a, b, locale));
+        titles
+    }
+
+    #[test]
+    fn compare_titles_ignores_case_at_the_primary_level() {
+        assert_eq!(sorted(&["banana", "Apple", "apple", "Cherry"], "en"), vec!["apple", "Apple", "banana", "Cherry"]);
+    }
+
+    #[test]
+    fn compare_titles_sorts_accents_after_their_base_letter() {
+        assert_eq!(sorted(&["ecrire", "école", "eclair"], "en"), vec!["eclair", "école", "ecrire"]);
+        assert_eq!(compare_titles("resume", "résumé", "en"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_titles_uses_the_alphabet_of_the_locale() {
+        assert_eq!(sorted(&["ö", "z", "å", "ä"], "sv"), vec!["z", "å", "ä", "ö"]);
+        assert_eq!(sorted(&["ö", "z", "o"], "en"), vec!["o", "ö", "z"]);
+        assert_eq!(sorted(&["ñu", "nz", "oa"], "es-ES"), vec!["nz", "ñu", "oa"]);
+    }
+
+    #[test]
+    fn compare_titles_compares_numbers_by_value() {
+        assert_eq!(sorted(&["Note 10", "Note 2", "Note 1"], "en"), vec!["Note 1", "Note 2", "Note 10"]);
+        assert_eq!(sorted(&["Note 7", "Note 07", "Note 6"], "en"), vec!["Note 6", "Note 7", "Note 07"]);
+        assert_eq!(sorted(&["a", "1", "-"], "en"), vec!["-", "1", "a"]);
+    }
+
+    #[test]
+    fn set_locale_rejects_invalid_tags() {
+        assert!(set_locale("").is_err());
+        assert!(set_locale("en--US").is_err());
+        assert!(set_locale("en US").is_err());
+    }
+}
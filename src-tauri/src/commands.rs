@@ -0,0 +1,451 @@
+// commands.rs
+
+use crate::models::{ApiScope, CommandArg, CommandDescriptor};
+
+
+/// The types of the arguments, as named by JSON Schema.
+const STRING: &str = "string";
+const INTEGER: &str = "integer";
+const BOOLEAN: &str = "boolean";
+const ARRAY: &str = "array";
+const OBJECT: &str = "object";
+
+/// The categories under which the commands are grouped.
+const NOTES: &str = "Notes";
+const ATTACHMENTS: &str = "Attachments";
+const SEARCH: &str = "Search";
+const VAULT: &str = "Vault";
+const BUCKETS: &str = "Buckets";
+const CLOUD: &str = "Cloud";
+const IMPORT_EXPORT: &str = "Import and export";
+const EMAIL: &str = "Email";
+const SETTINGS: &str = "Settings";
+const DIAGNOSTICS: &str = "Diagnostics";
+const UPDATES: &str = "Updates";
+
+/// The scopes an API token needs to run the commands (see `required_scope`).
+const READ_ONLY: ApiScope = ApiScope::ReadOnly;
+const READ_WRITE: ApiScope = ApiScope::ReadWrite;
+const ADMIN: ApiScope = ApiScope::Admin;
+
+
+/// The description of a command.
+struct CommandSpec {
+    id: &'static str,
+    title: &'static str,
+    category: &'static str,
+    scope: ApiScope,
+    args: &'static [ArgSpec],
+}
+
+/// The description of an argument of a command.
+struct ArgSpec {
+    name: &'static str,
+    kind: &'static str,
+    required: bool,
+}
+
+/// Describes a required argument.
+const fn arg(name: &'static str, kind: &'static str) -> ArgSpec {
+    ArgSpec { name, kind, required: true }
+}
+
+/// Describes an optional argument.
+const fn optional(name: &'static str, kind: &'static str) -> ArgSpec {
+    ArgSpec { name, kind, required: false }
+}
+
+
+/// The commands of `handlers` the user can run from the command palette, in the order of their category.
+///
+/// Each command has the scope a token needs to run it: admin for the commands of the vault, the diagnostics, and the
+/// updates, and for the ones that delete many notes at once, change how the application reaches its remotes or what
+/// it stores unencrypted, handle credentials, or open connections elsewhere, read-only for the ones that change
+/// nothing, not even a record of what was opened, and read-write for the others.
+///
+/// "drafts_flushed" is left out, since it only answers the shutdown and must not be run by the user, and so are
+/// "take_opened_notes", which hands the opened files over to the window, and "run_benchmarks", which is only built
+/// for development.
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { id: "create_local_note", title: "Create note", category: NOTES, scope: READ_WRITE, args: &[arg("note", OBJECT)] },
+    CommandSpec { id: "get_local_note", title: "Open note", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "update_local_note", title: "Update note", category: NOTES, scope: READ_WRITE, args: &[arg("note", OBJECT)] },
+    CommandSpec { id: "delete_local_note", title: "Delete note", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "list_trashed_notes", title: "Show trash", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "restore_note", title: "Restore note from trash", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "purge_note", title: "Delete note permanently", category: NOTES, scope: ADMIN, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "get_trash_retention", title: "Show trash retention", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_trash_retention", title: "Set trash retention", category: NOTES, scope: ADMIN, args: &[arg("days", INTEGER)] },
+    CommandSpec { id: "get_local_notes", title: "List notes", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "get_local_note_summaries", title: "List note summaries", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "get_local_notes_paged", title: "List notes by page", category: NOTES, scope: READ_ONLY, args: &[arg("offset", INTEGER), arg("limit", INTEGER), optional("options", OBJECT)] },
+    CommandSpec { id: "render_markdown", title: "Render note as HTML", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "render_content", title: "Render Markdown", category: NOTES, scope: READ_ONLY, args: &[arg("text", STRING)] },
+    CommandSpec { id: "get_tray_menu_items", title: "List notes pinned to tray", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "pin_note_to_tray", title: "Pin note to tray", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "unpin_note_from_tray", title: "Unpin note from tray", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "delete_all_local_notes", title: "Delete all notes", category: NOTES, scope: ADMIN, args: &[] },
+    CommandSpec { id: "list_note_templates", title: "List templates", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "save_note_template", title: "Save template", category: NOTES, scope: READ_WRITE, args: &[arg("template", OBJECT)] },
+    CommandSpec { id: "delete_note_template", title: "Delete template", category: NOTES, scope: READ_WRITE, args: &[arg("name", STRING)] },
+    CommandSpec { id: "create_note_from_template", title: "New note from template", category: NOTES, scope: READ_WRITE, args: &[arg("name", STRING), optional("values", OBJECT)] },
+    CommandSpec { id: "list_scheduled_notes", title: "List scheduled notes", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "create_schedule", title: "Schedule note from template", category: NOTES, scope: READ_WRITE, args: &[arg("config", OBJECT)] },
+    CommandSpec { id: "delete_schedule", title: "Delete note schedule", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "merge_notes", title: "Merge notes", category: NOTES, scope: READ_WRITE, args: &[arg("ids", ARRAY), optional("separator", STRING)] },
+    CommandSpec { id: "split_note", title: "Split note", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("delimiter", STRING)] },
+    CommandSpec { id: "set_note_status", title: "Set note status", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), optional("status", STRING)] },
+    CommandSpec { id: "get_status_counts", title: "Count notes by status", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_note_icon", title: "Set note icon", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), optional("icon", STRING)] },
+    CommandSpec { id: "set_note_locked", title: "Lock or unlock note", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("locked", BOOLEAN)] },
+    CommandSpec { id: "set_note_pinned", title: "Pin or unpin note", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("pinned", BOOLEAN)] },
+    CommandSpec { id: "set_note_archived", title: "Archive or unarchive note", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("archived", BOOLEAN)] },
+    CommandSpec { id: "set_note_cover", title: "Set note cover", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), optional("attachment_id", INTEGER)] },
+    CommandSpec { id: "list_note_versions", title: "List note versions", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "restore_note_version", title: "Restore note version", category: NOTES, scope: READ_WRITE, args: &[arg("version_id", INTEGER)] },
+    CommandSpec { id: "get_note_code_blocks", title: "List code blocks of note", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "list_snippets", title: "List snippets", category: NOTES, scope: READ_ONLY, args: &[optional("lang", STRING), optional("query", STRING)] },
+    CommandSpec { id: "copy_snippet", title: "Copy snippet", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "get_note_citations", title: "List citations of note", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "get_note_source", title: "Show web source of note", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "create_notebook", title: "Create notebook", category: NOTES, scope: READ_WRITE, args: &[arg("name", STRING), optional("parent_id", INTEGER)] },
+    CommandSpec { id: "rename_notebook", title: "Rename notebook", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("name", STRING)] },
+    CommandSpec { id: "delete_notebook", title: "Delete notebook", category: NOTES, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "list_notebooks", title: "List notebooks", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "move_notes_to_notebook", title: "Move notes to notebook", category: NOTES, scope: READ_WRITE, args: &[arg("note_ids", ARRAY), optional("notebook_id", INTEGER)] },
+    CommandSpec { id: "get_notes_by_notebook", title: "Browse notebook", category: NOTES, scope: READ_ONLY, args: &[optional("notebook_id", INTEGER), optional("include_sub_notebooks", BOOLEAN)] },
+    CommandSpec { id: "create_notebook_note", title: "Create note in notebook", category: NOTES, scope: READ_WRITE, args: &[arg("notebook_id", INTEGER), arg("note", OBJECT)] },
+    CommandSpec { id: "get_notebook_notes", title: "List notes in notebook", category: NOTES, scope: READ_ONLY, args: &[arg("notebook_id", INTEGER), optional("options", OBJECT)] },
+    CommandSpec { id: "get_notebook_schema", title: "Show notebook fields", category: NOTES, scope: READ_ONLY, args: &[arg("notebook_id", INTEGER)] },
+    CommandSpec { id: "set_notebook_schema", title: "Set notebook fields", category: NOTES, scope: READ_WRITE, args: &[arg("notebook_id", INTEGER), arg("fields", ARRAY)] },
+    CommandSpec { id: "get_note_fields", title: "Show note fields", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "set_note_fields", title: "Set note fields", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("values", OBJECT)] },
+    CommandSpec { id: "get_notebook_settings", title: "Show notebook settings", category: NOTES, scope: READ_ONLY, args: &[arg("notebook_id", INTEGER)] },
+    CommandSpec { id: "set_notebook_settings", title: "Set notebook settings", category: NOTES, scope: READ_WRITE, args: &[arg("notebook_id", INTEGER), arg("settings", OBJECT)] },
+    CommandSpec { id: "get_note_aliases", title: "Show note aliases", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "set_note_aliases", title: "Set note aliases", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("aliases", ARRAY)] },
+    CommandSpec { id: "add_tag_to_note", title: "Add tag to note", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("tag", STRING)] },
+    CommandSpec { id: "remove_tag_from_note", title: "Remove tag from note", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("tag", STRING)] },
+    CommandSpec { id: "get_notes_by_tag", title: "List notes with tag", category: NOTES, scope: READ_ONLY, args: &[arg("tag", STRING)] },
+    CommandSpec { id: "list_tags", title: "List tags", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "rename_tag", title: "Rename tag", category: NOTES, scope: READ_WRITE, args: &[arg("old", STRING), arg("new", STRING)] },
+    CommandSpec { id: "merge_tags", title: "Merge tags", category: NOTES, scope: READ_WRITE, args: &[arg("source", STRING), arg("target", STRING)] },
+    CommandSpec { id: "resolve_wikilink", title: "Open linked note", category: NOTES, scope: READ_ONLY, args: &[arg("name", STRING)] },
+    CommandSpec { id: "get_backlinks", title: "List notes linking to note", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "find_unlinked_mentions", title: "Find unlinked mentions of note", category: NOTES, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "check_links", title: "Check links of notes", category: NOTES, scope: READ_WRITE, args: &[optional("check_urls", BOOLEAN)] },
+    CommandSpec { id: "get_periodic_note", title: "Open periodic note", category: NOTES, scope: READ_WRITE, args: &[arg("kind", STRING), optional("date", STRING)] },
+    CommandSpec { id: "get_period", title: "Go to period", category: NOTES, scope: READ_ONLY, args: &[arg("kind", STRING), optional("date", STRING), optional("offset", INTEGER)] },
+    CommandSpec { id: "mark_for_review", title: "Add note to review queue", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "unmark_for_review", title: "Remove note from review queue", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "get_due_reviews", title: "List notes due for review", category: NOTES, scope: READ_ONLY, args: &[optional("limit", INTEGER)] },
+    CommandSpec { id: "record_review", title: "Record note review", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("grade", INTEGER)] },
+    CommandSpec { id: "list_due_cards", title: "Study due flashcards", category: NOTES, scope: READ_ONLY, args: &[optional("limit", INTEGER), optional("note_id", INTEGER), optional("tag", STRING)] },
+    CommandSpec { id: "answer_card", title: "Answer flashcard", category: NOTES, scope: READ_WRITE, args: &[arg("card_id", INTEGER), arg("grade", INTEGER)] },
+    CommandSpec { id: "get_deck_stats", title: "Show flashcard deck statistics", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "export_anki", title: "Export flashcards to Anki", category: NOTES, scope: READ_WRITE, args: &[arg("deck_scope", OBJECT), arg("path", STRING)] },
+    CommandSpec { id: "start_focus_session", title: "Start focus session", category: NOTES, scope: READ_WRITE, args: &[arg("note_id", INTEGER), optional("planned_minutes", INTEGER)] },
+    CommandSpec { id: "stop_focus_session", title: "Stop focus session", category: NOTES, scope: READ_WRITE, args: &[] },
+    CommandSpec { id: "get_focus_session", title: "Show focus session", category: NOTES, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "get_time_report", title: "Show time spent on notes", category: NOTES, scope: READ_ONLY, args: &[optional("range", STRING), optional("group_by", STRING)] },
+
+    CommandSpec { id: "add_attachment", title: "Add attachment", category: ATTACHMENTS, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("path", STRING)] },
+    CommandSpec { id: "get_note_attachments", title: "List attachments of note", category: ATTACHMENTS, scope: READ_ONLY, args: &[arg("note_id", INTEGER)] },
+    CommandSpec { id: "get_attachment_data", title: "Open attachment", category: ATTACHMENTS, scope: READ_ONLY, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "delete_attachment", title: "Delete attachment", category: ATTACHMENTS, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "save_annotation", title: "Save annotation", category: ATTACHMENTS, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("annotation", STRING)] },
+    CommandSpec { id: "get_annotation", title: "Open annotation", category: ATTACHMENTS, scope: READ_ONLY, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "reextract_attachment_text", title: "Extract attachment text again", category: ATTACHMENTS, scope: READ_WRITE, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "get_attachment_text_status", title: "Show attachment text status", category: ATTACHMENTS, scope: READ_ONLY, args: &[arg("id", INTEGER)] },
+
+    CommandSpec { id: "search_in_notes", title: "Search notes", category: SEARCH, scope: READ_ONLY, args: &[arg("query", STRING), arg("local", BOOLEAN), optional("bucket_name", STRING)] },
+    CommandSpec { id: "search_all_notes", title: "Search all notes and buckets", category: SEARCH, scope: READ_ONLY, args: &[arg("query", STRING)] },
+    CommandSpec {
+        id: "replace_in_notes",
+        title: "Find and replace in notes",
+        category: SEARCH,
+        scope: READ_WRITE,
+        args: &[arg("query", STRING), arg("replacement", STRING), optional("regex", BOOLEAN), optional("scope", OBJECT), optional("dry_run", BOOLEAN)],
+    },
+    CommandSpec { id: "get_index_status", title: "Show search index status", category: SEARCH, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "quick_switch", title: "Quick switch", category: SEARCH, scope: READ_ONLY, args: &[arg("query", STRING), optional("limit", INTEGER)] },
+    CommandSpec { id: "get_index_protection", title: "Show search index protection", category: SEARCH, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_index_protection", title: "Set search index protection", category: SEARCH, scope: READ_WRITE, args: &[arg("protection", STRING)] },
+
+    CommandSpec { id: "set_note_secure", title: "Set note secure", category: VAULT, scope: ADMIN, args: &[arg("id", INTEGER), arg("secure", BOOLEAN)] },
+    CommandSpec { id: "unlock_vault", title: "Unlock vault", category: VAULT, scope: ADMIN, args: &[optional("passphrase", STRING)] },
+    CommandSpec { id: "lock_vault", title: "Lock vault", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_vault_status", title: "Show vault status", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "set_passphrase", title: "Set vault passphrase", category: VAULT, scope: ADMIN, args: &[optional("passphrase", STRING)] },
+    CommandSpec { id: "set_vault_password", title: "Set vault password", category: VAULT, scope: ADMIN, args: &[optional("current_password", STRING), arg("password", STRING)] },
+    CommandSpec { id: "export_encryption_key", title: "Export encryption key", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_encryption_key_error", title: "Show encryption key problem", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "import_encryption_key", title: "Import encryption key", category: VAULT, scope: ADMIN, args: &[arg("key", STRING)] },
+    CommandSpec { id: "get_schema_status", title: "Show database schema version", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_encryption_cipher", title: "Show encryption cipher", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "set_encryption_cipher", title: "Set encryption cipher", category: VAULT, scope: ADMIN, args: &[arg("cipher", STRING)] },
+    CommandSpec { id: "select_fastest_cipher", title: "Benchmark and select encryption cipher", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_reencryption_status", title: "Show encryption progress", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "resume_reencryption", title: "Resume encryption with the new key", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_vault_lock_status", title: "Show vault lock", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec { id: "take_over_vault_lock", title: "Take over vault lock", category: VAULT, scope: ADMIN, args: &[] },
+    CommandSpec {
+        id: "inspect_note_envelope",
+        title: "Inspect note encryption",
+        category: VAULT,
+        scope: ADMIN,
+        args: &[optional("note_id", INTEGER), optional("bucket_name", STRING), optional("uuid", STRING)],
+    },
+
+    CommandSpec { id: "create_bucket", title: "Create bucket", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "fetch_buckets", title: "List buckets", category: BUCKETS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "delete_bucket", title: "Delete bucket", category: BUCKETS, scope: ADMIN, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "upload_note_to_bucket", title: "Upload note to bucket", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING), arg("note", OBJECT)] },
+    CommandSpec { id: "fetch_bucket_note", title: "Open bucket note", category: BUCKETS, scope: READ_ONLY, args: &[arg("bucket_name", STRING), arg("uuid", STRING)] },
+    CommandSpec { id: "update_bucket_note", title: "Update bucket note", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING), arg("note", OBJECT)] },
+    CommandSpec { id: "delete_bucket_note", title: "Delete bucket note", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING), arg("uuid", STRING)] },
+    CommandSpec { id: "fetch_bucket_notes", title: "List bucket notes", category: BUCKETS, scope: READ_ONLY, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "list_bucket_note_stats", title: "List bucket note statistics", category: BUCKETS, scope: READ_ONLY, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "delete_bucket_notes", title: "Delete all bucket notes", category: BUCKETS, scope: ADMIN, args: &[arg("bucket_name", STRING)] },
+    CommandSpec {
+        id: "set_note_presence",
+        title: "Set presence on bucket note",
+        category: BUCKETS,
+        scope: READ_WRITE,
+        args: &[arg("bucket_name", STRING), arg("uuid", STRING), arg("editing", BOOLEAN)],
+    },
+    CommandSpec { id: "get_note_presence", title: "Show who is on bucket note", category: BUCKETS, scope: READ_ONLY, args: &[arg("bucket_name", STRING), arg("uuid", STRING)] },
+    CommandSpec { id: "set_display_name", title: "Set display name", category: BUCKETS, scope: READ_WRITE, args: &[arg("display_name", STRING)] },
+    CommandSpec { id: "find_orphaned_bucket_objects", title: "Find orphaned bucket objects", category: BUCKETS, scope: READ_ONLY, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "migrate_bucket_keys", title: "Migrate bucket note keys", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "migrate_bucket_format", title: "Migrate bucket to the current format", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "clean_orphaned_objects", title: "Clean orphaned bucket objects", category: BUCKETS, scope: ADMIN, args: &[arg("bucket_name", STRING), arg("keys", ARRAY)] },
+    CommandSpec { id: "export_bucket_inventory", title: "Export bucket inventory", category: BUCKETS, scope: READ_WRITE, args: &[arg("bucket_name", STRING), arg("path", STRING)] },
+
+    CommandSpec {
+        id: "login_sso",
+        title: "Sign in with SSO",
+        category: CLOUD,
+        scope: ADMIN,
+        args: &[arg("start_url", STRING), arg("region", STRING), arg("account_id", STRING), arg("role_name", STRING)],
+    },
+    CommandSpec { id: "logout_sso", title: "Sign out of SSO", category: CLOUD, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_cloud_usage_report", title: "Show cloud usage", category: CLOUD, scope: READ_ONLY, args: &[optional("month", STRING)] },
+    CommandSpec { id: "get_cloud_budget", title: "Show cloud budget", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_cloud_budget", title: "Set cloud budget", category: CLOUD, scope: ADMIN, args: &[arg("budget", OBJECT)] },
+    CommandSpec { id: "sync_bucket", title: "Sync bucket now", category: CLOUD, scope: READ_WRITE, args: &[arg("bucket_name", STRING)] },
+    CommandSpec { id: "get_proxy_settings", title: "Show proxy settings", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_proxy_settings", title: "Set proxy settings", category: CLOUD, scope: ADMIN, args: &[arg("config", OBJECT), optional("password", STRING)] },
+    CommandSpec { id: "get_s3_config", title: "Show S3 endpoint", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_s3_config", title: "Set S3 endpoint", category: CLOUD, scope: ADMIN, args: &[arg("config", OBJECT)] },
+    CommandSpec { id: "get_tls_remotes", title: "List trusted certificates", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_tls_remote", title: "Trust certificate for remote", category: CLOUD, scope: ADMIN, args: &[arg("host", STRING), optional("ca_bundle_path", STRING), optional("pinned_certificate_path", STRING)] },
+    CommandSpec { id: "delete_tls_remote", title: "Remove trusted certificate", category: CLOUD, scope: ADMIN, args: &[arg("host", STRING)] },
+    CommandSpec { id: "test_tls_connection", title: "Test TLS connection", category: CLOUD, scope: ADMIN, args: &[arg("address", STRING)] },
+    CommandSpec { id: "get_sync_settings", title: "Show sync settings", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_sync_settings", title: "Set sync settings", category: CLOUD, scope: ADMIN, args: &[arg("config", OBJECT)] },
+    CommandSpec { id: "list_conflicts", title: "List sync conflicts", category: CLOUD, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "resolve_conflict", title: "Resolve sync conflict", category: CLOUD, scope: READ_WRITE, args: &[arg("uuid", STRING), arg("strategy", STRING), optional("bucket_name", STRING)] },
+    CommandSpec { id: "set_plain_sync", title: "Sync note unencrypted", category: CLOUD, scope: ADMIN, args: &[arg("note_id", INTEGER), arg("enabled", BOOLEAN)] },
+    CommandSpec { id: "list_plain_sync_notes", title: "List notes synced unencrypted", category: CLOUD, scope: READ_ONLY, args: &[] },
+
+    CommandSpec { id: "export_notes", title: "Export all notes", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("format", STRING), arg("path", STRING)] },
+    CommandSpec { id: "verify_export", title: "Verify export", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("path", STRING)] },
+    CommandSpec { id: "prepare_print", title: "Print notes", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("note_ids", ARRAY), optional("options", OBJECT)] },
+    CommandSpec { id: "export_note_card", title: "Export note as image", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("theme", STRING)] },
+    CommandSpec { id: "export_site", title: "Export notes as a static site", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("path", STRING), arg("flavor", STRING)] },
+    CommandSpec { id: "export_notebook_csv", title: "Export notebook as CSV", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("notebook_id", INTEGER), arg("path", STRING)] },
+    CommandSpec {
+        id: "import_notebook_csv",
+        title: "Import CSV into notebook",
+        category: IMPORT_EXPORT,
+        scope: READ_WRITE,
+        args: &[arg("notebook_id", INTEGER), arg("path", STRING), optional("mapping", OBJECT)],
+    },
+    CommandSpec { id: "import_notes_from_dir", title: "Import folder of notes", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("path", STRING)] },
+    CommandSpec { id: "clear_import_ledger", title: "Forget imported files", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[] },
+    CommandSpec { id: "handle_dropped_files", title: "Import dropped files", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("paths", ARRAY), optional("target_note", INTEGER)] },
+    CommandSpec { id: "export_cnote", title: "Export note file", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("id", INTEGER), arg("path", STRING)] },
+    CommandSpec { id: "attach_bibliography", title: "Attach bibliography", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("path", STRING)] },
+    CommandSpec { id: "export_bibliography", title: "Export bibliography", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("note_ids", ARRAY)] },
+    CommandSpec { id: "save_url_as_note", title: "Save web page as note", category: IMPORT_EXPORT, scope: READ_WRITE, args: &[arg("url", STRING)] },
+    CommandSpec { id: "get_capture_bridge", title: "Show capture bridge settings", category: IMPORT_EXPORT, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_capture_bridge", title: "Set capture bridge settings", category: IMPORT_EXPORT, scope: ADMIN, args: &[arg("config", OBJECT), optional("token", STRING)] },
+
+    CommandSpec { id: "get_email_ingestion", title: "Show email import settings", category: EMAIL, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_email_ingestion", title: "Set email import settings", category: EMAIL, scope: ADMIN, args: &[arg("config", OBJECT), optional("password", STRING)] },
+    CommandSpec { id: "fetch_email_now", title: "Import email now", category: EMAIL, scope: READ_WRITE, args: &[] },
+    CommandSpec { id: "get_smtp_settings", title: "Show email sending settings", category: EMAIL, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_smtp_settings", title: "Set email sending settings", category: EMAIL, scope: ADMIN, args: &[arg("config", OBJECT), optional("password", STRING)] },
+    CommandSpec { id: "send_note_email", title: "Send note by email", category: EMAIL, scope: READ_WRITE, args: &[arg("note_id", INTEGER), arg("to", STRING)] },
+
+    CommandSpec { id: "get_locale", title: "Show sorting language", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_locale", title: "Set sorting language", category: SETTINGS, scope: READ_WRITE, args: &[arg("locale", STRING)] },
+    CommandSpec { id: "get_status_workflow", title: "Show status workflow", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_status_workflow", title: "Set status workflow", category: SETTINGS, scope: READ_WRITE, args: &[arg("workflow", OBJECT)] },
+    CommandSpec { id: "get_periodic_note_settings", title: "Show periodic note settings", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_periodic_note_settings", title: "Set periodic note settings", category: SETTINGS, scope: READ_WRITE, args: &[arg("periodic_notes", OBJECT)] },
+    CommandSpec { id: "get_journal_context", title: "Show journal context settings", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_journal_context", title: "Set journal context settings", category: SETTINGS, scope: READ_WRITE, args: &[arg("config", OBJECT), optional("api_key", STRING)] },
+    CommandSpec { id: "get_notification_settings", title: "Show notification settings", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_notifications_muted", title: "Mute notifications", category: SETTINGS, scope: READ_WRITE, args: &[arg("muted", BOOLEAN)] },
+    CommandSpec { id: "set_quiet_hours", title: "Set quiet hours", category: SETTINGS, scope: READ_WRITE, args: &[arg("quiet_hours", OBJECT)] },
+    CommandSpec { id: "list_features", title: "List experimental features", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "is_feature_enabled", title: "Check experimental feature", category: SETTINGS, scope: READ_ONLY, args: &[arg("id", STRING)] },
+    CommandSpec { id: "set_feature_enabled", title: "Enable experimental feature", category: SETTINGS, scope: ADMIN, args: &[arg("id", STRING), arg("enabled", BOOLEAN)] },
+    CommandSpec { id: "get_memory_budgets", title: "Show memory budgets", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_memory_budgets", title: "Set memory budgets", category: SETTINGS, scope: READ_WRITE, args: &[arg("budgets", OBJECT)] },
+    CommandSpec { id: "export_settings", title: "Export settings profile", category: SETTINGS, scope: ADMIN, args: &[arg("path", STRING)] },
+    CommandSpec { id: "import_settings", title: "Import settings profile", category: SETTINGS, scope: ADMIN, args: &[arg("path", STRING)] },
+    CommandSpec { id: "get_workspace_state", title: "Show current workspace", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "set_workspace_state", title: "Set current workspace", category: SETTINGS, scope: READ_WRITE, args: &[arg("state", OBJECT)] },
+    CommandSpec { id: "save_workspace_snapshot", title: "Save workspace", category: SETTINGS, scope: READ_WRITE, args: &[arg("name", STRING)] },
+    CommandSpec { id: "restore_workspace_snapshot", title: "Switch workspace", category: SETTINGS, scope: READ_WRITE, args: &[arg("name", STRING)] },
+    CommandSpec { id: "list_workspace_snapshots", title: "List workspaces", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "delete_workspace_snapshot", title: "Delete workspace", category: SETTINGS, scope: READ_WRITE, args: &[arg("name", STRING)] },
+    CommandSpec { id: "list_available_commands", title: "List commands", category: SETTINGS, scope: READ_ONLY, args: &[] },
+    CommandSpec { id: "list_api_tokens", title: "List API tokens", category: SETTINGS, scope: ADMIN, args: &[] },
+    CommandSpec {
+        id: "create_api_token",
+        title: "Create API token",
+        category: SETTINGS,
+        scope: ADMIN,
+        args: &[arg("name", STRING), arg("scopes", ARRAY), optional("expires_at", INTEGER)],
+    },
+    CommandSpec { id: "revoke_api_token", title: "Revoke API token", category: SETTINGS, scope: ADMIN, args: &[arg("id", INTEGER)] },
+
+    CommandSpec { id: "get_startup_timings", title: "Show startup timings", category: DIAGNOSTICS, scope: ADMIN, args: &[] },
+    CommandSpec { id: "list_background_tasks", title: "List background tasks", category: DIAGNOSTICS, scope: ADMIN, args: &[] },
+    CommandSpec { id: "cancel_task", title: "Cancel background task", category: DIAGNOSTICS, scope: ADMIN, args: &[arg("id", INTEGER)] },
+    CommandSpec { id: "get_recovery_report", title: "Show crash recovery report", category: DIAGNOSTICS, scope: ADMIN, args: &[] },
+    CommandSpec { id: "get_usage_metrics", title: "Show usage metrics", category: DIAGNOSTICS, scope: ADMIN, args: &[optional("days", INTEGER)] },
+    CommandSpec { id: "set_usage_metrics_enabled", title: "Enable usage metrics", category: DIAGNOSTICS, scope: ADMIN, args: &[arg("enabled", BOOLEAN)] },
+    CommandSpec { id: "purge_usage_metrics", title: "Purge usage metrics", category: DIAGNOSTICS, scope: ADMIN, args: &[] },
+    CommandSpec { id: "export_usage_metrics", title: "Export usage metrics", category: DIAGNOSTICS, scope: ADMIN, args: &[arg("path", STRING), optional("days", INTEGER)] },
+    CommandSpec { id: "get_stats_history", title: "Show vault statistics", category: DIAGNOSTICS, scope: ADMIN, args: &[optional("range", STRING)] },
+    CommandSpec {
+        id: "generate_test_data",
+        title: "Generate test notes",
+        category: DIAGNOSTICS,
+        scope: ADMIN,
+        args: &[arg("n_notes", INTEGER), arg("avg_size", INTEGER), arg("n_tags", INTEGER)],
+    },
+    CommandSpec { id: "run_doctor", title: "Check vault for problems", category: DIAGNOSTICS, scope: ADMIN, args: &[optional("fix", BOOLEAN), optional("bucket_name", STRING)] },
+
+    CommandSpec { id: "get_update_manifest_url", title: "Show update address", category: UPDATES, scope: ADMIN, args: &[] },
+    CommandSpec { id: "set_update_manifest_url", title: "Set update address", category: UPDATES, scope: ADMIN, args: &[arg("url", STRING)] },
+    CommandSpec { id: "check_for_updates", title: "Check for updates", category: UPDATES, scope: ADMIN, args: &[] },
+    CommandSpec { id: "install_update", title: "Install update", category: UPDATES, scope: ADMIN, args: &[] },
+];
+
+
+/// Returns the scope a token needs to run a command through `execute_api_command`, as declared in `COMMANDS`.
+///
+/// # Arguments
+///
+/// * `command` - The ID of the command.
+///
+/// # Returns
+///
+/// Returns the needed `ApiScope`, or `None` for the commands the user cannot run from the command palette, which
+/// no token can run.
+pub fn required_scope(command: &str) -> Option<ApiScope> {
+    COMMANDS.iter().find(|spec| spec.id == command).map(|spec| spec.scope)
+}
+
+
+/// Lists the commands the user can run, so the frontend can offer them in a command palette and bind them to shortcuts.
+///
+/// # Returns
+///
+/// Returns a `CommandDescriptor` for each command, with its ID, the name of the command the window calls, its title, its category,
+/// and its arguments, each with its name, JSON type, and whether it is required. A command without required arguments
+/// can be run as is.
+pub fn list_available_commands() -> Vec<CommandDescriptor> {
+    COMMANDS.iter()
+        .map(|command| CommandDescriptor {
+            id: command.id.to_string(),
+            title: command.title.to_string(),
+            category: command.category.to_string(),
+            args: command.args.iter()
+                .map(|arg| CommandArg {
+                    name: arg.name.to_string(),
+                    kind: arg.kind.to_string(),
+                    required: arg.required,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The commands read-only tokens can run, as they change nothing.
+    const EXPECTED_READ_ONLY: &[&str] = &[
+        "list_trashed_notes", "get_trash_retention", "get_local_notes", "get_local_note_summaries",
+        "get_local_notes_paged", "render_markdown", "render_content", "get_tray_menu_items", "list_note_templates",
+        "list_scheduled_notes", "get_status_counts", "list_note_versions", "get_note_code_blocks", "list_snippets",
+        "get_note_citations", "get_note_source", "list_notebooks", "get_notes_by_notebook", "get_notebook_notes",
+        "get_notebook_schema", "get_note_fields", "get_notebook_settings", "get_note_aliases", "get_notes_by_tag",
+        "list_tags", "resolve_wikilink", "get_backlinks", "find_unlinked_mentions", "get_period", "get_due_reviews",
+        "list_due_cards", "get_deck_stats", "get_focus_session", "get_time_report", "get_note_attachments",
+        "get_attachment_data", "get_annotation", "get_attachment_text_status", "search_in_notes", "search_all_notes",
+        "get_index_status", "quick_switch", "get_index_protection", "fetch_buckets", "fetch_bucket_note",
+        "fetch_bucket_notes", "list_bucket_note_stats", "get_note_presence", "find_orphaned_bucket_objects",
+        "get_cloud_usage_report", "get_cloud_budget", "get_proxy_settings", "get_s3_config", "get_tls_remotes",
+        "get_sync_settings", "list_conflicts", "list_plain_sync_notes", "get_capture_bridge", "get_email_ingestion",
+        "get_smtp_settings", "get_locale", "get_status_workflow", "get_periodic_note_settings", "get_journal_context",
+        "get_notification_settings", "list_features", "is_feature_enabled", "get_memory_budgets", "get_workspace_state",
+        "list_workspace_snapshots", "list_available_commands",
+    ];
+
+    /// The commands only admin tokens can run.
+    const EXPECTED_ADMIN: &[&str] = &[
+        "purge_note", "set_trash_retention", "delete_all_local_notes", "set_note_secure", "unlock_vault", "lock_vault",
+        "get_vault_status", "set_passphrase", "set_vault_password", "export_encryption_key", "get_encryption_key_error",
+        "import_encryption_key", "get_schema_status", "get_encryption_cipher", "set_encryption_cipher",
+        "select_fastest_cipher", "get_reencryption_status", "resume_reencryption", "get_vault_lock_status",
+        "take_over_vault_lock", "inspect_note_envelope", "delete_bucket", "delete_bucket_notes",
+        "clean_orphaned_objects", "login_sso", "logout_sso", "set_cloud_budget", "set_proxy_settings", "set_s3_config",
+        "set_tls_remote", "delete_tls_remote", "test_tls_connection", "set_sync_settings", "set_plain_sync",
+        "set_capture_bridge", "set_email_ingestion", "set_smtp_settings", "set_feature_enabled", "export_settings",
+        "import_settings", "list_api_tokens", "create_api_token", "revoke_api_token", "get_startup_timings",
+        "list_background_tasks", "cancel_task", "get_recovery_report", "get_usage_metrics", "set_usage_metrics_enabled",
+        "purge_usage_metrics", "export_usage_metrics", "get_stats_history", "generate_test_data", "run_doctor",
+        "get_update_manifest_url", "set_update_manifest_url", "check_for_updates", "install_update",
+    ];
+
+    #[test]
+    fn every_command_has_its_expected_scope() {
+        for id in EXPECTED_READ_ONLY.iter().chain(EXPECTED_ADMIN) {
+            assert!(COMMANDS.iter().any(|spec| spec.id == *id), "{} is not a command", id);
+        }
+        for spec in COMMANDS {
+            let expected = if EXPECTED_READ_ONLY.contains(&spec.id) {
+                ApiScope::ReadOnly
+            } else if EXPECTED_ADMIN.contains(&spec.id) {
+                ApiScope::Admin
+            } else {
+                ApiScope::ReadWrite
+            };
+            assert_eq!(required_scope(spec.id), Some(expected), "{}", spec.id);
+        }
+    }
+
+    #[test]
+    fn commands_with_side_effects_are_not_read_only() {
+        assert_eq!(required_scope("get_local_note"), Some(ApiScope::ReadWrite));
+        assert_eq!(required_scope("get_periodic_note"), Some(ApiScope::ReadWrite));
+        assert_eq!(required_scope("test_tls_connection"), Some(ApiScope::Admin));
+    }
+
+    #[test]
+    fn commands_outside_of_the_palette_have_no_scope() {
+        assert_eq!(required_scope("drafts_flushed"), None);
+        assert_eq!(required_scope("take_opened_notes"), None);
+        assert_eq!(required_scope("unknown_command"), None);
+    }
+}
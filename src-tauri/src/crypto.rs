@@ -0,0 +1,344 @@
+// crypto.rs
+
+use crate::{ key_management, key_management::ContentKey, local_operations::CONNECTION, models::AppError, models::Cipher, models::CipherBenchmark, models::CipherSelection };
+use ring::aead::{Aad, Algorithm, Nonce, AES_256_GCM, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use base64::{Engine as _, engine::general_purpose};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::RwLock;
+use std::time::Instant;
+
+
+/// The settings key under which the cipher new content is encrypted with is stored.
+const CIPHER_SETTING: &str = "encryption_cipher";
+
+/// The prefix of the nonce of the content encrypted with AES-256-GCM. The nonce of the content encrypted with
+/// ChaCha20-Poly1305 has no prefix, like the content encrypted before the cipher could be chosen.
+const AES_256_GCM_PREFIX: &str = "aes-256-gcm:";
+
+/// The size of the content encrypted and decrypted to benchmark a cipher, in bytes.
+const BENCHMARK_SIZE: usize = 64 * 1024;
+
+/// The number of times the content is encrypted and decrypted to benchmark a cipher.
+const BENCHMARK_ROUNDS: usize = 32;
+
+/// The cipher new content is encrypted with, read when the database is opened (see `load_cipher`).
+static CIPHER: RwLock<Cipher> = RwLock::new(Cipher::ChaCha20Poly1305);
+
+
+/// Encrypts the content of a note for storage in the local database.
+///
+/// # Arguments
+///
+/// * `content` - The plaintext content.
+///
+/// # Returns
+///
+/// Returns a tuple with the encrypted content and the randomly generated nonce, both encoded in base64,
+/// or `Err(AppError::Encryption)` if the vault is locked or the encryption fails. The content is encrypted with the
+/// current cipher, which is recorded with the nonce (see `split_nonce`).
+pub fn encrypt_content(content: &str) -> Result<(String, String), AppError> {
+    encrypt_content_with(&*key_management::content_key()?, content).map_err(AppError::Encryption)
+}
+
+
+/// Decrypts the content of a note stored in the local database.
+///
+/// # Arguments
+///
+/// * `encrypted_content` - The encrypted content, encoded in base64.
+/// * `nonce_str` - The nonce used to encrypt the content, encoded in base64.
+///
+/// # Returns
+///
+/// Returns the plaintext content, or `Err(AppError::Encryption)` if the vault is locked, if the content or the nonce
+/// is malformed or if the decryption fails.
+pub fn decrypt_content(encrypted_content: &str, nonce_str: &str) -> Result<String, AppError> {
+    decrypt_content_with(&*key_management::content_key()?, encrypted_content, nonce_str).or_else(|e| {
+        // The content not encrypted again yet after a key change is still encrypted with the previous key
+        match key_management::previous_key() {
+            Some(previous_key) => decrypt_content_with(&previous_key, encrypted_content, nonce_str).map_err(|_| e),
+            None => Err(e),
+        }
+    }).map_err(AppError::Encryption)
+}
+
+
+/// Encrypts the content of a note for upload to a bucket, where it is stored as raw bytes.
+///
+/// # Arguments
+///
+/// * `content` - The plaintext content.
+///
+/// # Returns
+///
+/// Returns a tuple with the encrypted content and the randomly generated nonce, encoded in base64,
+/// or `Err(AppError::Encryption)` if the vault is locked or the encryption fails. The content is encrypted with the
+/// current cipher, which is recorded with the nonce (see `split_nonce`).
+pub fn encrypt_bytes(content: &[u8]) -> Result<(Vec<u8>, String), AppError> {
+    let key = key_management::content_key()?;
+    seal(&key, content.to_vec()).map_err(AppError::Encryption)
+}
+
+
+/// Decrypts the content of a note downloaded from a bucket.
+///
+/// # Arguments
+///
+/// * `encrypted_content` - The encrypted content.
+/// * `nonce_str` - The nonce used to encrypt the content, encoded in base64.
+/// * `key_version` - The version of the key it was encrypted with, from the metadata of its object. The objects
+///   uploaded before the key was managed have version 0, and are decrypted with the legacy key.
+///
+/// # Returns
+///
+/// Returns the plaintext content, or `Err(AppError::Encryption)` if the vault is locked, if the nonce is malformed or
+/// if the decryption fails.
+pub fn decrypt_bytes(encrypted_content: Vec<u8>, nonce_str: &str, key_version: i64) -> Result<Vec<u8>, AppError> {
+    if key_version == 0 {
+        return open(&legacy_key(), encrypted_content, nonce_str).map_err(AppError::Encryption);
+    }
+    let key = key_management::content_key()?;
+    open(&key, encrypted_content, nonce_str).map_err(AppError::Encryption)
+}
+
+
+/// Encrypts content for storage in the local database with a given key, e.g. while the database is encrypted again
+/// with another key (see `key_management`).
+///
+/// # Arguments
+///
+/// * `key` - The key.
+/// * `content` - The plaintext content.
+///
+/// # Returns
+///
+/// Returns a tuple with the encrypted content and the randomly generated nonce, both encoded in base64,
+/// or `Err(String)` if the encryption fails.
+pub fn encrypt_content_with(key: &ContentKey, content: &str) -> Result<(String, String), String> {
+    let (encrypted_content, nonce_str) = seal(key, content.as_bytes().to_vec())?;
+    Ok((general_purpose::STANDARD.encode(encrypted_content), nonce_str))
+}
+
+
+/// Decrypts content stored in the local database with a given key.
+///
+/// # Arguments
+///
+/// * `key` - The key.
+/// * `encrypted_content` - The encrypted content, encoded in base64.
+/// * `nonce_str` - The nonce used to encrypt the content, encoded in base64.
+///
+/// # Returns
+///
+/// Returns the plaintext content, or `Err(String)` if the content or the nonce is malformed or the decryption fails.
+pub fn decrypt_content_with(key: &ContentKey, encrypted_content: &str, nonce_str: &str) -> Result<String, String> {
+    // Decode the content
+    let content_bytes = general_purpose::STANDARD.decode(encrypted_content)
+        .map_err(|_| "Failed to decode content")?;
+
+    // Convert the decrypted content to a string
+    String::from_utf8(open(key, content_bytes, nonce_str)?).map_err(|e| e.to_string())
+}
+
+
+/// Returns the all-zero key everything was encrypted with before the key was managed (see `key_management`).
+pub fn legacy_key() -> ContentKey {
+    ContentKey::new([0; 32]).unwrap()
+}
+
+
+/// Returns the cipher new content is encrypted with, locally and in the buckets.
+pub fn current_cipher() -> Cipher {
+    *CIPHER.read().unwrap()
+}
+
+
+/// Reads the cipher new content is encrypted with when the database is opened. It is called while `CONNECTION` is
+/// initialized.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Operation
+///
+/// On first run, both ciphers are benchmarked and the faster one is stored in the "settings" table (see
+/// `benchmark_ciphers`). AES-256-GCM is usually the faster where the CPU accelerates AES, and ChaCha20-Poly1305
+/// elsewhere. The content is decrypted with the cipher it was encrypted with, whichever is chosen.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn load_cipher(conn: &Connection) -> Result<(), String> {
+    let stored: Option<String> = conn.query_row(
+        "SELECT value FROM settings WHERE key = ?1",
+        params![CIPHER_SETTING],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())?;
+
+    let cipher = match stored.and_then(|stored| serde_json::from_str(&stored).ok()) {
+        Some(cipher) => cipher,
+        None => {
+            let cipher = fastest_cipher(&benchmark_ciphers()?);
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![CIPHER_SETTING, serde_json::to_string(&cipher).map_err(|e| e.to_string())?],
+            ).map_err(|e| e.to_string())?;
+            cipher
+        },
+    };
+    *CIPHER.write().unwrap() = cipher;
+    Ok(())
+}
+
+
+/// Benchmarks both ciphers again and encrypts new content with the faster one, e.g. after moving the vault to
+/// another computer.
+///
+/// # Returns
+///
+/// Returns a `CipherSelection` with the chosen cipher and the throughput of each cipher.
+///
+/// # Errors
+///
+/// This function will return an error if a cipher fails or if there is an issue with the database connection.
+pub fn select_cipher() -> Result<CipherSelection, String> {
+    let benchmarks = benchmark_ciphers()?;
+    let cipher = fastest_cipher(&benchmarks);
+    set_cipher(cipher)?;
+    Ok(CipherSelection { cipher, benchmarks })
+}
+
+
+/// Chooses the cipher new content is encrypted with, instead of the faster one.
+///
+/// # Arguments
+///
+/// * `cipher` - The cipher. The content already encrypted is not encrypted again, and stays readable.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn set_cipher(cipher: Cipher) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![CIPHER_SETTING, serde_json::to_string(&cipher).map_err(|e| e.to_string())?],
+    ).map_err(|e| e.to_string())?;
+    *CIPHER.write().unwrap() = cipher;
+    Ok(())
+}
+
+
+/// Measures the throughput of each cipher, encrypting and decrypting 64 KB of content 32 times with a random key.
+///
+/// # Errors
+///
+/// This function will return an error if a random key cannot be generated or if a cipher fails.
+pub fn benchmark_ciphers() -> Result<Vec<CipherBenchmark>, String> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| "Failed to generate the benchmark key")?;
+    let key = ContentKey::new(bytes)?;
+    let content = vec![0u8; BENCHMARK_SIZE];
+
+    [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm].into_iter().map(|cipher| {
+        // The first round warms up the caches and is not measured
+        let (in_out, nonce_str) = seal_with(&key, cipher, content.clone())?;
+        open(&key, in_out, &nonce_str)?;
+
+        let started_at = Instant::now();
+        for _ in 0..BENCHMARK_ROUNDS {
+            let (in_out, nonce_str) = seal_with(&key, cipher, content.clone())?;
+            open(&key, in_out, &nonce_str)?;
+        }
+        let seconds = started_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        Ok(CipherBenchmark {
+            cipher,
+            megabytes_per_second: (BENCHMARK_SIZE * BENCHMARK_ROUNDS) as f64 / 1_000_000.0 / seconds,
+        })
+    }).collect()
+}
+
+
+/// Returns the faster cipher of a benchmark.
+fn fastest_cipher(benchmarks: &[CipherBenchmark]) -> Cipher {
+    benchmarks.iter()
+        .max_by(|a, b| a.megabytes_per_second.total_cmp(&b.megabytes_per_second))
+        .map_or(Cipher::ChaCha20Poly1305, |fastest| fastest.cipher)
+}
+
+
+/// Splits a nonce as stored with the encrypted content into the cipher it was encrypted with and the nonce itself,
+/// encoded in base64.
+pub fn split_nonce(nonce_str: &str) -> (Cipher, &str) {
+    match nonce_str.strip_prefix(AES_256_GCM_PREFIX) {
+        Some(nonce_str) => (Cipher::Aes256Gcm, nonce_str),
+        None => (Cipher::ChaCha20Poly1305, nonce_str),
+    }
+}
+
+
+/// Returns the AEAD algorithm of a cipher.
+pub fn algorithm(cipher: Cipher) -> &'static Algorithm {
+    match cipher {
+        Cipher::ChaCha20Poly1305 => &CHACHA20_POLY1305,
+        Cipher::Aes256Gcm => &AES_256_GCM,
+    }
+}
+
+
+/// Returns the name of a cipher, as shown to the user.
+pub fn cipher_name(cipher: Cipher) -> &'static str {
+    match cipher {
+        Cipher::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+        Cipher::Aes256Gcm => "AES-256-GCM",
+    }
+}
+
+
+/// Encrypts bytes with a key and a random nonce with the current cipher, returning the ciphertext and the nonce
+/// encoded in base64.
+fn seal(key: &ContentKey, in_out: Vec<u8>) -> Result<(Vec<u8>, String), String> {
+    seal_with(key, current_cipher(), in_out)
+}
+
+
+/// Encrypts bytes with a key and a random nonce with a cipher, returning the ciphertext and the nonce encoded in
+/// base64, prefixed with the cipher unless it is ChaCha20-Poly1305.
+fn seal_with(key: &ContentKey, cipher: Cipher, mut in_out: Vec<u8>) -> Result<(Vec<u8>, String), String> {
+    // Generate a random nonce
+    let rng = SystemRandom::new();
+    let mut nonce = [0u8; 12];
+    rng.fill(&mut nonce).map_err(|_| "Failed to generate nonce")?;
+    let nonce = Nonce::assume_unique_for_key(nonce);
+
+    // Convert the nonce to a byte slice and then encode it, recording the cipher
+    let nonce_str = match cipher {
+        Cipher::ChaCha20Poly1305 => general_purpose::STANDARD.encode(nonce.as_ref()),
+        Cipher::Aes256Gcm => format!("{}{}", AES_256_GCM_PREFIX, general_purpose::STANDARD.encode(nonce.as_ref())),
+    };
+
+    key.aead(cipher).seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
+        .map_err(|_| "Encryption failed")?;
+
+    Ok((in_out, nonce_str))
+}
+
+
+/// Decrypts bytes with a key and their nonce encoded in base64, with the cipher recorded in the nonce.
+fn open(key: &ContentKey, mut in_out: Vec<u8>, nonce_str: &str) -> Result<Vec<u8>, String> {
+    let (cipher, nonce_str) = split_nonce(nonce_str);
+
+    // Decode the nonce
+    let nonce_bytes = general_purpose::STANDARD.decode(nonce_str)
+        .map_err(|_| "Failed to decode nonce")?;
+    let nonce_array: [u8; 12] = nonce_bytes.try_into()
+        .map_err(|_| "Nonce has wrong length")?;
+    let nonce = Nonce::assume_unique_for_key(nonce_array);
+
+    let decrypted_content = key.aead(cipher).open_in_place(nonce, Aad::empty(), &mut in_out)
+        .map_err(|_| "Decryption failed")?;
+    Ok(decrypted_content.to_vec())
+}
+
@@ -0,0 +1,542 @@
+// custom_fields.rs
+
+use crate::{ collation, local_operations, notebook_csv, notebook_operations, local_operations::CONNECTION, models::AppError, models::CustomField, models::FieldFilter, models::FieldKind, models::FieldListOptions, models::FieldOp, models::NoteSummary };
+use rusqlite::{params, Connection, OptionalExtension};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+
+/// The maximum number of custom fields of a notebook.
+const MAX_FIELDS: usize = 50;
+
+/// The maximum length of the name of a custom field.
+const MAX_FIELD_NAME_LENGTH: usize = 50;
+
+/// The maximum number of options of a select field.
+const MAX_OPTIONS: usize = 100;
+
+/// The maximum length of the value of a text field.
+const MAX_TEXT_LENGTH: usize = 1000;
+
+/// The format of the values of date fields, e.g. "2024-03-15".
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+
+/// Retrieves the custom fields of a notebook.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+///
+/// # Returns
+///
+/// Returns the fields in the order they were defined, empty if the notebook has none.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist or if there is an issue with the database connection.
+pub fn get_notebook_schema(notebook_id: i64) -> Result<Vec<CustomField>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+    read_schema(&conn, notebook_id)
+}
+
+
+/// Defines the custom fields of a notebook, replacing its previous ones.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `fields` - The fields, in the order they are shown. Their names are up to 50 characters, unique whatever their
+///   case, and other than the columns of the notes in CSV files (see `notebook_csv::NOTE_COLUMNS`). A select field
+///   has at least one option, the other fields have none.
+///
+/// # Operation
+///
+/// The values of the notes of the notebook are checked against the new fields in the same transaction: those of
+/// a field that was removed or renamed, or that are not valid for its new kind, are removed, and those of a select
+/// field take the case of its option.
+///
+/// # Returns
+///
+/// Returns the fields as stored, without the whitespace around their names and their options.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if a field is invalid, or if there is an
+/// issue with the database connection.
+pub fn set_notebook_schema(notebook_id: i64, fields: Vec<CustomField>) -> Result<Vec<CustomField>, String> {
+    let fields = check_schema(&fields)?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+    let values: Vec<(i64, String, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT note_fields.note_id, note_fields.name, note_fields.value FROM note_fields
+            JOIN notes ON notes.id = note_fields.note_id WHERE notes.notebook_id = ?1"
+        ).map_err(|e| e.to_string())?;
+        let values = stmt.query_map(params![notebook_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        values
+    };
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM notebook_fields WHERE notebook_id = ?1", params![notebook_id]).map_err(|e| e.to_string())?;
+    for (position, field) in fields.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO notebook_fields (notebook_id, name, kind, options, position) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![notebook_id, field.name, kind_name(field.kind), serde_json::to_string(&field.options).map_err(|e| e.to_string())?, position as i64],
+        ).map_err(|e| e.to_string())?;
+    }
+    for (note_id, name, value) in values {
+        let checked = fields.iter().find(|field| field.name == name).and_then(|field| check_value(field, &value).ok());
+        match checked {
+            Some(checked) if checked == value => {},
+            Some(checked) => {
+                tx.execute("UPDATE note_fields SET value = ?1 WHERE note_id = ?2 AND name = ?3", params![checked, note_id, name])
+                    .map_err(|e| e.to_string())?;
+            },
+            None => {
+                tx.execute("DELETE FROM note_fields WHERE note_id = ?1 AND name = ?2", params![note_id, name])
+                    .map_err(|e| e.to_string())?;
+            },
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(fields)
+}
+
+
+/// Retrieves the values of the custom fields of a local note.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the values by field name, for the fields of the notebook of the note that have one. It is empty for a
+/// note outside of any notebook.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn get_note_fields(note_id: i64) -> Result<BTreeMap<String, String>, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let notebook_id = note_notebook_id(&conn, note_id)?;
+    Ok(read_values(&conn, note_id, notebook_id)?)
+}
+
+
+/// Sets the values of custom fields of a local note, validated against the fields of its notebook.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `values` - The values by field name, whatever its case. An empty value removes the value of the field, and the
+///   fields left out keep their value.
+///
+/// # Operation
+///
+/// The values are stored in a canonical form, so they compare and sort by their kind:
+///
+/// * A number is stored as the shortest decimal of its value, e.g. "1.50" is stored as "1.5".
+/// * A date must be a calendar date such as "2024-03-15".
+/// * A select value must be one of the options of the field, whatever its case, and takes the case of the option.
+/// * A text is up to 1,000 characters.
+///
+/// # Returns
+///
+/// Returns the values of the custom fields of the note, as `get_note_fields` does.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or is outside of any notebook, if its
+/// notebook has no field with one of the names, if a value is not valid for its field, in which case no value is
+/// changed, or if there is an issue with the database connection.
+pub fn set_note_fields(note_id: i64, values: BTreeMap<String, String>) -> Result<BTreeMap<String, String>, AppError> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let notebook_id = note_notebook_id(&conn, note_id)?
+        .ok_or(AppError::Validation("The note is not in a notebook, which defines its fields".to_string()))?;
+    let schema = read_schema(&conn, notebook_id)?;
+
+    let mut checked = BTreeMap::new();
+    for (name, value) in &values {
+        let field = schema.iter()
+            .find(|field| field.name.to_lowercase() == name.trim().to_lowercase())
+            .ok_or_else(|| AppError::Validation(format!("The notebook has no field named '{}'", name.trim())))?;
+        let value = match value.trim().is_empty() {
+            true => None,
+            false => Some(check_value(field, value).map_err(AppError::Validation)?),
+        };
+        checked.insert(field.name.clone(), value);
+    }
+
+    let tx = conn.transaction()?;
+    store_values(&tx, note_id, &checked)?;
+    tx.commit()?;
+
+    Ok(read_values(&conn, note_id, Some(notebook_id))?)
+}
+
+
+/// Stores values of the custom fields of a note, already checked with `check_value`.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `values` - The values by field name, `None` removing the value of the field.
+///
+/// # Returns
+///
+/// Returns whether a value changed.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn store_values(conn: &Connection, note_id: i64, values: &BTreeMap<String, Option<String>>) -> Result<bool, String> {
+    let mut changed = 0;
+    for (name, value) in values {
+        changed += match value {
+            Some(value) => conn.execute(
+                "INSERT INTO note_fields (note_id, name, value) VALUES (?1, ?2, ?3)
+                ON CONFLICT (note_id, name) DO UPDATE SET value = excluded.value WHERE value <> excluded.value",
+                params![note_id, name, value],
+            ),
+            None => conn.execute("DELETE FROM note_fields WHERE note_id = ?1 AND name = ?2", params![note_id, name]),
+        }.map_err(|e| e.to_string())?;
+    }
+    Ok(changed > 0)
+}
+
+
+/// Lists the notes of a notebook, filtered and sorted by their custom fields.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `options` - The filters on the custom fields of the notes (see `matches`), all of which a note must match, and
+///   the custom field to sort the notes by.
+///
+/// # Operation
+///
+/// The notes are sorted by the custom field of `options.sort_field`, if any, with the notes without a value for it
+/// last, and then by title in natural order for the configured locale (see `collation::compare_titles`). Their
+/// content is not decrypted.
+///
+/// # Returns
+///
+/// Returns the summaries of the matching notes.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist or if there is an issue with the database connection.
+pub fn get_notebook_notes(notebook_id: i64, options: &FieldListOptions) -> Result<Vec<NoteSummary>, String> {
+    let locale = collation::get_locale()?;
+    let summaries = local_operations::get_local_note_summaries()?;
+
+    let (note_ids, sort_values) = {
+        let conn = CONNECTION.lock().unwrap();
+        notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+        let mut stmt = conn.prepare("SELECT id FROM notes WHERE notebook_id = ?1").map_err(|e| e.to_string())?;
+        let mut note_ids = stmt.query_map(params![notebook_id], |row| row.get::<_, i64>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        for filter in &options.fields {
+            let values = field_values(&conn, &filter.name)?;
+            note_ids.retain(|id| values.get(id).is_some_and(|(kind, value)| matches(*kind, value, filter)));
+        }
+        let sort_values = match &options.sort_field {
+            Some(name) => field_values(&conn, name)?,
+            None => HashMap::new(),
+        };
+        (note_ids, sort_values)
+    };
+
+    // The summaries are already sorted by title, and the sort is stable
+    let mut notes: Vec<NoteSummary> = summaries.into_iter().filter(|summary| note_ids.contains(&summary.id)).collect();
+    notes.sort_by(|a, b| match (sort_values.get(&a.id), sort_values.get(&b.id)) {
+        (Some((kind, a)), Some((_, b))) if options.sort_descending => compare_values(*kind, b, a, &locale),
+        (Some((kind, a)), Some((_, b))) => compare_values(*kind, a, b, &locale),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    Ok(notes)
+}
+
+
+/// Removes the values of the custom fields of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_fields(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_fields WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Removes the custom fields of a notebook after it was deleted, and the values of its notes.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `notebook_id` - The ID of the notebook.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_schema(conn: &Connection, notebook_id: i64) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM note_fields WHERE note_id IN (SELECT id FROM notes WHERE notebook_id = ?1)",
+        params![notebook_id],
+    ).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM notebook_fields WHERE notebook_id = ?1", params![notebook_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Reads the values of a custom field in the notes whose notebook has the field, to filter and sort the notes by it.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `name` - The name of the field, whatever its case.
+///
+/// # Returns
+///
+/// Returns the kind of the field in the notebook of each note and its value, by note ID.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn field_values(conn: &Connection, name: &str) -> Result<HashMap<i64, (FieldKind, String)>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT note_fields.note_id, notebook_fields.kind, note_fields.value FROM note_fields
+        JOIN notes ON notes.id = note_fields.note_id
+        JOIN notebook_fields ON notebook_fields.notebook_id = notes.notebook_id AND notebook_fields.name = note_fields.name
+        WHERE note_fields.name = ?1 COLLATE NOCASE"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![name.trim()], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows.into_iter()
+        .filter_map(|(note_id, kind, value)| parse_kind(&kind).map(|kind| (note_id, (kind, value))))
+        .collect())
+}
+
+
+/// Tells whether the value of a custom field matches a filter of the notes of a notebook.
+///
+/// Numbers and dates are compared by their value, and texts and select values alphabetically, whatever their case.
+/// A filter value that is not valid for the kind of the field, e.g. "soon" for a date, matches no note, except with
+/// `FieldOp::Contains`, which looks for the filter value in the value as text.
+pub fn matches(kind: FieldKind, value: &str, filter: &FieldFilter) -> bool {
+    let ordering = match kind {
+        FieldKind::Number => match (value.parse::<f64>(), filter.value.trim().parse::<f64>()) {
+            (Ok(value), Ok(filter_value)) => value.partial_cmp(&filter_value),
+            _ => None,
+        },
+        FieldKind::Date => match (chrono::NaiveDate::parse_from_str(value, DATE_FORMAT), chrono::NaiveDate::parse_from_str(filter.value.trim(), DATE_FORMAT)) {
+            (Ok(value), Ok(filter_value)) => Some(value.cmp(&filter_value)),
+            _ => None,
+        },
+        FieldKind::Text | FieldKind::Select => Some(value.to_lowercase().cmp(&filter.value.trim().to_lowercase())),
+    };
+    match filter.op {
+        FieldOp::Eq => ordering == Some(Ordering::Equal),
+        FieldOp::Lt => ordering == Some(Ordering::Less),
+        FieldOp::Gt => ordering == Some(Ordering::Greater),
+        FieldOp::Contains => value.to_lowercase().contains(&filter.value.trim().to_lowercase()),
+    }
+}
+
+
+/// Compares two values of a custom field, to sort the notes by it: numbers and dates by their value, and texts and
+/// select values in natural order for the locale (see `collation::compare_titles`).
+pub fn compare_values(kind: FieldKind, a: &str, b: &str, locale: &str) -> Ordering {
+    match kind {
+        FieldKind::Number => match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+        // Dates are stored as "YYYY-MM-DD", which sorts by date
+        FieldKind::Date => a.cmp(b),
+        FieldKind::Text | FieldKind::Select => collation::compare_titles(a, b, locale),
+    }
+}
+
+
+/// Reads the custom fields of a notebook, in the order they were defined.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `notebook_id` - The ID of the notebook.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn read_schema(conn: &Connection, notebook_id: i64) -> Result<Vec<CustomField>, String> {
+    let mut stmt = conn.prepare("SELECT name, kind, options FROM notebook_fields WHERE notebook_id = ?1 ORDER BY position")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![notebook_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut fields = Vec::new();
+    for (name, kind, options) in rows {
+        fields.push(CustomField {
+            kind: parse_kind(&kind).ok_or(format!("The field '{}' has an unknown kind: {}", name, kind))?,
+            options: serde_json::from_str(&options).map_err(|e| e.to_string())?,
+            name,
+        });
+    }
+    Ok(fields)
+}
+
+
+/// Checks the value of a custom field, returning it in its canonical form (see `set_note_fields`).
+///
+/// # Arguments
+///
+/// * `field` - The field.
+/// * `value` - The value, not empty.
+///
+/// # Errors
+///
+/// This function will return an error if the value is not valid for the field.
+pub fn check_value(field: &CustomField, value: &str) -> Result<String, String> {
+    let value = value.trim();
+    match field.kind {
+        FieldKind::Text => {
+            if value.chars().count() > MAX_TEXT_LENGTH {
+                return Err(format!("The value of '{}' cannot be longer than {} characters", field.name, MAX_TEXT_LENGTH));
+            }
+            Ok(value.to_string())
+        },
+        FieldKind::Number => match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number.to_string()),
+            _ => Err(format!("The value of '{}' must be a number, not '{}'", field.name, value)),
+        },
+        FieldKind::Date => chrono::NaiveDate::parse_from_str(value, DATE_FORMAT)
+            .map(|date| date.format(DATE_FORMAT).to_string())
+            .map_err(|_| format!("The value of '{}' must be a date such as 2024-03-15, not '{}'", field.name, value)),
+        FieldKind::Select => field.options.iter()
+            .find(|option| option.to_lowercase() == value.to_lowercase())
+            .cloned()
+            .ok_or(format!("'{}' is not an option of '{}'", value, field.name)),
+    }
+}
+
+
+/// Checks the custom fields of a notebook, returning them without the whitespace around their names and their options.
+fn check_schema(fields: &[CustomField]) -> Result<Vec<CustomField>, String> {
+    if fields.len() > MAX_FIELDS {
+        return Err(format!("A notebook can have at most {} fields", MAX_FIELDS));
+    }
+
+    let mut checked: Vec<CustomField> = Vec::new();
+    for field in fields {
+        let name = field.name.trim();
+        if name.is_empty() {
+            return Err("The name of a field cannot be empty".to_string());
+        }
+        if name.chars().count() > MAX_FIELD_NAME_LENGTH {
+            return Err(format!("The name of a field cannot be longer than {} characters", MAX_FIELD_NAME_LENGTH));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(format!("The name of the field '{}' contains invalid characters", name.escape_debug()));
+        }
+        if notebook_csv::NOTE_COLUMNS.iter().any(|column| column.to_lowercase() == name.to_lowercase()) {
+            return Err(format!("'{}' is the name of a column of the notes, and cannot name a field", name));
+        }
+        if checked.iter().any(|other| other.name.to_lowercase() == name.to_lowercase()) {
+            return Err(format!("There are several fields named '{}'", name));
+        }
+
+        let mut options: Vec<String> = Vec::new();
+        for option in field.options.iter().map(|option| option.trim()).filter(|option| !option.is_empty()) {
+            if !options.iter().any(|existing| existing.to_lowercase() == option.to_lowercase()) {
+                options.push(option.to_string());
+            }
+        }
+        match field.kind {
+            FieldKind::Select if options.is_empty() => {
+                return Err(format!("The select field '{}' needs at least one option", name));
+            },
+            FieldKind::Select if options.len() > MAX_OPTIONS => {
+                return Err(format!("The select field '{}' can have at most {} options", name, MAX_OPTIONS));
+            },
+            FieldKind::Select => {},
+            _ if !options.is_empty() => {
+                return Err(format!("Only select fields have options, not '{}'", name));
+            },
+            _ => {},
+        }
+
+        checked.push(CustomField { name: name.to_string(), kind: field.kind, options });
+    }
+    Ok(checked)
+}
+
+
+/// Reads the values of the custom fields of a note, for the fields of its notebook.
+fn read_values(conn: &Connection, note_id: i64, notebook_id: Option<i64>) -> Result<BTreeMap<String, String>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT note_fields.name, note_fields.value FROM note_fields
+        JOIN notebook_fields ON notebook_fields.name = note_fields.name AND notebook_fields.notebook_id = ?2
+        WHERE note_fields.note_id = ?1"
+    ).map_err(|e| e.to_string())?;
+    let values = stmt.query_map(params![note_id, notebook_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<BTreeMap<_, _>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(values)
+}
+
+
+/// Reads the notebook of a local note, `None` for a note outside of any notebook.
+fn note_notebook_id(conn: &Connection, note_id: i64) -> Result<Option<i64>, AppError> {
+    conn.query_row(
+        "SELECT notebook_id FROM notes WHERE id = ?1",
+        params![note_id],
+        |row| row.get::<_, Option<i64>>(0),
+    ).optional()?.ok_or(AppError::NotFound("Note not found".to_string()))
+}
+
+
+/// The name under which a kind of field is stored.
+fn kind_name(kind: FieldKind) -> &'static str {
+    match kind {
+        FieldKind::Text => "text",
+        FieldKind::Number => "number",
+        FieldKind::Date => "date",
+        FieldKind::Select => "select",
+    }
+}
+
+
+/// Reads the kind of a field from its stored name.
+fn parse_kind(name: &str) -> Option<FieldKind> {
+    match name {
+        "text" => Some(FieldKind::Text),
+        "number" => Some(FieldKind::Number),
+        "date" => Some(FieldKind::Date),
+        "select" => Some(FieldKind::Select),
+        _ => None,
+    }
+}
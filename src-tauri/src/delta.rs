@@ -0,0 +1,305 @@
+// delta.rs
+
+use serde_json::{json, Value};
+
+
+/// Parses the content of a note into the operations of a Quill delta.
+///
+/// # Arguments
+///
+/// * `content` - The content of the note, usually a Quill delta in JSON.
+///
+/// # Returns
+///
+/// Returns the operations of the delta, or a single insert of the content if it is not a delta.
+pub fn parse_ops(content: &str) -> Vec<Value> {
+    let delta: Value = match serde_json::from_str(content) {
+        Ok(delta) => delta,
+        Err(_) => return vec![json!({ "insert": content })],
+    };
+
+    // The editor either saves the delta object or its array of operations
+    match delta.get("ops").unwrap_or(&delta).as_array() {
+        Some(ops) => ops.clone(),
+        None => vec![json!({ "insert": content })],
+    }
+}
+
+
+/// Serializes the operations of a Quill delta into the content of a note, as saved by the editor.
+pub fn to_content(ops: Vec<Value>) -> String {
+    json!({ "ops": ops }).to_string()
+}
+
+
+/// Builds the operations of a header line.
+///
+/// # Arguments
+///
+/// * `text` - The text of the header.
+/// * `level` - The level of the header, from 1 to 6.
+pub fn header_ops(text: &str, level: u8) -> Vec<Value> {
+    vec![
+        json!({ "insert": text }),
+        json!({ "insert": "\n", "attributes": { "header": level } }),
+    ]
+}
+
+
+/// Splits the operations of a Quill delta at each occurrence of a delimiter, keeping the formatting.
+///
+/// # Arguments
+///
+/// * `ops` - The operations of the delta.
+/// * `delimiter` - The text to split at. It is removed from the parts.
+///
+/// # Returns
+///
+/// Returns the operations of each part. Occurrences of the delimiter spanning several operations are not split.
+pub fn split_ops(ops: &[Value], delimiter: &str) -> Vec<Vec<Value>> {
+    let mut parts = vec![Vec::new()];
+
+    for op in ops {
+        let text = match op.get("insert").and_then(|insert| insert.as_str()) {
+            Some(text) => text,
+            // Embeds such as images are kept as is
+            None => {
+                parts.last_mut().unwrap().push(op.clone());
+                continue;
+            }
+        };
+
+        for (index, piece) in text.split(delimiter).enumerate() {
+            if index > 0 {
+                parts.push(Vec::new());
+            }
+            if !piece.is_empty() {
+                let mut piece_op = op.clone();
+                piece_op["insert"] = Value::String(piece.to_string());
+                parts.last_mut().unwrap().push(piece_op);
+            }
+        }
+    }
+
+    parts
+}
+
+
+/// Converts the content of a note into Markdown.
+///
+/// # Arguments
+///
+/// * `content` - The content of the note, usually a Quill delta in JSON.
+///
+/// # Operation
+///
+/// * Headers, lists, checklists, quotes, and code blocks are converted from the line attributes of the delta.
+/// * Bold, italic, strikethrough, inline code, and links are converted from the inline attributes.
+/// * Images are converted to Markdown images and formulas to inline LaTeX math, other embeds are dropped.
+///
+/// # Returns
+///
+/// Returns the Markdown, with its blocks separated by empty lines.
+pub fn to_markdown(content: &str) -> String {
+    // Build the lines with their block format, which Quill stores on the newline ending each line
+    let mut lines: Vec<(String, String)> = Vec::new();
+    let mut current = String::new();
+    for op in parse_ops(content) {
+        let attributes = op.get("attributes").cloned().unwrap_or(Value::Null);
+        match op.get("insert") {
+            Some(Value::String(text)) => {
+                for (index, piece) in text.split('\n').enumerate() {
+                    if index > 0 {
+                        lines.push((block_format(&attributes), std::mem::take(&mut current)));
+                    }
+                    if !piece.is_empty() {
+                        current.push_str(&inline_markdown(piece, &attributes));
+                    }
+                }
+            },
+            Some(Value::Object(embed)) => {
+                if let Some(src) = embed.get("image").and_then(|src| src.as_str()) {
+                    current.push_str(&format!("![]({})", src));
+                } else if let Some(formula) = embed.get("formula").and_then(|formula| formula.as_str()) {
+                    current.push_str(&format!("${}$", formula));
+                }
+            },
+            _ => (),
+        }
+    }
+    if !current.is_empty() {
+        lines.push((String::new(), current));
+    }
+
+    // Group the lines into blocks, consecutive list items, quotes, and code lines forming a single block
+    let mut markdown = String::new();
+    let mut previous_format: Option<String> = None;
+    let mut ordered_index = 0;
+    for (format, text) in lines {
+        if format.is_empty() && text.trim().is_empty() {
+            previous_format = None;
+            continue;
+        }
+
+        let same_block = previous_format.as_deref() == Some(format.as_str()) && !format.is_empty() && !format.starts_with('#');
+        if previous_format.as_deref() == Some("code") && format != "code" {
+            markdown.push_str("```\n");
+        }
+        if !same_block && !markdown.is_empty() {
+            markdown.push('\n');
+        }
+        if format == "ordered" && !same_block {
+            ordered_index = 0;
+        }
+
+        match format.as_str() {
+            "code" => {
+                if !same_block {
+                    markdown.push_str("```\n");
+                }
+                markdown.push_str(&text);
+            },
+            "bullet" => markdown.push_str(&format!("- {}", text)),
+            "ordered" => {
+                ordered_index += 1;
+                markdown.push_str(&format!("{}. {}", ordered_index, text));
+            },
+            "checked" => markdown.push_str(&format!("- [x] {}", text)),
+            "unchecked" => markdown.push_str(&format!("- [ ] {}", text)),
+            "blockquote" => markdown.push_str(&format!("> {}", text)),
+            _ => markdown.push_str(&format!("{}{}", format, text)),
+        }
+        markdown.push('\n');
+        previous_format = Some(format);
+    }
+    if previous_format.as_deref() == Some("code") {
+        markdown.push_str("```\n");
+    }
+
+    markdown
+}
+
+
+/// Extracts the code blocks of the content of a note.
+///
+/// # Arguments
+///
+/// * `content` - The content of the note, usually a Quill delta in JSON.
+///
+/// # Operation
+///
+/// * Consecutive lines formatted as code by the editor form a block. Their language is the value of the "code-block"
+///   attribute, unless it is "plain".
+/// * Markdown fences typed as text, e.g. "```rust", also form a block, labeled with their info string.
+///
+/// # Returns
+///
+/// Returns the label of each block, if any, and its code, in the order of the note.
+pub fn code_blocks(content: &str) -> Vec<(Option<String>, String)> {
+    let mut blocks: Vec<(Option<String>, String)> = Vec::new();
+    let mut fence: Option<(Option<String>, Vec<String>)> = None;
+    let mut previous_label: Option<Option<String>> = None;
+    let mut current = String::new();
+
+    let mut push_line = |attributes: &Value, line: String| {
+        let code_block = attributes.get("code-block").filter(|code| !code.is_null() && code.as_bool() != Some(false));
+        let label = code_block.map(|code| code.as_str().filter(|label| *label != "plain").map(str::to_string));
+
+        match (&mut fence, label) {
+            // A Markdown fence is closed by a line of backticks
+            (Some((label, lines)), None) => {
+                if line.trim_start().starts_with("```") {
+                    blocks.push((label.take(), lines.join("\n")));
+                    fence = None;
+                } else {
+                    lines.push(line);
+                }
+            },
+            (None, None) => {
+                if let Some(info) = line.trim_start().strip_prefix("```") {
+                    let info = info.trim();
+                    fence = Some(((!info.is_empty()).then(|| info.to_string()), Vec::new()));
+                }
+            },
+            // Lines formatted as code by the editor join the previous block if it has the same language
+            (_, Some(label)) => {
+                match blocks.last_mut() {
+                    Some((last_label, code)) if previous_label.as_ref() == Some(&label) && *last_label == label => {
+                        code.push('\n');
+                        code.push_str(&line);
+                    },
+                    _ => blocks.push((label.clone(), line)),
+                }
+                previous_label = Some(label);
+                return;
+            },
+        }
+        previous_label = None;
+    };
+
+    for op in parse_ops(content) {
+        let attributes = op.get("attributes").cloned().unwrap_or(Value::Null);
+        if let Some(text) = op.get("insert").and_then(|insert| insert.as_str()) {
+            for (index, piece) in text.split('\n').enumerate() {
+                if index > 0 {
+                    push_line(&attributes, std::mem::take(&mut current));
+                }
+                current.push_str(piece);
+            }
+        }
+    }
+    if !current.is_empty() {
+        push_line(&Value::Null, current);
+    }
+    // A fence left open runs to the end of the note
+    if let Some((label, lines)) = fence {
+        blocks.push((label, lines.join("\n")));
+    }
+
+    blocks
+}
+
+
+/// Returns the Markdown block format of a line from the attributes of its newline: a header prefix,
+/// a list, quote or code marker, or an empty string for a paragraph.
+fn block_format(attributes: &Value) -> String {
+    if let Some(level) = attributes.get("header").and_then(|level| level.as_u64()) {
+        return format!("{} ", "#".repeat(level.clamp(1, 6) as usize));
+    }
+    if let Some(list) = attributes.get("list").and_then(|list| list.as_str()) {
+        return list.to_string();
+    }
+    if attributes.get("blockquote").is_some_and(|quote| quote.as_bool() == Some(true)) {
+        return "blockquote".to_string();
+    }
+    if attributes.get("code-block").is_some_and(|code| !code.is_null() && code.as_bool() != Some(false)) {
+        return "code".to_string();
+    }
+
+    String::new()
+}
+
+
+/// Applies the inline attributes of a piece of text as Markdown.
+fn inline_markdown(text: &str, attributes: &Value) -> String {
+    let enabled = |name: &str| attributes.get(name).is_some_and(|value| value.as_bool() == Some(true));
+
+    let mut markdown = text.to_string();
+    if enabled("code") {
+        markdown = format!("`{}`", markdown);
+    }
+    if enabled("bold") {
+        markdown = format!("**{}**", markdown);
+    }
+    if enabled("italic") {
+        markdown = format!("*{}*", markdown);
+    }
+    if enabled("strike") {
+        markdown = format!("~~{}~~", markdown);
+    }
+    if let Some(link) = attributes.get("link").and_then(|link| link.as_str()) {
+        markdown = format!("[{}]({})", markdown, link);
+    }
+
+    markdown
+}
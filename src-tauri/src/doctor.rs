@@ -0,0 +1,274 @@
+// doctor.rs
+
+use crate::{ attachment_operations, envelope, journal, plain_sync, presence_operations, s3_operations, search_index };
+use crate::local_operations::CONNECTION;
+use crate::models::{DoctorCheck, DoctorReport};
+use rusqlite::params;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+
+/// The number of seconds after which an operation of the journal that has not returned is considered stuck.
+const STUCK_AFTER_SECONDS: i64 = 10 * 60;
+
+/// The tables recording something about a note, with what they record, checked for the rows of deleted notes.
+const NOTE_RECORDS: [(&str, &str); 12] = [
+    ("code_blocks", "code blocks"),
+    ("note_citations", "citations"),
+    ("note_sources", "web sources"),
+    ("note_aliases", "aliases"),
+    ("note_tags", "tags"),
+    ("note_links", "wikilinks"),
+    ("periodic_notes", "periods"),
+    ("note_reviews", "review schedules"),
+    ("cards", "flashcards"),
+    ("focus_sessions", "focus sessions"),
+    ("note_opens", "openings"),
+    ("reencryption_notes", "encryption statuses"),
+];
+
+
+/// Checks the vault for common problems and fixes them if asked to.
+///
+/// # Arguments
+///
+/// * `fix` - Whether to fix the problems found, or only report them.
+/// * `bucket_name` - A bucket to check as well, if any.
+///
+/// # Operation
+///
+/// Each check reports the problems it found, and how many it fixed:
+///
+/// * "orphaned_attachments": attachments of deleted notes or without their file, and files belonging to no
+///   attachment. They are removed.
+/// * "missing_uuids": local notes without a UUID, which cannot be synced. They are given one.
+/// * "dangling_references": covers that are not attached to their note, and records of deleted notes, such as
+///   their code blocks, aliases, tags, wikilinks, flashcards, focus sessions, or the text of deleted
+///   attachments. They are cleared.
+/// * "index_drift": a search index holding a different number of notes than the database. It is rebuilt.
+/// * "stuck_operations": operations of the journal that have not returned for 10 minutes. They are run again.
+/// * "plain_sync_notes", with a bucket: note objects stored as unencrypted Markdown, which anyone who can read the
+///   bucket can read. They are only reported, since they were synced in plain text on purpose (see `plain_sync`),
+///   unless the note is no longer marked or is secure, which the next sync encrypts again.
+/// * "malformed_objects", with a bucket: note objects whose metadata is missing or malformed. They are only reported,
+///   since they may belong to another tool; `bucket_audit::clean_orphaned_objects` deletes them once confirmed.
+/// * "stale_locks", with a bucket: presence markers that expired. They are deleted.
+///
+/// A check that fails reports its error without stopping the others.
+///
+/// # Returns
+///
+/// Returns a `DoctorReport` with the outcome of each check.
+pub async fn run_doctor(fix: bool, bucket_name: Option<&str>) -> DoctorReport {
+    let mut checks = vec![
+        check("orphaned_attachments", attachment_operations::repair_attachments(fix)),
+        check("missing_uuids", check_missing_uuids(fix)),
+        check("dangling_references", check_dangling_references(fix)),
+        check("index_drift", check_index_drift(fix)),
+        check("stuck_operations", check_stuck_operations(fix).await),
+    ];
+    if let Some(bucket_name) = bucket_name {
+        checks.push(check("plain_sync_notes", check_plain_objects(bucket_name).await));
+        checks.push(check("malformed_objects", check_bucket_metadata(bucket_name).await));
+        checks.push(check("stale_locks", presence_operations::clean_expired_markers(bucket_name, fix).await));
+    }
+
+    DoctorReport {
+        fix,
+        checked_at: chrono::Utc::now().timestamp(),
+        checks,
+    }
+}
+
+
+/// Builds the outcome of a check from the problems it found and the number it fixed, or from its error.
+fn check(name: &str, result: Result<(Vec<String>, usize), String>) -> DoctorCheck {
+    match result {
+        Ok((problems, fixed)) => DoctorCheck { name: name.to_string(), problems, fixed, error: None },
+        Err(e) => DoctorCheck { name: name.to_string(), problems: Vec::new(), fixed: 0, error: Some(e) },
+    }
+}
+
+
+/// Finds the local notes without a UUID, and gives them one.
+fn check_missing_uuids(fix: bool) -> Result<(Vec<String>, usize), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT id, title FROM notes WHERE uuid IS NULL OR uuid = '' ORDER BY id").map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let problems = notes.iter().map(|(id, title)| format!("Note {} ('{}') has no UUID", id, title)).collect();
+    let mut fixed = 0;
+    if fix {
+        for (id, _) in &notes {
+            fixed += conn.execute("UPDATE notes SET uuid = ?1 WHERE id = ?2", params![Uuid::new_v4().to_string(), id])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok((problems, fixed))
+}
+
+
+/// Finds the covers that are not attached to their note and the records of deleted notes, and clears them.
+fn check_dangling_references(fix: bool) -> Result<(Vec<String>, usize), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut problems = Vec::new();
+    let mut fixed = 0;
+
+    let covers_condition = "cover_attachment_id IS NOT NULL
+        AND cover_attachment_id NOT IN (SELECT id FROM attachments WHERE attachments.note_id = notes.id)";
+    let mut stmt = conn.prepare(&format!("SELECT id, title FROM notes WHERE {} ORDER BY id", covers_condition)).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    problems.extend(notes.iter().map(|(id, title)| format!("The cover of note {} ('{}') is not attached to it", id, title)));
+    if fix {
+        fixed += conn.execute(&format!("UPDATE notes SET cover_attachment_id = NULL WHERE {}", covers_condition), [])
+            .map_err(|e| e.to_string())?;
+    }
+
+    for (table, records) in NOTE_RECORDS {
+        let condition = "note_id NOT IN (SELECT id FROM notes)";
+        let count: i64 = conn.query_row(&format!("SELECT COUNT(DISTINCT note_id) FROM {} WHERE {}", table, condition), [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if count > 0 {
+            problems.push(format!("The {} of {} deleted notes are still recorded", records, count));
+            if fix {
+                conn.execute(&format!("DELETE FROM {} WHERE {}", table, condition), []).map_err(|e| e.to_string())?;
+                fixed += 1;
+            }
+        }
+    }
+
+    let condition = "attachment_id NOT IN (SELECT id FROM attachments)";
+    let count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM attachment_texts WHERE {}", condition), [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if count > 0 {
+        problems.push(format!("The text of {} deleted attachments is still recorded", count));
+        if fix {
+            conn.execute(&format!("DELETE FROM attachment_texts WHERE {}", condition), []).map_err(|e| e.to_string())?;
+            fixed += 1;
+        }
+    }
+
+    Ok((problems, fixed))
+}
+
+
+/// Compares the number of notes in the local search index with the database, and rebuilds the index if they differ.
+///
+/// The index is only compared once built and up to date, since it legitimately lags behind while changes are queued.
+fn check_index_drift(fix: bool) -> Result<(Vec<String>, usize), String> {
+    let status = search_index::get_index_status();
+    if !status.ready || status.pending > 0 {
+        return Ok((Vec::new(), 0));
+    }
+
+    let condition = if search_index::indexes_secure_notes() { "1 = 1" } else { "secure = 0" };
+    let expected: i64 = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(&format!("SELECT COUNT(*) FROM notes WHERE {}", condition), [], |row| row.get(0)).map_err(|e| e.to_string())?
+    };
+    if expected as u64 == status.indexed_docs {
+        return Ok((Vec::new(), 0));
+    }
+
+    let problems = vec![format!("The search index holds {} notes instead of {}", status.indexed_docs, expected)];
+    if !fix {
+        return Ok((problems, 0));
+    }
+    search_index::schedule_rebuild();
+    Ok((problems, 1))
+}
+
+
+/// Finds the operations of the journal that have not returned for a while, and runs them again.
+async fn check_stuck_operations(fix: bool) -> Result<(Vec<String>, usize), String> {
+    let started_before = chrono::Utc::now().timestamp() - STUCK_AFTER_SECONDS;
+    let problems: Vec<String> = journal::get_stuck_operations(started_before)?
+        .into_iter()
+        .map(|(operation, target, started_at)| {
+            let started_at = chrono::DateTime::from_timestamp(started_at, 0).map(|time| time.to_rfc3339()).unwrap_or_default();
+            format!("Operation {} on {} started at {} has not returned", operation, target, started_at)
+        })
+        .collect();
+    if !fix || problems.is_empty() {
+        return Ok((problems, 0));
+    }
+
+    let retried = journal::retry_operations(started_before).await?;
+    Ok((problems, retried))
+}
+
+
+/// Finds the note objects of a bucket whose metadata is missing or malformed, and the UUIDs used by several objects.
+async fn check_bucket_metadata(bucket_name: &str) -> Result<(Vec<String>, usize), String> {
+    let client = s3_operations::s3_client().await;
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| e.to_string())?;
+    let mut problems = Vec::new();
+    let mut keys_by_uuid: HashMap<String, Vec<String>> = HashMap::new();
+    for (key, _) in objects {
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let metadata = head_object_output.metadata.unwrap_or_default();
+        let issues = envelope::metadata_issues(&metadata);
+        if !issues.is_empty() {
+            problems.push(format!("Object {}: {}", key, issues.join(", ")));
+        }
+        if let Some(uuid) = metadata.get("uuid") {
+            keys_by_uuid.entry(uuid.clone()).or_default().push(key);
+        }
+    }
+
+    let mut duplicates: Vec<_> = keys_by_uuid.into_iter().filter(|(_, keys)| keys.len() > 1).collect();
+    duplicates.sort();
+    for (uuid, keys) in duplicates {
+        problems.push(format!("Objects {} share the UUID {}", keys.join(", "), uuid));
+    }
+
+    Ok((problems, 0))
+}
+
+
+/// Finds the note objects of a bucket stored as unencrypted Markdown, telling which ones the next sync encrypts again.
+async fn check_plain_objects(bucket_name: &str) -> Result<(Vec<String>, usize), String> {
+    let client = s3_operations::s3_client().await;
+    let marked: HashMap<String, bool> = plain_sync::list_plain_sync_notes()?
+        .into_iter()
+        .map(|note| (note.uuid, note.secure))
+        .collect();
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| e.to_string())?;
+    let mut problems = Vec::new();
+    for (key, _) in objects {
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let metadata = head_object_output.metadata.unwrap_or_default();
+        if !plain_sync::is_plain_object(&metadata) {
+            continue;
+        }
+        let uuid = metadata.get("uuid").cloned().unwrap_or_default();
+        problems.push(match marked.get(&uuid) {
+            Some(false) => format!("Object {} is unencrypted: the note is synced in plain text", key),
+            Some(true) => format!("Object {} is unencrypted although the note is secure: the next sync encrypts it", key),
+            None => format!("Object {} is unencrypted although the note is not synced in plain text: the next sync encrypts it", key),
+        });
+    }
+
+    Ok((problems, 0))
+}
@@ -0,0 +1,142 @@
+// drop_operations.rs
+
+use crate::{ attachment_operations, delta, import_ledger, local_operations, notifier, models::DropFailure, models::DroppedFiles, models::Note };
+use serde_json::json;
+use std::path::Path;
+
+
+/// The extensions of the files turned into notes when dropped. Other files are attached to the open note.
+const NOTE_EXTENSIONS: [&str; 3] = ["md", "markdown", "txt"];
+
+
+/// Imports the files dropped on the window.
+///
+/// The window receives the paths of the dropped files with the "tauri://file-drop" event and hands them over with
+/// the note it shows, if any.
+///
+/// # Arguments
+///
+/// * `paths` - The paths of the dropped files.
+/// * `target_note` - The ID of the note open in the window, to which the other files are attached, if any.
+///
+/// # Operation
+///
+/// * Markdown and text files (".md", ".markdown", ".txt") become new notes, or update the notes they were imported as before. The title is the first line of a markdown
+///   file if it is a heading, or the name of the file otherwise.
+/// * The other files are attached to the open note, like `attachment_operations::add_attachment`. Without an open
+///   note, they are reported as failures.
+/// * Each file is imported on its own, so a file that cannot be imported, e.g. a directory or a text file that is not
+///   UTF-8, does not stop the others.
+///
+/// # Returns
+///
+/// Returns a `DroppedFiles` with the IDs of the new notes, the new attachments, and the files that could not be
+/// imported with the reason.
+pub fn handle_dropped_files(paths: &[String], target_note: Option<i64>) -> DroppedFiles {
+    let mut dropped_files = DroppedFiles {
+        note_ids: Vec::new(),
+        attachments: Vec::new(),
+        failures: Vec::new(),
+    };
+
+    for path in paths {
+        let source = Path::new(path);
+        let extension = source.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+
+        let result = if source.is_dir() {
+            Err(format!("{} is a directory", path))
+        } else if NOTE_EXTENSIONS.contains(&extension.as_str()) {
+            import_note(source, extension != "txt").map(|note_id| dropped_files.note_ids.push(note_id))
+        } else if let Some(note_id) = target_note {
+            attachment_operations::add_attachment(note_id, path)
+                .map(|attachment| dropped_files.attachments.push(attachment))
+                .map_err(String::from)
+        } else {
+            Err("No note is open to attach the file to".to_string())
+        };
+
+        if let Err(error) = result {
+            dropped_files.failures.push(DropFailure { path: path.clone(), error });
+        }
+    }
+
+    let imported = dropped_files.note_ids.len() + dropped_files.attachments.len();
+    match imported {
+        0 => {},
+        1 => notifier::notify("File imported", "The dropped file was imported."),
+        _ => notifier::notify("Files imported", &format!("{} dropped files were imported.", imported)),
+    }
+
+    dropped_files
+}
+
+
+/// Creates a note from a markdown or text file, e.g. a file dropped on the window or opened with the application.
+///
+/// # Arguments
+///
+/// * `source` - The path of the file.
+/// * `markdown` - Whether the file is markdown, in which case a heading on its first line is the title of the note.
+///
+/// # Operation
+///
+/// A file imported before updates its note rather than creating another one, or leaves it as it is if the file did
+/// not change (see `import_ledger`).
+///
+/// # Returns
+///
+/// Returns the ID of the note, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read or is not UTF-8, if it is too long for a note,
+/// if the note of a file imported before is locked, or if there is an issue with the database connection.
+pub fn import_note(source: &Path, markdown: bool) -> Result<i64, String> {
+    let text = std::fs::read(source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    let text = String::from_utf8(text).map_err(|_| format!("{} is not a UTF-8 text file", source.display()))?;
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(&file_title(source, &text, markdown)),
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", text.trim_end()) })]),
+        nonce: None,
+        created_at: chrono::Utc::now().timestamp(),
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status: None,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: Vec::new(),
+    };
+    let fingerprint = import_ledger::fingerprint(&import_ledger::path_source(source), "");
+    import_ledger::import_once(&fingerprint, &note).map(|imported| imported.note_id)
+}
+
+
+/// Returns the title of a note imported from a file: the heading on the first line of a markdown file, or the name of
+/// the file otherwise.
+///
+/// # Arguments
+///
+/// * `source` - The path of the file.
+/// * `text` - The text of the file.
+/// * `markdown` - Whether the file is markdown.
+pub fn file_title(source: &Path, text: &str, markdown: bool) -> String {
+    let heading = markdown
+        .then(|| text.lines().map(str::trim).find(|line| !line.is_empty()))
+        .flatten()
+        .and_then(|line| line.strip_prefix("# "))
+        .map(str::trim);
+    match heading {
+        Some(heading) if !heading.is_empty() => heading.to_string(),
+        _ => source.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+    }
+}
@@ -0,0 +1,439 @@
+// email_operations.rs
+
+use crate::{ attachment_operations, delta, local_operations, markdown, note_events, notifier, settings, tasks, vault };
+use crate::local_operations::CONNECTION;
+use crate::models::{AppError, EmailIngestionConfig, EmailIngestionSettings, Note, SmtpConfig, SmtpSettings, TaskPriority};
+use crate::tasks::TaskContext;
+use lettre::message::{header::ContentType, Attachment as MailAttachment, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use mail_parser::{MessageParser, MimeHeaders};
+use ring::digest;
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use std::time::Duration;
+
+
+/// The settings key under which the mailbox to ingest is stored, as JSON.
+const CONFIG_SETTING: &str = "email_ingestion";
+
+/// The settings key under which the password of the mailbox is stored encrypted (see `settings::set_secret_setting`).
+const PASSWORD_SETTING: &str = "email_ingestion_password";
+
+/// The settings key under which the SMTP account used to send notes is stored, as JSON.
+const SMTP_CONFIG_SETTING: &str = "email_smtp";
+
+/// The settings key under which the password of the SMTP account is stored encrypted (see `settings::set_secret_setting`).
+const SMTP_PASSWORD_SETTING: &str = "email_smtp_password";
+
+/// The SMTP port using implicit TLS. Other ports upgrade the connection with STARTTLS.
+const SMTPS_PORT: u16 = 465;
+
+/// The settings key under which the time of the last successful fetch is stored, as a Unix timestamp.
+const LAST_FETCHED_SETTING: &str = "email_last_fetched_at";
+
+/// The number of days of emails fetched the first time, before any email was ingested.
+const FIRST_FETCH_DAYS: i64 = 7;
+
+/// How often, in minutes, the configuration is read again while email ingestion is disabled.
+const DISABLED_CHECK_MINUTES: u32 = 5;
+
+/// The title given to the notes of emails without a subject.
+const UNTITLED_EMAIL: &str = "(no subject)";
+
+/// The tag of the notes created from emails.
+const EMAIL_TAG: &str = "email";
+
+
+/// Starts polling the configured mailbox for new emails.
+///
+/// # Operation
+///
+/// A thread submits a fetch task every poll interval while email ingestion is enabled. The configuration is read
+/// again before each poll, so changes apply without restarting the application.
+pub fn start_email_polling() {
+    std::thread::spawn(|| loop {
+        let interval = match get_config() {
+            Ok(Some(config)) if config.enabled => {
+                tasks::submit_unless_queued(tasks::EMAIL_FETCH, "Fetch new emails", TaskPriority::Low);
+                config.poll_interval_minutes
+            },
+            Ok(_) => DISABLED_CHECK_MINUTES,
+            Err(e) => {
+                eprintln!("Failed to read the email ingestion settings: {}", e);
+                DISABLED_CHECK_MINUTES
+            },
+        };
+        std::thread::sleep(Duration::from_secs(interval.max(1) as u64 * 60));
+    });
+}
+
+
+/// Runs the fetch task, which turns the new emails of the configured mailbox into notes.
+pub fn run_fetch(_context: &TaskContext) -> Result<(), String> {
+    fetch_email().map(|_| ())
+}
+
+
+/// Fetches the new emails of the configured mailbox and turns them into notes.
+///
+/// # Operation
+///
+/// * The emails received since the day before the last fetch are downloaded over IMAP with TLS, without marking
+///   them as read. The first fetch covers the last 7 days.
+/// * Each email not ingested yet, as told by its Message-ID, becomes a note: its subject is the title, its body is
+///   the content, converted to Markdown when it is HTML, and its attachments are attached to the note. The note is
+///   tagged "email".
+/// * An email that cannot be turned into a note is skipped, and tried again on the next fetch.
+///
+/// It must not be called while the database connection is locked.
+///
+/// # Returns
+///
+/// Returns the number of new notes, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if email ingestion is not configured, if the mailbox cannot be reached or
+/// the login fails, or if there is an issue with the database connection.
+pub fn fetch_email() -> Result<usize, String> {
+    let config = get_config()?.ok_or("Email ingestion is not configured".to_string())?;
+    let password = settings::get_secret_setting(PASSWORD_SETTING)?.ok_or("The password of the mailbox is not set".to_string())?;
+
+    let started_at = chrono::Utc::now();
+    let since = match settings::get_setting(LAST_FETCHED_SETTING)?.and_then(|value| value.parse::<i64>().ok()) {
+        // Searches are by day, so the day before is fetched again in case of a time zone mismatch
+        Some(last_fetched_at) => chrono::DateTime::from_timestamp(last_fetched_at, 0).unwrap_or(started_at) - chrono::Duration::days(1),
+        None => started_at - chrono::Duration::days(FIRST_FETCH_DAYS),
+    };
+
+    let raw_messages = fetch_raw_messages(&config, &password, since.date_naive())?;
+    let mut imported = 0;
+    for raw_message in raw_messages {
+        match import_message(&raw_message) {
+            Ok(true) => imported += 1,
+            Ok(false) => {},
+            Err(e) => eprintln!("Failed to import an email: {}", e),
+        }
+    }
+    settings::set_setting(LAST_FETCHED_SETTING, &started_at.timestamp().to_string())?;
+
+    if imported > 0 {
+        // Send a desktop notification
+        notifier::notify("Emails imported", &format!("{} new email(s) were turned into notes.", imported));
+    }
+
+    Ok(imported)
+}
+
+
+/// Retrieves the email ingestion settings. The password is never returned.
+///
+/// # Returns
+///
+/// Returns an `EmailIngestionSettings` with the configuration of the mailbox, or `None` if it is not configured,
+/// whether its password is set, and the time of the last successful fetch.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_email_ingestion() -> Result<EmailIngestionSettings, String> {
+    Ok(EmailIngestionSettings {
+        config: get_config()?,
+        has_password: settings::get_setting(PASSWORD_SETTING)?.is_some(),
+        last_fetched_at: settings::get_setting(LAST_FETCHED_SETTING)?.and_then(|value| value.parse().ok()),
+    })
+}
+
+
+/// Configures the mailbox whose emails are turned into notes.
+///
+/// # Arguments
+///
+/// * `config` - The IMAP server, the login, the folder to fetch, how often to poll it, and whether polling is enabled.
+/// * `password` - The password of the mailbox, stored encrypted. `None` keeps the current password.
+///
+/// # Operation
+///
+/// When polling is enabled, a fetch is submitted right away.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the host, the username, or the folder is empty, if the poll interval is
+/// zero, or if there is an issue with the database connection.
+pub fn set_email_ingestion(config: EmailIngestionConfig, password: Option<String>) -> Result<(), String> {
+    if config.host.trim().is_empty() {
+        return Err("Host cannot be empty".to_string());
+    }
+    if config.username.trim().is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+    if config.folder.trim().is_empty() {
+        return Err("Folder cannot be empty".to_string());
+    }
+    if config.poll_interval_minutes == 0 {
+        return Err("Poll interval should be at least 1 minute".to_string());
+    }
+
+    if let Some(password) = password {
+        settings::set_secret_setting(PASSWORD_SETTING, &password)?;
+    }
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)?;
+
+    if config.enabled {
+        tasks::submit_unless_queued(tasks::EMAIL_FETCH, "Fetch new emails", TaskPriority::Low);
+    }
+
+    Ok(())
+}
+
+
+/// Sends a local note by email, so it can be shared with people who do not use the application.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `to` - The address of the recipient, e.g. "Jane Doe <jane@example.org>".
+///
+/// # Operation
+///
+/// * The email is sent through the configured SMTP account, with TLS. Its subject is the title of the note.
+/// * Its body is the note rendered as HTML, with the Markdown of the note as the plain text alternative.
+/// * The attachments of the note are attached to the email.
+///
+/// It blocks until the server accepts the email, so it must not be called from the async runtime directly.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the email is sent successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if no SMTP account is configured, if the note does not exist, if it is secure
+/// while the vault is locked, if an address is invalid, if an attachment cannot be read, or if the server rejects the email.
+pub fn send_note_email(note_id: i64, to: &str) -> Result<(), AppError> {
+    let config = get_smtp_config()?.ok_or("No SMTP account is configured".to_string())?;
+    let password = settings::get_secret_setting(SMTP_PASSWORD_SETTING)?.ok_or("The password of the SMTP account is not set".to_string())?;
+
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if note.secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to send secure notes".to_string()));
+    }
+
+    // Render the note, keeping its Markdown as the plain text version
+    let markdown = delta::to_markdown(&note.content);
+    let html = markdown::render_content(&markdown);
+
+    let mut body = MultiPart::mixed().multipart(MultiPart::alternative_plain_html(markdown, html));
+    for attachment in attachment_operations::get_note_attachments(note_id)? {
+        let (attachment, data) = attachment_operations::read_attachment(attachment.id)?;
+        let content_type = ContentType::parse(&attachment.mime_type).unwrap_or(ContentType::TEXT_PLAIN);
+        body = body.singlepart(MailAttachment::new(attachment.file_name).body(data, content_type));
+    }
+
+    let email = Message::builder()
+        .from(config.from.parse().map_err(|e| format!("Invalid sender address {}: {}", config.from, e))?)
+        .to(to.parse().map_err(|e| format!("Invalid recipient address {}: {}", to, e))?)
+        .subject(note.title.clone())
+        .multipart(body)
+        .map_err(|e| e.to_string())?;
+
+    let transport = if config.port == SMTPS_PORT {
+        SmtpTransport::relay(&config.host)
+    } else {
+        SmtpTransport::starttls_relay(&config.host)
+    }.map_err(|e| format!("Failed to connect to {}: {}", config.host, e))?;
+    transport
+        .port(config.port)
+        .credentials(Credentials::new(config.username, password))
+        .build()
+        .send(&email)
+        .map_err(|e| format!("Failed to send the note: {}", e))?;
+
+    // Send a desktop notification
+    notifier::notify("Note sent", &format!("Note with title '{}' was sent to {}.", note.title, to));
+
+    Ok(())
+}
+
+
+/// Retrieves the SMTP account used to send notes. The password is never returned.
+///
+/// # Returns
+///
+/// Returns an `SmtpSettings` with the configuration of the account, or `None` if it is not configured,
+/// and whether its password is set.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_smtp_settings() -> Result<SmtpSettings, String> {
+    Ok(SmtpSettings {
+        config: get_smtp_config()?,
+        has_password: settings::get_setting(SMTP_PASSWORD_SETTING)?.is_some(),
+    })
+}
+
+
+/// Configures the SMTP account used to send notes.
+///
+/// # Arguments
+///
+/// * `config` - The SMTP server, its port, the login, and the sender address. Port 465 uses implicit TLS,
+///   other ports STARTTLS.
+/// * `password` - The password of the account, stored encrypted. `None` keeps the current password.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the host or the username is empty, if the sender address is invalid,
+/// or if there is an issue with the database connection.
+pub fn set_smtp_settings(config: SmtpConfig, password: Option<String>) -> Result<(), String> {
+    if config.host.trim().is_empty() {
+        return Err("Host cannot be empty".to_string());
+    }
+    if config.username.trim().is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+    config.from.parse::<lettre::message::Mailbox>().map_err(|e| format!("Invalid sender address {}: {}", config.from, e))?;
+
+    if let Some(password) = password {
+        settings::set_secret_setting(SMTP_PASSWORD_SETTING, &password)?;
+    }
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(SMTP_CONFIG_SETTING, &json)
+}
+
+
+/// Reads the configuration of the mailbox from the settings.
+fn get_config() -> Result<Option<EmailIngestionConfig>, String> {
+    settings::get_setting(CONFIG_SETTING)?
+        .map(|json| serde_json::from_str(&json).map_err(|e| format!("Invalid email ingestion settings: {}", e)))
+        .transpose()
+}
+
+
+/// Reads the configuration of the SMTP account from the settings.
+fn get_smtp_config() -> Result<Option<SmtpConfig>, String> {
+    settings::get_setting(SMTP_CONFIG_SETTING)?
+        .map(|json| serde_json::from_str(&json).map_err(|e| format!("Invalid SMTP settings: {}", e)))
+        .transpose()
+}
+
+
+/// Downloads the emails of the configured folder received since a day, oldest first, without marking them as read.
+fn fetch_raw_messages(config: &EmailIngestionConfig, password: &str, since: chrono::NaiveDate) -> Result<Vec<Vec<u8>>, String> {
+    let tls = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+    let client = imap::connect((config.host.as_str(), config.port), &config.host, &tls)
+        .map_err(|e| format!("Failed to connect to {}: {}", config.host, e))?;
+    let mut session = client.login(&config.username, password)
+        .map_err(|(e, _)| format!("Failed to log in to {}: {}", config.host, e))?;
+
+    session.select(&config.folder).map_err(|e| format!("Failed to open folder {}: {}", config.folder, e))?;
+    let mut uids: Vec<_> = session.uid_search(format!("SINCE {}", since.format("%d-%b-%Y")))
+        .map_err(|e| format!("Failed to search folder {}: {}", config.folder, e))?
+        .into_iter()
+        .collect();
+    uids.sort();
+
+    let mut raw_messages = Vec::new();
+    if !uids.is_empty() {
+        let uid_set = uids.iter().map(|uid| uid.to_string()).collect::<Vec<_>>().join(",");
+        let fetches = session.uid_fetch(uid_set, "BODY.PEEK[]").map_err(|e| format!("Failed to fetch emails: {}", e))?;
+        raw_messages = fetches.iter().filter_map(|fetch| fetch.body().map(<[u8]>::to_vec)).collect();
+    }
+    if let Err(e) = session.logout() {
+        eprintln!("Failed to log out from {}: {}", config.host, e);
+    }
+
+    Ok(raw_messages)
+}
+
+
+/// Turns an email into a note with its attachments, unless it was already ingested.
+///
+/// # Returns
+///
+/// Returns `Ok(true)` if a note is created, or `Ok(false)` if the email was already ingested.
+fn import_message(raw_message: &[u8]) -> Result<bool, String> {
+    let message = MessageParser::default().parse(raw_message).ok_or("Malformed email".to_string())?;
+
+    // Emails without a Message-ID are told apart by their content
+    let message_id = match message.message_id() {
+        Some(message_id) => message_id.to_string(),
+        None => format!("sha256:{}", hex(digest::digest(&digest::SHA256, raw_message).as_ref())),
+    };
+    {
+        let conn = CONNECTION.lock().unwrap();
+        let known: Option<i64> = conn.query_row("SELECT note_id FROM email_messages WHERE message_id = ?1", params![message_id], |row| row.get(0))
+            .optional().map_err(|e| e.to_string())?;
+        if known.is_some() {
+            return Ok(false);
+        }
+    }
+
+    let body = match message.html_part(0) {
+        Some(part) if part.is_text_html() => html2md::parse_html(&message.body_html(0).unwrap_or_default()),
+        _ => message.body_text(0).map(|text| text.to_string()).unwrap_or_default(),
+    };
+    let body = body.trim_end();
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(message.subject().map(str::trim).filter(|subject| !subject.is_empty()).unwrap_or(UNTITLED_EMAIL)),
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", body) })]),
+        nonce: None,
+        created_at: message.date().map(|date| date.to_timestamp()).unwrap_or_else(|| chrono::Utc::now().timestamp()),
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status: None,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: vec![EMAIL_TAG.to_string()],
+    };
+    local_operations::validate_params(note.clone())?;
+
+    // Create the note, its attachments, and remember the email in a single transaction
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let note_id = local_operations::insert_note(&tx, &note)?;
+    note_events::note_saved(&tx, note_id, &note.content)?;
+
+    for (index, attachment) in message.attachments().enumerate() {
+        let file_name = attachment.attachment_name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("attachment-{}", index + 1));
+        attachment_operations::store_attachment(&tx, note_id, &file_name, attachment.contents())?;
+    }
+
+    tx.execute(
+        "INSERT INTO email_messages (message_id, note_id, fetched_at) VALUES (?1, ?2, ?3)",
+        params![message_id, note_id, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(true)
+}
+
+
+/// Encodes bytes as lowercase hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,238 @@
+// envelope.rs
+
+use crate::{ crypto, key_management, plain_sync, s3_operations, local_operations::CONNECTION, models::AppError, models::NoteEnvelope };
+use base64::{Engine as _, engine::general_purpose};
+use ring::digest;
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashMap;
+
+
+/// The cipher reported for the objects of the notes synced in plain text, which are not encrypted (see `plain_sync`).
+const PLAIN_CIPHER: &str = "none (plain sync)";
+
+
+/// Describes the encryption envelope of a local note without decrypting it, to debug sync and encryption issues.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns a `NoteEnvelope` with the non-secret fields of the envelope: the cipher, the key version (0 for the all-zero
+/// key of the previous versions, see `key_management`), the length of the nonce, the sizes of the stored content, the
+/// SHA-256 checksum of the ciphertext, and the problems found with it.
+/// Neither the content nor the nonce is returned.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn inspect_local_envelope(note_id: i64) -> Result<NoteEnvelope, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let (uuid, content, nonce, secure): (Option<String>, String, Option<String>, bool) = conn.query_row(
+        "SELECT uuid, content, nonce, secure FROM notes WHERE id = ?1",
+        params![note_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    ).optional()?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+
+    let mut issues = Vec::new();
+    let ciphertext = match general_purpose::STANDARD.decode(&content) {
+        Ok(ciphertext) => Some(ciphertext),
+        Err(_) => {
+            issues.push("The content is not valid base64".to_string());
+            None
+        },
+    };
+
+    Ok(NoteEnvelope {
+        location: "local".to_string(),
+        uuid,
+        secure,
+        key_version: Some(key_management::key_version(&conn)?),
+        stored_size: content.len() as i64,
+        ..describe_envelope(ciphertext.as_deref(), nonce.as_deref(), issues)
+    })
+}
+
+
+/// Describes the encryption envelope of a note stored in an Amazon S3 bucket without decrypting it.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `uuid` - The UUID of the note.
+///
+/// # Operation
+///
+/// * The objects of the bucket are listed, and the note is found by the "uuid" field of their metadata.
+/// * The object is downloaded to measure and checksum its ciphertext. It is not decrypted.
+///
+/// # Returns
+///
+/// Returns a `NoteEnvelope` like `inspect_local_envelope`, with the ETag of the object and the names of its metadata
+/// fields. The object of a note synced in plain text has the cipher "none (plain sync)", without a nonce.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error or if the note is not found in the bucket.
+pub async fn inspect_bucket_envelope(bucket_name: &str, uuid: &str) -> Result<NoteEnvelope, AppError> {
+    let client = s3_operations::s3_client().await;
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))?;
+    for (key, e_tag) in objects {
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3(&e))?;
+
+        let metadata: HashMap<String, String> = head_object_output.metadata.unwrap_or_default();
+        if metadata.get("uuid").map(|s| s.as_str()) != Some(uuid) {
+            continue;
+        }
+
+        let object = client.get_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3(&e))?;
+        let ciphertext = object.body.collect().await.map_err(|e| AppError::S3(e.to_string()))?.to_vec();
+
+        let mut metadata_keys: Vec<String> = metadata.keys().cloned().collect();
+        metadata_keys.sort();
+
+        if plain_sync::is_plain_object(&metadata) {
+            return Ok(NoteEnvelope {
+                location: "bucket".to_string(),
+                uuid: Some(uuid.to_string()),
+                secure: metadata.get("secure").map(|s| s == "true").unwrap_or(false),
+                cipher: PLAIN_CIPHER.to_string(),
+                key_version: None,
+                nonce_length: None,
+                tag_length: 0,
+                stored_size: ciphertext.len() as i64,
+                ciphertext_size: None,
+                plaintext_size: Some(ciphertext.len() as i64),
+                sha256: Some(hex(digest::digest(&digest::SHA256, &ciphertext).as_ref())),
+                e_tag,
+                metadata_keys,
+                issues: metadata_issues(&metadata),
+            });
+        }
+
+        return Ok(NoteEnvelope {
+            location: "bucket".to_string(),
+            uuid: Some(uuid.to_string()),
+            secure: metadata.get("secure").map(|s| s == "true").unwrap_or(false),
+            key_version: Some(s3_operations::key_version_from_metadata(&metadata)),
+            stored_size: ciphertext.len() as i64,
+            e_tag,
+            metadata_keys,
+            ..describe_envelope(Some(&ciphertext), metadata.get("nonce").map(|s| s.as_str()), Vec::new())
+        });
+    }
+
+    Err(AppError::NotFound("Note not found".to_string()))
+}
+
+
+/// Checks the metadata of a note object of a bucket, which every version of the application writes with the
+/// UUID of the note, the nonce of its content, and whether it is secure. The objects of the notes synced in plain text
+/// have no nonce, and must not be secure (see `plain_sync`).
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns a description of each problem found, empty if the metadata is well-formed.
+pub fn metadata_issues(metadata: &HashMap<String, String>) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    match metadata.get("uuid") {
+        Some(uuid) if uuid::Uuid::parse_str(uuid).is_ok() => (),
+        Some(uuid) => issues.push(format!("The UUID {} is malformed", uuid)),
+        None => issues.push("The UUID is missing".to_string()),
+    }
+    if plain_sync::is_plain_object(metadata) {
+        if metadata.get("secure").is_some_and(|secure| secure == "true") {
+            issues.push("The note is secure but stored unencrypted".to_string());
+        }
+    } else {
+        let nonce = metadata.get("nonce").map(|nonce| crypto::split_nonce(nonce));
+        match nonce.map(|(cipher, nonce)| (crypto::algorithm(cipher).nonce_len(), general_purpose::STANDARD.decode(nonce))) {
+            Some((nonce_len, Ok(nonce))) if nonce.len() == nonce_len => (),
+            Some((nonce_len, Ok(nonce))) => issues.push(format!("The nonce is {} bytes long instead of {}", nonce.len(), nonce_len)),
+            Some((_, Err(_))) => issues.push("The nonce is not valid base64".to_string()),
+            None => issues.push("The nonce is missing".to_string()),
+        }
+    }
+    if let Some(secure) = metadata.get("secure").filter(|secure| *secure != "true" && *secure != "false") {
+        issues.push(format!("The secure flag {} is malformed", secure));
+    }
+    if let Some(created_at) = metadata.get("created_at").filter(|created_at| created_at.parse::<i64>().is_err()) {
+        issues.push(format!("The creation time {} is malformed", created_at));
+    }
+
+    issues
+}
+
+
+/// Describes the encryption of an envelope from its ciphertext and nonce, checking the nonce and the length of the
+/// ciphertext. The cipher is the one recorded with the nonce (see `crypto::split_nonce`). The fields about where the
+/// envelope is stored, and the version of its key, are left empty for the caller to fill.
+fn describe_envelope(ciphertext: Option<&[u8]>, nonce: Option<&str>, mut issues: Vec<String>) -> NoteEnvelope {
+    let (cipher, nonce) = match nonce.map(crypto::split_nonce) {
+        Some((cipher, nonce)) => (cipher, Some(nonce)),
+        None => (crypto::current_cipher(), None),
+    };
+    let algorithm = crypto::algorithm(cipher);
+    let tag_length = algorithm.tag_len();
+
+    let nonce_length = match nonce.map(|nonce| general_purpose::STANDARD.decode(nonce)) {
+        Some(Ok(nonce)) => Some(nonce.len()),
+        Some(Err(_)) => {
+            issues.push("The nonce is not valid base64".to_string());
+            None
+        },
+        None => {
+            issues.push("The nonce is missing".to_string());
+            None
+        },
+    };
+    if let Some(length) = nonce_length.filter(|length| *length != algorithm.nonce_len()) {
+        issues.push(format!("The nonce is {} bytes long instead of {}", length, algorithm.nonce_len()));
+    }
+
+    if let Some(ciphertext) = ciphertext {
+        if ciphertext.len() < tag_length {
+            issues.push("The ciphertext is shorter than its authentication tag".to_string());
+        }
+    }
+
+    NoteEnvelope {
+        location: String::new(),
+        uuid: None,
+        secure: false,
+        cipher: crypto::cipher_name(cipher).to_string(),
+        key_version: None,
+        nonce_length,
+        tag_length,
+        stored_size: 0,
+        ciphertext_size: ciphertext.map(|ciphertext| ciphertext.len() as i64),
+        plaintext_size: ciphertext.map(|ciphertext| ciphertext.len().saturating_sub(tag_length) as i64),
+        sha256: ciphertext.map(|ciphertext| hex(digest::digest(&digest::SHA256, ciphertext).as_ref())),
+        e_tag: None,
+        metadata_keys: Vec::new(),
+        issues,
+    }
+}
+
+
+/// Encodes bytes as lowercase hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,265 @@
+// errors.rs
+
+use crate::models::{ AppError, BucketError };
+use aws_sdk_s3 as s3;
+use s3::error::{ ConnectorError, ProvideErrorMetadata, SdkError };
+use s3::operation::{
+    create_bucket::CreateBucketError, delete_bucket::DeleteBucketError, delete_object::DeleteObjectError,
+    get_bucket_tagging::GetBucketTaggingError, get_object::GetObjectError, head_bucket::HeadBucketError,
+    head_object::HeadObjectError, list_buckets::ListBucketsError, list_objects_v2::ListObjectsV2Error,
+    put_bucket_tagging::PutBucketTaggingError, put_object::PutObjectError,
+};
+use std::error::Error;
+
+
+/// The codes S3 answers with for the objects and the buckets that do not exist.
+const NOT_FOUND_CODES: &[&str] = &["NoSuchKey", "NoSuchBucket", "NotFound"];
+
+
+/// Tells the kind of the failure of an S3 operation, going on with the next error of the chain if it is another
+/// operation than the ones the application sends.
+macro_rules! operation_error_kind {
+    ($err:expr, $($operation_error:ty),* $(,)?) => {
+        $(
+            if let Some(err) = $err.downcast_ref::<SdkError<$operation_error>>() {
+                return Some(sdk_error_kind(err));
+            }
+        )*
+    };
+}
+
+
+impl AppError {
+    /// Tells what failed in an S3 operation from the errors its failure wraps, so the window can tell apart, e.g., a
+    /// note that does not exist from missing AWS credentials, and show what to do about it.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The failure of the operation.
+    ///
+    /// # Operation
+    ///
+    /// The failure and its sources are looked at in turn, from the outermost:
+    ///
+    /// * An `AppError`, e.g. the note the operation looked for is missing, is kept as it is.
+    /// * The objects and the buckets that do not exist, i.e. S3 answered with one of `NOT_FOUND_CODES`, are
+    ///   `AppError::NotFound`.
+    /// * The requests that timed out or could not be sent are `AppError::Network`.
+    ///
+    /// Any other failure is `AppError::S3`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `AppError` with the message of the failure.
+    pub fn s3(err: &(dyn Error + 'static)) -> AppError {
+        let mut source = Some(err);
+        while let Some(current) = source {
+            if let Some(kind) = s3_error_kind(current) {
+                return kind(err.to_string());
+            }
+            source = current.source();
+        }
+        AppError::S3(err.to_string())
+    }
+
+
+    /// Tells what failed in an S3 operation as `AppError::s3` does, with what the operation was doing before the
+    /// message of the failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The failure of the operation.
+    /// * `context` - What the operation was doing, e.g. "Failed to read notes/1234".
+    ///
+    /// # Returns
+    ///
+    /// Returns the `AppError` with the context and the message of the failure.
+    pub fn s3_with(err: &(dyn Error + 'static), context: &str) -> AppError {
+        AppError::s3(err).kind()(format!("{}: {}", context, err))
+    }
+
+
+    /// The kind of the error, which builds an error of the same kind from a message.
+    fn kind(&self) -> fn(String) -> AppError {
+        match self {
+            AppError::Validation(_) => AppError::Validation,
+            AppError::NotFound(_) => AppError::NotFound,
+            AppError::Database(_) => AppError::Database,
+            AppError::S3(_) => AppError::S3,
+            AppError::Encryption(_) => AppError::Encryption,
+            AppError::Network(_) => AppError::Network,
+            AppError::Unavailable(_) => AppError::Unavailable,
+            AppError::Failed(_) => AppError::Failed,
+        }
+    }
+}
+
+
+/// Tells the kind of an S3 failure from one error of its chain, `None` if the error does not tell.
+fn s3_error_kind(err: &(dyn Error + 'static)) -> Option<fn(String) -> AppError> {
+    if let Some(err) = err.downcast_ref::<AppError>() {
+        return Some(err.kind());
+    }
+    if err.is::<ConnectorError>() {
+        return Some(AppError::Network);
+    }
+    if let Some(err) = err.downcast_ref::<s3::Error>() {
+        return match err {
+            s3::Error::NoSuchKey(_) | s3::Error::NoSuchBucket(_) | s3::Error::NotFound(_) => Some(AppError::NotFound),
+            // The failures S3 did not answer, e.g. timeouts, are told by their source
+            err if err.code().is_none() => None,
+            _ => Some(AppError::S3),
+        };
+    }
+    operation_error_kind!(
+        err,
+        CreateBucketError, DeleteBucketError, DeleteObjectError, GetBucketTaggingError, GetObjectError,
+        HeadBucketError, HeadObjectError, ListBucketsError, ListObjectsV2Error, PutBucketTaggingError, PutObjectError,
+    );
+    None
+}
+
+
+/// Tells the kind of the failure of an S3 operation.
+fn sdk_error_kind<E: ProvideErrorMetadata>(err: &SdkError<E>) -> fn(String) -> AppError {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) => AppError::Network,
+        SdkError::ServiceError(context) if context.err().code().is_some_and(|code| NOT_FOUND_CODES.contains(&code)) => {
+            AppError::NotFound
+        },
+        _ => AppError::S3,
+    }
+}
+
+
+/// The failures the operations return as messages tell nothing of what failed, and are `AppError::Failed`.
+impl From<String> for AppError {
+    fn from(message: String) -> AppError {
+        AppError::Failed(message)
+    }
+}
+
+
+impl From<&str> for AppError {
+    fn from(message: &str) -> AppError {
+        AppError::Failed(message.to_string())
+    }
+}
+
+
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> AppError {
+        match err {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound(err.to_string()),
+            err => AppError::Database(err.to_string()),
+        }
+    }
+}
+
+
+/// The failures of the operations returning `anyhow::Error` keep the `AppError` or the database error they wrap.
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> AppError {
+        match err.downcast::<AppError>() {
+            Ok(err) => err,
+            Err(err) => match err.downcast::<rusqlite::Error>() {
+                Ok(err) => AppError::from(err),
+                Err(err) => AppError::Failed(err.to_string()),
+            },
+        }
+    }
+}
+
+
+impl From<BucketError> for AppError {
+    fn from(err: BucketError) -> AppError {
+        match err {
+            BucketError::BucketAlreadyExists => AppError::Validation(err.to_string()),
+            BucketError::S3Error(source) => AppError::s3(source.as_ref()),
+            BucketError::TaggingError => AppError::S3(err.to_string()),
+        }
+    }
+}
+
+
+/// The operations returning their failures as messages keep the message of an `AppError`.
+impl From<AppError> for String {
+    fn from(err: AppError) -> String {
+        err.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+
+    /// An error wrapping another one, as the operations wrap the failures of their steps.
+    #[derive(Debug)]
+    struct Wrapped(Box<dyn Error + Send + Sync>);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "The operation failed")
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(self.0.as_ref())
+        }
+    }
+
+    #[test]
+    fn messages_are_failed() {
+        assert!(matches!(AppError::from("Something went wrong".to_string()), AppError::Failed(_)));
+        assert!(matches!(AppError::from("Something went wrong"), AppError::Failed(_)));
+    }
+
+    #[test]
+    fn missing_rows_are_not_found() {
+        assert!(matches!(AppError::from(rusqlite::Error::QueryReturnedNoRows), AppError::NotFound(_)));
+        assert!(matches!(AppError::from(rusqlite::Error::InvalidQuery), AppError::Database(_)));
+    }
+
+    #[test]
+    fn anyhow_keeps_the_wrapped_error() {
+        let err = anyhow::Error::new(AppError::Validation("Invalid title".to_string()));
+        assert!(matches!(AppError::from(err), AppError::Validation(message) if message == "Invalid title"));
+        assert!(matches!(AppError::from(anyhow::Error::new(rusqlite::Error::QueryReturnedNoRows)), AppError::NotFound(_)));
+        assert!(matches!(AppError::from(anyhow::anyhow!("Something went wrong")), AppError::Failed(_)));
+    }
+
+    #[test]
+    fn s3_keeps_an_app_error_of_the_chain() {
+        let err = Wrapped(Box::new(Wrapped(Box::new(AppError::NotFound("Note not found".to_string())))));
+        assert!(matches!(AppError::s3(&err), AppError::NotFound(message) if message == "The operation failed"));
+    }
+
+    #[test]
+    fn s3_tells_missing_objects_and_network_failures() {
+        let err = s3::Error::NoSuchKey(s3::types::error::NoSuchKey::builder().build());
+        assert!(matches!(AppError::s3(&err), AppError::NotFound(_)));
+
+        let err: SdkError<GetObjectError> = SdkError::timeout_error("The request timed out");
+        assert!(matches!(AppError::s3(&Wrapped(Box::new(err))), AppError::Network(_)));
+
+        let err: SdkError<GetObjectError> = SdkError::dispatch_failure(ConnectorError::timeout("The request timed out".into()));
+        assert!(matches!(AppError::s3(&err), AppError::Network(_)));
+    }
+
+    #[test]
+    fn s3_with_keeps_the_kind() {
+        let err: SdkError<GetObjectError> = SdkError::timeout_error("The request timed out");
+        let err = AppError::s3_with(&err, "Failed to read notes/1234");
+        assert!(matches!(err, AppError::Network(message) if message.starts_with("Failed to read notes/1234: ")));
+    }
+
+    #[test]
+    fn s3_defaults_to_s3() {
+        let err = std::io::Error::other("Access denied");
+        assert!(matches!(AppError::s3(&err), AppError::S3(_)));
+        assert!(matches!(AppError::from(BucketError::BucketAlreadyExists), AppError::Validation(_)));
+    }
+}
@@ -0,0 +1,447 @@
+// export_operations.rs
+
+use crate::{ delta, local_operations, notifier, vault, models::ArchivedNote, models::ExportFormat, models::ExportManifest, models::ManifestEntry, models::Note, models::NoteArchive, models::SiteFlavor };
+use ring::digest;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+
+/// The section of the site the notes are written to.
+const NOTES_SECTION: &str = "notes";
+
+/// The version of the format of the JSON archives, increased when a field is changed or removed.
+const ARCHIVE_VERSION: u32 = 1;
+
+/// The name of the manifest written alongside the Markdown files of an export.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// The version of the format of the manifests, increased when a field is changed or removed.
+const MANIFEST_VERSION: u32 = 1;
+
+
+/// Exports the local notes as the content tree of a static site.
+///
+/// # Arguments
+///
+/// * `path` - The root directory of the site. It must not exist or be empty.
+/// * `flavor` - The static site generator to target: Hugo or Zola.
+///
+/// # Operation
+///
+/// * Every note that is not secure is converted to Markdown and written to "content/notes/{slug}.md", with front matter
+///   holding its title and dates: YAML for Hugo, TOML for Zola. Secure notes are never published.
+/// * An "_index.md" file is written for the site and for the notes section.
+///
+/// # Returns
+///
+/// Returns the number of exported notes, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the directory is not empty, if a file cannot be written,
+/// or if there is an issue with the database connection.
+pub async fn export_site(path: &str, flavor: SiteFlavor) -> Result<usize, String> {
+    let root = Path::new(path);
+    if root.exists() && fs::read_dir(root).map_err(|e| e.to_string())?.next().is_some() {
+        return Err(format!("Directory '{}' is not empty", path));
+    }
+
+    let notes: Vec<Note> = local_operations::get_local_notes().await?
+        .into_iter()
+        .filter(|note| !note.secure)
+        .collect();
+
+    let section_dir = root.join("content").join(NOTES_SECTION);
+    fs::create_dir_all(&section_dir).map_err(|e| e.to_string())?;
+    fs::write(root.join("content").join("_index.md"), section_index(&flavor, "Home")).map_err(|e| e.to_string())?;
+    fs::write(section_dir.join("_index.md"), section_index(&flavor, "Notes")).map_err(|e| e.to_string())?;
+
+    // Write each note under a unique slug
+    let mut slugs = HashSet::new();
+    for note in &notes {
+        let slug = unique_slug(&mut slugs, &note.title);
+        let page = format!("{}\n{}", front_matter(&flavor, note), delta::to_markdown(&note.content));
+        fs::write(section_dir.join(format!("{}.md", slug)), page).map_err(|e| e.to_string())?;
+    }
+
+    // Send a desktop notification
+    notifier::notify("Site exported", &format!("{} notes were exported to {}.", notes.len(), path));
+
+    Ok(notes.len())
+}
+
+
+/// Exports every local note, decrypted, so the notes can be read or imported elsewhere.
+///
+/// # Arguments
+///
+/// * `format` - The format of the export: Markdown files or a JSON archive.
+/// * `path` - The directory the Markdown files are written to, which must not exist or be empty, or the file the
+///   JSON archive is written to.
+///
+/// # Operation
+///
+/// * Markdown: each note is converted to Markdown and written to "{slug}.md", with YAML front matter holding its
+///   title, UUID, creation and update dates, tags, aliases, status, and whether it is secure. A "manifest.json"
+///   file lists every file with the UUID of its note and its SHA-256 checksum, followed by the checksum of the
+///   manifest.
+/// * JSON: the notes are written to a single archive with the same fields, their Markdown, and their content as
+///   saved by the editor, so they can be imported again without losing their formatting. The archive ends with
+///   its SHA-256 checksum.
+/// * The secure notes are exported too, so the vault must be unlocked if there are any (see `vault`). The
+///   attachments and the history of the notes are not exported.
+/// * Exporting the same notes twice gives identical files, so two exports can be compared: the notes are sorted by
+///   creation date then UUID, their tags are sorted, the Markdown uses "\n" line endings, and the date of the
+///   export is the date of the last change to the exported notes rather than the current time.
+///
+/// # Returns
+///
+/// Returns the number of exported notes, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the directory is not empty, if there are secure notes while the vault is
+/// locked, if a file cannot be written, or if there is an issue with the database connection.
+pub async fn export_notes(format: ExportFormat, path: &str) -> Result<usize, String> {
+    let mut notes = local_operations::get_local_notes().await?;
+    if !vault::is_unlocked() && notes.iter().any(|note| note.secure) {
+        return Err("Unlock the vault to export the secure notes".to_string());
+    }
+
+    // Sort the notes and their tags, so the same notes are always exported the same way
+    notes.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.uuid.cmp(&b.uuid)).then_with(|| a.id.cmp(&b.id)));
+    for note in &mut notes {
+        note.tags.sort();
+    }
+    let exported_at = notes.iter().map(|note| note.updated_at.unwrap_or(note.created_at)).max().unwrap_or_default();
+
+    match format {
+        ExportFormat::Markdown => {
+            let root = Path::new(path);
+            if root.exists() && fs::read_dir(root).map_err(|e| e.to_string())?.next().is_some() {
+                return Err(format!("Directory '{}' is not empty", path));
+            }
+            fs::create_dir_all(root).map_err(|e| e.to_string())?;
+
+            let mut slugs = HashSet::new();
+            let mut files = Vec::with_capacity(notes.len());
+            for note in &notes {
+                let slug = unique_slug(&mut slugs, &note.title);
+                let file_name = format!("{}.md", slug);
+                let page = normalize_line_endings(&format!("{}\n{}", export_front_matter(note), delta::to_markdown(&note.content)));
+                fs::write(root.join(&file_name), &page).map_err(|e| e.to_string())?;
+                files.push(ManifestEntry {
+                    path: file_name,
+                    uuid: note.uuid.clone().unwrap_or_default(),
+                    sha256: sha256(page.as_bytes()),
+                });
+            }
+
+            let mut manifest = ExportManifest {
+                version: MANIFEST_VERSION,
+                exported_at,
+                files,
+                checksum: String::new(),
+            };
+            manifest.checksum = manifest_checksum(&manifest)?;
+            let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+            fs::write(root.join(MANIFEST_FILE), json + "\n").map_err(|e| e.to_string())?;
+        },
+        ExportFormat::Json => {
+            let mut archive = NoteArchive {
+                version: ARCHIVE_VERSION,
+                exported_at,
+                notes: notes.iter().map(|note| ArchivedNote {
+                    uuid: note.uuid.clone().unwrap_or_default(),
+                    title: note.title.clone(),
+                    markdown: normalize_line_endings(&delta::to_markdown(&note.content)),
+                    content: note.content.clone(),
+                    created_at: note.created_at,
+                    updated_at: note.updated_at,
+                    secure: note.secure,
+                    locked: note.locked,
+                    pinned: note.pinned,
+                    archived: note.archived,
+                    status: note.status.clone(),
+                    icon: note.icon.clone(),
+                    aliases: note.aliases.clone(),
+                    tags: note.tags.clone(),
+                }).collect(),
+                checksum: None,
+            };
+            archive.checksum = Some(archive_checksum(&archive)?);
+            let json = serde_json::to_string_pretty(&archive).map_err(|e| e.to_string())? + "\n";
+            if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        },
+    }
+
+    // Send a desktop notification
+    notifier::notify("Notes exported", &format!("{} notes were exported to {}.", notes.len(), path));
+
+    Ok(notes.len())
+}
+
+
+/// Checks that an export made by `export_notes` has not changed since it was written.
+///
+/// # Arguments
+///
+/// * `path` - The directory of a Markdown export, or the file of a JSON archive.
+///
+/// # Operation
+///
+/// * Markdown: the checksum of the manifest is checked, then the checksum of every file it lists. The Markdown
+///   files missing from the manifest are reported too.
+/// * JSON: the checksum of the archive is checked.
+///
+/// # Returns
+///
+/// Returns the problems found, empty if the export is intact, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the manifest or the archive cannot be read, or if the archive was
+/// exported without a checksum.
+pub fn verify_export(path: &str) -> Result<Vec<String>, String> {
+    let root = Path::new(path);
+    let mut problems = Vec::new();
+
+    if root.is_dir() {
+        let manifest_path = root.join(MANIFEST_FILE);
+        let json = fs::read_to_string(&manifest_path).map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+        let manifest: ExportManifest = serde_json::from_str(&json).map_err(|e| format!("Invalid manifest: {}", e))?;
+        if manifest_checksum(&manifest)? != manifest.checksum {
+            problems.push("The manifest was modified".to_string());
+        }
+
+        let mut listed = HashSet::new();
+        for entry in &manifest.files {
+            listed.insert(entry.path.clone());
+            match fs::read(root.join(&entry.path)) {
+                Ok(bytes) if sha256(&bytes) == entry.sha256 => {},
+                Ok(_) => problems.push(format!("{} was modified", entry.path)),
+                Err(_) => problems.push(format!("{} is missing", entry.path)),
+            }
+        }
+
+        let mut unlisted: Vec<String> = fs::read_dir(root).map_err(|e| e.to_string())?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".md") && !listed.contains(name))
+            .collect();
+        unlisted.sort();
+        problems.extend(unlisted.into_iter().map(|name| format!("{} is not in the manifest", name)));
+    } else {
+        let json = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let archive: NoteArchive = serde_json::from_str(&json).map_err(|e| format!("Invalid archive: {}", e))?;
+        let checksum = archive.checksum.as_ref().ok_or("The archive has no checksum: it was exported by an older version".to_string())?;
+        if &archive_checksum(&archive)? != checksum {
+            problems.push("The archive was modified".to_string());
+        }
+    }
+
+    Ok(problems)
+}
+
+
+/// Computes the checksum of a manifest, from its compact JSON with an empty checksum, so its version and date are
+/// covered too.
+fn manifest_checksum(manifest: &ExportManifest) -> Result<String, String> {
+    let unsigned = ExportManifest { checksum: String::new(), ..manifest.clone() };
+    let json = serde_json::to_string(&unsigned).map_err(|e| e.to_string())?;
+    Ok(sha256(json.as_bytes()))
+}
+
+
+/// Computes the checksum of an archive, from its compact JSON without a checksum, so its version and date are
+/// covered too.
+fn archive_checksum(archive: &NoteArchive) -> Result<String, String> {
+    let unsigned = NoteArchive { checksum: None, ..archive.clone() };
+    let json = serde_json::to_string(&unsigned).map_err(|e| e.to_string())?;
+    Ok(sha256(json.as_bytes()))
+}
+
+
+/// Computes the SHA-256 checksum of some bytes, as hexadecimal.
+fn sha256(bytes: &[u8]) -> String {
+    digest::digest(&digest::SHA256, bytes).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+/// Converts the "\r\n" and "\r" line endings of a text to "\n".
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+
+/// Builds the YAML front matter of an exported note.
+fn export_front_matter(note: &Note) -> String {
+    let list = |values: &[String]| values.iter().map(|value| format!("\"{}\"", escape(value))).collect::<Vec<_>>().join(", ");
+    let mut front_matter = format!(
+        "---\ntitle: \"{}\"\nuuid: \"{}\"\ncreated: {}\nupdated: {}\ntags: [{}]\naliases: [{}]\n",
+        escape(&note.title),
+        escape(note.uuid.as_deref().unwrap_or_default()),
+        format_date(note.created_at),
+        format_date(note.updated_at.unwrap_or(note.created_at)),
+        list(&note.tags),
+        list(&note.aliases),
+    );
+    if let Some(status) = &note.status {
+        front_matter.push_str(&format!("status: \"{}\"\n", escape(status)));
+    }
+    if note.secure {
+        front_matter.push_str("secure: true\n");
+    }
+    front_matter.push_str("---\n");
+    front_matter
+}
+
+
+/// Builds the front matter of a note page.
+fn front_matter(flavor: &SiteFlavor, note: &Note) -> String {
+    let date = format_date(note.created_at);
+    let updated = format_date(note.updated_at.unwrap_or(note.created_at));
+    let title = escape(&note.title);
+
+    match flavor {
+        SiteFlavor::Hugo => format!("---\ntitle: \"{}\"\ndate: {}\nlastmod: {}\n---\n", title, date, updated),
+        SiteFlavor::Zola => format!("+++\ntitle = \"{}\"\ndate = {}\nupdated = {}\n+++\n", title, date, updated),
+    }
+}
+
+
+/// Builds the "_index.md" file of a section.
+fn section_index(flavor: &SiteFlavor, title: &str) -> String {
+    match flavor {
+        SiteFlavor::Hugo => format!("---\ntitle: \"{}\"\n---\n", title),
+        SiteFlavor::Zola => format!("+++\ntitle = \"{}\"\nsort_by = \"date\"\n+++\n", title),
+    }
+}
+
+
+/// Formats a Unix timestamp as an RFC 3339 date, understood by both YAML and TOML front matter.
+fn format_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+
+/// Escapes a string for a double-quoted YAML or TOML value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+/// Builds a file name from the title of a note that is not taken yet, with a numbered suffix if needed.
+fn unique_slug(slugs: &mut HashSet<String>, title: &str) -> String {
+    let base_slug = slugify(title);
+    let mut slug = base_slug.clone();
+    let mut suffix = 2;
+    while !slugs.insert(slug.clone()) {
+        slug = format!("{}-{}", base_slug, suffix);
+        suffix += 1;
+    }
+    slug
+}
+
+
+/// Builds the file name of a page from the title of a note.
+fn slugify(title: &str) -> String {
+    let slug = title.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if slug.is_empty() { "note".to_string() } else { slug }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Creates an empty directory for a test, removed by the test once done.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("custom_notes_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a Markdown export of one file, as `export_notes` does.
+    fn write_export(dir: &Path, exported_at: i64) {
+        let page = "---\ntitle: Note\n---\nContent\n";
+        fs::write(dir.join("note.md"), page).unwrap();
+        let mut manifest = ExportManifest {
+            version: MANIFEST_VERSION,
+            exported_at,
+            files: vec![ManifestEntry { path: "note.md".to_string(), uuid: "uuid".to_string(), sha256: sha256(page.as_bytes()) }],
+            checksum: String::new(),
+        };
+        manifest.checksum = manifest_checksum(&manifest).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn normalize_line_endings_uses_line_feeds() {
+        assert_eq!(normalize_line_endings("a\r\nb\rc\nd"), "a\nb\nc\nd");
+        assert_eq!(normalize_line_endings("\r\n\r\n"), "\n\n");
+    }
+
+    #[test]
+    fn verify_export_accepts_an_intact_export() {
+        let dir = test_dir("intact");
+        write_export(&dir, 100);
+        assert!(verify_export(dir.to_str().unwrap()).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_export_reports_changed_files() {
+        let dir = test_dir("changed");
+        write_export(&dir, 100);
+        fs::write(dir.join("note.md"), "Changed\n").unwrap();
+        fs::write(dir.join("other.md"), "Other\n").unwrap();
+        assert_eq!(verify_export(dir.to_str().unwrap()).unwrap(), vec!["note.md was modified", "other.md is not in the manifest"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_export_covers_the_whole_manifest() {
+        let dir = test_dir("manifest");
+        write_export(&dir, 100);
+        let json = fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), json.replace("\"exported_at\": 100", "\"exported_at\": 200")).unwrap();
+        assert_eq!(verify_export(dir.to_str().unwrap()).unwrap(), vec!["The manifest was modified"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_export_checks_archives() {
+        let dir = test_dir("archive");
+        let path = dir.join("notes.json");
+        let mut archive = NoteArchive { version: ARCHIVE_VERSION, exported_at: 100, notes: Vec::new(), checksum: None };
+        fs::write(&path, serde_json::to_string(&archive).unwrap()).unwrap();
+        assert!(verify_export(path.to_str().unwrap()).is_err());
+
+        archive.checksum = Some(archive_checksum(&archive).unwrap());
+        fs::write(&path, serde_json::to_string(&archive).unwrap()).unwrap();
+        assert!(verify_export(path.to_str().unwrap()).unwrap().is_empty());
+
+        archive.exported_at = 200;
+        fs::write(&path, serde_json::to_string(&archive).unwrap()).unwrap();
+        assert_eq!(verify_export(path.to_str().unwrap()).unwrap(), vec!["The archive was modified"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
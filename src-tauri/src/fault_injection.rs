@@ -0,0 +1,235 @@
+// fault_injection.rs
+
+use aws_smithy_runtime_api::client::http::{http_client_fn, HttpClient, HttpConnector, HttpConnectorFuture, SharedHttpConnector};
+use aws_smithy_runtime_api::client::orchestrator::HttpRequest;
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use lazy_static::lazy_static;
+use rusqlite::Connection;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+
+/// The environment variable listing the faults to inject, read once on startup of debug builds.
+///
+/// It holds rules separated by commas, each written `<layer>.<operation>=<effect>`:
+///
+/// * The layer is "sqlite", whose operations are "read", "insert", "update", and "delete", or "s3", whose operations
+///   are "get", "put", "head", "list", and "delete". The operation "*" matches all the operations of the layer.
+/// * The effect is "delay:<milliseconds>" to add latency, "fail" to fail every call, or "fail:<count>" to fail the
+///   next calls, up to the count, and let the following ones through.
+///
+/// For example, `CUSTOMNOTES_FAULTS="s3.*=delay:2000,s3.put=fail:2,sqlite.update=fail"` slows down all the S3 requests,
+/// fails the next two uploads, and fails every update of the database.
+const FAULTS_VARIABLE: &str = "CUSTOMNOTES_FAULTS";
+
+/// The layers faults can be injected into.
+const SQLITE: &str = "sqlite";
+const S3: &str = "s3";
+
+
+/// A fault to inject into the matching operations.
+struct FaultRule {
+    layer: String,
+    operation: String,
+    effect: FaultEffect,
+}
+
+/// The effect of a fault.
+enum FaultEffect {
+    /// Adds latency to every call.
+    Delay(Duration),
+    /// Fails every call if there is no count, or the next calls up to the count.
+    Fail(Option<AtomicU64>),
+}
+
+/// The faults to inject into an operation.
+struct Fault {
+    delay: Duration,
+    fail: bool,
+}
+
+
+lazy_static! {
+    /// The faults read from the environment. Release builds never inject faults.
+    static ref FAULT_RULES: Vec<FaultRule> = match std::env::var(FAULTS_VARIABLE) {
+        Ok(rules) if cfg!(debug_assertions) => parse_rules(&rules),
+        _ => Vec::new(),
+    };
+}
+
+
+/// Injects the faults of the "sqlite" layer into a database connection.
+///
+/// # Arguments
+///
+/// * `conn` - The connection, once its tables are created.
+///
+/// # Operation
+///
+/// The faults are checked when a statement is prepared: a failure denies the statement, which fails with a
+/// "not authorized" error, and a delay blocks the thread holding the connection. An update is checked once per
+/// column it sets, so its delay adds up for each column.
+pub fn inject_sqlite_faults(conn: &Connection) {
+    if !FAULT_RULES.iter().any(|rule| rule.layer == SQLITE) {
+        return;
+    }
+
+    conn.authorizer(Some(|context: AuthContext<'_>| {
+        let operation = match context.action {
+            AuthAction::Select => "read",
+            AuthAction::Insert { .. } => "insert",
+            AuthAction::Update { .. } => "update",
+            AuthAction::Delete { .. } => "delete",
+            _ => return Authorization::Allow,
+        };
+
+        let fault = fault(SQLITE, operation);
+        if !fault.delay.is_zero() {
+            std::thread::sleep(fault.delay);
+        }
+        if fault.fail { Authorization::Deny } else { Authorization::Allow }
+    }));
+}
+
+
+/// Injects the faults of the "s3" layer into the S3 clients created from an AWS configuration.
+///
+/// # Arguments
+///
+/// * `config` - The AWS configuration.
+///
+/// # Operation
+///
+/// The HTTP client of the configuration is wrapped, so each request is delayed or failed before it is sent. A failure
+/// is reported as an I/O error, which the SDK retries like a dropped connection, so a failure with a count lower than
+/// the attempts of the SDK is only seen by the retries.
+///
+/// # Returns
+///
+/// Returns the configuration, with its HTTP client wrapped if there are faults to inject.
+pub fn inject_s3_faults(config: aws_config::SdkConfig) -> aws_config::SdkConfig {
+    if !FAULT_RULES.iter().any(|rule| rule.layer == S3) {
+        return config;
+    }
+    let Some(http_client) = config.http_client().or_else(aws_smithy_runtime::client::http::hyper_014::default_client) else {
+        eprintln!("Failed to inject the S3 faults: no HTTP client is available");
+        return config;
+    };
+
+    config.to_builder()
+        .http_client(http_client_fn(move |settings, components| {
+            SharedHttpConnector::new(FaultyConnector(http_client.http_connector(settings, components)))
+        }))
+        .build()
+}
+
+
+/// An HTTP connector injecting the faults of the "s3" layer before sending the requests.
+#[derive(Debug)]
+struct FaultyConnector(SharedHttpConnector);
+
+impl HttpConnector for FaultyConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let operation = s3_operation(request.method(), request.uri());
+        let fault = fault(S3, operation);
+        let connector = self.0.clone();
+
+        HttpConnectorFuture::new(async move {
+            tokio::time::sleep(fault.delay).await;
+            if fault.fail {
+                return Err(ConnectorError::io(format!("Injected failure of the S3 {} request", operation).into()));
+            }
+            connector.call(request).await
+        })
+    }
+}
+
+
+/// Returns the operation of an S3 request, from its method and its address.
+fn s3_operation(method: &str, uri: &str) -> &'static str {
+    let path = uri.split_once("://").map(|(_, rest)| rest).unwrap_or(uri);
+    let path = path.find('/').map(|start| &path[start..]).unwrap_or("");
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    match method {
+        "GET" if path.is_empty() || path == "/" => "list",
+        "GET" => "get",
+        "PUT" => "put",
+        "HEAD" => "head",
+        "DELETE" => "delete",
+        "POST" if query.split('&').any(|parameter| parameter == "delete" || parameter.starts_with("delete=")) => "delete",
+        _ => "other",
+    }
+}
+
+
+/// Returns the faults to inject into an operation, and uses up a failure of the rules failing a number of calls.
+fn fault(layer: &str, operation: &str) -> Fault {
+    let mut fault = Fault { delay: Duration::ZERO, fail: false };
+    for rule in FAULT_RULES.iter().filter(|rule| rule.layer == layer && (rule.operation == "*" || rule.operation == operation)) {
+        match &rule.effect {
+            FaultEffect::Delay(delay) => fault.delay += *delay,
+            FaultEffect::Fail(None) => fault.fail = true,
+            FaultEffect::Fail(Some(remaining)) => {
+                if remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| count.checked_sub(1)).is_ok() {
+                    fault.fail = true;
+                }
+            },
+        }
+    }
+
+    if fault.fail {
+        eprintln!("Injected a failure into {}.{}", layer, operation);
+    }
+    fault
+}
+
+
+/// Parses the rules of the environment variable, printing and skipping the malformed ones.
+fn parse_rules(rules: &str) -> Vec<FaultRule> {
+    rules.split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| match parse_rule(rule) {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                eprintln!("Ignored the fault {}: {}", rule, e);
+                None
+            },
+        })
+        .collect()
+}
+
+
+/// Parses a rule written `<layer>.<operation>=<effect>`.
+fn parse_rule(rule: &str) -> Result<FaultRule, String> {
+    let (target, effect) = rule.split_once('=').ok_or("expected <layer>.<operation>=<effect>".to_string())?;
+    let (layer, operation) = target.trim().split_once('.').ok_or("expected <layer>.<operation>".to_string())?;
+
+    let operations: &[&str] = match layer {
+        SQLITE => &["read", "insert", "update", "delete", "*"],
+        S3 => &["get", "put", "head", "list", "delete", "*"],
+        _ => return Err(format!("unknown layer {}", layer)),
+    };
+    if !operations.contains(&operation) {
+        return Err(format!("unknown operation {} of {}", operation, layer));
+    }
+
+    let effect = match effect.trim().split_once(':') {
+        None if effect.trim() == "fail" => FaultEffect::Fail(None),
+        Some(("fail", count)) => FaultEffect::Fail(Some(AtomicU64::new(
+            count.parse().map_err(|_| format!("invalid count {}", count))?
+        ))),
+        Some(("delay", milliseconds)) => FaultEffect::Delay(Duration::from_millis(
+            milliseconds.parse().map_err(|_| format!("invalid delay {}", milliseconds))?
+        )),
+        _ => return Err(format!("unknown effect {}", effect)),
+    };
+
+    Ok(FaultRule {
+        layer: layer.to_string(),
+        operation: operation.to_string(),
+        effect,
+    })
+}
@@ -0,0 +1,109 @@
+// features.rs
+
+use crate::{ settings, models::FeatureFlag };
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+
+/// The experimental feature searching the notes by meaning rather than by words.
+pub const SEMANTIC_SEARCH: &str = "semantic_search";
+
+/// The experimental feature calling web addresses when notes change.
+pub const WEBHOOKS: &str = "webhooks";
+
+/// The experimental feature running plugins.
+pub const PLUGINS: &str = "plugins";
+
+/// The experimental features, with their title and description shown to the user. They are all disabled by default.
+const FEATURES: [(&str, &str, &str); 3] = [
+    (SEMANTIC_SEARCH, "Semantic search", "Find notes by meaning rather than by exact words."),
+    (WEBHOOKS, "Webhooks", "Call web addresses when notes are created, updated, or deleted."),
+    (PLUGINS, "Plugins", "Run plugins that add commands to the application."),
+];
+
+/// The prefix of the settings keys under which the toggles are stored, as "true" or "false", followed by the feature ID.
+const FEATURE_SETTING_PREFIX: &str = "feature:";
+
+
+lazy_static! {
+    /// The enabled features, read once from the settings so checking a feature stays cheap.
+    static ref ENABLED_FEATURES: RwLock<HashSet<&'static str>> = RwLock::new(
+        FEATURES.iter()
+            .map(|(id, _, _)| *id)
+            .filter(|id| settings::get_setting(&setting_key(id)).ok().flatten().as_deref() == Some("true"))
+            .collect()
+    );
+}
+
+
+/// Lists the experimental features and whether they are enabled.
+///
+/// # Returns
+///
+/// Returns a `FeatureFlag` for each feature, with its ID, title, description, and whether it is enabled.
+pub fn list_features() -> Vec<FeatureFlag> {
+    let enabled_features = ENABLED_FEATURES.read().unwrap();
+    FEATURES.iter()
+        .map(|(id, title, description)| FeatureFlag {
+            id: id.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            enabled: enabled_features.contains(id),
+        })
+        .collect()
+}
+
+
+/// Enables or disables an experimental feature.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the feature.
+/// * `enabled` - Whether the feature is enabled. The choice takes effect right away and is kept across restarts.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the choice is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the feature does not exist or if there is an issue with the database connection.
+pub fn set_feature_enabled(id: &str, enabled: bool) -> Result<(), String> {
+    let id = FEATURES.iter()
+        .map(|(feature_id, _, _)| *feature_id)
+        .find(|feature_id| *feature_id == id)
+        .ok_or(format!("Unknown feature {}", id))?;
+
+    settings::set_setting(&setting_key(id), &enabled.to_string())?;
+    let mut enabled_features = ENABLED_FEATURES.write().unwrap();
+    if enabled {
+        enabled_features.insert(id);
+    } else {
+        enabled_features.remove(id);
+    }
+    Ok(())
+}
+
+
+/// Tells whether an experimental feature is enabled, so its commands and interface can stay hidden until it is.
+///
+/// The features are read from the settings on first use, so it must not be called while the database connection
+/// is locked the first time.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the feature.
+///
+/// # Returns
+///
+/// Returns `true` if the feature is enabled, or `false` if it is disabled or does not exist.
+pub fn is_enabled(id: &str) -> bool {
+    ENABLED_FEATURES.read().unwrap().contains(id)
+}
+
+
+/// Returns the settings key of the toggle of a feature.
+fn setting_key(id: &str) -> String {
+    format!("{}{}", FEATURE_SETTING_PREFIX, id)
+}
@@ -0,0 +1,226 @@
+// file_associations.rs
+
+use crate::{ drop_operations, local_operations, note_events, status_operations, vault, local_operations::CONNECTION, models::AppError, models::CnoteFile, models::Note };
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Manager};
+
+
+/// The extension of the files of a single note exported by `export_cnote`.
+const CNOTE_EXTENSION: &str = "cnote";
+
+/// The format written in the files of a single note, so other JSON files are not mistaken for them.
+const CNOTE_FORMAT: &str = "customnotes.note";
+
+/// The version of the format of the files of a single note.
+const CNOTE_VERSION: u32 = 1;
+
+/// The extensions of the markdown files the application opens.
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// The event telling the window that files were opened with the application, so it takes the new notes with `take_opened_notes`.
+const NOTES_OPENED_EVENT: &str = "notes-opened";
+
+
+/// The handle of the application, which tells the window about the opened files.
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+
+
+lazy_static! {
+    /// The notes to show that the window did not take yet, e.g. the notes created from the files the application was
+    /// launched with, opened before the window listens to the event.
+    static ref OPENED_NOTES: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+}
+
+
+/// Gives the handle of the application, to tell the window about the opened files. It is called once the application is set up.
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+pub fn init(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+}
+
+
+/// Returns the files the application was launched with, which is how Windows and Linux open a file associated with
+/// the application. macOS sends them with the `RunEvent::Opened` event instead.
+pub fn launch_files() -> Vec<PathBuf> {
+    std::env::args_os().skip(1).map(PathBuf::from).filter(|path| is_supported(path)).collect()
+}
+
+
+/// Opens files with the application, e.g. a markdown or ".cnote" file double-clicked in the file manager.
+///
+/// # Arguments
+///
+/// * `paths` - The paths of the files. Files of other types are ignored.
+///
+/// # Operation
+///
+/// * A markdown file becomes a new note, like a file dropped on the window (see `drop_operations`).
+/// * A ".cnote" file, exported by `export_cnote`, becomes a new note with its title, status, icon, and dates.
+/// * The new notes are kept until the window takes them with `take_opened_notes`, and the window is told with the
+///   "notes-opened" event so it shows them. The files that cannot be opened are printed and skipped.
+pub fn open_files(paths: &[PathBuf]) {
+    let mut note_ids = Vec::new();
+    for path in paths.iter().filter(|path| is_supported(path)) {
+        let result = if has_extension(path, &[CNOTE_EXTENSION]) {
+            import_cnote(path)
+        } else {
+            drop_operations::import_note(path, true)
+        };
+        match result {
+            Ok(note_id) => note_ids.push(note_id),
+            Err(e) => eprintln!("Failed to open {}: {}", path.display(), e),
+        }
+    }
+    show_notes(&note_ids);
+}
+
+
+/// Shows notes in the window, e.g. the notes created from the opened files or a note pinned to the tray (see `tray`).
+///
+/// # Arguments
+///
+/// * `note_ids` - The IDs of the notes.
+///
+/// # Operation
+///
+/// The notes are kept until the window takes them with `take_opened_notes`, and the window is told with the
+/// "notes-opened" event.
+pub fn show_notes(note_ids: &[i64]) {
+    if note_ids.is_empty() {
+        return;
+    }
+
+    OPENED_NOTES.lock().unwrap().extend(note_ids);
+    if let Some(app) = APP_HANDLE.get() {
+        if let Err(e) = app.emit_all(NOTES_OPENED_EVENT, note_ids) {
+            eprintln!("Failed to tell the window about the opened notes: {}", e);
+        }
+    }
+}
+
+
+/// Takes the notes to show since the last call, e.g. the notes created from the files opened with the application,
+/// so the window shows them.
+///
+/// # Returns
+///
+/// Returns the IDs of the notes, in the order they were opened.
+pub fn take_opened_notes() -> Vec<i64> {
+    std::mem::take(&mut *OPENED_NOTES.lock().unwrap())
+}
+
+
+/// Exports a local note to a ".cnote" file, which opens as a new note when double-clicked where the application is installed.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `path` - The path of the file. The ".cnote" extension is added if it is missing.
+///
+/// # Operation
+///
+/// The file is JSON, holding the title, plaintext content, status, icon, and dates of the note. Secure notes are only
+/// exported while the vault is unlocked, since the file is not encrypted.
+///
+/// # Returns
+///
+/// Returns the path of the written file, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it is secure while the vault is locked,
+/// if the file cannot be written, or if there is an issue with the database connection.
+pub fn export_cnote(id: i64, path: &str) -> Result<String, AppError> {
+    let note = local_operations::get_local_notes_where("id = ?1", &[&id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if note.secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to export a secure note".to_string()));
+    }
+
+    let mut path = PathBuf::from(path);
+    if !has_extension(&path, &[CNOTE_EXTENSION]) {
+        path.as_mut_os_string().push(format!(".{}", CNOTE_EXTENSION));
+    }
+
+    let cnote = CnoteFile {
+        format: CNOTE_FORMAT.to_string(),
+        version: CNOTE_VERSION,
+        title: note.title,
+        content: note.content,
+        status: note.status,
+        icon: note.icon,
+        created_at: note.created_at,
+        updated_at: note.updated_at,
+    };
+    let json = serde_json::to_string_pretty(&cnote).map_err(|e| e.to_string())?;
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(path.display().to_string())
+}
+
+
+/// Creates a note from a ".cnote" file.
+fn import_cnote(path: &Path) -> Result<i64, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let cnote: CnoteFile = serde_json::from_str(&json).map_err(|e| format!("Invalid note file: {}", e))?;
+    if cnote.format != CNOTE_FORMAT {
+        return Err("Not a note file".to_string());
+    }
+    if cnote.version > CNOTE_VERSION {
+        return Err("The note file was exported by a newer version of the application".to_string());
+    }
+
+    // The status may not be in the workflow of this vault, in which case it is dropped rather than failing the import
+    let status = cnote.status.filter(|status| status_operations::check_status(status).is_ok());
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(&cnote.title),
+        content: cnote.content,
+        nonce: None,
+        created_at: cnote.created_at,
+        updated_at: cnote.updated_at,
+        timestamp: None,
+        secure: false,
+        status,
+        icon: cnote.icon,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: Vec::new(),
+    };
+    local_operations::validate_params(note.clone())?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let note_id = local_operations::insert_note(&tx, &note)?;
+    tx.execute("UPDATE notes SET updated_at = ?1 WHERE id = ?2", rusqlite::params![note.updated_at, note_id])
+        .map_err(|e| e.to_string())?;
+    note_events::note_saved(&tx, note_id, &note.content)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(note_id)
+}
+
+
+/// Tells whether the application opens a file: a markdown or ".cnote" file.
+fn is_supported(path: &Path) -> bool {
+    has_extension(path, &MARKDOWN_EXTENSIONS) || has_extension(path, &[CNOTE_EXTENSION])
+}
+
+
+/// Tells whether a file has one of some extensions, whatever their case.
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| extensions.contains(&extension.as_str()))
+}
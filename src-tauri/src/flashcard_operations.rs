@@ -0,0 +1,471 @@
+// flashcard_operations.rs
+
+use crate::{ crypto, preview, review_operations, tag_operations, vault, local_operations::CONNECTION, models::AppError, models::CardKind, models::DeckScope, models::DeckStats, models::Flashcard };
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+
+/// The number of seconds in a day, the unit of the intervals.
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// The number of due cards returned when no limit is given.
+const DEFAULT_LIMIT: usize = 50;
+
+/// The interval, in days, from which a card is considered learned, as in Anki.
+const MATURE_INTERVAL_DAYS: i64 = 21;
+
+/// The deck the exported cards are filed under in Anki, each note being a subdeck of it.
+const ANKI_DECK: &str = "CustomNotes";
+
+/// What the front of a cloze card shows in place of the hidden text when it has no hint.
+const CLOZE_BLANK: &str = "[...]";
+
+
+lazy_static! {
+    /// Matches a cloze deletion, e.g. `{{Paris}}`, or in the Anki syntax `{{c1::Paris}}` or `{{c1::Paris::capital}}`
+    /// with a hint.
+    static ref CLOZE: Regex = Regex::new(r"\{\{(?:c\d+::)?([^{}\n]*?)\}\}").unwrap();
+}
+
+
+/// Records the flashcards of a note in the "cards" table, keeping the schedule of the cards that did not change.
+///
+/// The cards are written in the note as:
+///
+/// * A question on a line starting with "Q:", and its answer on the next line starting with "A:". The answer goes on
+///   until a blank line, the next question, or a cloze deletion.
+/// * A cloze deletion, e.g. "The capital of France is {{Paris}}.", which hides the text between the braces. Each
+///   deletion of a line is a card of its own. The Anki syntax, e.g. "{{c1::Paris::capital}}" with a hint, is understood.
+///
+/// A card is identified by its front, so editing its answer keeps its schedule, while editing its question makes it a
+/// new card. The cards are encrypted like the content of the note, since the cards of secure notes are recorded too.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `content` - The plaintext content of the note.
+///
+/// # Errors
+///
+/// This function will return an error if a card cannot be encrypted or decrypted, or if there is an issue with the
+/// database connection.
+pub fn index_cards(conn: &Connection, note_id: i64, content: &str) -> Result<(), String> {
+    let mut existing = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, kind, card, nonce FROM cards WHERE note_id = ?1").map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![note_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        }).map_err(|e| e.to_string())?;
+        for row in rows {
+            let (id, kind, encrypted_card, nonce_str) = row.map_err(|e| e.to_string())?;
+            let (front, back) = decrypt_card(&encrypted_card, &nonce_str)?;
+            existing.insert(front, (id, kind, back));
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for (kind, front, back) in parse_cards(&preview::plain_text(content)) {
+        match existing.remove(&front) {
+            Some((_, existing_kind, existing_back)) if existing_kind == kind_name(kind) && existing_back == back => {},
+            Some((id, _, _)) => {
+                let (encrypted_card, nonce_str) = encrypt_card(&front, &back)?;
+                conn.execute(
+                    "UPDATE cards SET kind = ?1, card = ?2, nonce = ?3 WHERE id = ?4",
+                    params![kind_name(kind), encrypted_card, nonce_str, id],
+                ).map_err(|e| e.to_string())?;
+            },
+            None => {
+                let (encrypted_card, nonce_str) = encrypt_card(&front, &back)?;
+                conn.execute(
+                    "INSERT INTO cards (note_id, kind, card, nonce, ease, interval_days, repetitions, due_at, reviewed_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, 0, 0, ?6, NULL)",
+                    params![note_id, kind_name(kind), encrypted_card, nonce_str, review_operations::INITIAL_EASE, now],
+                ).map_err(|e| e.to_string())?;
+            },
+        }
+    }
+
+    // The cards left were removed from the note
+    for (id, _, _) in existing.into_values() {
+        conn.execute("DELETE FROM cards WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+
+/// Removes the flashcards of a deleted note from the "cards" table, with their schedule.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_cards(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM cards WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Retrieves the flashcards that are due, to study them.
+///
+/// # Arguments
+///
+/// * `limit` - The maximum number of cards, 50 by default.
+/// * `note_id` - The ID of a note to study the cards of, or `None` for the cards of every note.
+/// * `tag` - A tag to study the cards of the notes with, or `None` for the cards of every note.
+///
+/// # Operation
+///
+/// New cards are due right away. The cards of secure notes are left out while the vault is locked.
+///
+/// # Returns
+///
+/// Returns the due cards, the longest overdue first.
+///
+/// # Errors
+///
+/// This function will return an error if a card cannot be decrypted or if there is an issue with the database connection.
+pub fn list_due_cards(limit: Option<usize>, note_id: Option<i64>, tag: Option<&str>) -> Result<Vec<Flashcard>, String> {
+    let tag = tag.map(tag_operations::clean_tag);
+    let conn = CONNECTION.lock().unwrap();
+    read_cards(
+        &conn,
+        "cards.due_at <= ?1 AND (?2 IS NULL OR cards.note_id = ?2) AND (notes.secure = 0 OR ?3)
+        AND (?4 IS NULL OR cards.note_id IN (SELECT note_tags.note_id FROM note_tags JOIN tags ON tags.id = note_tags.tag_id WHERE tags.name = ?4))
+        ORDER BY cards.due_at, cards.id LIMIT ?5",
+        params![chrono::Utc::now().timestamp(), note_id, vault::is_unlocked(), tag, limit.unwrap_or(DEFAULT_LIMIT) as i64],
+    )
+}
+
+
+/// Records how well a flashcard was recalled, and schedules its next review.
+///
+/// # Arguments
+///
+/// * `card_id` - The ID of the card, as returned by `list_due_cards`.
+/// * `grade` - How well it was recalled, from 0 (not at all) to 5 (perfectly), as in SM-2.
+///
+/// # Operation
+///
+/// The next review is scheduled with `review_operations::schedule`, like the notes of the review queue.
+///
+/// # Returns
+///
+/// Returns the card with its new schedule.
+///
+/// # Errors
+///
+/// This function will return an error if the grade is above 5, if the card does not exist, if its note is secure
+/// while the vault is locked, or if there is an issue with the database connection.
+pub fn answer_card(card_id: i64, grade: u8) -> Result<Flashcard, AppError> {
+    review_operations::check_grade(grade)?;
+
+    let conn = CONNECTION.lock().unwrap();
+    let (secure, ease, interval_days, repetitions): (bool, f64, i64, i64) = conn.query_row(
+        "SELECT notes.secure, cards.ease, cards.interval_days, cards.repetitions FROM cards JOIN notes ON notes.id = cards.note_id
+        WHERE cards.id = ?1",
+        params![card_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    ).optional()?.ok_or(AppError::NotFound("Card not found".to_string()))?;
+    if secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to study the cards of secure notes".to_string()));
+    }
+
+    let (ease, interval_days, repetitions) = review_operations::schedule(ease, interval_days, repetitions, grade);
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE cards SET ease = ?1, interval_days = ?2, repetitions = ?3, due_at = ?4, reviewed_at = ?5 WHERE id = ?6",
+        params![ease, interval_days, repetitions, now + interval_days * DAY_SECONDS, now, card_id],
+    )?;
+
+    read_cards(&conn, "cards.id = ?1", params![card_id])?.pop().ok_or(AppError::NotFound("Card not found".to_string()))
+}
+
+
+/// Retrieves the statistics of each deck of flashcards, a deck being the cards of a note.
+///
+/// # Returns
+///
+/// Returns a `DeckStats` per note with cards, sorted by title, with its number of cards, of new cards never studied,
+/// of cards due now, and of mature cards, whose interval is 21 days or more.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_deck_stats() -> Result<Vec<DeckStats>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT notes.id, notes.title, COUNT(*), SUM(cards.reviewed_at IS NULL), SUM(cards.due_at <= ?1), SUM(cards.interval_days >= ?2)
+        FROM cards JOIN notes ON notes.id = cards.note_id AND notes.deleted_at IS NULL
+        GROUP BY notes.id ORDER BY notes.title COLLATE NOCASE, notes.id"
+    ).map_err(|e| e.to_string())?;
+    let stats_iter = stmt.query_map(params![chrono::Utc::now().timestamp(), MATURE_INTERVAL_DAYS], |row| {
+        Ok(DeckStats {
+            note_id: row.get(0)?,
+            title: row.get(1)?,
+            cards: row.get(2)?,
+            new: row.get(3)?,
+            due: row.get(4)?,
+            mature: row.get(5)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    stats_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Exports flashcards to a text file that Anki imports, to study them in Anki.
+///
+/// # Arguments
+///
+/// * `deck_scope` - The cards to export: those of every note, those of one note, or those of the notes with a tag.
+/// * `path` - The path of the file to write, e.g. "cards.txt".
+///
+/// # Operation
+///
+/// * The file is tab-separated, with the headers of Anki 2.1.55 and later telling it to import each card as a
+///   "Basic" note with its front and back, so each card keeps its own schedule in Anki like it has here.
+/// * The cards of a note go to the subdeck of "CustomNotes" named after the note, e.g. "CustomNotes::Geography".
+/// * The cards are tagged in Anki with the tags of their note, their spaces replaced by underscores since Anki
+///   separates tags with spaces.
+/// * The cards of secure notes are never exported, since the file is not encrypted.
+///
+/// # Returns
+///
+/// Returns the number of exported cards.
+///
+/// # Errors
+///
+/// This function will return an error if a card cannot be decrypted, if there is an issue with the database
+/// connection, or if the file cannot be written.
+pub fn export_anki(deck_scope: DeckScope, path: &str) -> Result<usize, String> {
+    let (scope_condition, scope_params): (String, Vec<Box<dyn rusqlite::ToSql>>) = match deck_scope {
+        DeckScope::All => ("1 = 1".to_string(), Vec::new()),
+        DeckScope::Note { note_id } => ("cards.note_id = ?".to_string(), vec![Box::new(note_id)]),
+        DeckScope::Tag { tag } => (tag_operations::tagged_condition("cards.note_id", 1), vec![Box::new(tag_operations::clean_tag(&tag))]),
+    };
+    let (cards, tags) = {
+        let conn = CONNECTION.lock().unwrap();
+        let cards = read_cards(
+            &conn,
+            &format!("{} AND notes.secure = 0 ORDER BY notes.title COLLATE NOCASE, cards.note_id, cards.id", scope_condition),
+            &scope_params.iter().map(|param| param.as_ref()).collect::<Vec<_>>(),
+        )?;
+        (cards, tag_operations::read_tags(&conn)?)
+    };
+
+    let mut file = "#separator:tab\n#html:true\n#notetype:Basic\n#deck column:1\n#tags column:4\n".to_string();
+    for card in &cards {
+        let deck = format!("{}::{}", ANKI_DECK, card.note_title.replace("::", ":"));
+        let note_tags: Vec<String> = tags.get(&card.note_id).into_iter().flatten().map(|tag| tag.replace(' ', "_")).collect();
+        file.push_str(&[deck.as_str(), &card.front, &card.back].map(anki_field).join("\t"));
+        file.push('\t');
+        file.push_str(&note_tags.join(" "));
+        file.push('\n');
+    }
+    std::fs::write(path, file).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    Ok(cards.len())
+}
+
+
+/// Escapes a field of a card for Anki, which reads it as HTML, a line break becoming a `<br>`.
+fn anki_field(text: &str) -> String {
+    text.trim()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\t', " ")
+        .replace('\n', "<br>")
+}
+
+
+/// Parses the flashcards of the text of a note into their kind, front, and back, each front once.
+fn parse_cards(text: &str) -> Vec<(CardKind, String, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut cards: Vec<(CardKind, String, String)> = Vec::new();
+    let mut push = |kind: CardKind, front: String, back: String| {
+        if !front.is_empty() && !back.is_empty() && !cards.iter().any(|(_, existing, _)| *existing == front) {
+            cards.push((kind, front, back));
+        }
+    };
+
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index].trim();
+        index += 1;
+
+        let Some(question) = strip_marker(line, 'Q') else {
+            for (front, back) in cloze_cards(line) {
+                push(CardKind::Cloze, front, back);
+            }
+            continue;
+        };
+
+        // The question must be followed right away by its answer
+        let Some(answer) = lines.get(index).and_then(|next| strip_marker(next.trim(), 'A')) else {
+            continue;
+        };
+        index += 1;
+        let mut answer = vec![answer];
+        while let Some(next) = lines.get(index).map(|next| next.trim()) {
+            if next.is_empty() || strip_marker(next, 'Q').is_some() || CLOZE.is_match(next) {
+                break;
+            }
+            answer.push(next);
+            index += 1;
+        }
+        push(CardKind::Question, question.to_string(), answer.join("\n").trim().to_string());
+    }
+
+    cards
+}
+
+
+/// Returns the rest of a line starting with a marker such as "Q:", in either case.
+fn strip_marker(line: &str, marker: char) -> Option<&str> {
+    let mut chars = line.chars();
+    (chars.next()?.eq_ignore_ascii_case(&marker) && chars.next()? == ':').then(|| line[2..].trim())
+}
+
+
+/// Returns the front and back of a card for each cloze deletion of a line, the other deletions of the line shown.
+fn cloze_cards(line: &str) -> Vec<(String, String)> {
+    let deletions: Vec<_> = CLOZE.captures_iter(line).collect();
+    (0..deletions.len()).map(|hidden| {
+        let mut front = String::new();
+        let mut back = String::new();
+        let mut last = 0;
+        for (index, captures) in deletions.iter().enumerate() {
+            let deletion = captures.get(0).unwrap();
+            let mut parts = captures[1].splitn(2, "::");
+            let text = parts.next().unwrap_or_default().trim();
+            front.push_str(&line[last..deletion.start()]);
+            if index == hidden {
+                match parts.next().map(|hint| hint.trim()).filter(|hint| !hint.is_empty()) {
+                    Some(hint) => front.push_str(&format!("[{}]", hint)),
+                    None => front.push_str(CLOZE_BLANK),
+                }
+                back = text.to_string();
+            } else {
+                front.push_str(text);
+            }
+            last = deletion.end();
+        }
+        front.push_str(&line[last..]);
+        (front.trim().to_string(), back)
+    }).collect()
+}
+
+
+/// Returns the name of a kind of card, as stored in the "cards" table.
+fn kind_name(kind: CardKind) -> &'static str {
+    match kind {
+        CardKind::Question => "question",
+        CardKind::Cloze => "cloze",
+    }
+}
+
+
+/// Encrypts the front and back of a card together.
+fn encrypt_card(front: &str, back: &str) -> Result<(String, String), String> {
+    Ok(crypto::encrypt_content(&serde_json::to_string(&[front, back]).map_err(|e| e.to_string())?)?)
+}
+
+
+/// Decrypts the front and back of a card.
+fn decrypt_card(encrypted_card: &str, nonce_str: &str) -> Result<(String, String), String> {
+    let [front, back]: [String; 2] = serde_json::from_str(&crypto::decrypt_content(encrypted_card, nonce_str)?)
+        .map_err(|e| format!("Invalid card: {}", e))?;
+    Ok((front, back))
+}
+
+
+/// Reads the cards matching a condition, which may end with an ORDER BY and a LIMIT clause.
+fn read_cards(conn: &Connection, condition: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Flashcard>, String> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT cards.id, cards.note_id, notes.title, cards.kind, cards.card, cards.nonce, cards.ease, cards.interval_days,
+        cards.repetitions, cards.due_at, cards.reviewed_at
+        FROM cards JOIN notes ON notes.id = cards.note_id AND notes.deleted_at IS NULL WHERE {}",
+        condition
+    )).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params, |row| {
+        let kind: String = row.get(3)?;
+        Ok((
+            Flashcard {
+                id: row.get(0)?,
+                note_id: row.get(1)?,
+                note_title: row.get(2)?,
+                kind: if kind == kind_name(CardKind::Cloze) { CardKind::Cloze } else { CardKind::Question },
+                front: String::new(),
+                back: String::new(),
+                ease: row.get(6)?,
+                interval_days: row.get(7)?,
+                repetitions: row.get(8)?,
+                due_at: row.get(9)?,
+                reviewed_at: row.get(10)?,
+            },
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+        ))
+    }).map_err(|e| e.to_string())?;
+
+    let mut cards = Vec::new();
+    for row in rows {
+        let (mut card, encrypted_card, nonce_str) = row.map_err(|e| e.to_string())?;
+        (card.front, card.back) = decrypt_card(&encrypted_card, &nonce_str)?;
+        cards.push(card);
+    }
+    Ok(cards)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cards_makes_a_card_per_cloze_of_a_line() {
+        let cards = parse_cards("The {{c1::sun}} rises in the {{c2::east::direction}}.");
+        assert_eq!(cards, vec![
+            (CardKind::Cloze, "The [...] rises in the east.".to_string(), "sun".to_string()),
+            (CardKind::Cloze, "The sun rises in the [direction].".to_string(), "east".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_cards_ignores_unterminated_clozes() {
+        assert!(parse_cards("Water boils at {{c1::100 degrees").is_empty());
+        assert!(parse_cards("{{c1::one\ntwo}}").is_empty());
+    }
+
+    #[test]
+    fn parse_cards_skips_empty_answers() {
+        assert!(parse_cards("Q: What is the capital of France?\nA:").is_empty());
+        assert!(parse_cards("The capital of France is {{c1::}}.").is_empty());
+        assert!(parse_cards("The capital of France is {{c1:: ::city}}.").is_empty());
+    }
+
+    #[test]
+    fn parse_cards_reads_crlf_line_endings() {
+        let cards = parse_cards("Q: What is the capital of France?\r\nA: Paris\r\non the Seine\r\n\r\n{{Rome}} is in Italy.\r\n");
+        assert_eq!(cards, vec![
+            (CardKind::Question, "What is the capital of France?".to_string(), "Paris\non the Seine".to_string()),
+            (CardKind::Cloze, "[...] is in Italy.".to_string(), "Rome".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_cards_keeps_each_front_once() {
+        let cards = parse_cards("Q: Capital of France?\nA: Paris\nQ: Capital of France?\nA: Lyon");
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].2, "Paris");
+    }
+}
@@ -0,0 +1,290 @@
+// focus_sessions.rs
+
+use crate::{ notifier, tag_operations, local_operations::CONNECTION, models::AppError, models::FocusSession, models::StatsRange, models::TimeGroup, models::TimeReportEntry };
+use chrono::{Duration, Local, NaiveDate, TimeZone};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+
+/// The columns of a focus session with the title of its note, in the order `focus_session_from_row` reads them.
+const SESSION_COLUMNS: &str = "focus_sessions.id, focus_sessions.note_id, notes.title, focus_sessions.started_at, focus_sessions.ended_at, focus_sessions.planned_minutes";
+
+
+/// Starts a focus session on a local note, to track the time spent on it.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `planned_minutes` - The length of the session, e.g. 25 for a pomodoro, or `None` to run until it is stopped.
+///
+/// # Operation
+///
+/// * The running session, if any, is stopped first, so a single session runs at a time.
+/// * A planned session ends by itself when its time is up, with a desktop notification. It also ends at its planned
+///   time if the application was closed meanwhile.
+///
+/// # Returns
+///
+/// Returns the new `FocusSession`.
+///
+/// # Errors
+///
+/// This function will return an error if the planned length is 0, if the note does not exist, or if there is an
+/// issue with the database connection.
+pub fn start_focus_session(note_id: i64, planned_minutes: Option<u32>) -> Result<FocusSession, AppError> {
+    if planned_minutes == Some(0) {
+        return Err(AppError::Validation("The planned length of a focus session should be at least a minute".to_string()));
+    }
+
+    let session = {
+        let conn = CONNECTION.lock().unwrap();
+        let note_exists: Option<i64> = conn.query_row("SELECT id FROM notes WHERE id = ?1", params![note_id], |row| row.get(0))
+            .optional()?;
+        if note_exists.is_none() {
+            return Err(AppError::NotFound("Note not found".to_string()));
+        }
+
+        end_sessions(&conn)?;
+        conn.execute(
+            "INSERT INTO focus_sessions (note_id, started_at, ended_at, planned_minutes) VALUES (?1, ?2, NULL, ?3)",
+            params![note_id, chrono::Utc::now().timestamp(), planned_minutes],
+        )?;
+        focus_session(&conn, conn.last_insert_rowid())?.ok_or(AppError::NotFound("Focus session not found".to_string()))?
+    };
+
+    if let Some(planned_minutes) = planned_minutes {
+        let session_id = session.id;
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(planned_minutes as u64 * 60));
+            let ended = {
+                let conn = CONNECTION.lock().unwrap();
+                running_session_id(&conn) == Ok(Some(session_id)) && end_elapsed_sessions(&conn).is_ok()
+            };
+            if ended {
+                notifier::notify("Focus session over", &format!("Your {}-minute focus session is over.", planned_minutes));
+            }
+        });
+    }
+    Ok(session)
+}
+
+
+/// Stops the running focus session.
+///
+/// # Returns
+///
+/// Returns the stopped `FocusSession`, or `None` if no session was running.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn stop_focus_session() -> Result<Option<FocusSession>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    end_elapsed_sessions(&conn)?;
+    let Some(session_id) = running_session_id(&conn)? else {
+        return Ok(None);
+    };
+
+    end_sessions(&conn)?;
+    focus_session(&conn, session_id)
+}
+
+
+/// Retrieves the running focus session, e.g. to show its timer.
+///
+/// # Returns
+///
+/// Returns the running `FocusSession`, or `None` if no session is running.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_focus_session() -> Result<Option<FocusSession>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    end_elapsed_sessions(&conn)?;
+    match running_session_id(&conn)? {
+        Some(session_id) => focus_session(&conn, session_id),
+        None => Ok(None),
+    }
+}
+
+
+/// Summarizes the time spent in focus sessions over a period.
+///
+/// # Arguments
+///
+/// * `range` - The period covered, ending today in the local time zone. Defaults to the last week.
+/// * `group_by` - Whether the time is summed per note, per day, or per tag. Defaults to per note.
+///
+/// # Operation
+///
+/// Only the part of a session within the period counts, and a session spanning midnight is split between its days.
+/// The running session counts until now. Per tag, a session counts for each tag of its note, and the sessions of the
+/// notes without tags are left out.
+///
+/// # Returns
+///
+/// Returns a `TimeReportEntry` per note or per tag, the most time first, or per day with time, oldest first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_time_report(range: Option<StatsRange>, group_by: Option<TimeGroup>) -> Result<Vec<TimeReportEntry>, String> {
+    let days = match range.unwrap_or(StatsRange::Week) {
+        StatsRange::Week => Some(7),
+        StatsRange::Month => Some(30),
+        StatsRange::Quarter => Some(91),
+        StatsRange::Year => Some(365),
+        StatsRange::All => None,
+    };
+    let now = chrono::Utc::now().timestamp();
+    let since = days
+        .map(|days| local_midnight(Local::now().date_naive() - Duration::days(days - 1)))
+        .unwrap_or(i64::MIN);
+
+    let conn = CONNECTION.lock().unwrap();
+    end_elapsed_sessions(&conn)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM focus_sessions JOIN notes ON notes.id = focus_sessions.note_id
+        WHERE focus_sessions.ended_at IS NULL OR focus_sessions.ended_at > ?1 ORDER BY focus_sessions.started_at",
+        SESSION_COLUMNS,
+    )).map_err(|e| e.to_string())?;
+    let sessions = stmt.query_map(params![since], |row| focus_session_from_row(row, now))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let tags = match group_by {
+        Some(TimeGroup::Tag) => tag_operations::read_tags(&conn)?,
+        _ => HashMap::new(),
+    };
+
+    let mut entries: Vec<TimeReportEntry> = Vec::new();
+    let mut entry_index: HashMap<String, usize> = HashMap::new();
+    for session in sessions {
+        let start = session.started_at.max(since);
+        let end = session.ended_at.unwrap_or(now).min(now);
+        if end <= start {
+            continue;
+        }
+
+        let parts = match group_by.unwrap_or(TimeGroup::Note) {
+            TimeGroup::Note => vec![(session.note_id.to_string(), Some(session.note_id), session.note_title.clone(), end - start)],
+            TimeGroup::Day => split_by_day(start, end).into_iter()
+                .map(|(day, seconds)| (day.to_string(), None, day.to_string(), seconds))
+                .collect(),
+            TimeGroup::Tag => tags.get(&session.note_id).into_iter().flatten()
+                .map(|tag| (tag.to_lowercase(), None, tag.clone(), end - start))
+                .collect(),
+        };
+        for (key, note_id, label, seconds) in parts {
+            let index = *entry_index.entry(key.clone()).or_insert_with(|| {
+                entries.push(TimeReportEntry { key, note_id, label, seconds: 0, sessions: 0 });
+                entries.len() - 1
+            });
+            entries[index].seconds += seconds;
+            entries[index].sessions += 1;
+        }
+    }
+
+    match group_by.unwrap_or(TimeGroup::Note) {
+        TimeGroup::Note | TimeGroup::Tag => entries.sort_by(|a, b| b.seconds.cmp(&a.seconds).then_with(|| a.label.cmp(&b.label))),
+        TimeGroup::Day => entries.sort_by(|a, b| a.key.cmp(&b.key)),
+    }
+    Ok(entries)
+}
+
+
+/// Removes the focus sessions of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_focus_sessions(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM focus_sessions WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Ends the planned sessions whose time is up, at their planned end.
+fn end_elapsed_sessions(conn: &Connection) -> Result<(), String> {
+    conn.execute(
+        "UPDATE focus_sessions SET ended_at = started_at + planned_minutes * 60
+        WHERE ended_at IS NULL AND planned_minutes IS NOT NULL AND started_at + planned_minutes * 60 <= ?1",
+        params![chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Ends the running sessions now, or at their planned end if it is earlier.
+fn end_sessions(conn: &Connection) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE focus_sessions SET ended_at = MIN(?1, COALESCE(started_at + planned_minutes * 60, ?1)) WHERE ended_at IS NULL",
+        params![now],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Finds the running session, if any.
+fn running_session_id(conn: &Connection) -> Result<Option<i64>, String> {
+    conn.query_row("SELECT id FROM focus_sessions WHERE ended_at IS NULL ORDER BY started_at DESC LIMIT 1", [], |row| row.get(0))
+        .optional().map_err(|e| e.to_string())
+}
+
+
+/// Reads a focus session with the title of its note.
+fn focus_session(conn: &Connection, session_id: i64) -> Result<Option<FocusSession>, String> {
+    conn.query_row(
+        &format!("SELECT {} FROM focus_sessions JOIN notes ON notes.id = focus_sessions.note_id WHERE focus_sessions.id = ?1", SESSION_COLUMNS),
+        params![session_id],
+        |row| focus_session_from_row(row, chrono::Utc::now().timestamp()),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Builds a focus session from a row of `SESSION_COLUMNS`, its duration running until `now` if it was not stopped.
+fn focus_session_from_row(row: &rusqlite::Row, now: i64) -> rusqlite::Result<FocusSession> {
+    let started_at: i64 = row.get(3)?;
+    let ended_at: Option<i64> = row.get(4)?;
+    Ok(FocusSession {
+        id: row.get(0)?,
+        note_id: row.get(1)?,
+        note_title: row.get(2)?,
+        started_at,
+        ended_at,
+        planned_minutes: row.get(5)?,
+        duration_seconds: (ended_at.unwrap_or(now) - started_at).max(0),
+    })
+}
+
+
+/// Splits a span of time between the local days it covers, with the number of seconds in each.
+fn split_by_day(start: i64, end: i64) -> Vec<(NaiveDate, i64)> {
+    let mut parts = Vec::new();
+    let mut start = start;
+    while start < end {
+        let Some(day) = Local.timestamp_opt(start, 0).earliest().map(|time| time.date_naive()) else {
+            break;
+        };
+        let next_midnight = local_midnight(day + Duration::days(1)).max(start + 1);
+        parts.push((day, next_midnight.min(end) - start));
+        start = next_midnight;
+    }
+    parts
+}
+
+
+/// Returns the timestamp of the start of a day in the local time zone.
+fn local_midnight(day: NaiveDate) -> i64 {
+    let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+    Local.from_local_datetime(&midnight).earliest()
+        .map(|time| time.timestamp())
+        .unwrap_or_else(|| midnight.and_utc().timestamp())
+}
@@ -0,0 +1,2094 @@
+// handlers.rs
+
+use crate::{
+    alias_operations, api_tokens, article_operations, attachment_operations, bucket_audit, bucket_format,
+    capture_bridge, card_operations, citation_operations, code_block_operations, collation, commands, crypto,
+    custom_fields, doctor, drop_operations, email_operations, envelope, export_operations, features,
+    file_associations, flashcard_operations, focus_sessions, import, import_ledger, journal, journal_context,
+    key_management, link_operations, local_operations, markdown, memory_budget, merge_operations, metrics,
+    migrations, note_schedules, note_stats, note_templates, notebook_csv, notebook_operations, notifier,
+    periodic_notes, plain_sync, presence_operations, print_operations, proxy, quick_switch, reencryption,
+    replace_operations, review_operations, s3_config, s3_operations, search, search_index, settings_profile,
+    shutdown, sso_operations, startup, stats_operations, status_operations, sync, tag_operations, tasks, test_data,
+    text_extraction, tls, trash, tray, update_operations, usage_operations, vault, vault_lock, version_operations,
+    workspace, s3_operations::BucketNote,
+};
+#[cfg(feature = "benchmarks")]
+use crate::benchmarks;
+use crate::models::{
+    ApiScope, ApiToken, AppError, Attachment, AttachmentData, AttachmentText, BackgroundTask, Backlink,
+    BenchmarkReport, BucketFormatMigration, BucketKeyMigration, BucketNoteStats, CaptureBridgeConfig,
+    CaptureBridgeSettings, CardTheme, Cipher, CipherSelection, Citation, CloudBudget, CloudUsageReport, CodeBlock,
+    CommandDescriptor, CommandResponse, ConflictStrategy, CreatedApiToken, CustomField, DeckScope, DeckStats,
+    DirectoryImport, DoctorReport, DroppedFiles, DueReview, EmailIngestionConfig, EmailIngestionSettings,
+    ExportFormat, FeatureFlag, FieldListOptions, Flashcard, FocusSession, IndexProtection, IndexStatus,
+    JournalContextConfig, JournalContextSettings, MemoryBudgets, Note, NoteEnvelope, NoteLinkReport,
+    NoteListOptions, NotePresence, NoteSchedule, NoteScheduleConfig, NoteSource, NoteSummary, NoteTemplate,
+    NoteVersion, Notebook, NotebookCsvImport, NotebookSettings, NotesPage, NotificationSettings, OrphanedObject,
+    Period, PeriodKind, PeriodicNote, PeriodicNoteSettings, PlainSyncNote, PrintOptions, ProxyConfig,
+    ProxySettings, QuickSwitchMatch, QuietHours, RecoveryEntry, ReencryptionStatus, ReplacePreview, ReplaceScope,
+    ReviewSchedule, S3Config, SchemaStatus, SearchResult, SettingsImport, SiteFlavor, SmtpConfig, SmtpSettings,
+    Snippet, SsoDeviceAuthorization, StartupTiming, StatsRange, StatusCount, StatusWorkflow, SyncConflict,
+    SyncSettings, SyncSummary, TagCount, TagRefactor, TemplateOutcome, TimeGroup, TimeReportEntry,
+    TlsConnectionTest, TlsRemote, TrashedNote, TrayMenuItem, UnlinkedMention, UpdateInfo, UsageMetrics,
+    VaultLockStatus, VaultStats, VaultStatus, WorkspaceSnapshot, WorkspaceState,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+
+
+/// The error message of the commands that do not exist.
+const UNKNOWN_COMMAND: &str = "Unknown command";
+
+
+/// Awaits the operation of a command, converting its error into an `AppError`.
+async fn outcome<T, E: Into<AppError>>(operation: impl Future<Output = Result<T, E>>) -> Result<T, AppError> {
+    operation.await.map_err(Into::into)
+}
+
+
+/// Reads the arguments of a command run by name, missing arguments being read as `null`.
+fn arguments<T: serde::de::DeserializeOwned>(args: serde_json::Value) -> Result<T, AppError> {
+    let args = if args.is_null() { serde_json::Value::Object(Default::default()) } else { args };
+    serde_json::from_value(args).map_err(|e| AppError::Validation(format!("Invalid arguments: {}", e)))
+}
+
+
+/// Declares the commands of the backend.
+///
+/// Each command becomes a Tauri command, which the window calls with its arguments by name, and a case of `route`,
+/// which runs it by name with its arguments as a JSON object, for the local HTTP API and the plugins once their token
+/// is checked (see `execute_api_command`). The command palette calls the commands like the rest of the window. Either
+/// way the arguments are read into their types before the command runs, and its data is always of the same type,
+/// `null` for the commands that only tell they succeeded.
+///
+/// The body of a command returns a `Result` whose error converts into an `AppError`.
+macro_rules! commands {
+    ($($(#[$attr:meta])* async fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $out:ty $body:block)*) => {
+        $(
+            $(#[$attr])*
+            #[tauri::command(rename_all = "snake_case")]
+            pub async fn $name($($arg: $ty),*) -> CommandResponse<$out> {
+                run(stringify!($name), async move $body).await
+            }
+        )*
+
+        /// Runs a command by name, with its arguments as a JSON object.
+        ///
+        /// # Returns
+        ///
+        /// Returns the data of the command as JSON, or an `AppError` telling what failed, `AppError::NotFound` with
+        /// `UNKNOWN_COMMAND` if there is no such command.
+        async fn route(command: &str, args: serde_json::Value) -> Result<serde_json::Value, AppError> {
+            match command {
+                $(stringify!($name) => {
+                    #[derive(serde::Deserialize)]
+                    struct Args { $($arg: $ty,)* }
+                    let Args { $($arg,)* } = arguments(args)?;
+                    let data: $out = outcome(async move $body).await?;
+                    serde_json::to_value(data).map_err(|e| AppError::Failed(e.to_string()))
+                },)*
+                _ => Err(AppError::NotFound(UNKNOWN_COMMAND.to_string())),
+            }
+        }
+
+        /// Builds the handler of the commands the window calls, given to `tauri::Builder::invoke_handler`.
+        pub fn invoke_handler() -> impl Fn(tauri::Invoke) + Send + Sync + 'static {
+            tauri::generate_handler![execute_api_command, $($name),*]
+        }
+    };
+}
+
+
+commands! {
+    /// Creates a local note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note` - The note, with its title and content.
+    ///
+    /// # Returns
+    ///
+    /// Returns the created `Note`, with its ID.
+    async fn create_local_note(note: Note) -> Note {
+        local_operations::create_local_note(note).await
+    }
+
+    /// Retrieves a local note with its plaintext content, and records that it was opened for the quick switcher.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    async fn get_local_note(id: i64) -> Note {
+        let note = local_operations::get_local_note(id).await.map_err(AppError::from);
+        // The window opens notes with this command, which ranks them in the quick switcher
+        if note.is_ok() {
+            if let Err(e) = quick_switch::record_note_opened(id) {
+                eprintln!("Failed to record the opening of note {}: {}", id, e);
+            }
+        }
+        note
+    }
+
+    /// Updates the title and content of a local note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note` - The note, with its ID, title, and content.
+    async fn update_local_note(note: Note) -> () {
+        local_operations::update_local_note(note).await
+    }
+
+    /// Moves a local note to the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    async fn delete_local_note(id: i64) -> () {
+        local_operations::delete_local_note(id)
+    }
+
+    /// Lists the notes in the trash.
+    async fn list_trashed_notes() -> Vec<TrashedNote> {
+        trash::list_trashed_notes()
+    }
+
+    /// Restores a note from the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    async fn restore_note(id: i64) -> () {
+        trash::restore_note(id)
+    }
+
+    /// Deletes a note of the trash for good.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    async fn purge_note(id: i64) -> () {
+        trash::purge_note(id)
+    }
+
+    /// Retrieves the number of days the notes stay in the trash.
+    async fn get_trash_retention() -> i64 {
+        trash::get_trash_retention()
+    }
+
+    /// Sets the number of days the notes stay in the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `days` - The number of days.
+    async fn set_trash_retention(days: i64) -> () {
+        trash::set_trash_retention(days)
+    }
+
+    /// Retrieves the local notes with their plaintext content, sorted by title.
+    async fn get_local_notes() -> Vec<Note> {
+        local_operations::get_local_notes().await
+    }
+
+    /// Creates a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn create_bucket(bucket_name: String) -> () {
+        s3_operations::create_bucket(&bucket_name).await
+    }
+
+    /// Lists the buckets of the notes.
+    async fn fetch_buckets() -> Vec<String> {
+        s3_operations::fetch_buckets().await.map_err(|e| AppError::s3(&e))
+    }
+
+    /// Deletes a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn delete_bucket(bucket_name: String) -> () {
+        s3_operations::delete_bucket(&bucket_name).await.map_err(|e| AppError::s3(&e))
+    }
+
+    /// Moves every local note to the trash.
+    async fn delete_all_local_notes() -> () {
+        local_operations::delete_all_local_notes().await
+    }
+
+    /// Uploads a note to a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `note` - The note, with its plaintext content.
+    async fn upload_note_to_bucket(bucket_name: String, note: Note) -> () {
+        s3_operations::upload_note_to_bucket(&bucket_name, note).await.map(|_| ())
+    }
+
+    /// Downloads a note from a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `uuid` - The UUID of the note.
+    async fn fetch_bucket_note(bucket_name: String, uuid: String) -> Note {
+        s3_operations::fetch_bucket_note(&bucket_name, &uuid).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Updates a note in a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `note` - The note, with its UUID, title, and plaintext content.
+    async fn update_bucket_note(bucket_name: String, note: Note) -> () {
+        s3_operations::update_bucket_note(&bucket_name, note).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Deletes a note from a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `uuid` - The UUID of the note.
+    async fn delete_bucket_note(bucket_name: String, uuid: String) -> () {
+        s3_operations::delete_bucket_note(&bucket_name, &uuid).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Lists the notes of a bucket, with their title, last modification, and metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn fetch_bucket_notes(bucket_name: String) -> Vec<BucketNote> {
+        s3_operations::fetch_bucket_notes(&bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Lists the statistics of the notes of a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn list_bucket_note_stats(bucket_name: String) -> Vec<BucketNoteStats> {
+        note_stats::list_bucket_note_stats(&bucket_name).await
+    }
+
+    /// Deletes every note of a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn delete_bucket_notes(bucket_name: String) -> () {
+        s3_operations::delete_bucket_notes(&bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Searches the local notes or the notes of a bucket (see `search::search_in_notes` for the query syntax).
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `local` - Whether to search the local notes rather than a bucket.
+    /// * `bucket_name` - The name of the bucket to search, when `local` is `false`.
+    async fn search_in_notes(query: String, local: bool, bucket_name: Option<String>) -> Vec<Note> {
+        let bucket_name = bucket_name.filter(|bucket_name| !bucket_name.is_empty());
+        search::search_in_notes(&query, local, bucket_name.as_deref()).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Searches the local notes and the notes of every bucket at once (see `search::search_all_notes`).
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    async fn search_all_notes(query: String) -> Vec<SearchResult> {
+        search::search_all_notes(&query).await.map_err(|e| AppError::s3(e.as_ref()))
+    }
+
+    /// Signs in with AWS IAM Identity Center, returning the code the user enters in the browser.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_url` - The start URL of the AWS access portal.
+    /// * `region` - The region of IAM Identity Center.
+    /// * `account_id` - The ID of the AWS account.
+    /// * `role_name` - The name of the role to assume.
+    async fn login_sso(start_url: String, region: String, account_id: String, role_name: String) -> SsoDeviceAuthorization {
+        sso_operations::login_sso(&start_url, &region, &account_id, &role_name).await
+    }
+
+    /// Signs out of AWS IAM Identity Center.
+    async fn logout_sso() -> () {
+        sso_operations::logout_sso().await
+    }
+
+    /// Retrieves the estimated cost of the cloud requests of a month.
+    ///
+    /// # Arguments
+    ///
+    /// * `month` - The month, as "YYYY-MM", or the current month if `None`.
+    async fn get_cloud_usage_report(month: Option<String>) -> CloudUsageReport {
+        usage_operations::get_cloud_usage_report(month)
+    }
+
+    /// Retrieves the monthly budget of the cloud requests.
+    async fn get_cloud_budget() -> CloudBudget {
+        usage_operations::get_cloud_budget()
+    }
+
+    /// Sets the monthly budget of the cloud requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The budget.
+    async fn set_cloud_budget(budget: CloudBudget) -> () {
+        usage_operations::set_cloud_budget(budget)
+    }
+
+    /// Syncs the local notes with the notes of a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn sync_bucket(bucket_name: String) -> SyncSummary {
+        sync::sync_bucket(&bucket_name).await
+    }
+
+    /// Retrieves the settings of the sync.
+    async fn get_sync_settings() -> Option<SyncSettings> {
+        sync::get_sync_settings()
+    }
+
+    /// Sets the settings of the sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    async fn set_sync_settings(config: SyncSettings) -> () {
+        sync::set_sync_settings(config)
+    }
+
+    /// Lists the conflicts the sync could not resolve.
+    async fn list_conflicts() -> Vec<SyncConflict> {
+        sync::list_conflicts()
+    }
+
+    /// Sets whether a note is synced as plain text.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `enabled` - Whether the note is synced as plain text.
+    async fn set_plain_sync(note_id: i64, enabled: bool) -> () {
+        plain_sync::set_plain_sync(note_id, enabled)
+    }
+
+    /// Lists the notes synced as plain text.
+    async fn list_plain_sync_notes() -> Vec<PlainSyncNote> {
+        plain_sync::list_plain_sync_notes()
+    }
+
+    /// Resolves a conflict of the sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The UUID of the note.
+    /// * `strategy` - Which version to keep.
+    /// * `bucket_name` - The name of the bucket, if the conflict is not with the default one.
+    async fn resolve_conflict(uuid: String, strategy: ConflictStrategy, bucket_name: Option<String>) -> () {
+        sync::resolve_conflict(&uuid, strategy, bucket_name.as_deref()).await
+    }
+
+    /// Sets whether a note is kept in the vault.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `secure` - Whether the note is secure.
+    async fn set_note_secure(id: i64, secure: bool) -> () {
+        local_operations::set_note_secure(id, secure)
+    }
+
+    /// Sets whether a note is locked against edits.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `locked` - Whether the note is locked.
+    async fn set_note_locked(id: i64, locked: bool) -> () {
+        local_operations::set_note_locked(id, locked)
+    }
+
+    /// Sets whether a note is pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `pinned` - Whether the note is pinned.
+    async fn set_note_pinned(id: i64, pinned: bool) -> () {
+        local_operations::set_note_pinned(id, pinned)
+    }
+
+    /// Sets whether a note is archived.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `archived` - Whether the note is archived.
+    async fn set_note_archived(id: i64, archived: bool) -> () {
+        local_operations::set_note_archived(id, archived)
+    }
+
+    /// Unlocks the secure notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - The passphrase of the vault, if one is set, or else its password.
+    async fn unlock_vault(passphrase: Option<String>) -> () {
+        vault::unlock_vault(passphrase.as_deref())
+    }
+
+    /// Locks the secure notes.
+    async fn lock_vault() -> () {
+        vault::lock_vault();
+        Ok::<(), String>(())
+    }
+
+    /// Tells whether the vault is unlocked, and how it is protected.
+    async fn get_vault_status() -> VaultStatus {
+        key_management::has_vault_password().map(|password| VaultStatus {
+            unlocked: vault::is_unlocked(),
+            passphrase: key_management::has_passphrase(),
+            password,
+        })
+    }
+
+    /// Sets or removes the passphrase of the vault, returning the number of notes encrypted again.
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - The passphrase, or `None` to remove it.
+    async fn set_passphrase(passphrase: Option<String>) -> usize {
+        key_management::set_passphrase(passphrase.as_deref())
+    }
+
+    /// Sets the password that unlocks the vault.
+    ///
+    /// # Arguments
+    ///
+    /// * `current_password` - The current password, if one is set.
+    /// * `password` - The new password.
+    async fn set_vault_password(current_password: Option<String>, password: String) -> () {
+        key_management::set_vault_password(current_password.as_deref(), &password)
+    }
+
+    /// Tells how far the notes are encrypted again after a key change.
+    async fn get_reencryption_status() -> ReencryptionStatus {
+        reencryption::get_reencryption_status()
+    }
+
+    /// Resumes the encryption of the notes with the new key after a key change, returning the ID of its task.
+    async fn resume_reencryption() -> i64 {
+        reencryption::resume_reencryption()
+            .ok_or("No key change waits for the notes to be encrypted again, or the vault is locked")
+    }
+
+    /// Exports the master key, encoded in base64, to back it up.
+    async fn export_encryption_key() -> String {
+        key_management::export_encryption_key()
+    }
+
+    /// Imports a master key backed up with `export_encryption_key`, returning the number of notes encrypted again.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The master key, encoded in base64.
+    async fn import_encryption_key(key: String) -> usize {
+        key_management::import_encryption_key(&key)
+    }
+
+    /// Retrieves the schema version of the database and the migrations it received.
+    async fn get_schema_status() -> SchemaStatus {
+        migrations::get_schema_status()
+    }
+
+    /// Retrieves the cipher the notes are encrypted with.
+    async fn get_encryption_cipher() -> Cipher {
+        Ok::<_, String>(crypto::current_cipher())
+    }
+
+    /// Sets the cipher the notes are encrypted with.
+    ///
+    /// # Arguments
+    ///
+    /// * `cipher` - The cipher.
+    async fn set_encryption_cipher(cipher: Cipher) -> () {
+        crypto::set_cipher(cipher)
+    }
+
+    /// Measures the ciphers on this device and selects the fastest one.
+    async fn select_fastest_cipher() -> CipherSelection {
+        tokio::task::spawn_blocking(crypto::select_cipher).await.map_err(|e| e.to_string())?
+    }
+
+    /// Tells why the notes cannot be decrypted, if the master key does not match the database.
+    async fn get_encryption_key_error() -> Option<String> {
+        Ok::<_, String>(key_management::get_encryption_key_error())
+    }
+
+    /// Tells whether another instance of the application holds the vault.
+    async fn get_vault_lock_status() -> VaultLockStatus {
+        Ok::<_, String>(vault_lock::get_vault_lock_status())
+    }
+
+    /// Takes the vault over from another instance of the application.
+    async fn take_over_vault_lock() -> () {
+        vault_lock::take_over_vault_lock()
+    }
+
+    /// Finds and replaces text in the notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The text or the regular expression to find.
+    /// * `replacement` - The replacement.
+    /// * `regex` - Whether the query is a regular expression, `false` by default.
+    /// * `scope` - The notes to search, every note by default.
+    /// * `dry_run` - Whether to only preview the replacements, `true` by default so a missing flag never rewrites
+    ///   the vault.
+    async fn replace_in_notes(query: String, replacement: String, regex: Option<bool>, scope: Option<ReplaceScope>, dry_run: Option<bool>) -> Vec<ReplacePreview> {
+        replace_operations::replace_in_notes(
+            &query,
+            &replacement,
+            regex.unwrap_or(false),
+            scope.unwrap_or_default(),
+            dry_run.unwrap_or(true),
+        )
+    }
+
+    /// Lists the versions of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn list_note_versions(note_id: i64) -> Vec<NoteVersion> {
+        version_operations::list_note_versions(note_id)
+    }
+
+    /// Restores a note to one of its versions.
+    ///
+    /// # Arguments
+    ///
+    /// * `version_id` - The ID of the version.
+    async fn restore_note_version(version_id: i64) -> () {
+        version_operations::restore_note_version(version_id)
+    }
+
+    /// Retrieves the titles and previews of the local notes, without their content.
+    async fn get_local_note_summaries() -> Vec<NoteSummary> {
+        local_operations::get_local_note_summaries()
+    }
+
+    /// Retrieves a page of the local notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The number of notes to skip.
+    /// * `limit` - The number of notes of the page.
+    /// * `options` - How to filter and sort the notes.
+    async fn get_local_notes_paged(offset: usize, limit: usize, options: Option<NoteListOptions>) -> NotesPage {
+        local_operations::get_local_notes_paged(offset, limit, &options.unwrap_or_default())
+    }
+
+    /// Renders the Markdown of a note as HTML.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn render_markdown(note_id: i64) -> String {
+        markdown::render_markdown(note_id)
+    }
+
+    /// Renders Markdown as HTML.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The Markdown.
+    async fn render_content(text: String) -> String {
+        Ok::<_, String>(markdown::render_content(&text))
+    }
+
+    /// Retrieves the locale the notes are sorted by.
+    async fn get_locale() -> String {
+        collation::get_locale()
+    }
+
+    /// Sets the locale the notes are sorted by.
+    ///
+    /// # Arguments
+    ///
+    /// * `locale` - The locale.
+    async fn set_locale(locale: String) -> () {
+        collation::set_locale(&locale)
+    }
+
+    /// Lists the note templates.
+    async fn list_note_templates() -> Vec<NoteTemplate> {
+        note_templates::list_note_templates()
+    }
+
+    /// Saves a note template, replacing the one of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template.
+    async fn save_note_template(template: NoteTemplate) -> NoteTemplate {
+        note_templates::save_note_template(template)
+    }
+
+    /// Deletes a note template.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the template.
+    async fn delete_note_template(name: String) -> () {
+        note_templates::delete_note_template(&name)
+    }
+
+    /// Creates a note from a template.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the template.
+    /// * `values` - The values of the placeholders of the template.
+    async fn create_note_from_template(name: String, values: Option<HashMap<String, String>>) -> TemplateOutcome {
+        note_templates::create_note_from_template(&name, values.unwrap_or_default()).await
+    }
+
+    /// Lists the notes created on a schedule.
+    async fn list_scheduled_notes() -> Vec<NoteSchedule> {
+        note_schedules::list_scheduled_notes()
+    }
+
+    /// Creates a note on a schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The note and its schedule.
+    async fn create_schedule(config: NoteScheduleConfig) -> NoteSchedule {
+        note_schedules::create_schedule(config)
+    }
+
+    /// Deletes a schedule.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the schedule.
+    async fn delete_schedule(id: i64) -> () {
+        note_schedules::delete_schedule(id)
+    }
+
+    /// Lists the API tokens, without their secret.
+    async fn list_api_tokens() -> Vec<ApiToken> {
+        api_tokens::list_api_tokens()
+    }
+
+    /// Creates an API token, returning it with its secret, which is shown only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the token.
+    /// * `scopes` - What the token allows.
+    /// * `expires_at` - When the token expires, as a Unix timestamp, or never if `None`.
+    async fn create_api_token(name: String, scopes: Vec<ApiScope>, expires_at: Option<i64>) -> CreatedApiToken {
+        api_tokens::create_api_token(&name, scopes, expires_at)
+    }
+
+    /// Revokes an API token.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the token.
+    async fn revoke_api_token(id: i64) -> () {
+        api_tokens::revoke_api_token(id)
+    }
+
+    /// Creates a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the notebook.
+    /// * `parent_id` - The ID of the notebook it belongs to, or `None` for a notebook at the top.
+    async fn create_notebook(name: String, parent_id: Option<i64>) -> Notebook {
+        notebook_operations::create_notebook(&name, parent_id)
+    }
+
+    /// Renames a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the notebook.
+    /// * `name` - The new name.
+    async fn rename_notebook(id: i64, name: String) -> Notebook {
+        notebook_operations::rename_notebook(id, &name)
+    }
+
+    /// Deletes a notebook, returning the number of notes moved out of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the notebook.
+    async fn delete_notebook(id: i64) -> usize {
+        notebook_operations::delete_notebook(id)
+    }
+
+    /// Lists the notebooks.
+    async fn list_notebooks() -> Vec<Notebook> {
+        notebook_operations::list_notebooks()
+    }
+
+    /// Moves notes to a notebook, returning the number of notes moved.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes.
+    /// * `notebook_id` - The ID of the notebook, or `None` to move the notes out of their notebook.
+    async fn move_notes_to_notebook(note_ids: Vec<i64>, notebook_id: Option<i64>) -> usize {
+        notebook_operations::move_notes_to_notebook(&note_ids, notebook_id)
+    }
+
+    /// Lists the notes of a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook, or `None` for the notes outside the notebooks.
+    /// * `include_sub_notebooks` - Whether to list the notes of its sub-notebooks too, `false` by default.
+    async fn get_notes_by_notebook(notebook_id: Option<i64>, include_sub_notebooks: Option<bool>) -> Vec<Note> {
+        notebook_operations::get_notes_by_notebook(notebook_id, include_sub_notebooks.unwrap_or(false))
+    }
+
+    /// Shows the settings applied to the notes created in a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    async fn get_notebook_settings(notebook_id: i64) -> NotebookSettings {
+        notebook_operations::get_notebook_settings(notebook_id)
+    }
+
+    /// Sets the settings applied to the notes created in a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `settings` - Whether the notes are secure, and the bucket they are uploaded to.
+    async fn set_notebook_settings(notebook_id: i64, settings: NotebookSettings) -> NotebookSettings {
+        notebook_operations::set_notebook_settings(notebook_id, settings)
+    }
+
+    /// Lists the tags, with the number of notes of each.
+    async fn list_tags() -> Vec<TagCount> {
+        tag_operations::list_tags()
+    }
+
+    /// Renames a tag on every note.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The tag.
+    /// * `new` - Its new name.
+    async fn rename_tag(old: String, new: String) -> TagRefactor {
+        tag_operations::rename_tag(&old, &new)
+    }
+
+    /// Merges a tag into another on every note.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The tag merged.
+    /// * `target` - The tag it is merged into.
+    async fn merge_tags(source: String, target: String) -> TagRefactor {
+        tag_operations::merge_tags(&source, &target)
+    }
+
+    /// Creates a local note in a notebook, with the settings of the notebook applied to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `note` - The note, with its title and content.
+    async fn create_notebook_note(notebook_id: i64, note: Note) -> Note {
+        notebook_operations::create_notebook_note(notebook_id, note).await
+    }
+
+    /// Shows the custom fields of a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    async fn get_notebook_schema(notebook_id: i64) -> Vec<CustomField> {
+        custom_fields::get_notebook_schema(notebook_id)
+    }
+
+    /// Defines the custom fields of a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `fields` - The fields, replacing the previous ones.
+    async fn set_notebook_schema(notebook_id: i64, fields: Vec<CustomField>) -> Vec<CustomField> {
+        custom_fields::set_notebook_schema(notebook_id, fields)
+    }
+
+    /// Shows the values of the custom fields of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_fields(note_id: i64) -> BTreeMap<String, String> {
+        custom_fields::get_note_fields(note_id)
+    }
+
+    /// Sets values of the custom fields of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `values` - The values by field name, an empty value removing the value of its field.
+    async fn set_note_fields(note_id: i64, values: BTreeMap<String, String>) -> BTreeMap<String, String> {
+        custom_fields::set_note_fields(note_id, values)
+    }
+
+    /// Lists the notes of a notebook, filtered and sorted by their custom fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `options` - The filters on the custom fields, and the field to sort the notes by.
+    async fn get_notebook_notes(notebook_id: i64, options: Option<FieldListOptions>) -> Vec<NoteSummary> {
+        custom_fields::get_notebook_notes(notebook_id, &options.unwrap_or_default())
+    }
+
+    /// Merges notes into a new one, returning its ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - The IDs of the notes, in the order of the merge.
+    /// * `separator` - The text between the contents of the notes.
+    async fn merge_notes(ids: Vec<i64>, separator: Option<String>) -> i64 {
+        merge_operations::merge_notes(ids, separator)
+    }
+
+    /// Splits a note at a delimiter, returning the IDs of the new notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `delimiter` - The line the note is split at.
+    async fn split_note(note_id: i64, delimiter: String) -> Vec<i64> {
+        merge_operations::split_note(note_id, &delimiter)
+    }
+
+    /// Tells the other users of a bucket whether a note is being edited.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `uuid` - The UUID of the note.
+    /// * `editing` - Whether the note is being edited.
+    async fn set_note_presence(bucket_name: String, uuid: String, editing: bool) -> () {
+        presence_operations::set_note_presence(&bucket_name, &uuid, editing).await
+    }
+
+    /// Lists the users of a bucket editing a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `uuid` - The UUID of the note.
+    async fn get_note_presence(bucket_name: String, uuid: String) -> Vec<NotePresence> {
+        presence_operations::get_note_presence(&bucket_name, &uuid).await
+    }
+
+    /// Sets the name the other users of the buckets see.
+    ///
+    /// # Arguments
+    ///
+    /// * `display_name` - The name.
+    async fn set_display_name(display_name: String) -> () {
+        presence_operations::set_display_name(&display_name)
+    }
+
+    /// Exports the notes as the content of a static site, returning the number of pages written.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The folder of the site.
+    /// * `flavor` - The static site generator.
+    async fn export_site(path: String, flavor: SiteFlavor) -> usize {
+        export_operations::export_site(&path, flavor).await
+    }
+
+    /// Exports the notes to a folder or an archive, returning the number of notes exported.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the export.
+    /// * `path` - Where to write the export.
+    async fn export_notes(format: ExportFormat, path: String) -> usize {
+        export_operations::export_notes(format, &path).await
+    }
+
+    /// Checks an export against its manifest, returning the problems found.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where the export was written.
+    async fn verify_export(path: String) -> Vec<String> {
+        export_operations::verify_export(&path)
+    }
+
+    /// Writes notes to an HTML file to print, returning its path.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes.
+    /// * `options` - How to lay out the notes.
+    async fn prepare_print(note_ids: Vec<i64>, options: Option<PrintOptions>) -> String {
+        print_operations::prepare_print(&note_ids, options.unwrap_or_default())
+    }
+
+    /// Renders a note as an image to share, returning its path.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `theme` - The theme of the image.
+    async fn export_note_card(note_id: i64, theme: CardTheme) -> String {
+        tokio::task::spawn_blocking(move || card_operations::export_note_card(note_id, theme))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Exports the notes of a notebook with their custom fields as CSV, returning the number of notes exported.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `path` - Where to write the file.
+    async fn export_notebook_csv(notebook_id: i64, path: String) -> usize {
+        notebook_csv::export_notebook_csv(notebook_id, &path)
+    }
+
+    /// Imports a CSV file into the notes of a notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `notebook_id` - The ID of the notebook.
+    /// * `path` - The path of the file.
+    /// * `mapping` - The column or the field each column of the file is read into, by name, or `None` to read each
+    ///   column into the one it is named after.
+    async fn import_notebook_csv(notebook_id: i64, path: String, mapping: Option<HashMap<String, String>>) -> NotebookCsvImport {
+        notebook_csv::import_notebook_csv(notebook_id, &path, mapping)
+    }
+
+    /// Tells whether the search index is ready.
+    async fn get_index_status() -> IndexStatus {
+        Ok::<_, String>(search_index::get_index_status())
+    }
+
+    /// Lists the citations of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_citations(note_id: i64) -> Vec<Citation> {
+        citation_operations::get_note_citations(note_id)
+    }
+
+    /// Attaches a BibTeX bibliography, returning the number of entries read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the BibTeX file.
+    async fn attach_bibliography(path: String) -> usize {
+        citation_operations::attach_bibliography(&path)
+    }
+
+    /// Exports the entries cited by notes as BibTeX.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_ids` - The IDs of the notes.
+    async fn export_bibliography(note_ids: Vec<i64>) -> String {
+        citation_operations::export_bibliography(note_ids)
+    }
+
+    /// Lists the code blocks of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_code_blocks(note_id: i64) -> Vec<CodeBlock> {
+        code_block_operations::get_note_code_blocks(note_id)
+    }
+
+    /// Lists the code snippets of the notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `lang` - The language of the snippets, or every language if `None`.
+    /// * `query` - The text the snippets contain, or every snippet if `None`.
+    async fn list_snippets(lang: Option<String>, query: Option<String>) -> Vec<Snippet> {
+        code_block_operations::list_snippets(lang.as_deref(), query.as_deref())
+    }
+
+    /// Copies a code snippet to the clipboard, returning its code.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the snippet.
+    async fn copy_snippet(id: i64) -> String {
+        code_block_operations::copy_snippet(id)
+    }
+
+    /// Retrieves the statuses of the notes and the transitions between them.
+    async fn get_status_workflow() -> StatusWorkflow {
+        status_operations::get_status_workflow()
+    }
+
+    /// Sets the statuses of the notes and the transitions between them.
+    ///
+    /// # Arguments
+    ///
+    /// * `workflow` - The statuses and the transitions.
+    async fn set_status_workflow(workflow: StatusWorkflow) -> () {
+        status_operations::set_status_workflow(workflow)
+    }
+
+    /// Sets the status of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `status` - The status, or `None` to clear it.
+    async fn set_note_status(id: i64, status: Option<String>) -> () {
+        status_operations::set_note_status(id, status)
+    }
+
+    /// Counts the notes of each status.
+    async fn get_status_counts() -> Vec<StatusCount> {
+        status_operations::get_status_counts()
+    }
+
+    /// Finds the notes whose title matches a query, the most opened first.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `limit` - The maximum number of notes.
+    async fn quick_switch(query: String, limit: Option<usize>) -> Vec<QuickSwitchMatch> {
+        quick_switch::quick_switch(&query, limit)
+    }
+
+    /// Lists the aliases of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_aliases(note_id: i64) -> Vec<String> {
+        alias_operations::get_note_aliases(note_id)
+    }
+
+    /// Sets the aliases of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `aliases` - The aliases.
+    async fn set_note_aliases(note_id: i64, aliases: Vec<String>) -> () {
+        alias_operations::set_note_aliases(note_id, aliases)
+    }
+
+    /// Adds a tag to a note, returning its tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `tag` - The tag.
+    async fn add_tag_to_note(note_id: i64, tag: String) -> Vec<String> {
+        tag_operations::add_tag_to_note(note_id, &tag)
+    }
+
+    /// Removes a tag from a note, returning its tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `tag` - The tag.
+    async fn remove_tag_from_note(note_id: i64, tag: String) -> Vec<String> {
+        tag_operations::remove_tag_from_note(note_id, &tag)
+    }
+
+    /// Lists the notes with a tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag.
+    async fn get_notes_by_tag(tag: String) -> Vec<Note> {
+        tag_operations::get_notes_by_tag(&tag)
+    }
+
+    /// Finds the note a wikilink points to, by title or alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The target of the wikilink.
+    async fn resolve_wikilink(name: String) -> Option<i64> {
+        alias_operations::resolve_wikilink(&name)
+    }
+
+    /// Lists the notes linking to a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_backlinks(note_id: i64) -> Vec<Backlink> {
+        link_operations::get_backlinks(note_id)
+    }
+
+    /// Finds the notes mentioning the title or an alias of a note without linking to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn find_unlinked_mentions(note_id: i64) -> Vec<UnlinkedMention> {
+        // The search index may have to be built first
+        tokio::task::spawn_blocking(move || link_operations::find_unlinked_mentions(note_id))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Finds the broken links of the notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `check_urls` - Whether to request the external links too, `false` by default.
+    async fn check_links(check_urls: Option<bool>) -> Vec<NoteLinkReport> {
+        // The external links may have to be requested
+        tokio::task::spawn_blocking(move || link_operations::check_links(check_urls.unwrap_or(false)))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Opens the periodic note of a date, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The period of the note.
+    /// * `date` - A date of the period, as "YYYY-MM-DD", or today if `None`.
+    async fn get_periodic_note(kind: PeriodKind, date: Option<String>) -> PeriodicNote {
+        // Stamping a new daily note blocks on fetching the weather, so it runs off the async runtime
+        tokio::task::spawn_blocking(move || periodic_notes::get_periodic_note(kind, date.as_deref()))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Retrieves the period of a date, or of a period before or after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of period.
+    /// * `date` - A date of the period, as "YYYY-MM-DD", or today if `None`.
+    /// * `offset` - The number of periods after it, or before it if negative, 0 by default.
+    async fn get_period(kind: PeriodKind, date: Option<String>, offset: Option<i32>) -> Period {
+        periodic_notes::get_period(kind, date.as_deref(), offset.unwrap_or(0))
+    }
+
+    /// Retrieves the settings of the periodic notes.
+    async fn get_periodic_note_settings() -> PeriodicNoteSettings {
+        periodic_notes::get_periodic_note_settings()
+    }
+
+    /// Sets the settings of the periodic notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `periodic_notes` - The settings.
+    async fn set_periodic_note_settings(periodic_notes: PeriodicNoteSettings) -> () {
+        periodic_notes::set_periodic_note_settings(periodic_notes)
+    }
+
+    /// Retrieves the settings of the context stamped on the new daily notes.
+    async fn get_journal_context() -> JournalContextSettings {
+        journal_context::get_journal_context()
+    }
+
+    /// Sets the settings of the context stamped on the new daily notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    /// * `api_key` - The key of the weather API, or `None` to keep the current one.
+    async fn set_journal_context(config: JournalContextConfig, api_key: Option<String>) -> () {
+        journal_context::set_journal_context(config, api_key)
+    }
+
+    /// Adds a note to the notes to review.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn mark_for_review(note_id: i64) -> ReviewSchedule {
+        review_operations::mark_for_review(note_id)
+    }
+
+    /// Removes a note from the notes to review.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn unmark_for_review(note_id: i64) -> () {
+        review_operations::unmark_for_review(note_id)
+    }
+
+    /// Lists the notes due for review.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of notes.
+    async fn get_due_reviews(limit: Option<usize>) -> Vec<DueReview> {
+        review_operations::get_due_reviews(limit)
+    }
+
+    /// Records the review of a note, returning when it is due next.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `grade` - How well the note was remembered, from 0 to 5.
+    async fn record_review(note_id: i64, grade: u8) -> ReviewSchedule {
+        review_operations::record_review(note_id, grade)
+    }
+
+    /// Lists the flashcards due for review.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of cards.
+    /// * `note_id` - The ID of the note of the cards, or every note if `None`.
+    /// * `tag` - The tag of the notes of the cards, or every tag if `None`.
+    async fn list_due_cards(limit: Option<usize>, note_id: Option<i64>, tag: Option<String>) -> Vec<Flashcard> {
+        flashcard_operations::list_due_cards(limit, note_id, tag.as_deref())
+    }
+
+    /// Records the answer to a flashcard, returning when it is due next.
+    ///
+    /// # Arguments
+    ///
+    /// * `card_id` - The ID of the card.
+    /// * `grade` - How well the card was remembered, from 0 to 5.
+    async fn answer_card(card_id: i64, grade: u8) -> Flashcard {
+        flashcard_operations::answer_card(card_id, grade)
+    }
+
+    /// Counts the flashcards, due and learned.
+    async fn get_deck_stats() -> Vec<DeckStats> {
+        flashcard_operations::get_deck_stats()
+    }
+
+    /// Exports flashcards as an Anki deck, returning the number of cards exported.
+    ///
+    /// # Arguments
+    ///
+    /// * `deck_scope` - The cards to export.
+    /// * `path` - Where to write the deck.
+    async fn export_anki(deck_scope: DeckScope, path: String) -> usize {
+        flashcard_operations::export_anki(deck_scope, &path)
+    }
+
+    /// Starts a focus session on a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `planned_minutes` - How long the session should last, or until it is stopped if `None`.
+    async fn start_focus_session(note_id: i64, planned_minutes: Option<u32>) -> FocusSession {
+        focus_sessions::start_focus_session(note_id, planned_minutes)
+    }
+
+    /// Stops the focus session.
+    async fn stop_focus_session() -> Option<FocusSession> {
+        focus_sessions::stop_focus_session()
+    }
+
+    /// Retrieves the focus session in progress, if any.
+    async fn get_focus_session() -> Option<FocusSession> {
+        focus_sessions::get_focus_session()
+    }
+
+    /// Reports the time spent in focus sessions.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The period of the report, or every session if `None`.
+    /// * `group_by` - How to group the sessions.
+    async fn get_time_report(range: Option<StatsRange>, group_by: Option<TimeGroup>) -> Vec<TimeReportEntry> {
+        focus_sessions::get_time_report(range, group_by)
+    }
+
+    /// Tells how the secure notes are kept in the search index.
+    async fn get_index_protection() -> IndexProtection {
+        search_index::get_index_protection()
+    }
+
+    /// Sets how the secure notes are kept in the search index.
+    ///
+    /// # Arguments
+    ///
+    /// * `protection` - The protection.
+    async fn set_index_protection(protection: IndexProtection) -> () {
+        search_index::set_index_protection(protection)
+    }
+
+    /// Retrieves how long each step of the startup took.
+    async fn get_startup_timings() -> Vec<StartupTiming> {
+        Ok::<_, String>(startup::get_startup_timings())
+    }
+
+    /// Lists the background tasks, queued, running, and recently done.
+    async fn list_background_tasks() -> Vec<BackgroundTask> {
+        Ok::<_, String>(tasks::list_background_tasks())
+    }
+
+    /// Cancels a background task.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the task.
+    async fn cancel_task(id: i64) -> () {
+        tasks::cancel_task(id)
+    }
+
+    /// Reports the S3 operations run again after a crash.
+    async fn get_recovery_report() -> Vec<RecoveryEntry> {
+        journal::get_recovery_report()
+    }
+
+    /// Retrieves the usage metrics of the commands.
+    ///
+    /// # Arguments
+    ///
+    /// * `days` - The number of days, up to today.
+    async fn get_usage_metrics(days: Option<u32>) -> UsageMetrics {
+        metrics::get_usage_metrics(days)
+    }
+
+    /// Sets whether the usage metrics are recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether they are recorded.
+    async fn set_usage_metrics_enabled(enabled: bool) -> () {
+        metrics::set_usage_metrics_enabled(enabled)
+    }
+
+    /// Deletes the usage metrics, returning the number of records deleted.
+    async fn purge_usage_metrics() -> usize {
+        metrics::purge_usage_metrics()
+    }
+
+    /// Exports the usage metrics to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the metrics.
+    /// * `days` - The number of days, up to today.
+    async fn export_usage_metrics(path: String, days: Option<u32>) -> () {
+        metrics::export_usage_metrics(&path, days)
+    }
+
+    /// Exports the settings to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the settings.
+    async fn export_settings(path: String) -> () {
+        settings_profile::export_settings(&path)
+    }
+
+    /// Imports the settings from a file exported with `export_settings`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    async fn import_settings(path: String) -> SettingsImport {
+        settings_profile::import_settings(&path)
+    }
+
+    /// Retrieves the open notes and the layout of the window.
+    async fn get_workspace_state() -> WorkspaceState {
+        workspace::get_workspace_state()
+    }
+
+    /// Saves the open notes and the layout of the window.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The open notes and the layout.
+    async fn set_workspace_state(state: WorkspaceState) -> () {
+        workspace::set_workspace_state(state)
+    }
+
+    /// Saves the workspace under a name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the snapshot.
+    async fn save_workspace_snapshot(name: String) -> WorkspaceSnapshot {
+        workspace::save_workspace_snapshot(&name)
+    }
+
+    /// Restores the workspace saved under a name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the snapshot.
+    async fn restore_workspace_snapshot(name: String) -> WorkspaceState {
+        workspace::restore_workspace_snapshot(&name)
+    }
+
+    /// Lists the saved workspaces.
+    async fn list_workspace_snapshots() -> Vec<WorkspaceSnapshot> {
+        workspace::list_workspace_snapshots()
+    }
+
+    /// Deletes a saved workspace.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the snapshot.
+    async fn delete_workspace_snapshot(name: String) -> () {
+        workspace::delete_workspace_snapshot(&name)
+    }
+
+    /// Retrieves the settings of the notifications.
+    async fn get_notification_settings() -> NotificationSettings {
+        Ok::<_, String>(notifier::get_notification_settings())
+    }
+
+    /// Sets whether the notifications are muted.
+    ///
+    /// # Arguments
+    ///
+    /// * `muted` - Whether they are muted.
+    async fn set_notifications_muted(muted: bool) -> () {
+        notifier::set_notifications_muted(muted)
+    }
+
+    /// Sets the hours without notifications.
+    ///
+    /// # Arguments
+    ///
+    /// * `quiet_hours` - The hours.
+    async fn set_quiet_hours(quiet_hours: QuietHours) -> () {
+        notifier::set_quiet_hours(quiet_hours)
+    }
+
+    /// Sets the icon of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `icon` - The icon, or `None` to remove it.
+    async fn set_note_icon(id: i64, icon: Option<String>) -> () {
+        local_operations::set_note_icon(id, icon)
+    }
+
+    /// Sets the cover image of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `attachment_id` - The ID of the attachment shown as its cover, or `None` to remove it.
+    async fn set_note_cover(id: i64, attachment_id: Option<i64>) -> () {
+        local_operations::set_note_cover(id, attachment_id)
+    }
+
+    /// Attaches a file to a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `path` - The path of the file.
+    async fn add_attachment(note_id: i64, path: String) -> Attachment {
+        attachment_operations::add_attachment(note_id, &path)
+    }
+
+    /// Imports the files dropped on the window, as notes or as attachments of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The paths of the files.
+    /// * `target_note` - The ID of the note to attach the files to, or `None` to import them as notes.
+    async fn handle_dropped_files(paths: Vec<String>, target_note: Option<i64>) -> DroppedFiles {
+        // Copying and encrypting the files blocks, so it runs off the async runtime
+        tokio::task::spawn_blocking(move || drop_operations::handle_dropped_files(&paths, target_note))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Imports the notes of a folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the folder.
+    async fn import_notes_from_dir(path: String) -> DirectoryImport {
+        tokio::task::spawn_blocking(move || import::import_notes_from_dir(&path))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Forgets the files imported, so they can be imported again, returning the number of files forgotten.
+    async fn clear_import_ledger() -> usize {
+        import_ledger::clear_import_ledger()
+    }
+
+    /// Returns the IDs of the notes opened from files since the last call.
+    async fn take_opened_notes() -> Vec<i64> {
+        Ok::<_, String>(file_associations::take_opened_notes())
+    }
+
+    /// Lists the notes pinned to the tray.
+    async fn get_tray_menu_items() -> Vec<TrayMenuItem> {
+        tray::get_tray_menu_items()
+    }
+
+    /// Pins a note to the tray, returning the notes pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn pin_note_to_tray(note_id: i64) -> Vec<TrayMenuItem> {
+        tray::pin_note_to_tray(note_id)
+    }
+
+    /// Unpins a note from the tray, returning the notes pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn unpin_note_from_tray(note_id: i64) -> Vec<TrayMenuItem> {
+        tray::unpin_note_from_tray(note_id)
+    }
+
+    /// Exports a note as a ".cnote" file, returning its path.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the note.
+    /// * `path` - Where to write the file.
+    async fn export_cnote(id: i64, path: String) -> String {
+        file_associations::export_cnote(id, &path)
+    }
+
+    /// Lists the attachments of a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_attachments(note_id: i64) -> Vec<Attachment> {
+        attachment_operations::get_note_attachments(note_id)
+    }
+
+    /// Retrieves the content of an attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    async fn get_attachment_data(id: i64) -> AttachmentData {
+        attachment_operations::get_attachment_data(id)
+    }
+
+    /// Deletes an attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    async fn delete_attachment(id: i64) -> () {
+        attachment_operations::delete_attachment(id)
+    }
+
+    /// Finds the objects of a bucket that belong to no note.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn find_orphaned_bucket_objects(bucket_name: String) -> Vec<OrphanedObject> {
+        bucket_audit::find_orphaned_bucket_objects(&bucket_name).await
+    }
+
+    /// Moves the notes of a bucket to the keys of their notebook.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn migrate_bucket_keys(bucket_name: String) -> BucketKeyMigration {
+        s3_operations::migrate_bucket_keys(&bucket_name).await
+    }
+
+    /// Writes the notes of a bucket again in the current format.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    async fn migrate_bucket_format(bucket_name: String) -> BucketFormatMigration {
+        bucket_format::migrate_bucket_format(&bucket_name).await
+    }
+
+    /// Deletes objects of a bucket found by `find_orphaned_bucket_objects`, returning the keys deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `keys` - The keys of the objects.
+    async fn clean_orphaned_objects(bucket_name: String, keys: Vec<String>) -> Vec<String> {
+        bucket_audit::clean_orphaned_objects(&bucket_name, &keys).await
+    }
+
+    /// Lists the features and whether each is enabled.
+    async fn list_features() -> Vec<FeatureFlag> {
+        Ok::<_, String>(features::list_features())
+    }
+
+    /// Tells whether a feature is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the feature.
+    async fn is_feature_enabled(id: String) -> bool {
+        Ok::<_, String>(features::is_enabled(&id))
+    }
+
+    /// Enables or disables a feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the feature.
+    /// * `enabled` - Whether it is enabled.
+    async fn set_feature_enabled(id: String, enabled: bool) -> () {
+        features::set_feature_enabled(&id, enabled)
+    }
+
+    /// Retrieves the memory budgets of the caches.
+    async fn get_memory_budgets() -> MemoryBudgets {
+        Ok::<_, String>(memory_budget::get_memory_budgets())
+    }
+
+    /// Sets the memory budgets of the caches.
+    ///
+    /// # Arguments
+    ///
+    /// * `budgets` - The budgets.
+    async fn set_memory_budgets(budgets: MemoryBudgets) -> () {
+        memory_budget::set_memory_budgets(budgets)
+    }
+
+    /// Retrieves the URL the updates are checked at, if it was changed.
+    async fn get_update_manifest_url() -> Option<String> {
+        update_operations::get_update_manifest_url()
+    }
+
+    /// Sets the URL the updates are checked at.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the update manifest.
+    async fn set_update_manifest_url(url: String) -> () {
+        update_operations::set_update_manifest_url(&url)
+    }
+
+    /// Checks whether an update is available.
+    async fn check_for_updates() -> UpdateInfo {
+        update_operations::check_for_updates().await
+    }
+
+    /// Downloads and installs the update available, returning its version.
+    async fn install_update() -> String {
+        update_operations::install_update().await
+    }
+
+    /// Exports the list of the objects of a bucket, returning the number of objects listed.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_name` - The name of the bucket.
+    /// * `path` - Where to write the list.
+    async fn export_bucket_inventory(bucket_name: String, path: String) -> usize {
+        bucket_audit::export_bucket_inventory(&bucket_name, &path).await
+    }
+
+    /// Checks the database, the search index, and a bucket for problems.
+    ///
+    /// # Arguments
+    ///
+    /// * `fix` - Whether to fix the problems found, `false` by default.
+    /// * `bucket_name` - The name of the bucket to check, or none if `None`.
+    async fn run_doctor(fix: Option<bool>, bucket_name: Option<String>) -> DoctorReport {
+        Ok::<_, String>(doctor::run_doctor(fix.unwrap_or(false), bucket_name.as_deref()).await)
+    }
+
+    /// Describes how a note is encrypted, locally or in a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the local note.
+    /// * `bucket_name` - The name of the bucket, when `note_id` is `None`.
+    /// * `uuid` - The UUID of the note in the bucket, when `note_id` is `None`.
+    async fn inspect_note_envelope(note_id: Option<i64>, bucket_name: Option<String>, uuid: Option<String>) -> NoteEnvelope {
+        match (note_id, bucket_name, uuid) {
+            (Some(note_id), _, _) => envelope::inspect_local_envelope(note_id),
+            (None, Some(bucket_name), Some(uuid)) => envelope::inspect_bucket_envelope(&bucket_name, &uuid).await,
+            (None, None, _) => Err(AppError::Validation("Missing 'note_id' or 'bucket_name' key in args".to_string())),
+            (None, Some(_), None) => Err(AppError::Validation("Missing 'uuid' key in args".to_string())),
+        }
+    }
+
+    /// Saves the annotation of an attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    /// * `annotation` - The annotation.
+    async fn save_annotation(id: i64, annotation: String) -> () {
+        attachment_operations::save_annotation(id, &annotation)
+    }
+
+    /// Retrieves the annotation of an attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    async fn get_annotation(id: i64) -> Option<String> {
+        attachment_operations::get_annotation(id)
+    }
+
+    /// Extracts the text of an attachment again, for the search.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    async fn reextract_attachment_text(id: i64) -> () {
+        text_extraction::reextract_attachment_text(id)
+    }
+
+    /// Tells whether the text of an attachment was extracted.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the attachment.
+    async fn get_attachment_text_status(id: i64) -> Option<AttachmentText> {
+        text_extraction::get_attachment_text_status(id)
+    }
+
+    /// Retrieves the daily statistics of the notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The period of the statistics, or every day if `None`.
+    async fn get_stats_history(range: Option<StatsRange>) -> Vec<VaultStats> {
+        stats_operations::get_stats_history(range)
+    }
+
+    /// Retrieves the settings of the mailbox the notes are imported from.
+    async fn get_email_ingestion() -> EmailIngestionSettings {
+        email_operations::get_email_ingestion()
+    }
+
+    /// Sets the settings of the mailbox the notes are imported from.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    /// * `password` - The password of the mailbox, or `None` to keep the current one.
+    async fn set_email_ingestion(config: EmailIngestionConfig, password: Option<String>) -> () {
+        email_operations::set_email_ingestion(config, password)
+    }
+
+    /// Imports the new emails of the mailbox as notes, returning the number of notes imported.
+    async fn fetch_email_now() -> usize {
+        // Fetching blocks on the IMAP connection, so it runs off the async runtime
+        tokio::task::spawn_blocking(email_operations::fetch_email).await.map_err(|e| e.to_string())?
+    }
+
+    /// Retrieves the settings of the server the notes are emailed with.
+    async fn get_smtp_settings() -> SmtpSettings {
+        email_operations::get_smtp_settings()
+    }
+
+    /// Sets the settings of the server the notes are emailed with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    /// * `password` - The password of the server, or `None` to keep the current one.
+    async fn set_smtp_settings(config: SmtpConfig, password: Option<String>) -> () {
+        email_operations::set_smtp_settings(config, password)
+    }
+
+    /// Emails a note.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    /// * `to` - The address to send it to.
+    async fn send_note_email(note_id: i64, to: String) -> () {
+        // Sending blocks on the SMTP connection, so it runs off the async runtime
+        tokio::task::spawn_blocking(move || email_operations::send_note_email(note_id, &to)).await.map_err(|e| e.to_string())?
+    }
+
+    /// Retrieves the settings of the bridge the browser extension captures notes with.
+    async fn get_capture_bridge() -> CaptureBridgeSettings {
+        capture_bridge::get_capture_bridge()
+    }
+
+    /// Sets the settings of the bridge the browser extension captures notes with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    /// * `token` - The token of the extension, or `None` to keep the current one.
+    async fn set_capture_bridge(config: CaptureBridgeConfig, token: Option<String>) -> () {
+        capture_bridge::set_capture_bridge(config, token)
+    }
+
+    /// Retrieves the settings of the proxy.
+    async fn get_proxy_settings() -> ProxySettings {
+        proxy::get_proxy_settings()
+    }
+
+    /// Sets the settings of the proxy.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    /// * `password` - The password of the proxy, or `None` to keep the current one.
+    async fn set_proxy_settings(config: ProxyConfig, password: Option<String>) -> () {
+        proxy::set_proxy_settings(config, password)
+    }
+
+    /// Retrieves the settings of the S3 service.
+    async fn get_s3_config() -> S3Config {
+        s3_config::get_s3_config()
+    }
+
+    /// Sets the settings of the S3 service, returning them as saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The settings.
+    async fn set_s3_config(config: S3Config) -> S3Config {
+        s3_config::set_s3_config(config)
+    }
+
+    /// Lists the hosts with their own certificates.
+    async fn get_tls_remotes() -> Vec<TlsRemote> {
+        tls::get_tls_remotes()
+    }
+
+    /// Sets the certificates of a host.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `ca_bundle_path` - The path of the certificate authorities trusted for the host.
+    /// * `pinned_certificate_path` - The path of the only certificate accepted for the host.
+    async fn set_tls_remote(host: String, ca_bundle_path: Option<String>, pinned_certificate_path: Option<String>) -> TlsRemote {
+        tls::set_tls_remote(&host, ca_bundle_path.as_deref(), pinned_certificate_path.as_deref())
+    }
+
+    /// Removes the certificates of a host.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    async fn delete_tls_remote(host: String) -> () {
+        tls::delete_tls_remote(&host)
+    }
+
+    /// Connects to an address to test its certificates.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address, as "host:port".
+    async fn test_tls_connection(address: String) -> TlsConnectionTest {
+        tls::test_tls_connection(&address).await
+    }
+
+    /// Saves the article of a web page as a note, returning its ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the page.
+    async fn save_url_as_note(url: String) -> i64 {
+        // Downloading blocks on the HTTP requests, so it runs off the async runtime
+        tokio::task::spawn_blocking(move || article_operations::save_url_as_note(&url)).await.map_err(|e| e.to_string())?
+    }
+
+    /// Retrieves the web page a note was saved from.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_id` - The ID of the note.
+    async fn get_note_source(note_id: i64) -> Option<NoteSource> {
+        article_operations::get_note_source(note_id)
+    }
+
+    /// Lists the commands of the command palette.
+    async fn list_available_commands() -> Vec<CommandDescriptor> {
+        Ok::<_, String>(commands::list_available_commands())
+    }
+
+    /// Generates notes to test the application with, returning the number of notes created.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_notes` - The number of notes.
+    /// * `avg_size` - The average size of the notes, in bytes.
+    /// * `n_tags` - The number of tags.
+    async fn generate_test_data(n_notes: usize, avg_size: usize, n_tags: usize) -> usize {
+        // Generating many notes takes a while, so it runs off the async runtime
+        tokio::task::spawn_blocking(move || test_data::generate_test_data(n_notes, avg_size, n_tags)).await.map_err(|e| e.to_string())?
+    }
+
+    /// Measures the core operations, writing the report to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the report.
+    async fn run_benchmarks(path: String) -> BenchmarkReport {
+        benchmark(path).await
+    }
+
+    /// Records that the window saved the note being edited, in answer to the "flush-drafts" event (see `shutdown`).
+    async fn drafts_flushed() -> () {
+        shutdown::drafts_flushed();
+        Ok::<(), String>(())
+    }
+}
+
+
+/// Executes a command on behalf of the local HTTP API or a plugin, which authenticate with an API token.
+///
+/// # Arguments
+///
+/// * `token` - The secret of the API token.
+/// * `command` - The name of the command.
+/// * `args` - The arguments of the command, as a JSON object.
+///
+/// # Returns
+///
+/// A `CommandResponse` with the data of the command as JSON, or its error (see `respond`).
+///
+/// Only the commands of the command palette can be run, and only those the scopes of the token allow (see
+/// `api_tokens::authorize`). The refused commands are not run, nor recorded in the usage metrics.
+#[tauri::command]
+pub async fn execute_api_command(token: String, command: String, args: serde_json::Value) -> CommandResponse<serde_json::Value> {
+    let request_id = new_request_id();
+    let started = std::time::Instant::now();
+    let result = match api_tokens::authorize(&token, &command) {
+        Ok(_) => run_command(&command, args).await,
+        Err(e) => Err(e.into()),
+    };
+    respond(&command, request_id, result, started)
+}
+
+
+/// Runs a command by name like `run` does, tracking it if it writes to S3 and recording it in the usage metrics.
+///
+/// Unknown command names are not recorded, as they could be anything.
+async fn run_command(command: &str, args: serde_json::Value) -> Result<serde_json::Value, AppError> {
+    let started = std::time::Instant::now();
+    let result = match shutdown::track_write(command) {
+        Ok(_write) => route(command, args).await,
+        Err(e) => Err(AppError::Unavailable(e)),
+    };
+    if !matches!(&result, Err(AppError::NotFound(message)) if message == UNKNOWN_COMMAND) {
+        metrics::record_command(command, result.is_ok(), started.elapsed());
+    }
+
+    result
+}
+
+
+/// Measures the core operations off the async runtime, as it takes minutes.
+#[cfg(feature = "benchmarks")]
+async fn benchmark(path: String) -> Result<BenchmarkReport, String> {
+    tokio::task::spawn_blocking(move || benchmarks::run_benchmarks(&path)).await.map_err(|e| e.to_string())?
+}
+
+
+/// Fails, as the benchmarks are left out of this build.
+#[cfg(not(feature = "benchmarks"))]
+async fn benchmark(_path: String) -> Result<BenchmarkReport, String> {
+    Err("Benchmarks are only available in builds with the 'benchmarks' feature".to_string())
+}
+
+
+/// Runs the operation of a command the window calls.
+///
+/// The command is tracked if it writes to S3, so the shutdown waits for it, and its name, outcome, and duration are
+/// recorded in the usage metrics if the user opted in (see `metrics`).
+///
+/// # Returns
+///
+/// Returns the `CommandResponse` of the command (see `respond`). Its error is `AppError::Unavailable` if the command
+/// writes to S3 and the application is shutting down, or tells what failed if the operation fails (see `errors`).
+async fn run<T, E: Into<AppError>>(command: &str, operation: impl Future<Output = Result<T, E>>) -> CommandResponse<T> {
+    let request_id = new_request_id();
+    let started = std::time::Instant::now();
+    let result = match shutdown::track_write(command) {
+        Ok(_write) => operation.await.map_err(Into::into),
+        Err(e) => Err(AppError::Unavailable(e)),
+    };
+    metrics::record_command(command, result.is_ok(), started.elapsed());
+
+    respond(command, request_id, result, started)
+}
+
+
+/// Returns a new ID for a command call, which the window logs with the response.
+pub fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+
+/// Wraps the result of a command in the envelope every command responds with, so the window can tell apart the
+/// successes and the failures the same way for each command, and report the ID of a failed call.
+///
+/// # Arguments
+///
+/// * `command` - The name of the command.
+/// * `request_id` - The ID of the call (see `new_request_id`).
+/// * `result` - The result of the command.
+/// * `started` - When the command started.
+///
+/// # Operation
+///
+/// A failed call is logged with its ID, so a bug report quoting the ID logged by the window can be matched with the
+/// logs of the backend.
+///
+/// # Returns
+///
+/// Returns a `CommandResponse` with the data or the error of the command, its ID, and its duration.
+pub fn respond<T>(command: &str, request_id: String, result: Result<T, AppError>, started: std::time::Instant) -> CommandResponse<T> {
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(data) => CommandResponse { ok: true, data: Some(data), error: None, request_id, duration_ms },
+        Err(error) => {
+            eprintln!("[{}] {} failed after {} ms: {}", request_id, command, duration_ms, error);
+            CommandResponse { ok: false, data: None, error: Some(error), request_id, duration_ms }
+        },
+    }
+}
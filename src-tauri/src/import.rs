@@ -0,0 +1,293 @@
+// import.rs
+
+use crate::{ delta, drop_operations, import_ledger, local_operations, notifier, status_operations, local_operations::CONNECTION, models::DirectoryImport, models::Note, models::SkippedFile };
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+/// The extensions of the files imported as notes, and whether they are markdown.
+const NOTE_EXTENSIONS: [(&str, bool); 3] = [("md", true), ("markdown", true), ("txt", false)];
+
+/// The line opening and closing the YAML front matter of a markdown file.
+const FRONT_MATTER_DELIMITER: &str = "---";
+
+
+/// The metadata read from the front matter of a file.
+#[derive(Default)]
+struct FrontMatter {
+    title: Option<String>,
+    created_at: Option<i64>,
+    status: Option<String>,
+    tags: Vec<String>,
+    aliases: Vec<String>,
+}
+
+
+/// Imports the markdown and text files of a folder and its subfolders as notes, e.g. a folder of notes from
+/// another application.
+///
+/// # Arguments
+///
+/// * `path` - The path of the folder.
+///
+/// # Operation
+///
+/// * Each ".md", ".markdown", and ".txt" file becomes a note. The other files, the hidden files and folders, and
+///   the links are skipped.
+/// * The YAML front matter of a file, e.g. written by `export_operations::export_notes`, is removed from the note
+///   and its "title", "created" (or "date"), "status", "tags", and "aliases" fields are kept. Without a title, the
+///   title is the heading on the first line of a markdown file, or the name of the file. A status that is not in
+///   the workflow is dropped.
+/// * The notes are encrypted and written in a single transaction. A file imported before updates its note rather
+///   than creating another one, or leaves it as it is if the file did not change (see `import_ledger`), so importing
+///   the folder again picks up the changes.
+/// * A file that cannot be imported, e.g. a text file that is not UTF-8 or whose note is locked, is skipped with the
+///   reason, without stopping the others.
+///
+/// # Returns
+///
+/// Returns a `DirectoryImport` with the imported notes and the skipped files, by their path within the folder.
+///
+/// # Errors
+///
+/// This function will return an error if the path is not a readable folder, or if there is an issue with the
+/// database connection.
+pub fn import_notes_from_dir(path: &str) -> Result<DirectoryImport, String> {
+    let root = Path::new(path);
+    if !root.is_dir() {
+        return Err(format!("{} is not a directory", path));
+    }
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    collect_files(root, root, &mut files, &mut skipped)?;
+    files.sort();
+
+    // The files are read before the transaction, which holds the database connection
+    let mut notes = Vec::new();
+    for file in files {
+        let relative = relative_path(root, &file);
+        match read_note(&file) {
+            Ok(note) => notes.push((relative, note)),
+            Err(reason) => skipped.push(SkippedFile { path: relative, reason }),
+        }
+    }
+
+    let source = import_ledger::path_source(root);
+    let mut imported = Vec::new();
+    {
+        let mut conn = CONNECTION.lock().unwrap();
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        for (relative, note) in notes {
+            // A file that cannot be imported is rolled back on its own, keeping the others
+            let savepoint = tx.savepoint().map_err(|e| e.to_string())?;
+            match import_ledger::import_in(&savepoint, &import_ledger::fingerprint(&source, &relative), &note) {
+                Ok(note) => {
+                    savepoint.commit().map_err(|e| e.to_string())?;
+                    imported.push(note);
+                },
+                Err(reason) => skipped.push(SkippedFile { path: relative, reason }),
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+
+    if !imported.is_empty() {
+        notifier::notify("Notes imported", &format!("{} files were imported from {}.", imported.len(), path));
+    }
+
+    Ok(DirectoryImport { imported, skipped })
+}
+
+
+/// Collects the files of a folder and its subfolders, without the hidden ones.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>, skipped: &mut Vec<SkippedFile>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // The type of a link is not followed, so a link to a parent folder does not loop
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if let Err(reason) = collect_files(root, &path, files, skipped) {
+                    skipped.push(SkippedFile { path: relative_path(root, &path), reason });
+                }
+            },
+            Ok(file_type) if file_type.is_file() => files.push(path),
+            _ => skipped.push(SkippedFile { path: relative_path(root, &path), reason: "Not a file".to_string() }),
+        }
+    }
+    Ok(())
+}
+
+
+/// Builds a note from a markdown or text file.
+fn read_note(path: &Path) -> Result<Note, String> {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let markdown = NOTE_EXTENSIONS.iter()
+        .find(|(note_extension, _)| *note_extension == extension)
+        .map(|(_, markdown)| *markdown)
+        .ok_or("Not a markdown or text file".to_string())?;
+
+    let text = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let text = String::from_utf8(text).map_err(|_| format!("{} is not a UTF-8 text file", path.display()))?;
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let (front_matter, body) = match markdown.then(|| split_front_matter(&text)).flatten() {
+        Some((front_matter, body)) => (parse_front_matter(front_matter), body),
+        None => (FrontMatter::default(), text.as_str()),
+    };
+
+    let title = front_matter.title
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| drop_operations::file_title(path, body, markdown));
+    // The status may not be in the workflow of this vault, in which case it is dropped rather than failing the import
+    let status = front_matter.status.filter(|status| status_operations::check_status(status).is_ok());
+    let note = Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(title.trim()),
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", body.trim_start_matches('\n').trim_end()) })]),
+        nonce: None,
+        created_at: front_matter.created_at.unwrap_or_else(|| chrono::Utc::now().timestamp()),
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: front_matter.aliases,
+        tags: front_matter.tags,
+    };
+    local_operations::validate_params(note.clone())?;
+    Ok(note)
+}
+
+
+/// Splits the YAML front matter of a markdown file from its body.
+///
+/// # Returns
+///
+/// Returns the lines of the front matter, without its delimiters, and the body, or `None` if the file does not
+/// start with front matter.
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let rest = text.strip_prefix(FRONT_MATTER_DELIMITER)?.strip_prefix('\n')?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+
+/// Parses the fields of the front matter kept on the imported notes. The other fields, and the values that cannot be
+/// read, are ignored.
+///
+/// Only the subset of YAML written by note applications is understood: "key: value" lines, with quoted or plain
+/// values, and lists written as "[a, b]" or as "- a" lines.
+fn parse_front_matter(front_matter: &str) -> FrontMatter {
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
+    for line in front_matter.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            if let Some((_, values)) = fields.last_mut() {
+                values.push(unquote(item));
+            }
+            continue;
+        }
+        if let Some((key, value)) = trimmed.split_once(':') {
+            let value = value.trim();
+            let values = match value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+                Some(list) => split_list(list),
+                None if value.is_empty() => Vec::new(),
+                None => vec![unquote(value)],
+            };
+            fields.push((key.trim().to_lowercase(), values));
+        }
+    }
+
+    let mut parsed = FrontMatter::default();
+    for (key, values) in fields {
+        match key.as_str() {
+            "title" => parsed.title = values.into_iter().next(),
+            "created" | "date" => parsed.created_at = values.first().and_then(|value| parse_date(value)),
+            "status" => parsed.status = values.into_iter().next().filter(|status| !status.is_empty()),
+            "tags" => parsed.tags = values.into_iter().filter(|tag| !tag.is_empty()).collect(),
+            "aliases" => parsed.aliases = values.into_iter().filter(|alias| !alias.is_empty()).collect(),
+            _ => {},
+        }
+    }
+    parsed
+}
+
+
+/// Splits the items of a "[a, b]" list, without the brackets, keeping the commas of the quoted items.
+fn split_list(list: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in list.chars() {
+        if quote.is_none() && c == ',' {
+            items.push(unquote(&std::mem::take(&mut item)));
+            continue;
+        }
+        match quote {
+            Some(open) if c == open && !escaped => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            _ => {},
+        }
+        escaped = quote == Some('"') && c == '\\' && !escaped;
+        item.push(c);
+    }
+    if !item.trim().is_empty() {
+        items.push(unquote(&item));
+    }
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+
+/// Reads a plain, single-quoted, or double-quoted YAML value.
+fn unquote(value: &str) -> String {
+    let value = value.trim().trim_end_matches(',').trim();
+    if let Some(value) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        value.replace("\\\"", "\"").replace("\\\\", "\\")
+    } else if let Some(value) = value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')) {
+        value.replace("''", "'")
+    } else {
+        value.to_string()
+    }
+}
+
+
+/// Parses a date of the front matter, as an RFC 3339 date and time or a "YYYY-MM-DD" date.
+fn parse_date(value: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|date| date.timestamp())
+        .ok()
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc().timestamp())
+        })
+}
+
+
+/// Returns the path of a file within the imported folder, with forward slashes, so it is the same on every platform.
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
@@ -0,0 +1,169 @@
+// import_ledger.rs
+
+use crate::{ local_operations, note_events, version_operations, local_operations::CONNECTION, models::ImportOutcome, models::ImportedNote, models::Note };
+use ring::digest;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use uuid::Uuid;
+
+
+/// The reason of the snapshot taken before a note is updated by an import (see `version_operations`).
+const SNAPSHOT_REASON: &str = "import";
+
+
+/// Returns the fingerprint of an item of an import source, e.g. a file of a folder or a note of an ENEX export,
+/// which tells the same item apart when the source is imported again.
+///
+/// # Arguments
+///
+/// * `source` - The source, e.g. the path of the archive (see `path_source`).
+/// * `item` - The item within the source, e.g. the path of the file in the archive, or an empty string if the
+///   source is a single note.
+///
+/// # Returns
+///
+/// Returns the fingerprint, as the hex SHA-256 of the source and the item.
+pub fn fingerprint(source: &str, item: &str) -> String {
+    hash(&format!("{}\0{}", source, item))
+}
+
+
+/// Returns the source of the items imported from a path, which is the same however the path is written.
+pub fn path_source(path: &Path) -> String {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+
+/// Imports a note from an item of an import source, once: an item imported before updates its note rather than
+/// creating another one.
+///
+/// # Arguments
+///
+/// * `fingerprint` - The fingerprint of the item (see `fingerprint`).
+/// * `note` - The note built from the item, with its title and content.
+///
+/// # Operation
+///
+/// The ledger of the imports maps the fingerprint of each imported item to the UUID of its note and a hash of the
+/// title and the content it was imported with, so an interrupted import is resumed by running it again, and a
+/// repeated import is an update:
+///
+/// * An item not imported before, or whose note was deleted since, creates a note.
+/// * An item whose title and content did not change since it was imported leaves its note as it is, even if the
+///   note was edited since.
+/// * An item that changed updates the title and the content of its note, after a snapshot of the note, so the
+///   edits made since the previous import can be restored (see `version_operations`).
+///
+/// The note and the ledger are written in the same transaction, so an import stopped at any point never leaves a
+/// note the ledger does not know about.
+///
+/// # Returns
+///
+/// Returns an `ImportedNote` with the ID and the UUID of the note, and whether it was created, updated, or unchanged.
+///
+/// # Errors
+///
+/// This function will return an error if the note is invalid, if the note to update is locked, or if there is an
+/// issue with the database connection.
+pub fn import_once(fingerprint: &str, note: &Note) -> Result<ImportedNote, String> {
+    local_operations::validate_params(note.clone())?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let imported = import_in(&tx, fingerprint, note)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(imported)
+}
+
+
+/// Imports a note from an item of an import source within a transaction, like `import_once`, e.g. to import the
+/// files of a folder in a single transaction.
+///
+/// # Arguments
+///
+/// * `tx` - The transaction, which the caller commits.
+/// * `fingerprint` - The fingerprint of the item (see `fingerprint`).
+/// * `note` - The note built from the item, already validated (see `local_operations::validate_params`).
+///
+/// # Returns
+///
+/// Returns an `ImportedNote` with the ID and the UUID of the note, and whether it was created, updated, or unchanged.
+///
+/// # Errors
+///
+/// This function will return an error if the note to update is locked, or if there is an issue with the database connection.
+pub fn import_in(tx: &Connection, fingerprint: &str, note: &Note) -> Result<ImportedNote, String> {
+    let content_hash = hash(&format!("{}\0{}", note.title, note.content));
+    let entry: Option<(String, String)> = tx.query_row(
+        "SELECT note_uuid, content_hash FROM import_ledger WHERE fingerprint = ?1",
+        params![fingerprint],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().map_err(|e| e.to_string())?;
+    let existing: Option<(i64, String, String)> = match entry {
+        Some((uuid, previous_hash)) => tx.query_row(
+            "SELECT id FROM notes WHERE uuid = ?1 AND deleted_at IS NULL",
+            params![uuid],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?.map(|id| (id, uuid, previous_hash)),
+        None => None,
+    };
+
+    let imported = match existing {
+        Some((note_id, uuid, previous_hash)) if previous_hash == content_hash => {
+            ImportedNote { note_id, uuid, outcome: ImportOutcome::Unchanged }
+        },
+        Some((note_id, uuid, _)) => {
+            local_operations::check_not_locked(tx, note_id)?;
+            version_operations::snapshot_note(tx, note_id, SNAPSHOT_REASON)?;
+            let (encrypted_content, nonce_str) = crate::crypto::encrypt_content(&note.content)?;
+            let now = chrono::Utc::now();
+            tx.execute(
+                "UPDATE notes SET title = ?1, content = ?2, nonce = ?3, updated_at = ?4, timestamp = ?5,
+                preview = CASE WHEN secure = 1 THEN NULL ELSE ?6 END WHERE id = ?7",
+                params![
+                    note.title, encrypted_content, nonce_str, now.timestamp(), now.to_rfc3339(),
+                    local_operations::stored_preview(&note.content, false), note_id,
+                ],
+            ).map_err(|e| e.to_string())?;
+            note_events::note_saved(tx, note_id, &note.content)?;
+            ImportedNote { note_id, uuid, outcome: ImportOutcome::Updated }
+        },
+        None => {
+            let uuid = Uuid::new_v4().to_string();
+            let note = Note { uuid: Some(uuid.clone()), ..note.clone() };
+            let note_id = local_operations::insert_note(tx, &note)?;
+            note_events::note_saved(tx, note_id, &note.content)?;
+            ImportedNote { note_id, uuid, outcome: ImportOutcome::Created }
+        },
+    };
+
+    tx.execute(
+        "INSERT INTO import_ledger (fingerprint, note_uuid, content_hash, imported_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (fingerprint) DO UPDATE SET note_uuid = ?2, content_hash = ?3, imported_at = ?4",
+        params![fingerprint, imported.uuid, content_hash, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(imported)
+}
+
+
+/// Forgets the imported items, so importing them again creates new notes rather than updating the imported ones.
+///
+/// # Returns
+///
+/// Returns the number of forgotten items.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn clear_import_ledger() -> Result<usize, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute("DELETE FROM import_ledger", []).map_err(|e| e.to_string())
+}
+
+
+/// Hashes a text as hex SHA-256.
+fn hash(text: &str) -> String {
+    digest::digest(&digest::SHA256, text.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,236 @@
+// journal.rs
+
+use crate::{ crypto, s3_operations, local_operations::CONNECTION, models::AppError, models::JournaledOperation, models::RecoveryEntry, models::RecoveryOutcome };
+use rusqlite::params;
+
+
+/// The number of days the recovered operations are kept in the report.
+const REPORT_RETENTION_DAYS: i64 = 30;
+
+
+/// An operation left in the journal, with its ID, or the error met while decrypting it.
+type PendingOperation = (i64, Result<JournaledOperation, String>);
+
+
+/// An operation recorded in the journal, which is removed from it once the operation returns, whether it succeeded
+/// or failed. Only the operations interrupted by a crash or a forced exit are left in the journal.
+pub struct JournalEntry {
+    id: i64,
+}
+
+impl Drop for JournalEntry {
+    fn drop(&mut self) {
+        let conn = CONNECTION.lock().unwrap();
+        if let Err(e) = conn.execute("DELETE FROM operation_journal WHERE id = ?1", params![self.id]) {
+            eprintln!("Failed to remove operation {} from the journal: {}", self.id, e);
+        }
+    }
+}
+
+
+/// Records an operation in the journal before it starts.
+///
+/// # Arguments
+///
+/// * `operation` - The operation, with everything needed to run it again.
+///
+/// # Operation
+///
+/// The details of the operation, which can hold the content of a note, are encrypted.
+///
+/// # Returns
+///
+/// Returns a `JournalEntry` to keep until the operation returns.
+///
+/// # Errors
+///
+/// This function will return an error if the operation cannot be encrypted or if there is an issue with the database connection.
+pub fn record(operation: &JournaledOperation) -> Result<JournalEntry, String> {
+    let (name, target) = describe(operation);
+    let details = serde_json::to_string(operation).map_err(|e| e.to_string())?;
+    let (details, nonce) = crypto::encrypt_content(&details)?;
+
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO operation_journal (operation, target, details, nonce, started_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, target, details, nonce, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(JournalEntry { id: conn.last_insert_rowid() })
+}
+
+
+/// Recovers the operations left in the journal by a crash or a forced exit, when the application starts.
+///
+/// # Operation
+///
+/// * Each operation is run again. The operations are idempotent: a note is uploaded or updated with the same
+///   content, and deleting a note that is no longer in the bucket counts as already done.
+/// * The outcome of each operation is kept for 30 days, and listed by `get_recovery_report`.
+pub async fn recover() {
+    if let Err(e) = retry_operations(i64::MAX).await {
+        eprintln!("Failed to read the operation journal: {}", e);
+    }
+}
+
+
+/// Runs again the operations of the journal started before a time, like `recover` does at startup.
+///
+/// # Arguments
+///
+/// * `started_before` - The time before which the operations started, so operations in progress are left alone.
+///
+/// # Returns
+///
+/// Returns the number of operations run again, whatever their outcome.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub async fn retry_operations(started_before: i64) -> Result<usize, String> {
+    let pending = pending_operations(started_before)?;
+    let count = pending.len();
+
+    for (id, operation) in pending {
+        let (outcome, error) = match operation {
+            Ok(operation) => match run(operation).await {
+                Ok(outcome) => (outcome, None),
+                Err(e) => (RecoveryOutcome::Failed, Some(e)),
+            },
+            Err(e) => (RecoveryOutcome::Failed, Some(e)),
+        };
+
+        let conn = CONNECTION.lock().unwrap();
+        let result = conn.execute(
+            "UPDATE operation_journal SET outcome = ?1, error = ?2, recovered_at = ?3, details = '', nonce = '' WHERE id = ?4",
+            params![outcome.as_str(), error, chrono::Utc::now().timestamp(), id],
+        );
+        if let Err(e) = result {
+            eprintln!("Failed to record the recovery of operation {}: {}", id, e);
+        }
+    }
+
+    Ok(count)
+}
+
+
+/// Retrieves the operations of the journal started before a time that have not returned, i.e. the operations
+/// interrupted by a crash that were not recovered, or stuck waiting on the network.
+///
+/// # Arguments
+///
+/// * `started_before` - The time before which the operations started.
+///
+/// # Returns
+///
+/// Returns the name of each operation, what it is about, and when it started, oldest first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_stuck_operations(started_before: i64) -> Result<Vec<(String, String, i64)>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT operation, target, started_at FROM operation_journal WHERE outcome IS NULL AND started_at < ?1 ORDER BY id"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![started_before], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|e| e.to_string())?;
+
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Retrieves the operations recovered after a crash over the last 30 days, so the user knows what was repaired.
+///
+/// # Returns
+///
+/// Returns the recovered operations, newest first, with what they were about and their outcome.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_recovery_report() -> Result<Vec<RecoveryEntry>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, operation, target, started_at, outcome, error, recovered_at FROM operation_journal
+        WHERE outcome IS NOT NULL AND recovered_at >= ?1 ORDER BY recovered_at DESC, id DESC"
+    ).map_err(|e| e.to_string())?;
+    let entry_iter = stmt.query_map(
+        params![chrono::Utc::now().timestamp() - REPORT_RETENTION_DAYS * 24 * 60 * 60],
+        |row| {
+            Ok(RecoveryEntry {
+                id: row.get(0)?,
+                operation: row.get(1)?,
+                target: row.get(2)?,
+                started_at: row.get(3)?,
+                outcome: RecoveryOutcome::from_str(&row.get::<_, String>(4)?),
+                error: row.get(5)?,
+                recovered_at: row.get(6)?,
+            })
+        },
+    ).map_err(|e| e.to_string())?;
+
+    entry_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Returns the name of an operation and what it is about, shown in the report.
+fn describe(operation: &JournaledOperation) -> (&'static str, String) {
+    match operation {
+        JournaledOperation::UploadNote { bucket_name, note } => ("upload_note", format!("{}/{}", bucket_name, note.title)),
+        JournaledOperation::UpdateBucketNote { bucket_name, note } => ("update_bucket_note", format!("{}/{}", bucket_name, note.title)),
+        JournaledOperation::DeleteBucketNote { bucket_name, uuid } => ("delete_bucket_note", format!("{}/{}", bucket_name, uuid)),
+        JournaledOperation::DeleteBucketNotes { bucket_name } => ("delete_bucket_notes", bucket_name.clone()),
+    }
+}
+
+
+/// Reads the operations left in the journal started before a time, removing the recovered ones older than 30 days
+/// on the way.
+fn pending_operations(started_before: i64) -> Result<Vec<PendingOperation>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "DELETE FROM operation_journal WHERE outcome IS NOT NULL AND recovered_at < ?1",
+        params![chrono::Utc::now().timestamp() - REPORT_RETENTION_DAYS * 24 * 60 * 60],
+    ).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare("SELECT id, details, nonce FROM operation_journal WHERE outcome IS NULL AND started_at < ?1 ORDER BY id")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![started_before], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut pending = Vec::new();
+    for row in rows {
+        let (id, details, nonce) = row.map_err(|e| e.to_string())?;
+        let operation = crypto::decrypt_content(&details, &nonce)
+            .map_err(|e| e.to_string())
+            .and_then(|details| serde_json::from_str(&details).map_err(|e| e.to_string()));
+        pending.push((id, operation));
+    }
+
+    Ok(pending)
+}
+
+
+/// Runs an interrupted operation again.
+async fn run(operation: JournaledOperation) -> Result<RecoveryOutcome, String> {
+    match operation {
+        JournaledOperation::UploadNote { bucket_name, note } => {
+            s3_operations::upload_note_to_bucket(&bucket_name, note).await?;
+        },
+        JournaledOperation::UpdateBucketNote { bucket_name, note } => {
+            s3_operations::update_bucket_note(&bucket_name, note).await.map_err(|e| e.to_string())?;
+        },
+        JournaledOperation::DeleteBucketNote { bucket_name, uuid } => {
+            match s3_operations::delete_bucket_note(&bucket_name, &uuid).await.map_err(|e| AppError::s3(e.as_ref())) {
+                Err(AppError::NotFound(_)) => return Ok(RecoveryOutcome::AlreadyDone),
+                result => result?,
+            }
+        },
+        JournaledOperation::DeleteBucketNotes { bucket_name } => {
+            s3_operations::delete_bucket_notes(&bucket_name).await.map_err(|e| e.to_string())?;
+        },
+    }
+
+    Ok(RecoveryOutcome::Retried)
+}
@@ -0,0 +1,238 @@
+// journal_context.rs
+
+use crate::{ proxy, settings, models::JournalContextConfig, models::JournalContextSettings, models::TemperatureUnit };
+use chrono::{Local, NaiveDate, TimeZone};
+use serde_json::Value;
+use std::time::Duration;
+
+
+/// The settings key under which the journal context configuration is stored, as JSON.
+const CONFIG_SETTING: &str = "journal_context";
+
+/// The settings key under which the OpenWeatherMap API key is stored encrypted (see `settings::set_secret_setting`).
+const API_KEY_SETTING: &str = "journal_context_api_key";
+
+/// The current weather endpoint of the OpenWeatherMap API.
+const WEATHER_API: &str = "https://api.openweathermap.org/data/2.5/weather";
+
+/// How long to wait for the weather before creating the note without it.
+const WEATHER_TIMEOUT_SECONDS: u64 = 10;
+
+/// The altitude of the sun, in degrees, at sunrise and sunset, accounting for refraction and the size of its disc.
+const SUN_ALTITUDE_AT_HORIZON: f64 = -0.833;
+
+/// The tilt of the Earth's axis, in degrees.
+const EARTH_AXIAL_TILT: f64 = 23.4397;
+
+/// The Julian date of 2000-01-01 at noon, the epoch of the sunrise equation.
+const J2000: f64 = 2451545.0;
+
+/// The Julian date of the Unix epoch.
+const UNIX_EPOCH_JULIAN_DATE: f64 = 2440587.5;
+
+
+/// Retrieves the journal context settings. The API key is never returned.
+///
+/// # Returns
+///
+/// Returns a `JournalContextSettings` with the configuration of the context, or `None` if it is not configured,
+/// and whether the weather API key is set.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_journal_context() -> Result<JournalContextSettings, String> {
+    Ok(JournalContextSettings {
+        config: get_config()?,
+        has_api_key: settings::get_setting(API_KEY_SETTING)?.is_some(),
+    })
+}
+
+
+/// Configures the context the new daily notes are stamped with.
+///
+/// # Arguments
+///
+/// * `config` - Whether the context is enabled, and which of the day of the week, the weather, and the sunrise and
+///   sunset times it holds. The weather and the sun need the latitude and the longitude of the user.
+/// * `api_key` - The OpenWeatherMap API key the weather is fetched with, stored encrypted. `None` keeps the current
+///   key, and an empty key removes it.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the location is missing or out of range while the weather or the sun is
+/// enabled, if the weather is enabled without an API key, or if there is an issue with the database connection.
+pub fn set_journal_context(config: JournalContextConfig, api_key: Option<String>) -> Result<(), String> {
+    if config.weather || config.sun {
+        let (Some(latitude), Some(longitude)) = (config.latitude, config.longitude) else {
+            return Err("The weather and the sun need a latitude and a longitude".to_string());
+        };
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err("Latitude should be between -90 and 90".to_string());
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err("Longitude should be between -180 and 180".to_string());
+        }
+    }
+
+    let has_api_key = match api_key.as_deref().map(str::trim) {
+        Some("") => false,
+        Some(_) => true,
+        None => settings::get_setting(API_KEY_SETTING)?.is_some(),
+    };
+    if config.weather && !has_api_key {
+        return Err("The weather needs an OpenWeatherMap API key".to_string());
+    }
+
+    match api_key.as_deref().map(str::trim) {
+        Some("") => settings::delete_setting(API_KEY_SETTING)?,
+        Some(api_key) => settings::set_secret_setting(API_KEY_SETTING, api_key)?,
+        None => {},
+    }
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)
+}
+
+
+/// Builds the context a new daily note is stamped with, as a front matter block to put before its content.
+///
+/// # Arguments
+///
+/// * `date` - The day of the note.
+///
+/// # Operation
+///
+/// * The block holds a "key: value" line per enabled field, between "---" lines, e.g. "day: Monday",
+///   "weather: light rain, 12 °C", "sunrise: 07:42", and "sunset: 18:51".
+/// * The weather is the current weather, so it is only added to the note of today. A failure to fetch it is logged
+///   and leaves it out, so the note is still created.
+/// * The sunrise and sunset times are computed for the location and shown in the local time zone. A day without
+///   sunrise or sunset is stamped "sun: polar night" or "sun: midnight sun".
+///
+/// It blocks until the weather is fetched, so it must not be called from the async runtime directly.
+///
+/// # Returns
+///
+/// Returns the front matter, or `None` if the context is not enabled or has no field.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn daily_context(date: NaiveDate) -> Result<Option<String>, String> {
+    let Some(config) = get_config()?.filter(|config| config.enabled) else {
+        return Ok(None);
+    };
+
+    let mut fields = Vec::new();
+    if config.day_of_week {
+        fields.push(("day", date.format("%A").to_string()));
+    }
+    if let (Some(latitude), Some(longitude)) = (config.latitude, config.longitude) {
+        if config.weather && date == Local::now().date_naive() {
+            match fetch_weather(latitude, longitude, config.temperature_unit) {
+                Ok(weather) => fields.push(("weather", weather)),
+                Err(e) => eprintln!("Failed to fetch the weather for the daily note: {}", e),
+            }
+        }
+        if config.sun {
+            match sun_times(date, latitude, longitude) {
+                SunTimes::Times(sunrise, sunset) => {
+                    fields.push(("sunrise", local_time(sunrise)));
+                    fields.push(("sunset", local_time(sunset)));
+                },
+                SunTimes::PolarNight => fields.push(("sun", "polar night".to_string())),
+                SunTimes::MidnightSun => fields.push(("sun", "midnight sun".to_string())),
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let lines: Vec<String> = fields.into_iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+    Ok(Some(format!("---\n{}\n---\n", lines.join("\n"))))
+}
+
+
+/// The sunrise and sunset of a day, as Unix timestamps, or why the sun does not rise or set.
+enum SunTimes {
+    Times(i64, i64),
+    PolarNight,
+    MidnightSun,
+}
+
+
+/// Reads the stored configuration, if any.
+fn get_config() -> Result<Option<JournalContextConfig>, String> {
+    match settings::get_setting(CONFIG_SETTING)? {
+        Some(config) => serde_json::from_str(&config).map(Some).map_err(|e| format!("Invalid journal context settings: {}", e)),
+        None => Ok(None),
+    }
+}
+
+
+/// Fetches the current weather at a location, as its description and temperature, e.g. "light rain, 12 °C".
+fn fetch_weather(latitude: f64, longitude: f64, temperature_unit: TemperatureUnit) -> Result<String, String> {
+    let api_key = settings::get_secret_setting(API_KEY_SETTING)?.ok_or("The OpenWeatherMap API key is not set".to_string())?;
+    let (units, symbol) = match temperature_unit {
+        TemperatureUnit::Celsius => ("metric", "°C"),
+        TemperatureUnit::Fahrenheit => ("imperial", "°F"),
+    };
+
+    let client = proxy::blocking_client_builder()?.timeout(Duration::from_secs(WEATHER_TIMEOUT_SECONDS)).build().map_err(|e| e.to_string())?;
+    let response: Value = client.get(WEATHER_API)
+        .query(&[("lat", latitude.to_string()), ("lon", longitude.to_string()), ("units", units.to_string()), ("appid", api_key)])
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        // The error would show the address, which holds the API key
+        .map_err(|e| format!("Failed to reach OpenWeatherMap: {}", e.without_url()))?;
+
+    let description = response["weather"][0]["description"].as_str().ok_or("No weather in the OpenWeatherMap response".to_string())?;
+    match response["main"]["temp"].as_f64() {
+        Some(temperature) => Ok(format!("{}, {:.0} {}", description, temperature, symbol)),
+        None => Ok(description.to_string()),
+    }
+}
+
+
+/// Computes the sunrise and sunset of a day at a location with the sunrise equation.
+fn sun_times(date: NaiveDate, latitude: f64, longitude: f64) -> SunTimes {
+    let noon = date.and_hms_opt(12, 0, 0).unwrap().and_utc().timestamp() as f64;
+    let day = (noon / 86400.0 + UNIX_EPOCH_JULIAN_DATE - J2000 + 0.0008).round();
+
+    // The mean solar noon, the position of the sun on its orbit, and its ecliptic longitude
+    let mean_noon = day - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0).to_radians();
+    let center = 1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+    let transit = J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    // The hour angle of the sun when it crosses the horizon
+    let declination = (ecliptic_longitude.sin() * EARTH_AXIAL_TILT.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = (SUN_ALTITUDE_AT_HORIZON.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if cos_hour_angle > 1.0 {
+        return SunTimes::PolarNight;
+    }
+    if cos_hour_angle < -1.0 {
+        return SunTimes::MidnightSun;
+    }
+
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+    let timestamp = |julian_date: f64| ((julian_date - UNIX_EPOCH_JULIAN_DATE) * 86400.0).round() as i64;
+    SunTimes::Times(timestamp(transit - half_day), timestamp(transit + half_day))
+}
+
+
+/// Formats a Unix timestamp as a time of day in the local time zone, e.g. "07:42".
+fn local_time(timestamp: i64) -> String {
+    Local.timestamp_opt(timestamp, 0).earliest()
+        .map(|time| time.format("%H:%M").to_string())
+        .unwrap_or_default()
+}
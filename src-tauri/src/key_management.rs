@@ -0,0 +1,998 @@
+// key_management.rs
+
+use crate::{ app_paths, crypto, local_operations, reencryption, search_index, vault, vault_lock, local_operations::CONNECTION, models::AppError, models::Cipher };
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose};
+use ring::aead::{LessSafeKey, UnboundKey, AES_256_GCM, CHACHA20_POLY1305};
+use ring::hkdf;
+use ring::rand::{SecureRandom, SystemRandom};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+
+/// The version of the key the content is encrypted with, recorded with the encrypted data: 0 for the all-zero key
+/// used before the key was managed, 1 for the content key, derived from the master key or from the passphrase.
+pub const KEY_VERSION: i64 = 1;
+
+/// The service under which the master key is stored in the keyring of the operating system.
+const KEYRING_SERVICE: &str = "com.customnotes.rust";
+
+/// The entry of the master key in the keyring.
+const MASTER_KEY_ENTRY: &str = "master-key";
+
+/// The entry keeping the previous master key while another one is imported, so an import interrupted by a crash is
+/// rolled back on the next start.
+const PREVIOUS_MASTER_KEY_ENTRY: &str = "previous-master-key";
+
+/// The length of the master key, in bytes.
+const MASTER_KEY_LENGTH: usize = 32;
+
+/// The length of the content key, in bytes.
+const CONTENT_KEY_LENGTH: usize = 32;
+
+/// The context the content key is derived from the master key with, so other keys can be derived for other uses.
+const CONTENT_KEY_INFO: &[u8] = b"customnotes content key v1";
+
+/// The settings key under which the version of the key of the local database is stored.
+const KEY_VERSION_SETTING: &str = "encryption_key_version";
+
+/// The settings key under which a known text encrypted with the content key is stored, to check that the key
+/// matches the database.
+const KEY_CHECK_SETTING: &str = "encryption_key_check";
+
+/// The text encrypted into the key check.
+const KEY_CHECK: &str = "customnotes";
+
+/// The settings key under which the salt of the passphrase is stored, encoded in base64, when the content key is
+/// derived from a passphrase.
+const PASSPHRASE_SALT_SETTING: &str = "encryption_passphrase_salt";
+
+/// The settings key under which the salt of the vault password, encoded in base64, and a known text encrypted with
+/// the key derived from it are stored, when no passphrase is set.
+const VAULT_PASSWORD_SETTING: &str = "vault_password_check";
+
+/// The settings key under which the previous content key is stored, encrypted with the current one, while the
+/// database is encrypted again after a key change (see `reencryption`).
+const PREVIOUS_KEY_SETTING: &str = "encryption_previous_key";
+
+/// The length of the salt of the passphrase, in bytes.
+const SALT_LENGTH: usize = 16;
+
+/// The minimum length of a passphrase, in characters.
+const MIN_PASSPHRASE_LENGTH: usize = 8;
+
+/// The memory cost of Argon2id, in KiB, as recommended by OWASP. The parameters of Argon2id are fixed, since
+/// changing them would change the key of every passphrase.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// The number of passes of Argon2id over its memory.
+const ARGON2_PASSES: u32 = 2;
+
+/// The number of lanes of Argon2id.
+const ARGON2_LANES: u32 = 1;
+
+/// The encrypted columns of the local database, with their table and the column of their nonce.
+pub const ENCRYPTED_COLUMNS: [(&str, &str, &str); 8] = [
+    ("notes", "content", "nonce"),
+    ("note_versions", "content", "nonce"),
+    ("bucket_mirror", "content", "nonce"),
+    ("code_blocks", "code", "nonce"),
+    ("cards", "card", "nonce"),
+    ("attachment_texts", "text", "nonce"),
+    ("operation_journal", "details", "nonce"),
+    ("sso_session", "access_token", "access_token_nonce"),
+];
+
+
+/// The content key, derived from the master key on first use, or from the passphrase when the vault is unlocked.
+static CONTENT_KEY: RwLock<Option<Arc<ContentKey>>> = RwLock::new(None);
+
+/// The previous content key, while the database is encrypted again after a key change.
+static PREVIOUS_KEY: RwLock<Option<Arc<ContentKey>>> = RwLock::new(None);
+
+/// Whether the content key is derived from a passphrase, read when the database is opened.
+static PASSPHRASE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the database was already encrypted with a content key when it was opened, in which case a missing master
+/// key is never generated again.
+static DATABASE_KEYED: AtomicBool = AtomicBool::new(false);
+
+/// Why the content key cannot be used, when the master key was missing or did not match the database when it was
+/// opened. Nothing is encrypted or decrypted while it is set, so no note is written with the wrong key.
+static KEY_ERROR: RwLock<Option<String>> = RwLock::new(None);
+
+
+/// A content key, with its bytes, so it can be stored encrypted with the next key while the database is encrypted
+/// again after a key change. It is used with either cipher (see `crypto::load_cipher`).
+pub struct ContentKey {
+    chacha20_poly1305: LessSafeKey,
+    aes_256_gcm: LessSafeKey,
+    bytes: [u8; CONTENT_KEY_LENGTH],
+}
+
+impl ContentKey {
+    /// Builds a content key from its bytes.
+    pub fn new(bytes: [u8; CONTENT_KEY_LENGTH]) -> Result<Self, String> {
+        let chacha20_poly1305 = UnboundKey::new(&CHACHA20_POLY1305, &bytes).map_err(|_| "Invalid content key")?;
+        let aes_256_gcm = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| "Invalid content key")?;
+        Ok(ContentKey {
+            chacha20_poly1305: LessSafeKey::new(chacha20_poly1305),
+            aes_256_gcm: LessSafeKey::new(aes_256_gcm),
+            bytes,
+        })
+    }
+
+    /// Returns the key for a cipher.
+    pub fn aead(&self, cipher: Cipher) -> &LessSafeKey {
+        match cipher {
+            Cipher::ChaCha20Poly1305 => &self.chacha20_poly1305,
+            Cipher::Aes256Gcm => &self.aes_256_gcm,
+        }
+    }
+}
+
+
+/// Returns the key encrypting the content of the notes, in the local database and in the buckets, and the secrets
+/// of the settings.
+///
+/// # Operation
+///
+/// * The master key is read from the keyring of the operating system: the Keychain on macOS, the Credential
+///   Manager on Windows, or the Secret Service on Linux. Where there is no keyring, e.g. on Linux without a
+///   Secret Service, it is read from a file only the user can read in the configuration directory.
+/// * On first launch, a random master key is generated and stored there. Once the database is encrypted with a key,
+///   a missing master key is an error rather than a reason to generate another one.
+/// * The content key is derived from the master key with HKDF-SHA256, and kept for the session.
+/// * When a passphrase is set, there is no master key: the content key is derived from the passphrase when the vault
+///   is unlocked, and forgotten when it is locked (see `set_passphrase`).
+///
+/// # Errors
+///
+/// This function will return an error if a passphrase is set and the vault is locked, if the master key is missing or
+/// did not match the database when it was opened, if the keyring cannot be read or written, or if the stored master
+/// key is malformed.
+pub fn content_key() -> Result<Arc<ContentKey>, AppError> {
+    if let Some(key_error) = KEY_ERROR.read().unwrap().as_ref() {
+        return Err(AppError::Encryption(key_error.clone()));
+    }
+    if let Some(key) = CONTENT_KEY.read().unwrap().as_ref() {
+        return Ok(key.clone());
+    }
+    if has_passphrase() {
+        return Err(AppError::Encryption("The notes are encrypted with a passphrase, unlock the vault to decrypt them".to_string()));
+    }
+
+    // The write lock is held while the master key is generated, so it is generated once
+    let mut cached = CONTENT_KEY.write().unwrap();
+    if let Some(key) = cached.as_ref() {
+        return Ok(key.clone());
+    }
+    let master_key = match read_secret(MASTER_KEY_ENTRY).map_err(AppError::Encryption)? {
+        Some(master_key) => master_key,
+        None if DATABASE_KEYED.load(Ordering::SeqCst) => {
+            return Err(AppError::Encryption("The master key is missing from the keyring, so the notes cannot be decrypted: import it again".to_string()));
+        },
+        None => {
+            let mut master_key = vec![0u8; MASTER_KEY_LENGTH];
+            SystemRandom::new().fill(&mut master_key).map_err(|_| AppError::Encryption("Failed to generate the master key".to_string()))?;
+            write_secret(MASTER_KEY_ENTRY, &master_key).map_err(AppError::Encryption)?;
+            master_key
+        },
+    };
+    let key = Arc::new(derive_content_key(&master_key).map_err(AppError::Encryption)?);
+    *cached = Some(key.clone());
+    Ok(key)
+}
+
+
+/// Returns the previous content key while the database is encrypted again after a key change, so the values not
+/// encrypted again yet can still be decrypted (see `reencryption`).
+///
+/// # Returns
+///
+/// Returns the previous key, or `None` if no key change is in progress or if the vault is locked.
+pub fn previous_key() -> Option<Arc<ContentKey>> {
+    PREVIOUS_KEY.read().unwrap().clone()
+}
+
+
+/// Moves the local database to the managed key when it is opened. It is called while `CONNECTION` is initialized.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Operation
+///
+/// * A database encrypted with the all-zero key of the previous versions is encrypted again with the content key,
+///   in a single transaction, so an interrupted migration is run again on the next start.
+/// * Otherwise, the key is checked against the database. If an import of another key was interrupted, the previous
+///   key is restored (see `import_encryption_key`). If the database was still being encrypted again after a key
+///   change, the previous key is read, so the values not encrypted again yet can be decrypted until it is done.
+/// * When a passphrase is set, the key is only checked once the vault is unlocked (see `unlock_content_key`).
+///
+/// # Errors
+///
+/// This function will return an error if the master key cannot be read, if it does not match the database, or if
+/// there is an issue with the database connection.
+pub fn migrate_legacy_encryption(conn: &Connection) -> Result<(), String> {
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    if read_setting(&tx, KEY_VERSION_SETTING)?.is_some() {
+        drop(tx);
+        if read_setting(conn, PASSPHRASE_SALT_SETTING)?.is_some() {
+            PASSPHRASE_MODE.store(true, Ordering::SeqCst);
+            // A master key left by a passphrase set right before a crash is not needed anymore
+            return delete_secret(MASTER_KEY_ENTRY);
+        }
+        DATABASE_KEYED.store(true, Ordering::SeqCst);
+        check_key(conn)?;
+        return load_previous_key(conn);
+    }
+
+    let key = content_key()?;
+    let count = reencrypt_database(&tx, &crypto::legacy_key(), &key)?;
+    store_key_check(&tx, &key)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![KEY_VERSION_SETTING, KEY_VERSION.to_string()],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    DATABASE_KEYED.store(true, Ordering::SeqCst);
+
+    if count > 0 {
+        eprintln!("Encrypted {} values again with the master key", count);
+    }
+    Ok(())
+}
+
+
+/// Stops using the content key when it could not be set up while `CONNECTION` is initialized, e.g. because the master
+/// key is missing from the keyring or does not match the database.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `error` - Why the key could not be set up.
+///
+/// # Operation
+///
+/// * Nothing is encrypted or decrypted until the right master key is imported (see `import_encryption_key`), so the
+///   notes changed in the meantime are not encrypted with a key the database was not encrypted with.
+/// * The connection is made read-only until then (see `vault_lock::refresh_read_only`).
+/// * The error is reported to the interface (see `get_encryption_key_error`).
+pub fn disable_content_key(conn: &Connection, error: String) {
+    eprintln!("Failed to set up the encryption key, the vault is opened read-only: {}", error);
+    *KEY_ERROR.write().unwrap() = Some(error);
+    *CONTENT_KEY.write().unwrap() = None;
+    if let Err(e) = conn.pragma_update(None, "query_only", true) {
+        eprintln!("Failed to open the vault read-only: {}", e);
+    }
+}
+
+
+/// Returns why the content key cannot be used, if the master key was missing or did not match the database when it
+/// was opened, so the interface can tell the user to import it.
+pub fn get_encryption_key_error() -> Option<String> {
+    KEY_ERROR.read().unwrap().clone()
+}
+
+
+/// Returns the version of the key the local database is encrypted with, 0 before it was migrated.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn key_version(conn: &Connection) -> Result<i64, String> {
+    Ok(read_setting(conn, KEY_VERSION_SETTING)?.and_then(|version| version.parse().ok()).unwrap_or(0))
+}
+
+
+/// Exports the master key, to import it on another device that shares a bucket.
+///
+/// The notes uploaded to a bucket are encrypted with the content key, so another device reads them only with the
+/// same master key. Anyone with the master key can decrypt the notes, so it should be kept like a password.
+///
+/// # Returns
+///
+/// Returns the master key, encoded in base64.
+///
+/// # Errors
+///
+/// This function will return an error if the vault is locked, if a passphrase is set, or if the master key cannot be read.
+pub fn export_encryption_key() -> Result<String, String> {
+    if !vault::is_unlocked() {
+        return Err("Unlock the vault to export the encryption key".to_string());
+    }
+    if has_passphrase() {
+        return Err("The notes are encrypted with a passphrase, there is no encryption key to export".to_string());
+    }
+
+    content_key()?;
+    let master_key = read_secret(MASTER_KEY_ENTRY)?.ok_or("The master key is missing".to_string())?;
+    Ok(general_purpose::STANDARD.encode(master_key))
+}
+
+
+/// Imports the master key of another device, replacing the master key of this one.
+///
+/// # Arguments
+///
+/// * `master_key` - The master key, encoded in base64, as exported by `export_encryption_key`.
+///
+/// # Operation
+///
+/// * The content key of the imported master key is used right away, and the local database is encrypted again with
+///   it in the background (see `reencryption`).
+/// * The previous master key is kept in the keyring until the key change is committed, so an import interrupted by a
+///   crash is rolled back on the next start.
+/// * The notes uploaded to a bucket with the previous key cannot be read anymore until they are uploaded again.
+/// * If the master key was missing or did not match the database when it was opened, the imported key must be the
+///   one the database is encrypted with: it is restored, and the vault can be changed again. The vault does not need
+///   to be unlocked for this, since the key is proof enough.
+///
+/// # Returns
+///
+/// Returns the number of notes to encrypt again.
+///
+/// # Errors
+///
+/// This function will return an error if the vault is locked, if a passphrase is set, if the key is malformed, if
+/// the database is still being encrypted again after the last key change, if the keyring cannot be written, if the
+/// key restored after a startup failure does not match the database, or if there is an issue with the database connection.
+pub fn import_encryption_key(master_key: &str) -> Result<usize, String> {
+    let master_key = decode_master_key(master_key.trim())?;
+    if KEY_ERROR.read().unwrap().is_some() {
+        restore_master_key(&master_key)?;
+        return Ok(0);
+    }
+    if !vault::is_unlocked() {
+        return Err("Unlock the vault to import an encryption key".to_string());
+    }
+    if has_passphrase() {
+        return Err("The notes are encrypted with a passphrase, remove it to import an encryption key".to_string());
+    }
+
+    // The connection is locked before the key, like every other path using both
+    let mut conn = CONNECTION.lock().unwrap();
+    check_no_key_change(&conn)?;
+    let from = content_key()?;
+    let mut cached = CONTENT_KEY.write().unwrap();
+    let previous_master_key = read_secret(MASTER_KEY_ENTRY)?.ok_or("The master key is missing".to_string())?;
+    if previous_master_key == master_key {
+        return Ok(0);
+    }
+    let to = Arc::new(derive_content_key(&master_key)?);
+
+    write_secret(PREVIOUS_MASTER_KEY_ENTRY, &previous_master_key)?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let count = begin_key_change(&tx, &from, &to)?;
+    write_secret(MASTER_KEY_ENTRY, &master_key)?;
+    if let Err(e) = tx.commit() {
+        write_secret(MASTER_KEY_ENTRY, &previous_master_key)?;
+        return Err(e.to_string());
+    }
+    delete_secret(PREVIOUS_MASTER_KEY_ENTRY)?;
+
+    *cached = Some(to);
+    *PREVIOUS_KEY.write().unwrap() = Some(from);
+    reencryption::resume_reencryption();
+    Ok(count)
+}
+
+
+/// Tells whether the content key is derived from a passphrase, in which case the notes can only be decrypted while
+/// the vault is unlocked.
+pub fn has_passphrase() -> bool {
+    PASSPHRASE_MODE.load(Ordering::SeqCst)
+}
+
+
+/// Sets, changes, or removes the passphrase the content key is derived from.
+///
+/// # Arguments
+///
+/// * `passphrase` - The new passphrase, at least 8 characters long, or `None` to go back to a master key in the keyring.
+///
+/// # Operation
+///
+/// * The content key is derived from the passphrase with Argon2id and a new random salt. Only the salt and the key
+///   check are stored, in the "settings" table, so the notes cannot be decrypted from the database and the keyring
+///   alone. The master key is removed from the keyring.
+/// * Without a passphrase, a new master key is generated and stored in the keyring, as on first launch.
+/// * Either way, the new key is used right away, and the local database is encrypted again with it in the background,
+///   one note at a time (see `reencryption`). The previews of the notes are stored unencrypted, so they are dropped
+///   while a passphrase is set, and computed again on the next start once it is removed.
+/// * The local search index is built again, in memory while a passphrase is set and on disk otherwise (see `search_index`).
+/// * The notes uploaded to a bucket with the previous key cannot be read anymore until they are uploaded again.
+///
+/// # Returns
+///
+/// Returns the number of notes to encrypt again.
+///
+/// # Errors
+///
+/// This function will return an error if the vault is locked, if the passphrase is too short, if the database is
+/// still being encrypted again after the last key change, if the keyring cannot be written, or if there is an issue
+/// with the database connection.
+pub fn set_passphrase(passphrase: Option<&str>) -> Result<usize, String> {
+    if !vault::is_unlocked() {
+        return Err("Unlock the vault to set a passphrase".to_string());
+    }
+    if let Some(passphrase) = passphrase {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+            return Err(format!("The passphrase should be at least {} characters long", MIN_PASSPHRASE_LENGTH));
+        }
+    } else if !has_passphrase() {
+        return Ok(0);
+    }
+
+    // The key is derived before the connection is locked, since Argon2id takes a while and every command waits for it
+    let derived = match passphrase {
+        Some(passphrase) => {
+            let mut salt = [0u8; SALT_LENGTH];
+            SystemRandom::new().fill(&mut salt).map_err(|_| "Failed to generate the salt")?;
+            Some((salt, Arc::new(derive_passphrase_key(passphrase, &salt)?)))
+        },
+        None => None,
+    };
+
+    // The connection is locked before the key, like every other path using both
+    let mut conn = CONNECTION.lock().unwrap();
+    check_no_key_change(&conn)?;
+    let from = content_key()?;
+    let mut cached = CONTENT_KEY.write().unwrap();
+
+    let count = match derived {
+        Some((salt, to)) => {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let count = begin_key_change(&tx, &from, &to)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![PASSPHRASE_SALT_SETTING, general_purpose::STANDARD.encode(salt)],
+            ).map_err(|e| e.to_string())?;
+            tx.execute("UPDATE notes SET preview = NULL", []).map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+
+            PASSPHRASE_MODE.store(true, Ordering::SeqCst);
+            *cached = Some(to);
+            delete_secret(MASTER_KEY_ENTRY)?;
+            count
+        },
+        None => {
+            let mut master_key = vec![0u8; MASTER_KEY_LENGTH];
+            SystemRandom::new().fill(&mut master_key).map_err(|_| "Failed to generate the master key")?;
+            let to = Arc::new(derive_content_key(&master_key)?);
+
+            // The master key is ignored until the transaction is committed, since the salt is still stored
+            write_secret(MASTER_KEY_ENTRY, &master_key)?;
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let count = begin_key_change(&tx, &from, &to)?;
+            tx.execute("DELETE FROM settings WHERE key = ?1", params![PASSPHRASE_SALT_SETTING]).map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+
+            PASSPHRASE_MODE.store(false, Ordering::SeqCst);
+            *cached = Some(to);
+            count
+        },
+    };
+    *PREVIOUS_KEY.write().unwrap() = Some(from);
+    reencryption::resume_reencryption();
+    search_index::schedule_rebuild();
+    Ok(count)
+}
+
+
+/// Checks the credential the vault is unlocked with, and derives the content key from the passphrase when one is
+/// set. It is called by `vault::unlock_vault`.
+///
+/// # Arguments
+///
+/// * `passphrase` - The passphrase when one is set, or else the vault password (see `set_vault_password`).
+///
+/// # Operation
+///
+/// * Without a passphrase, the vault password is checked, and nothing else is needed since the content key comes
+///   from the keyring. The vault cannot be unlocked before a vault password is set.
+/// * With a passphrase, the key is checked against the key check of the database, and kept until the vault is locked.
+/// * If the database was being encrypted again after a key change, the previous key is read and the encryption
+///   resumes (see `reencryption`).
+/// * The data derived from the notes that could not be derived while they could not be decrypted is derived, and the
+///   search index is rebuilt with the notes.
+///
+/// # Errors
+///
+/// This function will return an error if the passphrase or the vault password is missing or wrong, if no vault
+/// password is set while there is no passphrase, or if there is an issue with the database connection.
+pub fn unlock_content_key(passphrase: Option<&str>) -> Result<(), String> {
+    // The key is derived without holding the connection, since Argon2id takes a while and every command waits for it
+    if !has_passphrase() {
+        let password_check = read_setting(&CONNECTION.lock().unwrap(), VAULT_PASSWORD_SETTING)?
+            .ok_or("Set a vault password to unlock the secure notes".to_string())?;
+        let password = passphrase.ok_or("The vault is protected by a password".to_string())?;
+        if !check_vault_password(&password_check, password)? {
+            return Err("Wrong password".to_string());
+        }
+        return Ok(());
+    }
+    let passphrase = passphrase.ok_or("The vault is protected by a passphrase".to_string())?;
+
+    let (salt, key_check) = {
+        let conn = CONNECTION.lock().unwrap();
+        let salt = read_setting(&conn, PASSPHRASE_SALT_SETTING)?.ok_or("The salt of the passphrase is missing".to_string())?;
+        let key_check = read_setting(&conn, KEY_CHECK_SETTING)?.ok_or("The key check is missing".to_string())?;
+        (salt, key_check)
+    };
+    let salt = general_purpose::STANDARD.decode(salt).map_err(|_| "The salt of the passphrase is not valid base64".to_string())?;
+    let key = derive_passphrase_key(passphrase, &salt)?;
+
+    let (encrypted_check, nonce_str) = key_check.split_once(':').ok_or("Invalid key check".to_string())?;
+    if crypto::decrypt_content_with(&key, encrypted_check, nonce_str).is_err() {
+        return Err("Wrong passphrase".to_string());
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    let was_locked = CONTENT_KEY.write().unwrap().replace(Arc::new(key)).is_none();
+    if was_locked {
+        load_previous_key(&conn)?;
+        reencryption::resume_reencryption();
+        local_operations::backfill_notes(&conn).map_err(|e| e.to_string())?;
+        search_index::schedule_rebuild();
+    }
+    Ok(())
+}
+
+
+/// Tells whether a vault password is set, which unlocks the vault when the notes are not encrypted with a passphrase.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn has_vault_password() -> Result<bool, String> {
+    let conn = CONNECTION.lock().unwrap();
+    Ok(read_setting(&conn, VAULT_PASSWORD_SETTING)?.is_some())
+}
+
+
+/// Sets or changes the password the vault is unlocked with when the notes are not encrypted with a passphrase.
+///
+/// # Arguments
+///
+/// * `current_password` - The current vault password, required to change it.
+/// * `password` - The new vault password, at least 8 characters long.
+///
+/// # Operation
+///
+/// * Only a salt and a known text encrypted with the key derived from the password with Argon2id are stored, in the
+///   "settings" table, so the password cannot be read from the database.
+/// * The first vault password can be set without one, since until then the vault cannot be unlocked at all.
+///
+/// # Errors
+///
+/// This function will return an error if a passphrase is set, since it unlocks the vault, if the new password is too
+/// short, if the current password is missing or wrong, or if there is an issue with the database connection.
+pub fn set_vault_password(current_password: Option<&str>, password: &str) -> Result<(), String> {
+    if has_passphrase() {
+        return Err("The vault is unlocked with the passphrase the notes are encrypted with".to_string());
+    }
+    if password.chars().count() < MIN_PASSPHRASE_LENGTH {
+        return Err(format!("The password should be at least {} characters long", MIN_PASSPHRASE_LENGTH));
+    }
+
+    // The keys are derived without holding the connection, since Argon2id takes a while and every command waits for it
+    let password_check = read_setting(&CONNECTION.lock().unwrap(), VAULT_PASSWORD_SETTING)?;
+    if let Some(password_check) = &password_check {
+        let current_password = current_password.ok_or("Enter the current password to change it".to_string())?;
+        if !check_vault_password(password_check, current_password)? {
+            return Err("Wrong password".to_string());
+        }
+    }
+
+    let mut salt = [0u8; SALT_LENGTH];
+    SystemRandom::new().fill(&mut salt).map_err(|_| "Failed to generate the salt")?;
+    let key = derive_passphrase_key(password, &salt)?;
+    let (encrypted_check, nonce_str) = crypto::encrypt_content_with(&key, KEY_CHECK)?;
+
+    // The password is only replaced if it did not change while the keys were derived
+    let conn = CONNECTION.lock().unwrap();
+    if read_setting(&conn, VAULT_PASSWORD_SETTING)? != password_check {
+        return Err("The vault password was changed meanwhile, try again".to_string());
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![VAULT_PASSWORD_SETTING, format!("{}:{}:{}", general_purpose::STANDARD.encode(salt), encrypted_check, nonce_str)],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Forgets the content key derived from the passphrase when the vault is locked, so the notes cannot be decrypted
+/// until it is unlocked again. It is called by `vault::lock_vault`.
+pub fn forget_content_key() {
+    if has_passphrase() {
+        *CONTENT_KEY.write().unwrap() = None;
+        *PREVIOUS_KEY.write().unwrap() = None;
+    }
+}
+
+
+/// Encrypts again every encrypted value of the local database with another key.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction.
+/// * `from` - The key the values are encrypted with.
+/// * `to` - The key to encrypt them with.
+///
+/// # Operation
+///
+/// * The encrypted columns of the tables are encrypted again. Tables that do not exist yet are skipped.
+/// * The secrets of the settings (see `settings::set_secret_setting`) are found by decrypting them with the old key,
+///   which only succeeds for an encrypted value, and encrypted again.
+/// * Values that cannot be decrypted with the old key, e.g. if they are corrupt, are left as they are.
+///
+/// # Returns
+///
+/// Returns the number of values encrypted again.
+///
+/// # Errors
+///
+/// This function will return an error if a value cannot be encrypted or if there is an issue with the database connection.
+pub fn reencrypt_database(conn: &Connection, from: &ContentKey, to: &ContentKey) -> Result<usize, String> {
+    let mut count = 0;
+    for (table, column, nonce_column) in ENCRYPTED_COLUMNS {
+        let table_exists: Option<i64> = conn.query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![table],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+        if table_exists.is_none() {
+            continue;
+        }
+
+        let rows = {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT rowid, {column}, {nonce_column} FROM {table} WHERE {column} IS NOT NULL AND {nonce_column} IS NOT NULL"
+            )).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+                .map_err(|e| e.to_string())?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            rows
+        };
+        for (rowid, encrypted_value, nonce_str) in rows {
+            match reencrypt(&encrypted_value, &nonce_str, from, to) {
+                Ok((encrypted_value, nonce_str)) => {
+                    conn.execute(
+                        &format!("UPDATE {table} SET {column} = ?1, {nonce_column} = ?2 WHERE rowid = ?3"),
+                        params![encrypted_value, nonce_str, rowid],
+                    ).map_err(|e| e.to_string())?;
+                    count += 1;
+                },
+                Err(e) => eprintln!("Failed to encrypt {} {} again: {}", table, rowid, e),
+            }
+        }
+    }
+
+    Ok(count + reencrypt_secrets(conn, from, to)?)
+}
+
+
+/// Tells whether the local database is being encrypted again after a key change, which is done once the previous
+/// key is forgotten (see `reencryption`).
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn is_key_change_pending(conn: &Connection) -> Result<bool, String> {
+    Ok(read_setting(conn, PREVIOUS_KEY_SETTING)?.is_some())
+}
+
+
+/// Ends a key change once every value of the local database is encrypted with the current key, by removing the
+/// previous key from the "settings" table. It should be followed by `forget_previous_key` once the transaction is committed.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn end_key_change(conn: &Connection) -> Result<(), String> {
+    conn.execute("DELETE FROM settings WHERE key = ?1", params![PREVIOUS_KEY_SETTING]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Forgets the previous content key once a key change is over.
+pub fn forget_previous_key() {
+    *PREVIOUS_KEY.write().unwrap() = None;
+}
+
+
+/// Starts a key change in a transaction: the secrets of the settings are encrypted again right away, the key check
+/// is replaced, the previous key is stored encrypted with the new one, and the notes are queued to be encrypted
+/// again in the background. Returns the number of queued notes.
+fn begin_key_change(conn: &Connection, from: &ContentKey, to: &ContentKey) -> Result<usize, String> {
+    reencrypt_secrets(conn, from, to)?;
+    store_key_check(conn, to)?;
+    let (encrypted_key, nonce_str) = crypto::encrypt_content_with(to, &general_purpose::STANDARD.encode(from.bytes))?;
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![PREVIOUS_KEY_SETTING, format!("{}:{}", encrypted_key, nonce_str)],
+    ).map_err(|e| e.to_string())?;
+
+    reencryption::queue_notes(conn)
+}
+
+
+/// Refuses another key change while the database is still being encrypted again after the last one, since the
+/// values would then be encrypted with three keys.
+fn check_no_key_change(conn: &Connection) -> Result<(), String> {
+    if is_key_change_pending(conn)? {
+        return Err("The notes are still being encrypted again after the last key change, try again once it is done".to_string());
+    }
+    Ok(())
+}
+
+
+/// Reads the previous content key while the database is being encrypted again after a key change. It needs the
+/// current content key, which it is stored encrypted with.
+fn load_previous_key(conn: &Connection) -> Result<(), String> {
+    let Some(previous_key) = read_setting(conn, PREVIOUS_KEY_SETTING)? else {
+        return Ok(());
+    };
+    let (encrypted_key, nonce_str) = previous_key.split_once(':').ok_or("Invalid previous key".to_string())?;
+    let encoded = crypto::decrypt_content_with(&*content_key()?, encrypted_key, nonce_str)?;
+    let bytes: [u8; CONTENT_KEY_LENGTH] = general_purpose::STANDARD.decode(encoded).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Invalid previous key".to_string())?;
+
+    *PREVIOUS_KEY.write().unwrap() = Some(Arc::new(ContentKey::new(bytes)?));
+    Ok(())
+}
+
+
+/// Encrypts again the secrets of the settings (see `settings::set_secret_setting`), found by decrypting them with
+/// the old key, which only succeeds for an encrypted value. Returns the number of secrets encrypted again.
+fn reencrypt_secrets(conn: &Connection, from: &ContentKey, to: &ContentKey) -> Result<usize, String> {
+    let mut count = 0;
+    let settings = {
+        let mut stmt = conn.prepare("SELECT key, value FROM settings WHERE key NOT IN (?1, ?2)").map_err(|e| e.to_string())?;
+        let settings = stmt.query_map(params![KEY_CHECK_SETTING, PREVIOUS_KEY_SETTING], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        settings
+    };
+    for (key, value) in settings {
+        let Some((encrypted_secret, nonce_str)) = value.split_once(':') else {
+            continue;
+        };
+        if let Ok((encrypted_secret, nonce_str)) = reencrypt(encrypted_secret, nonce_str, from, to) {
+            conn.execute(
+                "UPDATE settings SET value = ?1 WHERE key = ?2",
+                params![format!("{}:{}", encrypted_secret, nonce_str), key],
+            ).map_err(|e| e.to_string())?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+
+/// Checks that the content key decrypts the key check of the database, restoring the previous master key if an
+/// import was interrupted.
+fn check_key(conn: &Connection) -> Result<(), String> {
+    let Some(key_check) = read_setting(conn, KEY_CHECK_SETTING)? else {
+        return Ok(());
+    };
+    let (encrypted_check, nonce_str) = key_check.split_once(':').ok_or("Invalid key check".to_string())?;
+
+    let key = content_key()?;
+    if crypto::decrypt_content_with(&key, encrypted_check, nonce_str).is_ok() {
+        return delete_secret(PREVIOUS_MASTER_KEY_ENTRY);
+    }
+    if let Some(previous_master_key) = read_secret(PREVIOUS_MASTER_KEY_ENTRY)? {
+        let previous_key = derive_content_key(&previous_master_key)?;
+        if crypto::decrypt_content_with(&previous_key, encrypted_check, nonce_str).is_ok() {
+            write_secret(MASTER_KEY_ENTRY, &previous_master_key)?;
+            delete_secret(PREVIOUS_MASTER_KEY_ENTRY)?;
+            *CONTENT_KEY.write().unwrap() = Some(Arc::new(previous_key));
+            eprintln!("The import of an encryption key was interrupted, the previous key was restored");
+            return Ok(());
+        }
+    }
+
+    Err("The master key does not match the database, the notes cannot be decrypted".to_string())
+}
+
+
+/// Tells whether a password matches the stored check of the vault password: its salt, and the known text encrypted
+/// with the key derived from the password.
+fn check_vault_password(password_check: &str, password: &str) -> Result<bool, String> {
+    let (salt, key_check) = password_check.split_once(':').ok_or("Invalid vault password".to_string())?;
+    let (encrypted_check, nonce_str) = key_check.split_once(':').ok_or("Invalid vault password".to_string())?;
+    let salt = general_purpose::STANDARD.decode(salt).map_err(|_| "The salt of the vault password is not valid base64".to_string())?;
+    let key = derive_passphrase_key(password, &salt)?;
+    Ok(crypto::decrypt_content_with(&key, encrypted_check, nonce_str).is_ok())
+}
+
+
+/// Restores the master key the database is encrypted with, after it was missing or did not match the database when
+/// it was opened, then derives the data that could not be derived without it.
+fn restore_master_key(master_key: &[u8]) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let key = derive_content_key(master_key)?;
+    let key_check = read_setting(&conn, KEY_CHECK_SETTING)?.ok_or("The key check is missing".to_string())?;
+    let (encrypted_check, nonce_str) = key_check.split_once(':').ok_or("Invalid key check".to_string())?;
+    if crypto::decrypt_content_with(&key, encrypted_check, nonce_str).is_err() {
+        return Err("This master key does not match the database either, the notes cannot be decrypted".to_string());
+    }
+
+    write_secret(MASTER_KEY_ENTRY, master_key)?;
+    delete_secret(PREVIOUS_MASTER_KEY_ENTRY)?;
+    *CONTENT_KEY.write().unwrap() = Some(Arc::new(key));
+    *KEY_ERROR.write().unwrap() = None;
+    vault_lock::refresh_read_only(&conn)?;
+
+    load_previous_key(&conn)?;
+    reencryption::resume_reencryption();
+    local_operations::backfill_notes(&conn).map_err(|e| e.to_string())?;
+    search_index::schedule_rebuild();
+    Ok(())
+}
+
+
+/// Stores the key check of a content key in the "settings" table.
+fn store_key_check(conn: &Connection, key: &ContentKey) -> Result<(), String> {
+    let (encrypted_check, nonce_str) = crypto::encrypt_content_with(key, KEY_CHECK)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![KEY_CHECK_SETTING, format!("{}:{}", encrypted_check, nonce_str)],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Decrypts a value with a key and encrypts it with another, with a new nonce.
+fn reencrypt(encrypted_value: &str, nonce_str: &str, from: &ContentKey, to: &ContentKey) -> Result<(String, String), String> {
+    crypto::encrypt_content_with(to, &crypto::decrypt_content_with(from, encrypted_value, nonce_str)?)
+}
+
+
+/// Reads a setting with an explicit connection, since the settings are read while `CONNECTION` is initialized.
+fn read_setting(conn: &Connection, key: &str) -> Result<Option<String>, String> {
+    conn.query_row("SELECT value FROM settings WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())
+}
+
+
+/// Derives the content key from the master key with HKDF-SHA256.
+fn derive_content_key(master_key: &[u8]) -> Result<ContentKey, String> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(master_key);
+    let okm = prk.expand(&[CONTENT_KEY_INFO], &CHACHA20_POLY1305).map_err(|_| "Failed to derive the content key")?;
+    let mut bytes = [0u8; CONTENT_KEY_LENGTH];
+    okm.fill(&mut bytes).map_err(|_| "Failed to derive the content key")?;
+    ContentKey::new(bytes)
+}
+
+
+/// Derives the content key from a passphrase and its salt with Argon2id.
+fn derive_passphrase_key(passphrase: &str, salt: &[u8]) -> Result<ContentKey, String> {
+    let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_PASSES, ARGON2_LANES, Some(CONTENT_KEY_LENGTH))
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+    let mut key = [0u8; CONTENT_KEY_LENGTH];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive the key from the passphrase: {}", e))?;
+    ContentKey::new(key)
+}
+
+
+/// Decodes a master key encoded in base64, checking its length.
+fn decode_master_key(master_key: &str) -> Result<Vec<u8>, String> {
+    let master_key = general_purpose::STANDARD.decode(master_key).map_err(|_| "The master key is not valid base64".to_string())?;
+    if master_key.len() != MASTER_KEY_LENGTH {
+        return Err(format!("The master key should be {} bytes long", MASTER_KEY_LENGTH));
+    }
+    Ok(master_key)
+}
+
+
+/// Reads a key from the keyring, or from its file where there is no keyring.
+fn read_secret(entry: &str) -> Result<Option<Vec<u8>>, String> {
+    match keyring_entry(entry)?.get_password() {
+        Ok(master_key) => return decode_master_key(&master_key).map(Some),
+        Err(keyring::Error::NoEntry) => {},
+        Err(e) if is_unavailable(&e) => {},
+        Err(e) => return Err(format!("Failed to read the {} from the keyring: {}", entry, e)),
+    }
+
+    // The key is in a file if the keyring was not available when it was stored
+    let path = key_file(entry)?;
+    match std::fs::read_to_string(&path) {
+        Ok(master_key) => decode_master_key(master_key.trim()).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+
+/// Stores a key in the keyring, or in a file only the user can read where there is no keyring.
+fn write_secret(entry: &str, master_key: &[u8]) -> Result<(), String> {
+    let encoded = general_purpose::STANDARD.encode(master_key);
+    let path = key_file(entry)?;
+    match keyring_entry(entry)?.set_password(&encoded) {
+        Ok(()) => {
+            // A key stored in a file while the keyring was not available is moved into it
+            if path.exists() {
+                std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+            }
+            Ok(())
+        },
+        Err(e) if is_unavailable(&e) => {
+            eprintln!("The keyring is not available ({}), the {} is stored in {}", e, entry, path.display());
+            write_key_file(&path, &encoded)
+        },
+        Err(e) => Err(format!("Failed to store the {} in the keyring: {}", entry, e)),
+    }
+}
+
+
+/// Removes a key from the keyring and from its file.
+fn delete_secret(entry: &str) -> Result<(), String> {
+    match keyring_entry(entry)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {},
+        Err(e) if is_unavailable(&e) => {},
+        Err(e) => return Err(format!("Failed to remove the {} from the keyring: {}", entry, e)),
+    }
+
+    let path = key_file(entry)?;
+    if path.exists() {
+        std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+
+/// Returns the entry of a key in the keyring.
+fn keyring_entry(entry: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, entry).map_err(|e| format!("Failed to open the keyring: {}", e))
+}
+
+
+/// Tells whether an error of the keyring means that there is no keyring to store the key in.
+fn is_unavailable(error: &keyring::Error) -> bool {
+    matches!(error, keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_))
+}
+
+
+/// Returns the file a key is stored in where there is no keyring, in the configuration directory.
+fn key_file(entry: &str) -> Result<PathBuf, String> {
+    Ok(app_paths::config_dir()?.join(format!("{}.key", entry)))
+}
+
+
+/// Writes a key file that only the user can read.
+fn write_key_file(path: &PathBuf, encoded: &str) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, encoded.as_bytes()).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
@@ -0,0 +1,516 @@
+// link_operations.rs
+
+use crate::{ alias_operations, attachment_operations, delta, local_operations, preview, proxy, search_index, vault, local_operations::CONNECTION, models::AppError, models::Backlink, models::BrokenReference, models::Note, models::NoteLinkReport, models::ReferenceKind, models::UnlinkedMention };
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use rusqlite::{params, Connection};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+use std::time::Duration;
+use tantivy::collector::DocSetCollector;
+use tantivy::query::QueryParser;
+use tantivy::schema::OwnedValue;
+use tantivy::TantivyDocument;
+
+
+/// The minimum length of a title or alias looked for by `find_unlinked_mentions`, so short names such as "AI" do not
+/// match in every note.
+const MIN_MENTION_LENGTH: usize = 3;
+
+/// The number of characters of the line kept on each side of a mention to show it in context.
+const CONTEXT_LENGTH: usize = 60;
+
+/// The character standing for an embed, such as an image, in the text of a note, which counts as one in the editor.
+const EMBED: char = '\u{fffc}';
+
+/// The number of seconds to wait for a web server when checking an external link.
+const URL_CHECK_TIMEOUT_SECONDS: u64 = 10;
+
+
+/// The ID, the name, and the stored file of an attachment.
+type AttachmentEntry = (i64, String, String);
+
+
+lazy_static! {
+    /// Matches a wikilink, e.g. `[[Meeting notes]]` or `[[Meeting notes#Actions|the meeting]]`.
+    static ref WIKILINK: Regex = Regex::new(r"\[\[([^\[\]\n]+)\]\]").unwrap();
+
+    /// Matches an external link written in the text of a note, e.g. `https://example.com/page`.
+    static ref URL: Regex = Regex::new(r#"https?://[^\s<>"'\[\]()]+"#).unwrap();
+}
+
+
+/// Records the wikilinks of a note in the "note_links" table, replacing the previous ones.
+///
+/// The links are recorded by the name of their target, so they point to whichever note has the name when they are
+/// followed, e.g. after a note was renamed or given an alias.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `content` - The plaintext content of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn index_links(conn: &Connection, note_id: i64, content: &str) -> Result<(), String> {
+    forget_links(conn, note_id)?;
+    let targets: BTreeSet<String> = WIKILINK.captures_iter(&preview::plain_text(content))
+        .map(|captures| link_target(&captures[1]).to_lowercase())
+        .filter(|target| !target.is_empty())
+        .collect();
+    for target in targets {
+        conn.execute(
+            "INSERT INTO note_links (note_id, target) VALUES (?1, ?2)",
+            params![note_id, target],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+
+/// Removes the wikilinks of a deleted note from the "note_links" table.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_links(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM note_links WHERE note_id = ?1",
+        params![note_id],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Returns the name of the note a wikilink points to, without the heading or the label after "#" or "|".
+///
+/// # Arguments
+///
+/// * `link` - The text between the brackets of the wikilink, e.g. "Meeting notes#Actions|the meeting".
+pub fn link_target(link: &str) -> &str {
+    link.split(['#', '|']).next().unwrap_or_default().trim()
+}
+
+
+/// Retrieves the local notes with a wikilink to a note, by its title or one of its aliases.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns the linking notes, sorted by title. A link to a name shared by several notes only counts for the note it
+/// opens (see `alias_operations::resolve_wikilink`).
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn get_backlinks(note_id: i64) -> Result<Vec<Backlink>, AppError> {
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+
+    let conn = CONNECTION.lock().unwrap();
+    let mut targets = Vec::new();
+    for name in names(&note, 1) {
+        if alias_operations::resolve_name(&conn, &name)? == Some(note_id) {
+            targets.push(name.to_lowercase());
+        }
+    }
+    if targets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; targets.len()].join(", ");
+    let mut params: Vec<&dyn rusqlite::ToSql> = targets.iter().map(|target| target as &dyn rusqlite::ToSql).collect();
+    params.push(&note_id);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT notes.id, notes.title, notes.icon FROM note_links JOIN notes ON notes.id = note_links.note_id
+        WHERE note_links.target IN ({}) AND notes.id != ? ORDER BY notes.title COLLATE NOCASE",
+        placeholders
+    ))?;
+    let backlink_iter = stmt.query_map(params.as_slice(), |row| {
+        Ok(Backlink {
+            note_id: row.get(0)?,
+            title: row.get(1)?,
+            icon: row.get(2)?,
+        })
+    })?;
+
+    Ok(backlink_iter.collect::<Result<Vec<_>, _>>()?)
+}
+
+
+/// Finds the mentions of a note in the other local notes that are not wikilinks yet, so the user can link them.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Operation
+///
+/// * The title and the aliases of the note are looked for, as whole words and whatever their case. Names shorter than
+///   3 characters are left out.
+/// * The notes that may mention them are found with the search index first, so only those are read. Notes changed in
+///   the last seconds may be missed until the index is updated.
+/// * Mentions inside a wikilink, to this note or any other, are skipped. Where names overlap, the longest counts.
+/// * Secure notes are only searched while the vault is unlocked.
+///
+/// # Returns
+///
+/// Returns the mentions, grouped by note, with their position in the editor so the UI can turn them into a wikilink,
+/// their line, and the text around them.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if the search index cannot be built, or if there
+/// is an issue with the database connection.
+pub fn find_unlinked_mentions(note_id: i64) -> Result<Vec<UnlinkedMention>, AppError> {
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    let names = names(&note, MIN_MENTION_LENGTH);
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut candidates = mentioning_notes(&names)?;
+    candidates.remove(&note_id);
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; candidates.len()].join(", ");
+    let params: Vec<&dyn rusqlite::ToSql> = candidates.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let mut condition = format!("id IN ({})", placeholders);
+    if !vault::is_unlocked() {
+        condition.push_str(" AND secure = 0");
+    }
+    let mut notes = local_operations::get_local_notes_where(&condition, &params)?;
+    notes.sort_by_key(|note| note.id);
+
+    let names: Vec<Vec<char>> = names.iter().map(|name| name.chars().collect()).collect();
+    Ok(notes.iter().flat_map(|note| note_mentions(note, &names)).collect())
+}
+
+
+/// Checks the references of the local notes, so the broken ones can be repaired.
+///
+/// # Arguments
+///
+/// * `check_urls` - Whether to request the external links, which reaches every web server they point to, rather than
+///   only checking that they are valid URLs.
+///
+/// # Operation
+///
+/// * A wikilink is broken if no note has its target as title or alias (see `alias_operations::resolve_wikilink`).
+/// * An attachment is broken if its file is missing from the attachments directory, and the cover of a note if it is
+///   not one of the attachments of the note.
+/// * An external link, written in the text or set on it in the editor, or an image, is broken if it is not a valid
+///   URL or, when requested, if its server does not answer or answers with an error. Each URL is requested once,
+///   through the proxy if one is enabled.
+/// * Secure notes are skipped while the vault is locked.
+///
+/// It blocks until the external links are checked, so it must not be called from the async runtime directly.
+///
+/// # Returns
+///
+/// Returns the notes with broken references, sorted by title, each with its broken references in the order they
+/// were found.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be decrypted, if the attachments directory cannot be
+/// read, or if there is an issue with the database connection.
+pub fn check_links(check_urls: bool) -> Result<Vec<NoteLinkReport>, String> {
+    let condition = if vault::is_unlocked() { "1 = 1" } else { "secure = 0" };
+    let mut notes = local_operations::get_local_notes_where(condition, &[])?;
+    notes.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()).then(a.id.cmp(&b.id)));
+
+    let mut broken: BTreeMap<i64, Vec<BrokenReference>> = BTreeMap::new();
+    let mut urls: Vec<(i64, String)> = Vec::new();
+    {
+        let conn = CONNECTION.lock().unwrap();
+        let attachments = note_attachments(&conn)?;
+        let dir = attachment_operations::attachments_dir()?;
+
+        for note in &notes {
+            let note_id = note.id.unwrap_or(0);
+            let references = broken.entry(note_id).or_default();
+
+            let targets: BTreeSet<String> = WIKILINK.captures_iter(&preview::plain_text(&note.content))
+                .map(|captures| link_target(&captures[1]).to_string())
+                .filter(|target| !target.is_empty())
+                .collect();
+            for target in targets {
+                if alias_operations::resolve_name(&conn, &target)?.is_none() {
+                    references.push(BrokenReference {
+                        kind: ReferenceKind::Wikilink,
+                        reference: target,
+                        reason: "No note has this title or alias".to_string(),
+                    });
+                }
+            }
+
+            let own_attachments = attachments.get(&note_id).map(Vec::as_slice).unwrap_or_default();
+            for (_, file_name, stored_name) in own_attachments {
+                if !dir.join(stored_name).is_file() {
+                    references.push(BrokenReference {
+                        kind: ReferenceKind::Attachment,
+                        reference: file_name.clone(),
+                        reason: "The file of the attachment is missing".to_string(),
+                    });
+                }
+            }
+            if let Some(cover_id) = note.cover_attachment_id {
+                if !own_attachments.iter().any(|(id, _, _)| *id == cover_id) {
+                    references.push(BrokenReference {
+                        kind: ReferenceKind::Attachment,
+                        reference: format!("Cover attachment {}", cover_id),
+                        reason: "The cover is not an attachment of the note".to_string(),
+                    });
+                }
+            }
+
+            urls.extend(external_links(&note.content).into_iter().map(|url| (note_id, url)));
+        }
+    }
+
+    // The web servers are requested without holding the database connection
+    let client = match check_urls {
+        true => Some(proxy::blocking_client_builder()?
+            .timeout(Duration::from_secs(URL_CHECK_TIMEOUT_SECONDS))
+            .build()
+            .map_err(|e| e.to_string())?),
+        false => None,
+    };
+    let mut checked: HashMap<String, Option<String>> = HashMap::new();
+    for (note_id, url) in urls {
+        let reason = checked.entry(url.clone()).or_insert_with(|| check_url(&url, client.as_ref())).clone();
+        if let Some(reason) = reason {
+            broken.entry(note_id).or_default().push(BrokenReference { kind: ReferenceKind::Url, reference: url, reason });
+        }
+    }
+
+    Ok(notes.into_iter()
+        .filter_map(|note| {
+            let broken = broken.remove(&note.id.unwrap_or(0)).filter(|broken| !broken.is_empty())?;
+            Some(NoteLinkReport { note_id: note.id.unwrap_or(0), title: note.title, broken })
+        })
+        .collect())
+}
+
+
+/// Returns the distinct title and aliases of a note at least some characters long, longest first.
+fn names(note: &Note, min_length: usize) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in std::iter::once(&note.title).chain(&note.aliases).map(|name| name.trim()) {
+        if name.chars().count() >= min_length && !names.iter().any(|existing| existing.to_lowercase() == name.to_lowercase()) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort_by_key(|name| Reverse(name.chars().count()));
+    names
+}
+
+
+/// Finds the IDs of the local notes whose text has one of some names as a phrase, with the search index.
+fn mentioning_notes(names: &[String]) -> Result<BTreeSet<i64>, String> {
+    let mut note_ids = BTreeSet::new();
+    for (index, fields, searcher) in search_index::local_searchers()? {
+        let query_parser = QueryParser::for_index(&index, vec![fields.text]);
+        for name in names {
+            // A name without words, e.g. "???", is not in the index
+            let Ok(query) = query_parser.parse_query(&format!("\"{}\"", name.replace(['"', '\\'], " "))) else {
+                continue;
+            };
+            for address in searcher.search(&query, &DocSetCollector).map_err(|e| e.to_string())? {
+                let doc: TantivyDocument = searcher.doc(address).map_err(|e| e.to_string())?;
+                if let Some(OwnedValue::I64(id)) = doc.get_first(fields.id) {
+                    note_ids.insert(*id);
+                }
+            }
+        }
+    }
+    Ok(note_ids)
+}
+
+
+/// Finds the unlinked mentions of some names in a note, the longest names first.
+fn note_mentions(note: &Note, names: &[Vec<char>]) -> Vec<UnlinkedMention> {
+    let text = editor_text(&note.content);
+    let mut taken: Vec<Range<usize>> = wikilink_ranges(&text);
+
+    let mut ranges = Vec::new();
+    for name in names {
+        let mut start = 0;
+        while start + name.len() <= text.len() {
+            let range = start..start + name.len();
+            if is_mention(&text, range.clone(), name) && !taken.iter().any(|other| other.start < range.end && range.start < other.end) {
+                taken.push(range.clone());
+                ranges.push(range);
+                start += name.len();
+            } else {
+                start += 1;
+            }
+        }
+    }
+    ranges.sort_by_key(|range| range.start);
+
+    ranges.into_iter().map(|range| UnlinkedMention {
+        note_id: note.id.unwrap_or(0),
+        title: note.title.clone(),
+        text: text[range.clone()].iter().collect(),
+        index: utf16_len(&text[..range.start]),
+        length: utf16_len(&text[range.clone()]),
+        line: text[..range.start].iter().filter(|c| **c == '\n').count() + 1,
+        context: context(&text, range),
+    }).collect()
+}
+
+
+/// Returns the text of a note as the editor counts it, an embed standing for one character.
+fn editor_text(content: &str) -> Vec<char> {
+    let mut text = Vec::new();
+    for op in delta::parse_ops(content) {
+        match op.get("insert") {
+            Some(serde_json::Value::String(insert)) => text.extend(insert.chars()),
+            Some(_) => text.push(EMBED),
+            None => {},
+        }
+    }
+    text
+}
+
+
+/// Returns the ranges of the wikilinks of a text, in characters, brackets included.
+fn wikilink_ranges(text: &[char]) -> Vec<Range<usize>> {
+    let text: String = text.iter().collect();
+    WIKILINK.find_iter(&text)
+        .map(|link| text[..link.start()].chars().count()..text[..link.end()].chars().count())
+        .collect()
+}
+
+
+/// Tells whether a name is at a range of a text as a whole word, whatever its case.
+fn is_mention(text: &[char], range: Range<usize>, name: &[char]) -> bool {
+    let is_word = |index: Option<usize>| index.and_then(|index| text.get(index)).is_some_and(|c| c.is_alphanumeric());
+    text[range.clone()].iter().zip(name).all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+        && !is_word(range.start.checked_sub(1))
+        && !is_word(Some(range.end))
+}
+
+
+/// Returns the line of a mention, cut to some characters on each side of it.
+fn context(text: &[char], range: Range<usize>) -> String {
+    let line_start = text[..range.start].iter().rposition(|c| *c == '\n').map_or(0, |index| index + 1);
+    let line_end = text[range.end..].iter().position(|c| *c == '\n').map_or(text.len(), |index| range.end + index);
+    let start = line_start.max(range.start.saturating_sub(CONTEXT_LENGTH));
+    let end = line_end.min(range.end + CONTEXT_LENGTH);
+
+    let mut context: String = text[start..end].iter().filter(|c| **c != EMBED).collect();
+    if start > line_start {
+        context.insert(0, '…');
+    }
+    if end < line_end {
+        context.push('…');
+    }
+    context.trim().to_string()
+}
+
+
+/// Returns the length of some characters in UTF-16 code units, which is how the editor counts them.
+fn utf16_len(chars: &[char]) -> usize {
+    chars.iter().map(|c| c.len_utf16()).sum()
+}
+
+
+/// Reads the ID, the name, and the stored file of the attachments of every note, by note ID.
+fn note_attachments(conn: &Connection) -> Result<HashMap<i64, Vec<AttachmentEntry>>, String> {
+    let mut stmt = conn.prepare("SELECT note_id, id, file_name, stored_name FROM attachments ORDER BY id")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, (row.get(1)?, row.get(2)?, row.get(3)?))))
+        .map_err(|e| e.to_string())?;
+
+    let mut attachments: HashMap<i64, Vec<AttachmentEntry>> = HashMap::new();
+    for row in rows {
+        let (note_id, attachment) = row.map_err(|e| e.to_string())?;
+        attachments.entry(note_id).or_default().push(attachment);
+    }
+    Ok(attachments)
+}
+
+
+/// Returns the distinct external links of the content of a note: the URLs written in its text, the links set on its
+/// text, and its images, without the images embedded in the content.
+fn external_links(content: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    let mut add = |link: &str| {
+        let link = link.trim().trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        if !link.is_empty() && !link.starts_with("data:") && !links.iter().any(|existing| existing == link) {
+            links.push(link.to_string());
+        }
+    };
+    for op in delta::parse_ops(content) {
+        match op.get("insert") {
+            Some(serde_json::Value::String(insert)) => URL.find_iter(insert).for_each(|url| add(url.as_str())),
+            Some(embed) => {
+                if let Some(image) = embed.get("image").and_then(|image| image.as_str()) {
+                    add(image);
+                }
+            },
+            None => {},
+        }
+        if let Some(link) = op.get("attributes").and_then(|attributes| attributes.get("link")).and_then(|link| link.as_str()) {
+            add(link);
+        }
+    }
+    links
+}
+
+
+/// Checks an external link.
+///
+/// # Arguments
+///
+/// * `url` - The link.
+/// * `client` - The client requesting the link, or `None` to only check that it is a valid URL.
+///
+/// # Returns
+///
+/// Returns why the link is broken, or `None` if it is not.
+fn check_url(url: &str, client: Option<&reqwest::blocking::Client>) -> Option<String> {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(format!("Invalid URL: {}", e)),
+    };
+    // The other schemes, e.g. "mailto:", are not requested
+    let (Some(client), "http" | "https") = (client, parsed.scheme()) else {
+        return None;
+    };
+
+    // Some servers refuse HEAD requests, so the page is requested when they do
+    let response = client.head(parsed.clone()).send().and_then(|response| match response.status().as_u16() {
+        405 | 501 => client.get(parsed).send(),
+        _ => Ok(response),
+    });
+    match response {
+        Ok(response) if response.status().is_client_error() || response.status().is_server_error() => {
+            Some(format!("The server answered {}", response.status()))
+        },
+        Ok(_) => None,
+        Err(e) => Some(format!("The server cannot be reached: {}", e)),
+    }
+}
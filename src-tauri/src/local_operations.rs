@@ -1,15 +1,10 @@
 // local_operations.rs
 
-use crate::models::Note;
+use crate::{ alias_operations, app_paths, attachment_operations, citation_operations, code_block_operations, collation, crypto, fault_injection, flashcard_operations, key_management, link_operations, migrations, note_events, notebook_operations, notifier, preview, search_index, status_operations, tag_operations, vault_lock, models::AppError, models::ArchivedFilter, models::Note, models::NoteListOptions, models::NoteSummary, models::NotesPage };
 use std::sync::Mutex;
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use lazy_static::lazy_static;
 use uuid::Uuid;
-use dirs;
-use notify_rust::Notification;
-use ring::aead::{Aad, Nonce, LessSafeKey, UnboundKey, CHACHA20_POLY1305};
-use ring::rand::{SecureRandom, SystemRandom};
-use base64::{Engine as _, engine::general_purpose};
 
 
 lazy_static! {
@@ -17,7 +12,11 @@ lazy_static! {
 ///
 /// # Initialization
 ///
-/// * The connection is established to a SQLite database named "notes.db" located in the user's home directory. If the file does not exist, it will be created.
+/// * The connection is established to a SQLite database named "notes.db" located in the data directory of the application,
+///   where a database left in the home directory by a previous version is moved (see `app_paths`). If the file does not
+///   exist, it will be created.
+/// * The schema version of the database is checked before anything else touches it, so a database migrated by a newer
+///   version of the application is left as it is (see `migrations::check_version`).
 /// * A SQL statement is executed to create a new table named "notes" in the database if it does not already exist.
 /// The table has the following columns:
 ///   - "id" (INTEGER): The primary key of the table.
@@ -28,6 +27,93 @@ lazy_static! {
 ///   - "created_at" (INTEGER): The timestamp when the note was created.
 ///   - "updated_at" (INTEGER): The timestamp when the note was last updated. It can be null.
 ///   - "timestamp" (TEXT): The timestamp of the note in RFC 3339 format. It can be null.
+///   - "secure" (INTEGER): Whether the note is secure, i.e. hidden from search results while the vault is locked.
+///   - "preview" (TEXT): A short plaintext preview of the content, stored unencrypted. It is null for secure notes.
+///   - "status" (TEXT): The status of the note in the workflow, e.g. "draft" (see `status_operations`). It can be null.
+///   - "notebook_id" (INTEGER): The notebook of the note (see `notebook_operations`). It is null for the notes outside
+///     of any notebook.
+///   - "icon" (TEXT): An emoji or short string shown before the title. It can be null.
+///   - "cover_attachment_id" (INTEGER): The attachment shown as the cover image of the note. It can be null.
+///   - "pinned" (INTEGER): Whether the note is pinned, i.e. listed before the other notes (see `set_note_pinned`).
+///   - "archived" (INTEGER): Whether the note is archived, i.e. hidden from the lists of notes (see `set_note_archived`).
+///   - "notebook_id" (INTEGER): The notebook of the note (see `notebook_operations`). It is null for the notes outside
+///     of any notebook.
+/// * Columns added after the table was first created are added to existing databases by the migrations
+///   (see `migrations`).
+/// * A table named "sso_session" is created to cache the AWS SSO session (see `sso_operations`).
+/// * A table named "settings" is created to store the application settings as key/value pairs (see `settings`).
+/// * A database encrypted with the all-zero key of the previous versions is encrypted again with the key derived from
+///   the master key in the keyring, and the key is checked against the database (see `key_management`). If the
+///   master key is missing or does not match, the vault is read-only until it is imported.
+/// * The cipher new content is encrypted with is read, or chosen by benchmarking both on first run (see `crypto::load_cipher`).
+/// * The previews of existing notes are computed (see `backfill_previews`).
+/// * A table named "notebooks" is created to store the notebooks the notes are organized in (see `notebook_operations`).
+/// * Tables named "notebook_fields" and "note_fields" are created to store the custom fields of the notebooks and
+///   their values in the notes (see `custom_fields`).
+/// * A table named "notebook_settings" is created to store the settings applied to the notes created in each
+///   notebook (see `notebook_operations::create_notebook_note`).
+/// * A table named "cloud_usage" is created to count the S3 requests and bytes transferred per month (see `usage_operations`).
+/// * A table named "command_metrics" is created to count the calls, errors, and durations of the commands per day,
+///   once the user opted in (see `metrics`).
+/// * A table named "note_versions" is created to keep snapshots of the notes before bulk changes (see `version_operations`).
+/// * A table named "bucket_mirror" is created to mirror the notes of the S3 buckets for search (see `bucket_mirror`).
+/// * Tables named "note_citations" and "bib_entries" are created to track the citation keys of the notes and the entries
+///   of the attached BibTeX library, and the citations of existing notes are recorded (see `citation_operations`).
+/// * A table named "code_blocks" is created to store the code blocks of the notes with their language,
+///   and the code blocks of existing notes are recorded (see `code_block_operations`).
+/// * A view named "snippets" is created over the code blocks with the title and flag of their note, once the
+///   database is migrated.
+/// * A table named "attachments" is created to store the files attached to the notes, which are copied into the
+///   attachments directory with the sidecar files of their annotations (see `attachment_operations`).
+/// * A table named "attachment_texts" is created to store the text extracted from the attachments, encrypted,
+///   with the status of its extraction (see `text_extraction`).
+/// * A table named "vault_stats" is created to keep a daily snapshot of the statistics of the vault (see `stats_operations`).
+/// * A table named "note_sources" is created to store the web page a note was saved from, with its author
+///   (see `article_operations`).
+/// * A table named "note_aliases" is created to store the other names of the notes, which they are found and linked
+///   by (see `alias_operations`).
+/// * Tables named "tags" and "note_tags" are created to store the tags shared by the notes, and which notes have
+///   them (see `tag_operations`).
+/// * A table named "note_links" is created to store the wikilinks of the notes by the name of their target, and the
+///   wikilinks of existing notes are recorded (see `link_operations`).
+/// * A table named "periodic_notes" is created to store the note of each day, week, month, and quarter (see `periodic_notes`).
+/// * A table named "note_reviews" is created to schedule the reviews of the notes of the review queue (see `review_operations`).
+/// * A table named "cards" is created to store the flashcards written in the notes, encrypted, with their study
+///   schedule, and the flashcards of existing notes are recorded (see `flashcard_operations`).
+/// * A table named "focus_sessions" is created to store the focus sessions spent on the notes (see `focus_sessions`).
+/// * A table named "sync_state" is created to store when each note was last changed on both sides at its last sync
+///   with a bucket, to tell which side changed since (see `sync`).
+/// * A table named "conflicts" is created to store the notes changed on both sides since their last sync with a
+///   bucket, until they are resolved (see `sync`).
+/// * A table named "workspace_snapshots" is created to store the workspaces saved by name, with their open notes,
+///   filters, and sort (see `workspace`).
+/// * A table named "note_templates" is created to store the templates notes are created from, with their
+///   expressions (see `note_templates`).
+/// * A table named "note_schedules" is created to store the notes created from a template on a schedule, with
+///   their next run (see `note_schedules`).
+/// * A table named "import_ledger" is created to map the items imported from files and archives to their notes, so
+///   importing them again updates the notes (see `import_ledger`).
+/// * A table named "plain_sync_notes" is created to store the notes uploaded to the buckets as unencrypted
+///   Markdown, by UUID (see `plain_sync`).
+/// * A table named "notebooks" is created to store the notebooks the notes are organized in, each inside another one
+///   or at the top level (see `notebook_operations`).
+/// * A table named "bucket_format_migrations" is created to remember the UUIDs given to the objects of the previous
+///   versions being migrated, so a migration resumed later gives them the same ones (see `bucket_format`).
+/// * A table named "api_tokens" is created to store the tokens of the HTTP API and the plugins, with a hash of their
+///   secret and their scopes (see `api_tokens`).
+/// * A table named "note_opens" is created to store how often and how recently the notes were opened, which the
+///   quick switcher ranks them by (see `quick_switch`).
+/// * A table named "email_messages" is created to remember the emails turned into notes by their Message-ID,
+///   so each email is ingested once (see `email_operations`).
+/// * A table named "background_tasks" is created to keep track of the background tasks across restarts (see `tasks`).
+/// * A table named "operation_journal" is created to record the S3 operations in progress, so the ones interrupted
+///   by a crash are recovered on the next start (see `journal`).
+/// * The pending migrations of the schema are applied in order, each in a transaction, and recorded in a table named
+///   "schema_version" (see `migrations`). The application does not start if one fails.
+/// * The vault is locked for this instance with a lock file next to the database. If another running instance holds
+///   the lock, the connection is read-only until the lock is taken over or released (see `vault_lock`).
+/// * In debug builds, the faults of the "sqlite" layer listed in the CUSTOMNOTES_FAULTS environment variable are
+///   injected into the connection (see `fault_injection`).
 ///
 /// # Usage
 ///
@@ -36,24 +122,435 @@ lazy_static! {
 ///
 /// # Panics
 ///
-/// The program will panic and exit if an error occurs when opening the connection or executing the SQL statement.
-    static ref CONNECTION: Mutex<Connection> = {
-        let mut db_path = dirs::home_dir().unwrap();
-        db_path.push("notes.db");
-        let conn = Connection::open(db_path).unwrap();
+/// The program will panic and exit if an error occurs when opening the connection, executing the SQL statement, or
+/// migrating the database, or if the database was migrated by a newer version of the application.
+    pub static ref CONNECTION: Mutex<Connection> = {
+        let db_path = app_paths::database_path().unwrap();
+        let conn = Connection::open(&db_path).unwrap();
+        migrations::check_version(&conn).unwrap();
+        create_notes_table(&conn).unwrap();
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS notes (
+            "CREATE TABLE IF NOT EXISTS sso_session (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            start_url TEXT NOT NULL,
+            region TEXT NOT NULL,
+            account_id TEXT NOT NULL,
+            role_name TEXT NOT NULL,
+            access_token TEXT,
+            access_token_nonce TEXT,
+            expires_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        if let Err(e) = key_management::migrate_legacy_encryption(&conn) {
+            key_management::disable_content_key(&conn, e);
+        }
+        if let Err(e) = crypto::load_cipher(&conn) {
+            eprintln!("Failed to choose the cipher: {}", e);
+        }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS notebooks (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS notebook_fields (
+            notebook_id INTEGER NOT NULL REFERENCES notebooks(id),
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            options TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (notebook_id, name)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_fields (
+            note_id INTEGER NOT NULL REFERENCES notes(id),
+            name TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (note_id, name)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS notebook_settings (
+            notebook_id INTEGER PRIMARY KEY REFERENCES notebooks(id),
+            settings TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cloud_usage (
+            month TEXT NOT NULL,
+            request_type TEXT NOT NULL,
+            request_count INTEGER NOT NULL DEFAULT 0,
+            bytes INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (month, request_type)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS command_metrics (
+            day TEXT NOT NULL,
+            command TEXT NOT NULL,
+            calls INTEGER NOT NULL DEFAULT 0,
+            errors INTEGER NOT NULL DEFAULT 0,
+            total_ms INTEGER NOT NULL DEFAULT 0,
+            max_ms INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (day, command)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_versions (
             id INTEGER PRIMARY KEY,
+            note_id INTEGER NOT NULL,
             uuid TEXT,
             title TEXT NOT NULL,
             content TEXT NOT NULL,
             nonce TEXT,
+            note_created_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            reason TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS bucket_mirror (
+            bucket TEXT NOT NULL,
+            key TEXT NOT NULL,
+            etag TEXT,
+            last_modified TEXT,
+            metadata TEXT,
+            content TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            PRIMARY KEY (bucket, key)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_citations (
+            note_id INTEGER NOT NULL,
+            citation_key TEXT NOT NULL,
+            PRIMARY KEY (note_id, citation_key)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS bib_entries (
+            citation_key TEXT PRIMARY KEY,
+            entry TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS code_blocks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            language TEXT,
+            detected INTEGER NOT NULL DEFAULT 0,
+            code TEXT NOT NULL,
+            nonce TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            file_name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            stored_name TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            annotated_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS attachment_texts (
+            attachment_id INTEGER PRIMARY KEY,
+            status TEXT NOT NULL,
+            text TEXT,
+            nonce TEXT,
+            characters INTEGER,
+            error TEXT,
+            extracted_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS vault_stats (
+            day TEXT PRIMARY KEY,
+            note_count INTEGER NOT NULL,
+            secure_count INTEGER NOT NULL,
+            total_size INTEGER NOT NULL,
+            attachment_count INTEGER NOT NULL,
+            attachment_size INTEGER NOT NULL,
+            recorded_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_sources (
+            note_id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL,
+            author TEXT,
+            site_name TEXT,
+            saved_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_aliases (
+            note_id INTEGER NOT NULL,
+            alias TEXT NOT NULL,
+            PRIMARY KEY (note_id, alias)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_links (
+            note_id INTEGER NOT NULL,
+            target TEXT NOT NULL,
+            PRIMARY KEY (note_id, target)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS periodic_notes (
+            kind TEXT NOT NULL,
+            period_start TEXT NOT NULL,
+            note_id INTEGER NOT NULL,
+            PRIMARY KEY (kind, period_start)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_reviews (
+            note_id INTEGER PRIMARY KEY,
+            ease REAL NOT NULL,
+            interval_days INTEGER NOT NULL,
+            repetitions INTEGER NOT NULL,
+            due_at INTEGER NOT NULL,
+            reviewed_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cards (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            card TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            ease REAL NOT NULL,
+            interval_days INTEGER NOT NULL,
+            repetitions INTEGER NOT NULL,
+            due_at INTEGER NOT NULL,
+            reviewed_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS focus_sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            started_at INTEGER NOT NULL,
+            ended_at INTEGER,
+            planned_minutes INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS reencryption_notes (
+            note_id INTEGER PRIMARY KEY,
+            status TEXT NOT NULL,
+            error TEXT,
+            updated_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            created_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_tags (
+            note_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (note_id, tag_id)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sync_state (
+            bucket TEXT NOT NULL,
+            uuid TEXT NOT NULL,
+            local_updated_at INTEGER NOT NULL,
+            remote_updated_at INTEGER NOT NULL,
+            PRIMARY KEY (bucket, uuid)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS conflicts (
+            bucket TEXT NOT NULL,
+            uuid TEXT NOT NULL,
+            local_title TEXT NOT NULL,
+            remote_title TEXT NOT NULL,
+            local_updated_at INTEGER NOT NULL,
+            remote_updated_at INTEGER NOT NULL,
+            detected_at INTEGER NOT NULL,
+            PRIMARY KEY (bucket, uuid)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS workspace_snapshots (
+            name TEXT PRIMARY KEY COLLATE NOCASE,
+            state TEXT NOT NULL,
+            saved_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_templates (
+            name TEXT PRIMARY KEY COLLATE NOCASE,
+            template TEXT NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_schedules (
+            id INTEGER PRIMARY KEY,
+            config TEXT NOT NULL,
+            next_run_at INTEGER NOT NULL,
+            last_run_at INTEGER,
+            last_note_id INTEGER,
+            last_error TEXT
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS import_ledger (
+            fingerprint TEXT PRIMARY KEY,
+            note_uuid TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            imported_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS plain_sync_notes (
+            note_uuid TEXT PRIMARY KEY,
+            enabled_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS notebooks (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id INTEGER REFERENCES notebooks(id),
+            created_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS bucket_format_migrations (
+            bucket_name TEXT NOT NULL,
+            object_key TEXT NOT NULL,
+            note_uuid TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            PRIMARY KEY (bucket_name, object_key)
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS api_tokens (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            secret_hash TEXT NOT NULL UNIQUE,
+            scopes TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER,
+            last_used_at INTEGER,
+            revoked_at INTEGER
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS note_opens (
+            note_id INTEGER PRIMARY KEY,
+            frecency REAL NOT NULL,
+            opened_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS email_messages (
+            message_id TEXT PRIMARY KEY,
+            note_id INTEGER NOT NULL,
+            fetched_at INTEGER NOT NULL
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS background_tasks (
+            id INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            label TEXT NOT NULL,
+            priority TEXT NOT NULL,
+            status TEXT NOT NULL,
             created_at INTEGER NOT NULL,
-            updated_at INTEGER,
-            timestamp TEXT
+            started_at INTEGER,
+            finished_at INTEGER,
+            error TEXT
+            )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS operation_journal (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            operation TEXT NOT NULL,
+            target TEXT NOT NULL,
+            details TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            outcome TEXT,
+            error TEXT,
+            recovered_at INTEGER
             )",
             [],
         ).unwrap();
+        migrations::run(&conn).unwrap();
+        // The view reads columns the migrations add to the notes of existing databases
+        conn.execute(
+            "CREATE VIEW IF NOT EXISTS snippets AS
+            SELECT c.id, c.note_id, n.title AS note_title, n.secure, c.language, c.detected, c.code, c.nonce
+            FROM code_blocks c JOIN notes n ON n.id = c.note_id",
+            [],
+        ).unwrap();
+        backfill_notes(&conn).unwrap();
+        vault_lock::acquire(&conn, &db_path);
+        fault_injection::inject_sqlite_faults(&conn);
         Mutex::new(conn)
     };
 }
@@ -63,75 +560,168 @@ lazy_static! {
     /// 
     /// # Arguments
     /// 
-    /// * `note` - The note to create. It should contain the title and content of the note.
+    /// * `note` - The note to create. It should contain the title and content of the note, and can contain the
+    ///   notebook to create it in.
     /// 
     /// # Returns
     /// 
-    /// Returns `Ok(Note)` if the note is created successfully, or `Err(String)` if an error occurs.
+    /// Returns `Ok(Note)` with the created note, its ID, and its plaintext content if the note is created successfully,
+    /// or `Err(AppError)` if an error occurs.
     ///
     /// # Errors
     ///
-    /// This function will return an error if the title is too long (more than 100 characters) or if the content is too long (more than 1,000,000 characters).
-pub async fn create_local_note(note: Note) -> Result<Note, String> {
+    /// This function will return an error if the title is too long (more than 100 characters), if the content is too long (more than 1,000,000 characters),
+    /// if the aliases or the tags are invalid (see `alias_operations::check_aliases` and `tag_operations::check_tags`),
+    /// or if the notebook does not exist.
+pub async fn create_local_note(note: Note) -> Result<Note, AppError> {
 
     match validate_params(note.clone()) {
         Ok(_) => {
         },
         Err(e) => {
             println!("Error: {}", e);
-            return Err(e);
+            return Err(AppError::Validation(e));
         }
     }
+    if let Some(status) = &note.status {
+        status_operations::check_status(status).map_err(AppError::Validation)?;
+    }
+    if let Some(icon) = &note.icon {
+        check_icon(icon).map_err(AppError::Validation)?;
+    }
+    let aliases = alias_operations::check_aliases(&note.aliases).map_err(AppError::Validation)?;
+    let tags = tag_operations::check_tags(&note.tags).map_err(AppError::Validation)?;
 
-    // Generate a random nonce
-    let rng = SystemRandom::new();
-    let mut nonce = [0u8; 12];
-    rng.fill(&mut nonce).unwrap();
-    let nonce = Nonce::assume_unique_for_key(nonce);
-
-    // Convert the nonce to a byte slice and then encode it
-    let nonce_str = general_purpose::STANDARD.encode(nonce.as_ref());
-
-    // Generate a random key
-    let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-    let crypt_key = LessSafeKey::new(crypt_key);
+    // Encrypt the content with a random nonce
+    let (encrypted_content, nonce_str) = crypto::encrypt_content(&note.content)?;
 
-    // Encrypt the content
-    let mut in_out = note.content.clone().into_bytes();
-    crypt_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
-        .map_err(|_| "Encryption failed")?;
-    let encrypted_content = general_purpose::STANDARD.encode(&in_out);
+    let note_preview = stored_preview(&note.content, note.secure);
 
     let conn = CONNECTION.lock().unwrap();
+    if let Some(notebook_id) = note.notebook_id {
+        notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+    }
     let now = chrono::Utc::now().timestamp();
     let uuid = Uuid::new_v4().to_string();
     let timestamp = Some(chrono::Utc::now().to_rfc3339());
 
     conn.execute(
-        "INSERT INTO notes (uuid, title, content, nonce, created_at, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![uuid, note.title, encrypted_content, nonce_str, now, timestamp],
-    ).map_err(|e| e.to_string())?;
+        "INSERT INTO notes (uuid, title, content, nonce, created_at, timestamp, secure, preview, status, icon, notebook_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![uuid, note.title, encrypted_content, nonce_str, now, timestamp, note.secure, note_preview, note.status, note.icon, note.notebook_id],
+    )?;
+    let note_id = conn.last_insert_rowid();
+    alias_operations::store_aliases(&conn, note_id, &aliases)?;
+    tag_operations::store_tags(&conn, note_id, &tags)?;
+    note_events::note_saved(&conn, note_id, &note.content)?;
 
     // Send a desktop notification
-    Notification::new()
-    .summary("New note created")
-    .body(&format!("Note with title '{}' was created.", note.title))
-    .show().unwrap();
+    notifier::notify_grouped(
+        "create",
+        "New note created",
+        &format!("Note with title '{}' was created.", note.title),
+        "New notes created",
+        "{count} notes were created.",
+    );
 
     Ok(Note {
-        id: None,
+        id: Some(note_id),
         uuid: Some(uuid),
         title: note.title,
-        content: encrypted_content,
+        content: note.content,
         nonce: Some(nonce_str),
         created_at: now,
         updated_at: None,
-        timestamp: timestamp,
+        timestamp,
+        secure: note.secure,
+        status: note.status,
+        icon: note.icon,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: note.notebook_id,
+        aliases,
+        tags,
     })
 }
 
 
 
+/// Creates the "notes" table if it doesn't exist.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection, e.g. a scratch database to measure the operations on notes.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn create_notes_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS notes (
+        id INTEGER PRIMARY KEY,
+        uuid TEXT,
+        title TEXT NOT NULL,
+        content TEXT NOT NULL,
+        nonce TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER,
+        timestamp TEXT,
+        secure INTEGER NOT NULL DEFAULT 0,
+        preview TEXT,
+        status TEXT,
+        notebook_id INTEGER REFERENCES notebooks(id),
+        icon TEXT,
+        cover_attachment_id INTEGER,
+        locked INTEGER NOT NULL DEFAULT 0,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        archived INTEGER NOT NULL DEFAULT 0,
+        notebook_id INTEGER REFERENCES notebooks(id),
+        deleted_at INTEGER
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+
+/// Inserts a note, keeping its creation date.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note` - The note, with its plaintext content. It keeps its UUID, e.g. when it is downloaded from a bucket
+///   (see `sync`), or is given a new one if it has none.
+///
+/// # Returns
+///
+/// Returns the ID of the inserted note.
+///
+/// # Errors
+///
+/// This function will return an error if the content cannot be encrypted or if there is an issue with the database connection.
+pub fn insert_note(conn: &Connection, note: &Note) -> Result<i64, String> {
+    let (encrypted_content, nonce_str) = crypto::encrypt_content(&note.content)?;
+    let note_preview = stored_preview(&note.content, note.secure);
+
+    conn.execute(
+        "INSERT INTO notes (uuid, title, content, nonce, created_at, timestamp, secure, preview, status, icon) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![note.uuid.clone().unwrap_or_else(|| Uuid::new_v4().to_string()), note.title, encrypted_content, nonce_str, note.created_at, chrono::Utc::now().to_rfc3339(), note.secure, note_preview, note.status, note.icon],
+    ).map_err(|e| e.to_string())?;
+    let note_id = conn.last_insert_rowid();
+    if !note.aliases.is_empty() {
+        alias_operations::store_aliases(conn, note_id, &alias_operations::check_aliases(&note.aliases)?)?;
+    }
+    if !note.tags.is_empty() {
+        tag_operations::store_tags(conn, note_id, &tag_operations::check_tags(&note.tags)?)?;
+    }
+
+    Ok(note_id)
+}
+
+
+
 /// Retrieves a note from the local database based on its ID.
 /// 
 /// # Arguments
@@ -144,50 +734,19 @@ pub async fn create_local_note(note: Note) -> Result<Note, String> {
 ///
 /// # Errors
 ///
-/// This function will return an error if there is an issue with the database connection or if the note with the specified ID does not exist.
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, if there is an issue with the database connection, or if the note with the specified ID does not exist.
 pub async fn get_local_note(id: i64) -> Result<Note, anyhow::Error> {
+    // Fails early when the notes cannot be decrypted, e.g. while the vault is locked with a passphrase
+    key_management::content_key()?;
     let conn = CONNECTION.lock().unwrap();
-    let mut stmt = conn.prepare("SELECT id, uuid, title, content, nonce, created_at, updated_at, timestamp FROM notes WHERE id = ?1")?;
-    let mut note_iter = stmt.query_map(params![id], |row| {
-
-        let content_str: String = row.get(3)?;
-        let nonce_str: String = row.get(4)?;
-
-        // Decode the content
-        let mut content_bytes = general_purpose::STANDARD.decode(&content_str).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-
-        // Decode the nonce
-        let nonce_bytes = general_purpose::STANDARD.decode(&nonce_str).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-        if nonce_bytes.len() != 12 {
-            eprintln!("Nonce has wrong length");
-            return Err(rusqlite::Error::InvalidQuery.into());
-        }
-        let nonce_array: [u8; 12] = nonce_bytes.try_into().unwrap();
-        let nonce = Nonce::assume_unique_for_key(nonce_array);
-
-        // Generate the key
-        let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-        let crypt_key = LessSafeKey::new(crypt_key);
-
-        // Decrypt the content
-        let decrypted_content = crypt_key.open_in_place(nonce, Aad::empty(), &mut content_bytes).unwrap();
-
-        // Convert the decrypted content to a string
-        let content = String::from_utf8(decrypted_content.to_vec()).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-
-        Ok(Note {
-            id: row.get(0)?,
-            uuid: row.get(1)?,
-            title: row.get(2)?,
-            content: content,
-            nonce: Some(nonce_str),
-            created_at: row.get::<_, i64>(5)?,
-            updated_at: row.get::<_, Option<i64>>(6)?,
-            timestamp: row.get(7)?,
-        })
-    })?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM notes WHERE id = ?1 AND deleted_at IS NULL", NOTE_COLUMNS))?;
+    let mut note_iter = stmt.query_map(params![id], note_from_row)?;
 
-    note_iter.next().transpose()?.ok_or_else(|| anyhow::anyhow!("Note not found"))
+    let mut note = note_iter.next().transpose()?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+    alias_operations::fill_aliases(&conn, std::slice::from_mut(&mut note)).map_err(|e| anyhow::anyhow!(e))?;
+    tag_operations::fill_tags(&conn, std::slice::from_mut(&mut note)).map_err(|e| anyhow::anyhow!(e))?;
+    Ok(note)
 }
 
 
@@ -195,89 +754,104 @@ pub async fn get_local_note(id: i64) -> Result<Note, anyhow::Error> {
 /// 
 /// # Arguments
 /// 
-/// * `note` - The note to update. It should contain the ID, title, and content of the note.
+/// * `note` - The note to update. It should contain the ID, title, and content of the note. Its aliases and tags are
+///   left as they are, since they are set with `alias_operations::set_note_aliases` and `tag_operations`.
 /// 
 /// # Returns
 /// 
-/// Returns `Ok(())` if the note is updated successfully, or `Err(String)` if an error occurs.
+/// Returns `Ok(())` if the note is updated successfully, or `Err(AppError)` if an error occurs.
 ///
 /// # Errors
 ///
-/// This function will return an error if the title is too long (more than 100 characters) or if the content is too long (more than 1,000,000 characters) or if the note with the specified ID does not exist.
-pub async fn update_local_note(note: Note) -> Result<(), String> {
+/// This function will return an error if the title is too long (more than 100 characters) or if the content is too long (more than 1,000,000 characters),
+/// if the note is locked (see `set_note_locked`), or if the note with the specified ID does not exist or is in the trash.
+pub async fn update_local_note(note: Note) -> Result<(), AppError> {
 
     match validate_params(note.clone()) {
         Ok(_) => {
         },
         Err(e) => {
             println!("Error: {}", e);
-            return Err(e);
+            return Err(AppError::Validation(e));
         }
     }
 
-    // Generate a random nonce
-    let rng = SystemRandom::new();
-    let mut nonce = [0u8; 12];
-    rng.fill(&mut nonce).unwrap();
-    let nonce = Nonce::assume_unique_for_key(nonce);
-
-    // Convert the nonce to a byte slice and then encode it
-    let nonce_str = general_purpose::STANDARD.encode(nonce.as_ref());
-
-    // Generate a random key
-    let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-    let crypt_key = LessSafeKey::new(crypt_key);
-
-    // Encrypt the content
-    let mut in_out = note.content.clone().into_bytes();
-    crypt_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
-        .map_err(|_| "Encryption failed")?;
-    let encrypted_content = general_purpose::STANDARD.encode(&in_out);
+    // Encrypt the content with a random nonce
+    let (encrypted_content, nonce_str) = crypto::encrypt_content(&note.content)?;
 
     let conn = CONNECTION.lock().unwrap();
+    if let Some(id) = note.id {
+        check_not_locked(&conn, id)?;
+    }
     let now = chrono::Utc::now().timestamp();
     let timestamp = Some(chrono::Utc::now().to_rfc3339());
 
-    conn.execute(
-        "UPDATE notes SET title = ?1, content = ?2, nonce = ?3, updated_at = ?4, timestamp = ?5 WHERE id = ?6",
-        params![note.title, encrypted_content, nonce_str, now, timestamp, note.id],
-    ).map_err(|e| e.to_string())?;
+    // Secure notes are never previewed since the preview is stored unencrypted
+    let updated = conn.execute(
+        "UPDATE notes SET title = ?1, content = ?2, nonce = ?3, updated_at = ?4, timestamp = ?5,
+        preview = CASE WHEN secure = 1 THEN NULL ELSE ?6 END WHERE id = ?7 AND deleted_at IS NULL",
+        params![note.title, encrypted_content, nonce_str, now, timestamp, stored_preview(&note.content, false), note.id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    if let Some(id) = note.id {
+        note_events::note_saved(&conn, id, &note.content)?;
+    }
 
     // Send a desktop notification
-    Notification::new()
-    .summary("Local note updated")
-    .body(&format!("Note with title '{}' was updated.", note.title))
-    .show().unwrap();
+    notifier::notify_grouped(
+        "update",
+        "Local note updated",
+        &format!("Note with title '{}' was updated.", note.title),
+        "Local notes updated",
+        "{count} notes were updated.",
+    );
 
     Ok(())
 }
 
 
-/// Deletes the note with the given ID from the local database.
+/// Moves the note with the given ID to the trash.
 /// 
 /// # Arguments
 /// 
 /// * `id` - The ID of the note to delete.
 /// 
+/// # Operation
+/// 
+/// The note is marked as deleted rather than removed, so it can be restored until it is purged, by hand or once it
+/// has been in the trash for the configured number of days (see `trash`). It is left out of the notes, the search,
+/// and the sync in the meantime.
+/// 
 /// # Returns
 /// 
-/// Returns `Ok(())` if the note is deleted successfully, or `Err(String)` if an error occurs.
+/// Returns `Ok(())` if the note is moved to the trash successfully, or `Err(AppError)` if an error occurs.
 ///
 /// # Errors
 ///
-/// This function will return an error if there is an issue with the database connection or if the note with the specified ID does not exist.
-pub fn delete_local_note(id: i64) -> Result<(), String> {
+/// This function will return an error if the note is locked (see `set_note_locked`), if there is an issue with the
+/// database connection, or if the note with the specified ID does not exist or is already in the trash.
+pub fn delete_local_note(id: i64) -> Result<(), AppError> {
     let conn = CONNECTION.lock().unwrap();
-    conn.execute(
-        "DELETE FROM notes WHERE id = ?1",
-        params![id],
-    ).map_err(|e| e.to_string())?;
+    check_not_locked(&conn, id)?;
+    let trashed = conn.execute(
+        "UPDATE notes SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![chrono::Utc::now().timestamp(), id],
+    )?;
+    if trashed == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    note_events::note_trashed(&conn, id)?;
 
     // Send a desktop notification
-    Notification::new()
-    .summary("Local note deleted")
-    .body(&format!("Note with id '{}' was deleted.", id))
-    .show().unwrap();
+    notifier::notify_grouped(
+        "delete",
+        "Local note deleted",
+        &format!("Note with id '{}' was moved to the trash.", id),
+        "Local notes deleted",
+        "{count} notes were moved to the trash.",
+    );
 
     Ok(())
 }
@@ -287,77 +861,199 @@ pub fn delete_local_note(id: i64) -> Result<(), String> {
 /// 
 /// # Returns
 /// 
-/// Returns a vector of tuples containing the ID, UUID, title, content, created_at, updated_at, and timestamp of each note.
+/// Returns a vector of tuples containing the ID, UUID, title, content, created_at, updated_at, and timestamp of each note,
+/// sorted by title in natural order for the configured locale (see `collation::compare_titles`).
 /// 
 /// # Errors
 ///
-/// This function will return an error if there is an issue with the database connection.
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, or if there is an issue with the database connection.
 pub async fn get_local_notes() -> Result<Vec<Note>, String> {
-    let conn = CONNECTION.lock().unwrap();
-    let mut stmt = conn.prepare("SELECT id, uuid, title, content, nonce, created_at, updated_at, timestamp FROM notes").map_err(|e| e.to_string())?;
-    let note_iter = stmt.query_map([], |row| {
-        let content_str: String = row.get(3)?;
-        let nonce_str: String = row.get(4)?;
-
-        // Decode the content
-        let mut content_bytes = general_purpose::STANDARD.decode(&content_str).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-
-        // Decode the nonce
-        let nonce_bytes = general_purpose::STANDARD.decode(&nonce_str).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-        if nonce_bytes.len() != 12 {
-            eprintln!("Nonce has wrong length");
-            return Err(rusqlite::Error::InvalidQuery.into());
-        }
-        let nonce_array: [u8; 12] = nonce_bytes.try_into().unwrap();
-        let nonce = Nonce::assume_unique_for_key(nonce_array);
-
-        // Generate the key
-        let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-        let crypt_key = LessSafeKey::new(crypt_key);
-
-        // Decrypt the content
-        let decrypted_content = crypt_key.open_in_place(nonce, Aad::empty(), &mut content_bytes).unwrap();
-
-        // Convert the decrypted content to a string
-        let content = String::from_utf8(decrypted_content.to_vec()).map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
-
-        Ok(Note {
-            id: row.get(0)?,
-            uuid: row.get(1)?,
-            title: row.get(2)?,
-            content: content,
-            nonce: Some(nonce_str),
-            created_at: row.get::<_, i64>(5)?,
-            updated_at: row.get::<_, Option<i64>>(6)?,
-            timestamp: row.get(7)?,
-        })
-    }).map_err(|e| e.to_string())?;
-    let notes: Result<Vec<_>, _> = note_iter.collect();
-    notes.map_err(|e| e.to_string())
+    let locale = collation::get_locale()?;
+    let mut notes = get_local_notes_where("1 = 1", &[])?;
+    notes.sort_by(|a, b| collation::compare_titles(&a.title, &b.title, &locale));
+    Ok(notes)
 }
 
 
-/// Deletes all notes from the local database.
-/// 
-/// # Returns
-/// 
+/// Retrieves a page of the notes from the local database, pinned notes first.
+///
+/// # Arguments
+///
+/// * `offset` - The number of notes to skip.
+/// * `limit` - The maximum number of notes to return.
+/// * `options` - Whether to leave out, include, or only list the archived notes, and whether to only list the
+///   pinned notes.
+///
+/// # Operation
+///
+/// The notes are sorted by their titles only, pinned notes first and each group in natural order for the configured
+/// locale (see `collation::compare_titles`), and only the notes of the page are decrypted.
+///
+/// # Returns
+///
+/// Returns a `NotesPage` with the notes of the page and the total number of notes matching the options.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, or if there is an issue with the database connection.
+pub fn get_local_notes_paged(offset: usize, limit: usize, options: &NoteListOptions) -> Result<NotesPage, String> {
+    let locale = collation::get_locale()?;
+    let mut condition = match options.archived {
+        ArchivedFilter::Exclude => "archived = 0",
+        ArchivedFilter::Include => "1 = 1",
+        ArchivedFilter::Only => "archived = 1",
+    }.to_string();
+    if options.pinned_only {
+        condition.push_str(" AND pinned = 1");
+    }
+
+    let mut entries: Vec<(i64, String, bool)> = {
+        let conn = CONNECTION.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT id, title, pinned FROM notes WHERE deleted_at IS NULL AND {}", condition))
+            .map_err(|e| e.to_string())?;
+        let entry_iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        entry_iter.collect::<Result<Vec<_>>>().map_err(|e| e.to_string())?
+    };
+    entries.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| collation::compare_titles(&a.1, &b.1, &locale)));
+
+    let total = entries.len();
+    let page_ids: Vec<i64> = entries.into_iter().skip(offset).take(limit).map(|(id, _, _)| id).collect();
+    let mut notes = match page_ids.is_empty() {
+        true => Vec::new(),
+        false => {
+            let placeholders = vec!["?"; page_ids.len()].join(", ");
+            let params: Vec<&dyn rusqlite::ToSql> = page_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+            get_local_notes_where(&format!("id IN ({})", placeholders), &params)?
+        },
+    };
+    notes.sort_by_key(|note| page_ids.iter().position(|id| Some(*id) == note.id));
+
+    Ok(NotesPage { notes, offset, total })
+}
+
+
+/// Retrieves the notes from the local database that match an SQL condition.
+///
+/// # Arguments
+///
+/// * `condition` - The SQL condition on the "notes" table, used as the `WHERE` clause. The notes in the trash are left out.
+/// * `params` - The values bound to the `?` placeholders of the condition.
+///
+/// # Returns
+///
+/// Returns a vector of the matching notes with their content decrypted.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, if there is an issue with the database connection, or if the condition is invalid.
+pub fn get_local_notes_where(condition: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Note>, String> {
+    // Fails early when the notes cannot be decrypted, e.g. while the vault is locked with a passphrase
+    key_management::content_key()?;
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM notes WHERE deleted_at IS NULL AND ({})", NOTE_COLUMNS, condition)).map_err(|e| e.to_string())?;
+    let note_iter = stmt.query_map(params, note_from_row).map_err(|e| e.to_string())?;
+    let mut notes = note_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+    alias_operations::fill_aliases(&conn, &mut notes)?;
+    tag_operations::fill_tags(&conn, &mut notes)?;
+    Ok(notes)
+}
+
+
+/// Deletes all notes from the local database.
+/// 
+/// # Returns
+/// 
 /// Returns `Ok(())` if all notes are deleted successfully, or `Err(String)` if an error occurs.
 ///
 /// # Errors
 ///
-/// This function will return an error if there is an issue with the database connection.
+/// This function will return an error if a note is locked (see `set_note_locked`), in which case no note is deleted,
+/// or if there is an issue with the database connection.
 pub async fn delete_all_local_notes() -> Result<(), String> {
     let conn = CONNECTION.lock().unwrap();
+    let locked_count: i64 = conn.query_row("SELECT COUNT(*) FROM notes WHERE locked = 1", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if locked_count > 0 {
+        return Err(format!("{} notes are locked. Unlock them to delete every note.", locked_count));
+    }
     conn.execute(
         "DELETE FROM notes",
         [],
     ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_citations",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM code_blocks",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_fields",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_sources",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_aliases",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_tags",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM tags",
+        [],
+    ).map_err(|e| e.to_string())?;
+    // Forgetting what was synced downloads the notes of the buckets again, instead of deleting them
+    conn.execute(
+        "DELETE FROM sync_state",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM conflicts",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_links",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM periodic_notes",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_reviews",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM cards",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM focus_sessions",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM note_opens",
+        [],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM reencryption_notes",
+        [],
+    ).map_err(|e| e.to_string())?;
+    attachment_operations::forget_all_attachments(&conn)?;
+    search_index::schedule_rebuild();
 
     // Send a desktop notification
-    Notification::new()
-    .summary("Local notes deleted")
-    .body(&format!("Your local notes were deleted."))
-    .show().unwrap();
+    notifier::notify("Local notes deleted", "Your local notes were deleted.");
 
         
     Ok(())
@@ -386,6 +1082,396 @@ pub fn validate_params(note: Note) -> Result<(), String> {
 }
 
 
+/// Shortens a text to the maximum length of a title, without splitting a character.
+///
+/// # Arguments
+///
+/// * `text` - The text, e.g. the subject of an email or the first line of a message.
+///
+/// # Returns
+///
+/// Returns the longest start of the text accepted by `validate_params` as a title.
+pub fn truncate_title(text: &str) -> String {
+    let mut title = String::new();
+    for c in text.chars() {
+        if title.len() + c.len_utf8() > 100 {
+            break;
+        }
+        title.push(c);
+    }
+    title
+}
+
+
+/// Retrieves the summaries of all notes from the local database, with their preview instead of their content.
+///
+/// The content is not decrypted, so this is cheaper than `get_local_notes` for listing notes.
+///
+/// # Returns
+///
+/// Returns a vector of the summaries of the notes, sorted like `get_local_notes`. Secure notes have no preview.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_local_note_summaries() -> Result<Vec<NoteSummary>, String> {
+    let locale = collation::get_locale()?;
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, uuid, title, preview, created_at, updated_at, timestamp, secure, icon, locked, pinned, archived, notebook_id FROM notes WHERE deleted_at IS NULL"
+    ).map_err(|e| e.to_string())?;
+    let summary_iter = stmt.query_map([], |row| {
+        Ok(NoteSummary {
+            id: row.get(0)?,
+            uuid: row.get(1)?,
+            title: row.get(2)?,
+            preview: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            timestamp: row.get(6)?,
+            secure: row.get(7)?,
+            icon: row.get(8)?,
+            locked: row.get(9)?,
+            pinned: row.get(10)?,
+            archived: row.get(11)?,
+            notebook_id: row.get(12)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    let mut summaries = summary_iter.collect::<Result<Vec<_>>>().map_err(|e| e.to_string())?;
+    summaries.sort_by(|a, b| collation::compare_titles(&a.title, &b.title, &locale));
+    Ok(summaries)
+}
+
+
+/// Marks a note as secure or not secure in the local database.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `secure` - Whether the note is secure. Secure notes are redacted from search results while the vault is locked.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or if the note with the specified ID does not exist.
+pub fn set_note_secure(id: i64, secure: bool) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let (encrypted_content, nonce_str): (String, String) = conn.query_row(
+        "SELECT content, nonce FROM notes WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+
+    // The preview is stored unencrypted, so it is dropped when the note becomes secure
+    let note_preview = if secure {
+        None
+    } else {
+        stored_preview(&crypto::decrypt_content(&encrypted_content, &nonce_str)?, false)
+    };
+
+    conn.execute(
+        "UPDATE notes SET secure = ?1, preview = ?2 WHERE id = ?3",
+        params![secure, note_preview, id],
+    )?;
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Locks or unlocks a local note, e.g. to protect a reference or published note from accidental edits.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `locked` - Whether the note is locked. The content and the title of a locked note cannot be changed, e.g. by
+///   an update, a find and replace, or the restoration of a version, and it cannot be deleted or merged until it is unlocked.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn set_note_locked(id: i64, locked: bool) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE notes SET locked = ?1 WHERE id = ?2",
+        params![locked, id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Pins or unpins a local note, to keep it at the top of the lists of notes (see `get_local_notes_paged`).
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `pinned` - Whether the note is pinned.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn set_note_pinned(id: i64, pinned: bool) -> Result<(), AppError> {
+    set_note_flag(id, "pinned", pinned)
+}
+
+
+/// Archives or unarchives a local note, to hide a finished note from the lists of notes without deleting it.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `archived` - Whether the note is archived. An archived note is left out of `get_local_notes_paged` unless
+///   asked for, and is still found by the searches, opened by its links, and synced like the other notes.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn set_note_archived(id: i64, archived: bool) -> Result<(), AppError> {
+    set_note_flag(id, "archived", archived)
+}
+
+
+/// Sets a flag column of a note that is not in the trash, and reindexes it.
+fn set_note_flag(id: i64, column: &str, value: bool) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let updated = conn.execute(
+        &format!("UPDATE notes SET {} = ?1 WHERE id = ?2 AND deleted_at IS NULL", column),
+        params![value, id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Checks that a local note is not locked before changing or deleting it (see `set_note_locked`).
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `id` - The ID of the note. A note that does not exist is not locked.
+///
+/// # Errors
+///
+/// This function will return an error if the note is locked or if there is an issue with the database connection.
+pub fn check_not_locked(conn: &Connection, id: i64) -> Result<(), String> {
+    let title: Option<String> = conn.query_row(
+        "SELECT title FROM notes WHERE id = ?1 AND locked = 1",
+        params![id],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())?;
+    match title {
+        Some(title) => Err(format!("The note '{}' is locked. Unlock it to change it.", title)),
+        None => Ok(()),
+    }
+}
+
+
+/// Sets the icon shown before the title of a local note.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `icon` - An emoji or a short string of up to 8 characters, or `None` to remove the icon.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the icon is blank or too long, if the note does not exist,
+/// or if there is an issue with the database connection.
+pub fn set_note_icon(id: i64, icon: Option<String>) -> Result<(), AppError> {
+    if let Some(icon) = &icon {
+        check_icon(icon)?;
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE notes SET icon = ?1 WHERE id = ?2",
+        params![icon, id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Sets the cover image shown above the title of a local note.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `attachment_id` - An image attached to the note (see `attachment_operations::add_attachment`),
+///   or `None` to remove the cover.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is updated successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment is not an image attached to the note, if the note does not exist,
+/// or if there is an issue with the database connection.
+pub fn set_note_cover(id: i64, attachment_id: Option<i64>) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    if let Some(attachment_id) = attachment_id {
+        attachment_operations::check_cover(&conn, id, attachment_id)?;
+    }
+
+    let updated = conn.execute(
+        "UPDATE notes SET cover_attachment_id = ?1 WHERE id = ?2",
+        params![attachment_id, id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Checks that an icon is an emoji or a short string of up to 8 characters, without line breaks.
+fn check_icon(icon: &str) -> Result<(), String> {
+    if icon.trim().is_empty() || icon.chars().count() > 8 || icon.contains(['\n', '\r']) {
+        return Err("The icon should be an emoji or a short string of up to 8 characters".to_string());
+    }
+    Ok(())
+}
+
+
+/// The columns selected to build a `Note` with `note_from_row`, in order.
+pub const NOTE_COLUMNS: &str = "id, uuid, title, content, nonce, created_at, updated_at, timestamp, secure, status, icon, cover_attachment_id, locked, pinned, archived, notebook_id";
+
+
+/// Builds a `Note` from a row selected with `NOTE_COLUMNS`, decrypting its content.
+///
+/// # Arguments
+///
+/// * `row` - The row to read.
+///
+/// # Returns
+///
+/// Returns the decrypted `Note`, or a `rusqlite::Error` if the row cannot be read or the content cannot be decrypted.
+pub fn note_from_row(row: &rusqlite::Row) -> rusqlite::Result<Note> {
+    let content_str: String = row.get(3)?;
+    let nonce_str: String = row.get(4)?;
+
+    // Decrypt the content
+    let content = crypto::decrypt_content(&content_str, &nonce_str).map_err(|e| {
+        eprintln!("Failed to decrypt note: {}", e);
+        rusqlite::Error::InvalidQuery
+    })?;
+
+    Ok(Note {
+        id: row.get(0)?,
+        uuid: row.get(1)?,
+        title: row.get(2)?,
+        content,
+        nonce: Some(nonce_str),
+        created_at: row.get::<_, i64>(5)?,
+        updated_at: row.get::<_, Option<i64>>(6)?,
+        timestamp: row.get(7)?,
+        secure: row.get(8)?,
+        status: row.get(9)?,
+        icon: row.get(10)?,
+        cover_attachment_id: row.get(11)?,
+        locked: row.get(12)?,
+        pinned: row.get(13)?,
+        archived: row.get(14)?,
+        notebook_id: row.get(15)?,
+        // The aliases and the tags are in their own tables (see `alias_operations::fill_aliases`)
+        aliases: Vec::new(),
+        tags: Vec::new(),
+    })
+}
+
+
+/// Derives the previews and the data of the notes saved before they were stored (see `note_events::backfill`).
+///
+/// It is called once the database is opened, and again when the vault is unlocked with a passphrase, since the
+/// notes cannot be decrypted before (see `key_management::set_passphrase`).
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+pub fn backfill_notes(conn: &Connection) -> Result<()> {
+    backfill_previews(conn)?;
+    note_events::backfill(conn, "citations_indexed", citation_operations::index_citations)?;
+    note_events::backfill(conn, "code_blocks_indexed", code_block_operations::index_code_blocks)?;
+    note_events::backfill(conn, "links_indexed", link_operations::index_links)?;
+    note_events::backfill(conn, "cards_indexed", flashcard_operations::index_cards)?;
+    Ok(())
+}
+
+
+/// Returns the preview stored with a note, which is stored unencrypted: secure notes are never previewed, and no
+/// note is while a passphrase is set.
+pub fn stored_preview(content: &str, secure: bool) -> Option<String> {
+    (!secure && !key_management::has_passphrase()).then(|| preview::build_preview(content))
+}
+
+
+/// Computes the preview of the notes saved before previews were stored.
+///
+/// Notes that cannot be decrypted are skipped, so they keep no preview. No preview is computed while a passphrase is set.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+fn backfill_previews(conn: &Connection) -> Result<()> {
+    if key_management::has_passphrase() {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare("SELECT id, content, nonce FROM notes WHERE preview IS NULL AND secure = 0")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (id, encrypted_content, nonce_str) in rows {
+        match crypto::decrypt_content(&encrypted_content, &nonce_str) {
+            Ok(content) => {
+                conn.execute(
+                    "UPDATE notes SET preview = ?1 WHERE id = ?2",
+                    params![preview::build_preview(&content), id],
+                )?;
+            },
+            Err(e) => eprintln!("Failed to preview note {}: {}", id, e),
+        }
+    }
+
+    Ok(())
+}
+
+
 // /// Derives the nonce from the note ID in the local database.
 // /// 
 // /// # Arguments
@@ -407,4 +1493,33 @@ pub fn validate_params(note: Note) -> Result<(), String> {
 //     })?;
 
 //     nonce_iter.next().transpose()?.ok_or_else(|| anyhow::anyhow!("Nonce not found"))
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A note as the window sends it to be created.
+    fn new_note(title: &str) -> Note {
+        serde_json::from_value(serde_json::json!({ "title": title, "content": "Content", "created_at": 0 })).unwrap()
+    }
+
+    #[test]
+    fn invalid_notes_are_validation_errors() {
+        let err = tauri::async_runtime::block_on(create_local_note(new_note(&"a".repeat(101)))).unwrap_err();
+        assert!(matches!(err, AppError::Validation(message) if message == "Title too long"));
+
+        let note = Note { tags: vec!["a".repeat(1000)], ..new_note("Title") };
+        let err = tauri::async_runtime::block_on(create_local_note(note)).unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)));
+    }
+
+    #[test]
+    fn notes_cannot_be_encrypted_without_the_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        key_management::disable_content_key(&conn, "The master key is missing".to_string());
+
+        let err = tauri::async_runtime::block_on(create_local_note(new_note("Title"))).unwrap_err();
+        assert!(matches!(err, AppError::Encryption(message) if message == "The master key is missing"));
+    }
+}
@@ -6,439 +6,184 @@
 mod models;
 mod s3_operations;
 mod local_operations;
+mod migrations;
+mod sso_operations;
+mod settings;
+mod settings_profile;
+mod usage_operations;
+mod vault;
+mod search;
+mod crypto;
+mod key_management;
+mod reencryption;
+mod version_operations;
+mod replace_operations;
+mod search_index;
+mod bucket_mirror;
+mod preview;
+mod collation;
+mod notebook_operations;
+mod custom_fields;
+mod notebook_csv;
+mod delta;
+mod markdown;
+mod print_operations;
+mod merge_operations;
+mod presence_operations;
+mod export_operations;
+mod card_operations;
+mod citation_operations;
+mod note_events;
+mod code_block_operations;
+mod status_operations;
+mod startup;
+mod tasks;
+mod shutdown;
+mod journal;
+mod journal_context;
+mod metrics;
+mod notifier;
+mod attachment_operations;
+mod stats_operations;
+mod email_operations;
+mod capture_bridge;
+mod article_operations;
+mod text_extraction;
+mod envelope;
+mod doctor;
+mod bucket_audit;
+mod bucket_format;
+mod update_operations;
+mod features;
+mod commands;
+mod fault_injection;
+mod test_data;
+mod memory_budget;
+mod app_paths;
+mod vault_lock;
+mod drop_operations;
+mod import_ledger;
+mod import;
+mod file_associations;
+mod tray;
+mod quick_switch;
+mod alias_operations;
+mod tag_operations;
+mod sync;
+mod plain_sync;
+mod proxy;
+mod s3_config;
+mod tls;
+mod workspace;
+mod note_templates;
+mod note_schedules;
+mod note_stats;
+mod api_tokens;
+mod trash;
+mod link_operations;
+mod periodic_notes;
+mod review_operations;
+mod flashcard_operations;
+mod focus_sessions;
+mod handlers;
+mod errors;
+#[cfg(feature = "benchmarks")]
+mod benchmarks;
+
+use tauri::Manager;
 
-use std::str;
-use models::Note;
-use tantivy::schema::{Schema, TEXT, STORED};
-use tantivy::Index;
-use tantivy::query::QueryParser;
-use tantivy::TantivyDocument;
-use tantivy::DocAddress;
-use tantivy::Score;
-use tantivy::collector::TopDocs;
-
-
-/// Routes a command to the appropriate operation based on the command string and arguments.
-///
-/// # Arguments
-///
-/// * `command` - A string representing the command to be executed.
-/// * `args` - A string representing the arguments for the command.
-///
-/// # Returns
-///
-/// A `Result` containing either the result of the operation as a string or an error message as a string.
-async fn route_command(command: String, args: String) -> Result<String, String> {
-    match command.as_str() {
-        "create_local_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let note_value = args_value.get("note")
-                .ok_or("Missing 'note' key in args".to_string())?
-                .to_string();
-            let note: models::Note = serde_json::from_str(&note_value)
-                .map_err(|_| "Invalid note in args".to_string())?;
-            match local_operations::create_local_note(note).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "get_local_note" => {
-            let args: serde_json::Value = serde_json::from_str(&args).map_err(|_| "Invalid args".to_string())?;
-            let id = args["id"].as_i64().ok_or("Invalid id in args".to_string())?;
-            match local_operations::get_local_note(id).await {
-                Ok(note) => Ok(serde_json::to_string(&note).map_err(|e| e.to_string())?),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "update_local_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let note_value = args_value.get("note")
-                .ok_or("Missing 'note' key in args".to_string())?
-                .to_string();
-            let note: models::Note = serde_json::from_str(&note_value)
-                .map_err(|_| "Invalid note in args".to_string())?;
-            match local_operations::update_local_note(note).await {
-                Ok(note) => Ok(serde_json::to_string(&note).map_err(|e| e.to_string())?),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "delete_local_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let id_value = args_value.get("id")
-                .ok_or("Missing 'id' key in args".to_string())?
-                .to_string();
-            let id: i64 = id_value.parse().map_err(|_| "Invalid id in args".to_string())?;
-            match local_operations::delete_local_note(id) {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "get_local_notes" => {
-            match local_operations::get_local_notes().await {
-                Ok(notes) => Ok(serde_json::to_string(&notes).unwrap()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "create_bucket" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .to_string();
-            match s3_operations::create_bucket(&bucket_name).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "fetch_buckets" => {
-            let buckets = s3_operations::fetch_buckets().await.map_err(|e| e.to_string())?;
-            Ok(serde_json::to_string(&buckets).map_err(|e| e.to_string())?)
-        },
-        "delete_bucket" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .to_string();
-            match s3_operations::delete_bucket(&bucket_name).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "delete_all_local_notes" => {
-           match local_operations::delete_all_local_notes().await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "upload_note_to_bucket" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let args_value = args_value.as_object()
-                .ok_or("args should be a JSON object".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .as_str()
-                .ok_or("bucket_name should be a string".to_string())?;
-            let note_value = args_value.get("note")
-                .ok_or("Missing 'note' key in args".to_string())?;
-            let note: models::Note = serde_json::from_value(note_value.clone())
-                .map_err(|_| "Invalid note in args".to_string())?;
-            match s3_operations::upload_note_to_bucket(bucket_name, note).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "fetch_bucket_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let args_value = args_value.as_object()
-                .ok_or("args should be a JSON object".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .as_str()
-                .ok_or("bucket_name should be a string".to_string())?;
-            let uuid = args_value.get("uuid")
-                .ok_or("Missing 'uuid' key in args".to_string())?
-                .as_str()
-                .ok_or("uuid should be a string".to_string())?;
-            match s3_operations::fetch_bucket_note(bucket_name, uuid).await {
-                Ok(note) => Ok(serde_json::to_string(&note).map_err(|e| e.to_string())?),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "update_bucket_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let args_value = args_value.as_object()
-                .ok_or("args should be a JSON object".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .as_str()
-                .ok_or("bucket_name should be a string".to_string())?;
-            let note_value = args_value.get("note")
-                .ok_or("Missing 'note' key in args".to_string())?;
-            let note: models::Note = serde_json::from_value(note_value.clone())
-                .map_err(|_| "Invalid note in args".to_string())?;
-            match s3_operations::update_bucket_note(bucket_name, note).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "delete_bucket_note" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let args_value = args_value.as_object()
-                .ok_or("args should be a JSON object".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .as_str()
-                .ok_or("bucket_name should be a string".to_string())?;
-            let uuid = args_value.get("uuid")
-                .ok_or("Missing 'uuid' key in args".to_string())?
-                .as_str()
-                .ok_or("uuid should be a string".to_string())?;
-            match s3_operations::delete_bucket_note(bucket_name, uuid).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "fetch_bucket_notes" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .to_string();
-            match s3_operations::fetch_bucket_notes(&bucket_name).await {
-                Ok(notes) => Ok(serde_json::to_string(&notes).map_err(|e| e.to_string())?),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "delete_bucket_notes" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .ok_or("Missing 'bucket_name' key in args".to_string())?
-                .to_string();
-            match s3_operations::delete_bucket_notes(&bucket_name).await {
-                Ok(_) => Ok("Success".to_string()),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        "search_in_notes" => {
-            let args_value: serde_json::Value = serde_json::from_str(&args)
-                .map_err(|_| "Invalid JSON in args".to_string())?;
-            let query = args_value.get("query")
-                .ok_or("Missing 'query' key in args".to_string())?
-                .to_string();
-            let local = args_value.get("local")
-                .ok_or("Missing 'local' key in args".to_string())?
-                .as_bool()
-                .ok_or("'local' key in args is not a boolean".to_string())?;
-            let bucket_name = args_value.get("bucket_name")
-                .map(|v| v.to_string());
-            let bucket_name_option = if let Some(name) = &bucket_name {
-                if name.is_empty() {
-                    None
-                } else {
-                    Some(name.as_str())
-                }
-            } else {
-                None
-            };
-            match search_in_notes(&query, local, bucket_name_option).await {
-                Ok(notes) => Ok(serde_json::to_string(&notes).map_err(|e| e.to_string())?),
-                Err(e) => Err(e.to_string()),
-            }
-        },
-        _ => Err("Unknown command".to_string()),
-    }
-}
-
-/// Routes a command and its arguments to the appropriate function and returns the result.
-///
-/// # Arguments
-///
-/// * `command` - A string representing the command to be executed.
-/// * `args` - A string representing the arguments for the command.
-///
-/// # Returns
-///
-/// A `Result` containing either the success message as a `String` or an error message as a `String`.
-#[tauri::command]
-async fn execute_command(command: String, args: serde_json::Value) -> Result<String, String> {
-    route_command(command, args.to_string()).await
-}
-
-
-    /// Retrieves notes based on the specified parameters.
-    ///
-    /// # Arguments
-    ///
-    /// * `local` - A boolean indicating whether to fetch local notes or not.
-    /// * `bucket_name` - An optional string representing the name of the bucket.
-    ///
-    /// # Returns
-    ///
-    /// A vector of tuples containing the retrieved notes. Each tuple consists of the following elements:
-    /// * An integer representing the note's ID.
-    /// * A string representing the note's UUID.
-    /// * A string representing the note's title.
-    /// * A string representing the note's content.
-    /// * An integer representing the note's status.
-    /// * An optional integer representing the note's last modified timestamp.
-    /// * An optional string representing the note's timestamp.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if any of the following conditions are met:
-    /// * `local` is `true` and there was an error retrieving local notes.
-    /// * `local` is `false` and `bucket_name` is not provided.
-    /// * `local` is `false` and there was an error fetching bucket notes.
-pub async fn search_in_notes(query_str: &str, local: bool, bucket_name: Option<&str>) -> Result<Vec<Note>, Box<dyn std::error::Error>> {
-    // Define the schema for the index
-    let mut schema_builder = Schema::builder();
-    let title_field = schema_builder.add_text_field("title", TEXT | STORED);
-    let content_field = schema_builder.add_text_field("content", TEXT | STORED);
-    let id_field = schema_builder.add_i64_field("id", STORED);
-    let uuid_field = schema_builder.add_text_field("uuid", TEXT | STORED);
-    let created_at_field = schema_builder.add_i64_field("created_at", STORED);
-    let updated_at_field = schema_builder.add_i64_field("updated_at", STORED);
-    let timestamp_field = schema_builder.add_text_field("timestamp", TEXT | STORED);
-    let schema = schema_builder.build();
-
-    // Create a new index
-    let index = Index::create_in_ram(schema.clone());
-
-    // Get the index writer
-    let mut index_writer = index.writer(100_000_000)?;
-
-    // Get the notes
-    let notes = if local {
-        local_operations::get_local_notes().await?
-    } else {
-        // let bucket_name = bucket_name
-        //     .map(|name| name.trim_matches('"'))
-        //     .ok_or("Bucket name is required when local is false")?;
-        // let bucket_notes = s3_operations::fetch_bucket_notes(bucket_name).await?;
-        // bucket_notes.into_iter().map(|(title, last_modified, metadata, content)| {
-        //     let (uuid, timestamp) = metadata.map_or((String::new(), String::new()), |map| {
-        //         let uuid = map.get("uuid").cloned().unwrap_or_else(String::new);
-        //         let timestamp = map.get("timestamp").cloned().unwrap_or_else(String::new);
-        //         (uuid, timestamp)
-        //     });
-        //     (0, uuid, title, content, 0, last_modified.map(|lm| lm.parse::<i64>().unwrap_or(0)), Some(timestamp))
-        // }).collect::<Vec<_>>()
-
-        let bucket_name = bucket_name
-        .map(|name| name.trim_matches('"'))
-        .ok_or("Bucket name is required when local is false")?;
-        let bucket_notes = s3_operations::fetch_bucket_notes(bucket_name).await?;
-        bucket_notes.into_iter().map(|(title, last_modified, metadata, content)| {
-            let (uuid, timestamp) = metadata.map_or((String::new(), String::new()), |map| {
-                let uuid = map.get("uuid").cloned().unwrap_or_else(String::new);
-                let timestamp = map.get("timestamp").cloned().unwrap_or_else(String::new);
-                (uuid, timestamp)
-            });
-            Note {
-                id: Some(0),
-                uuid: Some(uuid),
-                title,
-                content,
-                nonce: None,
-                created_at: 0,
-                updated_at: last_modified.map(|lm| lm.parse::<i64>().unwrap_or(0)),
-                timestamp: Some(timestamp),
-            }
-        }).collect::<Vec<_>>()
-    };
-
-    // Index the notes
-    for note in &notes {
-        let mut doc = TantivyDocument::new();
-        doc.add_text(title_field, &note.title);
-        doc.add_text(content_field, &note.content);
-        doc.add_i64(id_field, note.id.unwrap_or(0));
-        doc.add_text(uuid_field, note.uuid.as_ref().unwrap_or(&"".to_string()));
-        doc.add_i64(created_at_field, note.created_at);
-        if let Some(updated_at) = note.updated_at {
-            doc.add_i64(updated_at_field, updated_at);
-        }
-        if let Some(timestamp) = &note.timestamp {
-            doc.add_text(timestamp_field, timestamp);
-        }
-        let _ = index_writer.add_document(doc);
-    }
-
-    // Commit the documents to the index
-    index_writer.commit()?;
-
-    // Create a reader and a searcher
-    let reader = index.reader()?;
-    let searcher = reader.searcher();
-
-    // Create a query parser for the content field
-    let query_parser = QueryParser::for_index(&index, vec![content_field]);
-
-    // Parse the query
-    let query = query_parser.parse_query(query_str)?;
-
-    // Perform the search
-    let top_docs: Vec<(Score, DocAddress)> = searcher.search(&query, &TopDocs::with_limit(10))?;
-
-    // Retrieve the actual content of the documents
-    let mut matching_notes = Vec::new();
-    for (_score, doc_address) in top_docs {
-        let retrieved_doc: tantivy::TantivyDocument = searcher.doc(doc_address)?;
-        let title = retrieved_doc.get_first(title_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::Str(t) => Some(t.to_string()),
-            _ => None,
-        }).unwrap_or_else(|| "".to_string());
-        let content = retrieved_doc.get_first(content_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::Str(t) => Some(t.to_string()),
-            _ => None,
-        }).unwrap_or_else(|| "".to_string());
-        let schema = index.schema();
-        let id_field = schema.get_field("id").unwrap();
-        let uuid_field = schema.get_field("uuid").unwrap();
-        let created_at_field = schema.get_field("created_at").unwrap();
-        let updated_at_field = schema.get_field("updated_at").unwrap();
-        let timestamp_field = schema.get_field("timestamp").unwrap();
-
-        let id = retrieved_doc.get_first(id_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::I64(t) => Some(*t),
-            _ => None,
-        });
-
-        let uuid = retrieved_doc.get_first(uuid_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::Str(t) => Some(t.to_string()),
-            _ => None,
-        });
-        let created_at = retrieved_doc.get_first(created_at_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::I64(t) => Some(*t),
-            _ => None,
-        }).unwrap_or_else(|| 0);
-
-        let updated_at = retrieved_doc.get_first(updated_at_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::I64(t) => Some(*t),
-            _ => None,
-        });
-        
-        let timestamp = retrieved_doc.get_first(timestamp_field).and_then(|v| match v {
-            tantivy::schema::OwnedValue::Str(t) => Some(t.to_string()),
-            _ => None,
-        });
-    
-        matching_notes.push(Note {
-            id,
-            uuid,
-            title,
-            content,
-            nonce: None,
-            created_at,
-            updated_at,
-            timestamp,
-        });
-    }
-
-    Ok(matching_notes)
-}
 
 /// The main entry point of the application.
 /// 
 /// This function initializes the Tauri application and sets up the necessary database connection.
-/// It registers the command handlers for creating, reading, updating, and deleting notes.
+/// It registers the commands of `handlers`, which the window calls by name, and `execute_api_command`, which runs them
+/// for the local HTTP API and the plugins once their API token is checked.
+/// The database, the search index, and the AWS configuration are initialized in the background once the
+/// application is set up, and their timings are exposed by the "get_startup_timings" command.
+/// The background tasks, such as the search index warm-up, are run by the workers of `tasks`.
+/// The updater is given the handle of the application, to check for and install updates on demand (see `update_operations`).
+/// Closing the window or exiting the application first flushes the pending writes (see `shutdown::begin`),
+/// and the S3 operations interrupted by a crash are run again on the next start (see `journal::recover`).
+/// The tray holds the notes pinned to it, which open in one click (see `tray`).
 /// 
 /// Executes the Tauri application and runs the event loop.
 #[tokio::main]
 async fn main() {
+    startup::mark_process_start();
+
     tauri::Builder::default()
-    .invoke_handler(tauri::generate_handler![
-        execute_command,
-    ])
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .setup(|app| {
+        update_operations::init(app.handle());
+        app_paths::init(&app.path_resolver());
+        file_associations::init(app.handle());
+        reencryption::init(app.handle());
+        tray::init(app.handle());
+        let launch_files = file_associations::launch_files();
+
+        // Open the database and warm up the search index off the main thread, so the window shows right away
+        std::thread::spawn(move || {
+            startup::time("database", || lazy_static::initialize(&local_operations::CONNECTION));
+            if let Err(e) = notifier::load_settings() {
+                eprintln!("Failed to load the notification settings: {}", e);
+            }
+            if let Err(e) = memory_budget::load_settings() {
+                eprintln!("Failed to load the memory budgets: {}", e);
+            }
+            vault_lock::start_heartbeat();
+            tasks::start_workers();
+            tasks::submit(tasks::WARM_UP, "Build the search index", models::TaskPriority::High);
+            reencryption::resume_reencryption();
+            if let Err(e) = text_extraction::resume_extractions() {
+                eprintln!("Failed to resume the text extraction of attachments: {}", e);
+            }
+            stats_operations::start_daily_snapshots();
+            trash::start_daily_purge();
+            note_schedules::start_schedules();
+            email_operations::start_email_polling();
+            capture_bridge::start_capture_bridge();
+            file_associations::open_files(&launch_files);
+            if let Err(e) = tray::refresh() {
+                eprintln!("Failed to add the pinned notes to the tray: {}", e);
+            }
+        });
+        // Load the AWS configuration ahead of the first S3 operation, which may take a while on slow networks
+        tokio::spawn(async {
+            let started = std::time::Instant::now();
+            s3_operations::preload_aws_config().await;
+            startup::record("aws config", started);
+
+            // Run again the S3 operations interrupted by a crash
+            let started = std::time::Instant::now();
+            journal::recover().await;
+            startup::record("recovery", started);
+
+            // Sync the buckets once the interrupted operations are over
+            sync::start_auto_sync();
+        });
+        Ok(())
+    })
+    .system_tray(tray::system_tray())
+    .on_system_tray_event(tray::handle_event)
+    .on_window_event(|event| {
+        if let tauri::WindowEvent::CloseRequested { api, .. } = event.event() {
+            api.prevent_close();
+            shutdown::begin(event.window().app_handle());
+        }
+    })
+    .invoke_handler(handlers::invoke_handler())
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|app, event| match event {
+        tauri::RunEvent::ExitRequested { api, .. } => {
+            api.prevent_exit();
+            shutdown::begin(app.clone());
+        },
+        // macOS opens the files associated with the application with an event rather than arguments
+        #[cfg(target_os = "macos")]
+        tauri::RunEvent::Opened { urls } => {
+            let paths: Vec<_> = urls.iter().filter_map(|url| url.to_file_path().ok()).collect();
+            std::thread::spawn(move || file_associations::open_files(&paths));
+        },
+        _ => {},
+    });
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+// markdown.rs
+
+use crate::{ delta, local_operations, vault, models::AppError };
+use pulldown_cmark::{html, Options, Parser};
+use std::collections::HashSet;
+
+
+/// The prefix of the classes kept on code blocks, which name their language for the highlighting of the window.
+const LANGUAGE_CLASS_PREFIX: &str = "language-";
+
+
+/// Renders a note as HTML, from its Markdown.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Operation
+///
+/// The formatting of the editor is converted to Markdown (see `delta::to_markdown`), and the Markdown typed in the
+/// note is kept as it is, so notes can be written in either. The Markdown is then rendered like `render_content`.
+///
+/// # Returns
+///
+/// Returns the sanitized HTML.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it is secure while the vault is locked, or if
+/// there is an issue with the database connection.
+pub fn render_markdown(note_id: i64) -> Result<String, AppError> {
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if note.secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to render secure notes".to_string()));
+    }
+    Ok(render_content(&delta::to_markdown(&note.content)))
+}
+
+
+/// Renders Markdown as HTML that is safe to insert in the window.
+///
+/// # Arguments
+///
+/// * `markdown` - The Markdown, with the tables, strikethrough, task lists, and footnotes of GitHub.
+///
+/// # Operation
+///
+/// The rendered HTML is sanitized: the scripts, styles, event handlers, forms, and frames of any HTML written in the
+/// Markdown are removed, and only the links to "http", "https", and "mailto" addresses, and the images from these or
+/// from data addresses, are kept.
+/// The links open in a new window without access to the application. The checkboxes of the task lists and the
+/// language of the code blocks are kept.
+///
+/// # Returns
+///
+/// Returns the sanitized HTML.
+pub fn render_content(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, Parser::new_ext(markdown, options));
+
+    ammonia::Builder::default()
+        .url_schemes(HashSet::from(["http", "https", "mailto", "data"]))
+        .link_rel(Some("noopener noreferrer"))
+        .set_tag_attribute_value("a", "target", "_blank")
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("code", ["class"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            ("input", "type") if value != "checkbox" => None,
+            // The images inserted in the editor are data addresses, which are not safe as links
+            ("a", "href") if value.starts_with("data:") => None,
+            ("img", "src") if value.starts_with("data:") && !value.starts_with("data:image/") => None,
+            ("code", "class") if !value.starts_with(LANGUAGE_CLASS_PREFIX) => None,
+            _ => Some(value.into()),
+        })
+        .clean(&rendered)
+        .to_string()
+}
@@ -0,0 +1,126 @@
+// memory_budget.rs
+
+use crate::{ search_index, settings, models::MemoryBudgets };
+use lazy_static::lazy_static;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+
+/// The settings key under which the memory budgets are stored.
+const MEMORY_BUDGETS_SETTING: &str = "memory_budgets";
+
+/// The smallest memory budget of an index writer tantivy accepts, in megabytes.
+const MIN_INDEX_WRITER_HEAP_MB: u64 = 15;
+
+/// The largest memory budget of an index writer, in megabytes.
+const MAX_INDEX_WRITER_HEAP_MB: u64 = 4_096;
+
+/// The largest number of note bodies downloaded and decrypted at the same time.
+const MAX_DECRYPTED_BODIES: u64 = 64;
+
+
+lazy_static! {
+    /// The memory budgets, kept in memory since the indexes are created while the database may be locked.
+    static ref MEMORY_BUDGETS: RwLock<MemoryBudgets> = RwLock::new(MemoryBudgets::default());
+
+    /// The permits of the note bodies held in memory, one per body being downloaded and decrypted.
+    /// It is replaced when the budget changes, and the bodies holding a permit of the previous one finish as they are.
+    static ref DECRYPTED_BODIES: RwLock<Arc<Semaphore>> = RwLock::new(Arc::new(Semaphore::new(MemoryBudgets::default().max_decrypted_bodies as usize)));
+}
+
+
+/// Loads the memory budgets from the settings. It is called once at startup, before the search index is built.
+///
+/// # Errors
+///
+/// This function will return an error if the stored budgets are malformed or if there is an issue with the database connection.
+pub fn load_settings() -> Result<(), String> {
+    let budgets = match settings::get_setting(MEMORY_BUDGETS_SETTING)? {
+        Some(budgets) => serde_json::from_str(&budgets).map_err(|e| format!("Invalid memory budgets: {}", e))?,
+        None => MemoryBudgets::default(),
+    };
+
+    apply(budgets);
+    Ok(())
+}
+
+
+/// Retrieves the memory budgets.
+///
+/// # Returns
+///
+/// Returns the memory budget of each index writer in megabytes, 100 by default, and the number of note bodies
+/// downloaded and decrypted at the same time, 4 by default.
+pub fn get_memory_budgets() -> MemoryBudgets {
+    MEMORY_BUDGETS.read().unwrap().clone()
+}
+
+
+/// Sets the memory budgets, to keep the memory used by big synchronizations within what the machine has.
+///
+/// # Arguments
+///
+/// * `budgets` - The memory budget of each index writer, between 15 and 4,096 megabytes, and the number of note
+///   bodies downloaded and decrypted at the same time, between 1 and 64.
+///
+/// # Operation
+///
+/// * The budgets are stored and apply right away to the next downloads.
+/// * The memory budget of the writers only applies to the indexes created afterwards, so the local index is rebuilt
+///   when it changes. The indexes of the buckets pick it up on restart.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the budgets are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if a budget is out of range or if there is an issue with the database connection.
+pub fn set_memory_budgets(budgets: MemoryBudgets) -> Result<(), String> {
+    if !(MIN_INDEX_WRITER_HEAP_MB..=MAX_INDEX_WRITER_HEAP_MB).contains(&budgets.index_writer_heap_mb) {
+        return Err(format!("The index writer heap should be between {} and {} MB", MIN_INDEX_WRITER_HEAP_MB, MAX_INDEX_WRITER_HEAP_MB));
+    }
+    if !(1..=MAX_DECRYPTED_BODIES).contains(&budgets.max_decrypted_bodies) {
+        return Err(format!("The number of decrypted bodies should be between 1 and {}", MAX_DECRYPTED_BODIES));
+    }
+
+    let json = serde_json::to_string(&budgets).map_err(|e| e.to_string())?;
+    settings::set_setting(MEMORY_BUDGETS_SETTING, &json)?;
+
+    let heap_changed = get_memory_budgets().index_writer_heap_mb != budgets.index_writer_heap_mb;
+    apply(budgets);
+    if heap_changed {
+        search_index::schedule_rebuild();
+    }
+    Ok(())
+}
+
+
+/// Returns the memory budget of an index writer, in bytes.
+pub fn index_writer_heap_size() -> usize {
+    let heap_mb = MEMORY_BUDGETS.read().unwrap().index_writer_heap_mb;
+    heap_mb.clamp(MIN_INDEX_WRITER_HEAP_MB, MAX_INDEX_WRITER_HEAP_MB) as usize * 1_000_000
+}
+
+
+/// Waits until a note body can be held in memory, so a big synchronization downloads and decrypts a bounded number
+/// of bodies at once instead of buffering them all.
+///
+/// # Returns
+///
+/// Returns a permit to keep until the body is decrypted and handed over.
+pub async fn acquire_body_permit() -> OwnedSemaphorePermit {
+    let decrypted_bodies = DECRYPTED_BODIES.read().unwrap().clone();
+    // The semaphore is never closed
+    decrypted_bodies.acquire_owned().await.unwrap()
+}
+
+
+/// Keeps the budgets in memory and resizes the permits of the note bodies.
+fn apply(budgets: MemoryBudgets) {
+    let mut memory_budgets = MEMORY_BUDGETS.write().unwrap();
+    if memory_budgets.max_decrypted_bodies != budgets.max_decrypted_bodies {
+        *DECRYPTED_BODIES.write().unwrap() = Arc::new(Semaphore::new(budgets.max_decrypted_bodies.clamp(1, MAX_DECRYPTED_BODIES) as usize));
+    }
+    *memory_budgets = budgets;
+}
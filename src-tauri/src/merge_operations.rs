@@ -0,0 +1,207 @@
+// merge_operations.rs
+
+use crate::{ crypto, delta, local_operations, note_events, notifier, preview, tag_operations, vault, version_operations };
+use crate::local_operations::CONNECTION;
+use crate::models::{AppError, Note};
+use rusqlite::params;
+use serde_json::json;
+
+
+/// The separator inserted between the merged notes when none is given.
+const DEFAULT_SEPARATOR: &str = "\n";
+
+
+/// Merges several local notes into a single new note.
+///
+/// # Arguments
+///
+/// * `ids` - The IDs of the notes to merge, in the order their content is combined.
+/// * `separator` - The text inserted between the notes. Defaults to an empty line.
+///
+/// # Operation
+///
+/// * Each note becomes a section of the merged note, starting with its title as a header.
+/// * The merged note takes the title of the first note and the oldest creation date. It is secure if any note is.
+///   It takes the first icon of the notes, and the attachments and the tags of every note.
+/// * In a single transaction, a snapshot of each note is saved in the "note_versions" table, the merged note is
+///   created, the attachments are moved to it, and the notes are deleted. The notes can be recovered with `restore_note_version`.
+///
+/// # Returns
+///
+/// Returns the ID of the merged note, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if fewer than two notes are given, if a note does not exist, if a note is secure
+/// while the vault is locked, if a note is locked (see `local_operations::set_note_locked`), if the merged note would be
+/// too long or have too many tags, or if there is an issue with the database connection.
+pub fn merge_notes(ids: Vec<i64>, separator: Option<String>) -> Result<i64, AppError> {
+    if ids.len() < 2 {
+        return Err(AppError::Validation("At least two notes are required to merge".to_string()));
+    }
+
+    // Get the notes in the requested order
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let params: Vec<&dyn rusqlite::ToSql> = ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let found = local_operations::get_local_notes_where(&format!("id IN ({})", placeholders), &params)?;
+    let mut notes = Vec::new();
+    for id in &ids {
+        let note = found.iter().find(|note| note.id == Some(*id)).ok_or(AppError::NotFound(format!("Note {} not found", id)))?;
+        if note.secure && !vault::is_unlocked() {
+            return Err(AppError::Encryption("Unlock the vault to merge secure notes".to_string()));
+        }
+        if note.locked {
+            return Err(AppError::Validation(format!("The note '{}' is locked. Unlock it to merge it.", note.title)));
+        }
+        notes.push(note);
+    }
+
+    // Combine the notes, each under a header with its title
+    let separator = separator.unwrap_or_else(|| DEFAULT_SEPARATOR.to_string());
+    let mut ops = Vec::new();
+    for (index, note) in notes.iter().enumerate() {
+        if index > 0 && !separator.is_empty() {
+            ops.push(json!({ "insert": separator }));
+        }
+        ops.extend(delta::header_ops(&note.title, 2));
+        ops.extend(delta::parse_ops(&note.content));
+    }
+
+    let merged_note = Note {
+        id: None,
+        uuid: None,
+        title: notes[0].title.clone(),
+        content: delta::to_content(ops),
+        nonce: None,
+        created_at: notes.iter().map(|note| note.created_at).min().unwrap_or(0),
+        updated_at: None,
+        timestamp: None,
+        secure: notes.iter().any(|note| note.secure),
+        status: notes[0].status.clone(),
+        icon: notes.iter().find_map(|note| note.icon.clone()),
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: tag_operations::check_tags(&notes.iter().flat_map(|note| note.tags.clone()).collect::<Vec<_>>())?,
+    };
+    local_operations::validate_params(merged_note.clone())?;
+
+    // Replace the notes with the merged note in a single transaction
+    let merged_id = {
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let merged_id = local_operations::insert_note(&tx, &merged_note)?;
+        note_events::note_saved(&tx, merged_id, &merged_note.content)?;
+        for id in &ids {
+            version_operations::snapshot_note(&tx, *id, "merge")?;
+            // Move the attachments first, so they are not deleted with the note
+            tx.execute("UPDATE attachments SET note_id = ?1 WHERE note_id = ?2", params![merged_id, id])?;
+            tx.execute("DELETE FROM notes WHERE id = ?1", params![id])?;
+            note_events::note_deleted(&tx, *id)?;
+        }
+
+        tx.commit()?;
+        merged_id
+    };
+
+    // Send a desktop notification
+    notifier::notify("Notes merged", &format!("{} notes were merged into '{}'.", ids.len(), merged_note.title));
+
+    Ok(merged_id)
+}
+
+
+/// Splits a local note into several notes at each occurrence of a delimiter.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note to split.
+/// * `delimiter` - The text to split at, e.g. a horizontal rule. It is removed from the notes.
+///
+/// # Operation
+///
+/// * The note keeps the first part. Each other part becomes a new note titled after its first line,
+///   or after the note with its part number if the line is empty.
+/// * The new notes keep the creation date, the secure flag, and the tags of the note.
+/// * In a single transaction, a snapshot of the note is saved in the "note_versions" table, the note is updated,
+///   and the new notes are created.
+///
+/// # Returns
+///
+/// Returns the IDs of the notes, starting with the split note, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the delimiter is empty or not found, if the note does not exist, is secure
+/// while the vault is locked, or is locked (see `local_operations::set_note_locked`), or if there is an issue with the
+/// database connection.
+pub fn split_note(note_id: i64, delimiter: &str) -> Result<Vec<i64>, AppError> {
+    if delimiter.is_empty() {
+        return Err(AppError::Validation("Delimiter cannot be empty".to_string()));
+    }
+
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if note.secure && !vault::is_unlocked() {
+        return Err(AppError::Encryption("Unlock the vault to split secure notes".to_string()));
+    }
+    if note.locked {
+        return Err(AppError::Validation(format!("The note '{}' is locked. Unlock it to split it.", note.title)));
+    }
+
+    let parts: Vec<_> = delta::split_ops(&delta::parse_ops(&note.content), delimiter)
+        .into_iter()
+        .filter(|ops| !preview::plain_text(&delta::to_content(ops.clone())).trim().is_empty())
+        .collect();
+    if parts.len() < 2 {
+        return Err(AppError::Validation("Delimiter not found".to_string()));
+    }
+
+    // Build the new notes, titled after their first line
+    let mut new_notes = Vec::new();
+    for (index, ops) in parts.iter().enumerate().skip(1) {
+        let content = delta::to_content(ops.clone());
+        let first_line = preview::plain_text(&content).lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default().to_string();
+        let title = if first_line.is_empty() {
+            format!("{} ({})", note.title, index + 1)
+        } else {
+            first_line.chars().take(100).collect()
+        };
+        // The aliases stay with the note that is split, and the tags go with every part
+        new_notes.push(Note { uuid: None, title, content, aliases: Vec::new(), ..note.clone() });
+    }
+    let first_content = delta::to_content(parts[0].clone());
+
+    // Update the note and create the new notes in a single transaction
+    let mut ids = vec![note_id];
+    {
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        version_operations::snapshot_note(&tx, note_id, "split")?;
+        let (encrypted_content, nonce_str) = crypto::encrypt_content(&first_content)?;
+        let note_preview = (!note.secure).then(|| preview::build_preview(&first_content));
+        tx.execute(
+            "UPDATE notes SET content = ?1, nonce = ?2, updated_at = ?3, timestamp = ?4, preview = ?5 WHERE id = ?6",
+            params![encrypted_content, nonce_str, chrono::Utc::now().timestamp(), chrono::Utc::now().to_rfc3339(), note_preview, note_id],
+        )?;
+        note_events::note_saved(&tx, note_id, &first_content)?;
+        for new_note in &new_notes {
+            let new_id = local_operations::insert_note(&tx, new_note)?;
+            note_events::note_saved(&tx, new_id, &new_note.content)?;
+            ids.push(new_id);
+        }
+
+        tx.commit()?;
+    }
+
+    // Send a desktop notification
+    notifier::notify("Note split", &format!("Note with title '{}' was split into {} notes.", note.title, ids.len()));
+
+    Ok(ids)
+}
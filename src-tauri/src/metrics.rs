@@ -0,0 +1,169 @@
+// metrics.rs
+
+use crate::local_operations::CONNECTION;
+use crate::models::{CommandMetrics, UsageMetrics};
+use crate::settings;
+use lazy_static::lazy_static;
+use rusqlite::params;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+
+/// The settings key under which the opt-in to the usage metrics is stored, as "true" or "false".
+const METRICS_ENABLED_SETTING: &str = "usage_metrics_enabled";
+
+/// The number of days covered by the metrics when no period is given.
+const DEFAULT_PERIOD_DAYS: u32 = 30;
+
+
+lazy_static! {
+    /// Whether the usage metrics are recorded, read once from the settings so recording a command stays cheap.
+    static ref METRICS_ENABLED: AtomicBool = AtomicBool::new(
+        settings::get_setting(METRICS_ENABLED_SETTING).ok().flatten().as_deref() == Some("true")
+    );
+}
+
+
+/// Records a command in the usage metrics of the current day, if the user opted in.
+///
+/// # Arguments
+///
+/// * `command` - The name of the command.
+/// * `succeeded` - Whether the command succeeded.
+/// * `duration` - How long the command took.
+///
+/// # Operation
+///
+/// Only the name of the command, its outcome, and its duration are recorded, never its arguments or its result.
+/// The counters are kept in the "command_metrics" table and never leave the device unless the user exports them
+/// (see `export_usage_metrics`).
+///
+/// Failing to record a command never fails the command itself, so errors are only printed.
+/// It must not be called while the database connection is locked.
+pub fn record_command(command: &str, succeeded: bool, duration: Duration) {
+    if !METRICS_ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let duration_ms = duration.as_millis() as i64;
+    let conn = CONNECTION.lock().unwrap();
+    if let Err(e) = conn.execute(
+        "INSERT INTO command_metrics (day, command, calls, errors, total_ms, max_ms) VALUES (?1, ?2, 1, ?3, ?4, ?4)
+        ON CONFLICT (day, command) DO UPDATE SET calls = calls + 1, errors = errors + ?3, total_ms = total_ms + ?4,
+        max_ms = MAX(max_ms, ?4)",
+        params![current_day(), command, !succeeded as i64, duration_ms],
+    ) {
+        eprintln!("Failed to record usage metrics: {}", e);
+    }
+}
+
+
+/// Retrieves the usage metrics of the commands, for the local dashboard.
+///
+/// # Arguments
+///
+/// * `days` - The number of days covered, including today. Defaults to 30.
+///
+/// # Returns
+///
+/// Returns a `UsageMetrics` with whether the metrics are recorded, and the calls, error rate, and durations of each
+/// command over the period, the most called first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_usage_metrics(days: Option<u32>) -> Result<UsageMetrics, String> {
+    let days = days.unwrap_or(DEFAULT_PERIOD_DAYS).max(1);
+    let since = (chrono::Utc::now() - chrono::Duration::days(days as i64 - 1)).format("%Y-%m-%d").to_string();
+
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT command, SUM(calls), SUM(errors), SUM(total_ms), MAX(max_ms) FROM command_metrics
+        WHERE day >= ?1 GROUP BY command ORDER BY SUM(calls) DESC, command"
+    ).map_err(|e| e.to_string())?;
+    let metrics_iter = stmt.query_map(params![since], |row| {
+        let calls: i64 = row.get(1)?;
+        let errors: i64 = row.get(2)?;
+        let total_ms: i64 = row.get(3)?;
+        Ok(CommandMetrics {
+            command: row.get(0)?,
+            calls,
+            errors,
+            error_rate: errors as f64 / calls.max(1) as f64,
+            average_ms: total_ms as f64 / calls.max(1) as f64,
+            max_ms: row.get(4)?,
+        })
+    }).map_err(|e| e.to_string())?;
+    let commands = metrics_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+    Ok(UsageMetrics {
+        enabled: METRICS_ENABLED.load(Ordering::SeqCst),
+        since,
+        total_calls: commands.iter().map(|metrics| metrics.calls).sum(),
+        total_errors: commands.iter().map(|metrics| metrics.errors).sum(),
+        commands,
+    })
+}
+
+
+/// Opts in or out of the usage metrics.
+///
+/// # Arguments
+///
+/// * `enabled` - Whether the commands are recorded from now on. Opting out keeps the metrics recorded so far,
+///   which can be removed with `purge_usage_metrics`.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the choice is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn set_usage_metrics_enabled(enabled: bool) -> Result<(), String> {
+    settings::set_setting(METRICS_ENABLED_SETTING, &enabled.to_string())?;
+    METRICS_ENABLED.store(enabled, Ordering::SeqCst);
+    Ok(())
+}
+
+
+/// Removes every usage metric recorded so far.
+///
+/// # Returns
+///
+/// Returns the number of removed daily counters, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn purge_usage_metrics() -> Result<usize, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute("DELETE FROM command_metrics", []).map_err(|e| e.to_string())
+}
+
+
+/// Exports the usage metrics to a JSON file chosen by the user, the only way they leave the application.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to write.
+/// * `days` - The number of days covered, including today. Defaults to 30.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file is written successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the metrics cannot be retrieved or if the file cannot be written.
+pub fn export_usage_metrics(path: &str, days: Option<u32>) -> Result<(), String> {
+    let metrics = get_usage_metrics(days)?;
+    let json = serde_json::to_string_pretty(&metrics).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+
+/// Returns the current day in "YYYY-MM-DD" format.
+fn current_day() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
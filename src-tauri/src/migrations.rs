@@ -0,0 +1,289 @@
+// migrations.rs
+
+use crate::{ local_operations::CONNECTION, models::AppliedMigration, models::SchemaStatus };
+use rusqlite::{ params, Connection };
+
+
+/// A change to the schema of the database, applied once and in order.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    /// The columns added to existing tables, as (table, column, definition). They are added before `sql` is run, and
+    /// only to the tables that do not have them yet, since the tables created by this version already do.
+    columns: &'static [(&'static str, &'static str, &'static str)],
+    sql: &'static str,
+}
+
+
+/// The migrations of the schema, by increasing version. A migration is never changed once released: a new one is
+/// appended instead.
+///
+/// The tables of the previous versions are still created when the database is opened (see
+/// `local_operations::CONNECTION`), with all their columns for a new database, so the migrations start from the schema
+/// they produce and add columns only where they are missing.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Add the columns of the notes and the attachments added after their tables were first created",
+        columns: &[
+            ("notes", "secure", "INTEGER NOT NULL DEFAULT 0"),
+            ("notes", "preview", "TEXT"),
+            ("notes", "status", "TEXT"),
+            ("notes", "icon", "TEXT"),
+            ("notes", "cover_attachment_id", "INTEGER"),
+            ("notes", "locked", "INTEGER NOT NULL DEFAULT 0"),
+            ("notes", "pinned", "INTEGER NOT NULL DEFAULT 0"),
+            ("notes", "archived", "INTEGER NOT NULL DEFAULT 0"),
+            ("notes", "notebook_id", "INTEGER REFERENCES notebooks(id)"),
+            ("notes", "deleted_at", "INTEGER"),
+            ("attachments", "annotated_at", "INTEGER"),
+        ],
+        sql: "",
+    },
+    Migration {
+        version: 2,
+        description: "Index the notes by UUID and notebook, and the notes of each tag",
+        columns: &[],
+        sql: "CREATE INDEX IF NOT EXISTS notes_uuid ON notes (uuid);
+            CREATE INDEX IF NOT EXISTS notes_notebook_id ON notes (notebook_id);
+            CREATE INDEX IF NOT EXISTS note_tags_tag_id ON note_tags (tag_id);",
+    },
+    Migration {
+        version: 3,
+        description: "Encrypt the AWS SSO access token, dropping the plaintext one so the user signs in again",
+        columns: &[
+            ("sso_session", "access_token_nonce", "TEXT"),
+        ],
+        sql: "UPDATE sso_session SET access_token = NULL, expires_at = 0 WHERE access_token IS NOT NULL AND access_token_nonce IS NULL;",
+    },
+];
+
+
+/// Checks that the database was not migrated by a newer version of the application, before anything else touches it.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Operation
+///
+/// * A table named "schema_version" is created to record the migrations applied, with when they were applied.
+///
+/// # Returns
+///
+/// Returns the schema version of the database, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the database is at a version this version of the application does not know,
+/// or if there is an issue with the database connection.
+pub fn check_version(conn: &Connection) -> Result<u32, String> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+        version INTEGER PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at INTEGER NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    let version = current_version(conn)?;
+    if version > latest_version() {
+        return Err(format!(
+            "The database is at schema version {}, but this version of the application only knows version {}: update the application",
+            version,
+            latest_version(),
+        ));
+    }
+
+    Ok(version)
+}
+
+
+/// Applies the migrations the database has not received yet.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection, with the tables of `local_operations::CONNECTION` created.
+///
+/// # Operation
+///
+/// * Each pending migration is applied in a transaction with its record, so a migration that fails leaves the
+///   database as it was and is tried again on the next start.
+///
+/// # Returns
+///
+/// Returns the number of migrations applied, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the database was migrated by a newer version of the application, or if a
+/// migration fails.
+pub fn run(conn: &Connection) -> Result<usize, String> {
+    let version = check_version(conn)?;
+
+    let mut applied = 0;
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > version) {
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let failed = |e: rusqlite::Error| format!("Migration {} ({}) failed: {}", migration.version, migration.description, e);
+        for (table, column, definition) in migration.columns {
+            add_column_if_missing(&tx, table, column, definition).map_err(failed)?;
+        }
+        tx.execute_batch(migration.sql).map_err(failed)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.description, chrono::Utc::now().timestamp()],
+        ).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+
+/// Retrieves the schema version of the database and the migrations it received.
+///
+/// # Returns
+///
+/// Returns the version of the database, the latest version known to the application, and the migrations applied
+/// from the oldest, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_schema_status() -> Result<SchemaStatus, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT version, description, applied_at FROM schema_version ORDER BY version")
+        .map_err(|e| e.to_string())?;
+    let migrations = stmt.query_map([], |row| {
+        Ok(AppliedMigration {
+            version: row.get(0)?,
+            description: row.get(1)?,
+            applied_at: row.get(2)?,
+        })
+    }).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(SchemaStatus {
+        version: current_version(&conn)?,
+        latest_version: latest_version(),
+        migrations,
+    })
+}
+
+
+/// Adds a column to a table if it does not exist yet.
+///
+/// `CREATE TABLE IF NOT EXISTS` leaves the tables of existing databases untouched, so columns added after a table was
+/// first released must be added with `ALTER TABLE`, while the tables created since already have them.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `table` - The name of the table.
+/// * `column` - The name of the column to add.
+/// * `definition` - The type and constraints of the column.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+
+    Ok(())
+}
+
+
+/// Reads the version of the last migration applied to the database, 0 if none was.
+fn current_version(conn: &Connection) -> Result<u32, String> {
+    conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())
+}
+
+
+/// The version of the last migration known to the application.
+fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens an in-memory database with the tables as first released, before any migration.
+    fn open_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, uuid TEXT, title TEXT NOT NULL, content TEXT NOT NULL);
+            CREATE TABLE attachments (id INTEGER PRIMARY KEY, note_id INTEGER NOT NULL);
+            CREATE TABLE notebooks (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            CREATE TABLE note_tags (note_id INTEGER NOT NULL, tag_id INTEGER NOT NULL);
+            CREATE TABLE sso_session (id INTEGER PRIMARY KEY, region TEXT NOT NULL, access_token TEXT, expires_at INTEGER);"
+        ).unwrap();
+        conn
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        let columns = stmt.query_map([], |row| row.get(1)).unwrap().collect::<rusqlite::Result<Vec<_>>>().unwrap();
+        columns
+    }
+
+    #[test]
+    fn run_applies_the_migrations_once() {
+        let conn = open_database();
+        assert_eq!(run(&conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert!(columns(&conn, "notes").contains(&"notebook_id".to_string()));
+        assert!(columns(&conn, "attachments").contains(&"annotated_at".to_string()));
+
+        assert_eq!(run(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn run_drops_the_plaintext_sso_token() {
+        let conn = open_database();
+        conn.execute("INSERT INTO sso_session (id, region, access_token, expires_at) VALUES (1, 'eu-west-1', 'token', 4102444800)", []).unwrap();
+        run(&conn).unwrap();
+        let (access_token, expires_at): (Option<String>, i64) = conn.query_row(
+            "SELECT access_token, expires_at FROM sso_session WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!(access_token, None);
+        assert_eq!(expires_at, 0);
+    }
+
+    #[test]
+    fn run_keeps_the_columns_already_there() {
+        let conn = open_database();
+        conn.execute("ALTER TABLE notes ADD COLUMN secure INTEGER NOT NULL DEFAULT 0", []).unwrap();
+        assert_eq!(run(&conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(columns(&conn, "notes").iter().filter(|column| *column == "secure").count(), 1);
+    }
+
+    #[test]
+    fn run_rolls_back_a_failed_migration() {
+        let conn = open_database();
+        conn.execute("DROP TABLE attachments", []).unwrap();
+        assert!(run(&conn).unwrap_err().starts_with("Migration 1 "));
+        assert_eq!(current_version(&conn).unwrap(), 0);
+        assert!(!columns(&conn, "notes").contains(&"secure".to_string()));
+    }
+
+    #[test]
+    fn run_refuses_a_newer_database() {
+        let conn = open_database();
+        check_version(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, 'Newer', 0)",
+            params![latest_version() + 1],
+        ).unwrap();
+        assert!(run(&conn).is_err());
+    }
+}
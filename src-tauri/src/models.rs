@@ -1,6 +1,7 @@
 // models.rs
 
 use aws_sdk_s3 as s3;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use s3::error::SdkError;
 use std::error::Error as StdError;
@@ -17,6 +18,1471 @@ pub struct Note {
     pub created_at: i64,
     pub updated_at: Option<i64>,
     pub timestamp: Option<String>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub cover_attachment_id: Option<i64>,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub notebook_id: Option<i64>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteSummary {
+    pub id: i64,
+    pub uuid: Option<String>,
+    pub title: String,
+    pub preview: Option<String>,
+    pub created_at: i64,
+    pub updated_at: Option<i64>,
+    pub timestamp: Option<String>,
+    pub secure: bool,
+    pub icon: Option<String>,
+    pub locked: bool,
+    pub pinned: bool,
+    pub archived: bool,
+    pub notebook_id: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct Notebook {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub path: String,
+    pub note_count: i64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivedFilter {
+    #[default]
+    Exclude,
+    Include,
+    Only,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Default)]
+pub struct NoteListOptions {
+    #[serde(default)]
+    pub archived: ArchivedFilter,
+    #[serde(default)]
+    pub pinned_only: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NotesPage {
+    pub notes: Vec<Note>,
+    pub offset: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct NotebookSettings {
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub bucket: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Text,
+    Number,
+    Date,
+    Select,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct CustomField {
+    pub name: String,
+    pub kind: FieldKind,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NotebookCsvImport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub skipped_columns: Vec<String>,
+    pub errors: Vec<CsvRowError>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CsvRowError {
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldOp {
+    #[default]
+    Eq,
+    Lt,
+    Gt,
+    Contains,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct FieldFilter {
+    pub name: String,
+    #[serde(default)]
+    pub op: FieldOp,
+    pub value: String,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Default)]
+pub struct FieldListOptions {
+    #[serde(default)]
+    pub fields: Vec<FieldFilter>,
+    #[serde(default)]
+    pub sort_field: Option<String>,
+    #[serde(default)]
+    pub sort_descending: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TrashedNote {
+    pub id: i64,
+    pub uuid: Option<String>,
+    pub title: String,
+    pub preview: Option<String>,
+    pub secure: bool,
+    pub icon: Option<String>,
+    pub deleted_at: i64,
+    pub purge_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct Backlink {
+    pub note_id: i64,
+    pub title: String,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceKind {
+    Wikilink,
+    Attachment,
+    Url,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct BrokenReference {
+    pub kind: ReferenceKind,
+    pub reference: String,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteLinkReport {
+    pub note_id: i64,
+    pub title: String,
+    pub broken: Vec<BrokenReference>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct UnlinkedMention {
+    pub note_id: i64,
+    pub title: String,
+    pub text: String,
+    pub index: usize,
+    pub length: usize,
+    pub line: usize,
+    pub context: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ReviewSchedule {
+    pub note_id: i64,
+    pub ease: f64,
+    pub interval_days: i64,
+    pub repetitions: i64,
+    pub due_at: i64,
+    pub reviewed_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DueReview {
+    pub note_id: i64,
+    pub title: String,
+    pub icon: Option<String>,
+    pub due_at: i64,
+    pub interval_days: i64,
+    pub repetitions: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CardKind {
+    Question,
+    Cloze,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct Flashcard {
+    pub id: i64,
+    pub note_id: i64,
+    pub note_title: String,
+    pub kind: CardKind,
+    pub front: String,
+    pub back: String,
+    pub ease: f64,
+    pub interval_days: i64,
+    pub repetitions: i64,
+    pub due_at: i64,
+    pub reviewed_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeckScope {
+    All,
+    Note { note_id: i64 },
+    Tag { tag: String },
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DeckStats {
+    pub note_id: i64,
+    pub title: String,
+    pub cards: i64,
+    pub new: i64,
+    pub due: i64,
+    pub mature: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct FocusSession {
+    pub id: i64,
+    pub note_id: i64,
+    pub note_title: String,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    pub planned_minutes: Option<i64>,
+    pub duration_seconds: i64,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeGroup {
+    Note,
+    Day,
+    Tag,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TimeReportEntry {
+    pub key: String,
+    pub note_id: Option<i64>,
+    pub label: String,
+    pub seconds: i64,
+    pub sessions: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct QuickSwitchMatch {
+    pub id: i64,
+    pub title: String,
+    pub icon: Option<String>,
+    pub alias: Option<String>,
+    pub positions: Vec<usize>,
+    pub score: f64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SsoDeviceAuthorization {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: i32,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default)]
+pub struct CloudBudget {
+    pub max_requests: Option<i64>,
+    pub max_bytes: Option<i64>,
+    pub pause_sync: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct RequestUsage {
+    pub request_type: String,
+    pub request_count: i64,
+    pub bytes: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CloudUsageReport {
+    pub month: String,
+    pub requests: Vec<RequestUsage>,
+    pub total_requests: i64,
+    pub total_bytes: i64,
+    pub estimated_cost_usd: f64,
+    pub budget: CloudBudget,
+    pub budget_exceeded: bool,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct SyncSettings {
+    pub enabled: bool,
+    pub buckets: Vec<String>,
+    #[serde(default = "default_sync_interval")]
+    pub interval_minutes: u32,
+}
+
+fn default_sync_interval() -> u32 {
+    15
+}
+
+#[derive(Debug, serde::Serialize, Clone, Default)]
+pub struct SyncSummary {
+    pub bucket_name: String,
+    pub created_locally: i64,
+    pub created_in_bucket: i64,
+    pub updated_locally: i64,
+    pub updated_in_bucket: i64,
+    pub deleted_locally: i64,
+    pub deleted_in_bucket: i64,
+    pub unchanged: i64,
+    pub conflicts: Vec<SyncedNote>,
+    pub skipped: Vec<SkippedNote>,
+    pub plain_notes: Vec<SyncedNote>,
+    pub synced_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SyncedNote {
+    pub uuid: String,
+    pub title: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SyncConflict {
+    pub bucket_name: String,
+    pub uuid: String,
+    pub local_title: String,
+    pub remote_title: String,
+    pub local_updated_at: i64,
+    pub remote_updated_at: i64,
+    pub detected_at: i64,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum ConflictStrategy {
+    #[serde(rename = "keep_local")]
+    Local,
+    #[serde(rename = "keep_remote")]
+    Remote,
+    #[serde(rename = "keep_both")]
+    Both,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SkippedNote {
+    pub uuid: String,
+    pub title: String,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default)]
+pub struct SettingsProfile {
+    pub version: u32,
+    pub exported_at: i64,
+    pub locale: Option<String>,
+    pub display_name: Option<String>,
+    pub status_workflow: Option<StatusWorkflow>,
+    pub periodic_notes: Option<PeriodicNoteSettings>,
+    pub journal_context: Option<JournalContextConfig>,
+    pub notifications_muted: Option<bool>,
+    pub quiet_hours: Option<QuietHours>,
+    pub memory_budgets: Option<MemoryBudgets>,
+    pub index_protection: Option<IndexProtection>,
+    pub update_manifest_url: Option<String>,
+    pub cloud_budget: Option<CloudBudget>,
+    pub sync: Option<SyncSettings>,
+    pub email_ingestion: Option<EmailIngestionConfig>,
+    pub email_smtp: Option<SmtpConfig>,
+    pub capture_bridge: Option<CaptureBridgeConfig>,
+    pub proxy: Option<ProxyConfig>,
+    #[serde(default)]
+    pub features: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SettingsImport {
+    pub imported: Vec<String>,
+    pub skipped: Vec<SkippedSetting>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SkippedSetting {
+    pub setting: String,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub struct WorkspaceState {
+    pub open_note_ids: Vec<i64>,
+    pub active_note_id: Option<i64>,
+    pub query: Option<String>,
+    pub sort_by: Option<String>,
+    pub sort_descending: bool,
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct WorkspaceSnapshot {
+    pub name: String,
+    pub state: WorkspaceState,
+    pub saved_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct Attachment {
+    pub id: i64,
+    pub note_id: i64,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub created_at: i64,
+    pub annotated_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DroppedFiles {
+    pub note_ids: Vec<i64>,
+    pub attachments: Vec<Attachment>,
+    pub failures: Vec<DropFailure>,
+}
+
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportOutcome {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ImportedNote {
+    pub note_id: i64,
+    pub uuid: String,
+    pub outcome: ImportOutcome,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DirectoryImport {
+    pub imported: Vec<ImportedNote>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DropFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct CnoteFile {
+    pub format: String,
+    pub version: u32,
+    pub title: String,
+    pub content: String,
+    pub status: Option<String>,
+    pub icon: Option<String>,
+    pub created_at: i64,
+    pub updated_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct PlainSyncNote {
+    pub note_id: i64,
+    pub uuid: String,
+    pub title: String,
+    pub secure: bool,
+    pub enabled_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TrayMenuItem {
+    pub note_id: i64,
+    pub title: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct AttachmentData {
+    pub attachment: Attachment,
+    pub data: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct AttachmentText {
+    pub attachment_id: i64,
+    pub status: String,
+    pub error: Option<String>,
+    pub characters: Option<i64>,
+    pub extracted_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct QuietHours {
+    pub enabled: bool,
+    pub start: String,
+    pub end: String,
+    #[serde(default)]
+    pub days: Vec<u32>,
+}
+
+impl Default for QuietHours {
+    fn default() -> QuietHours {
+        QuietHours {
+            enabled: false,
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+            days: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NotificationSettings {
+    pub muted: bool,
+    pub quiet_hours: QuietHours,
+    pub in_quiet_hours: bool,
+    pub os_do_not_disturb: Option<bool>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsRange {
+    Week,
+    Month,
+    Quarter,
+    Year,
+    All,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct VaultStats {
+    pub day: String,
+    pub note_count: i64,
+    pub secure_count: i64,
+    pub total_size: i64,
+    pub attachment_count: i64,
+    pub attachment_size: i64,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct EmailIngestionConfig {
+    pub enabled: bool,
+    pub host: String,
+    #[serde(default = "default_imap_port")]
+    pub port: u16,
+    pub username: String,
+    #[serde(default = "default_email_folder")]
+    pub folder: String,
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_minutes: u32,
+}
+
+fn default_imap_port() -> u16 {
+    993
+}
+
+fn default_email_folder() -> String {
+    "INBOX".to_string()
+}
+
+fn default_poll_interval() -> u32 {
+    15
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct EmailIngestionSettings {
+    pub config: Option<EmailIngestionConfig>,
+    pub has_password: bool,
+    pub last_fetched_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: String,
+    pub from: String,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SmtpSettings {
+    pub config: Option<SmtpConfig>,
+    pub has_password: bool,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeKind {
+    Telegram,
+    Matrix,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct CaptureBridgeConfig {
+    pub enabled: bool,
+    pub kind: BridgeKind,
+    #[serde(default)]
+    pub homeserver: Option<String>,
+    #[serde(default)]
+    pub room_id: Option<String>,
+    #[serde(default)]
+    pub allowed_senders: Vec<String>,
+    #[serde(default = "default_bridge_poll_interval")]
+    pub poll_interval_seconds: u32,
+}
+
+fn default_bridge_poll_interval() -> u32 {
+    30
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct ProxyConfig {
+    pub enabled: bool,
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct S3Config {
+    #[serde(default)]
+    pub endpoint_url: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default = "default_true")]
+    pub force_path_style: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config { endpoint_url: None, region: None, force_path_style: true }
+    }
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ProxySettings {
+    pub config: Option<ProxyConfig>,
+    pub has_password: bool,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct TlsRemote {
+    pub host: String,
+    #[serde(default)]
+    pub ca_bundle: Option<String>,
+    #[serde(default)]
+    pub pinned_certificate: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TlsConnectionTest {
+    pub host: String,
+    pub success: bool,
+    pub tls_error: bool,
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CaptureBridgeSettings {
+    pub config: Option<CaptureBridgeConfig>,
+    pub has_token: bool,
+}
+
+#[cfg_attr(not(feature = "benchmarks"), allow(dead_code))]
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct BenchmarkResult {
+    pub name: String,
+    pub mean_ns: f64,
+    pub std_dev_ns: f64,
+    pub change: Option<f64>,
+}
+
+#[cfg_attr(not(feature = "benchmarks"), allow(dead_code))]
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct BenchmarkReport {
+    pub ran_at: i64,
+    pub version: String,
+    pub results: Vec<BenchmarkResult>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CommandArg {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub required: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CommandDescriptor {
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    pub args: Vec<CommandArg>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    ReadOnly,
+    ReadWrite,
+    Admin,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CreatedApiToken {
+    pub token: ApiToken,
+    pub secret: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+pub struct VaultLockOwner {
+    pub pid: u32,
+    pub device_id: String,
+    pub host_name: Option<String>,
+    pub user_name: Option<String>,
+    pub acquired_at: i64,
+    pub heartbeat_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct VaultStatus {
+    pub unlocked: bool,
+    pub passphrase: bool,
+    pub password: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ReencryptionProgress {
+    pub in_progress: bool,
+    pub total: i64,
+    pub done: i64,
+    pub failed: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ReencryptionNote {
+    pub note_id: i64,
+    pub title: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub updated_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ReencryptionStatus {
+    pub progress: ReencryptionProgress,
+    pub notes: Vec<ReencryptionNote>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct VaultLockStatus {
+    pub held: bool,
+    pub owner: Option<VaultLockOwner>,
+    pub stale: bool,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct MemoryBudgets {
+    pub index_writer_heap_mb: u64,
+    pub max_decrypted_bodies: u64,
+}
+
+impl Default for MemoryBudgets {
+    fn default() -> MemoryBudgets {
+        MemoryBudgets {
+            index_writer_heap_mb: 100,
+            max_decrypted_bodies: 4,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct NoteTemplate {
+    pub name: String,
+    pub title: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TemplatePrompt {
+    pub label: String,
+    pub default: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct TemplateOutcome {
+    pub note: Option<Note>,
+    pub prompts: Vec<TemplatePrompt>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(tag = "every", rename_all = "snake_case")]
+pub enum Recurrence {
+    Day,
+    Week { weekday: u32 },
+    Month { day: u32 },
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct NoteScheduleConfig {
+    pub template: String,
+    pub recurrence: Recurrence,
+    pub time: String,
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+    #[serde(default)]
+    pub catch_up: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteSchedule {
+    pub id: i64,
+    pub config: NoteScheduleConfig,
+    pub next_run_at: i64,
+    pub last_run_at: Option<i64>,
+    pub last_note_id: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodKind {
+    Daily,
+    Weekly,
+    Monthly,
+    Quarterly,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct PeriodicNoteFormat {
+    pub title_format: String,
+    #[serde(default)]
+    pub template: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct PeriodicNoteSettings {
+    pub daily: PeriodicNoteFormat,
+    pub weekly: PeriodicNoteFormat,
+    pub monthly: PeriodicNoteFormat,
+    pub quarterly: PeriodicNoteFormat,
+}
+
+impl Default for PeriodicNoteSettings {
+    fn default() -> PeriodicNoteSettings {
+        let format = |title_format: &str| PeriodicNoteFormat {
+            title_format: title_format.to_string(),
+            template: String::new(),
+        };
+        PeriodicNoteSettings {
+            daily: format("%Y-%m-%d"),
+            weekly: format("%G-W%V"),
+            monthly: format("%Y-%m"),
+            quarterly: format("%Y-Q{{quarter}}"),
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct JournalContextConfig {
+    pub enabled: bool,
+    #[serde(default = "default_true")]
+    pub day_of_week: bool,
+    #[serde(default)]
+    pub weather: bool,
+    #[serde(default)]
+    pub sun: bool,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub temperature_unit: TemperatureUnit,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct JournalContextSettings {
+    pub config: Option<JournalContextConfig>,
+    pub has_api_key: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct Period {
+    pub kind: PeriodKind,
+    pub start: String,
+    pub end: String,
+    pub title: String,
+    pub note_id: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct PeriodicNote {
+    pub period: Period,
+    pub note: Note,
+    pub created: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct FeatureFlag {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct UpdateInfo {
+    pub available: bool,
+    pub current_version: String,
+    pub latest_version: Option<String>,
+    pub date: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct InventoryEntry {
+    pub key: String,
+    pub uuid: Option<String>,
+    pub size: i64,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct MigratedObject {
+    pub old_key: String,
+    pub new_key: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SkippedObject {
+    pub key: String,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone, Default)]
+pub struct BucketFormatMigration {
+    pub migrated: Vec<MigratedObject>,
+    pub duplicates_removed: Vec<String>,
+    pub skipped: Vec<SkippedObject>,
+}
+
+#[derive(Debug, serde::Serialize, Clone, Default)]
+pub struct BucketKeyMigration {
+    pub migrated: Vec<String>,
+    pub duplicates_removed: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct OrphanedObject {
+    pub key: String,
+    pub size: i64,
+    pub last_modified: Option<String>,
+    pub issues: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub problems: Vec<String>,
+    pub fixed: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct DoctorReport {
+    pub fix: bool,
+    pub checked_at: i64,
+    pub checks: Vec<DoctorCheck>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum Cipher {
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CipherBenchmark {
+    pub cipher: Cipher,
+    pub megabytes_per_second: f64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CipherSelection {
+    pub cipher: Cipher,
+    pub benchmarks: Vec<CipherBenchmark>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteEnvelope {
+    pub location: String,
+    pub uuid: Option<String>,
+    pub secure: bool,
+    pub cipher: String,
+    pub key_version: Option<i64>,
+    pub nonce_length: Option<usize>,
+    pub tag_length: usize,
+    pub stored_size: i64,
+    pub ciphertext_size: Option<i64>,
+    pub plaintext_size: Option<i64>,
+    pub sha256: Option<String>,
+    pub e_tag: Option<String>,
+    pub metadata_keys: Vec<String>,
+    pub issues: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteSource {
+    pub note_id: i64,
+    pub url: String,
+    pub author: Option<String>,
+    pub site_name: Option<String>,
+    pub saved_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct CommandMetrics {
+    pub command: String,
+    pub calls: i64,
+    pub errors: i64,
+    pub error_rate: f64,
+    pub average_ms: f64,
+    pub max_ms: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct UsageMetrics {
+    pub enabled: bool,
+    pub since: String,
+    pub commands: Vec<CommandMetrics>,
+    pub total_calls: i64,
+    pub total_errors: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct NoteVersion {
+    pub id: i64,
+    pub note_id: i64,
+    pub uuid: Option<String>,
+    pub title: String,
+    pub content: String,
+    pub created_at: i64,
+    pub reason: String,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Default)]
+pub struct ReplaceScope {
+    pub note_ids: Option<Vec<i64>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub notebooks: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct LineChange {
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct ReplacePreview {
+    pub note_id: i64,
+    pub title: String,
+    pub replacement_count: usize,
+    pub changes: Vec<LineChange>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+pub struct PrintOptions {
+    #[serde(default = "default_true")]
+    pub header: bool,
+    #[serde(default = "default_true")]
+    pub footer: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions { header: true, footer: true }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CardTheme {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SiteFlavor {
+    Hugo,
+    Zola,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct NoteArchive {
+    pub version: u32,
+    pub exported_at: i64,
+    pub notes: Vec<ArchivedNote>,
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct ExportManifest {
+    pub version: u32,
+    pub exported_at: i64,
+    pub files: Vec<ManifestEntry>,
+    pub checksum: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub uuid: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct ArchivedNote {
+    pub uuid: String,
+    pub title: String,
+    pub markdown: String,
+    pub content: String,
+    pub created_at: i64,
+    pub updated_at: Option<i64>,
+    pub secure: bool,
+    pub locked: bool,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
+    pub status: Option<String>,
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexProtection {
+    Redact,
+    UnlockedOnly,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl TaskPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskPriority::Low => "low",
+            TaskPriority::Normal => "normal",
+            TaskPriority::High => "high",
+        }
+    }
+
+    pub fn from_str(priority: &str) -> TaskPriority {
+        match priority {
+            "low" => TaskPriority::Low,
+            "high" => TaskPriority::High,
+            _ => TaskPriority::Normal,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TaskStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Queued => "queued",
+            TaskStatus::Running => "running",
+            TaskStatus::Completed => "completed",
+            TaskStatus::Failed => "failed",
+            TaskStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct BackgroundTask {
+    pub id: i64,
+    pub kind: String,
+    pub label: String,
+    pub priority: TaskPriority,
+    pub status: TaskStatus,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum JournaledOperation {
+    UploadNote { bucket_name: String, note: Note },
+    UpdateBucketNote { bucket_name: String, note: Note },
+    DeleteBucketNote { bucket_name: String, uuid: String },
+    DeleteBucketNotes { bucket_name: String },
+}
+
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryOutcome {
+    Retried,
+    AlreadyDone,
+    Failed,
+}
+
+impl RecoveryOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryOutcome::Retried => "retried",
+            RecoveryOutcome::AlreadyDone => "already_done",
+            RecoveryOutcome::Failed => "failed",
+        }
+    }
+
+    pub fn from_str(outcome: &str) -> RecoveryOutcome {
+        match outcome {
+            "retried" => RecoveryOutcome::Retried,
+            "already_done" => RecoveryOutcome::AlreadyDone,
+            _ => RecoveryOutcome::Failed,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct RecoveryEntry {
+    pub id: i64,
+    pub operation: String,
+    pub target: String,
+    pub started_at: i64,
+    pub outcome: RecoveryOutcome,
+    pub error: Option<String>,
+    pub recovered_at: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SchemaStatus {
+    pub version: u32,
+    pub latest_version: u32,
+    pub migrations: Vec<AppliedMigration>,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub description: String,
+    pub applied_at: i64,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct NotePresence {
+    pub device_id: String,
+    pub display_name: String,
+    pub updated_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct IndexStatus {
+    pub ready: bool,
+    pub indexed_docs: u64,
+    pub pending: usize,
+    pub last_commit_at: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Citation {
+    pub key: String,
+    pub in_library: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CodeBlock {
+    pub id: i64,
+    pub note_id: i64,
+    pub position: i64,
+    pub language: Option<String>,
+    pub detected: bool,
+    pub code: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Snippet {
+    pub id: i64,
+    pub note_id: i64,
+    pub note_title: String,
+    pub language: Option<String>,
+    pub detected: bool,
+    pub code: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct StatusWorkflow {
+    pub statuses: Vec<String>,
+    pub transitions: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct StatusCount {
+    pub status: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+#[serde(tag = "type", content = "bucket_name", rename_all = "lowercase")]
+pub enum SearchSource {
+    Local,
+    Bucket(String),
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub note: Note,
+    pub source: SearchSource,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct BucketNoteStats {
+    pub key: String,
+    pub uuid: Option<String>,
+    pub title: String,
+    pub updated_at: Option<i64>,
+    pub secure: bool,
+    pub tags: Vec<String>,
+    pub word_count: Option<i64>,
+    pub char_count: Option<i64>,
+    pub checksum: Option<String>,
+    pub size: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TagRefactor {
+    pub tag: String,
+    pub tagged_notes: usize,
+    pub rewritten_notes: usize,
+    pub references: usize,
+    pub skipped_notes: usize,
+}
+
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct StartupTiming {
+    pub phase: String,
+    pub started_at_ms: u64,
+    pub duration_ms: u64,
 }
 
 #[derive(Debug)]
@@ -52,4 +1518,43 @@ impl From<aws_sdk_s3::Error> for BucketError {
     fn from(err: aws_sdk_s3::Error) -> BucketError {
         BucketError::S3Error(Box::new(err))
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CommandResponse<T> {
+    pub ok: bool,
+    pub data: Option<T>,
+    pub error: Option<AppError>,
+    pub request_id: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AppError {
+    Validation(String),
+    NotFound(String),
+    Database(String),
+    S3(String),
+    Encryption(String),
+    Network(String),
+    Unavailable(String),
+    Failed(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Validation(message)
+            | AppError::NotFound(message)
+            | AppError::Database(message)
+            | AppError::S3(message)
+            | AppError::Encryption(message)
+            | AppError::Network(message)
+            | AppError::Unavailable(message)
+            | AppError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl StdError for AppError {}
\ No newline at end of file
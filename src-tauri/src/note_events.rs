@@ -0,0 +1,132 @@
+// note_events.rs
+
+use crate::{ alias_operations, article_operations, attachment_operations, citation_operations, code_block_operations, crypto, custom_fields, flashcard_operations, focus_sessions, key_management, link_operations, periodic_notes, quick_switch, reencryption, review_operations, search_index, tag_operations };
+use rusqlite::{Connection, OptionalExtension};
+
+
+/// Updates the data derived from a local note after it was created or its content changed.
+///
+/// It is meant to be called with the connection or transaction that saved the note, so the derived data
+/// is committed or rolled back with it.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `content` - The plaintext content of the note.
+///
+/// # Operation
+///
+/// * The citation keys of the note are recorded (see `citation_operations`).
+/// * The code blocks of the note are recorded with their language (see `code_block_operations`).
+/// * The wikilinks of the note are recorded (see `link_operations`).
+/// * The flashcards of the note are recorded, keeping the schedule of the unchanged ones (see `flashcard_operations`).
+/// * The note is queued to be reindexed for search (see `search_index`).
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn note_saved(conn: &Connection, note_id: i64, content: &str) -> Result<(), String> {
+    citation_operations::index_citations(conn, note_id, content)?;
+    code_block_operations::index_code_blocks(conn, note_id, content)?;
+    link_operations::index_links(conn, note_id, content)?;
+    flashcard_operations::index_cards(conn, note_id, content)?;
+    search_index::schedule_reindex(note_id);
+
+    Ok(())
+}
+
+
+/// Removes the data derived from a local note after it was deleted, its attachments, its source, the values of its
+/// custom fields, its aliases, and its tags.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn note_deleted(conn: &Connection, note_id: i64) -> Result<(), String> {
+    citation_operations::forget_citations(conn, note_id)?;
+    code_block_operations::forget_code_blocks(conn, note_id)?;
+    custom_fields::forget_fields(conn, note_id)?;
+    link_operations::forget_links(conn, note_id)?;
+    attachment_operations::forget_attachments(conn, note_id)?;
+    article_operations::forget_source(conn, note_id)?;
+    alias_operations::forget_aliases(conn, note_id)?;
+    tag_operations::forget_tags(conn, note_id)?;
+    periodic_notes::forget_periodic_note(conn, note_id)?;
+    review_operations::forget_review(conn, note_id)?;
+    flashcard_operations::forget_cards(conn, note_id)?;
+    focus_sessions::forget_focus_sessions(conn, note_id)?;
+    quick_switch::forget_note(conn, note_id)?;
+    reencryption::forget_note(conn, note_id)?;
+    search_index::schedule_reindex(note_id);
+
+    Ok(())
+}
+
+
+/// Removes the data derived from the content of a local note after it was moved to the trash, and its periodic note.
+///
+/// Its attachments, source, aliases, tags, and flashcards are kept until it is purged (see `trash`), and the
+/// derived data is recorded again with `note_saved` if it is restored.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn note_trashed(conn: &Connection, note_id: i64) -> Result<(), String> {
+    citation_operations::forget_citations(conn, note_id)?;
+    code_block_operations::forget_code_blocks(conn, note_id)?;
+    link_operations::forget_links(conn, note_id)?;
+    periodic_notes::forget_periodic_note(conn, note_id)?;
+    search_index::schedule_reindex(note_id);
+
+    Ok(())
+}
+
+
+/// Derives data from the notes saved before it was tracked.
+///
+/// It runs once per database for each kind of data, which is remembered with a setting. Notes that cannot be
+/// decrypted are skipped. While the content key is not available, e.g. when the vault is locked with a passphrase,
+/// it does not run (see `local_operations::backfill_notes`). The connection is passed explicitly since it is called
+/// while `CONNECTION` is initialized.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `setting` - The settings key remembering that the data was derived, e.g. "citations_indexed".
+/// * `derive` - The function deriving the data of a note from its ID and plaintext content.
+pub fn backfill(conn: &Connection, setting: &str, derive: fn(&Connection, i64, &str) -> Result<(), String>) -> rusqlite::Result<()> {
+    let done = conn.query_row(
+        "SELECT 1 FROM settings WHERE key = ?1",
+        [setting],
+        |_| Ok(()),
+    ).optional()?.is_some();
+    if done || key_management::content_key().is_err() {
+        return Ok(());
+    }
+
+    let mut stmt = conn.prepare("SELECT id, content, nonce FROM notes")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, encrypted_content, nonce_str) in rows {
+        let result = crypto::decrypt_content(&encrypted_content, &nonce_str)
+            .map_err(|e| e.to_string())
+            .and_then(|content| derive(conn, id, &content));
+        if let Err(e) = result {
+            eprintln!("Failed to backfill '{}' for note {}: {}", setting, id, e);
+        }
+    }
+
+    conn.execute("INSERT OR REPLACE INTO settings (key, value) VALUES (?1, '1')", [setting])?;
+    Ok(())
+}
@@ -0,0 +1,329 @@
+// note_schedules.rs
+
+use crate::{ note_templates, tasks, local_operations::CONNECTION, models::AppError, models::NoteSchedule, models::NoteScheduleConfig, models::Recurrence, models::TaskPriority, tasks::TaskContext };
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime};
+use rusqlite::{params, Connection};
+use std::time::Duration;
+
+
+/// How often the schedules are checked, in seconds.
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// How late a scheduled note can be created, in seconds. A later one was missed while the application was not
+/// running, and is only created if its schedule catches up.
+const MISSED_AFTER_SECONDS: i64 = 15 * 60;
+
+/// The number of days searched for the next occurrence of a schedule, enough for any recurrence.
+const MAX_SEARCHED_DAYS: u32 = 400;
+
+
+/// Lists the scheduled notes.
+///
+/// # Returns
+///
+/// Returns a vector of `NoteSchedule`, the next one to run first, with the outcome of their last run.
+///
+/// # Errors
+///
+/// This function will return an error if a schedule is malformed or if there is an issue with the database connection.
+pub fn list_scheduled_notes() -> Result<Vec<NoteSchedule>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    read_schedules(&conn, None)
+}
+
+
+/// Schedules the creation of a note from a template, e.g. a "Weekly Review" every Monday at 9:00.
+///
+/// # Arguments
+///
+/// * `config` - The name of the template, the recurrence (every day, every week on a weekday from 1 for Monday to
+///   7 for Sunday, or every month on a day, the last day of the shorter months for the days they lack), the time as
+///   `HH:MM` in the local time zone, the values of the prompts of the template, and whether a note missed while the
+///   application was not running is created when it starts again.
+///
+/// # Operation
+///
+/// The notes are created by a background task (see `tasks`), with the dates of the template evaluated from the day
+/// they were scheduled for, and the prompts without a value taking their default value. Missed notes are created
+/// once per schedule, however many were missed.
+///
+/// # Returns
+///
+/// Returns the created `NoteSchedule`, with its next run.
+///
+/// # Errors
+///
+/// This function will return an error if there is no template with this name, if the recurrence or the time is
+/// invalid, or if there is an issue with the database connection.
+pub fn create_schedule(config: NoteScheduleConfig) -> Result<NoteSchedule, String> {
+    let template = note_templates::get_note_template(&config.template)?;
+    let time = parse_time(&config.time)?;
+    match config.recurrence {
+        Recurrence::Week { weekday } if !(1..=7).contains(&weekday) => {
+            return Err(format!("Invalid weekday {}, expected 1 for Monday to 7 for Sunday", weekday));
+        },
+        Recurrence::Month { day } if !(1..=31).contains(&day) => {
+            return Err(format!("Invalid day of the month {}, expected 1 to 31", day));
+        },
+        _ => {},
+    }
+    let config = NoteScheduleConfig { template: template.name, time: time.format("%H:%M").to_string(), ..config };
+    let next_run_at = next_occurrence(config.recurrence, time, Local::now())
+        .ok_or("The schedule has no next occurrence".to_string())?;
+
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO note_schedules (config, next_run_at) VALUES (?1, ?2)",
+        params![json, next_run_at.timestamp()],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(NoteSchedule {
+        id: conn.last_insert_rowid(),
+        config,
+        next_run_at: next_run_at.timestamp(),
+        last_run_at: None,
+        last_note_id: None,
+        last_error: None,
+    })
+}
+
+
+/// Deletes a schedule. The notes it created are kept.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the schedule.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the schedule is deleted, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the schedule does not exist or if there is an issue with the database connection.
+pub fn delete_schedule(id: i64) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let deleted = conn.execute("DELETE FROM note_schedules WHERE id = ?1", params![id])?;
+    if deleted == 0 {
+        return Err(AppError::NotFound(format!("Schedule {} not found", id)));
+    }
+    Ok(())
+}
+
+
+/// Starts the thread checking the schedules every minute, which submits the task creating the scheduled notes when
+/// one is due. The first check, at startup, catches up with the notes missed while the application was not running.
+pub fn start_schedules() {
+    std::thread::spawn(|| loop {
+        let due: Result<i64, String> = {
+            let conn = CONNECTION.lock().unwrap();
+            conn.query_row(
+                "SELECT COUNT(*) FROM note_schedules WHERE next_run_at <= ?1",
+                params![chrono::Utc::now().timestamp()],
+                |row| row.get(0),
+            ).map_err(|e| e.to_string())
+        };
+        match due {
+            Ok(0) => {},
+            Ok(_) => {
+                tasks::submit_unless_queued(tasks::SCHEDULED_NOTES, "Create the scheduled notes", TaskPriority::Low);
+            },
+            Err(e) => eprintln!("Failed to check the scheduled notes: {}", e),
+        }
+        std::thread::sleep(Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+
+/// Runs the task creating the scheduled notes that are due.
+///
+/// A failed note is recorded as the last error of its schedule, which moves on to its next occurrence.
+pub fn run_schedules(context: &TaskContext) -> Result<(), String> {
+    let now = Local::now();
+    let due = {
+        let conn = CONNECTION.lock().unwrap();
+        read_schedules(&conn, Some(now.timestamp()))?
+    };
+
+    for schedule in due {
+        if context.is_cancelled() {
+            break;
+        }
+        let config = &schedule.config;
+        let time = parse_time(&config.time)?;
+        let missed = now.timestamp() - schedule.next_run_at > MISSED_AFTER_SECONDS;
+        // A missed note is created once, for its latest occurrence
+        let occurrence = match missed {
+            true => latest_occurrence(config.recurrence, time, now),
+            false => DateTime::from_timestamp(schedule.next_run_at, 0).map(|at| at.with_timezone(&Local)),
+        };
+
+        let (note_id, error) = match occurrence {
+            Some(occurrence) if !missed || config.catch_up => {
+                match note_templates::create_note_from_template_on(&config.template, &config.values, occurrence.date_naive()) {
+                    Ok(note_id) => (Some(note_id), None),
+                    Err(e) => {
+                        eprintln!("Failed to create the scheduled note of schedule {}: {}", schedule.id, e);
+                        (None, Some(e))
+                    },
+                }
+            },
+            _ => (schedule.last_note_id, None),
+        };
+
+        let next_run_at = next_occurrence(config.recurrence, time, now)
+            .map(|at| at.timestamp())
+            .unwrap_or(i64::MAX);
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute(
+            "UPDATE note_schedules SET next_run_at = ?1, last_run_at = ?2, last_note_id = ?3, last_error = ?4 WHERE id = ?5",
+            params![next_run_at, now.timestamp(), note_id, error, schedule.id],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+
+/// Reads the schedules, or the ones due at a time, the next one to run first.
+fn read_schedules(conn: &Connection, due_at: Option<i64>) -> Result<Vec<NoteSchedule>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT id, config, next_run_at, last_run_at, last_note_id, last_error FROM note_schedules
+        WHERE ?1 IS NULL OR next_run_at <= ?1 ORDER BY next_run_at, id"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![due_at], |row| Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, String>(1)?,
+        row.get::<_, i64>(2)?,
+        row.get::<_, Option<i64>>(3)?,
+        row.get::<_, Option<i64>>(4)?,
+        row.get::<_, Option<String>>(5)?,
+    ))).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    rows.into_iter().map(|(id, config, next_run_at, last_run_at, last_note_id, last_error)| {
+        let config = serde_json::from_str(&config).map_err(|e| format!("Invalid schedule {}: {}", id, e))?;
+        Ok(NoteSchedule { id, config, next_run_at, last_run_at, last_note_id, last_error })
+    }).collect()
+}
+
+
+/// Parses a time as `HH:MM`.
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("Invalid time '{}', expected HH:MM", time))
+}
+
+
+/// Tells whether a note is scheduled on a day.
+fn occurs_on(recurrence: Recurrence, day: NaiveDate) -> bool {
+    match recurrence {
+        Recurrence::Day => true,
+        Recurrence::Week { weekday } => day.weekday().number_from_monday() == weekday,
+        Recurrence::Month { day: day_of_month } => day.day() == day_of_month.min(last_day_of_month(day)),
+    }
+}
+
+
+/// Returns the number of the last day of the month of a day.
+fn last_day_of_month(day: NaiveDate) -> u32 {
+    (28..=31).rev()
+        .find(|last_day| day.with_day(*last_day).is_some())
+        .unwrap_or(28)
+}
+
+
+/// Returns the first occurrence of a schedule after a time. The times skipped by a change to daylight saving time
+/// have no occurrence.
+fn next_occurrence(recurrence: Recurrence, time: NaiveTime, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    after.date_naive().iter_days()
+        .take(MAX_SEARCHED_DAYS as usize)
+        .filter(|day| occurs_on(recurrence, *day))
+        .filter_map(|day| day.and_time(time).and_local_timezone(Local).earliest())
+        .find(|at| *at > after)
+}
+
+
+/// Returns the last occurrence of a schedule at or before a time.
+fn latest_occurrence(recurrence: Recurrence, time: NaiveTime, before: DateTime<Local>) -> Option<DateTime<Local>> {
+    before.date_naive().iter_days().rev()
+        .take(MAX_SEARCHED_DAYS as usize)
+        .filter(|day| occurs_on(recurrence, *day))
+        .filter_map(|day| day.and_time(time).and_local_timezone(Local).earliest())
+        .find(|at| *at <= before)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Sets the local time zone to Central European Time, which changes to summer time on the last Sunday of March at
+    /// 02:00, and back on the last Sunday of October at 03:00.
+    fn central_european_time() {
+        std::env::set_var("TZ", "CET-1CEST,M3.5.0,M10.5.0/3");
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).earliest().unwrap()
+    }
+
+    fn time(time: &str) -> NaiveTime {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn next_occurrence_is_strictly_after() {
+        central_european_time();
+        let monday = Recurrence::Week { weekday: 1 };
+        assert_eq!(next_occurrence(monday, time("09:00"), at(2024, 3, 4, 9, 0)), Some(at(2024, 3, 11, 9, 0)));
+        assert_eq!(next_occurrence(monday, time("09:00"), at(2024, 3, 4, 8, 59)), Some(at(2024, 3, 4, 9, 0)));
+        assert_eq!(next_occurrence(Recurrence::Day, time("18:30"), at(2024, 12, 31, 20, 0)), Some(at(2025, 1, 1, 18, 30)));
+    }
+
+    #[test]
+    fn next_occurrence_falls_back_to_the_end_of_shorter_months() {
+        central_european_time();
+        let last_day = Recurrence::Month { day: 31 };
+        assert_eq!(next_occurrence(last_day, time("08:00"), at(2024, 1, 31, 10, 0)), Some(at(2024, 2, 29, 8, 0)));
+        assert_eq!(next_occurrence(last_day, time("08:00"), at(2024, 4, 1, 0, 0)), Some(at(2024, 4, 30, 8, 0)));
+        assert_eq!(next_occurrence(last_day, time("08:00"), at(2024, 4, 30, 9, 0)), Some(at(2024, 5, 31, 8, 0)));
+    }
+
+    #[test]
+    fn next_occurrence_keeps_the_leap_day_to_leap_years() {
+        central_european_time();
+        let leap_day = Recurrence::Month { day: 29 };
+        assert_eq!(next_occurrence(leap_day, time("08:00"), at(2023, 2, 1, 0, 0)), Some(at(2023, 2, 28, 8, 0)));
+        assert_eq!(next_occurrence(leap_day, time("08:00"), at(2024, 2, 1, 0, 0)), Some(at(2024, 2, 29, 8, 0)));
+        assert_eq!(next_occurrence(leap_day, time("08:00"), at(2024, 2, 29, 9, 0)), Some(at(2024, 3, 29, 8, 0)));
+    }
+
+    #[test]
+    fn next_occurrence_skips_the_times_lost_to_daylight_saving_time() {
+        central_european_time();
+        // 02:30 does not exist on 2024-03-31, when the clocks go from 02:00 to 03:00
+        assert_eq!(next_occurrence(Recurrence::Day, time("02:30"), at(2024, 3, 30, 12, 0)), Some(at(2024, 4, 1, 2, 30)));
+        assert_eq!(next_occurrence(Recurrence::Day, time("03:00"), at(2024, 3, 30, 12, 0)), Some(at(2024, 3, 31, 3, 0)));
+    }
+
+    #[test]
+    fn next_occurrence_happens_once_on_repeated_times() {
+        central_european_time();
+        // 02:30 happens twice on 2024-10-27, when the clocks go from 03:00 back to 02:00
+        let repeated = next_occurrence(Recurrence::Day, time("02:30"), at(2024, 10, 26, 12, 0)).unwrap();
+        assert_eq!(repeated.naive_local(), at(2024, 10, 27, 2, 30).naive_local());
+        assert_eq!(next_occurrence(Recurrence::Day, time("02:30"), repeated), Some(at(2024, 10, 28, 2, 30)));
+    }
+
+    #[test]
+    fn latest_occurrence_includes_the_time_itself() {
+        central_european_time();
+        let monday = Recurrence::Week { weekday: 1 };
+        assert_eq!(latest_occurrence(monday, time("09:00"), at(2024, 3, 11, 9, 0)), Some(at(2024, 3, 11, 9, 0)));
+        assert_eq!(latest_occurrence(monday, time("09:00"), at(2024, 3, 11, 8, 59)), Some(at(2024, 3, 4, 9, 0)));
+        assert_eq!(latest_occurrence(Recurrence::Month { day: 31 }, time("08:00"), at(2024, 3, 1, 0, 0)), Some(at(2024, 2, 29, 8, 0)));
+    }
+}
@@ -0,0 +1,99 @@
+// note_stats.rs
+
+use crate::{ preview, s3_operations, models::AppError, models::BucketNoteStats, models::Note };
+use ring::digest;
+use std::collections::HashMap;
+
+
+/// The metadata field holding the number of words of a note.
+pub const WORD_COUNT_METADATA: &str = "word_count";
+
+/// The metadata field holding the number of characters of the text of a note.
+pub const CHAR_COUNT_METADATA: &str = "char_count";
+
+/// The metadata field holding the SHA-256 checksum of the plaintext content of a note.
+pub const CHECKSUM_METADATA: &str = "checksum";
+
+
+/// Computes the statistics of a note stored with its object, so other devices can list and chart the notes of a
+/// bucket from the metadata of the objects, without downloading and decrypting them.
+///
+/// # Arguments
+///
+/// * `note` - The note, with its plaintext content.
+///
+/// # Returns
+///
+/// Returns the metadata fields of the statistics: the number of words and of characters of its text, and the
+/// checksum of its content, which tells whether two copies of the note are the same. A secure note has none, since
+/// they would tell something about its content.
+pub fn stats_metadata(note: &Note) -> Vec<(&'static str, String)> {
+    if note.secure {
+        return Vec::new();
+    }
+    let text = preview::plain_text(&note.content);
+    vec![
+        (WORD_COUNT_METADATA, text.split_whitespace().count().to_string()),
+        (CHAR_COUNT_METADATA, text.trim_end().chars().count().to_string()),
+        (CHECKSUM_METADATA, checksum(&note.content)),
+    ]
+}
+
+
+/// Returns the SHA-256 checksum of the content of a note, in hexadecimal.
+pub fn checksum(content: &str) -> String {
+    digest::digest(&digest::SHA256, content.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+/// Lists the notes of a bucket with their statistics, from the metadata of their objects only.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Operation
+///
+/// The objects are listed and their metadata read, without downloading them, so neither the content key nor the
+/// bandwidth of the notes is needed. The objects uploaded before the statistics were stored, and those of the secure
+/// notes, have no statistics until they are uploaded again.
+///
+/// # Returns
+///
+/// Returns a vector of `BucketNoteStats`, by key.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when listing the objects or reading their
+/// metadata.
+pub async fn list_bucket_note_stats(bucket_name: &str) -> Result<Vec<BucketNoteStats>, AppError> {
+    let client = s3_operations::s3_client().await;
+
+    let objects = s3_operations::list_bucket_objects(&client, bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))?;
+    let mut stats = Vec::new();
+    for (key, _e_tag) in objects {
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3(&e))?;
+
+        let metadata: HashMap<String, String> = head_object_output.metadata.unwrap_or_default();
+        let number = |field: &str| metadata.get(field).and_then(|value| value.parse().ok());
+        stats.push(BucketNoteStats {
+            uuid: metadata.get("uuid").cloned(),
+            title: s3_operations::title_from_metadata(&key, &metadata),
+            updated_at: number("updated_at").filter(|updated_at| *updated_at > 0).or(number("created_at")),
+            secure: metadata.get("secure").is_some_and(|secure| secure == "true"),
+            tags: s3_operations::tags_from_metadata(&metadata),
+            word_count: number(WORD_COUNT_METADATA),
+            char_count: number(CHAR_COUNT_METADATA),
+            checksum: metadata.get(CHECKSUM_METADATA).cloned(),
+            size: head_object_output.content_length.unwrap_or(0),
+            key,
+        });
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,494 @@
+// note_templates.rs
+
+use crate::{ delta, local_operations, note_events, notifier, presence_operations, tag_operations, local_operations::CONNECTION, models::Note, models::NoteTemplate, models::TemplateOutcome, models::TemplatePrompt };
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Duration, Months, NaiveDate};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use std::collections::HashMap;
+
+
+lazy_static! {
+    /// Matches an expression of a template, e.g. `{{title}}`, `{{date+7d|%A}}`, or `{{prompt:Client}}`.
+    static ref EXPRESSION: Regex = Regex::new(r"\{\{\s*([^{}]+?)\s*\}\}").unwrap();
+
+    /// Matches a date with an optional offset in days, weeks, months, or years, e.g. `date` or `date-2w`.
+    static ref DATE: Regex = Regex::new(r"^date\s*(?:([+-])\s*(\d{1,5})\s*([dwmy]))?$").unwrap();
+}
+
+/// The format of the dates of a template when it gives none.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The format of the times of a template when it gives none.
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+
+/// An expression of a template, between double braces.
+enum Expression {
+    /// Today, or the day at an offset from today, e.g. `{{date+7d}}`, with a chrono format.
+    Date { offset: Option<(i64, char)>, format: String },
+    /// The current time, e.g. `{{time}}`, with a chrono format.
+    Time { format: String },
+    /// The title of the new note, which the content of a template may refer to.
+    Title,
+    /// The name of the template.
+    Template,
+    /// The display name of the user (see `presence_operations`).
+    Author,
+    /// The tags of the template, separated by commas.
+    Tags,
+    /// A value asked to the user, e.g. `{{prompt:Client}}` or `{{prompt:Client|ACME}}` with a default value.
+    Prompt { label: String, default: Option<String> },
+}
+
+
+/// What the expressions of a template are evaluated with.
+struct Context<'a> {
+    template: &'a NoteTemplate,
+    /// The title of the new note, once it is evaluated.
+    title: Option<&'a str>,
+    author: &'a str,
+    today: NaiveDate,
+    values: &'a HashMap<String, String>,
+    /// Whether the prompts without a value take their default value rather than being asked.
+    use_defaults: bool,
+}
+
+
+/// Lists the note templates.
+///
+/// # Returns
+///
+/// Returns a vector of `NoteTemplate`, sorted by name.
+///
+/// # Errors
+///
+/// This function will return an error if a template is malformed or if there is an issue with the database connection.
+pub fn list_note_templates() -> Result<Vec<NoteTemplate>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT name, template FROM note_templates ORDER BY name COLLATE NOCASE")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    rows.into_iter()
+        .map(|(name, template)| serde_json::from_str(&template).map_err(|e| format!("Invalid note template '{}': {}", name, e)))
+        .collect()
+}
+
+
+/// Saves a note template, to create notes from with `create_note_from_template`.
+///
+/// # Arguments
+///
+/// * `template` - The template, with its name, and the title, content, and tags of the notes created from it. The
+///   title and the content may hold expressions between double braces:
+///   - `{{date}}` - Today, or a day at an offset from today in days, weeks, months, or years, e.g. `{{date+7d}}`,
+///     `{{date-1w}}`, or `{{date+1m}}`.
+///   - `{{time}}` - The current time.
+///   - `{{title}}` - The title of the new note, in the content only.
+///   - `{{template}}`, `{{author}}`, `{{tags}}` - The name of the template, the display name of the user, and the
+///     tags of the template.
+///   - `{{prompt:Label}}` - A value asked to the user when the note is created, with a default value after a `|`,
+///     e.g. `{{prompt:Client|ACME}}`.
+///
+///   Dates and times are formatted with a chrono format after a `|`, e.g. `{{date+7d|%A %d %B}}`. A template with
+///   the same name, ignoring case, is replaced.
+///
+/// # Returns
+///
+/// Returns the saved `NoteTemplate`.
+///
+/// # Errors
+///
+/// This function will return an error if the name is blank or longer than 100 characters, if the title is blank, if
+/// an expression is invalid, if a tag is invalid, or if there is an issue with the database connection.
+pub fn save_note_template(template: NoteTemplate) -> Result<NoteTemplate, String> {
+    let name = template.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err("The name of a template should be between 1 and 100 characters".to_string());
+    }
+    if template.title.trim().is_empty() {
+        return Err("The title of a template should not be empty".to_string());
+    }
+    for captures in EXPRESSION.captures_iter(&template.title) {
+        if matches!(parse_expression(&captures[1])?, Expression::Title) {
+            return Err("The title of a template cannot refer to itself".to_string());
+        }
+    }
+    for captures in EXPRESSION.captures_iter(&template.content) {
+        parse_expression(&captures[1])?;
+    }
+    let template = NoteTemplate {
+        name,
+        tags: tag_operations::check_tags(&template.tags)?,
+        updated_at: chrono::Utc::now().timestamp(),
+        ..template
+    };
+
+    let json = serde_json::to_string(&template).map_err(|e| e.to_string())?;
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO note_templates (name, template) VALUES (?1, ?2)
+        ON CONFLICT (name) DO UPDATE SET name = ?1, template = ?2",
+        params![template.name, json],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(template)
+}
+
+
+/// Deletes a note template.
+///
+/// # Arguments
+///
+/// * `name` - The name of the template, ignoring case.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the template is deleted, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is no template with this name or if there is an issue with the database connection.
+pub fn delete_note_template(name: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let deleted = conn.execute("DELETE FROM note_templates WHERE name = ?1", params![name.trim()])
+        .map_err(|e| e.to_string())?;
+    if deleted == 0 {
+        return Err(format!("No note template named '{}'", name.trim()));
+    }
+    Ok(())
+}
+
+
+/// Creates a note from a template, evaluating its expressions (see `save_note_template`).
+///
+/// # Arguments
+///
+/// * `name` - The name of the template, ignoring case.
+/// * `values` - The values of the prompts of the template, by label.
+///
+/// # Operation
+///
+/// * If a prompt of the template has no value, no note is created, and the prompts without a value are returned
+///   with their default value, for the window to ask the user and call this function again with the values.
+/// * Otherwise, the note is created with the evaluated title and content, and the tags of the template. Dates and
+///   times are evaluated in the local time zone.
+///
+/// # Returns
+///
+/// Returns a `TemplateOutcome` with either the created note or the prompts to ask.
+///
+/// # Errors
+///
+/// This function will return an error if there is no template with this name, if it is malformed, if an offset
+/// date is out of range, or if the note cannot be created.
+pub async fn create_note_from_template(name: &str, values: HashMap<String, String>) -> Result<TemplateOutcome, String> {
+    let template = get_note_template(name)?;
+    let (note, prompts) = instantiate(&template, &values, chrono::Local::now().date_naive(), false)?;
+    if !prompts.is_empty() {
+        return Ok(TemplateOutcome { note: None, prompts });
+    }
+
+    let note = local_operations::create_local_note(note).await?;
+    Ok(TemplateOutcome { note: Some(note), prompts })
+}
+
+
+/// Creates a note from a template without asking anything, e.g. on a schedule (see `note_schedules`).
+///
+/// # Arguments
+///
+/// * `name` - The name of the template, ignoring case.
+/// * `values` - The values of the prompts of the template, by label. The prompts without a value take their
+///   default value, or are left empty.
+/// * `day` - The day the dates of the template are evaluated from, e.g. the day the note was scheduled for.
+///
+/// # Returns
+///
+/// Returns the ID of the created note.
+///
+/// # Errors
+///
+/// This function will return an error if there is no template with this name, if it is malformed, if an offset
+/// date is out of range, or if the note cannot be created.
+pub fn create_note_from_template_on(name: &str, values: &HashMap<String, String>, day: NaiveDate) -> Result<i64, String> {
+    let template = get_note_template(name)?;
+    let (note, _) = instantiate(&template, values, day, true)?;
+    local_operations::validate_params(note.clone())?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let note_id = local_operations::insert_note(&tx, &note)?;
+    note_events::note_saved(&tx, note_id, &note.content)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    notifier::notify("New note created", &format!("Note with title '{}' was created from the template '{}'.", note.title, template.name));
+    Ok(note_id)
+}
+
+
+/// Reads a note template by name, ignoring case.
+///
+/// # Errors
+///
+/// This function will return an error if there is no template with this name, if it is malformed, or if there is an
+/// issue with the database connection.
+pub fn get_note_template(name: &str) -> Result<NoteTemplate, String> {
+    let template: Option<String> = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            "SELECT template FROM note_templates WHERE name = ?1",
+            params![name.trim()],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?
+    };
+    let template = template.ok_or(format!("No note template named '{}'", name.trim()))?;
+    serde_json::from_str(&template).map_err(|e| format!("Invalid note template '{}': {}", name.trim(), e))
+}
+
+
+/// Evaluates a template into a note, and the prompts without a value unless they take their default value.
+fn instantiate(template: &NoteTemplate, values: &HashMap<String, String>, today: NaiveDate, use_defaults: bool) -> Result<(Note, Vec<TemplatePrompt>), String> {
+    let author = presence_operations::get_display_name()?.unwrap_or_default();
+    let mut context = Context {
+        template,
+        title: None,
+        author: &author,
+        today,
+        values,
+        use_defaults,
+    };
+
+    let mut prompts = Vec::new();
+    let title = local_operations::truncate_title(render(&template.title, &context, &mut prompts)?.trim());
+    context.title = Some(&title);
+    let content = render(&template.content, &context, &mut prompts)?;
+
+    let note = Note {
+        id: None,
+        uuid: None,
+        title,
+        content: delta::to_content(vec![json!({ "insert": format!("{}\n", content.trim_end()) })]),
+        nonce: None,
+        created_at: chrono::Utc::now().timestamp(),
+        updated_at: None,
+        timestamp: None,
+        secure: false,
+        status: None,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags: template.tags.clone(),
+    };
+    Ok((note, prompts))
+}
+
+
+/// Parses an expression of a template, without its braces.
+fn parse_expression(expression: &str) -> Result<Expression, String> {
+    let (head, argument) = match expression.split_once('|') {
+        Some((head, argument)) => (head.trim(), Some(argument.trim())),
+        None => (expression.trim(), None),
+    };
+
+    if let Some(label) = head.strip_prefix("prompt:") {
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(format!("The prompt '{{{{{}}}}}' has no label", expression));
+        }
+        return Ok(Expression::Prompt { label: label.to_string(), default: argument.map(|default| default.to_string()) });
+    }
+    let date_format = |default: &str| -> Result<String, String> {
+        let date_format = argument.unwrap_or(default);
+        if date_format.is_empty() || StrftimeItems::new(date_format).any(|item| matches!(item, Item::Error)) {
+            return Err(format!("Invalid date format in '{{{{{}}}}}'", expression));
+        }
+        Ok(date_format.to_string())
+    };
+    if let Some(captures) = DATE.captures(head) {
+        let offset = match (captures.get(1), captures.get(2), captures.get(3)) {
+            (Some(sign), Some(amount), Some(unit)) => {
+                let amount: i64 = amount.as_str().parse().map_err(|_| format!("Invalid offset in '{{{{{}}}}}'", expression))?;
+                let amount = if sign.as_str() == "-" { -amount } else { amount };
+                Some((amount, unit.as_str().chars().next().unwrap_or('d')))
+            },
+            _ => None,
+        };
+        return Ok(Expression::Date { offset, format: date_format(DEFAULT_DATE_FORMAT)? });
+    }
+    if head == "time" {
+        return Ok(Expression::Time { format: date_format(DEFAULT_TIME_FORMAT)? });
+    }
+
+    let expression_kind = match head {
+        "title" => Expression::Title,
+        "template" => Expression::Template,
+        "author" => Expression::Author,
+        "tags" => Expression::Tags,
+        _ => return Err(format!("Unknown expression '{{{{{}}}}}' in the template", expression)),
+    };
+    if argument.is_some() {
+        return Err(format!("Only the dates, the times, and the prompts take a value after '|' in '{{{{{}}}}}'", expression));
+    }
+    Ok(expression_kind)
+}
+
+
+/// Evaluates the expressions of a text of a template. The prompts without a value are added to `prompts`, once each.
+fn render(text: &str, context: &Context, prompts: &mut Vec<TemplatePrompt>) -> Result<String, String> {
+    let mut error = None;
+    let rendered = EXPRESSION.replace_all(text, |captures: &Captures| {
+        match parse_expression(&captures[1]).and_then(|expression| evaluate(expression, context, prompts)) {
+            Ok(value) => value,
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            },
+        }
+    }).to_string();
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(rendered),
+    }
+}
+
+
+/// Evaluates an expression of a template.
+fn evaluate(expression: Expression, context: &Context, prompts: &mut Vec<TemplatePrompt>) -> Result<String, String> {
+    Ok(match expression {
+        Expression::Date { offset, format } => {
+            let date = match offset {
+                Some((amount, unit)) => offset_date(context.today, amount, unit)?,
+                None => context.today,
+            };
+            date.format(&format).to_string()
+        },
+        Expression::Time { format } => chrono::Local::now().format(&format).to_string(),
+        Expression::Title => context.title.ok_or("The title of a template cannot refer to itself".to_string())?.to_string(),
+        Expression::Template => context.template.name.clone(),
+        Expression::Author => context.author.to_string(),
+        Expression::Tags => context.template.tags.join(", "),
+        Expression::Prompt { label, default } => match context.values.get(&label) {
+            Some(value) => value.clone(),
+            None if context.use_defaults => default.unwrap_or_default(),
+            None => {
+                if !prompts.iter().any(|prompt| prompt.label == label) {
+                    prompts.push(TemplatePrompt { label, default });
+                }
+                String::new()
+            },
+        },
+    })
+}
+
+
+/// Returns the day at an offset from another, in days ("d"), weeks ("w"), months ("m"), or years ("y").
+fn offset_date(date: NaiveDate, amount: i64, unit: char) -> Result<NaiveDate, String> {
+    let months = |months: i64| {
+        let delta = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+        match months >= 0 {
+            true => date.checked_add_months(delta),
+            false => date.checked_sub_months(delta),
+        }
+    };
+    match unit {
+        'd' => date.checked_add_signed(Duration::days(amount)),
+        'w' => date.checked_add_signed(Duration::weeks(amount)),
+        'm' => months(amount),
+        _ => months(amount * 12),
+    }.ok_or(format!("The date {:+}{} is out of range", amount, unit))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> NoteTemplate {
+        NoteTemplate {
+            name: "Meeting".to_string(),
+            title: "{{date}} {{template}}".to_string(),
+            content: String::new(),
+            tags: vec!["work".to_string(), "meeting".to_string()],
+            updated_at: 0,
+        }
+    }
+
+    fn render_on(text: &str, today: NaiveDate, values: &HashMap<String, String>) -> (Result<String, String>, Vec<TemplatePrompt>) {
+        let template = template();
+        let context = Context { template: &template, title: Some("Weekly"), author: "Ada", today, values, use_defaults: false };
+        let mut prompts = Vec::new();
+        let rendered = render(text, &context, &mut prompts);
+        (rendered, prompts)
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn render_evaluates_the_expressions() {
+        let (rendered, prompts) = render_on("{{ title }} by {{author}} from {{template}}: {{tags}}", day(2024, 3, 1), &HashMap::new());
+        assert_eq!(rendered.unwrap(), "Weekly by Ada from Meeting: work, meeting");
+        assert!(prompts.is_empty());
+    }
+
+    #[test]
+    fn render_rejects_unknown_variables() {
+        let (rendered, _) = render_on("Hello {{client}}", day(2024, 3, 1), &HashMap::new());
+        assert_eq!(rendered.unwrap_err(), "Unknown expression '{{client}}' in the template");
+    }
+
+    #[test]
+    fn render_rejects_malformed_expressions() {
+        let today = day(2024, 3, 1);
+        assert!(render_on("{{prompt:}}", today, &HashMap::new()).0.unwrap_err().contains("has no label"));
+        assert!(render_on("{{date|%Q}}", today, &HashMap::new()).0.unwrap_err().contains("Invalid date format"));
+        assert!(render_on("{{title|upper}}", today, &HashMap::new()).0.unwrap_err().contains("take a value after '|'"));
+        assert!(render_on("{{date+d}}", today, &HashMap::new()).0.unwrap_err().contains("Unknown expression"));
+    }
+
+    #[test]
+    fn render_leaves_unclosed_braces_alone() {
+        let (rendered, _) = render_on("{{title} and {title}}", day(2024, 3, 1), &HashMap::new());
+        assert_eq!(rendered.unwrap(), "{{title} and {title}}");
+    }
+
+    #[test]
+    fn render_offsets_the_dates() {
+        let today = day(2024, 1, 31);
+        let (rendered, _) = render_on("{{date}} {{date+1d}} {{date-2w}} {{date+1m}} {{date - 1 y|%d/%m/%Y}}", today, &HashMap::new());
+        assert_eq!(rendered.unwrap(), "2024-01-31 2024-02-01 2024-01-17 2024-02-29 31/01/2023");
+    }
+
+    #[test]
+    fn offset_date_clamps_to_the_end_of_the_month() {
+        assert_eq!(offset_date(day(2024, 2, 29), 1, 'y').unwrap(), day(2025, 2, 28));
+        assert_eq!(offset_date(day(2024, 3, 31), -1, 'm').unwrap(), day(2024, 2, 29));
+        assert!(offset_date(NaiveDate::MAX, 1, 'd').is_err());
+    }
+
+    #[test]
+    fn render_collects_each_missing_prompt_once() {
+        let (rendered, prompts) = render_on("{{prompt:Client|ACME}} {{prompt:Client}} {{prompt:Room}}", day(2024, 3, 1), &HashMap::new());
+        assert_eq!(rendered.unwrap(), "  ");
+        let prompts: Vec<_> = prompts.into_iter().map(|prompt| (prompt.label, prompt.default)).collect();
+        assert_eq!(prompts, vec![("Client".to_string(), Some("ACME".to_string())), ("Room".to_string(), None)]);
+
+        let values = HashMap::from([("Client".to_string(), "Initech".to_string()), ("Room".to_string(), "B2".to_string())]);
+        let (rendered, prompts) = render_on("{{prompt:Client}} in {{prompt:Room}}", day(2024, 3, 1), &values);
+        assert_eq!(rendered.unwrap(), "Initech in B2");
+        assert!(prompts.is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+// notebook_csv.rs
+
+use crate::{ collation, crypto, custom_fields, delta, note_events, notebook_operations, notifier, preview, search_index, version_operations, local_operations::CONNECTION, models::CsvRowError, models::CustomField, models::NotebookCsvImport };
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+
+/// The columns of the notes written before their custom fields, which the fields cannot be named after.
+pub const NOTE_COLUMNS: [&str; 2] = ["uuid", "title"];
+
+/// The reason recorded with the version of a note saved before an import changes its title.
+const SNAPSHOT_REASON: &str = "import";
+
+
+/// What a column of an imported CSV file is read into.
+#[derive(Clone, Copy, PartialEq)]
+enum Column {
+    Uuid,
+    Title,
+    /// The custom field at this index of the schema of the notebook.
+    Field(usize),
+}
+
+
+/// Exports the local notes of a notebook as a CSV file, one row per note with its custom fields, e.g. to edit them
+/// in a spreadsheet and import them back with `import_notebook_csv`.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `path` - The file to write.
+///
+/// # Operation
+///
+/// * The first row holds the names of the columns: "uuid", "title", and the custom fields of the notebook, in their
+///   order (see `custom_fields::get_notebook_schema`).
+/// * The notes of the notebook follow, sorted by title in natural order for the configured locale. A field without a value is an empty cell.
+/// * The content of the notes is not exported, so it is never written in plaintext.
+///
+/// # Returns
+///
+/// Returns the number of notes exported.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if the file cannot be written, or if there
+/// is an issue with the database connection.
+pub fn export_notebook_csv(notebook_id: i64, path: &str) -> Result<usize, String> {
+    let locale = collation::get_locale()?;
+    let (schema, mut notes, values) = {
+        let conn = CONNECTION.lock().unwrap();
+        notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+        let schema = custom_fields::read_schema(&conn, notebook_id)?;
+        let mut stmt = conn.prepare("SELECT id, uuid, title FROM notes WHERE notebook_id = ?1").map_err(|e| e.to_string())?;
+        let notes = stmt.query_map(params![notebook_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, String>(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        (schema, notes, notebook_values(&conn, notebook_id)?)
+    };
+    notes.sort_by(|a, b| collation::compare_titles(&a.2, &b.2, &locale));
+
+    let header: Vec<String> = NOTE_COLUMNS.iter().map(|column| column.to_string())
+        .chain(schema.iter().map(|field| csv_field(&field.name)))
+        .collect();
+    let mut csv = format!("{}\n", header.join(","));
+    for (note_id, uuid, title) in &notes {
+        let row: Vec<String> = [csv_field(uuid.as_deref().unwrap_or_default()), csv_field(title)].into_iter()
+            .chain(schema.iter().map(|field| {
+                let value = values.get(&(*note_id, field.name.clone())).map(String::as_str).unwrap_or_default();
+                csv_field(value)
+            }))
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    std::fs::write(path, csv).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    // Send a desktop notification
+    notifier::notify("Notebook exported", &format!("{} notes were exported to {}.", notes.len(), path));
+
+    Ok(notes.len())
+}
+
+
+/// Imports a CSV file into the local notes of a notebook, e.g. one exported with `export_notebook_csv` and edited in
+/// a spreadsheet.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `path` - The CSV file, whose first row holds the names of the columns.
+/// * `mapping` - The column each name of the first row is read into: "uuid", "title", or the name of a custom field
+///   of the notebook, whatever its case. The columns left out are skipped. Without a mapping, each column is read
+///   into the column or the field it is named after, and the others are skipped.
+///
+/// # Operation
+///
+/// Each row is a note:
+///
+/// * A row whose UUID is a note of the notebook updates its title, if the title is not empty, and its custom fields.
+///   Its version is saved before its title changes (see `version_operations`).
+/// * Any other row creates a note in the notebook, with an empty content and the UUID of the row if it has one.
+/// * An empty cell removes the value of its field, and a value is checked like `custom_fields::set_note_fields` does.
+///   The fields without a column keep their value.
+///
+/// A row that cannot be imported, e.g. with a value that is not valid for its field, a note in another notebook,
+/// or without a title for a new note, is skipped and reported. The other rows are imported in a
+/// single transaction.
+///
+/// # Returns
+///
+/// Returns a `NotebookCsvImport` with the number of notes created, updated, and unchanged, the columns skipped, and
+/// the rows that could not be imported, numbered from 1 for the row after the names of the columns.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if the file cannot be read or is not valid
+/// CSV, if the mapping names a field the notebook does not have, if no column is read into the UUIDs or the titles,
+/// or if there is an issue with the database connection.
+pub fn import_notebook_csv(notebook_id: i64, path: &str, mapping: Option<HashMap<String, String>>) -> Result<NotebookCsvImport, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut rows = parse_csv(&text)?.into_iter();
+    let header = rows.next().ok_or(format!("{} is empty", path))?;
+
+    let mut conn = CONNECTION.lock().unwrap();
+    notebook_operations::check_notebook_exists(&conn, notebook_id)?;
+    let schema = custom_fields::read_schema(&conn, notebook_id)?;
+
+    let mut import = NotebookCsvImport { created: 0, updated: 0, unchanged: 0, skipped_columns: Vec::new(), errors: Vec::new() };
+    let mut columns: Vec<Option<Column>> = Vec::new();
+    for name in &header {
+        let target = match &mapping {
+            Some(mapping) => match mapping.get(name) {
+                Some(target) => Some(column(&schema, target).ok_or(format!("The notebook has no field named '{}'", target.trim()))?),
+                None => None,
+            },
+            None => column(&schema, name),
+        };
+        if target.is_none() {
+            import.skipped_columns.push(name.clone());
+        }
+        columns.push(target);
+    }
+    if !columns.iter().any(|target| matches!(target, Some(Column::Uuid) | Some(Column::Title))) {
+        return Err("No column is read into the UUIDs or the titles of the notes".to_string());
+    }
+
+    let mut saved_ids = Vec::new();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for (index, row) in rows.enumerate() {
+        let mut cells: Vec<(Column, &str)> = Vec::new();
+        for (target, cell) in columns.iter().zip(row.iter()) {
+            if let Some(target) = target {
+                cells.push((*target, cell.trim()));
+            }
+        }
+        let cell = |column: Column| cells.iter().rev().find(|(target, _)| *target == column).map(|(_, cell)| *cell);
+
+        match import_row(&tx, notebook_id, &schema, cell) {
+            Ok(Some((note_id, created))) => {
+                match created {
+                    true => import.created += 1,
+                    false => import.updated += 1,
+                }
+                saved_ids.push(note_id);
+            },
+            Ok(None) => import.unchanged += 1,
+            Err(message) => import.errors.push(CsvRowError { row: index + 1, message }),
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    drop(conn);
+
+    for note_id in saved_ids {
+        search_index::schedule_reindex(note_id);
+    }
+
+    // Send a desktop notification
+    notifier::notify(
+        "Notebook imported",
+        &format!("{} notes were created and {} updated from {}.", import.created, import.updated, path),
+    );
+
+    Ok(import)
+}
+
+
+/// Imports a row of a CSV file into a note of a notebook, within the transaction of the import.
+///
+/// Returns the ID of the note and whether it was created, `None` if the note was unchanged, or the reason the row
+/// cannot be imported, in which case nothing was changed.
+fn import_row<'a>(tx: &Connection, notebook_id: i64, schema: &[CustomField], cell: impl Fn(Column) -> Option<&'a str>) -> Result<Option<(i64, bool)>, String> {
+    let title = cell(Column::Title).unwrap_or_default();
+    if title.len() > 100 {
+        return Err("Title too long".to_string());
+    }
+    let mut values = BTreeMap::new();
+    for (index, field) in schema.iter().enumerate() {
+        // The fields without a column keep their value
+        let Some(value) = cell(Column::Field(index)) else {
+            continue;
+        };
+        let value = match value.is_empty() {
+            true => None,
+            false => Some(custom_fields::check_value(field, value)?),
+        };
+        values.insert(field.name.clone(), value);
+    }
+
+    let uuid = cell(Column::Uuid).unwrap_or_default();
+    let existing: Option<(i64, Option<i64>, String)> = match uuid.is_empty() {
+        true => None,
+        false => tx.query_row(
+            "SELECT id, notebook_id, title FROM notes WHERE uuid = ?1",
+            params![uuid],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        ).optional().map_err(|e| e.to_string())?,
+    };
+
+    match existing {
+        Some((_, Some(other_id), _)) if other_id != notebook_id => Err(format!("The note {} is in another notebook", uuid)),
+        Some((_, None, _)) => Err(format!("The note {} is not in the notebook", uuid)),
+        Some((note_id, _, current_title)) => {
+            let mut changed = false;
+            if !title.is_empty() && title != current_title {
+                version_operations::snapshot_note(tx, note_id, SNAPSHOT_REASON)?;
+                let now = chrono::Utc::now();
+                tx.execute(
+                    "UPDATE notes SET title = ?1, updated_at = ?2, timestamp = ?3 WHERE id = ?4",
+                    params![title, now.timestamp(), now.to_rfc3339(), note_id],
+                ).map_err(|e| e.to_string())?;
+                changed = true;
+            }
+            changed |= custom_fields::store_values(tx, note_id, &values)?;
+            Ok(changed.then_some((note_id, false)))
+        },
+        None => {
+            if title.is_empty() {
+                return Err("A new note needs a title".to_string());
+            }
+            if !uuid.is_empty() && Uuid::parse_str(uuid).is_err() {
+                return Err(format!("'{}' is not a UUID", uuid));
+            }
+            let content = delta::to_content(vec![json!({ "insert": "\n" })]);
+            let (encrypted_content, nonce_str) = crypto::encrypt_content(&content)?;
+            let uuid = match uuid.is_empty() {
+                true => Uuid::new_v4().to_string(),
+                false => uuid.to_string(),
+            };
+            let now = chrono::Utc::now();
+            tx.execute(
+                "INSERT INTO notes (uuid, title, content, nonce, created_at, timestamp, preview, notebook_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![uuid, title, encrypted_content, nonce_str, now.timestamp(), now.to_rfc3339(), preview::build_preview(&content), notebook_id],
+            ).map_err(|e| e.to_string())?;
+            let note_id = tx.last_insert_rowid();
+            custom_fields::store_values(tx, note_id, &values)?;
+            note_events::note_saved(tx, note_id, &content)?;
+            Ok(Some((note_id, true)))
+        },
+    }
+}
+
+
+/// Finds the column a name of the first row of a CSV file is read into, whatever its case.
+fn column(schema: &[CustomField], name: &str) -> Option<Column> {
+    let name = name.trim().to_lowercase();
+    match name.as_str() {
+        "uuid" => Some(Column::Uuid),
+        "title" => Some(Column::Title),
+        _ => schema.iter().position(|field| field.name.to_lowercase() == name).map(Column::Field),
+    }
+}
+
+
+/// Reads the values of the custom fields of the notes of a notebook, by note ID and field name.
+fn notebook_values(conn: &Connection, notebook_id: i64) -> Result<HashMap<(i64, String), String>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT note_fields.note_id, note_fields.name, note_fields.value FROM note_fields
+        JOIN notes ON notes.id = note_fields.note_id WHERE notes.notebook_id = ?1"
+    ).map_err(|e| e.to_string())?;
+    let values = stmt.query_map(params![notebook_id], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(values)
+}
+
+
+/// Parses a CSV file into its rows, as written by spreadsheets: fields separated by commas, quoted with double quotes
+/// when they hold a comma, a quote, or a line break, and a quote in a quoted field doubled. The byte order mark some
+/// spreadsheets write is left out, and so are the empty rows.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\r' | '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("The CSV file ends in a quoted field".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows.retain(|row: &Vec<String>| row.iter().any(|field| !field.is_empty()));
+    Ok(rows)
+}
+
+
+/// Quotes a CSV field if it holds a comma, a quote, or a line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
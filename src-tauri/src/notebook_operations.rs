@@ -0,0 +1,582 @@
+// notebook_operations.rs
+
+use crate::{ collation, custom_fields, local_operations, s3_operations, search_index, local_operations::CONNECTION, models::AppError, models::Note, models::Notebook, models::NotebookSettings };
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+
+/// The maximum length of the name of a notebook.
+const MAX_NOTEBOOK_NAME_LENGTH: usize = 100;
+
+/// The maximum depth of the notebooks, so their path fits in the key of the objects of their notes in a bucket.
+const MAX_NOTEBOOK_DEPTH: usize = 8;
+
+
+/// A row of the "notebooks" table.
+struct NotebookRow {
+    id: i64,
+    name: String,
+    parent_id: Option<i64>,
+    created_at: i64,
+}
+
+
+/// Creates a notebook, at the top level or inside another notebook.
+///
+/// # Arguments
+///
+/// * `name` - The name of the notebook, up to 100 characters, without "/". Case is ignored when comparing it to the
+///   names of the other notebooks of the same parent.
+/// * `parent_id` - The ID of the notebook to create it in, or `None` for a top-level notebook.
+///
+/// # Returns
+///
+/// Returns the created `Notebook`, without notes.
+///
+/// # Errors
+///
+/// This function will return an error if the name is invalid or already taken in the parent, if the parent does not
+/// exist, if the notebooks would be nested more than 8 levels deep, or if there is an issue with the database connection.
+pub fn create_notebook(name: &str, parent_id: Option<i64>) -> Result<Notebook, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    if let Some(parent_id) = parent_id {
+        if !notebooks.contains_key(&parent_id) {
+            return Err(AppError::NotFound("Notebook not found".to_string()));
+        }
+        if ancestors(&notebooks, parent_id).len() >= MAX_NOTEBOOK_DEPTH {
+            return Err(AppError::Validation(format!("Notebooks cannot be nested more than {} levels deep", MAX_NOTEBOOK_DEPTH)));
+        }
+    }
+    let name = check_name(&notebooks, name, parent_id, None)?;
+
+    conn.execute(
+        "INSERT INTO notebooks (name, parent_id, created_at) VALUES (?1, ?2, ?3)",
+        params![name, parent_id, chrono::Utc::now().timestamp()],
+    )?;
+    get_notebook(&conn, conn.last_insert_rowid())
+}
+
+
+/// Renames a notebook.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the notebook.
+/// * `name` - The new name, with the same rules as `create_notebook`.
+///
+/// # Operation
+///
+/// The objects of the notes of the notebook and of its sub-notebooks in the buckets are named after its path (see
+/// `s3_operations::note_key`), so they move to the new path the next time the notes are uploaded.
+///
+/// # Returns
+///
+/// Returns the renamed `Notebook`.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if the name is invalid or already taken in
+/// its parent, or if there is an issue with the database connection.
+pub fn rename_notebook(id: i64, name: &str) -> Result<Notebook, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    let notebook = notebooks.get(&id).ok_or(AppError::NotFound("Notebook not found".to_string()))?;
+    let name = check_name(&notebooks, name, notebook.parent_id, Some(id))?;
+
+    conn.execute("UPDATE notebooks SET name = ?1 WHERE id = ?2", params![name, id])?;
+    get_notebook(&conn, id)
+}
+
+
+/// Deletes a notebook, keeping its notes and its sub-notebooks.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the notebook.
+///
+/// # Operation
+///
+/// The notes and the sub-notebooks of the notebook are moved to its parent, or to the top level for a top-level
+/// notebook, in a single transaction. The notes in the trash are moved too, so they are restored to the parent. The
+/// custom fields of the notebook are removed, with their values in its notes, and so are its settings.
+///
+/// # Returns
+///
+/// Returns the number of notes moved to the parent, including those in the trash.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if a sub-notebook has the name of a notebook
+/// of the parent, or if there is an issue with the database connection.
+pub fn delete_notebook(id: i64) -> Result<usize, AppError> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    let parent_id = notebooks.get(&id).ok_or(AppError::NotFound("Notebook not found".to_string()))?.parent_id;
+    for child in notebooks.values().filter(|notebook| notebook.parent_id == Some(id)) {
+        check_name(&notebooks, &child.name, parent_id, Some(child.id))
+            .map_err(|_| format!("The sub-notebook '{}' has the name of another notebook, rename it first", child.name))?;
+    }
+
+    let note_ids: Vec<i64> = {
+        let mut stmt = conn.prepare("SELECT id FROM notes WHERE notebook_id = ?1 AND deleted_at IS NULL")?;
+        let note_ids = stmt.query_map(params![id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        note_ids
+    };
+
+    let tx = conn.transaction()?;
+    custom_fields::forget_schema(&tx, id)?;
+    tx.execute("DELETE FROM notebook_settings WHERE notebook_id = ?1", params![id])?;
+    let moved = tx.execute("UPDATE notes SET notebook_id = ?1 WHERE notebook_id = ?2", params![parent_id, id])?;
+    tx.execute("UPDATE notebooks SET parent_id = ?1 WHERE parent_id = ?2", params![parent_id, id])?;
+    tx.execute("DELETE FROM notebooks WHERE id = ?1", params![id])?;
+    tx.commit()?;
+
+    for note_id in note_ids {
+        search_index::schedule_reindex(note_id);
+    }
+    Ok(moved)
+}
+
+
+/// Lists the notebooks.
+///
+/// # Returns
+///
+/// Returns the notebooks with their path and their number of notes, not counting those of their sub-notebooks and
+/// those in the trash, sorted by path in natural order for the configured locale, so each notebook comes right
+/// before its sub-notebooks.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_notebooks() -> Result<Vec<Notebook>, String> {
+    let locale = collation::get_locale()?;
+    let conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    let note_counts: HashMap<i64, i64> = {
+        let mut stmt = conn.prepare(
+            "SELECT notebook_id, COUNT(*) FROM notes WHERE notebook_id IS NOT NULL AND deleted_at IS NULL GROUP BY notebook_id"
+        ).map_err(|e| e.to_string())?;
+        let note_counts = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map_err(|e| e.to_string())?;
+        note_counts
+    };
+
+    let mut listed: Vec<(Vec<String>, Notebook)> = notebooks.values().map(|notebook| {
+        let names: Vec<String> = ancestors(&notebooks, notebook.id).into_iter().rev().map(|ancestor| ancestor.name.clone()).collect();
+        (names.clone(), Notebook {
+            id: notebook.id,
+            name: notebook.name.clone(),
+            parent_id: notebook.parent_id,
+            path: names.join("/"),
+            note_count: note_counts.get(&notebook.id).copied().unwrap_or(0),
+            created_at: notebook.created_at,
+        })
+    }).collect();
+    listed.sort_by(|(a, _), (b, _)| {
+        a.iter().zip(b.iter())
+            .map(|(a, b)| collation::compare_titles(a, b, &locale))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len()))
+    });
+    Ok(listed.into_iter().map(|(_, notebook)| notebook).collect())
+}
+
+
+/// Moves local notes to a notebook.
+///
+/// # Arguments
+///
+/// * `note_ids` - The IDs of the notes.
+/// * `notebook_id` - The ID of the notebook, or `None` to move the notes out of any notebook.
+///
+/// # Operation
+///
+/// The notes are moved in a single transaction and reindexed so the `notebook:` filter of a search finds them. Their
+/// content is unchanged, so they are not updated. Their objects in the buckets move to the path of the notebook the
+/// next time the notes are uploaded (see `s3_operations::note_key`). The values of their custom fields are kept, and
+/// only those of the fields of their new notebook are shown (see `custom_fields::get_note_fields`).
+///
+/// # Returns
+///
+/// Returns the number of notes moved.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook or one of the notes does not exist, in which case no note is
+/// moved, or if there is an issue with the database connection.
+pub fn move_notes_to_notebook(note_ids: &[i64], notebook_id: Option<i64>) -> Result<usize, AppError> {
+    let mut conn = CONNECTION.lock().unwrap();
+    if let Some(notebook_id) = notebook_id {
+        check_notebook_exists(&conn, notebook_id)?;
+    }
+
+    let tx = conn.transaction()?;
+    for note_id in note_ids {
+        let updated = tx.execute(
+            "UPDATE notes SET notebook_id = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![notebook_id, note_id],
+        )?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("Note {} not found", note_id)));
+        }
+    }
+    tx.commit()?;
+
+    for note_id in note_ids {
+        search_index::schedule_reindex(*note_id);
+    }
+    Ok(note_ids.len())
+}
+
+
+/// Retrieves the local notes of a notebook.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook, or `None` for the notes outside of any notebook.
+/// * `include_sub_notebooks` - Whether to include the notes of the sub-notebooks, at any depth.
+///
+/// # Returns
+///
+/// Returns the notes with their plaintext content, sorted by title like `local_operations::get_local_notes`.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if the notes cannot be decrypted, e.g. while
+/// the vault is locked with a passphrase, or if there is an issue with the database connection.
+pub fn get_notes_by_notebook(notebook_id: Option<i64>, include_sub_notebooks: bool) -> Result<Vec<Note>, AppError> {
+    let locale = collation::get_locale()?;
+    let mut notes = match notebook_id {
+        Some(notebook_id) => {
+            let notebook_ids = {
+                let conn = CONNECTION.lock().unwrap();
+                let notebooks = notebook_rows(&conn)?;
+                if !notebooks.contains_key(&notebook_id) {
+                    return Err(AppError::NotFound("Notebook not found".to_string()));
+                }
+                match include_sub_notebooks {
+                    true => with_descendants(&notebooks, &[notebook_id]),
+                    false => vec![notebook_id],
+                }
+            };
+            let params: Vec<&dyn rusqlite::ToSql> = notebook_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+            local_operations::get_local_notes_where(&in_notebooks_condition("notebook_id", notebook_ids.len()), &params)?
+        },
+        None => local_operations::get_local_notes_where("notebook_id IS NULL", &[])?,
+    };
+    notes.sort_by(|a, b| collation::compare_titles(&a.title, &b.title, &locale));
+    Ok(notes)
+}
+
+
+/// Retrieves the settings of a notebook, applied to the notes created in it.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+///
+/// # Returns
+///
+/// Returns the `NotebookSettings`, the default ones if they were never set.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist or if there is an issue with the database connection.
+pub fn get_notebook_settings(notebook_id: i64) -> Result<NotebookSettings, String> {
+    let conn = CONNECTION.lock().unwrap();
+    check_notebook_exists(&conn, notebook_id)?;
+    read_settings(&conn, notebook_id)
+}
+
+
+/// Sets the settings of a notebook, applied to the notes created in it from then on (see `create_notebook_note`).
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `settings` - The settings: whether the new notes are secure, and the bucket they are uploaded to.
+///
+/// # Returns
+///
+/// Returns the settings as stored, without an empty bucket.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist or if there is an issue with the database connection.
+pub fn set_notebook_settings(notebook_id: i64, settings: NotebookSettings) -> Result<NotebookSettings, String> {
+    let settings = NotebookSettings {
+        secure: settings.secure,
+        bucket: settings.bucket.map(|bucket| bucket.trim().trim_matches('"').to_string()).filter(|bucket| !bucket.is_empty()),
+    };
+
+    let conn = CONNECTION.lock().unwrap();
+    check_notebook_exists(&conn, notebook_id)?;
+    conn.execute(
+        "INSERT INTO notebook_settings (notebook_id, settings) VALUES (?1, ?2)
+        ON CONFLICT (notebook_id) DO UPDATE SET settings = ?2",
+        params![notebook_id, serde_json::to_string(&settings).map_err(|e| e.to_string())?],
+    ).map_err(|e| e.to_string())?;
+    Ok(settings)
+}
+
+
+/// Creates a local note in a notebook, with the settings of the notebook applied to it.
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook.
+/// * `note` - The note to create, as for `local_operations::create_local_note`.
+///
+/// # Operation
+///
+/// * The note is secure if the notebook makes its notes secure.
+/// * The note is created and moved to the notebook.
+/// * A note created in a notebook bound to a bucket is then uploaded to it in the background, so a failed upload
+///   does not undo its creation.
+///
+/// # Returns
+///
+/// Returns the created note, as `local_operations::create_local_note` does.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist, if the note cannot be created, or if there
+/// is an issue with the database connection.
+pub async fn create_notebook_note(notebook_id: i64, mut note: Note) -> Result<Note, AppError> {
+    let settings = {
+        let conn = CONNECTION.lock().unwrap();
+        check_notebook_exists(&conn, notebook_id)?;
+        read_settings(&conn, notebook_id)?
+    };
+    note.secure |= settings.secure;
+
+    let created = local_operations::create_local_note(note.clone()).await?;
+    {
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute("UPDATE notes SET notebook_id = ?1 WHERE uuid = ?2", params![notebook_id, created.uuid])?;
+    }
+
+    if let Some(bucket_name) = settings.bucket {
+        // The note is uploaded with its plaintext content, which the upload encrypts
+        let uploaded = Note { uuid: created.uuid.clone(), ..note };
+        tokio::spawn(async move {
+            if let Err(e) = s3_operations::upload_note_to_bucket(&bucket_name, uploaded).await {
+                eprintln!("Failed to upload the note to bucket {}: {}", bucket_name, e);
+            }
+        });
+    }
+
+    Ok(created)
+}
+
+
+/// Finds a notebook by its name or its path, e.g. "Projects" or "Work/Projects", whatever its case, for the
+/// `notebook:` filter of a search and the scope of a find and replace.
+///
+/// # Arguments
+///
+/// * `name` - The name or the path of the notebook.
+///
+/// # Returns
+///
+/// Returns the IDs of the matching notebooks, several if several notebooks have the name in different parents, and
+/// those of all their sub-notebooks. It is empty if no notebook matches.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn notebook_ids_by_name(name: &str) -> Result<Vec<i64>, String> {
+    let name = name.trim().trim_matches('/').to_lowercase();
+    let conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    let matching: Vec<i64> = notebooks.values()
+        .filter(|notebook| notebook.name.to_lowercase() == name || path(&notebooks, notebook.id).to_lowercase() == name)
+        .map(|notebook| notebook.id)
+        .collect();
+    Ok(with_descendants(&notebooks, &matching))
+}
+
+
+/// Returns the path of a notebook, with the names of its ancestors separated by "/", e.g. "Work/Projects", so the
+/// objects of the notes in a bucket keep the structure of the notebooks (see `s3_operations::note_key`).
+///
+/// # Arguments
+///
+/// * `notebook_id` - The ID of the notebook, e.g. the `notebook_id` of a note, or `None`.
+///
+/// # Returns
+///
+/// Returns the path, or `None` without a notebook or if the notebook does not exist anymore.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn notebook_path(notebook_id: Option<i64>) -> Result<Option<String>, String> {
+    let Some(notebook_id) = notebook_id else {
+        return Ok(None);
+    };
+    let conn = CONNECTION.lock().unwrap();
+    let notebooks = notebook_rows(&conn)?;
+    Ok(notebooks.contains_key(&notebook_id).then(|| path(&notebooks, notebook_id)))
+}
+
+
+/// Returns the path of the notebook of a local note, where the object of the note is expected in a bucket.
+///
+/// # Arguments
+///
+/// * `uuid` - The UUID of the note.
+///
+/// # Returns
+///
+/// Returns the path of its notebook (see `notebook_path`), or `None` if the note is outside of any notebook or is not
+/// stored locally.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn note_notebook_path(uuid: &str) -> Result<Option<String>, String> {
+    let notebook_id = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row("SELECT notebook_id FROM notes WHERE uuid = ?1", params![uuid], |row| row.get::<_, Option<i64>>(0))
+            .optional()
+            .map_err(|e| e.to_string())?
+            .flatten()
+    };
+    notebook_path(notebook_id)
+}
+
+
+/// Checks that a notebook exists, e.g. before creating or moving a note into it.
+///
+/// # Errors
+///
+/// This function will return an error if the notebook does not exist or if there is an issue with the database connection.
+pub fn check_notebook_exists(conn: &Connection, notebook_id: i64) -> Result<(), AppError> {
+    conn.query_row("SELECT 1 FROM notebooks WHERE id = ?1", params![notebook_id], |_| Ok(()))
+        .optional()?
+        .ok_or(AppError::NotFound("Notebook not found".to_string()))
+}
+
+
+/// Returns an SQL condition on a column holding a notebook ID, for notes in any of `count` notebooks bound as parameters.
+pub fn in_notebooks_condition(column: &str, count: usize) -> String {
+    format!("{} IN ({})", column, vec!["?"; count].join(", "))
+}
+
+
+/// Reads the settings of a notebook, the default ones if they were never set.
+fn read_settings(conn: &Connection, notebook_id: i64) -> Result<NotebookSettings, String> {
+    let settings: Option<String> = conn.query_row(
+        "SELECT settings FROM notebook_settings WHERE notebook_id = ?1",
+        params![notebook_id],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())?;
+    match settings {
+        Some(settings) => serde_json::from_str(&settings).map_err(|e| format!("Invalid settings of notebook {}: {}", notebook_id, e)),
+        None => Ok(NotebookSettings::default()),
+    }
+}
+
+
+/// Reads a notebook with its path and its number of notes.
+fn get_notebook(conn: &Connection, id: i64) -> Result<Notebook, AppError> {
+    let notebooks = notebook_rows(conn)?;
+    let notebook = notebooks.get(&id).ok_or(AppError::NotFound("Notebook not found".to_string()))?;
+    let note_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM notes WHERE notebook_id = ?1 AND deleted_at IS NULL",
+        params![id],
+        |row| row.get(0),
+    )?;
+
+    Ok(Notebook {
+        id,
+        name: notebook.name.clone(),
+        parent_id: notebook.parent_id,
+        path: path(&notebooks, id),
+        note_count,
+        created_at: notebook.created_at,
+    })
+}
+
+
+/// Reads every notebook, by ID.
+fn notebook_rows(conn: &Connection) -> Result<HashMap<i64, NotebookRow>, String> {
+    let mut stmt = conn.prepare("SELECT id, name, parent_id, created_at FROM notebooks").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok(NotebookRow {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        created_at: row.get(3)?,
+    })).map_err(|e| e.to_string())?;
+    rows.map(|row| row.map(|row| (row.id, row)))
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|e| e.to_string())
+}
+
+
+/// Returns a notebook followed by its ancestors, up to its top-level notebook.
+fn ancestors(notebooks: &HashMap<i64, NotebookRow>, id: i64) -> Vec<&NotebookRow> {
+    let mut ancestors = Vec::new();
+    let mut current = notebooks.get(&id);
+    while let Some(notebook) = current {
+        // A parent that does not exist anymore, or a cycle, ends the path
+        if ancestors.len() > MAX_NOTEBOOK_DEPTH {
+            break;
+        }
+        ancestors.push(notebook);
+        current = notebook.parent_id.and_then(|parent_id| notebooks.get(&parent_id));
+    }
+    ancestors
+}
+
+
+/// Returns the path of a notebook, with the names of its ancestors separated by "/".
+fn path(notebooks: &HashMap<i64, NotebookRow>, id: i64) -> String {
+    ancestors(notebooks, id).into_iter().rev().map(|notebook| notebook.name.as_str()).collect::<Vec<_>>().join("/")
+}
+
+
+/// Returns the IDs of some notebooks and of all their sub-notebooks, at any depth.
+fn with_descendants(notebooks: &HashMap<i64, NotebookRow>, ids: &[i64]) -> Vec<i64> {
+    let mut all_ids: Vec<i64> = ids.to_vec();
+    let mut i = 0;
+    while i < all_ids.len() {
+        let parent_id = all_ids[i];
+        for notebook in notebooks.values().filter(|notebook| notebook.parent_id == Some(parent_id)) {
+            if !all_ids.contains(&notebook.id) {
+                all_ids.push(notebook.id);
+            }
+        }
+        i += 1;
+    }
+    all_ids
+}
+
+
+/// Checks the name of a notebook, which must be unique among the notebooks of its parent whatever its case.
+/// Returns the name without its surrounding whitespace.
+fn check_name(notebooks: &HashMap<i64, NotebookRow>, name: &str, parent_id: Option<i64>, except_id: Option<i64>) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The name of the notebook cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_NOTEBOOK_NAME_LENGTH {
+        return Err(format!("The name of the notebook cannot be longer than {} characters", MAX_NOTEBOOK_NAME_LENGTH));
+    }
+    if name.contains('/') || name.chars().any(|c| c.is_control()) {
+        return Err("The name of the notebook cannot contain '/' or control characters".to_string());
+    }
+    let taken = notebooks.values().any(|notebook| {
+        notebook.parent_id == parent_id && Some(notebook.id) != except_id && notebook.name.to_lowercase() == name.to_lowercase()
+    });
+    if taken {
+        return Err(format!("There is already a notebook named '{}' here", name));
+    }
+    Ok(name.to_string())
+}
@@ -0,0 +1,296 @@
+// notifier.rs
+
+use crate::models::{NotificationSettings, QuietHours};
+use crate::settings;
+use chrono::{Datelike, Local, NaiveTime, Timelike};
+use lazy_static::lazy_static;
+use notify_rust::Notification;
+use std::collections::{HashMap, VecDeque};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+/// The settings key under which the global mute is stored, as "true" or "false".
+const MUTED_SETTING: &str = "notifications_muted";
+
+/// The settings key under which the quiet hours are stored, as JSON.
+const QUIET_HOURS_SETTING: &str = "notification_quiet_hours";
+
+/// How long a group of notifications waits for another one before it is shown (see `notify_grouped`).
+const GROUP_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long a group of notifications waits at most, so a long bulk operation still reports its progress.
+const GROUP_MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// The maximum number of notifications shown per minute, beyond which they are dropped.
+const MAX_NOTIFICATIONS_PER_MINUTE: usize = 6;
+
+/// The placeholder of the number of notifications in the text of a group (see `notify_grouped`).
+const COUNT_PLACEHOLDER: &str = "{count}";
+
+
+lazy_static! {
+    /// The global mute and the quiet hours, kept in memory since notifications are sent while the database is locked.
+    static ref NOTIFICATION_SETTINGS: Mutex<(bool, QuietHours)> = Mutex::new((false, QuietHours::default()));
+
+    /// The groups of notifications waiting to be shown, by group.
+    static ref PENDING_GROUPS: Mutex<HashMap<String, PendingGroup>> = Mutex::new(HashMap::new());
+
+    /// When the notifications of the last minute were shown, oldest first.
+    static ref SHOWN_AT: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+}
+
+
+/// Notifications of a group waiting to be shown as one.
+struct PendingGroup {
+    count: usize,
+    summary: String,
+    body: String,
+    batch_summary: String,
+    batch_body: String,
+    first_at: Instant,
+    last_at: Instant,
+}
+
+
+/// Sends a desktop notification, unless notifications are muted, in quiet hours, or the system is in do-not-disturb mode.
+///
+/// # Arguments
+///
+/// * `summary` - The title of the notification.
+/// * `body` - The text of the notification.
+///
+/// Failing to show a notification never fails the operation that sent it, so errors are only printed.
+pub fn notify(summary: &str, body: &str) {
+    let silenced = {
+        let notification_settings = NOTIFICATION_SETTINGS.lock().unwrap();
+        let (muted, quiet_hours) = &*notification_settings;
+        *muted || in_quiet_hours(quiet_hours, Local::now().naive_local())
+    };
+    if silenced || os_do_not_disturb() == Some(true) {
+        return;
+    }
+    if !within_rate_limit(Instant::now()) {
+        eprintln!("Too many notifications, dropped '{}': {}", summary, body);
+        return;
+    }
+
+    if let Err(e) = Notification::new().summary(summary).body(body).show() {
+        eprintln!("Failed to show notification '{}': {}", summary, e);
+    }
+}
+
+
+/// Sends a desktop notification for one of many similar events, e.g. a note uploaded while a whole folder is, as a
+/// single notification for the group rather than one per event.
+///
+/// # Arguments
+///
+/// * `group` - The key of the group, e.g. "upload:notes-bucket", shared by the events shown together.
+/// * `summary` - The title of the notification, shown if the event is the only one of its group.
+/// * `body` - The text of the notification, shown if the event is the only one of its group.
+/// * `batch_summary` - The title of the notification of the group.
+/// * `batch_body` - The text of the notification of the group, where "{count}" is replaced with the number of events,
+///   e.g. "{count} notes were uploaded to bucket notes-bucket.".
+///
+/// # Operation
+///
+/// The notification is shown once no event of its group came for 2 seconds, or 30 seconds after the first event of
+/// the group if they keep coming, like `notify`. It is sent from a background thread, so the operation does not wait.
+pub fn notify_grouped(group: &str, summary: &str, body: &str, batch_summary: &str, batch_body: &str) {
+    let now = Instant::now();
+    {
+        let mut pending_groups = PENDING_GROUPS.lock().unwrap();
+        if let Some(pending) = pending_groups.get_mut(group) {
+            pending.count += 1;
+            pending.last_at = now;
+            return;
+        }
+        pending_groups.insert(group.to_string(), PendingGroup {
+            count: 1,
+            summary: summary.to_string(),
+            body: body.to_string(),
+            batch_summary: batch_summary.to_string(),
+            batch_body: batch_body.to_string(),
+            first_at: now,
+            last_at: now,
+        });
+    }
+
+    let group = group.to_string();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(GROUP_DEBOUNCE);
+        let pending = {
+            let mut pending_groups = PENDING_GROUPS.lock().unwrap();
+            let waiting = pending_groups.get(&group)
+                .is_some_and(|pending| pending.last_at.elapsed() < GROUP_DEBOUNCE && pending.first_at.elapsed() < GROUP_MAX_WAIT);
+            if waiting {
+                continue;
+            }
+            pending_groups.remove(&group)
+        };
+        if let Some(pending) = pending {
+            match pending.count {
+                1 => notify(&pending.summary, &pending.body),
+                count => notify(&pending.batch_summary, &pending.batch_body.replace(COUNT_PLACEHOLDER, &count.to_string())),
+            }
+        }
+        return;
+    });
+}
+
+
+/// Loads the global mute and the quiet hours from the settings. It is called once at startup.
+///
+/// # Errors
+///
+/// This function will return an error if the stored quiet hours are malformed or if there is an issue with the database connection.
+pub fn load_settings() -> Result<(), String> {
+    let muted = settings::get_setting(MUTED_SETTING)?.as_deref() == Some("true");
+    let quiet_hours = match settings::get_setting(QUIET_HOURS_SETTING)? {
+        Some(quiet_hours) => serde_json::from_str(&quiet_hours).map_err(|e| format!("Invalid quiet hours: {}", e))?,
+        None => QuietHours::default(),
+    };
+
+    *NOTIFICATION_SETTINGS.lock().unwrap() = (muted, quiet_hours);
+    Ok(())
+}
+
+
+/// Retrieves the notification settings.
+///
+/// # Returns
+///
+/// Returns a `NotificationSettings` with the global mute, the quiet hours, whether notifications are currently
+/// silenced by them, and the do-not-disturb state of the system, or `None` where it cannot be detected.
+pub fn get_notification_settings() -> NotificationSettings {
+    let (muted, quiet_hours) = NOTIFICATION_SETTINGS.lock().unwrap().clone();
+
+    NotificationSettings {
+        in_quiet_hours: in_quiet_hours(&quiet_hours, Local::now().naive_local()),
+        muted,
+        quiet_hours,
+        os_do_not_disturb: os_do_not_disturb(),
+    }
+}
+
+
+/// Mutes or unmutes every notification.
+///
+/// # Arguments
+///
+/// * `muted` - Whether notifications are muted.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setting is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn set_notifications_muted(muted: bool) -> Result<(), String> {
+    settings::set_setting(MUTED_SETTING, &muted.to_string())?;
+    NOTIFICATION_SETTINGS.lock().unwrap().0 = muted;
+    Ok(())
+}
+
+
+/// Configures the quiet hours, during which no notification is shown.
+///
+/// # Arguments
+///
+/// * `quiet_hours` - Whether they apply, their start and end in local "HH:MM" time, and the days they start on,
+///   0 being Monday. A window ending before it starts runs past midnight, e.g. from "22:00" to "07:00".
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the quiet hours are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if a time is not in "HH:MM" format, if a day is not between 0 and 6,
+/// or if there is an issue with the database connection.
+pub fn set_quiet_hours(quiet_hours: QuietHours) -> Result<(), String> {
+    parse_time(&quiet_hours.start)?;
+    parse_time(&quiet_hours.end)?;
+    if let Some(day) = quiet_hours.days.iter().find(|day| **day > 6) {
+        return Err(format!("Invalid day {}, days should be between 0 (Monday) and 6 (Sunday)", day));
+    }
+
+    let json = serde_json::to_string(&quiet_hours).map_err(|e| e.to_string())?;
+    settings::set_setting(QUIET_HOURS_SETTING, &json)?;
+    NOTIFICATION_SETTINGS.lock().unwrap().1 = quiet_hours;
+    Ok(())
+}
+
+
+/// Tells whether a local time falls in the quiet hours.
+fn in_quiet_hours(quiet_hours: &QuietHours, now: chrono::NaiveDateTime) -> bool {
+    let (Ok(start), Ok(end)) = (parse_time(&quiet_hours.start), parse_time(&quiet_hours.end)) else {
+        return false;
+    };
+    if !quiet_hours.enabled || start == end {
+        return false;
+    }
+
+    let time = NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).unwrap_or(start);
+    let today = now.weekday().num_days_from_monday();
+    let starts_on = |day: u32| quiet_hours.days.is_empty() || quiet_hours.days.contains(&day);
+
+    if start < end {
+        starts_on(today) && start <= time && time < end
+    } else {
+        // The window runs past midnight, so the early hours belong to the window started the day before
+        (starts_on(today) && time >= start) || (starts_on((today + 6) % 7) && time < end)
+    }
+}
+
+
+/// Records a notification about to be shown, unless too many were shown in the last minute.
+fn within_rate_limit(now: Instant) -> bool {
+    let mut shown_at = SHOWN_AT.lock().unwrap();
+    while shown_at.front().is_some_and(|shown| now.duration_since(*shown) >= Duration::from_secs(60)) {
+        shown_at.pop_front();
+    }
+    if shown_at.len() >= MAX_NOTIFICATIONS_PER_MINUTE {
+        return false;
+    }
+    shown_at.push_back(now);
+    true
+}
+
+
+/// Parses a local time in "HH:MM" format.
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("Invalid time '{}', expected HH:MM", time))
+}
+
+
+/// Detects whether the system is in do-not-disturb mode.
+///
+/// # Returns
+///
+/// Returns `None` where the state cannot be detected: it is read from the GNOME settings on Linux and from the
+/// Notification Center preferences on macOS.
+fn os_do_not_disturb() -> Option<bool> {
+    let output = if cfg!(target_os = "linux") {
+        Command::new("gsettings").args(["get", "org.gnome.desktop.notifications", "show-banners"]).output().ok()?
+    } else if cfg!(target_os = "macos") {
+        Command::new("defaults").args(["-currentHost", "read", "com.apple.notificationcenterui", "doNotDisturb"]).output().ok()?
+    } else {
+        return None;
+    };
+    if !output.status.success() {
+        return None;
+    }
+
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        // GNOME shows no banner in do-not-disturb mode
+        "false" if cfg!(target_os = "linux") => Some(true),
+        "true" if cfg!(target_os = "linux") => Some(false),
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
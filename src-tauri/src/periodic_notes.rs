@@ -0,0 +1,318 @@
+// periodic_notes.rs
+
+use crate::{ delta, journal_context, local_operations, note_events, notifier, settings, local_operations::CONNECTION, models::AppError, models::Note, models::Period, models::PeriodKind, models::PeriodicNote, models::PeriodicNoteFormat, models::PeriodicNoteSettings };
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::json;
+
+
+/// The settings key under which the naming and templates of the periodic notes are stored, as JSON.
+const PERIODIC_NOTES_SETTING: &str = "periodic_notes";
+
+/// The placeholder of the quarter in a title format, which strftime has no specifier for.
+const QUARTER_PLACEHOLDER: &str = "{{quarter}}";
+
+
+/// Retrieves how the periodic notes are named and what they are created with.
+///
+/// # Returns
+///
+/// Returns the `PeriodicNoteSettings`, with the default of each kind that was not configured: "2024-06-01" for a
+/// day, "2024-W22" for a week, "2024-06" for a month, and "2024-Q2" for a quarter, without a template.
+///
+/// # Errors
+///
+/// This function will return an error if the stored settings are malformed or if there is an issue with the database connection.
+pub fn get_periodic_note_settings() -> Result<PeriodicNoteSettings, String> {
+    match settings::get_setting(PERIODIC_NOTES_SETTING)? {
+        Some(periodic_notes) => serde_json::from_str(&periodic_notes).map_err(|e| format!("Invalid periodic note settings: {}", e)),
+        None => Ok(PeriodicNoteSettings::default()),
+    }
+}
+
+
+/// Configures how the periodic notes are named and what they are created with.
+///
+/// # Arguments
+///
+/// * `periodic_notes` - The format of each kind of periodic note:
+///   - `title_format` - The title of the note, formatted from the first day of the period with the strftime
+///     specifiers, e.g. "%Y-%m-%d" or "%B %Y", and "{{quarter}}" for the quarter.
+///   - `template` - The content of a new note, in which "{{title}}", "{{start}}", and "{{end}}" are replaced with
+///     the title and the first and last days of the period.
+///
+/// The notes created before keep their title. They are still found for their period, which is recorded when they are created.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if a title format is empty or invalid, or if there is an issue with the database connection.
+pub fn set_periodic_note_settings(periodic_notes: PeriodicNoteSettings) -> Result<(), String> {
+    for (kind, format) in formats(&periodic_notes) {
+        check_title_format(kind, &format.title_format)?;
+    }
+
+    let periodic_notes = serde_json::to_string(&periodic_notes).map_err(|e| e.to_string())?;
+    settings::set_setting(PERIODIC_NOTES_SETTING, &periodic_notes)
+}
+
+
+/// Retrieves the periodic note of a day, week, month, or quarter, creating it the first time.
+///
+/// # Arguments
+///
+/// * `kind` - The kind of period.
+/// * `date` - A day of the period as `YYYY-MM-DD`, or `None` for today. Weeks start on Monday.
+///
+/// # Operation
+///
+/// * The note of the period is found from the "periodic_notes" table, so it is found even after it was renamed.
+/// * Otherwise, a note is created with the title and the template of the kind (see `set_periodic_note_settings`),
+///   and recorded as the note of the period.
+/// * A new daily note starts with the context it is stamped with, if enabled (see `journal_context::daily_context`).
+///
+/// It blocks while the weather of a new daily note is fetched, so it must not be called from the async runtime directly.
+///
+/// # Returns
+///
+/// Returns a `PeriodicNote` with the period, the note with its plaintext content, and whether it was just created.
+///
+/// # Errors
+///
+/// This function will return an error if the date is invalid, if the settings are malformed, or if there is an
+/// issue with the database connection.
+pub fn get_periodic_note(kind: PeriodKind, date: Option<&str>) -> Result<PeriodicNote, AppError> {
+    let format = format_of(&get_periodic_note_settings()?, kind).clone();
+    let (start, end) = period_bounds(kind, parse_date(date)?);
+    let title = local_operations::truncate_title(&period_title(kind, &format.title_format, start)?);
+
+    // The context of a new daily note is gathered first, since fetching the weather must not hold the connection
+    let context = if kind == PeriodKind::Daily && period_note(&CONNECTION.lock().unwrap(), kind, start)?.is_none() {
+        journal_context::daily_context(start)?
+    } else {
+        None
+    };
+
+    let (note_id, created) = {
+        let mut conn = CONNECTION.lock().unwrap();
+        match period_note(&conn, kind, start)? {
+            Some(note_id) => (note_id, false),
+            None => {
+                let note = Note {
+                    id: None,
+                    uuid: None,
+                    title: title.clone(),
+                    content: delta::to_content(vec![json!({
+                        "insert": format!("{}{}\n", context.unwrap_or_default(), fill_template(&format.template, &title, start, end)),
+                    })]),
+                    nonce: None,
+                    created_at: chrono::Utc::now().timestamp(),
+                    updated_at: None,
+                    timestamp: None,
+                    secure: false,
+                    status: None,
+                    icon: None,
+                    cover_attachment_id: None,
+                    locked: false,
+                    pinned: false,
+                    archived: false,
+                    notebook_id: None,
+                    aliases: Vec::new(),
+                    tags: Vec::new(),
+                };
+                local_operations::validate_params(note.clone())?;
+
+                let tx = conn.transaction()?;
+                let note_id = local_operations::insert_note(&tx, &note)?;
+                tx.execute(
+                    "INSERT INTO periodic_notes (kind, period_start, note_id) VALUES (?1, ?2, ?3)",
+                    params![kind_name(kind), start.to_string(), note_id],
+                )?;
+                note_events::note_saved(&tx, note_id, &note.content)?;
+                tx.commit()?;
+                (note_id, true)
+            },
+        }
+    };
+    if created {
+        notifier::notify("New note created", &format!("Note with title '{}' was created.", title));
+    }
+
+    let note = local_operations::get_local_notes_where("id = ?1", &[&note_id])?
+        .pop()
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    Ok(PeriodicNote {
+        period: Period {
+            kind,
+            start: start.to_string(),
+            end: end.to_string(),
+            title,
+            note_id: Some(note_id),
+        },
+        note,
+        created,
+    })
+}
+
+
+/// Retrieves a period before or after the period of a day, to move between periodic notes without creating them.
+///
+/// # Arguments
+///
+/// * `kind` - The kind of period.
+/// * `date` - A day of the period as `YYYY-MM-DD`, or `None` for today.
+/// * `offset` - The number of periods to move by, e.g. -1 for the previous period, 1 for the next, or 0 for the period itself.
+///
+/// # Returns
+///
+/// Returns the `Period`, with the ID of its note if it has one. Its title is the title its note would be created with.
+///
+/// # Errors
+///
+/// This function will return an error if the date is invalid or out of range, if the settings are malformed, or if
+/// there is an issue with the database connection.
+pub fn get_period(kind: PeriodKind, date: Option<&str>, offset: i32) -> Result<Period, String> {
+    let format = format_of(&get_periodic_note_settings()?, kind).clone();
+    let (start, _) = period_bounds(kind, parse_date(date)?);
+    let (start, end) = period_bounds(kind, shift(kind, start, offset).ok_or("Date out of range".to_string())?);
+
+    let conn = CONNECTION.lock().unwrap();
+    Ok(Period {
+        kind,
+        start: start.to_string(),
+        end: end.to_string(),
+        title: local_operations::truncate_title(&period_title(kind, &format.title_format, start)?),
+        note_id: period_note(&conn, kind, start)?,
+    })
+}
+
+
+/// Removes a deleted note from the periodic notes, so a new one is created for its period.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_periodic_note(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM periodic_notes WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Returns the format of each kind of periodic note.
+fn formats(periodic_notes: &PeriodicNoteSettings) -> [(PeriodKind, &PeriodicNoteFormat); 4] {
+    [
+        (PeriodKind::Daily, &periodic_notes.daily),
+        (PeriodKind::Weekly, &periodic_notes.weekly),
+        (PeriodKind::Monthly, &periodic_notes.monthly),
+        (PeriodKind::Quarterly, &periodic_notes.quarterly),
+    ]
+}
+
+
+/// Returns the format of a kind of periodic note.
+fn format_of(periodic_notes: &PeriodicNoteSettings, kind: PeriodKind) -> &PeriodicNoteFormat {
+    match kind {
+        PeriodKind::Daily => &periodic_notes.daily,
+        PeriodKind::Weekly => &periodic_notes.weekly,
+        PeriodKind::Monthly => &periodic_notes.monthly,
+        PeriodKind::Quarterly => &periodic_notes.quarterly,
+    }
+}
+
+
+/// Returns the name of a kind of period, as stored in the "periodic_notes" table.
+fn kind_name(kind: PeriodKind) -> &'static str {
+    match kind {
+        PeriodKind::Daily => "daily",
+        PeriodKind::Weekly => "weekly",
+        PeriodKind::Monthly => "monthly",
+        PeriodKind::Quarterly => "quarterly",
+    }
+}
+
+
+/// Checks that a title format is not empty and only has valid strftime specifiers.
+fn check_title_format(kind: PeriodKind, title_format: &str) -> Result<(), String> {
+    if title_format.trim().is_empty() {
+        return Err(format!("The title format of the {} notes is empty", kind_name(kind)));
+    }
+    if StrftimeItems::new(title_format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid title format '{}' for the {} notes", title_format, kind_name(kind)));
+    }
+    Ok(())
+}
+
+
+/// Parses a `YYYY-MM-DD` day, or returns today in the local time zone.
+fn parse_date(date: Option<&str>) -> Result<NaiveDate, String> {
+    match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", date)),
+        None => Ok(chrono::Local::now().date_naive()),
+    }
+}
+
+
+/// Returns the first and last days of the period of a day.
+fn period_bounds(kind: PeriodKind, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = match kind {
+        PeriodKind::Daily => date,
+        PeriodKind::Weekly => date - Days::new(date.weekday().num_days_from_monday() as u64),
+        PeriodKind::Monthly => date.with_day(1).unwrap_or(date),
+        PeriodKind::Quarterly => NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1).unwrap_or(date),
+    };
+    let end = shift(kind, start, 1).and_then(|next| next.pred_opt()).unwrap_or(start);
+    (start, end)
+}
+
+
+/// Moves the first day of a period by a number of periods.
+fn shift(kind: PeriodKind, start: NaiveDate, offset: i32) -> Option<NaiveDate> {
+    let (days, months) = match kind {
+        PeriodKind::Daily => (1, 0),
+        PeriodKind::Weekly => (7, 0),
+        PeriodKind::Monthly => (0, 1),
+        PeriodKind::Quarterly => (0, 3),
+    };
+    let count = offset.unsigned_abs();
+    if offset >= 0 {
+        start.checked_add_days(Days::new(days * count as u64))?.checked_add_months(Months::new(months * count))
+    } else {
+        start.checked_sub_days(Days::new(days * count as u64))?.checked_sub_months(Months::new(months * count))
+    }
+}
+
+
+/// Formats the title of the note of a period from its first day.
+fn period_title(kind: PeriodKind, title_format: &str, start: NaiveDate) -> Result<String, String> {
+    check_title_format(kind, title_format)?;
+    let title_format = title_format.replace(QUARTER_PLACEHOLDER, &(start.month0() / 3 + 1).to_string());
+    Ok(start.format(&title_format).to_string())
+}
+
+
+/// Fills the placeholders of the template of a periodic note.
+fn fill_template(template: &str, title: &str, start: NaiveDate, end: NaiveDate) -> String {
+    template
+        .replace("{{title}}", title)
+        .replace("{{start}}", &start.to_string())
+        .replace("{{end}}", &end.to_string())
+}
+
+
+/// Finds the note of a period, if it was created.
+fn period_note(conn: &Connection, kind: PeriodKind, start: NaiveDate) -> Result<Option<i64>, String> {
+    conn.query_row(
+        "SELECT note_id FROM periodic_notes WHERE kind = ?1 AND period_start = ?2",
+        params![kind_name(kind), start.to_string()],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())
+}
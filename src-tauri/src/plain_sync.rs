@@ -0,0 +1,131 @@
+// plain_sync.rs
+
+use crate::{ delta, local_operations::CONNECTION, models::AppError, models::PlainSyncNote };
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use std::collections::HashMap;
+
+
+/// The metadata field telling how the content of a note object is encrypted, written only on the unencrypted objects.
+pub const ENCRYPTION_METADATA: &str = "encryption";
+
+/// The value of the "encryption" metadata field of the objects holding unencrypted Markdown.
+pub const PLAIN_ENCRYPTION: &str = "none";
+
+/// The content type of the objects holding unencrypted Markdown.
+pub const PLAIN_CONTENT_TYPE: &str = "text/markdown; charset=utf-8";
+
+
+/// Marks a note as synced in plain text, or back to encrypted.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `enabled` - Whether the copies of the note in the buckets are unencrypted Markdown, e.g. for a static-site
+///   pipeline or another tool reading the bucket, or encrypted like every other note.
+///
+/// # Operation
+///
+/// The copies in the buckets are rewritten the next time the buckets are synced (see `sync::sync_bucket`), even if
+/// the note did not change. Anyone who can read the bucket can then read the note, so a secure note cannot be synced
+/// in plain text, and a note made secure afterwards is encrypted again.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is marked, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it is secure, or if there is an issue with the
+/// database connection.
+pub fn set_plain_sync(note_id: i64, enabled: bool) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let (uuid, secure): (Option<String>, bool) = conn.query_row(
+        "SELECT uuid, secure FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+        params![note_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?
+        .ok_or(AppError::NotFound("Note not found".to_string()))?;
+    let uuid = uuid.filter(|uuid| !uuid.is_empty()).ok_or("The note has no UUID, so it cannot be synced".to_string())?;
+
+    if enabled {
+        if secure {
+            return Err(AppError::Validation("Secure notes are always encrypted in the buckets".to_string()));
+        }
+        conn.execute(
+            "INSERT OR IGNORE INTO plain_sync_notes (note_uuid, enabled_at) VALUES (?1, ?2)",
+            params![uuid, chrono::Utc::now().timestamp()],
+        )?;
+    } else {
+        conn.execute("DELETE FROM plain_sync_notes WHERE note_uuid = ?1", params![uuid])?;
+    }
+    Ok(())
+}
+
+
+/// Lists the notes synced in plain text.
+///
+/// # Returns
+///
+/// Returns a vector of `PlainSyncNote`, by title, telling for each note whether it is secure, in which case it is
+/// encrypted regardless.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_plain_sync_notes() -> Result<Vec<PlainSyncNote>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT notes.id, notes.uuid, notes.title, notes.secure, plain_sync_notes.enabled_at FROM plain_sync_notes
+        JOIN notes ON notes.uuid = plain_sync_notes.note_uuid
+        WHERE notes.deleted_at IS NULL
+        ORDER BY notes.title COLLATE NOCASE"
+    ).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| Ok(PlainSyncNote {
+        note_id: row.get(0)?,
+        uuid: row.get(1)?,
+        title: row.get(2)?,
+        secure: row.get(3)?,
+        enabled_at: row.get(4)?,
+    }))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(notes)
+}
+
+
+/// Tells whether a note is uploaded as unencrypted Markdown: it is marked with `set_plain_sync` and is not secure.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn is_plain_sync(uuid: &str, secure: bool) -> Result<bool, String> {
+    if secure {
+        return Ok(false);
+    }
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row("SELECT EXISTS(SELECT 1 FROM plain_sync_notes WHERE note_uuid = ?1)", params![uuid], |row| row.get(0))
+        .map_err(|e| e.to_string())
+}
+
+
+/// Tells whether a note object holds unencrypted Markdown, from its metadata.
+pub fn is_plain_object(metadata: &HashMap<String, String>) -> bool {
+    metadata.get(ENCRYPTION_METADATA).is_some_and(|encryption| encryption == PLAIN_ENCRYPTION)
+}
+
+
+/// Builds the content of a note from the unencrypted Markdown of its object, which keeps the Markdown as text since
+/// the formatting of the editor is not stored in the bucket.
+///
+/// # Errors
+///
+/// This function will return an error if the object is marked secure, which is never uploaded unencrypted, so the
+/// object was not written by the application and is not trusted.
+pub fn content_from_markdown(markdown: &str, metadata: &HashMap<String, String>) -> Result<String, String> {
+    if metadata.get("secure").is_some_and(|secure| secure == "true") {
+        return Err("A secure note is stored unencrypted in the bucket".to_string());
+    }
+    Ok(delta::to_content(vec![json!({ "insert": format!("{}\n", markdown.trim_end()) })]))
+}
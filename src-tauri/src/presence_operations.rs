@@ -0,0 +1,252 @@
+// presence_operations.rs
+
+use crate::{ s3_operations, settings, models::NotePresence };
+use aws_sdk_s3 as s3;
+use uuid::Uuid;
+
+
+/// The prefix of the presence markers in a bucket. Objects under it are not notes.
+pub const PRESENCE_PREFIX: &str = ".presence/";
+
+/// The number of seconds a presence marker is valid for. Editors refresh their marker before it expires.
+const PRESENCE_TTL: i64 = 60;
+
+/// The settings key under which the identifier of this device is stored.
+const DEVICE_ID_SETTING: &str = "device_id";
+
+/// The settings key under which the name shown to collaborators is stored.
+const DISPLAY_NAME_SETTING: &str = "display_name";
+
+
+/// Marks a note of a shared bucket as being edited on this device, or clears the mark.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `note_uuid` - The UUID of the note.
+/// * `editing` - Whether the note is being edited.
+///
+/// # Operation
+///
+/// * While editing, a tiny marker object is written under ".presence/{note_uuid}/{device_id}.json". It expires after
+///   60 seconds, so the editor should call this function again about every 30 seconds.
+/// * When editing stops, the marker is deleted.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the marker is written or deleted successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error or if there is an issue with the database connection.
+pub async fn set_note_presence(bucket_name: &str, note_uuid: &str, editing: bool) -> Result<(), String> {
+    let (device_id, display_name) = device_identity()?;
+    let key = format!("{}{}/{}.json", PRESENCE_PREFIX, note_uuid, device_id);
+
+    let client = s3_operations::s3_client().await;
+
+    if !editing {
+        client.delete_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to clear presence: {}", e))?;
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let presence = NotePresence {
+        device_id,
+        display_name,
+        updated_at: now,
+        expires_at: now + PRESENCE_TTL,
+    };
+    let body = serde_json::to_vec(&presence).map_err(|e| e.to_string())?;
+
+    client.put_object()
+        .bucket(bucket_name)
+        .key(&key)
+        .body(s3::primitives::ByteStream::from(body))
+        .content_type("application/json")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to write presence: {}", e))?;
+
+    Ok(())
+}
+
+
+/// Retrieves the collaborators currently editing a note of a shared bucket.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `note_uuid` - The UUID of the note.
+///
+/// # Returns
+///
+/// Returns the presence markers of the other devices that have not expired, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error or if there is an issue with the database connection.
+pub async fn get_note_presence(bucket_name: &str, note_uuid: &str) -> Result<Vec<NotePresence>, String> {
+    let (device_id, _) = device_identity()?;
+
+    let client = s3_operations::s3_client().await;
+
+    let list_output = client.list_objects_v2()
+        .bucket(bucket_name)
+        .prefix(format!("{}{}/", PRESENCE_PREFIX, note_uuid))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to list presence: {}", e))?;
+
+    let now = chrono::Utc::now().timestamp();
+    let mut presences = Vec::new();
+    for object in list_output.contents() {
+        let key = match object.key() {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let get_output = client.get_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to read presence: {}", e))?;
+        let body = get_output.body.collect().await.map_err(|e| e.to_string())?.to_vec();
+
+        // Markers that cannot be read are ignored, they expire anyway
+        match serde_json::from_slice::<NotePresence>(&body) {
+            Ok(presence) if presence.expires_at > now && presence.device_id != device_id => presences.push(presence),
+            Ok(_) => (),
+            Err(e) => eprintln!("Invalid presence marker {}: {}", key, e),
+        }
+    }
+
+    Ok(presences)
+}
+
+
+/// Finds the presence markers of a bucket that expired, e.g. left by an editor that crashed, and deletes them if
+/// asked to (see `doctor`).
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `fix` - Whether to delete the expired markers.
+///
+/// # Returns
+///
+/// Returns a description of each expired or unreadable marker, and the number of markers deleted.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error.
+pub async fn clean_expired_markers(bucket_name: &str, fix: bool) -> Result<(Vec<String>, usize), String> {
+    let client = s3_operations::s3_client().await;
+
+    let mut response = client.list_objects_v2()
+        .bucket(bucket_name)
+        .prefix(PRESENCE_PREFIX)
+        .into_paginator()
+        .send();
+    let mut keys = Vec::new();
+    while let Some(result) = response.next().await {
+        let output = result.map_err(|e| format!("Failed to list presence: {}", e))?;
+        keys.extend(output.contents().iter().filter_map(|object| object.key().map(|key| key.to_string())));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut problems = Vec::new();
+    let mut fixed = 0;
+    for key in keys {
+        let get_output = client.get_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to read presence: {}", e))?;
+        let body = get_output.body.collect().await.map_err(|e| e.to_string())?.to_vec();
+
+        match serde_json::from_slice::<NotePresence>(&body) {
+            Ok(presence) if presence.expires_at > now => continue,
+            Ok(presence) => problems.push(format!("Presence marker {} of {} expired", key, presence.display_name)),
+            Err(_) => problems.push(format!("Presence marker {} cannot be read", key)),
+        }
+
+        if fix {
+            client.delete_object()
+                .bucket(bucket_name)
+                .key(&key)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to clear presence: {}", e))?;
+            fixed += 1;
+        }
+    }
+
+    Ok((problems, fixed))
+}
+
+
+/// Stores the name shown to collaborators editing the same notes.
+///
+/// # Arguments
+///
+/// * `display_name` - The name to show.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the name is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the name is empty or too long, or if there is an issue with the database connection.
+pub fn set_display_name(display_name: &str) -> Result<(), String> {
+    let display_name = display_name.trim();
+    if display_name.is_empty() || display_name.chars().count() > 50 {
+        return Err("Display name should be between 1 and 50 characters".to_string());
+    }
+
+    settings::set_setting(DISPLAY_NAME_SETTING, display_name)
+}
+
+
+/// Retrieves the name shown to collaborators, if one was set with `set_display_name`.
+///
+/// # Returns
+///
+/// Returns the name, or `None` if the user name of the operating system is shown instead.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_display_name() -> Result<Option<String>, String> {
+    settings::get_setting(DISPLAY_NAME_SETTING)
+}
+
+
+/// Returns the identifier of this device, generated on first use, and the name shown to collaborators.
+///
+/// The name defaults to the user name of the operating system.
+fn device_identity() -> Result<(String, String), String> {
+    let device_id = match settings::get_setting(DEVICE_ID_SETTING)? {
+        Some(device_id) => device_id,
+        None => {
+            let device_id = Uuid::new_v4().to_string();
+            settings::set_setting(DEVICE_ID_SETTING, &device_id)?;
+            device_id
+        },
+    };
+
+    let display_name = settings::get_setting(DISPLAY_NAME_SETTING)?
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    Ok((device_id, display_name))
+}
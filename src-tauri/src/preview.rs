@@ -0,0 +1,71 @@
+// preview.rs
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+
+/// The maximum number of characters of a preview.
+const PREVIEW_LENGTH: usize = 200;
+
+
+lazy_static! {
+    /// Matches Markdown links and images, capturing their text.
+    static ref MARKDOWN_LINK: Regex = Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap();
+
+    /// Matches the Markdown markers at the start of a line: headings, quotes, and list items.
+    static ref MARKDOWN_LINE_MARKER: Regex = Regex::new(r"(?m)^\s*(#{1,6}\s+|>\s?|[-*+]\s+|\d+\.\s+)").unwrap();
+
+    /// Matches the Markdown emphasis, strikethrough, and code markers.
+    static ref MARKDOWN_INLINE_MARKER: Regex = Regex::new(r"(\*\*|__|\*|_|~~|`+)").unwrap();
+}
+
+
+/// Extracts the plain text of the content of a note.
+///
+/// # Arguments
+///
+/// * `content` - The content of the note, usually a Quill delta in JSON.
+///
+/// # Returns
+///
+/// Returns the text inserted by the delta, or the content itself if it is not a delta.
+pub fn plain_text(content: &str) -> String {
+    let delta: serde_json::Value = match serde_json::from_str(content) {
+        Ok(delta) => delta,
+        Err(_) => return content.to_string(),
+    };
+
+    // The editor either saves the delta object or its array of operations
+    let ops = match delta.get("ops").unwrap_or(&delta).as_array() {
+        Some(ops) => ops,
+        None => return content.to_string(),
+    };
+
+    ops.iter()
+        .filter_map(|op| op.get("insert").and_then(|insert| insert.as_str()))
+        .collect()
+}
+
+
+/// Builds the short plaintext preview of the content of a note.
+///
+/// # Arguments
+///
+/// * `content` - The content of the note.
+///
+/// # Returns
+///
+/// Returns the first 200 characters of the plain text of the note, stripped of Markdown and with its whitespace collapsed.
+pub fn build_preview(content: &str) -> String {
+    let text = plain_text(content);
+    let text = MARKDOWN_LINK.replace_all(&text, "$1");
+    let text = MARKDOWN_LINE_MARKER.replace_all(&text, "");
+    let text = MARKDOWN_INLINE_MARKER.replace_all(&text, "");
+
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(PREVIEW_LENGTH)
+        .collect()
+}
@@ -0,0 +1,120 @@
+// print_operations.rs
+
+use crate::{ delta, local_operations, markdown, vault, models::AppError, models::PrintOptions };
+use uuid::Uuid;
+
+
+/// The prefix of the names of the print documents in the temporary directory.
+const PRINT_FILE_PREFIX: &str = "customnotes-print-";
+
+/// The style of the print documents: the page margins, the page breaks between the notes, and a layout readable on
+/// paper, which does not split the code blocks, tables, and images across pages when they fit on one.
+const PRINT_STYLE: &str = "@page { size: auto; margin: 18mm 16mm; }
+body { font-family: Georgia, 'Times New Roman', serif; font-size: 11pt; line-height: 1.5; color: #000; background: #fff; margin: 0; }
+.note { break-after: page; page-break-after: always; }
+.note:last-child { break-after: auto; page-break-after: auto; }
+.note-header, .note-footer { font-family: sans-serif; font-size: 8.5pt; color: #555; display: flex; justify-content: space-between; }
+.note-header { border-bottom: 0.5pt solid #999; padding-bottom: 2mm; margin-bottom: 6mm; }
+.note-footer { border-top: 0.5pt solid #999; padding-top: 2mm; margin-top: 8mm; }
+h1, h2, h3, h4, h5, h6 { break-after: avoid; page-break-after: avoid; font-family: sans-serif; }
+pre, table, img, blockquote { break-inside: avoid; page-break-inside: avoid; }
+pre, code { font-family: Menlo, Consolas, monospace; font-size: 9.5pt; }
+pre { white-space: pre-wrap; border: 0.5pt solid #ccc; padding: 3mm; }
+table { border-collapse: collapse; }
+th, td { border: 0.5pt solid #999; padding: 1mm 2mm; }
+img { max-width: 100%; }
+a { color: #000; }";
+
+
+/// Prepares notes for printing, as a single HTML document the window opens and prints.
+///
+/// # Arguments
+///
+/// * `note_ids` - The IDs of the notes, in the order they are printed.
+/// * `options` - Whether each note starts with a header holding its title and the date it is printed, and ends with
+///   a footer holding the date it was last updated.
+///
+/// # Operation
+///
+/// Each note is rendered from its Markdown, like `markdown::render_markdown`, and starts on a new page. The document
+/// is styled for paper: serif text, page margins, and code blocks, tables, and images kept on a single page when they fit.
+/// The document is written to the temporary directory of the system, rather than kept with the data of the application.
+///
+/// # Returns
+///
+/// Returns the path of the document, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is no note, if a note does not exist, if a note is secure while the
+/// vault is locked, if the document cannot be written, or if there is an issue with the database connection.
+pub fn prepare_print(note_ids: &[i64], options: PrintOptions) -> Result<String, AppError> {
+    if note_ids.is_empty() {
+        return Err(AppError::Validation("Select at least one note to print".to_string()));
+    }
+
+    // Get the notes in the requested order
+    let placeholders = vec!["?"; note_ids.len()].join(", ");
+    let params: Vec<&dyn rusqlite::ToSql> = note_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let found = local_operations::get_local_notes_where(&format!("id IN ({})", placeholders), &params)?;
+    let mut notes = Vec::new();
+    for id in note_ids {
+        let note = found.iter().find(|note| note.id == Some(*id)).ok_or(AppError::NotFound(format!("Note {} not found", id)))?;
+        if note.secure && !vault::is_unlocked() {
+            return Err(AppError::Encryption("Unlock the vault to print secure notes".to_string()));
+        }
+        notes.push(note);
+    }
+
+    let printed_on = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let mut sections = String::new();
+    for note in &notes {
+        sections.push_str("<section class=\"note\">\n");
+        if options.header {
+            sections.push_str(&format!(
+                "<header class=\"note-header\"><span>{}</span><span>{}</span></header>\n",
+                escape(&note.title),
+                printed_on,
+            ));
+        }
+        sections.push_str(&markdown::render_content(&delta::to_markdown(&note.content)));
+        if options.footer {
+            sections.push_str(&format!(
+                "<footer class=\"note-footer\"><span>{}</span><span>Updated {}</span></footer>\n",
+                escape(&note.title),
+                format_date(note.updated_at.unwrap_or(note.created_at)),
+            ));
+        }
+        sections.push_str("</section>\n");
+    }
+
+    let title = match notes.as_slice() {
+        [note] => note.title.clone(),
+        _ => format!("{} notes", notes.len()),
+    };
+    let document = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(&title),
+        PRINT_STYLE,
+        sections,
+    );
+
+    let path = std::env::temp_dir().join(format!("{}{}.html", PRINT_FILE_PREFIX, Uuid::new_v4()));
+    std::fs::write(&path, document).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(path.display().to_string())
+}
+
+
+/// Formats a Unix timestamp as a local date.
+fn format_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+
+/// Escapes a text for the content of an HTML element.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
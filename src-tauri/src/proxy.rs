@@ -0,0 +1,248 @@
+// proxy.rs
+
+use crate::{ s3_operations, settings, tls, models::ProxyConfig, models::ProxySettings, models::TlsRemote };
+use aws_sdk_s3::primitives::SdkBody;
+use aws_smithy_runtime_api::client::http::{http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpConnector};
+use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_runtime_api::http::StatusCode;
+use reqwest::{NoProxy, Proxy, Url};
+
+
+/// The settings key under which the proxy configuration is stored, as JSON.
+const CONFIG_SETTING: &str = "proxy";
+
+/// The settings key under which the password of the proxy is stored, encrypted.
+const PASSWORD_SETTING: &str = "proxy_password";
+
+/// The schemes of the supported proxies. "socks5h" resolves the host names through the proxy.
+const SCHEMES: &[&str] = &["http", "https", "socks5", "socks5h"];
+
+
+/// Retrieves the proxy configuration.
+///
+/// # Returns
+///
+/// Returns a `ProxySettings` with the configuration of the proxy, or `None` if it is not configured, and whether
+/// its password is set.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_proxy_settings() -> Result<ProxySettings, String> {
+    Ok(ProxySettings {
+        config: get_config()?,
+        has_password: settings::get_setting(PASSWORD_SETTING)?.is_some(),
+    })
+}
+
+
+/// Configures the proxy the S3 requests and the other outbound HTTP requests go through, e.g. the articles saved
+/// as notes, the capture bridge, and the weather of the journal.
+///
+/// # Arguments
+///
+/// * `config` - The address of the proxy, e.g. "http://proxy.example.com:3128" or "socks5://127.0.0.1:1080", the
+///   username it is authenticated with, if any, the hosts reached directly (e.g. "localhost" or ".example.com"),
+///   and whether the proxy is enabled.
+/// * `password` - The password of the proxy, stored encrypted. `None` keeps the current password, and an empty
+///   password removes it.
+///
+/// # Operation
+///
+/// The proxy applies to the requests sent from then on, without restarting the application, including the AWS SSO
+/// login, since the shared S3 client is built again. The update checks are not proxied, since the updater sends them with its own HTTP client.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the address is not an HTTP, HTTPS, or SOCKS5 proxy, if it holds the
+/// credentials, or if there is an issue with the database connection.
+pub fn set_proxy_settings(config: ProxyConfig, password: Option<String>) -> Result<(), String> {
+    let url = Url::parse(config.url.trim()).map_err(|e| format!("Invalid proxy address {}: {}", config.url, e))?;
+    if !SCHEMES.contains(&url.scheme()) {
+        return Err(format!("Invalid proxy address {}: the scheme should be one of {}", config.url, SCHEMES.join(", ")));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("Invalid proxy address {}: the host is missing", config.url));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err("The username and the password of the proxy should be set apart from its address".to_string());
+    }
+    let username = config.username.map(|username| username.trim().to_string()).filter(|username| !username.is_empty());
+    let no_proxy = config.no_proxy.iter()
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect();
+
+    match password {
+        Some(password) if password.is_empty() => settings::delete_setting(PASSWORD_SETTING)?,
+        Some(password) => settings::set_secret_setting(PASSWORD_SETTING, &password)?,
+        None => {},
+    }
+    let config = ProxyConfig { url: url.to_string(), username, no_proxy, ..config };
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)?;
+    s3_operations::invalidate_s3_client();
+    Ok(())
+}
+
+
+/// Returns a builder of blocking HTTP clients going through the proxy, if one is enabled.
+///
+/// # Errors
+///
+/// This function will return an error if the proxy configuration or its password cannot be read.
+pub fn blocking_client_builder() -> Result<reqwest::blocking::ClientBuilder, String> {
+    let builder = reqwest::blocking::Client::builder();
+    Ok(match proxy()? {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    })
+}
+
+
+/// Returns an HTTP client going through the proxy, if one is enabled, and trusting the certificates configured for
+/// a host (see `tls`).
+///
+/// # Arguments
+///
+/// * `host` - The host the client sends its requests to.
+///
+/// # Errors
+///
+/// This function will return an error if the proxy configuration, its password, or the certificates cannot be read.
+pub fn client_for_host(host: &str) -> Result<reqwest::Client, String> {
+    let remotes = tls::get_tls_remotes()?;
+    client(proxy()?.as_ref(), tls::remote_for_host(&remotes, host))
+}
+
+
+/// Sends the requests of an AWS configuration through the proxy, if one is enabled, trusting the certificates
+/// configured for their hosts (see `tls`).
+///
+/// The requests are then sent by a `reqwest` client, which supports the HTTP and the SOCKS proxies. If the proxy
+/// configuration or the certificates cannot be read, the error is printed and the configuration is left as it is.
+///
+/// # Arguments
+///
+/// * `config` - The AWS configuration.
+///
+/// # Returns
+///
+/// Returns the AWS configuration, with an HTTP client going through the proxy and trusting the certificates.
+pub fn apply_to_aws_config(config: aws_config::SdkConfig) -> aws_config::SdkConfig {
+    let connector = proxy().and_then(|proxy| {
+        let remotes = tls::get_tls_remotes()?;
+        if proxy.is_none() && remotes.is_empty() {
+            return Ok(None);
+        }
+        let remote_clients = remotes.iter()
+            .map(|remote| Ok((remote.host.clone(), client(proxy.as_ref(), Some(remote))?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Some(ReqwestConnector { default_client: client(proxy.as_ref(), None)?, remote_clients }))
+    });
+    match connector {
+        Ok(Some(connector)) => config.to_builder()
+            .http_client(http_client_fn(move |_settings, _components| SharedHttpConnector::new(connector.clone())))
+            .build(),
+        Ok(None) => config,
+        Err(e) => {
+            eprintln!("Failed to apply the proxy and the certificates to the S3 requests: {}", e);
+            config
+        },
+    }
+}
+
+
+/// Reads the proxy configuration.
+fn get_config() -> Result<Option<ProxyConfig>, String> {
+    match settings::get_setting(CONFIG_SETTING)? {
+        Some(config) => serde_json::from_str(&config).map(Some).map_err(|e| format!("Invalid proxy settings: {}", e)),
+        None => Ok(None),
+    }
+}
+
+
+/// Builds the enabled proxy, with its credentials and the hosts reached directly.
+fn proxy() -> Result<Option<Proxy>, String> {
+    let Some(config) = get_config()?.filter(|config| config.enabled) else {
+        return Ok(None);
+    };
+    let mut url = Url::parse(&config.url).map_err(|e| format!("Invalid proxy address {}: {}", config.url, e))?;
+    // The credentials are given in the address, which is how the SOCKS proxies are authenticated too
+    if let Some(username) = &config.username {
+        let password = settings::get_secret_setting(PASSWORD_SETTING)?.unwrap_or_default();
+        url.set_username(username).and_then(|_| url.set_password(Some(&password)))
+            .map_err(|_| format!("Invalid proxy address {}: it cannot hold credentials", config.url))?;
+    }
+
+    let proxy = Proxy::all(url).map_err(|e| format!("Invalid proxy address {}: {}", config.url, e))?;
+    Ok(Some(proxy.no_proxy(NoProxy::from_string(&config.no_proxy.join(",")))))
+}
+
+
+/// Builds an HTTP client going through a proxy and trusting the certificates of a remote, if any.
+fn client(proxy: Option<&Proxy>, remote: Option<&TlsRemote>) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    if let Some(remote) = remote {
+        builder = tls::trust(builder, remote)?;
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+
+/// An HTTP connector sending the requests of the AWS SDK with `reqwest` clients: the client of the remote whose
+/// certificates are trusted for the host of a request, or the default client.
+#[derive(Debug, Clone)]
+struct ReqwestConnector {
+    default_client: reqwest::Client,
+    remote_clients: Vec<(String, reqwest::Client)>,
+}
+
+impl HttpConnector for ReqwestConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let host = Url::parse(request.uri()).ok().and_then(|url| url.host_str().map(|host| host.to_string())).unwrap_or_default();
+        let client = self.remote_clients.iter()
+            .filter(|(remote_host, _)| tls::host_matches(remote_host, &host))
+            .max_by_key(|(remote_host, _)| remote_host.len())
+            .map(|(_, client)| client)
+            .unwrap_or(&self.default_client)
+            .clone();
+
+        HttpConnectorFuture::new(async move {
+            let method = reqwest::Method::from_bytes(request.method().as_bytes())
+                .map_err(|e| ConnectorError::other(e.into(), None))?;
+            // The notes are uploaded from memory, so the bodies are never streamed
+            let body = request.body().bytes()
+                .ok_or_else(|| ConnectorError::other("Streamed request bodies cannot be sent through the proxy".into(), None))?
+                .to_vec();
+            let mut builder = client.request(method, request.uri());
+            for (name, value) in request.headers() {
+                builder = builder.header(name, value);
+            }
+
+            let response = builder.body(body).send().await.map_err(|e| match e.is_timeout() {
+                true => ConnectorError::timeout(e.into()),
+                false => ConnectorError::io(e.into()),
+            })?;
+            let status = StatusCode::try_from(response.status().as_u16()).map_err(|e| ConnectorError::other(e.into(), None))?;
+            let headers = response.headers().clone();
+            let body = response.bytes().await.map_err(|e| ConnectorError::io(e.into()))?;
+
+            let mut http_response = HttpResponse::new(status, SdkBody::from(body));
+            for (name, value) in &headers {
+                if let Ok(value) = value.to_str() {
+                    http_response.headers_mut().append(name.as_str().to_string(), value.to_string());
+                }
+            }
+            Ok(http_response)
+        })
+    }
+}
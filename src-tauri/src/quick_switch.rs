@@ -0,0 +1,164 @@
+// quick_switch.rs
+
+use crate::{ alias_operations, local_operations::CONNECTION, models::QuickSwitchMatch };
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use rusqlite::{params, Connection, OptionalExtension};
+
+
+/// The number of matches returned when no limit is given.
+const DEFAULT_LIMIT: usize = 20;
+
+/// The maximum number of matches returned.
+const MAX_LIMIT: usize = 100;
+
+/// The number of seconds after which the weight of an opening is halved: two weeks.
+const FRECENCY_HALF_LIFE_SECONDS: f64 = 14.0 * 24.0 * 60.0 * 60.0;
+
+/// The weight of the frecency of a note in its score, added to the score of the match of its title. The frecency
+/// only counts logarithmically, so a note opened often comes first among similar matches without hiding better ones.
+const FRECENCY_WEIGHT: f64 = 10.0;
+
+
+/// The ID, title, icon, and current frecency of a local note.
+type NoteEntry = (i64, String, Option<String>, f64);
+
+
+/// Finds the notes whose title or alias matches a query typed in the quick switcher, fast enough to run on each keystroke.
+///
+/// # Arguments
+///
+/// * `query` - The query. Its characters should appear in the title in order, but not necessarily next to each other,
+///   e.g. "mtgn" matches "Meeting notes". Case is ignored unless the query has uppercase letters.
+/// * `limit` - The maximum number of matches, 20 by default and at most 100.
+///
+/// # Operation
+///
+/// * The title and the aliases of each note are matched, and the best of them counts.
+/// * Matches are ranked by how well they match, e.g. consecutive characters and the start of words count more, plus
+///   the frecency of the note: how often and how recently it was opened (see `record_note_opened`).
+///   The aliases are set with `alias_operations::set_note_aliases`.
+/// * With an empty query, the notes opened before are ranked by frecency alone, so the recently and frequently opened
+///   notes come first.
+///
+/// # Returns
+///
+/// Returns the matching notes, best first, with the alias that matched if it was not the title, and the positions of
+/// the matched characters in the title or alias, counted in characters, to highlight them.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn quick_switch(query: &str, limit: Option<usize>) -> Result<Vec<QuickSwitchMatch>, String> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let query = query.trim();
+    let now = chrono::Utc::now().timestamp();
+
+    let (notes, aliases) = {
+        let conn = CONNECTION.lock().unwrap();
+        (read_notes(&conn, now)?, alias_operations::read_aliases(&conn)?)
+    };
+
+    let matcher = SkimMatcherV2::default().smart_case();
+    let mut matches: Vec<QuickSwitchMatch> = notes.into_iter()
+        .filter_map(|(id, title, icon, frecency)| {
+            let bonus = FRECENCY_WEIGHT * frecency.ln_1p();
+            if query.is_empty() {
+                return (frecency > 0.0).then_some(QuickSwitchMatch { id, title, icon, alias: None, positions: Vec::new(), score: bonus });
+            }
+
+            let mut best = matcher.fuzzy_indices(&title, query).map(|(score, positions)| (score, None, positions));
+            for alias in aliases.get(&id).into_iter().flatten() {
+                if let Some((score, positions)) = matcher.fuzzy_indices(alias, query) {
+                    if best.as_ref().is_none_or(|(best_score, _, _)| score > *best_score) {
+                        best = Some((score, Some(alias.clone()), positions));
+                    }
+                }
+            }
+
+            best.map(|(score, alias, positions)| QuickSwitchMatch { id, title, icon, alias, positions, score: score as f64 + bonus })
+        })
+        .collect();
+
+    // Among equal scores, the shorter title is the closer match
+    matches.sort_by(|a, b| {
+        b.score.total_cmp(&a.score)
+            .then_with(|| a.title.chars().count().cmp(&b.title.chars().count()))
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+
+/// Records that a local note was opened, which ranks it higher in the quick switcher.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Operation
+///
+/// The frecency of the note is the number of times it was opened, each opening weighing half as much every two weeks.
+/// It is stored with the time of the last opening in the "note_opens" table.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn record_note_opened(note_id: i64) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp();
+    let conn = CONNECTION.lock().unwrap();
+    let previous: Option<(f64, i64)> = conn.query_row(
+        "SELECT frecency, opened_at FROM note_opens WHERE note_id = ?1",
+        params![note_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().map_err(|e| e.to_string())?;
+
+    let frecency = previous.map_or(0.0, |(frecency, opened_at)| decay(frecency, opened_at, now)) + 1.0;
+    conn.execute(
+        "INSERT OR REPLACE INTO note_opens (note_id, frecency, opened_at) VALUES (?1, ?2, ?3)",
+        params![note_id, frecency, now],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Removes the openings of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_note(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_opens WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Reads the ID, title, icon, and current frecency of every local note.
+fn read_notes(conn: &Connection, now: i64) -> Result<Vec<NoteEntry>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT notes.id, notes.title, notes.icon, note_opens.frecency, note_opens.opened_at
+        FROM notes LEFT JOIN note_opens ON note_opens.note_id = notes.id WHERE notes.deleted_at IS NULL"
+    ).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| {
+        let frecency: Option<f64> = row.get(3)?;
+        let opened_at: Option<i64> = row.get(4)?;
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, frecency.map_or(0.0, |frecency| decay(frecency, opened_at.unwrap_or(now), now))))
+    })
+    .map_err(|e| e.to_string())?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| e.to_string())?;
+    Ok(notes)
+}
+
+
+/// Returns a frecency as of now, each opening weighing half as much every two weeks since the last one.
+fn decay(frecency: f64, opened_at: i64, now: i64) -> f64 {
+    frecency * 0.5f64.powf((now - opened_at).max(0) as f64 / FRECENCY_HALF_LIFE_SECONDS)
+}
@@ -0,0 +1,288 @@
+// reencryption.rs
+
+use crate::{ crypto, key_management, tasks, local_operations::CONNECTION, key_management::ContentKey };
+use crate::models::{ReencryptionNote, ReencryptionProgress, ReencryptionStatus, TaskPriority};
+use crate::tasks::TaskContext;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::{Arc, OnceLock};
+use tauri::{AppHandle, Manager};
+
+
+/// The status of a note waiting to be encrypted again.
+const PENDING: &str = "pending";
+
+/// The status of a note encrypted again with the new key.
+const DONE: &str = "done";
+
+/// The status of a note with values that could be decrypted with neither key, e.g. corrupt values, left as they are.
+const FAILED: &str = "failed";
+
+/// The event telling the window how far the encryption is, after each note.
+const PROGRESS_EVENT: &str = "reencryption-progress";
+
+/// The encrypted columns holding the data of a note, with their table, the column of their nonce, and the condition
+/// selecting the rows of the note.
+const NOTE_COLUMNS: [(&str, &str, &str, &str); 5] = [
+    ("notes", "content", "nonce", "id = ?1"),
+    ("note_versions", "content", "nonce", "note_id = ?1"),
+    ("code_blocks", "code", "nonce", "note_id = ?1"),
+    ("cards", "card", "nonce", "note_id = ?1"),
+    ("attachment_texts", "text", "nonce", "attachment_id IN (SELECT id FROM attachments WHERE note_id = ?1)"),
+];
+
+
+/// The handle of the application, which tells the window about the progress.
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+
+
+/// Gives the handle of the application, to tell the window about the progress. It is called once the application is set up.
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+pub fn init(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+}
+
+
+/// Queues every local note to be encrypted again, when the key changes (see `key_management::set_passphrase`).
+///
+/// # Arguments
+///
+/// * `conn` - The transaction changing the key.
+///
+/// # Returns
+///
+/// Returns the number of queued notes.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn queue_notes(conn: &Connection) -> Result<usize, String> {
+    conn.execute("DELETE FROM reencryption_notes", []).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO reencryption_notes (note_id, status, updated_at) SELECT id, ?1, ?2 FROM notes",
+        params![PENDING, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())
+}
+
+
+/// Submits the task encrypting the local database again after a key change, if one is in progress. It is called
+/// when the key changes, at startup, and when the vault is unlocked, since the task stops when it is locked.
+///
+/// It does not use the database connection, so it can be called while it is locked.
+///
+/// # Returns
+///
+/// Returns the ID of the task, or `None` if no key change is in progress or if the vault is locked.
+pub fn resume_reencryption() -> Option<i64> {
+    key_management::previous_key()?;
+    Some(tasks::submit_unless_queued(tasks::REENCRYPT, "Encrypt the notes again with the new key", TaskPriority::High))
+}
+
+
+/// Retrieves how far the local database is encrypted again after a key change.
+///
+/// # Returns
+///
+/// Returns a `ReencryptionStatus` with whether a key change is in progress, the number of notes to encrypt again,
+/// done, and failed, and the status of each note: "pending", "done", or "failed" with the error. Once the key change
+/// is over, only the failed notes are kept.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_reencryption_status() -> Result<ReencryptionStatus, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT reencryption_notes.note_id, notes.title, reencryption_notes.status, reencryption_notes.error, reencryption_notes.updated_at
+        FROM reencryption_notes JOIN notes ON notes.id = reencryption_notes.note_id ORDER BY reencryption_notes.note_id"
+    ).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| {
+        Ok(ReencryptionNote {
+            note_id: row.get(0)?,
+            title: row.get(1)?,
+            status: row.get(2)?,
+            error: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(ReencryptionStatus {
+        progress: progress(&conn)?,
+        notes,
+    })
+}
+
+
+/// Runs the task encrypting the local database again after a key change, one note at a time.
+///
+/// # Operation
+///
+/// * Each note is encrypted again in its own transaction, with its versions, code blocks, flashcards, and the text
+///   of its attachments, so an interruption never leaves a note half encrypted. Its status is saved in the same
+///   transaction, so the task resumes with the next note after a cancellation, a crash, or a lock of the vault.
+/// * A value already encrypted with the new key is left as it is, e.g. a note saved since the key changed.
+/// * The values of no note, e.g. the mirror of the buckets, are encrypted again once the notes are, and the previous
+///   key is then forgotten.
+/// * The window is told about the progress with the "reencryption-progress" event, after each note.
+///
+/// # Errors
+///
+/// This function will return an error if the vault is locked, if a value cannot be encrypted, or if there is an
+/// issue with the database connection.
+pub fn run_reencryption(context: &TaskContext) -> Result<(), String> {
+    while !context.is_cancelled() {
+        let to = current_key()?;
+        let Some(from) = key_management::previous_key() else {
+            return Ok(());
+        };
+        let mut conn = CONNECTION.lock().unwrap();
+        let next: Option<i64> = conn.query_row(
+            "SELECT note_id FROM reencryption_notes WHERE status = ?1 ORDER BY note_id LIMIT 1",
+            params![PENDING],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+        let Some(note_id) = next else {
+            break;
+        };
+
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut unreadable = 0;
+        for (table, column, nonce_column, condition) in NOTE_COLUMNS {
+            unreadable += reencrypt_rows(&tx, (table, column, nonce_column), Some((condition, note_id)), &from, &to)?;
+        }
+        let (status, error) = match unreadable {
+            0 => (DONE, None),
+            _ => (FAILED, Some(format!("{} values could not be decrypted and were left as they are", unreadable))),
+        };
+        tx.execute(
+            "UPDATE reencryption_notes SET status = ?1, error = ?2, updated_at = ?3 WHERE note_id = ?4",
+            params![status, error, chrono::Utc::now().timestamp(), note_id],
+        ).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+
+        let progress = progress(&conn)?;
+        drop(conn);
+        emit_progress(&progress);
+    }
+
+    // The values of no note, and of the notes deleted before they were encrypted again
+    for (table, column, nonce_column) in key_management::ENCRYPTED_COLUMNS {
+        if context.is_cancelled() {
+            return Ok(());
+        }
+        let to = current_key()?;
+        let Some(from) = key_management::previous_key() else {
+            return Ok(());
+        };
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let unreadable = reencrypt_rows(&tx, (table, column, nonce_column), None, &from, &to)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        if unreadable > 0 {
+            eprintln!("{} values of {} could not be decrypted and were left as they are", unreadable, table);
+        }
+    }
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM reencryption_notes WHERE status = ?1", params![DONE]).map_err(|e| e.to_string())?;
+    key_management::end_key_change(&tx)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    key_management::forget_previous_key();
+
+    let progress = progress(&conn)?;
+    drop(conn);
+    emit_progress(&progress);
+    Ok(())
+}
+
+
+/// Removes the encryption status of a note after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_note(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM reencryption_notes WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Returns the current key, which the task stops without when the vault is locked.
+fn current_key() -> Result<Arc<ContentKey>, String> {
+    key_management::content_key()
+        .map_err(|_| "The vault was locked, the notes will be encrypted again once it is unlocked".to_string())
+}
+
+
+/// Encrypts again with the new key the values of an encrypted column, given with its table and the column of its
+/// nonce, in every row or in the rows of a note, given with the condition selecting them. Returns the number of
+/// values that could be decrypted with neither key.
+fn reencrypt_rows(conn: &Connection, (table, column, nonce_column): (&str, &str, &str), note: Option<(&str, i64)>, from: &ContentKey, to: &ContentKey) -> Result<usize, String> {
+    let (condition, note_id) = note.unzip();
+    let rows = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT rowid, {column}, {nonce_column} FROM {table} WHERE {column} IS NOT NULL AND {nonce_column} IS NOT NULL AND {}",
+            condition.unwrap_or("1 = 1"),
+        )).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(note_id), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        rows
+    };
+
+    let mut unreadable = 0;
+    for (rowid, encrypted_value, nonce_str) in rows {
+        if crypto::decrypt_content_with(to, &encrypted_value, &nonce_str).is_ok() {
+            continue;
+        }
+        let Ok(value) = crypto::decrypt_content_with(from, &encrypted_value, &nonce_str) else {
+            unreadable += 1;
+            continue;
+        };
+        let (encrypted_value, nonce_str) = crypto::encrypt_content_with(to, &value)?;
+        conn.execute(
+            &format!("UPDATE {table} SET {column} = ?1, {nonce_column} = ?2 WHERE rowid = ?3"),
+            params![encrypted_value, nonce_str, rowid],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    Ok(unreadable)
+}
+
+
+/// Counts the notes to encrypt again, done, and failed.
+fn progress(conn: &Connection) -> Result<ReencryptionProgress, String> {
+    let (total, done, failed): (i64, Option<i64>, Option<i64>) = conn.query_row(
+        "SELECT COUNT(*), SUM(status = ?1), SUM(status = ?2) FROM reencryption_notes",
+        params![DONE, FAILED],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).map_err(|e| e.to_string())?;
+
+    Ok(ReencryptionProgress {
+        in_progress: key_management::is_key_change_pending(conn)?,
+        total,
+        done: done.unwrap_or(0),
+        failed: failed.unwrap_or(0),
+    })
+}
+
+
+/// Tells the window how far the encryption is.
+fn emit_progress(progress: &ReencryptionProgress) {
+    if let Some(app) = APP_HANDLE.get() {
+        if let Err(e) = app.emit_all(PROGRESS_EVENT, progress) {
+            eprintln!("Failed to tell the window about the encryption progress: {}", e);
+        }
+    }
+}
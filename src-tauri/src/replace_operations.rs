@@ -0,0 +1,157 @@
+// replace_operations.rs
+
+use crate::{ crypto, local_operations, note_events, notebook_operations, notifier, preview, tag_operations, vault, version_operations };
+use crate::local_operations::CONNECTION;
+use crate::models::{LineChange, Note, ReplacePreview, ReplaceScope};
+use regex::{NoExpand, Regex};
+use rusqlite::params;
+
+
+/// Finds and replaces text across the local notes.
+///
+/// # Arguments
+///
+/// * `query` - The text to find, or a regular expression if `regex` is `true`.
+/// * `replacement` - The replacement text. With a regular expression, it can refer to capture groups, e.g. `$1`.
+/// * `regex` - Whether `query` is a regular expression or literal text.
+/// * `scope` - The notes to search. Without `note_ids`, every note is searched. With `tags`, only the notes with
+///   any of the tags are, and with `notebooks`, only the notes in any of the notebooks or their sub-notebooks, by
+///   name or by path (see `notebook_operations::notebook_ids_by_name`).
+/// * `dry_run` - Whether to only preview the replacements without applying them.
+///
+/// # Operation
+///
+/// * The content of every note in the scope is searched. Secure notes are skipped while the vault is locked, and
+///   locked notes are skipped (see `local_operations::set_note_locked`).
+/// * For each note with matches, a preview of the changed lines is built.
+/// * Unless `dry_run` is `true`, all the notes are updated in a single transaction, after saving a snapshot of
+///   each one in the "note_versions" table so the replacement can be undone.
+///
+/// # Returns
+///
+/// Returns a vector with a preview of the changes of each matching note, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the regular expression is invalid, if a notebook of the scope does not exist,
+/// if a note would become too long, or if there is an issue with the database connection.
+pub fn replace_in_notes(query: &str, replacement: &str, regex: bool, scope: ReplaceScope, dry_run: bool) -> Result<Vec<ReplacePreview>, String> {
+    if query.is_empty() {
+        return Err("Query cannot be empty".to_string());
+    }
+    let mut notebook_ids = Vec::new();
+    for notebook in &scope.notebooks {
+        let ids = notebook_operations::notebook_ids_by_name(notebook)?;
+        if ids.is_empty() {
+            return Err(format!("There is no notebook '{}'", notebook));
+        }
+        notebook_ids.extend(ids);
+    }
+
+    // Build a single matcher for both modes, escaping literal text
+    let pattern = if regex { query.to_string() } else { regex::escape(query) };
+    let matcher = Regex::new(&pattern).map_err(|e| format!("Invalid regular expression: {}", e))?;
+
+    // Get the notes in the scope
+    let tags: Vec<String> = scope.tags.iter().map(|tag| tag_operations::clean_tag(tag)).collect();
+    let mut conditions = vec!["1 = 1".to_string()];
+    let mut params: Vec<&dyn rusqlite::ToSql> = Vec::new();
+    if let Some(note_ids) = &scope.note_ids {
+        conditions.push(format!("id IN ({})", vec!["?"; note_ids.len()].join(", ")));
+        params.extend(note_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    if !tags.is_empty() {
+        conditions.push(tag_operations::tagged_condition("id", tags.len()));
+        params.extend(tags.iter().map(|tag| tag as &dyn rusqlite::ToSql));
+    }
+    if !notebook_ids.is_empty() {
+        conditions.push(notebook_operations::in_notebooks_condition("notebook_id", notebook_ids.len()));
+        params.extend(notebook_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    let notes = match &scope.note_ids {
+        Some(note_ids) if note_ids.is_empty() => Vec::new(),
+        _ => local_operations::get_local_notes_where(&conditions.join(" AND "), &params)?,
+    };
+
+    // Compute the new content of every matching note
+    let mut previews = Vec::new();
+    let mut updated_notes = Vec::new();
+    for note in notes {
+        if (note.secure && !vault::is_unlocked()) || note.locked {
+            continue;
+        }
+
+        let replacement_count = matcher.find_iter(&note.content).count();
+        if replacement_count == 0 {
+            continue;
+        }
+
+        let new_content = replace(&matcher, &note.content, replacement, regex);
+        local_operations::validate_params(Note { content: new_content.clone(), ..note.clone() })?;
+
+        previews.push(ReplacePreview {
+            note_id: note.id.unwrap_or(0),
+            title: note.title.clone(),
+            replacement_count,
+            changes: changed_lines(&matcher, &note.content, replacement, regex),
+        });
+        updated_notes.push((note, new_content));
+    }
+
+    if dry_run || updated_notes.is_empty() {
+        return Ok(previews);
+    }
+
+    // Apply every replacement in a single transaction
+    {
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let now = chrono::Utc::now().timestamp();
+        let timestamp = chrono::Utc::now().to_rfc3339();
+
+        for (note, new_content) in &updated_notes {
+            let note_id = note.id.ok_or("Note without id".to_string())?;
+            version_operations::snapshot_note(&tx, note_id, "replace")?;
+
+            let (encrypted_content, nonce_str) = crypto::encrypt_content(new_content)?;
+            tx.execute(
+                "UPDATE notes SET content = ?1, nonce = ?2, updated_at = ?3, timestamp = ?4,
+                preview = CASE WHEN secure = 1 THEN NULL ELSE ?5 END WHERE id = ?6",
+                params![encrypted_content, nonce_str, now, timestamp, preview::build_preview(new_content), note_id],
+            ).map_err(|e| e.to_string())?;
+            note_events::note_saved(&tx, note_id, new_content)?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+
+    // Send a desktop notification
+    notifier::notify("Notes updated", &format!("Replaced text in {} notes.", updated_notes.len()));
+
+    Ok(previews)
+}
+
+
+/// Replaces every match in a text, expanding capture groups only for regular expressions.
+fn replace(matcher: &Regex, text: &str, replacement: &str, regex: bool) -> String {
+    if regex {
+        matcher.replace_all(text, replacement).into_owned()
+    } else {
+        matcher.replace_all(text, NoExpand(replacement)).into_owned()
+    }
+}
+
+
+/// Builds the preview of the lines changed by a replacement.
+///
+/// Lines are compared one by one, so matches spanning several lines are applied but not previewed.
+fn changed_lines(matcher: &Regex, content: &str, replacement: &str, regex: bool) -> Vec<LineChange> {
+    content.lines().enumerate()
+        .filter(|(_, line)| matcher.is_match(line))
+        .map(|(index, line)| LineChange {
+            line: index + 1,
+            before: line.to_string(),
+            after: replace(matcher, line, replacement, regex),
+        })
+        .collect()
+}
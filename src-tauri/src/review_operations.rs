@@ -0,0 +1,276 @@
+// review_operations.rs
+
+use crate::{ local_operations::CONNECTION, models::AppError, models::DueReview, models::ReviewSchedule };
+use rusqlite::{params, Connection, OptionalExtension};
+
+
+/// The ease of a note or card that was never reviewed, as in SM-2.
+pub const INITIAL_EASE: f64 = 2.5;
+
+/// The lowest ease, so an item that was hard to recall still gets longer intervals as it is recalled.
+const MIN_EASE: f64 = 1.3;
+
+/// The highest grade of a review: a perfect recall.
+pub const MAX_GRADE: u8 = 5;
+
+/// The lowest grade of a successful review. Below it, the item is learned again from the start.
+const PASSING_GRADE: u8 = 3;
+
+/// The number of seconds in a day, the unit of the intervals.
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// The number of due reviews returned when no limit is given.
+const DEFAULT_LIMIT: usize = 50;
+
+
+/// Adds a local note to the review queue, so it comes back to be reread from time to time.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Operation
+///
+/// The note is due right away. A note already in the queue keeps its schedule.
+///
+/// # Returns
+///
+/// Returns the `ReviewSchedule` of the note.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist or if there is an issue with the database connection.
+pub fn mark_for_review(note_id: i64) -> Result<ReviewSchedule, AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let note_exists: Option<i64> = conn.query_row("SELECT id FROM notes WHERE id = ?1", params![note_id], |row| row.get(0))
+        .optional()?;
+    if note_exists.is_none() {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+
+    conn.execute(
+        "INSERT OR IGNORE INTO note_reviews (note_id, ease, interval_days, repetitions, due_at, reviewed_at) VALUES (?1, ?2, 0, 0, ?3, NULL)",
+        params![note_id, INITIAL_EASE, chrono::Utc::now().timestamp()],
+    )?;
+
+    review_schedule(&conn, note_id)?.ok_or(AppError::NotFound("Note not found".to_string()))
+}
+
+
+/// Removes a local note from the review queue, forgetting its schedule.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is no longer in the queue, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn unmark_for_review(note_id: i64) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    forget_review(&conn, note_id)
+}
+
+
+/// Retrieves the notes of the review queue that are due.
+///
+/// # Arguments
+///
+/// * `limit` - The maximum number of notes, 50 by default.
+///
+/// # Returns
+///
+/// Returns the due notes, the longest overdue first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_due_reviews(limit: Option<usize>) -> Result<Vec<DueReview>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT notes.id, notes.title, notes.icon, note_reviews.due_at, note_reviews.interval_days, note_reviews.repetitions
+        FROM note_reviews JOIN notes ON notes.id = note_reviews.note_id AND notes.deleted_at IS NULL
+        WHERE note_reviews.due_at <= ?1 ORDER BY note_reviews.due_at, notes.id LIMIT ?2"
+    ).map_err(|e| e.to_string())?;
+    let review_iter = stmt.query_map(params![chrono::Utc::now().timestamp(), limit.unwrap_or(DEFAULT_LIMIT) as i64], |row| {
+        Ok(DueReview {
+            note_id: row.get(0)?,
+            title: row.get(1)?,
+            icon: row.get(2)?,
+            due_at: row.get(3)?,
+            interval_days: row.get(4)?,
+            repetitions: row.get(5)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    review_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+
+/// Records how well a note of the review queue was remembered, and schedules its next review.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `grade` - How well it was remembered, from 0 (not at all) to 5 (perfectly), as in SM-2.
+///
+/// # Operation
+///
+/// The next review is scheduled with `schedule`: the better the note is remembered, the longer until it comes back.
+/// A note can be reviewed before it is due.
+///
+/// # Returns
+///
+/// Returns the new `ReviewSchedule` of the note.
+///
+/// # Errors
+///
+/// This function will return an error if the grade is above 5, if the note is not in the review queue, or if there
+/// is an issue with the database connection.
+pub fn record_review(note_id: i64, grade: u8) -> Result<ReviewSchedule, String> {
+    check_grade(grade)?;
+
+    let conn = CONNECTION.lock().unwrap();
+    let current = review_schedule(&conn, note_id)?.ok_or("Note is not in the review queue".to_string())?;
+    let (ease, interval_days, repetitions) = schedule(current.ease, current.interval_days, current.repetitions, grade);
+
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE note_reviews SET ease = ?1, interval_days = ?2, repetitions = ?3, due_at = ?4, reviewed_at = ?5 WHERE note_id = ?6",
+        params![ease, interval_days, repetitions, now + interval_days * DAY_SECONDS, now, note_id],
+    ).map_err(|e| e.to_string())?;
+
+    review_schedule(&conn, note_id)?.ok_or("Note is not in the review queue".to_string())
+}
+
+
+/// Computes the next schedule of a reviewed item with the SM-2 algorithm.
+///
+/// # Arguments
+///
+/// * `ease` - The ease of the item, which grows when it is easy to recall and shrinks otherwise, at least 1.3.
+/// * `interval_days` - The number of days between the last two reviews.
+/// * `repetitions` - The number of successful reviews in a row.
+/// * `grade` - How well it was recalled, from 0 to 5.
+///
+/// # Operation
+///
+/// * A grade of 3 or more is a success: the item comes back after 1 day, then 6 days, then the previous interval
+///   times the ease.
+/// * A lower grade starts the item over: it comes back the next day.
+///
+/// # Returns
+///
+/// Returns the new ease, interval in days, and number of successful reviews in a row.
+pub fn schedule(ease: f64, interval_days: i64, repetitions: i64, grade: u8) -> (f64, i64, i64) {
+    let grade = grade.min(MAX_GRADE);
+    let missed = (MAX_GRADE - grade) as f64;
+    let ease = (ease + 0.1 - missed * (0.08 + missed * 0.02)).max(MIN_EASE);
+
+    if grade < PASSING_GRADE {
+        return (ease, 1, 0);
+    }
+    let interval_days = match repetitions {
+        0 => 1,
+        1 => 6,
+        _ => ((interval_days as f64) * ease).round() as i64,
+    };
+    (ease, interval_days, repetitions + 1)
+}
+
+
+/// Checks that a grade is between 0 and 5.
+///
+/// # Errors
+///
+/// This function will return an error if the grade is above 5.
+pub fn check_grade(grade: u8) -> Result<(), String> {
+    if grade > MAX_GRADE {
+        return Err(format!("The grade should be between 0 and {}", MAX_GRADE));
+    }
+    Ok(())
+}
+
+
+/// Removes a note from the review queue, e.g. after it was deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_review(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_reviews WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Reads the review schedule of a note, if it is in the review queue.
+fn review_schedule(conn: &Connection, note_id: i64) -> Result<Option<ReviewSchedule>, String> {
+    conn.query_row(
+        "SELECT note_id, ease, interval_days, repetitions, due_at, reviewed_at FROM note_reviews WHERE note_id = ?1",
+        params![note_id],
+        |row| Ok(ReviewSchedule {
+            note_id: row.get(0)?,
+            ease: row.get(1)?,
+            interval_days: row.get(2)?,
+            repetitions: row.get(3)?,
+            due_at: row.get(4)?,
+            reviewed_at: row.get(5)?,
+        }),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_spaces_the_first_two_repetitions() {
+        let (ease, interval_days, repetitions) = schedule(INITIAL_EASE, 0, 0, 4);
+        assert!((ease - INITIAL_EASE).abs() < 1e-9);
+        assert_eq!((interval_days, repetitions), (1, 1));
+
+        let (_, interval_days, repetitions) = schedule(ease, interval_days, repetitions, 4);
+        assert_eq!((interval_days, repetitions), (6, 2));
+    }
+
+    #[test]
+    fn schedule_multiplies_the_interval_by_the_ease() {
+        let (ease, interval_days, repetitions) = schedule(INITIAL_EASE, 6, 2, 5);
+        assert!((ease - 2.6).abs() < 1e-9);
+        assert_eq!((interval_days, repetitions), (16, 3));
+    }
+
+    #[test]
+    fn schedule_starts_over_after_a_lapse() {
+        let (ease, interval_days, repetitions) = schedule(INITIAL_EASE, 40, 5, 2);
+        assert!((ease - 2.18).abs() < 1e-9);
+        assert_eq!((interval_days, repetitions), (1, 0));
+    }
+
+    #[test]
+    fn schedule_keeps_the_ease_above_its_floor() {
+        let (ease, _, _) = schedule(MIN_EASE, 1, 0, 0);
+        assert!((ease - MIN_EASE).abs() < 1e-9);
+
+        let (ease, interval_days, _) = schedule(MIN_EASE, 10, 3, 3);
+        assert!(ease >= MIN_EASE);
+        assert_eq!(interval_days, 13);
+    }
+
+    #[test]
+    fn schedule_caps_the_grade() {
+        assert_eq!(schedule(INITIAL_EASE, 6, 2, 9), schedule(INITIAL_EASE, 6, 2, MAX_GRADE));
+        assert!(check_grade(MAX_GRADE).is_ok());
+        assert!(check_grade(MAX_GRADE + 1).is_err());
+    }
+}
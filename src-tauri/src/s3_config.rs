@@ -0,0 +1,122 @@
+// s3_config.rs
+
+use crate::{ s3_operations, settings, models::S3Config };
+use aws_sdk_s3 as s3;
+use reqwest::Url;
+
+
+/// The settings key under which the S3 configuration is stored, as JSON.
+const CONFIG_SETTING: &str = "s3_config";
+
+/// The region signed with when a custom endpoint is given without one, which MinIO and most S3-compatible stores accept.
+const DEFAULT_CUSTOM_REGION: &str = "us-east-1";
+
+
+/// Retrieves the S3 configuration.
+///
+/// # Returns
+///
+/// Returns the `S3Config`, without an endpoint if the buckets are on AWS.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_s3_config() -> Result<S3Config, String> {
+    match settings::get_setting(CONFIG_SETTING)? {
+        Some(config) => serde_json::from_str(&config).map_err(|e| format!("Invalid S3 settings: {}", e)),
+        None => Ok(S3Config::default()),
+    }
+}
+
+
+/// Configures the object store the buckets are on, e.g. a self-hosted MinIO, Backblaze B2, or Cloudflare R2 rather than AWS.
+///
+/// # Arguments
+///
+/// * `config` - The address of the S3-compatible endpoint, e.g. "https://minio.example.com:9000" or
+///   "https://<account>.r2.cloudflarestorage.com", or `None` for AWS, the region the requests are signed for, e.g.
+///   "auto" for R2 or "us-west-004" for B2, and whether the buckets are addressed in the path of the requests rather
+///   than in their host, which most self-hosted stores need.
+///
+/// # Operation
+///
+/// The configuration applies to the S3 requests sent from then on, since the shared S3 client is built again. The
+/// credentials are still those of the default credentials provider chain, e.g. the AWS profile files or the
+/// environment, or of the SSO session. A remote with a self-signed certificate can be trusted with `tls::set_tls_remote`.
+///
+/// # Returns
+///
+/// Returns the stored `S3Config`.
+///
+/// # Errors
+///
+/// This function will return an error if the endpoint is not an HTTP or HTTPS address, or if there is an issue with
+/// the database connection.
+pub fn set_s3_config(config: S3Config) -> Result<S3Config, String> {
+    let endpoint_url = config.endpoint_url.as_deref()
+        .map(str::trim)
+        .filter(|endpoint_url| !endpoint_url.is_empty())
+        .map(parse_endpoint)
+        .transpose()?;
+    let region = config.region.map(|region| region.trim().to_string()).filter(|region| !region.is_empty());
+    let config = S3Config { endpoint_url, region, ..config };
+
+    let json = serde_json::to_string(&config).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)?;
+    s3_operations::invalidate_s3_client();
+    Ok(config)
+}
+
+
+/// Applies the S3 configuration to the configuration of an S3 client: its endpoint, region, and addressing style.
+///
+/// # Arguments
+///
+/// * `builder` - The configuration of the S3 client.
+///
+/// # Returns
+///
+/// Returns the configuration, unchanged if the buckets are on AWS in the default region, or if the S3
+/// configuration cannot be read.
+pub fn apply_to_s3_config(builder: s3::config::Builder) -> s3::config::Builder {
+    let config = match get_s3_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to apply the S3 configuration: {}", e);
+            return builder;
+        },
+    };
+    let region = match (&config.region, &config.endpoint_url) {
+        (Some(region), _) => Some(region.clone()),
+        (None, Some(_)) => Some(DEFAULT_CUSTOM_REGION.to_string()),
+        (None, None) => None,
+    };
+
+    let mut builder = builder;
+    if let Some(region) = region {
+        builder = builder.region(s3::config::Region::new(region));
+    }
+    if let Some(endpoint_url) = config.endpoint_url {
+        builder = builder.endpoint_url(endpoint_url).force_path_style(config.force_path_style);
+    }
+    builder
+}
+
+
+/// Tells whether the buckets are on an S3-compatible store rather than AWS.
+pub fn is_custom_endpoint() -> bool {
+    get_s3_config().is_ok_and(|config| config.endpoint_url.is_some())
+}
+
+
+/// Parses the address of an S3-compatible endpoint, without a trailing slash.
+fn parse_endpoint(endpoint_url: &str) -> Result<String, String> {
+    let url = Url::parse(endpoint_url).map_err(|e| format!("Invalid endpoint {}: {}", endpoint_url, e))?;
+    if !["http", "https"].contains(&url.scheme()) || url.host_str().is_none() {
+        return Err(format!("Invalid endpoint {}: it should be an HTTP or HTTPS address", endpoint_url));
+    }
+    if url.path() != "/" || url.query().is_some() {
+        return Err(format!("Invalid endpoint {}: it should not hold a path, e.g. a bucket name", endpoint_url));
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
@@ -3,12 +3,107 @@
 use aws_sdk_s3 as s3;
 use rusqlite::Result;
 use s3::types::{ BucketLocationConstraint, CreateBucketConfiguration, Tag, Tagging };
-use crate::{ local_operations, models::Note, models::BucketError };
+use crate::{ bucket_mirror, crypto, delta, fault_injection, journal, key_management, local_operations, memory_budget, note_stats, notebook_operations, notifier, plain_sync, presence_operations, proxy, s3_config, search_index, sso_operations, usage_operations, models::Note, models::AppError, models::BucketError, models::BucketKeyMigration, models::JournaledOperation };
 use std::collections::HashMap;
-use notify_rust::Notification;
-use ring::aead::{Aad, Nonce, LessSafeKey, UnboundKey, CHACHA20_POLY1305};
-use ring::rand::{SecureRandom, SystemRandom};
 use base64::{Engine as _, engine::general_purpose};
+use tokio::sync::OnceCell;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+
+/// The AWS configuration of the default credentials provider chain, loaded once.
+static DEFAULT_AWS_CONFIG: OnceCell<aws_config::SdkConfig> = OnceCell::const_new();
+
+lazy_static! {
+    /// The S3 client shared by the S3 operations, with the SSO credentials it was built with, if any.
+    static ref S3_CLIENT: Mutex<Option<(s3::Client, Option<String>)>> = Mutex::new(None);
+}
+
+
+/// The prefix of the keys of the note objects (see `note_key`).
+pub const NOTES_PREFIX: &str = "notes/";
+
+
+/// A note object of a bucket: its key, last modified timestamp, metadata, and decrypted content.
+pub type BucketNote = (String, Option<String>, Option<HashMap<String, String>>, String);
+
+
+/// Returns the S3 client shared by every S3 operation, built on first use.
+///
+/// # Operation
+///
+/// * The region for the S3 service is set to "eu-west-3".
+/// * The configuration of the default credentials provider chain is loaded on first use only (see `preload_aws_config`).
+/// * If the user is signed in with AWS SSO, the role credentials of the SSO session are used instead. While the
+///   session cannot provide them, e.g. once it expired, the requests fail with the reason rather than falling back
+///   to the default credentials provider chain.
+/// * If an S3-compatible endpoint is configured, e.g. MinIO, the requests are sent to it instead of AWS, signed for
+///   its region (see `s3_config`).
+/// * If a proxy is enabled, the requests go through it, trusting the certificates configured for the self-hosted
+///   remotes (see `proxy` and `tls`).
+/// * Each request is recorded in the usage counters of the month, which the cloud budget is checked against (see
+///   `usage_operations::record_s3_requests`).
+/// * In debug builds, the faults of the "s3" layer listed in the CUSTOMNOTES_FAULTS environment variable are injected
+///   into the HTTP client (see `fault_injection`).
+///
+/// The client is built again when the SSO credentials change, e.g. when they are renewed or the user signs in or
+/// out, and after `invalidate_s3_client`, e.g. when the proxy or the endpoint changes.
+///
+/// # Returns
+///
+/// Returns the shared `s3::Client`, which is cheap to clone.
+pub async fn s3_client() -> s3::Client {
+    let credentials = sso_operations::sso_credentials().await;
+    let credentials_id = match &credentials {
+        Ok(credentials) => credentials.as_ref().map(|credentials| format!("{}:{:?}", credentials.access_key_id(), credentials.expiry())),
+        Err(e) => Some(e.clone()),
+    };
+    if let Some((client, client_credentials_id)) = S3_CLIENT.lock().unwrap().as_ref() {
+        if *client_credentials_id == credentials_id {
+            return client.clone();
+        }
+    }
+
+    let config = preload_aws_config().await;
+    let config = match credentials {
+        Ok(Some(credentials)) => config.to_builder()
+            .credentials_provider(s3::config::SharedCredentialsProvider::new(credentials))
+            .build(),
+        Ok(None) => config.clone(),
+        Err(e) => config.to_builder()
+            .credentials_provider(s3::config::SharedCredentialsProvider::new(sso_operations::UnavailableCredentials(e)))
+            .build(),
+    };
+    let config = fault_injection::inject_s3_faults(usage_operations::record_s3_requests(proxy::apply_to_aws_config(config)));
+    let client = s3::Client::from_conf(s3_config::apply_to_s3_config(s3::config::Builder::from(&config)).build());
+    *S3_CLIENT.lock().unwrap() = Some((client.clone(), credentials_id));
+    client
+}
+
+
+/// Drops the shared S3 client, so the next S3 operation builds it again with the current settings.
+///
+/// It is called when a setting the client is built with changes, e.g. the proxy or the trusted certificates.
+pub fn invalidate_s3_client() {
+    S3_CLIENT.lock().unwrap().take();
+}
+
+
+/// Loads the AWS configuration of the default credentials provider chain, unless it is already loaded.
+///
+/// It reads the AWS profile files, so it is called in the background at startup rather than on the first S3 operation.
+///
+/// # Returns
+///
+/// Returns the loaded `SdkConfig`.
+pub async fn preload_aws_config() -> &'static aws_config::SdkConfig {
+    DEFAULT_AWS_CONFIG.get_or_init(|| async {
+        aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(aws_config::Region::new("eu-west-3"))
+            .load()
+            .await
+    }).await
+}
 
 
 /// Creates a new Amazon S3 bucket.
@@ -22,6 +117,7 @@ use base64::{Engine as _, engine::general_purpose};
 /// * A connection to the Amazon S3 service is established using the AWS SDK for Rust.
 /// * The region for the S3 service is set to "eu-west-3".
 /// * A new S3 bucket with the specified `bucket_name` is created in the "eu-west-3" region.
+/// * On an S3-compatible endpoint, the bucket is created in the region of the store (see `s3_config`).
 /// * If the bucket already exists, an error of type `BucketError::BucketAlreadyExists` is returned.
 /// * After creating the bucket, a tag with key "App" and value "RustCustomNotes" is added to the bucket.
 ///
@@ -42,28 +138,28 @@ pub async fn create_bucket(bucket_name: &str) -> Result<(), BucketError> {
         return Err(BucketError::BucketAlreadyExists);
     }
 
-    // Create a new S3 client with the specified region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-    let s3_client = s3::Client::new(&myconfig);
+    let s3_client = s3_client().await;
 
     // Get the region string from the client's configuration
     let region_string = s3_client.config().region().unwrap().as_ref().to_string();
 
-    // Parse the region string into a BucketLocationConstraint
-    let constraint = BucketLocationConstraint::try_parse(&region_string)
-        .unwrap_or_else(|_| panic!("Invalid region: {}", region_string));
-
-    // Build the bucket configuration with the location constraint
-    let bucket_config = CreateBucketConfiguration::builder()
-        .location_constraint(constraint)
-        .build();
+    // Build the bucket configuration with the location constraint. The S3-compatible stores create the buckets in
+    // their own region, which is often not one of AWS, e.g. "auto" for R2
+    let bucket_config = match s3_config::is_custom_endpoint() {
+        true => None,
+        false => {
+            // Parse the region string into a BucketLocationConstraint
+            let constraint = BucketLocationConstraint::try_parse(&region_string)
+                .unwrap_or_else(|_| panic!("Invalid region: {}", region_string));
+            Some(CreateBucketConfiguration::builder()
+                .location_constraint(constraint)
+                .build())
+        },
+    };
 
     // Send the create bucket request
     let create_bucket_result = s3_client.create_bucket()
-        .create_bucket_configuration(bucket_config)
+        .set_create_bucket_configuration(bucket_config)
         .bucket(bucket_name)
         .send()
         .await;
@@ -101,10 +197,7 @@ pub async fn create_bucket(bucket_name: &str) -> Result<(), BucketError> {
     }
 
     // Send a desktop notification
-    Notification::new()
-    .summary("New bucket created")
-    .body(&format!("Bucket with name '{}' was created.", bucket_name))
-    .show().unwrap();
+    notifier::notify("New bucket created", &format!("Bucket with name '{}' was created.", bucket_name));
 
     Ok(())
 }
@@ -129,12 +222,7 @@ pub async fn create_bucket(bucket_name: &str) -> Result<(), BucketError> {
 ///
 /// This function will return an error if the AWS SDK encounters an error when fetching the list of buckets or retrieving the tags.
 pub async fn fetch_buckets() -> Result<Vec<String>, s3::Error> {
-    // Establish a connection to the Amazon S3 service
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-    let s3_client = s3::Client::new(&myconfig);
+    let s3_client = s3_client().await;
 
     let mut buckets_with_tag = Vec::new();
 
@@ -185,14 +273,7 @@ pub async fn fetch_buckets() -> Result<Vec<String>, s3::Error> {
 ///
 /// This function will return an error if the AWS SDK encounters an error when checking the bucket existence.
 pub async fn bucket_exists(bucket_name: &str) -> Result<bool, s3::Error> {
-    // Create AWS configuration with the desired region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-
-    // Create an S3 client using the AWS configuration
-    let s3_client = s3::Client::new(&myconfig);
+    let s3_client = s3_client().await;
 
     // Send a HEAD request to check if the bucket exists
     match s3_client.head_bucket().bucket(bucket_name).send().await {
@@ -226,28 +307,237 @@ pub async fn delete_bucket(bucket_name: &str) -> Result<(), s3::Error> {
     // Trim any surrounding quotes from the bucket name
     let bucket_name = bucket_name.trim_matches('"');
 
-    // Configure the AWS SDK with the desired region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-
-    // Create a new S3 client
-    let s3_client = s3::Client::new(&myconfig);
+    let s3_client = s3_client().await;
 
     // Send a request to delete the specified bucket
     s3_client.delete_bucket().bucket(bucket_name).send().await?;
 
+    // Forget the mirrored notes of the bucket
+    if let Err(e) = bucket_mirror::clear_bucket_mirror(bucket_name) {
+        eprintln!("Failed to clear bucket mirror: {}", e);
+    }
+    search_index::drop_bucket_index(bucket_name);
+
     // Send a desktop notification
-    Notification::new()
-    .summary("Bucket deleted")
-    .body(&format!("Bucket with name '{}' was deleted.", bucket_name))
-    .show().unwrap();
+    notifier::notify("Bucket deleted", &format!("Bucket with name '{}' was deleted.", bucket_name));
 
     Ok(())
 }
 
 
+/// Encodes the icon of a note for the object metadata, which only allows ASCII characters.
+///
+/// # Arguments
+///
+/// * `icon` - The icon of the note, e.g. an emoji.
+///
+/// # Returns
+///
+/// Returns the icon encoded in base64.
+pub fn icon_metadata(icon: &str) -> String {
+    general_purpose::STANDARD.encode(icon)
+}
+
+
+/// Decodes the icon of a note from the object metadata, as encoded by `icon_metadata`.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns the icon, or `None` if the note has no icon or the icon cannot be decoded.
+pub fn icon_from_metadata(metadata: &HashMap<String, String>) -> Option<String> {
+    let icon = general_purpose::STANDARD.decode(metadata.get("icon")?).ok()?;
+    String::from_utf8(icon).ok()
+}
+
+
+/// Tells whether a note was uploaded while it was locked, from the object metadata.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns `true` if the "locked" metadata is "true". The notes uploaded before notes could be locked are not locked.
+pub fn is_locked(metadata: &HashMap<String, String>) -> bool {
+    metadata.get("locked").is_some_and(|locked| locked == "true")
+}
+
+
+/// Reads a flag of the note of an object, e.g. "pinned" or "archived".
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+/// * `field` - The name of the metadata field of the flag.
+///
+/// # Returns
+///
+/// Returns `true` if the metadata field is "true". The notes uploaded before the flag existed do not have it.
+pub fn flag_from_metadata(metadata: &HashMap<String, String>, field: &str) -> bool {
+    metadata.get(field).is_some_and(|value| value == "true")
+}
+
+
+/// Encodes the aliases of a note for the object metadata, which only allows ASCII characters.
+///
+/// # Arguments
+///
+/// * `aliases` - The aliases of the note.
+///
+/// # Returns
+///
+/// Returns the aliases as a JSON array encoded in base64.
+pub fn aliases_metadata(aliases: &[String]) -> String {
+    list_metadata(aliases)
+}
+
+
+/// Decodes the aliases of a note from the object metadata, as encoded by `aliases_metadata`.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns the aliases, or none if the note has no aliases or they cannot be decoded.
+pub fn aliases_from_metadata(metadata: &HashMap<String, String>) -> Vec<String> {
+    list_from_metadata(metadata, "aliases")
+}
+
+
+/// Encodes the tags of a note for the object metadata, like its aliases (see `aliases_metadata`).
+///
+/// # Arguments
+///
+/// * `tags` - The tags of the note.
+///
+/// # Returns
+///
+/// Returns the tags as a JSON array encoded in base64.
+pub fn tags_metadata(tags: &[String]) -> String {
+    list_metadata(tags)
+}
+
+
+/// Decodes the tags of a note from the object metadata, as encoded by `tags_metadata`.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns the tags, or none if the note has no tags or they cannot be decoded.
+pub fn tags_from_metadata(metadata: &HashMap<String, String>) -> Vec<String> {
+    list_from_metadata(metadata, "tags")
+}
+
+
+/// Encodes a list of strings for the object metadata as a JSON array in base64.
+fn list_metadata(values: &[String]) -> String {
+    general_purpose::STANDARD.encode(serde_json::to_string(values).unwrap_or_default())
+}
+
+
+/// Decodes a list of strings from the object metadata, as encoded by `list_metadata`.
+fn list_from_metadata(metadata: &HashMap<String, String>, key: &str) -> Vec<String> {
+    metadata.get(key)
+        .and_then(|values| general_purpose::STANDARD.decode(values).ok())
+        .and_then(|values| serde_json::from_slice(&values).ok())
+        .unwrap_or_default()
+}
+
+
+/// Reads the version of the key the content of an object is encrypted with from its metadata.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns the version of the key, or 0 for the objects uploaded before the key was managed, which have no
+/// "key_version" field (see `key_management`).
+pub fn key_version_from_metadata(metadata: &HashMap<String, String>) -> i64 {
+    metadata.get("key_version").and_then(|version| version.parse().ok()).unwrap_or(0)
+}
+
+
+/// Returns the key of the object of a note, named after its UUID so it is read, written, and deleted without
+/// listing the bucket, under the path of its notebook so the bucket keeps the structure of the notebooks.
+///
+/// # Arguments
+///
+/// * `uuid` - The UUID of the note.
+/// * `notebook` - The path of the notebook of the note, e.g. "Work/Projects" (see `notebook_operations::notebook_path`),
+///   or `None` for a note outside of any notebook.
+///
+/// # Returns
+///
+/// Returns the key, e.g. "notes/6f1c0e0a-5a8e-4f0e-9d0b-2f8a3c1d9e7b.txt", or
+/// "notes/Work/Projects/6f1c0e0a-5a8e-4f0e-9d0b-2f8a3c1d9e7b.txt" in a notebook. The notes uploaded by the previous
+/// versions were named after their title, e.g. "Groceries.txt", until they are migrated (see `migrate_bucket_keys`).
+pub fn note_key(uuid: &str, notebook: Option<&str>) -> String {
+    match notebook {
+        Some(notebook) => format!("{}{}/{}.txt", NOTES_PREFIX, notebook, uuid),
+        None => format!("{}{}.txt", NOTES_PREFIX, uuid),
+    }
+}
+
+
+/// Returns the path of the notebook of a note from the key of its object (see `note_key`), or `None` for a note
+/// outside of any notebook or named after its title by a previous version.
+pub fn notebook_from_key(key: &str) -> Option<String> {
+    let (notebook, _uuid) = key.strip_prefix(NOTES_PREFIX)?.rsplit_once('/')?;
+    Some(notebook.to_string())
+}
+
+
+/// Tells whether a key is the key of the object of a note, in any notebook (see `note_key`).
+fn is_note_key(key: &str, uuid: &str) -> bool {
+    key == note_key(uuid, notebook_from_key(key).as_deref())
+}
+
+
+/// Encodes the title of a note for the object metadata, like its icon (see `icon_metadata`).
+///
+/// # Arguments
+///
+/// * `title` - The title of the note.
+///
+/// # Returns
+///
+/// Returns the title encoded in base64.
+pub fn title_metadata(title: &str) -> String {
+    general_purpose::STANDARD.encode(title)
+}
+
+
+/// Decodes the title of a note from the object metadata, as encoded by `title_metadata`.
+///
+/// # Arguments
+///
+/// * `key` - The key of the object.
+/// * `metadata` - The metadata of the object.
+///
+/// # Returns
+///
+/// Returns the title, or the key without its ".txt" extension for the objects named after their title, which have
+/// no "title" field.
+pub fn title_from_metadata(key: &str, metadata: &HashMap<String, String>) -> String {
+    metadata.get("title")
+        .and_then(|title| general_purpose::STANDARD.decode(title).ok())
+        .and_then(|title| String::from_utf8(title).ok())
+        .unwrap_or_else(|| key.strip_suffix(".txt").unwrap_or(key).to_string())
+}
+
+
 /// Uploads a note to an Amazon S3 bucket.
 ///
 /// # Parameters
@@ -261,19 +551,25 @@ pub async fn delete_bucket(bucket_name: &str) -> Result<(), s3::Error> {
 /// * The region for the S3 service is set to "eu-west-3".
 /// * The content of the note is encrypted using a randomly generated key and nonce.
 /// * The encrypted content is converted to bytes and then to a ByteStream.
-/// * The title of the note is used as the base name of the file, with ".txt" appended to it.
+/// * The object is named after the UUID of the note, under the path of its notebook (see `note_key`), and its title
+///   is stored in its metadata. The object of the note in another notebook, e.g. before it was moved, or under its
+///   title from a previous version, is deleted once the note is uploaded (see `find_note_object`).
 /// * The file is uploaded to the specified S3 bucket.
 /// * The content type of the file is set to "text/plain".
+/// * The status, the icon, the cover, the aliases, the tags, and the locked flag of the local note are stored as object metadata.
+/// * The upload is recorded in the operation journal until it returns, so it is run again if the application crashes (see `journal`).
 ///
 /// # Returns
 ///
 /// * If the operation is successful, a `Result` containing a `String` with the message "Object uploaded successfully" is returned.
-/// * If the operation fails, a `Result` containing an `Err` with a `String` describing the error is returned.
+/// * If the operation fails, a `Result` containing an `Err` with an `AppError` describing the error is returned, of
+///   the kind of the failure of the upload (see `AppError::s3`).
 ///
 /// # Errors
 ///
-/// This function will return an error if the AWS SDK encounters an error when uploading the file to the S3 bucket.
-pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<String, String> {
+/// This function will return an error if the title or the content of the note is too long, if the local note does
+/// not exist, or if the AWS SDK encounters an error when uploading the file to the S3 bucket.
+pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<String, AppError> {
     // Validate the parameters of the note
     match local_operations::validate_params(note.clone()) {
         Ok(_) => {
@@ -281,10 +577,16 @@ pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<Stri
         },
         Err(e) => {
             // Parameters are invalid, return the error
-            return Err(e);
+            return Err(AppError::Validation(e));
         }
     }
 
+    // Record the upload in the journal until it returns
+    let _journal_entry = journal::record(&JournaledOperation::UploadNote {
+        bucket_name: bucket_name.to_string(),
+        note: note.clone(),
+    })?;
+
     // // Decrypt the note content before uploading
     // // If used note parameter for function upload_note_to_bucket needs to be mutable
     // let decrypted_content = match note.id {
@@ -306,78 +608,59 @@ pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<Stri
 
     
 
-    // Configure the AWS SDK with the desired region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-    let s3_client = s3::Client::new(&myconfig);
-
-    // Convert the content of the note to bytes and create a ByteStream
-    let input_string = note.content.as_bytes().to_vec();
-
-    // Generate a random nonce
-    let rng = SystemRandom::new();
-    let mut nonce = [0u8; 12];
-    rng.fill(&mut nonce).unwrap();
-    let nonce = Nonce::assume_unique_for_key(nonce);
+    let s3_client = s3_client().await;
 
-    // Convert the nonce to a byte slice and then encode it
-    let nonce_str = general_purpose::STANDARD.encode(nonce.as_ref());
-
-    // Generate a random key
-    let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-    let crypt_key = LessSafeKey::new(crypt_key);
-
-    // Encrypt the content and create a ByteStream
-    let mut in_out = input_string.clone();
-    crypt_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out).unwrap();
-
-    let bytestream = s3::primitives::ByteStream::from(in_out);
-
-    // Generate the filename for the note by appending ".txt" to the title
-    let filename = format!("{}.txt", note.title);
-
-    // Get the UUID of the note from the local storage
+    // Get the UUID, the secure and locked flags, the status, the aliases, and the tags of the note from the local storage
     let note_result = local_operations::get_local_note(note.id.unwrap()).await;
-    let uuid = match note_result {
-        Ok(note) => note.uuid.unwrap(),
-        Err(e) => return Err(format!("Failed to get local note: {}", e)),
+    let object_note = match note_result {
+        Ok(local_note) => Note {
+            title: note.title.clone(),
+            content: note.content,
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+            ..local_note
+        },
+        Err(e) => return Err(AppError::from(e)),
     };
 
-    // Get the current timestamp
-    let timestamp = chrono::Utc::now().to_rfc3339();
-
-    // Get the created_at and updated_at timestamps as strings
-    let created_at = note.created_at.to_string();
-    let updated_at = note.updated_at.unwrap_or(0).to_string();
-
-    // Upload the note to the S3 bucket with the specified metadata
-    let put_object = s3_client.put_object()
-        .bucket(bucket_name)
-        .key(&filename)
-        .metadata("uuid", &uuid)
-        .metadata("timestamp", &timestamp)
-        .metadata("created_at", &created_at)
-        .metadata("updated_at", &updated_at)
-        .metadata("nonce", &nonce_str)
-        .body(bytestream)
-        .content_type("text/plain")
-        .send().await;
+    // Name the object after the UUID of the note, under the path of its notebook
+    let uuid = object_note.uuid.clone().ok_or("The note has no UUID".to_string())?;
+    let notebook = notebook_operations::notebook_path(object_note.notebook_id)?;
+    let filename = note_key(&uuid, notebook.as_deref());
+    let previous = find_note_object(&s3_client, bucket_name, &uuid, notebook.as_deref()).await
+        .map_err(|e| AppError::s3(e.as_ref()))?;
+
+    // Upload the note to the S3 bucket with its metadata
+    let mut put_object = put_note_object(&s3_client, bucket_name, &filename, &object_note).await
+        .map_err(|e| AppError::s3_with(e.as_ref(), "Object upload failed"));
+
+    // Remove the object of the note in its previous notebook, or under its title from a previous version
+    if let (Ok(_), Some((previous_key, _))) = (&put_object, previous.filter(|(key, _)| *key != filename)) {
+        put_object = s3_client.delete_object()
+            .bucket(bucket_name)
+            .key(&previous_key)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| AppError::s3_with(&e, "Object upload failed"));
+    }
 
     // Check if the upload was successful or return an error
     match put_object {
         Ok(_) => {
             // Send a desktop notification
-            Notification::new()
-            .summary("Note uploaded")
-            .body(&format!("Note with title {} was uploaded to bucket {}.", note.title, bucket_name))
-            .show().unwrap();
+            notifier::notify_grouped(
+                &format!("upload:{}", bucket_name),
+                "Note uploaded",
+                &format!("Note with title {} was uploaded to bucket {}.", note.title, bucket_name),
+                "Notes uploaded",
+                &format!("{{count}} notes were uploaded to bucket {}.", bucket_name),
+            );
 
             Ok("Object uploaded successfully".to_string())
         },
         Err(e) => {
-            Err(format!("Object upload failed: {:?}", e))
+            Err(e)
         },
     }
 }
@@ -390,6 +673,12 @@ pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<Stri
 /// * `bucket` - The name of the bucket to fetch the note from.
 /// * `uuid` - The UUID of the note to fetch.
 ///
+/// # Operation
+///
+/// The object of the note is read from its key, under the notebook of its local copy (see `note_key`). The bucket is
+/// only listed if there is no such object, for a note moved to another notebook since, or uploaded under its title by
+/// a previous version (see `find_note_object`).
+///
 /// # Returns
 ///
 /// Returns a `Result` containing a `Note` if the note is found in the bucket.
@@ -399,96 +688,40 @@ pub async fn upload_note_to_bucket(bucket_name: &str, note: Note) -> Result<Stri
 ///
 /// This function will return an error if the AWS SDK encounters an error when fetching the note or if the note is not found.
 pub async fn fetch_bucket_note(bucket: &str, uuid: &str) -> Result<Note, Box<dyn std::error::Error>> {
-    // Create AWS configuration with the specified region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-
-    // Create an S3 client using the AWS configuration
-    let client = s3::Client::new(&myconfig);
-
-    // List objects in the bucket
-    let list_objects_output = client.list_objects_v2()
-        .bucket(bucket)
-        .send()
-        .await?;
-
-    // Iterate over the objects in the bucket
-    for object in list_objects_output.contents.unwrap_or_default() {
-        let key = object.key.unwrap_or_default();
-
-        // Retrieve the metadata of the object
-        let head_object_output = client.head_object()
-            .bucket(bucket)
-            .key(&key)
-            .send()
-            .await?;
-
-        // Check if the object has the specified UUID in its metadata
-        if let Some(metadata) = head_object_output.metadata {
-            if metadata.get("uuid").map(|s| s.as_str()) == Some(&uuid) {
-                // Fetch the object and return the note
-                let mut object = client.get_object()
-                    .bucket(bucket)
-                    .key(&key)
-                    .send()
-                    .await?;
-
-                // Read the object's body and convert it to a string
-                let mut body = Vec::new();
-                while let Some(bytes) = object.body.try_next().await? {
-                    body.extend_from_slice(&bytes);
-                }
-
-                // Retrieve the nonce from the metadata and convert it from a base64 string
-                let nonce_str = metadata.get("nonce").map(|s| s.clone()).unwrap_or_else(|| String::from(""));
-                let nonce_bytes = match general_purpose::STANDARD.decode(&nonce_str) {
-                    Ok(bytes) => bytes,
-                    Err(_) => {
-                        eprintln!("Failed to decode nonce");
-                        return Err("Failed to decode nonce".into());
-                    }
-                };
-                if nonce_bytes.len() != 12 {
-                    eprintln!("Nonce has wrong length");
-                    return Err("Nonce has wrong length".into());
-                }
-                let nonce_array: [u8; 12] = nonce_bytes.try_into().unwrap();
-                let nonce = Nonce::assume_unique_for_key(nonce_array);
-
-                // Generate a random key
-                let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-                let crypt_key = LessSafeKey::new(crypt_key);
-
-                // Decrypt the content
-                let decrypted_content = crypt_key.open_in_place(nonce, Aad::empty(), &mut body).unwrap();
-
-                // Convert the decrypted content to a string
-                let body_str = String::from_utf8(decrypted_content.to_vec())?;
-
-                // Extract the creation timestamp from the metadata
-                let created_at = metadata.get("created_at").unwrap_or(&String::from("")).clone();
-
-                // Create a Note object with the fetched data
-                let note = Note {
-                    id: Some(1),
-                    uuid: Some(uuid.to_string()),
-                    title: key,
-                    content: body_str,
-                    nonce: Some(nonce_str),
-                    created_at: created_at.parse::<i64>().unwrap_or(0),
-                    updated_at: Some(chrono::Utc::now().timestamp()),
-                    timestamp: metadata.get("timestamp").map(|s| s.to_string()),
-                };
-
-                return Ok(note);
-            }
-        }
-    }
+    let client = s3_client().await;
+
+    // Find the object of the note in the notebook of its local copy, then fetch and decrypt it
+    let notebook = notebook_operations::note_notebook_path(uuid)?;
+    let (key, _) = find_note_object(&client, bucket, uuid, notebook.as_deref()).await?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+    let (_, metadata, body_str) = fetch_bucket_object(&client, bucket, &key).await?;
+    let metadata = metadata.unwrap_or_default();
+
+    // Extract the creation timestamp from the metadata
+    let created_at = metadata.get("created_at").unwrap_or(&String::from("")).clone();
+
+    // Create a Note object with the fetched data
+    let note = Note {
+        id: Some(1),
+        uuid: Some(uuid.to_string()),
+        title: title_from_metadata(&key, &metadata),
+        content: body_str,
+        nonce: metadata.get("nonce").cloned(),
+        created_at: created_at.parse::<i64>().unwrap_or(0),
+        updated_at: Some(chrono::Utc::now().timestamp()),
+        timestamp: metadata.get("timestamp").map(|s| s.to_string()),
+        secure: metadata.get("secure").map(|s| s == "true").unwrap_or(false),
+        status: metadata.get("status").cloned(),
+        icon: icon_from_metadata(&metadata),
+        cover_attachment_id: metadata.get("cover_attachment_id").and_then(|s| s.parse::<i64>().ok()),
+        locked: is_locked(&metadata),
+        pinned: flag_from_metadata(&metadata, "pinned"),
+        archived: flag_from_metadata(&metadata, "archived"),
+        notebook_id: None,
+        aliases: aliases_from_metadata(&metadata),
+        tags: tags_from_metadata(&metadata),
+    };
 
-    // Return an error if the note is not found
-    Err("Note not found".into())
+    Ok(note)
 }
 
 
@@ -503,13 +736,15 @@ pub async fn fetch_bucket_note(bucket: &str, uuid: &str) -> Result<Note, Box<dyn
 ///
 /// * A connection to the Amazon S3 service is established using the AWS SDK for Rust.
 /// * The region for the S3 service is set to "eu-west-3".
-/// * The list of objects in the bucket is retrieved using the `list_objects_v2` API.
-/// * For each object, the `head_object` API is called to retrieve the metadata associated with the object.
-/// * If the object has a metadata field with key "uuid" and value matching the UUID of the note, the object is considered as the note to be updated.
+/// * The metadata of the object of the note is retrieved using the `head_object` API, in the notebook of its local
+///   copy (see `find_note_object`).
+/// * A note uploaded while it was locked is not updated (see `local_operations::set_note_locked`).
 /// * The content of the note is encrypted using a randomly generated key and nonce.
 /// * The encrypted content is converted to bytes and then to a `ByteStream`.
-/// * The metadata fields "uuid" and "timestamp" are updated with the UUID and current timestamp of the note.
-/// * The note is updated by uploading the new content to the object in the bucket.
+/// * The metadata of the object is updated from the note, with the current timestamp (see `put_note_object`).
+/// * The note is updated by uploading the new content to the object in the bucket, in the same notebook. A note
+///   uploaded under its title by a previous version is moved to the object named after its UUID (see `note_key`).
+/// * The update is recorded in the operation journal until it returns, so it is run again if the application crashes (see `journal`).
 ///
 /// # Returns
 ///
@@ -518,88 +753,53 @@ pub async fn fetch_bucket_note(bucket: &str, uuid: &str) -> Result<Note, Box<dyn
 ///
 /// # Errors
 ///
-/// This function will return an error if the AWS SDK encounters an error when updating the note or if the note is not found.
+/// This function will return an error if the AWS SDK encounters an error when updating the note, if the note is not found,
+/// or if it is locked.
 pub async fn update_bucket_note (bucket: &str, note: Note) -> Result<(), Box<dyn std::error::Error>> {
-    // Establish a connection to the Amazon S3 service
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-    let client = s3::Client::new(&myconfig);
+    // Record the update in the journal until it returns
+    let _journal_entry = journal::record(&JournaledOperation::UpdateBucketNote {
+        bucket_name: bucket.to_string(),
+        note: note.clone(),
+    })?;
+
+    let client = s3_client().await;
 
     // Extract the UUID from the note
-    let uuid = note.uuid.unwrap();
+    let uuid = note.uuid.clone().unwrap();
 
-    // Retrieve the list of objects in the bucket
-    let list_objects_output = client.list_objects_v2()
-        .bucket(bucket)
-        .send()
-        .await?;
+    // Retrieve the object of the note and its metadata, in the notebook of its local copy
+    let notebook = notebook_operations::note_notebook_path(&uuid)?;
+    let (key, metadata) = find_note_object(&client, bucket, &uuid, notebook.as_deref()).await?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+    if is_locked(&metadata) {
+        return Err(format!("The note '{}' is locked. Unlock it to change it.", note.title).into());
+    }
 
-    // Iterate over each object in the bucket
-    for object in list_objects_output.contents.unwrap_or_default() {
-        let key = object.key.unwrap_or_default();
+    // Update the note by uploading the new content to the object named after its UUID, in the same notebook
+    let new_key = match key.starts_with(NOTES_PREFIX) {
+        true => key.clone(),
+        false => note_key(&uuid, notebook.as_deref()),
+    };
+    put_note_object(&client, bucket, &new_key, &note).await?;
 
-        // Retrieve the metadata associated with the object
-        let head_object_output = client.head_object()
+    // Remove the object the note was uploaded under by a previous version
+    if key != new_key {
+        client.delete_object()
             .bucket(bucket)
             .key(&key)
             .send()
             .await?;
-
-        // Check if the object has a metadata field with key "uuid" and value matching the UUID of the note
-        if let Some(metadata) = head_object_output.metadata {
-            if metadata.get("uuid").map(|s| s.as_str()) == Some(&uuid) {
-                // Convert the content of the note to bytes and then to a ByteStream
-                let input_string = note.content.as_bytes().to_vec();
-
-                // Generate a random nonce
-                let rng = SystemRandom::new();
-                let mut nonce = [0u8; 12];
-                rng.fill(&mut nonce).unwrap();
-                let nonce = Nonce::assume_unique_for_key(nonce);
-
-                // Convert the nonce to a byte slice and then encode it
-                let nonce_str = general_purpose::STANDARD.encode(nonce.as_ref());
-
-                // Generate a random key
-                let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-                let crypt_key = LessSafeKey::new(crypt_key);
-
-                // Encrypt the content and create a ByteStream
-                let mut in_out = input_string.clone();
-                crypt_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out).unwrap();
-                
-                let bytestream = s3::primitives::ByteStream::from(in_out);
-
-                // Get the current timestamp
-                let timestamp = chrono::Utc::now().to_rfc3339();
-
-                // Update the note by uploading the new content to the object in the bucket
-                client.put_object()
-                    .bucket(bucket)
-                    .key(&key)
-                    .metadata("uuid", &uuid)
-                    .metadata("timestamp", &timestamp)
-                    .metadata("nonce", &nonce_str)
-                    .body(bytestream)
-                    .content_type("text/plain")
-                    .send()
-                    .await?;
-
-                // Send a desktop notification
-                Notification::new()
-                .summary("Bucket note updated")
-                .body(&format!("Note with title {} was updated.", key))
-                .show().unwrap();
-
-                return Ok(());
-            }
-        }
     }
 
-    // Return an error if the note is not found
-    Err("Note not found".into())
+    // Send a desktop notification
+    notifier::notify_grouped(
+        &format!("update:{}", bucket),
+        "Bucket note updated",
+        &format!("Note with title {} was updated.", note.title),
+        "Bucket notes updated",
+        &format!("{{count}} notes were updated in bucket {}.", bucket),
+    );
+
+    Ok(())
 }
 
 
@@ -614,10 +814,11 @@ pub async fn update_bucket_note (bucket: &str, note: Note) -> Result<(), Box<dyn
 ///
 /// * A connection to the Amazon S3 service is established using the AWS SDK for Rust.
 /// * The region for the S3 service is set to "eu-west-3".
-/// * The list of objects in the bucket is retrieved using the `list_objects_v2` API.
-/// * For each object, the `head_object` API is called to retrieve the metadata associated with the object.
-/// * If the object has a metadata field with key "uuid" and value matching the UUID of the note, the object is considered as the note to be deleted.
+/// * The metadata of the object of the note is retrieved using the `head_object` API, in the notebook of its local
+///   copy (see `find_note_object`).
+/// * A note uploaded while it was locked is not deleted (see `local_operations::set_note_locked`).
 /// * The note is deleted by calling the `delete_object` API with the key of the object.
+/// * The deletion is recorded in the operation journal until it returns, so it is run again if the application crashes (see `journal`).
 ///
 /// # Returns
 ///
@@ -626,55 +827,189 @@ pub async fn update_bucket_note (bucket: &str, note: Note) -> Result<(), Box<dyn
 ///
 /// # Errors
 ///
-/// This function will return an error if the AWS SDK encounters an error when deleting the note or if the note is not found.
+/// This function will return an error if the AWS SDK encounters an error when deleting the note, if the note is not found,
+/// or if it is locked.
 pub async fn delete_bucket_note (bucket: &str, uuid: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Establish a connection to the Amazon S3 service
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
-    let client = s3::Client::new(&myconfig);
+    // Record the deletion in the journal until it returns
+    let _journal_entry = journal::record(&JournaledOperation::DeleteBucketNote {
+        bucket_name: bucket.to_string(),
+        uuid: uuid.to_string(),
+    })?;
+
+    let client = s3_client().await;
+
+    // Retrieve the object of the note and its metadata, in the notebook of its local copy
+    let notebook = notebook_operations::note_notebook_path(uuid)?;
+    let (key, metadata) = find_note_object(&client, bucket, uuid, notebook.as_deref()).await?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+    let title = title_from_metadata(&key, &metadata);
+    if is_locked(&metadata) {
+        return Err(format!("The note '{}' is locked. Unlock it to delete it.", title).into());
+    }
 
-    // Retrieve the list of objects in the bucket
-    let list_objects_output = client.list_objects_v2()
+    // Delete the note by calling the `delete_object` API with the key of the object
+    client.delete_object()
         .bucket(bucket)
+        .key(&key)
         .send()
         .await?;
 
-    // Iterate over each object in the bucket
-    for object in list_objects_output.contents.unwrap_or_default() {
-        let key = object.key.unwrap_or_default();
+    // Send a desktop notification
+    notifier::notify_grouped(
+        &format!("delete:{}", bucket),
+        "Bucket note deleted",
+        &format!("Note with title {} was deleted.", title),
+        "Bucket notes deleted",
+        &format!("{{count}} notes were deleted from bucket {}.", bucket),
+    );
+
+    Ok(())
+}
+
+
+/// Finds the object of a note in an Amazon S3 bucket.
+///
+/// # Parameters
+///
+/// * `client` - The S3 client to use.
+/// * `bucket_name` - The name of the bucket.
+/// * `uuid` - The UUID of the note.
+/// * `notebook` - The path of the notebook the object is expected in, or `None` outside of any notebook.
+///
+/// # Operation
+///
+/// The metadata of the object named after the UUID in the notebook is retrieved with a single `head_object` request
+/// (see `note_key`). If there is no such object, the bucket is listed: the object of the note in another notebook is
+/// found by its key, and the objects named after their title by the previous versions are searched, which takes a
+/// `head_object` request per object until the bucket is migrated (see `migrate_bucket_keys`).
+///
+/// # Returns
+///
+/// Returns the key and the metadata of the object, or `None` if the note is not in the bucket.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error.
+pub async fn find_note_object(client: &s3::Client, bucket_name: &str, uuid: &str, notebook: Option<&str>) -> Result<Option<(String, HashMap<String, String>)>, Box<dyn std::error::Error>> {
+    let key = note_key(uuid, notebook);
+    let head_object = client.head_object()
+        .bucket(bucket_name)
+        .key(&key)
+        .send()
+        .await;
+    match head_object {
+        Ok(output) => return Ok(Some((key, output.metadata.unwrap_or_default()))),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {},
+        Err(e) => return Err(e.into()),
+    }
 
-        // Retrieve the metadata associated with the object
+    // Search the object of the note in the other notebooks, then the objects named after their title
+    let objects = list_bucket_objects(client, bucket_name).await?;
+    let moved = objects.iter().find(|(key, _)| is_note_key(key, uuid));
+    if let Some((key, _)) = moved {
         let head_object_output = client.head_object()
-            .bucket(bucket)
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await?;
+        return Ok(Some((key.clone(), head_object_output.metadata.unwrap_or_default())));
+    }
+    for (key, _) in objects {
+        if key.starts_with(NOTES_PREFIX) {
+            continue;
+        }
+        let head_object_output = client.head_object()
+            .bucket(bucket_name)
             .key(&key)
             .send()
             .await?;
-
-        // Check if the object has a metadata field with key "uuid" and value matching the UUID of the note
-        if let Some(metadata) = head_object_output.metadata {
-            if metadata.get("uuid").map(|s| s.as_str()) == Some(&uuid) {
-                // Delete the note by calling the `delete_object` API with the key of the object
-                client.delete_object()
-                    .bucket(bucket)
-                    .key(&key)
-                    .send()
-                    .await?;
-
-                // Send a desktop notification
-                Notification::new()
-                .summary("Bucket note deleted")
-                .body(&format!("Note with title {} was deleted.", key))
-                .show().unwrap();
-
-                return Ok(());
-            }
+        let metadata = head_object_output.metadata.unwrap_or_default();
+        if metadata.get("uuid").map(|s| s.as_str()) == Some(uuid) {
+            return Ok(Some((key, metadata)));
         }
     }
 
-    // Return an error if the note is not found
-    Err("Note not found".into())
+    Ok(None)
+}
+
+
+/// Writes a note to an object of an Amazon S3 bucket, with its metadata.
+///
+/// # Parameters
+///
+/// * `client` - The S3 client to use.
+/// * `bucket_name` - The name of the bucket.
+/// * `key` - The key of the object, named after the UUID of the note (see `note_key`).
+/// * `note` - The note, with its plaintext content and its UUID.
+///
+/// # Operation
+///
+/// * The content is encrypted with the content key, and the object is written with the content type "text/plain".
+///   A note synced in plain text is written as unencrypted Markdown instead, with the content type "text/markdown"
+///   and the "encryption" metadata field set to "none" (see `plain_sync`).
+/// * The UUID, the title, the current timestamp, the creation and update times, the nonce, the version of the key, the
+///   secure, locked, pinned, and archived flags, the status, the icon, the cover, the aliases, and the tags of the note
+///   are stored as object metadata. A note never updated has an update time of 0.
+/// * The statistics of the note are stored as object metadata too, except for a secure note (see `note_stats`).
+///
+/// # Errors
+///
+/// This function will return an error if the content cannot be encrypted, if there is an issue with the database
+/// connection, or if the AWS SDK encounters an error when writing the object.
+pub async fn put_note_object(client: &s3::Client, bucket_name: &str, key: &str, note: &Note) -> Result<(), Box<dyn std::error::Error>> {
+    let uuid = note.uuid.as_deref().unwrap_or_default();
+    let plain = plain_sync::is_plain_sync(uuid, note.secure)?;
+
+    let mut put_object = client.put_object()
+        .bucket(bucket_name)
+        .key(key)
+        .metadata("uuid", uuid)
+        .metadata("title", title_metadata(&note.title))
+        .metadata("timestamp", chrono::Utc::now().to_rfc3339())
+        .metadata("created_at", note.created_at.to_string())
+        .metadata("updated_at", note.updated_at.unwrap_or(0).to_string())
+        .metadata("secure", note.secure.to_string())
+        .metadata("locked", note.locked.to_string())
+        .metadata("pinned", note.pinned.to_string())
+        .metadata("archived", note.archived.to_string());
+    let body = if plain {
+        put_object = put_object
+            .metadata(plain_sync::ENCRYPTION_METADATA, plain_sync::PLAIN_ENCRYPTION)
+            .content_type(plain_sync::PLAIN_CONTENT_TYPE);
+        delta::to_markdown(&note.content).into_bytes()
+    } else {
+        // Encrypt the content with the content key
+        let (in_out, nonce_str) = crypto::encrypt_bytes(note.content.as_bytes())?;
+        put_object = put_object
+            .metadata("nonce", &nonce_str)
+            .metadata("key_version", key_management::KEY_VERSION.to_string())
+            .content_type("text/plain");
+        in_out
+    };
+    let bytestream = s3::primitives::ByteStream::from(body);
+    if let Some(status) = &note.status {
+        put_object = put_object.metadata("status", status);
+    }
+    if let Some(icon) = &note.icon {
+        put_object = put_object.metadata("icon", icon_metadata(icon));
+    }
+    if let Some(cover_attachment_id) = note.cover_attachment_id {
+        put_object = put_object.metadata("cover_attachment_id", cover_attachment_id.to_string());
+    }
+    if !note.aliases.is_empty() {
+        put_object = put_object.metadata("aliases", aliases_metadata(&note.aliases));
+    }
+    if !note.tags.is_empty() {
+        put_object = put_object.metadata("tags", tags_metadata(&note.tags));
+    }
+    for (field, value) in note_stats::stats_metadata(note) {
+        put_object = put_object.metadata(field, value);
+    }
+    put_object
+        .body(bytestream)
+        .send()
+        .await?;
+
+    Ok(())
 }
 
 
@@ -695,19 +1030,43 @@ pub async fn delete_bucket_note (bucket: &str, uuid: &str) -> Result<(), Box<dyn
 /// # Errors
 ///
 /// This function will return an error if the AWS SDK encounters an error when fetching the notes or if there is an error in the response.
-pub async fn fetch_bucket_notes(bucket_name: &str) -> Result<Vec<(String, Option<String>, Option<HashMap<String, String>>, String)>, Box<dyn std::error::Error>> {
+pub async fn fetch_bucket_notes(bucket_name: &str) -> Result<Vec<BucketNote>, Box<dyn std::error::Error>> {
     // Trim any surrounding quotes from the bucket name
     let bucket_name = bucket_name.trim_matches('"');
 
-    // Create AWS configuration with the desired region
-    let myconfig = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_config::Region::new("eu-west-3"))
-        .load()
-        .await;
+    let client = s3_client().await;
+
+    let objects = list_bucket_objects(&client, bucket_name).await?;
+    let mut keys = Vec::new();
 
-    // Create an S3 client using the configuration
-    let client = s3::Client::new(&myconfig);
+    // Fetch the metadata and content of each object in the bucket
+    for (key, _etag) in objects {
+        let (last_modified, metadata, content) = fetch_bucket_object(&client, bucket_name, &key).await?;
 
+        // Add the note's key, last modified timestamp, metadata, and content to the result vector
+        keys.push((key, last_modified, metadata, content));
+    }
+
+    Ok(keys)
+}
+
+
+/// Lists the objects of an Amazon S3 bucket without downloading them.
+///
+/// # Parameters
+///
+/// * `client` - The S3 client to use.
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Returns
+///
+/// Returns a `Result` containing a vector of tuples with the key and the ETag of each note object.
+/// Presence markers are left out (see `presence_operations`).
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when listing the objects.
+pub async fn list_bucket_objects(client: &s3::Client, bucket_name: &str) -> Result<Vec<(String, Option<String>)>, Box<dyn std::error::Error>> {
     // Send a request to list objects in the bucket
     let mut response = client
         .list_objects_v2()
@@ -716,83 +1075,77 @@ pub async fn fetch_bucket_notes(bucket_name: &str) -> Result<Vec<(String, Option
         .into_paginator()
         .send();
 
-    let mut keys = Vec::new();
+    let mut objects = Vec::new();
 
     // Iterate over the paginated response
     while let Some(result) = response.next().await {
-        match result {
-            Ok(output) => {
-                // Process each object in the response
-                for object in output.contents() {
-                    if let Some(key) = object.key() {
-                        // Send a request to get the object's metadata and content
-                        let get_object_output = client
-                            .get_object()
-                            .bucket(bucket_name)
-                            .key(key)
-                            .send()
-                            .await;
-
-                        // Extract the last modified timestamp, metadata, and content from the response
-                        let (last_modified, metadata, content) = match get_object_output {
-                            Ok(get_object) => {
-                                let last_modified = get_object.last_modified().cloned().map(|dt| dt.to_string());
-                                let metadata = get_object.metadata().cloned();
-                                let mut content = get_object.body.collect().await.unwrap().to_vec();
-                                // let content = String::from_utf8(content).unwrap_or_else(|_| String::new());
-
-                                // Retrieve the nonce from the metadata and convert it from a base64 string
-                                let nonce_str = match &metadata {
-                                    Some(map) => map.get("nonce").cloned().unwrap_or_else(|| String::from("")),
-                                    None => String::from(""),
-                                };
-                                let nonce_bytes = match general_purpose::STANDARD.decode(&nonce_str) {
-                                    Ok(bytes) => bytes,
-                                    Err(_) => {
-                                        eprintln!("Failed to decode nonce");
-                                        return Err("Failed to decode nonce".into());
-                                    }
-                                };
-                                if nonce_bytes.len() != 12 {
-                                    eprintln!("Nonce has wrong length");
-                                    return Err("Nonce has wrong length".into());
-                                }
-                                let nonce_array: [u8; 12] = nonce_bytes.try_into().unwrap();
-                                let nonce = Nonce::assume_unique_for_key(nonce_array);
-
-                                // Generate a random key
-                                let crypt_key = UnboundKey::new(&CHACHA20_POLY1305, &[0; 32]).unwrap();
-                                let crypt_key = LessSafeKey::new(crypt_key);
-
-                                // Decrypt the content
-                                let decrypted_content = match crypt_key.open_in_place(nonce, Aad::empty(), &mut content) {
-                                    Ok(decrypted_content) => decrypted_content,
-                                    Err(_) => {
-                                        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to decrypt content")));
-                                    }
-                                };
-
-                                let content = String::from_utf8(decrypted_content.to_vec()).unwrap_or_else(|_| String::new());
-                                
-                                (last_modified, metadata, content)
-                            },
-                            Err(err) => {
-                                return Err(Box::new(err));
-                            }
-                        };
-
-                        // Add the note's key, last modified timestamp, metadata, and content to the result vector
-                        keys.push((key.to_string(), last_modified, metadata, content));
-                    }
-                }
-            }
-            Err(err) => {
-                return Err(Box::new(err));
+        let output = result?;
+        for object in output.contents() {
+            // Presence markers are not notes
+            if let Some(key) = object.key().filter(|key| !key.starts_with(presence_operations::PRESENCE_PREFIX)) {
+                objects.push((key.to_string(), object.e_tag().map(|etag| etag.to_string())));
             }
         }
     }
 
-    Ok(keys)
+    Ok(objects)
+}
+
+
+/// Downloads and decrypts a note object of an Amazon S3 bucket.
+///
+/// # Parameters
+///
+/// * `client` - The S3 client to use.
+/// * `bucket_name` - The name of the bucket.
+/// * `key` - The key of the note object.
+///
+/// # Operation
+///
+/// The download waits while the number of note bodies held in memory is at its budget (see `memory_budget`), so
+/// many downloads at once do not buffer every body.
+///
+/// # Returns
+///
+/// Returns a `Result` containing a tuple with the last modified timestamp, the metadata, and the decrypted content of
+/// the note. The Markdown of a note synced in plain text is returned as the content (see `plain_sync::content_from_markdown`).
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when fetching the object or if the content cannot be decrypted.
+pub async fn fetch_bucket_object(client: &s3::Client, bucket_name: &str, key: &str) -> Result<(Option<String>, Option<HashMap<String, String>>, String), Box<dyn std::error::Error>> {
+    // Wait for the memory budget before buffering the body
+    let _body_permit = memory_budget::acquire_body_permit().await;
+
+    // Send a request to get the object's metadata and content
+    let get_object = client
+        .get_object()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await?;
+
+    // Extract the last modified timestamp, metadata, and content from the response
+    let last_modified = get_object.last_modified().cloned().map(|dt| dt.to_string());
+    let metadata = get_object.metadata().cloned();
+    let content = get_object.body.collect().await?.to_vec();
+
+    // A note synced in plain text holds its Markdown
+    if let Some(map) = metadata.as_ref().filter(|map| plain_sync::is_plain_object(map)) {
+        let content = plain_sync::content_from_markdown(&String::from_utf8_lossy(&content), map)?;
+        return Ok((last_modified, metadata, content));
+    }
+
+    // Retrieve the nonce from the metadata and decrypt the content with the key it was encrypted with
+    let (nonce_str, key_version) = match &metadata {
+        Some(map) => (map.get("nonce").cloned().unwrap_or_else(|| String::from("")), key_version_from_metadata(map)),
+        None => (String::from(""), 0),
+    };
+    let decrypted_content = crypto::decrypt_bytes(content, &nonce_str, key_version)?;
+
+    let content = String::from_utf8(decrypted_content).unwrap_or_else(|_| String::new());
+
+    Ok((last_modified, metadata, content))
 }
 
 
@@ -808,7 +1161,9 @@ pub async fn fetch_bucket_notes(bucket_name: &str) -> Result<Vec<(String, Option
 /// * The `bucket_name` parameter is trimmed to remove any surrounding quotes.
 /// * The `fetch_bucket_notes` function is called to retrieve the list of notes in the bucket.
 /// * For each note, the `delete_bucket_note` function is called to delete the note from the bucket.
+/// * The notes uploaded while they were locked are kept (see `local_operations::set_note_locked`).
 /// * If an error occurs while deleting a note, the error is printed to the standard error stream and returned.
+/// * The deletion is recorded in the operation journal until it returns, so it is run again if the application crashes (see `journal`).
 ///
 /// # Returns
 ///
@@ -822,12 +1177,20 @@ pub async fn delete_bucket_notes(bucket_name: &str) -> Result<(), Box<dyn std::e
     // Trim the bucket name to remove any surrounding quotes
     let bucket_name = bucket_name.trim_matches('"');
 
+    // Record the deletion in the journal until it returns
+    let _journal_entry = journal::record(&JournaledOperation::DeleteBucketNotes {
+        bucket_name: bucket_name.to_string(),
+    })?;
+
     // Fetch the list of notes in the bucket
     let notes = fetch_bucket_notes(bucket_name).await?;
 
-    // Iterate over each note and delete it from the bucket
+    // Iterate over each note and delete it from the bucket, keeping the locked notes
     for (_, _, metadata_option, _) in notes {
         if let Some(metadata) = metadata_option {
+            if is_locked(&metadata) {
+                continue;
+            }
             if let Some(uuid) = metadata.get("uuid") {
                 // Delete the note from the bucket
                 match delete_bucket_note(bucket_name, uuid).await {
@@ -841,15 +1204,126 @@ pub async fn delete_bucket_notes(bucket_name: &str) -> Result<(), Box<dyn std::e
     }
 
     // Send a desktop notification
-    Notification::new()
-    .summary("Bucket notes deleted")
-    .body(&format!("Notes from bucket {} were deleted.", bucket_name))
-    .show().unwrap();
+    notifier::notify("Bucket notes deleted", &format!("Notes from bucket {} were deleted.", bucket_name));
 
     Ok(())
 }
 
 
+/// Moves the notes uploaded under their title by the previous versions to the objects named after their UUID.
+///
+/// # Parameters
+///
+/// * `bucket_name` - The name of the bucket to migrate.
+///
+/// # Operation
+///
+/// * The objects of the bucket outside of the "notes/" prefix are listed, and their metadata retrieved.
+/// * The encrypted body of each note object is copied as it is to the object named after its UUID (see `note_key`),
+///   with its title added to its metadata, then the object named after its title is deleted. The object is written
+///   under the path of the notebook of the local copy of the note, or outside of any notebook if there is none.
+/// * If the object named after the UUID already exists, in any notebook, e.g. uploaded since by another device, it
+///   stays where it is, and the more recently changed of the two copies is kept.
+/// * The objects without a UUID are not notes of the application and are left as they are.
+/// * A migration stopped halfway is finished by running it again, since each note is written to its new object
+///   before its previous object is deleted.
+///
+/// # Returns
+///
+/// Returns a `BucketKeyMigration` with the previous keys of the migrated notes, of the removed duplicates, and of
+/// the skipped objects.
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when reading, writing, or deleting an object,
+/// of the kind of the failure (see `AppError::s3`).
+pub async fn migrate_bucket_keys(bucket_name: &str) -> Result<BucketKeyMigration, AppError> {
+    let bucket_name = bucket_name.trim_matches('"');
+    let client = s3_client().await;
+    let objects = list_bucket_objects(&client, bucket_name).await.map_err(|e| AppError::s3(e.as_ref()))?;
+    let mut migration = BucketKeyMigration::default();
+
+    for (key, _) in &objects {
+        if key.starts_with(NOTES_PREFIX) {
+            continue;
+        }
+        let head_object = client.head_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3_with(&e, &format!("Failed to read {}", key)))?;
+        let mut metadata = head_object.metadata.unwrap_or_default();
+        let Some(uuid) = metadata.get("uuid").filter(|uuid| !uuid.is_empty()).cloned() else {
+            migration.skipped.push(key.clone());
+            continue;
+        };
+
+        // Write to the object named after the UUID in any notebook, else in the notebook of the local copy, and keep
+        // the object named after the UUID if it is the more recent copy of the note
+        let existing = match objects.iter().find(|(other, _)| is_note_key(other, &uuid)) {
+            Some((existing_key, _)) => find_note_object(&client, bucket_name, &uuid, notebook_from_key(existing_key).as_deref())
+                .await
+                .map_err(|e| AppError::s3(e.as_ref()))?,
+            None => None,
+        };
+        let new_key = match &existing {
+            Some((existing_key, _)) => existing_key.clone(),
+            None => note_key(&uuid, notebook_operations::note_notebook_path(&uuid)?.as_deref()),
+        };
+        let is_duplicate = existing.is_some_and(|(_, existing)| changed_at(&existing) >= changed_at(&metadata));
+
+        if !is_duplicate {
+            // Copy the encrypted body as it is, once the memory budget allows buffering it
+            let _body_permit = memory_budget::acquire_body_permit().await;
+            let object = client.get_object()
+                .bucket(bucket_name)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| AppError::s3_with(&e, &format!("Failed to read {}", key)))?;
+            let body = object.body.collect().await.map_err(|e| AppError::s3_with(&e, &format!("Failed to read {}", key)))?.to_vec();
+
+            metadata.insert("title".to_string(), title_metadata(&title_from_metadata(key, &metadata)));
+            client.put_object()
+                .bucket(bucket_name)
+                .key(&new_key)
+                .set_metadata(Some(metadata))
+                .body(s3::primitives::ByteStream::from(body))
+                .content_type("text/plain")
+                .send()
+                .await
+                .map_err(|e| AppError::s3_with(&e, &format!("Failed to write {}", new_key)))?;
+        }
+
+        client.delete_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::s3_with(&e, &format!("Failed to delete {}", key)))?;
+
+        match is_duplicate {
+            true => migration.duplicates_removed.push(key.clone()),
+            false => migration.migrated.push(key.clone()),
+        }
+    }
+
+    if !migration.migrated.is_empty() {
+        notifier::notify("Bucket migrated", &format!("{} notes of bucket {} were migrated.", migration.migrated.len(), bucket_name));
+    }
+
+    Ok(migration)
+}
+
+
+/// Returns the time a note object was last changed, from its metadata.
+pub fn changed_at(metadata: &HashMap<String, String>) -> i64 {
+    let time = |field: &str| metadata.get(field).and_then(|value| value.parse::<i64>().ok()).filter(|time| *time > 0);
+    time("updated_at").or_else(|| time("created_at")).unwrap_or(0)
+}
+
+
 // /// Decrypts the content of a note using the provided encrypted content and note ID.
 // ///
 // /// # Parameters
@@ -906,4 +1380,33 @@ pub async fn delete_bucket_notes(bucket_name: &str) -> Result<(), Box<dyn std::e
 //     let decrypted_content = String::from_utf8_lossy(&in_out).into_owned();
 
 //     Ok(decrypted_content)
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "6f1c0e0a-5a8e-4f0e-9d0b-2f8a3c1d9e7b";
+
+    #[test]
+    fn note_key_is_named_after_the_uuid() {
+        assert_eq!(note_key(UUID, None), format!("notes/{}.txt", UUID));
+        assert!(is_note_key(&note_key(UUID, None), UUID));
+        assert!(!is_note_key("Groceries.txt", UUID));
+    }
+
+    #[test]
+    fn note_key_keeps_the_path_of_the_notebook() {
+        let key = note_key(UUID, Some("Work/Projects"));
+        assert_eq!(key, format!("notes/Work/Projects/{}.txt", UUID));
+        assert_eq!(notebook_from_key(&key).as_deref(), Some("Work/Projects"));
+        assert!(is_note_key(&key, UUID));
+    }
+
+    #[test]
+    fn notebook_from_key_is_none_outside_of_notebooks() {
+        assert_eq!(notebook_from_key(&note_key(UUID, None)), None);
+        assert_eq!(notebook_from_key("Groceries.txt"), None);
+        assert_eq!(notebook_from_key("attachments/Work/file.png"), None);
+    }
+}
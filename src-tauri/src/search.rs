@@ -0,0 +1,453 @@
+// search.rs
+
+use crate::{ bucket_mirror, code_block_operations, notebook_operations, s3_operations, search_index, tag_operations, vault, models::AppError, models::Note, models::SearchResult, models::SearchSource, search_index::NoteFields };
+use chrono::{NaiveDate, NaiveTime};
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use tantivy::schema::IndexRecordOption;
+use tantivy::Term;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
+use tantivy::TantivyDocument;
+use tantivy::DocAddress;
+use tantivy::Index;
+use tantivy::Score;
+use tantivy::Searcher;
+use tantivy::collector::TopDocs;
+
+
+/// The title returned in place of the real title of a secure note while the vault is locked.
+const SECURE_NOTE_PLACEHOLDER: &str = "Secure note";
+
+/// The operators recognized in a search query, written as `operator:value`.
+const OPERATORS: [&str; 6] = ["tag", "notebook", "lang", "before", "after", "is"];
+
+
+/// The structured filters parsed out of a search query.
+#[derive(Debug, Default)]
+pub struct SearchFilters {
+    pub tags: Vec<String>,
+    pub notebooks: Vec<String>,
+    pub languages: Vec<String>,
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+    pub secure: bool,
+    pub pinned: bool,
+    pub notebook_ids: Vec<Vec<i64>>,
+}
+
+
+/// A search query split into its free text and its structured filters.
+#[derive(Debug, Default)]
+pub struct ParsedQuery {
+    pub text: String,
+    pub filters: SearchFilters,
+}
+
+
+/// Searches the local notes or the notes of a bucket.
+///
+/// # Arguments
+///
+/// * `query_str` - The search query. Besides free text, it can contain the following operators:
+///   - `tag:work` - Only notes with the tag, whatever its case.
+///   - `notebook:Projects` - Only notes in the notebook or its sub-notebooks, by name or by path, e.g. `notebook:Work/Projects`.
+///   - `lang:rust` - Only notes with a code block in the language, labeled or detected.
+///   - `before:2024-06-01` - Only notes created before the day.
+///   - `after:2024-06-01` - Only notes created after the day.
+///   - `is:secure`, `is:pinned` - Only secure or pinned notes.
+///
+///   Values containing spaces can be quoted, e.g. `notebook:"Side projects"`.
+/// * `local` - A boolean indicating whether to search the local notes or not.
+/// * `bucket_name` - An optional string representing the name of the bucket.
+///
+/// # Operation
+///
+/// * The operators are parsed out of the query.
+/// * Local notes are searched in the index maintained in the background, whose notes and secure notes are searched
+///   separately and their best matches combined.
+/// * Bucket notes are searched in an index of the bucket mirror, after downloading only the objects that changed.
+/// * The free text, the tag, language, notebook, and flag filters, and the date filters are combined into a tantivy boolean query.
+/// * Secure notes are not matched while the vault is locked, and redacted from the results if they still are.
+///
+/// # Returns
+///
+/// A vector of the matching notes, at most 10.
+///
+/// # Errors
+///
+/// Returns an error if any of the following conditions are met:
+/// * The query contains an invalid or unsupported operator.
+/// * `local` is `true` and there was an error retrieving local notes.
+/// * `local` is `false` and `bucket_name` is not provided.
+/// * `local` is `false` and there was an error fetching bucket notes.
+pub async fn search_in_notes(query_str: &str, local: bool, bucket_name: Option<&str>) -> Result<Vec<Note>, Box<dyn std::error::Error>> {
+    // Parse the operators out of the query
+    let mut parsed_query = parse_query(query_str).map_err(AppError::Validation)?;
+    resolve_notebooks(&mut parsed_query.filters)?;
+
+    // Get the indexes of the notes: the two parts of the local index, or the index of the bucket
+    let indexes = if local {
+        // The local notes are indexed in the background
+        search_index::local_searchers()?
+    } else {
+        let bucket_name = bucket_name
+        .map(|name| name.trim_matches('"'))
+        .ok_or(AppError::Validation("Bucket name is required when local is false".to_string()))?;
+        bucket_searchers(bucket_name).await?
+    };
+
+    Ok(search_indexes(indexes, &parsed_query)?.into_iter().map(|(_score, note)| redact(note)).collect())
+}
+
+
+/// Searches the local notes and the notes of every bucket of the application at once.
+///
+/// # Arguments
+///
+/// * `query_str` - The search query, with the operators of `search_in_notes`.
+///
+/// # Operation
+///
+/// * The local notes and the notes of each bucket tagged for the application (see `s3_operations::fetch_buckets`)
+///   are searched like `search_in_notes` does, and their best matches combined.
+/// * A note found in several places is returned once, by its UUID: its local copy if it has one, or else its best
+///   matching copy in a bucket.
+/// * A bucket that cannot be searched, e.g. one the credentials cannot read, is skipped, so it does not hide the
+///   results of the others.
+/// * Secure notes are not matched while the vault is locked, and redacted from the results if they still are.
+///
+/// # Returns
+///
+/// A vector of the matching notes, at most 10, each with where it was found: locally or in which bucket.
+///
+/// # Errors
+///
+/// Returns an error if the query contains an invalid or unsupported operator, if the local notes cannot be searched,
+/// or if the buckets cannot be listed.
+pub async fn search_all_notes(query_str: &str) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
+    let mut parsed_query = parse_query(query_str).map_err(AppError::Validation)?;
+    resolve_notebooks(&mut parsed_query.filters)?;
+
+    let mut scored_results: Vec<(Score, Note, SearchSource)> = search_indexes(search_index::local_searchers()?, &parsed_query)?
+        .into_iter()
+        .map(|(score, note)| (score, note, SearchSource::Local))
+        .collect();
+    for bucket_name in s3_operations::fetch_buckets().await? {
+        let indexes = match bucket_searchers(&bucket_name).await {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                eprintln!("Failed to search bucket {}: {}", bucket_name, e);
+                continue;
+            },
+        };
+        for (score, note) in search_indexes(indexes, &parsed_query)? {
+            scored_results.push((score, note, SearchSource::Bucket(bucket_name.clone())));
+        }
+    }
+
+    // Keep a single copy of each note, the local one first
+    let local_uuids: HashSet<String> = scored_results.iter()
+        .filter(|(_, _, source)| matches!(source, SearchSource::Local))
+        .filter_map(|(_, note, _)| note.uuid.clone().filter(|uuid| !uuid.is_empty()))
+        .collect();
+    scored_results.retain(|(_, note, source)| {
+        matches!(source, SearchSource::Local) || !note.uuid.as_ref().is_some_and(|uuid| local_uuids.contains(uuid))
+    });
+    scored_results.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut seen_uuids = HashSet::new();
+    scored_results.retain(|(_, note, _)| match note.uuid.as_ref().filter(|uuid| !uuid.is_empty()) {
+        Some(uuid) => seen_uuids.insert(uuid.clone()),
+        None => true,
+    });
+    scored_results.truncate(10);
+
+    Ok(scored_results.into_iter().map(|(_score, note, source)| SearchResult { note: redact(note), source }).collect())
+}
+
+
+/// Returns the index of the notes of a bucket, after downloading only the objects that changed since the last search.
+async fn bucket_searchers(bucket_name: &str) -> Result<Vec<(Index, NoteFields, Searcher)>, Box<dyn std::error::Error>> {
+    let changes = bucket_mirror::sync_bucket_mirror(bucket_name).await?;
+    let keys = search_index::has_bucket_index(bucket_name).then_some(changes.changed);
+    let notes = bucket_mirror::get_mirrored_notes(bucket_name, keys.as_deref())?
+        .into_iter()
+        .map(|(key, last_modified, metadata, content)| (key.clone(), bucket_note(key, last_modified, metadata, content)))
+        .collect();
+    Ok(vec![search_index::update_bucket_index(bucket_name, notes, &changes.removed)?])
+}
+
+
+/// Performs a search on each index and keeps the best matches overall, at most 10, with their score.
+fn search_indexes(indexes: Vec<(Index, NoteFields, Searcher)>, parsed_query: &ParsedQuery) -> Result<Vec<(Score, Note)>, Box<dyn std::error::Error>> {
+    let mut scored_notes: Vec<(Score, Note)> = Vec::new();
+    for (index, fields, searcher) in indexes {
+        let query = build_query(&index, &fields, parsed_query)?;
+        let top_docs: Vec<(Score, DocAddress)> = searcher.search(&query, &TopDocs::with_limit(10))?;
+
+        // Retrieve the actual content of the documents
+        for (score, doc_address) in top_docs {
+            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
+            scored_notes.push((score, search_index::document_to_note(&fields, &retrieved_doc)));
+        }
+    }
+    scored_notes.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored_notes.truncate(10);
+    Ok(scored_notes)
+}
+
+
+/// Redacts a secure note found by a search while the vault is locked.
+fn redact(mut note: Note) -> Note {
+    if note.secure && !vault::is_unlocked() {
+        note.title = SECURE_NOTE_PLACEHOLDER.to_string();
+        note.content = String::new();
+        note.aliases.clear();
+        note.tags.clear();
+    }
+    note
+}
+
+
+/// Combines the free text, the tag, language, notebook, and flag filters, and the date filters of a query into a single tantivy query.
+fn build_query(index: &Index, fields: &NoteFields, parsed_query: &ParsedQuery) -> Result<BooleanQuery, Box<dyn std::error::Error>> {
+    let filters = &parsed_query.filters;
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    if parsed_query.text.is_empty() {
+        clauses.push((Occur::Must, Box::new(AllQuery)));
+    } else {
+        // Create a query parser for the prose, code, and math of the content, the aliases, and the text of the attachments
+        let query_parser = QueryParser::for_index(index, vec![fields.text, fields.code, fields.math, fields.aliases, fields.attachment_text]);
+        clauses.push((Occur::Must, query_parser.parse_query(&parsed_query.text)?));
+    }
+    for tag in &filters.tags {
+        let term = Term::from_field_text(fields.tags, tag);
+        clauses.push((Occur::Must, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+    }
+    for language in &filters.languages {
+        let term = Term::from_field_text(fields.language, language);
+        clauses.push((Occur::Must, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+    }
+    if filters.secure {
+        let term = Term::from_field_bool(fields.secure, true);
+        clauses.push((Occur::Must, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+    }
+    if filters.pinned {
+        let term = Term::from_field_bool(fields.pinned, true);
+        clauses.push((Occur::Must, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+    }
+    // Secure notes are not matched while the vault is locked, so a search cannot tell which words they contain
+    if !vault::is_unlocked() {
+        let term = Term::from_field_bool(fields.secure, true);
+        clauses.push((Occur::MustNot, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+    }
+    for notebook_ids in &filters.notebook_ids {
+        let notebook_clauses: Vec<(Occur, Box<dyn Query>)> = notebook_ids.iter().map(|notebook_id| {
+            let term = Term::from_field_i64(fields.notebook_id, *notebook_id);
+            (Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>)
+        }).collect();
+        clauses.push((Occur::Must, Box::new(BooleanQuery::new(notebook_clauses))));
+    }
+    if filters.before.is_some() || filters.after.is_some() {
+        let lower = filters.after.map_or(Bound::Unbounded, Bound::Included);
+        let upper = filters.before.map_or(Bound::Unbounded, Bound::Excluded);
+        clauses.push((Occur::Must, Box::new(RangeQuery::new_i64_bounds("created_at".to_string(), lower, upper))));
+    }
+    Ok(BooleanQuery::new(clauses))
+}
+
+
+/// Builds a note from a bucket object, as returned by `bucket_mirror::get_mirrored_notes`.
+fn bucket_note(key: String, last_modified: Option<String>, metadata: Option<HashMap<String, String>>, content: String) -> Note {
+    let metadata = metadata.unwrap_or_default();
+    let title = s3_operations::title_from_metadata(&key, &metadata);
+    let uuid = metadata.get("uuid").cloned().unwrap_or_default();
+    let timestamp = metadata.get("timestamp").cloned().unwrap_or_default();
+    let created_at = metadata.get("created_at").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
+    let secure = metadata.get("secure").map(|s| s == "true").unwrap_or(false);
+    Note {
+        id: Some(0),
+        uuid: Some(uuid),
+        title,
+        content,
+        nonce: None,
+        created_at,
+        updated_at: last_modified.map(|lm| lm.parse::<i64>().unwrap_or(0)),
+        timestamp: Some(timestamp),
+        secure,
+        status: metadata.get("status").cloned(),
+        icon: s3_operations::icon_from_metadata(&metadata),
+        cover_attachment_id: metadata.get("cover_attachment_id").and_then(|s| s.parse::<i64>().ok()),
+        locked: s3_operations::is_locked(&metadata),
+        pinned: s3_operations::flag_from_metadata(&metadata, "pinned"),
+        archived: s3_operations::flag_from_metadata(&metadata, "archived"),
+        notebook_id: None,
+        aliases: s3_operations::aliases_from_metadata(&metadata),
+        tags: s3_operations::tags_from_metadata(&metadata),
+    }
+}
+
+
+/// Splits a search query into its free text and its structured filters.
+///
+/// # Arguments
+///
+/// * `query_str` - The search query, e.g. `tag:work before:2024-06-01 "release notes"`.
+///
+/// # Returns
+///
+/// Returns the `ParsedQuery`, whose text keeps the terms that are not operators in their original order.
+///
+/// # Errors
+///
+/// This function will return an error if a date is not in `YYYY-MM-DD` format or if an `is:` value is unknown.
+pub fn parse_query(query_str: &str) -> Result<ParsedQuery, String> {
+    let mut parsed_query = ParsedQuery::default();
+    let mut text_terms = Vec::new();
+
+    for term in split_terms(query_str) {
+        let operator = term.split_once(':')
+            .filter(|(name, value)| OPERATORS.contains(name) && !value.is_empty());
+
+        let (name, value) = match operator {
+            Some((name, value)) => (name, value.trim_matches('"')),
+            None => {
+                text_terms.push(term);
+                continue;
+            }
+        };
+
+        let filters = &mut parsed_query.filters;
+        match name {
+            // The tags are indexed lowercase (see `search_index::TAG_TOKENIZER`)
+            "tag" => filters.tags.push(tag_operations::clean_tag(value).to_lowercase()),
+            "notebook" => filters.notebooks.push(value.to_string()),
+            "lang" => filters.languages.push(code_block_operations::normalize_language(value)),
+            "before" => filters.before = Some(parse_day(value)?),
+            // Notes created after a day are the ones created from the start of the next day
+            "after" => filters.after = Some(parse_day(value)? + 24 * 60 * 60),
+            _ => match value {
+                "secure" => filters.secure = true,
+                "pinned" => filters.pinned = true,
+                _ => return Err(format!("Unknown filter 'is:{}'", value)),
+            },
+        }
+    }
+
+    parsed_query.text = text_terms.join(" ");
+
+    Ok(parsed_query)
+}
+
+
+/// Resolves the notebooks of the `notebook:` filters to their IDs and those of their sub-notebooks. The notes of
+/// the buckets are in no notebook, so they do not match these filters.
+fn resolve_notebooks(filters: &mut SearchFilters) -> Result<(), AppError> {
+    for notebook in &filters.notebooks {
+        let notebook_ids = notebook_operations::notebook_ids_by_name(notebook)?;
+        if notebook_ids.is_empty() {
+            return Err(AppError::NotFound(format!("There is no notebook '{}'", notebook)));
+        }
+        filters.notebook_ids.push(notebook_ids);
+    }
+    Ok(())
+}
+
+
+/// Splits a query on whitespace, keeping double-quoted sections together.
+fn split_terms(query_str: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in query_str.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            },
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+
+    terms
+}
+
+
+/// Parses a `YYYY-MM-DD` day into the Unix timestamp of its start, in UTC.
+fn parse_day(value: &str) -> Result<i64, String> {
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", value))?;
+
+    Ok(day.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_query_keeps_free_text_in_order() {
+        let parsed = parse_query("release tag:work notes \"next week\"").unwrap();
+        assert_eq!(parsed.text, "release notes \"next week\"");
+        assert_eq!(parsed.filters.tags, vec!["work"]);
+    }
+
+    #[test]
+    fn parse_query_reads_operators() {
+        let parsed = parse_query("tag:work notebook:\"Work/Projects\" is:secure is:pinned").unwrap();
+        assert_eq!(parsed.text, "");
+        assert_eq!(parsed.filters.tags, vec!["work"]);
+        assert_eq!(parsed.filters.notebooks, vec!["Work/Projects"]);
+        assert!(parsed.filters.secure);
+        assert!(parsed.filters.pinned);
+    }
+
+    #[test]
+    fn parse_query_normalizes_languages() {
+        let parsed = parse_query("lang:rs lang:Python3 lang:zig").unwrap();
+        assert_eq!(parsed.filters.languages, vec!["rust", "python", "zig"]);
+    }
+
+    #[test]
+    fn parse_query_reads_days_in_utc() {
+        let parsed = parse_query("before:2024-06-01 after:2024-06-01").unwrap();
+        assert_eq!(parsed.filters.before, Some(1_717_200_000));
+        assert_eq!(parsed.filters.after, Some(1_717_200_000 + 24 * 60 * 60));
+    }
+
+    #[test]
+    fn parse_query_treats_unknown_and_empty_operators_as_text() {
+        let parsed = parse_query("http://example.com tag: due:today").unwrap();
+        assert_eq!(parsed.text, "http://example.com tag: due:today");
+        assert!(parsed.filters.tags.is_empty());
+    }
+
+    #[test]
+    fn parse_query_rejects_invalid_values() {
+        assert!(parse_query("before:yesterday").is_err());
+        assert!(parse_query("after:2024-13-01").is_err());
+        assert!(parse_query("is:shared").is_err());
+    }
+
+    #[test]
+    fn invalid_queries_are_validation_errors() {
+        // The commands tell the kind of a failed search from its chain, as they do for a failed S3 operation
+        let err = tauri::async_runtime::block_on(search_in_notes("before:yesterday", true, None)).unwrap_err();
+        assert!(matches!(AppError::s3(err.as_ref()), AppError::Validation(_)));
+
+        let err = tauri::async_runtime::block_on(search_in_notes("release", false, None)).unwrap_err();
+        assert!(matches!(AppError::s3(err.as_ref()), AppError::Validation(_)));
+
+        let err = tauri::async_runtime::block_on(search_all_notes("is:shared")).unwrap_err();
+        assert!(matches!(AppError::s3(err.as_ref()), AppError::Validation(_)));
+    }
+}
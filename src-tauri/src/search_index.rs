@@ -0,0 +1,930 @@
+// search_index.rs
+
+use crate::{ app_paths, code_block_operations, delta, key_management, local_operations, memory_budget, settings, startup, tasks, text_extraction, vault, local_operations::CONNECTION, tasks::TaskContext, models::IndexProtection, models::IndexStatus, models::Note, models::TaskPriority };
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tantivy::schema::{Field, IndexRecordOption, OwnedValue, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED, STRING, TEXT, STORED};
+use tantivy::tokenizer::{LowerCaser, RawTokenizer, RegexTokenizer, TextAnalyzer, WhitespaceTokenizer};
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term};
+
+
+/// The settings key under which the protection of the secure notes in the index is stored.
+const INDEX_PROTECTION_SETTING: &str = "index_protection";
+
+/// The name of the tokenizer of code, which keeps identifiers such as `snake_case` and `os.path.join` whole.
+const CODE_TOKENIZER: &str = "code";
+
+/// The name of the tokenizer of math, which keeps LaTeX expressions as written and only splits them on whitespace.
+const MATH_TOKENIZER: &str = "math";
+
+/// The name of the tokenizer of tags, which keeps each tag whole and ignores its case.
+const TAG_TOKENIZER: &str = "tag";
+
+/// The tokens of code: identifiers with their dotted paths, e.g. `snake_case` or `os.path.join`. Operators are dropped.
+const CODE_TOKEN: &str = r"[\p{L}\p{N}_]+(?:\.[\p{L}\p{N}_]+)*";
+
+/// The version of the schema and the tokenizers, stored with each commit of the local index. An index written with
+/// another version is built again.
+const INDEX_VERSION: i64 = 4;
+
+/// The name of the file holding the name of the current generation of the local index, in its directory.
+const CURRENT_GENERATION_FILE: &str = "current";
+
+
+/// The fields of the note schema.
+#[derive(Clone, Copy)]
+pub struct NoteFields {
+    pub title: Field,
+    pub content: Field,
+    pub text: Field,
+    pub code: Field,
+    pub math: Field,
+    pub attachment_text: Field,
+    pub aliases: Field,
+    pub tags: Field,
+    pub language: Field,
+    pub id: Field,
+    pub uuid: Field,
+    pub created_at: Field,
+    pub updated_at: Field,
+    pub timestamp: Field,
+    pub secure: Field,
+    pub status: Field,
+    pub icon: Field,
+    pub cover_attachment_id: Field,
+    pub locked: Field,
+    pub pinned: Field,
+    pub archived: Field,
+    pub notebook_id: Field,
+    pub key: Field,
+}
+
+
+/// An index of notes, kept for the lifetime of the application.
+pub struct NoteIndex {
+    index: Index,
+    writer: IndexWriter,
+    reader: IndexReader,
+    fields: NoteFields,
+    last_commit_at: Option<i64>,
+}
+
+
+/// The index of the local notes, in two parts so the plaintext of the secure notes is never written to disk.
+struct LocalIndex {
+    /// The notes that are not secure, in an index kept on disk between runs, or in memory while a passphrase is set.
+    notes: NoteIndex,
+    /// The secure notes, in an index kept in memory.
+    secure: NoteIndex,
+    /// The directory of the generation of the index of the notes, or `None` if it is kept in memory.
+    dir: Option<PathBuf>,
+}
+
+
+/// The kind of block of a note being read by `split_content`.
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    Code,
+    Math,
+}
+
+
+/// The text of a note split by content type, each indexed with its own tokenizer.
+#[derive(Default)]
+struct ContentParts {
+    text: String,
+    code: String,
+    math: String,
+}
+
+
+/// The changes waiting to be applied to the local index.
+#[derive(Default)]
+struct ReindexQueue {
+    note_ids: BTreeSet<i64>,
+    rebuild: bool,
+    in_progress: usize,
+}
+
+
+lazy_static! {
+    /// The index of the local notes, opened or built by the warm-up or by the first search.
+    static ref LOCAL_INDEX: Mutex<Option<LocalIndex>> = Mutex::new(None);
+
+    /// The indexes of the bucket notes by bucket name, updated from the bucket mirror on each search.
+    static ref BUCKET_INDEXES: Mutex<HashMap<String, NoteIndex>> = Mutex::new(HashMap::new());
+
+    /// The queue of the notes to reindex, drained by the reindex task.
+    static ref REINDEX_QUEUE: Mutex<ReindexQueue> = Mutex::new(ReindexQueue::default());
+
+    /// Matches the inline spans of a Markdown line: code, then display and inline LaTeX math.
+    /// Inline `$...$` math cannot start or end with a space, so prices such as "$5 and $6" are left alone.
+    static ref INLINE_SPAN: Regex = Regex::new(
+        r"`([^`]+)`|\$\$(.+?)\$\$|\\\((.+?)\\\)|\\\[(.+?)\\\]|\$([^\s$](?:[^$]*[^\s$])?)\$"
+    ).unwrap();
+}
+
+
+/// Builds the schema used to index notes.
+///
+/// # Returns
+///
+/// Returns the schema and its fields. The content is stored as is and indexed in three fields: "text" for the prose,
+/// "code" for code blocks and inline code, and "math" for LaTeX math, each with its own tokenizer.
+/// The text extracted from the attachments of local notes is indexed in "attachment_text", and the aliases of the
+/// notes in "aliases", one value each. The tags are indexed whole and lowercase in "tags", to filter by them.
+/// The "id" and "key" fields are indexed so local and bucket notes can be deleted by ID and by object key, and "id"
+/// is a fast field to list the notes of an index opened from disk,
+/// "language" holds the languages of the code blocks, "secure", "pinned", and "notebook_id" are indexed to filter by
+/// them, and "created_at" for date range queries.
+pub fn build_schema() -> (Schema, NoteFields) {
+    let indexed_with = |tokenizer| TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(tokenizer)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+    );
+
+    let mut schema_builder = Schema::builder();
+    let fields = NoteFields {
+        title: schema_builder.add_text_field("title", TEXT | STORED),
+        content: schema_builder.add_text_field("content", STORED),
+        text: schema_builder.add_text_field("text", TEXT),
+        code: schema_builder.add_text_field("code", indexed_with(CODE_TOKENIZER)),
+        math: schema_builder.add_text_field("math", indexed_with(MATH_TOKENIZER)),
+        attachment_text: schema_builder.add_text_field("attachment_text", TEXT),
+        aliases: schema_builder.add_text_field("aliases", TEXT | STORED),
+        tags: schema_builder.add_text_field("tags", indexed_with(TAG_TOKENIZER) | STORED),
+        language: schema_builder.add_text_field("language", STRING),
+        id: schema_builder.add_i64_field("id", INDEXED | STORED | FAST),
+        uuid: schema_builder.add_text_field("uuid", TEXT | STORED),
+        created_at: schema_builder.add_i64_field("created_at", INDEXED | STORED),
+        updated_at: schema_builder.add_i64_field("updated_at", STORED),
+        timestamp: schema_builder.add_text_field("timestamp", TEXT | STORED),
+        secure: schema_builder.add_bool_field("secure", INDEXED | STORED),
+        status: schema_builder.add_text_field("status", STRING | STORED),
+        icon: schema_builder.add_text_field("icon", STORED),
+        cover_attachment_id: schema_builder.add_i64_field("cover_attachment_id", STORED),
+        locked: schema_builder.add_bool_field("locked", STORED),
+        pinned: schema_builder.add_bool_field("pinned", INDEXED | STORED),
+        archived: schema_builder.add_bool_field("archived", STORED),
+        notebook_id: schema_builder.add_i64_field("notebook_id", INDEXED | STORED),
+        key: schema_builder.add_text_field("key", STRING),
+    };
+
+    (schema_builder.build(), fields)
+}
+
+
+/// Converts a note into an index document.
+pub fn note_to_document(fields: &NoteFields, note: &Note) -> TantivyDocument {
+    let mut doc = TantivyDocument::new();
+    doc.add_text(fields.title, &note.title);
+    doc.add_text(fields.content, &note.content);
+    let parts = split_content(&note.content);
+    doc.add_text(fields.text, &parts.text);
+    doc.add_text(fields.code, &parts.code);
+    doc.add_text(fields.math, &parts.math);
+    for alias in &note.aliases {
+        doc.add_text(fields.aliases, alias);
+    }
+    for tag in &note.tags {
+        doc.add_text(fields.tags, tag);
+    }
+    for (label, code) in delta::code_blocks(&note.content) {
+        if let (Some(language), _) = code_block_operations::block_language(label.as_deref(), &code) {
+            doc.add_text(fields.language, &language);
+        }
+    }
+    doc.add_i64(fields.id, note.id.unwrap_or(0));
+    doc.add_text(fields.uuid, note.uuid.as_deref().unwrap_or_default());
+    doc.add_i64(fields.created_at, note.created_at);
+    if let Some(updated_at) = note.updated_at {
+        doc.add_i64(fields.updated_at, updated_at);
+    }
+    if let Some(timestamp) = &note.timestamp {
+        doc.add_text(fields.timestamp, timestamp);
+    }
+    doc.add_bool(fields.secure, note.secure);
+    if let Some(status) = &note.status {
+        doc.add_text(fields.status, status);
+    }
+    if let Some(icon) = &note.icon {
+        doc.add_text(fields.icon, icon);
+    }
+    if let Some(cover_attachment_id) = note.cover_attachment_id {
+        doc.add_i64(fields.cover_attachment_id, cover_attachment_id);
+    }
+    doc.add_bool(fields.locked, note.locked);
+    doc.add_bool(fields.pinned, note.pinned);
+    doc.add_bool(fields.archived, note.archived);
+    if let Some(notebook_id) = note.notebook_id {
+        doc.add_i64(fields.notebook_id, notebook_id);
+    }
+    doc
+}
+
+
+/// Converts an index document back into a note.
+pub fn document_to_note(fields: &NoteFields, doc: &TantivyDocument) -> Note {
+    let text = |field| doc.get_first(field).and_then(|v| match v {
+        OwnedValue::Str(t) => Some(t.to_string()),
+        _ => None,
+    });
+    let integer = |field| doc.get_first(field).and_then(|v| match v {
+        OwnedValue::I64(t) => Some(*t),
+        _ => None,
+    });
+    let flag = |field| doc.get_first(field).is_some_and(|v| matches!(v, OwnedValue::Bool(true)));
+
+    Note {
+        id: integer(fields.id),
+        uuid: text(fields.uuid),
+        title: text(fields.title).unwrap_or_default(),
+        content: text(fields.content).unwrap_or_default(),
+        nonce: None,
+        created_at: integer(fields.created_at).unwrap_or(0),
+        updated_at: integer(fields.updated_at),
+        timestamp: text(fields.timestamp),
+        secure: doc.get_first(fields.secure).and_then(|v| match v {
+            OwnedValue::Bool(t) => Some(*t),
+            _ => None,
+        }).unwrap_or(false),
+        status: text(fields.status),
+        icon: text(fields.icon),
+        cover_attachment_id: integer(fields.cover_attachment_id),
+        locked: doc.get_first(fields.locked).and_then(|v| match v {
+            OwnedValue::Bool(t) => Some(*t),
+            _ => None,
+        }).unwrap_or(false),
+        pinned: flag(fields.pinned),
+        archived: flag(fields.archived),
+        notebook_id: integer(fields.notebook_id),
+        aliases: doc.get_all(fields.aliases).filter_map(|v| match v {
+            OwnedValue::Str(t) => Some(t.to_string()),
+            _ => None,
+        }).collect(),
+        tags: doc.get_all(fields.tags).filter_map(|v| match v {
+            OwnedValue::Str(t) => Some(t.to_string()),
+            _ => None,
+        }).collect(),
+    }
+}
+
+
+/// Runs the warm-up task, which opens the local index kept on disk, or indexes every local note, so the first search
+/// does not have to wait for it.
+pub fn run_warm_up(_context: &TaskContext) -> Result<(), String> {
+    startup::time("search index", warm_up)
+}
+
+
+/// Runs the reindex task, which applies the changes queued so far in one commit.
+pub fn run_reindex(_context: &TaskContext) -> Result<(), String> {
+    let (note_ids, rebuild) = {
+        let mut queue = REINDEX_QUEUE.lock().unwrap();
+        let note_ids = std::mem::take(&mut queue.note_ids);
+        let rebuild = std::mem::take(&mut queue.rebuild);
+        queue.in_progress = note_ids.len();
+        (note_ids, rebuild)
+    };
+
+    let result = if rebuild { rebuild_index() } else { reindex_notes(&note_ids) };
+    REINDEX_QUEUE.lock().unwrap().in_progress = 0;
+
+    result
+}
+
+
+/// Queues a local note to be reindexed in the background after it was created, updated, or deleted.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+pub fn schedule_reindex(note_id: i64) {
+    REINDEX_QUEUE.lock().unwrap().note_ids.insert(note_id);
+    tasks::submit_unless_queued(tasks::REINDEX, "Update the search index", TaskPriority::Normal);
+}
+
+
+/// Queues a full rebuild of the local index, e.g. after every note was deleted.
+pub fn schedule_rebuild() {
+    {
+        let mut queue = REINDEX_QUEUE.lock().unwrap();
+        queue.note_ids.clear();
+        queue.rebuild = true;
+    }
+    tasks::submit_unless_queued(tasks::REINDEX, "Update the search index", TaskPriority::Normal);
+}
+
+
+/// Retrieves the status of the local index.
+///
+/// # Returns
+///
+/// Returns an `IndexStatus` with the number of indexed notes, the number of notes waiting to be reindexed,
+/// and the time of the last commit.
+pub fn get_index_status() -> IndexStatus {
+    let pending = {
+        let queue = REINDEX_QUEUE.lock().unwrap();
+        queue.note_ids.len() + queue.in_progress
+    };
+
+    let local_index = LOCAL_INDEX.lock().unwrap();
+    match local_index.as_ref() {
+        Some(local_index) => IndexStatus {
+            ready: true,
+            indexed_docs: local_index.notes.reader.searcher().num_docs() + local_index.secure.reader.searcher().num_docs(),
+            pending,
+            last_commit_at: local_index.notes.last_commit_at.max(local_index.secure.last_commit_at),
+        },
+        None => IndexStatus {
+            ready: false,
+            indexed_docs: 0,
+            pending,
+            last_commit_at: None,
+        },
+    }
+}
+
+
+/// Returns searchers over the local index, opening or building the index first if the warm-up has not finished.
+///
+/// # Returns
+///
+/// Returns the index, its fields, and a searcher on its last commit for each part of the local index: the notes,
+/// then the secure notes. A query is run on each and their results combined.
+///
+/// # Errors
+///
+/// This function will return an error if the local notes cannot be retrieved or indexed.
+pub fn local_searchers() -> Result<Vec<(Index, NoteFields, Searcher)>, String> {
+    warm_up()?;
+
+    let local_index = LOCAL_INDEX.lock().unwrap();
+    let local_index = local_index.as_ref().ok_or("Search index is not available".to_string())?;
+    Ok([&local_index.notes, &local_index.secure].into_iter()
+        .map(|note_index| (note_index.index.clone(), note_index.fields, note_index.reader.searcher()))
+        .collect())
+}
+
+
+/// Tells whether the notes of a bucket were already indexed since the application started.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+pub fn has_bucket_index(bucket_name: &str) -> bool {
+    BUCKET_INDEXES.lock().unwrap().contains_key(bucket_name)
+}
+
+
+/// Applies the changes of a bucket mirror to the index of the bucket and returns a searcher over it.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+/// * `notes` - The new and modified notes with their object key. The index is created if it does not exist yet,
+///   in which case every note of the bucket should be given.
+/// * `removed_keys` - The keys of the objects removed from the bucket.
+///
+/// # Returns
+///
+/// Returns the index, its fields, and a searcher on its last commit.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be indexed.
+pub fn update_bucket_index(bucket_name: &str, notes: Vec<(String, Note)>, removed_keys: &[String]) -> Result<(Index, NoteFields, Searcher), String> {
+    let index_secure_notes = indexes_secure_notes();
+    let mut bucket_indexes = BUCKET_INDEXES.lock().unwrap();
+    if !bucket_indexes.contains_key(bucket_name) {
+        bucket_indexes.insert(bucket_name.to_string(), create_note_index(None)?);
+    }
+    let bucket_index = bucket_indexes.get_mut(bucket_name).ok_or("Search index is not available".to_string())?;
+
+    if !notes.is_empty() || !removed_keys.is_empty() {
+        for key in removed_keys.iter().chain(notes.iter().map(|(key, _)| key)) {
+            bucket_index.writer.delete_term(Term::from_field_text(bucket_index.fields.key, key));
+        }
+        // Secure notes are still removed from the index above, in case they just became secure
+        for (key, note) in notes.iter().filter(|(_, note)| index_secure_notes || !note.secure) {
+            let mut doc = note_to_document(&bucket_index.fields, note);
+            doc.add_text(bucket_index.fields.key, key);
+            bucket_index.writer.add_document(doc).map_err(|e| e.to_string())?;
+        }
+        commit(bucket_index)?;
+    }
+
+    Ok((bucket_index.index.clone(), bucket_index.fields, bucket_index.reader.searcher()))
+}
+
+
+/// Retrieves how the secure notes are protected in the search index.
+///
+/// # Returns
+///
+/// Returns `IndexProtection::Redact` if secure notes are always indexed and only redacted from the results while the
+/// vault is locked, which is the default, or `IndexProtection::UnlockedOnly` if their plaintext is only kept in the
+/// index while the vault is unlocked.
+///
+/// # Errors
+///
+/// This function will return an error if the stored value is malformed or if there is an issue with the database connection.
+pub fn get_index_protection() -> Result<IndexProtection, String> {
+    match settings::get_setting(INDEX_PROTECTION_SETTING)? {
+        Some(protection) => serde_json::from_str(&protection).map_err(|e| format!("Invalid index protection: {}", e)),
+        None => Ok(IndexProtection::Redact),
+    }
+}
+
+
+/// Sets how the secure notes are protected in the search index.
+///
+/// The secure notes are only indexed in memory, so they are never written to disk. With `IndexProtection::UnlockedOnly`,
+/// the plaintext of the secure notes does not stay in memory either while the vault is locked: it is derived
+/// again from the database when the vault is unlocked and wiped when it is locked.
+///
+/// # Arguments
+///
+/// * `protection` - The protection of the secure notes.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the protection is stored and applied, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or the index.
+pub fn set_index_protection(protection: IndexProtection) -> Result<(), String> {
+    let value = serde_json::to_string(&protection).map_err(|e| e.to_string())?;
+    settings::set_setting(INDEX_PROTECTION_SETTING, &value)?;
+
+    if vault::is_unlocked() {
+        return Ok(());
+    }
+    match protection {
+        IndexProtection::UnlockedOnly => wipe_secure_notes(),
+        IndexProtection::Redact => {
+            schedule_rebuild();
+            BUCKET_INDEXES.lock().unwrap().clear();
+            Ok(())
+        },
+    }
+}
+
+
+/// Indexes the secure notes again after the vault was unlocked, if they were left out while it was locked.
+pub fn vault_unlocked() {
+    if get_index_protection().ok() != Some(IndexProtection::Redact) {
+        schedule_rebuild();
+        // The bucket indexes are rebuilt from the bucket mirrors on the next search
+        BUCKET_INDEXES.lock().unwrap().clear();
+    }
+}
+
+
+/// Wipes the secure notes from the indexes after the vault was locked, if they are only kept while it is unlocked.
+pub fn vault_locked() {
+    if get_index_protection().ok() != Some(IndexProtection::Redact) {
+        if let Err(e) = wipe_secure_notes() {
+            eprintln!("Failed to wipe the secure notes from the search index: {}", e);
+        }
+    }
+}
+
+
+/// Drops the index of a bucket, e.g. after the bucket was deleted.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+pub fn drop_bucket_index(bucket_name: &str) {
+    BUCKET_INDEXES.lock().unwrap().remove(bucket_name);
+}
+
+
+/// Tells whether the secure notes can be indexed now, which is always the case unless they are only kept in the
+/// index while the vault is unlocked. When the protection cannot be read, secure notes are left out.
+pub fn indexes_secure_notes() -> bool {
+    vault::is_unlocked() || get_index_protection().ok() == Some(IndexProtection::Redact)
+}
+
+
+/// Removes the secure notes from the local index and drops the bucket indexes.
+fn wipe_secure_notes() -> Result<(), String> {
+    BUCKET_INDEXES.lock().unwrap().clear();
+
+    let mut local_index = LOCAL_INDEX.lock().unwrap();
+    if let Some(local_index) = local_index.as_mut() {
+        // The index of the secure notes is in memory, so dropping it drops their plaintext
+        local_index.secure = create_note_index(None)?;
+    }
+
+    Ok(())
+}
+
+
+/// Opens the local index, unless it is already open.
+fn warm_up() -> Result<(), String> {
+    if LOCAL_INDEX.lock().unwrap().is_some() {
+        return Ok(());
+    }
+    open_local_index()
+}
+
+
+/// Opens the local index kept on disk and brings it up to date, or builds it if there is none.
+///
+/// # Operation
+///
+/// * While a passphrase is set, the notes must not be written to disk unencrypted, so the index on disk is removed
+///   and the index is built in memory.
+/// * The index on disk is built again if it cannot be opened or was written with another `INDEX_VERSION`.
+/// * Otherwise, the notes changed since its last commit, and the notes it has or misses by mistake, e.g. after a
+///   crash before a commit, are reindexed. The secure notes are indexed in memory.
+fn open_local_index() -> Result<(), String> {
+    if key_management::has_passphrase() {
+        remove_index_dir()?;
+        return rebuild_index();
+    }
+
+    let (notes, committed_at) = match open_current_generation() {
+        Ok(Some(opened)) => opened,
+        Ok(None) => return rebuild_index(),
+        Err(e) => {
+            eprintln!("Failed to open the search index, building it again: {}", e);
+            return rebuild_index();
+        },
+    };
+    let indexed_ids = indexed_note_ids(&notes.reader.searcher())?;
+
+    // Find the notes to reindex before indexing the secure notes, which the index on disk never has
+    let stale_ids = stale_note_ids(&indexed_ids, committed_at)?;
+    let secure_notes = if indexes_secure_notes() {
+        local_operations::get_local_notes_where("secure = 1", &[])?
+    } else {
+        Vec::new()
+    };
+    let attachment_texts = text_extraction::get_extracted_texts(None)?;
+    let mut secure = create_note_index(None)?;
+    for note in &secure_notes {
+        secure.writer.add_document(local_note_to_document(&secure.fields, note, &attachment_texts)).map_err(|e| e.to_string())?;
+    }
+    commit(&mut secure)?;
+
+    {
+        let mut local_index = LOCAL_INDEX.lock().unwrap();
+        // A rebuild may have finished meanwhile, with the current state of every note
+        if local_index.is_some() {
+            return Ok(());
+        }
+        *local_index = Some(LocalIndex { notes, secure, dir: current_generation_dir()? });
+    }
+    reindex_notes(&stale_ids)
+}
+
+
+/// Builds the local index from scratch from every local note.
+fn rebuild_index() -> Result<(), String> {
+    // Read the notes before locking the index, so the database is never locked while holding it
+    let condition = if indexes_secure_notes() { "1 = 1" } else { "secure = 0" };
+    let notes = local_operations::get_local_notes_where(condition, &[])?;
+    let attachment_texts = text_extraction::get_extracted_texts(None)?;
+
+    // The new index replaces the old one once built, since deleting every document and reusing the writer
+    // would leave later deletions unapplied. On disk, it is built in a new generation directory.
+    let dir = if key_management::has_passphrase() { None } else { Some(new_generation_dir()?) };
+    let mut note_index = create_note_index(dir.as_deref())?;
+    let mut secure = create_note_index(None)?;
+    for note in &notes {
+        let target = if note.secure { &mut secure } else { &mut note_index };
+        target.writer.add_document(local_note_to_document(&target.fields, note, &attachment_texts)).map_err(|e| e.to_string())?;
+    }
+    commit(&mut note_index)?;
+    commit(&mut secure)?;
+
+    let previous = {
+        let mut local_index = LOCAL_INDEX.lock().unwrap();
+        match &dir {
+            Some(dir) => set_current_generation(dir)?,
+            None => remove_index_dir()?,
+        }
+        local_index.replace(LocalIndex { notes: note_index, secure, dir })
+    };
+
+    // The previous generation is removed once its writer is dropped. If it cannot be, e.g. while a search still
+    // reads it, it is removed on the next start.
+    if let Some(previous_dir) = previous.and_then(|previous| previous.dir).filter(|previous_dir| previous_dir.exists()) {
+        if let Err(e) = fs::remove_dir_all(&previous_dir) {
+            eprintln!("Failed to remove {}: {}", previous_dir.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Replaces the documents of some notes in the local index with their current state.
+///
+/// Deleted notes are only removed from the index.
+fn reindex_notes(note_ids: &BTreeSet<i64>) -> Result<(), String> {
+    if note_ids.is_empty() {
+        return Ok(());
+    }
+
+    let placeholders = vec!["?"; note_ids.len()].join(", ");
+    let params: Vec<&dyn rusqlite::ToSql> = note_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let mut condition = format!("id IN ({})", placeholders);
+    if !indexes_secure_notes() {
+        condition.push_str(" AND secure = 0");
+    }
+    let notes = local_operations::get_local_notes_where(&condition, &params)?;
+    let attachment_texts = text_extraction::get_extracted_texts(Some(note_ids))?;
+
+    let mut local_index = LOCAL_INDEX.lock().unwrap();
+    // The warm-up indexes the current state of every note anyway
+    let Some(local_index) = local_index.as_mut() else {
+        return Ok(());
+    };
+
+    // A note is removed from both parts, since it moves between them when it becomes secure or not secure
+    for note_index in [&mut local_index.notes, &mut local_index.secure] {
+        for note_id in note_ids {
+            note_index.writer.delete_term(Term::from_field_i64(note_index.fields.id, *note_id));
+        }
+    }
+    for note in &notes {
+        let target = if note.secure { &mut local_index.secure } else { &mut local_index.notes };
+        target.writer.add_document(local_note_to_document(&target.fields, note, &attachment_texts)).map_err(|e| e.to_string())?;
+    }
+    commit(&mut local_index.notes)?;
+    commit(&mut local_index.secure)
+}
+
+
+/// Builds an index of notes in memory, apart from the local index, e.g. to measure how long indexing takes.
+///
+/// # Arguments
+///
+/// * `notes` - The notes to index, with their plaintext content.
+///
+/// # Errors
+///
+/// This function will return an error if the index cannot be created or committed.
+#[cfg(feature = "benchmarks")]
+pub fn build_scratch_index(notes: &[Note]) -> Result<NoteIndex, String> {
+    let mut note_index = create_note_index(None)?;
+    for note in notes {
+        note_index.writer.add_document(note_to_document(&note_index.fields, note)).map_err(|e| e.to_string())?;
+    }
+    commit(&mut note_index)?;
+
+    Ok(note_index)
+}
+
+
+/// Replaces some notes in an index built by `build_scratch_index`, the way `reindex_notes` updates the local index.
+///
+/// # Arguments
+///
+/// * `note_index` - The index.
+/// * `notes` - The notes to replace, found by their ID.
+///
+/// # Errors
+///
+/// This function will return an error if the index cannot be committed.
+#[cfg(feature = "benchmarks")]
+pub fn reindex_scratch_notes(note_index: &mut NoteIndex, notes: &[Note]) -> Result<(), String> {
+    for note in notes {
+        note_index.writer.delete_term(Term::from_field_i64(note_index.fields.id, note.id.unwrap_or(0)));
+        note_index.writer.add_document(note_to_document(&note_index.fields, note)).map_err(|e| e.to_string())?;
+    }
+    commit(note_index)
+}
+
+
+/// Converts a local note into an index document, with the text extracted from its attachments.
+fn local_note_to_document(fields: &NoteFields, note: &Note, attachment_texts: &HashMap<i64, String>) -> TantivyDocument {
+    let mut doc = note_to_document(fields, note);
+    if let Some(text) = note.id.and_then(|id| attachment_texts.get(&id)) {
+        doc.add_text(fields.attachment_text, text);
+    }
+    doc
+}
+
+
+/// Creates an empty index of notes, whose writer uses the memory budget of the settings.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to create the index in, or `None` to keep it in memory.
+fn create_note_index(dir: Option<&Path>) -> Result<NoteIndex, String> {
+    let (schema, fields) = build_schema();
+    let index = match dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            Index::create_in_dir(dir, schema).map_err(|e| e.to_string())?
+        },
+        None => Index::create_in_ram(schema),
+    };
+    note_index(index, fields, None)
+}
+
+
+/// Opens the current generation of the index of the notes kept on disk.
+///
+/// # Returns
+///
+/// Returns the index with the time of its last commit, or `None` if there is no index on disk or it was written with
+/// another `INDEX_VERSION`. The other generations, left by an interrupted rebuild, are removed.
+fn open_current_generation() -> Result<Option<(NoteIndex, i64)>, String> {
+    let Some(dir) = current_generation_dir()? else {
+        return Ok(None);
+    };
+    let base_dir = app_paths::search_index_dir()?;
+    for entry in fs::read_dir(&base_dir).map_err(|e| e.to_string())?.flatten() {
+        if entry.path().is_dir() && entry.path() != dir {
+            if let Err(e) = fs::remove_dir_all(entry.path()) {
+                eprintln!("Failed to remove {}: {}", entry.path().display(), e);
+            }
+        }
+    }
+
+    let index = Index::open_in_dir(&dir).map_err(|e| e.to_string())?;
+    let payload = index.load_metas().map_err(|e| e.to_string())?.payload.unwrap_or_default();
+    let payload: serde_json::Value = serde_json::from_str(&payload).unwrap_or_default();
+    let Some(committed_at) = payload["committed_at"].as_i64().filter(|_| payload["version"].as_i64() == Some(INDEX_VERSION)) else {
+        return Ok(None);
+    };
+
+    let (_, fields) = build_schema();
+    Ok(Some((note_index(index, fields, Some(committed_at))?, committed_at)))
+}
+
+
+/// Returns the directory of the current generation of the index on disk, or `None` if there is none.
+fn current_generation_dir() -> Result<Option<PathBuf>, String> {
+    if key_management::has_passphrase() {
+        return Ok(None);
+    }
+    let base_dir = app_paths::search_index_dir()?;
+    let Ok(generation) = fs::read_to_string(base_dir.join(CURRENT_GENERATION_FILE)) else {
+        return Ok(None);
+    };
+    let dir = base_dir.join(generation.trim());
+    Ok(dir.is_dir().then_some(dir))
+}
+
+
+/// Returns a new directory to build a generation of the index on disk in.
+fn new_generation_dir() -> Result<PathBuf, String> {
+    Ok(app_paths::search_index_dir()?.join(uuid::Uuid::new_v4().to_string()))
+}
+
+
+/// Makes a generation of the index on disk the current one, replacing the file naming it in a single step.
+fn set_current_generation(dir: &Path) -> Result<(), String> {
+    let base_dir = app_paths::search_index_dir()?;
+    let generation = dir.file_name().and_then(|name| name.to_str()).ok_or("Invalid search index directory".to_string())?;
+    let temporary_file = base_dir.join(format!("{}.tmp", CURRENT_GENERATION_FILE));
+    fs::write(&temporary_file, generation).map_err(|e| e.to_string())?;
+    fs::rename(&temporary_file, base_dir.join(CURRENT_GENERATION_FILE)).map_err(|e| e.to_string())
+}
+
+
+/// Removes the index on disk, e.g. once a passphrase is set.
+fn remove_index_dir() -> Result<(), String> {
+    let base_dir = app_paths::search_index_dir()?;
+    if base_dir.exists() {
+        fs::remove_dir_all(&base_dir).map_err(|e| format!("Failed to remove {}: {}", base_dir.display(), e))?;
+    }
+    Ok(())
+}
+
+
+/// Lists the IDs of the notes in an index, from the "id" fast field.
+fn indexed_note_ids(searcher: &Searcher) -> Result<BTreeSet<i64>, String> {
+    let mut note_ids = BTreeSet::new();
+    for segment_reader in searcher.segment_readers() {
+        let ids = segment_reader.fast_fields().i64("id").map_err(|e| e.to_string())?;
+        note_ids.extend(segment_reader.doc_ids_alive().filter_map(|doc| ids.first(doc)));
+    }
+    Ok(note_ids)
+}
+
+
+/// Finds the notes the index on disk is not up to date with: the notes changed since its last commit, the notes it
+/// misses, and the notes it has that were deleted or became secure.
+fn stale_note_ids(indexed_ids: &BTreeSet<i64>, committed_at: i64) -> Result<BTreeSet<i64>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT id, secure, COALESCE(updated_at, created_at) FROM notes WHERE deleted_at IS NULL").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?, row.get::<_, i64>(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut stale_ids = BTreeSet::new();
+    let mut expected_ids = BTreeSet::new();
+    for (id, secure, changed_at) in rows {
+        if !secure {
+            expected_ids.insert(id);
+        }
+        // A change in the second of the commit may have missed it
+        if changed_at >= committed_at || secure == indexed_ids.contains(&id) {
+            stale_ids.insert(id);
+        }
+    }
+    stale_ids.extend(indexed_ids.difference(&expected_ids));
+    Ok(stale_ids)
+}
+
+
+/// Builds an index of notes from a tantivy index: registers its tokenizers, and creates its writer and its reader.
+fn note_index(index: Index, fields: NoteFields, last_commit_at: Option<i64>) -> Result<NoteIndex, String> {
+    let code_tokenizer = RegexTokenizer::new(CODE_TOKEN).map_err(|e| e.to_string())?;
+    index.tokenizers().register(CODE_TOKENIZER, TextAnalyzer::builder(code_tokenizer).filter(LowerCaser).build());
+    index.tokenizers().register(MATH_TOKENIZER, TextAnalyzer::from(WhitespaceTokenizer::default()));
+    index.tokenizers().register(TAG_TOKENIZER, TextAnalyzer::builder(RawTokenizer::default()).filter(LowerCaser).build());
+    let writer = index.writer(memory_budget::index_writer_heap_size()).map_err(|e| e.to_string())?;
+    let reader = index.reader_builder()
+        .reload_policy(ReloadPolicy::Manual)
+        .try_into()
+        .map_err(|e: tantivy::TantivyError| e.to_string())?;
+
+    Ok(NoteIndex {
+        index,
+        writer,
+        reader,
+        fields,
+        last_commit_at,
+    })
+}
+
+
+/// Commits the pending documents of an index and makes them visible to new searchers.
+///
+/// The time of the commit and `INDEX_VERSION` are stored with it, so an index on disk is brought up to date when
+/// it is opened again.
+fn commit(note_index: &mut NoteIndex) -> Result<(), String> {
+    let committed_at = chrono::Utc::now().timestamp();
+    let mut prepared_commit = note_index.writer.prepare_commit().map_err(|e| e.to_string())?;
+    prepared_commit.set_payload(&serde_json::json!({ "version": INDEX_VERSION, "committed_at": committed_at }).to_string());
+    prepared_commit.commit().map_err(|e| e.to_string())?;
+    note_index.reader.reload().map_err(|e| e.to_string())?;
+    note_index.last_commit_at = Some(committed_at);
+    Ok(())
+}
+
+
+/// Splits the content of a note into prose, code, and math.
+///
+/// The content is read as Markdown, so code blocks of the editor are fenced. Fenced code blocks and inline code
+/// go to the code, `$$` and `\[` display blocks and inline `$`, `$$`, and `\(` spans to the math,
+/// and everything else to the prose.
+fn split_content(content: &str) -> ContentParts {
+    let mut parts = ContentParts::default();
+    let mut block = None;
+
+    for line in delta::to_markdown(content).lines() {
+        let trimmed = line.trim();
+        match (block, trimmed) {
+            // The language of a fence, e.g. "```rust", is not indexed
+            (None, fence) if fence.starts_with("```") => block = Some(Block::Code),
+            (Some(Block::Code), fence) if fence.starts_with("```") => block = None,
+            (None, "$$" | "\\[") => block = Some(Block::Math),
+            (Some(Block::Math), "$$" | "\\]") => block = None,
+            (Some(Block::Code), _) => append_line(&mut parts.code, line),
+            (Some(Block::Math), _) => append_line(&mut parts.math, line),
+            (None, _) => {
+                let mut last_end = 0;
+                for captures in INLINE_SPAN.captures_iter(line) {
+                    let span = captures.get(0).unwrap();
+                    parts.text.push_str(&line[last_end..span.start()]);
+                    parts.text.push(' ');
+                    match captures.get(1) {
+                        Some(code) => append_line(&mut parts.code, code.as_str()),
+                        None => {
+                            let math = (2..=5).find_map(|group| captures.get(group)).map_or("", |math| math.as_str());
+                            append_line(&mut parts.math, math);
+                        },
+                    }
+                    last_end = span.end();
+                }
+                append_line(&mut parts.text, &line[last_end..]);
+            },
+        }
+    }
+
+    parts
+}
+
+
+/// Appends a line to a part of the content.
+fn append_line(part: &mut String, line: &str) {
+    part.push_str(line);
+    part.push('\n');
+}
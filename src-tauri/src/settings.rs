@@ -0,0 +1,120 @@
+// settings.rs
+
+use crate::crypto;
+use crate::local_operations::CONNECTION;
+use rusqlite::{params, OptionalExtension};
+
+
+/// Retrieves the value of a setting from the "settings" table.
+///
+/// # Arguments
+///
+/// * `key` - The key of the setting.
+///
+/// # Returns
+///
+/// Returns `Ok(Some(String))` if the setting exists, `Ok(None)` if it does not, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_setting(key: &str) -> Result<Option<String>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row(
+        "SELECT value FROM settings WHERE key = ?1",
+        params![key],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Stores the value of a setting in the "settings" table, replacing any previous value.
+///
+/// # Arguments
+///
+/// * `key` - The key of the setting.
+/// * `value` - The value of the setting.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setting is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn set_setting(key: &str, value: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Removes a setting from the "settings" table.
+///
+/// # Arguments
+///
+/// * `key` - The key of the setting.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setting is removed or did not exist, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn delete_setting(key: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "DELETE FROM settings WHERE key = ?1",
+        params![key],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Stores a secret, e.g. a password, in the "settings" table, encrypted.
+///
+/// # Arguments
+///
+/// * `key` - The key of the setting.
+/// * `secret` - The secret, stored encrypted with a random nonce as "content:nonce".
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the secret is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the secret cannot be encrypted or if there is an issue with the database connection.
+pub fn set_secret_setting(key: &str, secret: &str) -> Result<(), String> {
+    let (encrypted_secret, nonce_str) = crypto::encrypt_content(secret)?;
+    set_setting(key, &format!("{}:{}", encrypted_secret, nonce_str))
+}
+
+
+/// Retrieves a secret stored with `set_secret_setting`, decrypted.
+///
+/// # Arguments
+///
+/// * `key` - The key of the setting.
+///
+/// # Returns
+///
+/// Returns `Ok(Some(String))` if the secret exists, `Ok(None)` if it does not, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the stored secret is malformed or cannot be decrypted,
+/// or if there is an issue with the database connection.
+pub fn get_secret_setting(key: &str) -> Result<Option<String>, String> {
+    let Some(stored) = get_setting(key)? else {
+        return Ok(None);
+    };
+    let (encrypted_secret, nonce_str) = stored.split_once(':').ok_or(format!("Invalid stored secret {}", key))?;
+    Ok(Some(crypto::decrypt_content(encrypted_secret, nonce_str)?))
+}
@@ -0,0 +1,173 @@
+// settings_profile.rs
+
+use crate::{ capture_bridge, collation, email_operations, features, journal_context, memory_budget, notifier, periodic_notes, presence_operations, proxy, search_index, status_operations, sync, update_operations, usage_operations };
+use crate::models::{SettingsImport, SettingsProfile, SkippedSetting};
+
+
+/// The version of the profile format, increased when a profile could no longer be read by an older version.
+const PROFILE_VERSION: u32 = 1;
+
+
+/// Exports the settings to a JSON file, to set up the application the same way on another machine.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to write.
+///
+/// # Operation
+///
+/// * The profile holds the preferences (sorting language, display name, status workflow, notifications, memory
+///   budgets, protection of the search index, and address of the update manifest), the templates of the periodic
+///   notes, the journal context, the cloud budget, the remotes (automatic sync, email import and sending, and capture
+///   bridge), the proxy, and the experimental features.
+/// * The secrets are never exported: the passwords, tokens, and API keys of the remotes and the proxy, and the keys
+///   of the vault.
+///   Neither are the settings of this machine only, such as its device ID, its progress through the remotes, or its
+///   opt-in to the usage metrics.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file is written successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if a stored setting is malformed, if there is an issue with the database
+/// connection, or if the file cannot be written.
+pub fn export_settings(path: &str) -> Result<(), String> {
+    let notification_settings = notifier::get_notification_settings();
+    let profile = SettingsProfile {
+        version: PROFILE_VERSION,
+        exported_at: chrono::Utc::now().timestamp(),
+        locale: Some(collation::get_locale()?),
+        display_name: presence_operations::get_display_name()?,
+        status_workflow: Some(status_operations::get_status_workflow()?),
+        periodic_notes: Some(periodic_notes::get_periodic_note_settings()?),
+        journal_context: journal_context::get_journal_context()?.config,
+        notifications_muted: Some(notification_settings.muted),
+        quiet_hours: Some(notification_settings.quiet_hours),
+        memory_budgets: Some(memory_budget::get_memory_budgets()),
+        index_protection: Some(search_index::get_index_protection()?),
+        update_manifest_url: update_operations::get_update_manifest_url()?,
+        cloud_budget: Some(usage_operations::get_cloud_budget()?),
+        sync: sync::get_sync_settings()?,
+        email_ingestion: email_operations::get_email_ingestion()?.config,
+        email_smtp: email_operations::get_smtp_settings()?.config,
+        capture_bridge: capture_bridge::get_capture_bridge()?.config,
+        proxy: proxy::get_proxy_settings()?.config,
+        features: features::list_features().into_iter().map(|feature| (feature.id, feature.enabled)).collect(),
+    };
+
+    let json = serde_json::to_string_pretty(&profile).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+
+/// Imports the settings exported with `export_settings`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the profile.
+///
+/// # Operation
+///
+/// * Each setting of the profile is applied as if it was set by the user, so it is checked the same way and takes
+///   effect right away. The settings missing from the profile are left as they are.
+/// * A setting that cannot be applied is skipped with the reason, and the others are still applied, e.g. a remote
+///   enabled in the profile whose secret was not set on this machine yet, or a status workflow without a status
+///   some local notes have. The secrets kept on this machine are kept.
+///
+/// # Returns
+///
+/// Returns a `SettingsImport` with the names of the applied settings, and the skipped ones with the reason.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read, if it is not a settings profile, if it was
+/// exported by a newer version of the application, or if there is an issue with the database connection.
+pub fn import_settings(path: &str) -> Result<SettingsImport, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let profile: SettingsProfile = serde_json::from_str(&json).map_err(|e| format!("Invalid settings profile: {}", e))?;
+    if profile.version > PROFILE_VERSION {
+        return Err(format!("The settings profile was exported by a newer version of the application (version {})", profile.version));
+    }
+
+    let mut import = SettingsImport { imported: Vec::new(), skipped: Vec::new() };
+    if let Some(locale) = profile.locale {
+        apply(&mut import, "locale", collation::set_locale(&locale));
+    }
+    if let Some(display_name) = profile.display_name {
+        apply(&mut import, "display_name", presence_operations::set_display_name(&display_name));
+    }
+    if let Some(status_workflow) = profile.status_workflow {
+        apply(&mut import, "status_workflow", status_operations::set_status_workflow(status_workflow));
+    }
+    if let Some(periodic_notes) = profile.periodic_notes {
+        apply(&mut import, "periodic_notes", periodic_notes::set_periodic_note_settings(periodic_notes));
+    }
+    if let Some(journal_context) = profile.journal_context {
+        apply(&mut import, "journal_context", journal_context::set_journal_context(journal_context, None));
+    }
+    if let Some(muted) = profile.notifications_muted {
+        apply(&mut import, "notifications_muted", notifier::set_notifications_muted(muted));
+    }
+    if let Some(quiet_hours) = profile.quiet_hours {
+        apply(&mut import, "quiet_hours", notifier::set_quiet_hours(quiet_hours));
+    }
+    if let Some(memory_budgets) = profile.memory_budgets {
+        apply(&mut import, "memory_budgets", memory_budget::set_memory_budgets(memory_budgets));
+    }
+    if let Some(index_protection) = profile.index_protection {
+        apply(&mut import, "index_protection", search_index::set_index_protection(index_protection));
+    }
+    if let Some(update_manifest_url) = profile.update_manifest_url {
+        apply(&mut import, "update_manifest_url", update_operations::set_update_manifest_url(&update_manifest_url));
+    }
+    if let Some(cloud_budget) = profile.cloud_budget {
+        apply(&mut import, "cloud_budget", usage_operations::set_cloud_budget(cloud_budget));
+    }
+    if let Some(sync) = profile.sync {
+        apply(&mut import, "sync", sync::set_sync_settings(sync));
+    }
+    if let Some(email_ingestion) = profile.email_ingestion {
+        let outcome = if email_ingestion.enabled && !email_operations::get_email_ingestion()?.has_password {
+            Err("Set the password of the mailbox on this machine first".to_string())
+        } else {
+            email_operations::set_email_ingestion(email_ingestion, None)
+        };
+        apply(&mut import, "email_ingestion", outcome);
+    }
+    if let Some(email_smtp) = profile.email_smtp {
+        apply(&mut import, "email_smtp", email_operations::set_smtp_settings(email_smtp, None));
+    }
+    if let Some(capture_bridge) = profile.capture_bridge {
+        let outcome = if capture_bridge.enabled && !capture_bridge::get_capture_bridge()?.has_token {
+            Err("Set the token of the bridge on this machine first".to_string())
+        } else {
+            capture_bridge::set_capture_bridge(capture_bridge, None)
+        };
+        apply(&mut import, "capture_bridge", outcome);
+    }
+    if let Some(proxy) = profile.proxy {
+        let outcome = if proxy.enabled && proxy.username.is_some() && !proxy::get_proxy_settings()?.has_password {
+            Err("Set the password of the proxy on this machine first".to_string())
+        } else {
+            proxy::set_proxy_settings(proxy, None)
+        };
+        apply(&mut import, "proxy", outcome);
+    }
+    for (id, enabled) in profile.features {
+        let outcome = features::set_feature_enabled(&id, enabled);
+        apply(&mut import, &format!("feature:{}", id), outcome);
+    }
+
+    Ok(import)
+}
+
+
+/// Records whether a setting of a profile was applied.
+fn apply(import: &mut SettingsImport, setting: &str, outcome: Result<(), String>) {
+    match outcome {
+        Ok(()) => import.imported.push(setting.to_string()),
+        Err(reason) => import.skipped.push(SkippedSetting { setting: setting.to_string(), reason }),
+    }
+}
@@ -0,0 +1,148 @@
+// shutdown.rs
+
+use crate::{ tasks, vault_lock, local_operations::CONNECTION };
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+
+/// The time given to the window to save the note being edited.
+const DRAFTS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The time given to the S3 writes and the background tasks in progress to finish.
+const PENDING_WORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The commands writing to S3, which are waited for before exiting.
+const WRITING_COMMANDS: [&str; 6] = [
+    "create_bucket",
+    "delete_bucket",
+    "upload_note_to_bucket",
+    "update_bucket_note",
+    "delete_bucket_note",
+    "delete_bucket_notes",
+];
+
+
+/// Whether the application is shutting down, in which case no new S3 write is started.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// The number of S3 writes in progress.
+static PENDING_WRITES: AtomicUsize = AtomicUsize::new(0);
+
+
+lazy_static! {
+    /// Whether the window saved the note being edited.
+    static ref DRAFTS_FLUSHED: Mutex<bool> = Mutex::new(false);
+
+    /// Wakes up the shutdown when the window saved the note being edited.
+    static ref DRAFTS_FLUSHED_CHANGED: Condvar = Condvar::new();
+}
+
+
+/// An S3 write in progress, which is counted until it is dropped.
+pub struct PendingWrite;
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        PENDING_WRITES.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Tracks a command if it writes to S3, so the shutdown waits for it.
+///
+/// # Arguments
+///
+/// * `command` - The name of the command.
+///
+/// # Returns
+///
+/// Returns a `PendingWrite` to keep until the command finishes if it writes to S3, or `None` otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if the command writes to S3 and the application is shutting down.
+pub fn track_write(command: &str) -> Result<Option<PendingWrite>, String> {
+    if !WRITING_COMMANDS.contains(&command) {
+        return Ok(None);
+    }
+
+    // Count the write before checking the flag, so the shutdown cannot miss it
+    PENDING_WRITES.fetch_add(1, Ordering::SeqCst);
+    let write = PendingWrite;
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Err("The application is shutting down".to_string());
+    }
+
+    Ok(Some(write))
+}
+
+
+/// Records that the window saved the note being edited, in answer to the "flush-drafts" event.
+pub fn drafts_flushed() {
+    *DRAFTS_FLUSHED.lock().unwrap() = true;
+    DRAFTS_FLUSHED_CHANGED.notify_all();
+}
+
+
+/// Shuts the application down once the pending writes are flushed, when the window is closed or the application exits.
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+///
+/// # Operation
+///
+/// * No new S3 write is started from now on. Calling it again while shutting down does nothing.
+/// * The "flush-drafts" event is sent to the window, which saves the note being edited and answers with the
+///   "drafts_flushed" command. It is given 3 seconds.
+/// * The S3 writes in progress are given 10 seconds to finish, the local notes being kept either way.
+/// * No new background task is started, and the running ones, such as a reindex committing the search index, are given
+///   the rest of the 10 seconds to finish. The tasks still queued or running are saved and resumed on the next start
+///   (see `tasks::start_workers`). The search index itself is rebuilt from the database on the next start.
+/// * The application exits once the last database write finished, releasing the lock of the vault (see `vault_lock`).
+pub fn begin(app: AppHandle) {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        match app.emit_all("flush-drafts", ()) {
+            Ok(_) => wait_for_drafts(),
+            Err(e) => eprintln!("Failed to ask the window to save the drafts: {}", e),
+        }
+
+        let deadline = Instant::now() + PENDING_WORK_TIMEOUT;
+        wait_for_writes(deadline);
+        tasks::shut_down(deadline);
+
+        // Exit between two database writes
+        let _conn = CONNECTION.lock().unwrap();
+        vault_lock::release();
+        app.exit(0);
+    });
+}
+
+
+/// Waits for the window to save the note being edited, for up to 3 seconds.
+fn wait_for_drafts() {
+    let flushed = DRAFTS_FLUSHED.lock().unwrap();
+    let (flushed, _) = DRAFTS_FLUSHED_CHANGED.wait_timeout_while(flushed, DRAFTS_TIMEOUT, |flushed| !*flushed).unwrap();
+    if !*flushed {
+        eprintln!("The window did not save the drafts in time");
+    }
+}
+
+
+/// Waits for the S3 writes in progress to finish, until the deadline.
+fn wait_for_writes(deadline: Instant) {
+    while PENDING_WRITES.load(Ordering::SeqCst) > 0 {
+        if Instant::now() >= deadline {
+            eprintln!("Exiting with {} S3 writes in progress", PENDING_WRITES.load(Ordering::SeqCst));
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
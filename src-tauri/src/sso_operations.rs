@@ -0,0 +1,383 @@
+// sso_operations.rs
+
+use crate::crypto;
+use crate::local_operations::CONNECTION;
+use crate::notifier;
+use crate::proxy;
+use crate::models::{AppError, SsoDeviceAuthorization};
+use aws_credential_types::provider::{ error::CredentialsError, future, ProvideCredentials };
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_ssooidc::operation::create_token::CreateTokenError;
+use lazy_static::lazy_static;
+use rusqlite::{params, OptionalExtension};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+/// The grant type used when polling for a token with the device authorization flow.
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Role credentials are refreshed this many seconds before they actually expire.
+const CREDENTIALS_EXPIRY_MARGIN: u64 = 300;
+
+
+lazy_static! {
+    /// In-memory cache of the role credentials obtained from the cached SSO access token.
+    ///
+    /// The credentials are short-lived, so they are never persisted: they are fetched again
+    /// with `GetRoleCredentials` once they are close to expiring.
+    static ref ROLE_CREDENTIALS: Mutex<Option<Credentials>> = Mutex::new(None);
+}
+
+
+/// The generation of the SSO login, increased when a login starts and when the user signs out, so the polling of an
+/// older login stops without touching the session that replaced it.
+static LOGIN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+
+/// The credentials provider of the S3 client while the AWS SSO session cannot provide role credentials, e.g. once
+/// it expired. Every request fails with the reason, rather than being sent with other credentials.
+#[derive(Debug)]
+pub struct UnavailableCredentials(pub String);
+
+impl ProvideCredentials for UnavailableCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::ready(Err(CredentialsError::not_loaded(self.0.clone())))
+    }
+}
+
+
+/// An AWS SSO session as stored in the "sso_session" table.
+struct SsoSession {
+    region: String,
+    account_id: String,
+    role_name: String,
+    /// The encrypted access token and its nonce, once the login is approved (see `SsoSession::access_token`).
+    encrypted_token: Option<(String, String)>,
+    expires_at: Option<i64>,
+}
+
+
+impl SsoSession {
+    /// Decrypts the access token of the session.
+    ///
+    /// # Returns
+    ///
+    /// Returns the access token, or `None` if the login is not approved yet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the vault is locked or the token cannot be decrypted.
+    fn access_token(&self) -> Result<Option<String>, AppError> {
+        self.encrypted_token.as_ref()
+            .map(|(encrypted_token, nonce_str)| crypto::decrypt_content(encrypted_token, nonce_str))
+            .transpose()
+    }
+}
+
+
+/// Starts the AWS SSO (IAM Identity Center) device authorization flow.
+///
+/// # Parameters
+///
+/// * `start_url` - The AWS access portal URL of the organization.
+/// * `region` - The region of the IAM Identity Center instance.
+/// * `account_id` - The AWS account to obtain role credentials for.
+/// * `role_name` - The name of the permission set to assume in the account.
+///
+/// # Operation
+///
+/// * A public OIDC client is registered with the IAM Identity Center instance.
+/// * A device authorization is started for the `start_url`.
+/// * The session parameters are saved in the "sso_session" table, replacing any previous session.
+/// * A background task polls for the access token until the user approves the request in the browser
+///   or the device code expires. Once received, the token is encrypted and cached in the "sso_session" table. The polling of a
+///   previous login stops, and never caches its token in the new session.
+///
+/// # Returns
+///
+/// * If the operation is successful, a `SsoDeviceAuthorization` is returned with the code the user
+///   must confirm and the URL to open.
+/// * If the operation fails, an `AppError` describing the error is returned, of the kind of the failure of the
+///   request (see `AppError::s3`).
+///
+/// # Errors
+///
+/// This function will return an error if the AWS SDK encounters an error when registering the client
+/// or starting the device authorization, or if the session cannot be saved.
+pub async fn login_sso(start_url: &str, region: &str, account_id: &str, role_name: &str) -> Result<SsoDeviceAuthorization, AppError> {
+    // The OIDC API is called anonymously, so no credentials are loaded
+    let myconfig = proxy::apply_to_aws_config(aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(aws_config::Region::new(region.to_string()))
+        .no_credentials()
+        .load()
+        .await);
+    let oidc_client = aws_sdk_ssooidc::Client::new(&myconfig);
+
+    // Register the application as a public client
+    let registration = oidc_client.register_client()
+        .client_name("custom_notes")
+        .client_type("public")
+        .send()
+        .await
+        .map_err(|e| AppError::s3_with(&e, "Failed to register SSO client"))?;
+    let client_id = registration.client_id().unwrap_or_default().to_string();
+    let client_secret = registration.client_secret().unwrap_or_default().to_string();
+
+    // Start the device authorization
+    let authorization = oidc_client.start_device_authorization()
+        .client_id(&client_id)
+        .client_secret(&client_secret)
+        .start_url(start_url)
+        .send()
+        .await
+        .map_err(|e| AppError::s3_with(&e, "Failed to start SSO device authorization"))?;
+    let device_code = authorization.device_code().unwrap_or_default().to_string();
+
+    // Save the session parameters, the token is filled in once the user approves the request
+    let generation = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO sso_session (id, start_url, region, account_id, role_name, access_token, access_token_nonce, expires_at) VALUES (1, ?1, ?2, ?3, ?4, NULL, NULL, NULL)",
+            params![start_url, region, account_id, role_name],
+        )?;
+        LOGIN_GENERATION.fetch_add(1, Ordering::SeqCst) + 1
+    };
+    ROLE_CREDENTIALS.lock().unwrap().take();
+
+    // Poll for the access token in the background
+    let interval = authorization.interval().max(1) as u64;
+    let expires_in = authorization.expires_in();
+    tokio::spawn(poll_for_token(oidc_client, client_id, client_secret, device_code, generation, interval, expires_in as u64));
+
+    Ok(SsoDeviceAuthorization {
+        user_code: authorization.user_code().unwrap_or_default().to_string(),
+        verification_uri: authorization.verification_uri().unwrap_or_default().to_string(),
+        verification_uri_complete: authorization.verification_uri_complete().map(|s| s.to_string()),
+        expires_in,
+    })
+}
+
+
+/// Polls the OIDC `CreateToken` API until the device authorization is approved, denied, or expired.
+///
+/// # Parameters
+///
+/// * `oidc_client` - The OIDC client used to start the device authorization.
+/// * `client_id` - The identifier of the registered client.
+/// * `client_secret` - The secret of the registered client.
+/// * `device_code` - The device code returned by `StartDeviceAuthorization`.
+/// * `generation` - The generation of the login (see `LOGIN_GENERATION`). The polling stops once another login
+///   starts or the user signs out, and the token is only cached if the login is still the current one.
+/// * `interval` - The number of seconds to wait between two polls.
+/// * `expires_in` - The number of seconds after which the device code expires.
+async fn poll_for_token(oidc_client: aws_sdk_ssooidc::Client, client_id: String, client_secret: String, device_code: String, generation: u64, mut interval: u64, expires_in: u64) {
+    let deadline = SystemTime::now() + Duration::from_secs(expires_in);
+
+    while SystemTime::now() < deadline {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        if LOGIN_GENERATION.load(Ordering::SeqCst) != generation {
+            return;
+        }
+
+        let token_result = oidc_client.create_token()
+            .client_id(&client_id)
+            .client_secret(&client_secret)
+            .grant_type(DEVICE_CODE_GRANT_TYPE)
+            .device_code(&device_code)
+            .send()
+            .await;
+
+        match token_result {
+            Ok(token) => {
+                let (encrypted_token, nonce_str) = match crypto::encrypt_content(token.access_token().unwrap_or_default()) {
+                    Ok(encrypted) => encrypted,
+                    Err(e) => {
+                        eprintln!("Failed to encrypt SSO token: {}", e);
+                        return;
+                    },
+                };
+                let expires_at = chrono::Utc::now().timestamp() + token.expires_in() as i64;
+
+                // The generation is checked with the database locked, since a login or a logout changes it with the
+                // database locked too
+                let conn = CONNECTION.lock().unwrap();
+                if LOGIN_GENERATION.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                if let Err(e) = conn.execute(
+                    "UPDATE sso_session SET access_token = ?1, access_token_nonce = ?2, expires_at = ?3 WHERE id = 1",
+                    params![encrypted_token, nonce_str, expires_at],
+                ) {
+                    eprintln!("Failed to cache SSO token: {}", e);
+                    return;
+                }
+
+                // Send a desktop notification
+                notifier::notify("AWS SSO login complete", "You are now signed in with AWS SSO.");
+
+                return;
+            },
+            Err(err) => match err.as_service_error() {
+                // The user has not approved the request yet
+                Some(CreateTokenError::AuthorizationPendingException(_)) => continue,
+                // The server asks to poll less frequently
+                Some(CreateTokenError::SlowDownException(_)) => interval += 5,
+                _ => {
+                    eprintln!("SSO login failed: {}", err);
+                    break;
+                },
+            },
+        }
+    }
+
+    // Send a desktop notification
+    notifier::notify("AWS SSO login failed", "The SSO login request was denied or expired.");
+}
+
+
+/// Signs out of the current AWS SSO session.
+///
+/// # Operation
+///
+/// * If an access token is cached, the session is ended with the SSO `Logout` API. Failures are ignored
+///   since the local session is removed anyway.
+/// * The "sso_session" table and the cached role credentials are cleared, and a login still waiting for its token
+///   stops polling.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the session is removed successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub async fn logout_sso() -> Result<(), String> {
+    if let Some(session) = load_session()? {
+        // A token that cannot be decrypted is not ended, the local session is removed anyway
+        if let Some(access_token) = session.access_token().unwrap_or_else(|e| {
+            eprintln!("Failed to decrypt SSO token: {}", e);
+            None
+        }) {
+            let myconfig = proxy::apply_to_aws_config(aws_config::defaults(aws_config::BehaviorVersion::latest())
+                .region(aws_config::Region::new(session.region))
+                .no_credentials()
+                .load()
+                .await);
+            let sso_client = aws_sdk_sso::Client::new(&myconfig);
+
+            if let Err(e) = sso_client.logout().access_token(access_token).send().await {
+                eprintln!("SSO logout request failed: {}", e);
+            }
+        }
+    }
+
+    {
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute("DELETE FROM sso_session", []).map_err(|e| e.to_string())?;
+        LOGIN_GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+    ROLE_CREDENTIALS.lock().unwrap().take();
+
+    // Send a desktop notification
+    notifier::notify("AWS SSO logout", "You are now signed out of AWS SSO.");
+
+    Ok(())
+}
+
+
+/// Returns the role credentials of the cached AWS SSO session, if any.
+///
+/// # Operation
+///
+/// * If role credentials are cached in memory and are not about to expire, they are returned.
+/// * Otherwise, if a valid SSO access token is cached, new role credentials are fetched with the SSO
+///   `GetRoleCredentials` API and cached in memory.
+///
+/// # Returns
+///
+/// * `Ok(Some(Credentials))` if the user is signed in with AWS SSO.
+/// * `Ok(None)` if there is no SSO session, in which case the default credentials provider chain should be used.
+///
+/// # Errors
+///
+/// This function will return an error if there is an SSO session that cannot provide credentials: the login is not
+/// approved yet, the access token expired, or `GetRoleCredentials` failed. The requests must then fail rather than
+/// use the default credentials provider chain, which may hold other credentials (see `UnavailableCredentials`).
+pub async fn sso_credentials() -> Result<Option<Credentials>, String> {
+    if let Some(credentials) = ROLE_CREDENTIALS.lock().unwrap().as_ref() {
+        let still_valid = credentials.expiry()
+            .map(|expiry| expiry > SystemTime::now() + Duration::from_secs(CREDENTIALS_EXPIRY_MARGIN))
+            .unwrap_or(true);
+        if still_valid {
+            return Ok(Some(credentials.clone()));
+        }
+    }
+
+    let Some(session) = load_session().map_err(|e| format!("Failed to load the AWS SSO session: {}", e))? else {
+        return Ok(None);
+    };
+    let access_token = session.access_token().map_err(|e| format!("Failed to decrypt the AWS SSO token: {}", e))?;
+    let Some(expires_at) = session.expires_at else {
+        return Err("The AWS SSO login is not approved yet: confirm the code in the browser, or sign out of AWS SSO".to_string());
+    };
+    // The plaintext tokens cached by older versions were dropped, leaving their session expired (see `migrations`)
+    let access_token = match access_token {
+        Some(access_token) if expires_at > chrono::Utc::now().timestamp() => access_token,
+        _ => return Err("The AWS SSO session expired: sign in with AWS SSO again".to_string()),
+    };
+
+    let myconfig = proxy::apply_to_aws_config(aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(aws_config::Region::new(session.region))
+        .no_credentials()
+        .load()
+        .await);
+    let sso_client = aws_sdk_sso::Client::new(&myconfig);
+
+    let output = sso_client.get_role_credentials()
+        .account_id(session.account_id)
+        .role_name(session.role_name)
+        .access_token(access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to get the AWS SSO role credentials: {}", e))?;
+    let missing = || "The AWS SSO role credentials are incomplete".to_string();
+    let role_credentials = output.role_credentials().ok_or_else(missing)?;
+
+    let credentials = Credentials::new(
+        role_credentials.access_key_id().ok_or_else(missing)?,
+        role_credentials.secret_access_key().ok_or_else(missing)?,
+        role_credentials.session_token().map(|s| s.to_string()),
+        Some(UNIX_EPOCH + Duration::from_millis(role_credentials.expiration() as u64)),
+        "sso",
+    );
+    *ROLE_CREDENTIALS.lock().unwrap() = Some(credentials.clone());
+
+    Ok(Some(credentials))
+}
+
+
+/// Loads the AWS SSO session from the "sso_session" table.
+///
+/// # Returns
+///
+/// Returns `Ok(Some(SsoSession))` if a session is stored, `Ok(None)` otherwise, or `Err(String)` if an error occurs.
+fn load_session() -> Result<Option<SsoSession>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row(
+        "SELECT region, account_id, role_name, access_token, access_token_nonce, expires_at FROM sso_session WHERE id = 1",
+        [],
+        |row| Ok(SsoSession {
+            region: row.get(0)?,
+            account_id: row.get(1)?,
+            role_name: row.get(2)?,
+            encrypted_token: row.get::<_, Option<String>>(3)?.zip(row.get::<_, Option<String>>(4)?),
+            expires_at: row.get(5)?,
+        }),
+    ).optional().map_err(|e| e.to_string())
+}
+
@@ -0,0 +1,68 @@
+// startup.rs
+
+use crate::models::StartupTiming;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use std::time::Instant;
+
+
+lazy_static! {
+    /// The instant the application started, set by `mark_process_start`.
+    static ref PROCESS_START: Instant = Instant::now();
+
+    /// The timings of the startup phases, in the order they finished.
+    static ref STARTUP_TIMINGS: Mutex<Vec<StartupTiming>> = Mutex::new(Vec::new());
+}
+
+
+/// Marks the start of the application, which the timings of the startup phases are relative to.
+///
+/// It should be called first thing in `main`.
+pub fn mark_process_start() {
+    lazy_static::initialize(&PROCESS_START);
+}
+
+
+/// Records the timing of a startup phase that has just finished.
+///
+/// # Arguments
+///
+/// * `phase` - The name of the phase, e.g. "database".
+/// * `started` - The instant the phase started.
+pub fn record(phase: &str, started: Instant) {
+    let timing = StartupTiming {
+        phase: phase.to_string(),
+        started_at_ms: started.saturating_duration_since(*PROCESS_START).as_millis() as u64,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    STARTUP_TIMINGS.lock().unwrap().push(timing);
+}
+
+
+/// Runs a startup phase and records its timing.
+///
+/// # Arguments
+///
+/// * `phase` - The name of the phase.
+/// * `run` - The function running the phase.
+///
+/// # Returns
+///
+/// Returns the result of the phase.
+pub fn time<T>(phase: &str, run: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = run();
+    record(phase, started);
+    result
+}
+
+
+/// Retrieves the timings of the startup phases, to diagnose a slow cold start.
+///
+/// # Returns
+///
+/// Returns the phases that finished so far, with their start relative to the start of the application and
+/// their duration, in milliseconds.
+pub fn get_startup_timings() -> Vec<StartupTiming> {
+    STARTUP_TIMINGS.lock().unwrap().clone()
+}
@@ -0,0 +1,94 @@
+// stats_operations.rs
+
+use crate::{ tasks, local_operations::CONNECTION, models::StatsRange, models::TaskPriority, models::VaultStats, tasks::TaskContext };
+use rusqlite::params;
+use std::time::Duration;
+
+
+/// Starts taking a snapshot of the vault statistics every day.
+///
+/// # Operation
+///
+/// A thread submits a snapshot task right away, then again after each midnight (UTC) while the application runs.
+pub fn start_daily_snapshots() {
+    std::thread::spawn(|| loop {
+        tasks::submit_unless_queued(tasks::STATS_SNAPSHOT, "Record the vault statistics", TaskPriority::Low);
+
+        let now = chrono::Utc::now();
+        let next_midnight = (now.date_naive() + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc();
+        std::thread::sleep((next_midnight - now).to_std().unwrap_or(Duration::from_secs(60 * 60)));
+    });
+}
+
+
+/// Runs the snapshot task, which records the statistics of the vault for the current day.
+///
+/// A snapshot taken earlier the same day is replaced, so each day keeps its latest statistics.
+pub fn run_snapshot(_context: &TaskContext) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let (note_count, secure_count, total_size): (i64, i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(secure), 0), COALESCE(SUM(LENGTH(title) + LENGTH(content)), 0) FROM notes WHERE deleted_at IS NULL",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).map_err(|e| e.to_string())?;
+    let (attachment_count, attachment_size): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM attachments",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO vault_stats (day, note_count, secure_count, total_size, attachment_count, attachment_size, recorded_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![chrono::Utc::now().format("%Y-%m-%d").to_string(), note_count, secure_count, total_size,
+            attachment_count, attachment_size, chrono::Utc::now().timestamp()],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+
+/// Retrieves the daily snapshots of the vault statistics, to chart the growth of the vault.
+///
+/// # Arguments
+///
+/// * `range` - The period covered, ending today. Defaults to the last month.
+///
+/// # Returns
+///
+/// Returns the snapshots of the days of the period, oldest first. Days the application did not run have no snapshot.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_stats_history(range: Option<StatsRange>) -> Result<Vec<VaultStats>, String> {
+    let days = match range.unwrap_or(StatsRange::Month) {
+        StatsRange::Week => Some(7),
+        StatsRange::Month => Some(30),
+        StatsRange::Quarter => Some(91),
+        StatsRange::Year => Some(365),
+        StatsRange::All => None,
+    };
+    // Days sort as text, so an empty day matches every snapshot
+    let since = days
+        .map(|days| (chrono::Utc::now() - chrono::Duration::days(days - 1)).format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT day, note_count, secure_count, total_size, attachment_count, attachment_size FROM vault_stats
+        WHERE day >= ?1 ORDER BY day"
+    ).map_err(|e| e.to_string())?;
+    let stats_iter = stmt.query_map(params![since], |row| {
+        Ok(VaultStats {
+            day: row.get(0)?,
+            note_count: row.get(1)?,
+            secure_count: row.get(2)?,
+            total_size: row.get(3)?,
+            attachment_count: row.get(4)?,
+            attachment_size: row.get(5)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    stats_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
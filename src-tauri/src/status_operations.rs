@@ -0,0 +1,194 @@
+// status_operations.rs
+
+use crate::{ search_index, settings, local_operations::CONNECTION, models::AppError, models::StatusCount, models::StatusWorkflow };
+use rusqlite::{params, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+
+
+/// The settings key under which the workflow is stored, as JSON.
+const WORKFLOW_SETTING: &str = "status_workflow";
+
+
+/// Returns the workflow used until another one is configured: idea → draft → done, with a way back at each step.
+fn default_workflow() -> StatusWorkflow {
+    StatusWorkflow {
+        statuses: vec!["idea".to_string(), "draft".to_string(), "done".to_string()],
+        transitions: HashMap::from([
+            ("idea".to_string(), vec!["draft".to_string()]),
+            ("draft".to_string(), vec!["idea".to_string(), "done".to_string()]),
+            ("done".to_string(), vec!["draft".to_string()]),
+        ]),
+    }
+}
+
+
+/// Retrieves the workflow of the note statuses.
+///
+/// # Returns
+///
+/// Returns the configured `StatusWorkflow`, or the default one if none is configured.
+///
+/// # Errors
+///
+/// This function will return an error if the stored workflow is malformed or if there is an issue with the database connection.
+pub fn get_status_workflow() -> Result<StatusWorkflow, String> {
+    match settings::get_setting(WORKFLOW_SETTING)? {
+        Some(workflow) => serde_json::from_str(&workflow).map_err(|e| format!("Invalid status workflow: {}", e)),
+        None => Ok(default_workflow()),
+    }
+}
+
+
+/// Configures the workflow of the note statuses.
+///
+/// # Arguments
+///
+/// * `workflow` - The statuses, in the order shown on a board, and the statuses each of them can move to.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the workflow is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the workflow has no status, a duplicate or blank status, or a transition
+/// between unknown statuses, if a removed status is still used by notes, or if there is an issue with the database connection.
+pub fn set_status_workflow(workflow: StatusWorkflow) -> Result<(), String> {
+    if workflow.statuses.is_empty() {
+        return Err("A workflow should have at least one status".to_string());
+    }
+    let mut statuses = HashSet::new();
+    for status in &workflow.statuses {
+        if status.trim().is_empty() || status.chars().count() > 50 {
+            return Err("Statuses should be between 1 and 50 characters".to_string());
+        }
+        if !statuses.insert(status.as_str()) {
+            return Err(format!("Duplicate status '{}'", status));
+        }
+    }
+    for (from, targets) in &workflow.transitions {
+        if let Some(unknown) = std::iter::once(from).chain(targets).find(|status| !statuses.contains(status.as_str())) {
+            return Err(format!("Unknown status '{}' in transitions", unknown));
+        }
+    }
+
+    // Notes cannot be left in a status that no longer exists
+    for (status, count) in count_statuses()? {
+        if let Some(status) = status.filter(|status| !statuses.contains(status.as_str())) {
+            return Err(format!("Status '{}' is still used by {} notes", status, count));
+        }
+    }
+
+    let workflow = serde_json::to_string(&workflow).map_err(|e| e.to_string())?;
+    settings::set_setting(WORKFLOW_SETTING, &workflow)
+}
+
+
+/// Checks that a status is part of the workflow.
+///
+/// # Arguments
+///
+/// * `status` - The status to check.
+///
+/// # Errors
+///
+/// This function will return an error if the status is unknown or if the workflow cannot be retrieved.
+pub fn check_status(status: &str) -> Result<(), String> {
+    if get_status_workflow()?.statuses.iter().any(|known| known == status) {
+        Ok(())
+    } else {
+        Err(format!("Unknown status '{}'", status))
+    }
+}
+
+
+/// Moves a local note to another status of the workflow.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+/// * `status` - The new status, or `None` to clear it.
+///
+/// # Operation
+///
+/// * A note without a status can be given any status of the workflow, and the status of a note can always be cleared.
+/// * Otherwise the workflow must allow moving from the current status to the new one.
+/// * The status is uploaded as object metadata with the note (see `s3_operations::upload_note_to_bucket`).
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the status is updated successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if the status is unknown, if the transition is
+/// not allowed, or if there is an issue with the database connection.
+pub fn set_note_status(id: i64, status: Option<String>) -> Result<(), AppError> {
+    let workflow = get_status_workflow()?;
+
+    let conn = CONNECTION.lock().unwrap();
+    let current: Option<String> = conn.query_row(
+        "SELECT status FROM notes WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+    ).optional()?.ok_or(AppError::NotFound("Note not found".to_string()))?;
+
+    if let Some(status) = &status {
+        if !workflow.statuses.contains(status) {
+            return Err(AppError::Validation(format!("Unknown status '{}'", status)));
+        }
+        if let Some(current) = current.as_ref().filter(|current| *current != status) {
+            let allowed = workflow.transitions.get(current).is_some_and(|targets| targets.contains(status));
+            if !allowed {
+                return Err(AppError::Validation(format!("A note cannot move from '{}' to '{}'", current, status)));
+            }
+        }
+    }
+
+    conn.execute(
+        "UPDATE notes SET status = ?1 WHERE id = ?2",
+        params![status, id],
+    )?;
+    search_index::schedule_reindex(id);
+
+    Ok(())
+}
+
+
+/// Counts the local notes in each status, e.g. for a dashboard.
+///
+/// # Returns
+///
+/// Returns the count of each status of the workflow in order, including empty ones, followed by the count of the
+/// notes without a status.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_status_counts() -> Result<Vec<StatusCount>, String> {
+    let workflow = get_status_workflow()?;
+    let counts = count_statuses()?;
+
+    let mut status_counts: Vec<StatusCount> = workflow.statuses.into_iter()
+        .map(|status| StatusCount {
+            count: counts.get(&Some(status.clone())).copied().unwrap_or(0),
+            status: Some(status),
+        })
+        .collect();
+    status_counts.push(StatusCount {
+        status: None,
+        count: counts.get(&None).copied().unwrap_or(0),
+    });
+
+    Ok(status_counts)
+}
+
+
+/// Counts the local notes by status, `None` standing for the notes without a status.
+fn count_statuses() -> Result<HashMap<Option<String>, i64>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT status, COUNT(*) FROM notes WHERE deleted_at IS NULL GROUP BY status").map_err(|e| e.to_string())?;
+    let count_iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).map_err(|e| e.to_string())?;
+
+    count_iter.collect::<Result<HashMap<_, _>, _>>().map_err(|e| e.to_string())
+}
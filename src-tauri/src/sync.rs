@@ -0,0 +1,848 @@
+// sync.rs
+
+use crate::{ alias_operations, bucket_mirror, key_management, local_operations, note_events, notebook_operations, notifier, plain_sync, s3_operations, search_index, settings, tag_operations, usage_operations, version_operations, local_operations::CONNECTION };
+use crate::models::{ConflictStrategy, Note, SkippedNote, SyncConflict, SyncSettings, SyncSummary, SyncedNote};
+use aws_sdk_s3 as s3;
+use lazy_static::lazy_static;
+use rusqlite::{params, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+
+/// The settings key under which the automatic sync configuration is stored, as JSON.
+const CONFIG_SETTING: &str = "sync";
+
+/// How often, in minutes, the configuration is read again while the automatic sync is disabled.
+const DISABLED_CHECK_MINUTES: u32 = 5;
+
+/// The reason given to the version of a local note saved before the sync changes or deletes it.
+const SNAPSHOT_REASON: &str = "sync";
+
+/// The suffix of the title of the local copy kept apart when a conflict is resolved by keeping both copies.
+const CONFLICT_COPY_SUFFIX: &str = " (local copy)";
+
+
+lazy_static! {
+    /// The buckets being synced, so a bucket is never synced twice at the same time.
+    static ref SYNCING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+
+/// A note of a bucket, as mirrored by `bucket_mirror`.
+struct RemoteNote {
+    key: String,
+    metadata: HashMap<String, String>,
+    content: String,
+    updated_at: i64,
+}
+
+
+/// What the sync does with a note, given the local and the remote copies.
+enum Action {
+    Unchanged,
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    Conflict,
+}
+
+
+/// Marks a bucket as being synced until it is dropped.
+struct SyncGuard(String);
+
+impl SyncGuard {
+    fn acquire(bucket_name: &str) -> Result<SyncGuard, String> {
+        if !SYNCING.lock().unwrap().insert(bucket_name.to_string()) {
+            return Err("The bucket is already being synced".to_string());
+        }
+        Ok(SyncGuard(bucket_name.to_string()))
+    }
+}
+
+impl Drop for SyncGuard {
+    fn drop(&mut self) {
+        SYNCING.lock().unwrap().remove(&self.0);
+    }
+}
+
+
+/// Syncs the local notes with the notes of an Amazon S3 bucket, in both directions.
+///
+/// # Arguments
+///
+/// * `bucket_name` - The name of the bucket.
+///
+/// # Operation
+///
+/// * The notes are matched by UUID, and each side is dated by its update time, or its creation time if it was never
+///   updated. The times of both sides are recorded after each sync in the "sync_state" table, so a later sync tells
+///   which side changed since.
+/// * A note changed on one side only is copied to the other: newer local versions are uploaded, newer remote
+///   versions are downloaded. A local note saved over by the sync is snapshotted first (see `version_operations`).
+///   Before the first sync of a note, the newer side wins.
+/// * A note changed on both sides since the last sync is a conflict: both copies are left as they are, and it is
+///   recorded in the "conflicts" table until it is resolved with `resolve_conflict`, or one side is brought back in
+///   line with the other.
+/// * A note created on one side is created on the other, keeping its UUID. A note deleted on one side and unchanged
+///   on the other since the last sync is deleted on the other side too.
+/// * A locked note is never changed nor deleted by the sync, whichever side it is locked on (see
+///   `local_operations::set_note_locked`). Such notes are reported as skipped, with the reason.
+/// * The notes are uploaded to the objects named after their UUID, and a note uploaded under its title by a previous
+///   version is moved there (see `s3_operations::note_key`).
+/// * The notes synced in plain text are uploaded as unencrypted Markdown (see `plain_sync`), and are uploaded again
+///   when they are marked or unmarked, even if they did not change. They are listed in the summary, whichever side
+///   changed, so the notes anyone with access to the bucket can read are never missed.
+/// * The search index of the bucket is rebuilt if its notes changed, and a desktop notification is sent if any note
+///   was synced.
+///
+/// # Returns
+///
+/// Returns a `SyncSummary` with the number of notes created, updated, and deleted on each side, the number of
+/// unchanged notes, the conflicts, the skipped notes, and the notes stored unencrypted in the bucket.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, if the bucket is already being synced, if the AWS SDK encounters an error, or if there is an issue
+/// with the database connection. The notes synced before the error keep their new state.
+pub async fn sync_bucket(bucket_name: &str) -> Result<SyncSummary, String> {
+    // Trim any surrounding quotes from the bucket name
+    let bucket_name = bucket_name.trim_matches('"');
+
+    // Fails early when the notes cannot be decrypted, e.g. while the vault is locked with a passphrase
+    key_management::content_key()?;
+    let _guard = SyncGuard::acquire(bucket_name)?;
+
+    let changes = bucket_mirror::sync_bucket_mirror(bucket_name).await.map_err(|e| e.to_string())?;
+    let mut summary = SyncSummary { bucket_name: bucket_name.to_string(), ..SyncSummary::default() };
+    let remote_notes = remote_notes(bucket_name, &mut summary.skipped)?;
+    let mut local_notes = HashMap::new();
+    for note in local_operations::get_local_notes_where("1 = 1", &[])? {
+        match note.uuid.clone() {
+            Some(uuid) => { local_notes.insert(uuid, note); },
+            None => summary.skipped.push(SkippedNote {
+                uuid: String::new(),
+                title: note.title,
+                reason: "The note has no UUID".to_string(),
+            }),
+        }
+    }
+    let states = read_states(bucket_name)?;
+    let plain_uuids: HashSet<String> = plain_sync::list_plain_sync_notes()?
+        .into_iter()
+        .filter(|note| !note.secure)
+        .map(|note| note.uuid)
+        .collect();
+
+    let mut uuids: Vec<&String> = local_notes.keys().chain(remote_notes.keys()).chain(states.keys()).collect();
+    uuids.sort();
+    uuids.dedup();
+
+    let client = s3_operations::s3_client().await;
+
+    for uuid in uuids {
+        let local = local_notes.get(uuid);
+        let remote = remote_notes.get(uuid);
+        let state = states.get(uuid).copied();
+        let title = local.map(|note| note.title.clone())
+            .or_else(|| remote.map(|remote| remote_title(&remote.key, &remote.metadata)))
+            .unwrap_or_default();
+        let skip = |reason: &str| SkippedNote { uuid: uuid.clone(), title: title.clone(), reason: reason.to_string() };
+
+        let plain = local.is_some_and(|local| !local.secure && plain_uuids.contains(uuid));
+        let remote_plain = remote.is_some_and(|remote| plain_sync::is_plain_object(&remote.metadata));
+        let action = match decide(local, remote, state) {
+            // A note marked or unmarked since it was uploaded is uploaded again, so the bucket holds what it should
+            Action::Unchanged if local.is_some() && remote.is_some() && remote_plain != plain => Action::Upload,
+            action => action,
+        };
+        let stored_plain = match action {
+            Action::Upload if check_upload(remote).is_ok() => plain,
+            Action::DeleteLocal | Action::DeleteRemote => false,
+            _ => remote_plain,
+        };
+        if stored_plain {
+            summary.plain_notes.push(SyncedNote { uuid: uuid.clone(), title: title.clone() });
+        }
+        if !matches!(action, Action::Conflict) {
+            forget_conflict(bucket_name, uuid)?;
+        }
+        match action {
+            Action::Unchanged => {
+                if let (Some(local), Some(remote)) = (local, remote) {
+                    save_state(bucket_name, uuid, local_updated_at(local), remote.updated_at)?;
+                    summary.unchanged += 1;
+                } else {
+                    forget_state(bucket_name, uuid)?;
+                }
+            },
+            Action::Upload => {
+                let local = local.unwrap();
+                if let Err(reason) = check_upload(remote) {
+                    summary.skipped.push(skip(&reason));
+                    continue;
+                }
+                upload_note(&client, bucket_name, uuid, local, remote).await?;
+                match remote {
+                    Some(_) => summary.updated_in_bucket += 1,
+                    None => summary.created_in_bucket += 1,
+                }
+            },
+            Action::Download => {
+                let remote = remote.unwrap();
+                let note = match check_download(uuid, local, remote) {
+                    Ok(note) => note,
+                    Err(reason) => {
+                        summary.skipped.push(skip(&reason));
+                        continue;
+                    },
+                };
+                download_note(bucket_name, uuid, local, remote, &note)?;
+                match local {
+                    Some(_) => summary.updated_locally += 1,
+                    None => summary.created_locally += 1,
+                }
+            },
+            Action::DeleteLocal => {
+                let local = local.unwrap();
+                if local.locked {
+                    summary.skipped.push(skip("The note is locked"));
+                    continue;
+                }
+                if let Some(id) = local.id {
+                    delete_local_note(id)?;
+                }
+                forget_state(bucket_name, uuid)?;
+                summary.deleted_locally += 1;
+            },
+            Action::DeleteRemote => {
+                let remote = remote.unwrap();
+                if s3_operations::is_locked(&remote.metadata) {
+                    summary.skipped.push(skip("The note is locked in the bucket"));
+                    continue;
+                }
+                delete_object(&client, bucket_name, &remote.key).await?;
+                forget_state(bucket_name, uuid)?;
+                summary.deleted_in_bucket += 1;
+            },
+            Action::Conflict => {
+                let (local, remote) = (local.unwrap(), remote.unwrap());
+                record_conflict(bucket_name, uuid, local, remote)?;
+                summary.conflicts.push(SyncedNote { uuid: uuid.clone(), title: title.clone() });
+            },
+        }
+    }
+
+    // The search index of the bucket does not know about the changes found by the mirror
+    if !changes.changed.is_empty() || !changes.removed.is_empty() {
+        search_index::drop_bucket_index(bucket_name);
+    }
+
+    summary.synced_at = chrono::Utc::now().timestamp();
+    let synced = summary.created_locally + summary.created_in_bucket + summary.updated_locally
+        + summary.updated_in_bucket + summary.deleted_locally + summary.deleted_in_bucket;
+    if synced > 0 {
+        // Send a desktop notification
+        notifier::notify("Bucket synced", &format!("{} notes were synced with bucket {}.", synced, bucket_name));
+    }
+
+    Ok(summary)
+}
+
+
+/// Lists the notes changed both locally and in a bucket since their last sync, found by `sync_bucket`.
+///
+/// # Returns
+///
+/// Returns a vector of `SyncConflict`, with the title and the update time of both copies, the most recent first.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_conflicts() -> Result<Vec<SyncConflict>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT bucket, uuid, local_title, remote_title, local_updated_at, remote_updated_at, detected_at
+        FROM conflicts ORDER BY detected_at DESC, bucket, uuid"
+    ).map_err(|e| e.to_string())?;
+    let conflicts = stmt.query_map([], |row| {
+        Ok(SyncConflict {
+            bucket_name: row.get(0)?,
+            uuid: row.get(1)?,
+            local_title: row.get(2)?,
+            remote_title: row.get(3)?,
+            local_updated_at: row.get(4)?,
+            remote_updated_at: row.get(5)?,
+            detected_at: row.get(6)?,
+        })
+    })
+    .map_err(|e| e.to_string())?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| e.to_string())?;
+    Ok(conflicts)
+}
+
+
+/// Resolves a conflict found by `sync_bucket`, by choosing which copy of the note is kept.
+///
+/// # Arguments
+///
+/// * `uuid` - The UUID of the note.
+/// * `strategy` - `KeepLocal` uploads the local copy over the remote one, `KeepRemote` saves the remote copy over
+///   the local one, and `KeepBoth` keeps the remote copy under the UUID of the note, and the local copy as a new note
+///   whose title ends with " (local copy)", uploaded on the next sync.
+/// * `bucket_name` - The bucket of the conflict, only needed if the note conflicts with several buckets.
+///
+/// # Operation
+///
+/// The bucket is mirrored again first, so the latest remote copy is kept. A local copy saved over is snapshotted
+/// first (see `version_operations`). The aliases stay with the note of the UUID, so the new note of `KeepBoth` has none.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the conflict is resolved, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note has no conflict, or conflicts with several buckets and no bucket
+/// is given, if one of the copies was deleted since, if the copy to overwrite is locked, if another note of the bucket
+/// has the title of the local copy, if the notes cannot be decrypted, if the bucket is being synced, if the AWS SDK
+/// encounters an error, or if there is an issue with the database connection. The conflict is kept on error.
+pub async fn resolve_conflict(uuid: &str, strategy: ConflictStrategy, bucket_name: Option<&str>) -> Result<(), String> {
+    key_management::content_key()?;
+    let buckets = conflict_buckets(uuid)?;
+    let bucket_name = match (bucket_name.map(|bucket_name| bucket_name.trim_matches('"')), buckets.as_slice()) {
+        (_, []) => return Err(format!("The note {} has no conflict", uuid)),
+        (Some(bucket_name), _) if buckets.iter().any(|bucket| bucket == bucket_name) => bucket_name.to_string(),
+        (Some(bucket_name), _) => return Err(format!("The note {} has no conflict with bucket {}", uuid, bucket_name)),
+        (None, [bucket_name]) => bucket_name.clone(),
+        (None, _) => return Err(format!("The note {} conflicts with several buckets: {}", uuid, buckets.join(", "))),
+    };
+    let bucket_name = bucket_name.as_str();
+    let _guard = SyncGuard::acquire(bucket_name)?;
+
+    let changes = bucket_mirror::sync_bucket_mirror(bucket_name).await.map_err(|e| e.to_string())?;
+    if !changes.changed.is_empty() || !changes.removed.is_empty() {
+        search_index::drop_bucket_index(bucket_name);
+    }
+    let remote_notes = remote_notes(bucket_name, &mut Vec::new())?;
+    let remote = remote_notes.get(uuid).ok_or("The note was deleted from the bucket since".to_string())?;
+    let local = local_operations::get_local_notes_where("uuid = ?1", &[&uuid])?.into_iter().next()
+        .ok_or("The local note was deleted since".to_string())?;
+
+    match strategy {
+        ConflictStrategy::Local => {
+            check_upload(Some(remote))?;
+            let client = s3_operations::s3_client().await;
+            upload_note(&client, bucket_name, uuid, &local, Some(remote)).await?;
+        },
+        ConflictStrategy::Remote => {
+            let note = check_download(uuid, Some(&local), remote)?;
+            download_note(bucket_name, uuid, Some(&local), remote, &note)?;
+        },
+        ConflictStrategy::Both => {
+            let note = check_download(uuid, Some(&local), remote)?;
+            // The suffix is kept whole within the 100 bytes of a title
+            let mut title = local.title.clone();
+            while title.len() + CONFLICT_COPY_SUFFIX.len() > 100 {
+                title.pop();
+            }
+            title.push_str(CONFLICT_COPY_SUFFIX);
+            create_local_note(&Note {
+                id: None,
+                uuid: None,
+                title,
+                created_at: chrono::Utc::now().timestamp(),
+                updated_at: None,
+                locked: false,
+                pinned: false,
+                archived: false,
+                notebook_id: None,
+                aliases: Vec::new(),
+                ..local.clone()
+            })?;
+            download_note(bucket_name, uuid, Some(&local), remote, &note)?;
+        },
+    }
+
+    forget_conflict(bucket_name, uuid)
+}
+
+
+/// Retrieves the automatic sync settings.
+///
+/// # Returns
+///
+/// Returns the `SyncSettings`, or `None` if the automatic sync is not configured.
+///
+/// # Errors
+///
+/// This function will return an error if the stored configuration is malformed or if there is an issue with the database connection.
+pub fn get_sync_settings() -> Result<Option<SyncSettings>, String> {
+    match settings::get_setting(CONFIG_SETTING)? {
+        Some(config) => serde_json::from_str(&config).map(Some).map_err(|e| format!("Invalid sync settings: {}", e)),
+        None => Ok(None),
+    }
+}
+
+
+/// Configures the automatic sync.
+///
+/// # Arguments
+///
+/// * `config` - Whether the automatic sync is enabled, the buckets to sync, and how often, in minutes. The bucket
+///   names are trimmed, and the empty ones and the duplicates are dropped.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the settings are stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the interval is 0, if the sync is enabled without buckets, or if there is
+/// an issue with the database connection.
+pub fn set_sync_settings(config: SyncSettings) -> Result<(), String> {
+    if config.interval_minutes == 0 {
+        return Err("The sync interval should be at least 1 minute".to_string());
+    }
+    let mut buckets: Vec<String> = Vec::new();
+    for bucket_name in config.buckets.iter().map(|bucket_name| bucket_name.trim()).filter(|bucket_name| !bucket_name.is_empty()) {
+        if !buckets.iter().any(|existing| existing == bucket_name) {
+            buckets.push(bucket_name.to_string());
+        }
+    }
+    if config.enabled && buckets.is_empty() {
+        return Err("The sync needs at least one bucket".to_string());
+    }
+
+    let json = serde_json::to_string(&SyncSettings { buckets, ..config }).map_err(|e| e.to_string())?;
+    settings::set_setting(CONFIG_SETTING, &json)
+}
+
+
+/// Starts syncing the configured buckets in the background.
+///
+/// # Operation
+///
+/// A task syncs each configured bucket every sync interval while the automatic sync is enabled, unless the cloud
+/// budget of the month is exceeded and asks to pause it (see `usage_operations::is_sync_paused`). The configuration
+/// is read again before each round, so changes apply without restarting the application. Failures are only
+/// printed, and the buckets are synced again on the next round.
+pub fn start_auto_sync() {
+    tokio::spawn(async {
+        loop {
+            let interval = match get_sync_settings() {
+                Ok(Some(config)) if config.enabled => {
+                    match usage_operations::is_sync_paused() {
+                        Ok(true) => eprintln!("The automatic sync is paused, the cloud budget of the month is exceeded"),
+                        Ok(false) => {
+                            for bucket_name in &config.buckets {
+                                if let Err(e) = sync_bucket(bucket_name).await {
+                                    eprintln!("Failed to sync bucket {}: {}", bucket_name, e);
+                                }
+                            }
+                        },
+                        Err(e) => eprintln!("Failed to check the cloud budget: {}", e),
+                    }
+                    config.interval_minutes
+                },
+                Ok(_) => DISABLED_CHECK_MINUTES,
+                Err(e) => {
+                    eprintln!("Failed to read the sync settings: {}", e);
+                    DISABLED_CHECK_MINUTES
+                },
+            };
+            tokio::time::sleep(Duration::from_secs(interval.max(1) as u64 * 60)).await;
+        }
+    });
+}
+
+
+/// Decides what to do with a note, given its local copy, its remote copy, and the times of both sides at the last
+/// sync, if it was synced before.
+fn decide(local: Option<&Note>, remote: Option<&RemoteNote>, state: Option<(i64, i64)>) -> Action {
+    match (local, remote, state) {
+        (Some(local), Some(remote), None) => match local_updated_at(local).cmp(&remote.updated_at) {
+            std::cmp::Ordering::Equal => Action::Unchanged,
+            std::cmp::Ordering::Greater => Action::Upload,
+            std::cmp::Ordering::Less => Action::Download,
+        },
+        (Some(local), Some(remote), Some((local_synced_at, remote_synced_at))) => {
+            match (local_updated_at(local) != local_synced_at, remote.updated_at != remote_synced_at) {
+                (false, false) => Action::Unchanged,
+                (true, false) => Action::Upload,
+                (false, true) => Action::Download,
+                // Both sides made the same change, e.g. a note synced before the state was lost
+                (true, true) if local.title == remote_title(&remote.key, &remote.metadata) && local.content == remote.content => Action::Unchanged,
+                (true, true) => Action::Conflict,
+            }
+        },
+        (Some(_), None, None) => Action::Upload,
+        (Some(local), None, Some((local_synced_at, _))) => match local_updated_at(local) == local_synced_at {
+            true => Action::DeleteLocal,
+            false => Action::Upload,
+        },
+        (None, Some(_), None) => Action::Download,
+        (None, Some(remote), Some((_, remote_synced_at))) => match remote.updated_at == remote_synced_at {
+            true => Action::DeleteRemote,
+            false => Action::Download,
+        },
+        (None, None, _) => Action::Unchanged,
+    }
+}
+
+
+/// Reads the mirrored notes of a bucket by UUID. The objects without a UUID, and all but the latest of the objects
+/// with the same UUID, are skipped.
+fn remote_notes(bucket_name: &str, skipped: &mut Vec<SkippedNote>) -> Result<HashMap<String, RemoteNote>, String> {
+    let mut remote_notes: HashMap<String, RemoteNote> = HashMap::new();
+    for (key, _last_modified, metadata, content) in bucket_mirror::get_mirrored_notes(bucket_name, None)? {
+        let metadata = metadata.unwrap_or_default();
+        let Some(uuid) = metadata.get("uuid").filter(|uuid| !uuid.is_empty()).cloned() else {
+            skipped.push(SkippedNote { uuid: String::new(), title: remote_title(&key, &metadata), reason: "The object has no UUID".to_string() });
+            continue;
+        };
+        let remote = RemoteNote { updated_at: remote_updated_at(&metadata), key, metadata, content };
+
+        let duplicate = match remote_notes.remove(&uuid) {
+            Some(other) if other.updated_at > remote.updated_at => {
+                remote_notes.insert(uuid.clone(), other);
+                remote
+            },
+            Some(other) => {
+                remote_notes.insert(uuid.clone(), remote);
+                other
+            },
+            None => {
+                remote_notes.insert(uuid.clone(), remote);
+                continue;
+            },
+        };
+        skipped.push(SkippedNote {
+            uuid,
+            title: remote_title(&duplicate.key, &duplicate.metadata),
+            reason: format!("Another object of the bucket has the same UUID: {}", duplicate.key),
+        });
+    }
+    Ok(remote_notes)
+}
+
+
+/// Returns the time a local note was last changed.
+fn local_updated_at(note: &Note) -> i64 {
+    note.updated_at.unwrap_or(note.created_at)
+}
+
+
+/// Returns the time a note of a bucket was last changed, from the metadata of its object.
+fn remote_updated_at(metadata: &HashMap<String, String>) -> i64 {
+    let time = |field: &str| metadata.get(field).and_then(|value| value.parse::<i64>().ok()).filter(|time| *time > 0);
+    time("updated_at").or_else(|| time("created_at")).unwrap_or(0)
+}
+
+
+/// Returns the title of a note from the metadata of its object, or from its key for the objects named after their title.
+fn remote_title(key: &str, metadata: &HashMap<String, String>) -> String {
+    local_operations::truncate_title(&s3_operations::title_from_metadata(key, metadata))
+}
+
+
+/// Checks that a local note can be uploaded over its remote copy, if any. Returns the reason otherwise.
+fn check_upload(remote: Option<&RemoteNote>) -> Result<(), String> {
+    if remote.is_some_and(|remote| s3_operations::is_locked(&remote.metadata)) {
+        return Err("The note is locked in the bucket".to_string());
+    }
+    Ok(())
+}
+
+
+/// Uploads a local note over its remote copy, if any, and records the times of both sides.
+async fn upload_note(client: &s3::Client, bucket_name: &str, uuid: &str, local: &Note, remote: Option<&RemoteNote>) -> Result<(), String> {
+    let notebook = notebook_operations::notebook_path(local.notebook_id)?;
+    let key = s3_operations::note_key(uuid, notebook.as_deref());
+    s3_operations::put_note_object(client, bucket_name, &key, local).await.map_err(|e| e.to_string())?;
+    // A note moved to another notebook, or uploaded under its title by a previous version, leaves that object
+    if let Some(previous) = remote.filter(|remote| remote.key != key) {
+        delete_object(client, bucket_name, &previous.key).await?;
+    }
+    let updated_at = local_updated_at(local);
+    save_state(bucket_name, uuid, updated_at, updated_at)
+}
+
+
+/// Checks that a note of a bucket can be saved over its local copy, if any. Returns the local copy to save, or the
+/// reason otherwise.
+fn check_download(uuid: &str, local: Option<&Note>, remote: &RemoteNote) -> Result<Note, String> {
+    if local.is_some_and(|local| local.locked) {
+        return Err("The note is locked".to_string());
+    }
+    let note = remote_to_note(uuid, remote);
+    let aliases = alias_operations::check_aliases(&note.aliases)?;
+    let tags = tag_operations::check_tags(&note.tags)?;
+    Ok(Note { aliases, tags, ..note })
+}
+
+
+/// Saves a note of a bucket over its local copy, or creates it, and records the times of both sides.
+fn download_note(bucket_name: &str, uuid: &str, local: Option<&Note>, remote: &RemoteNote, note: &Note) -> Result<(), String> {
+    // A local copy moved to the trash since the last sync is restored with the changes of the bucket
+    let local_id = match local.and_then(|local| local.id) {
+        Some(id) => Some(id),
+        None => trashed_note_id(uuid)?,
+    };
+    let local_updated_at = match local_id {
+        Some(id) => overwrite_local_note(id, note)?,
+        None => {
+            create_local_note(note)?;
+            note.created_at
+        },
+    };
+    save_state(bucket_name, uuid, local_updated_at, remote.updated_at)
+}
+
+
+/// Builds the local copy of a note of a bucket.
+fn remote_to_note(uuid: &str, remote: &RemoteNote) -> Note {
+    let metadata = &remote.metadata;
+    Note {
+        id: None,
+        uuid: Some(uuid.to_string()),
+        title: remote_title(&remote.key, &remote.metadata),
+        content: remote.content.clone(),
+        nonce: None,
+        created_at: metadata.get("created_at").and_then(|value| value.parse().ok()).unwrap_or(remote.updated_at),
+        updated_at: None,
+        timestamp: None,
+        secure: metadata.get("secure").map(|value| value == "true").unwrap_or(false),
+        status: metadata.get("status").cloned(),
+        icon: s3_operations::icon_from_metadata(metadata),
+        // The attachments are not synced, so the cover is left as it is
+        cover_attachment_id: None,
+        locked: s3_operations::is_locked(metadata),
+        pinned: s3_operations::flag_from_metadata(metadata, "pinned"),
+        archived: s3_operations::flag_from_metadata(metadata, "archived"),
+        notebook_id: None,
+        aliases: s3_operations::aliases_from_metadata(metadata),
+        tags: s3_operations::tags_from_metadata(metadata),
+    }
+}
+
+
+/// Returns the ID of the local note with the given UUID if it is in the trash.
+fn trashed_note_id(uuid: &str) -> Result<Option<i64>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row(
+        "SELECT id FROM notes WHERE uuid = ?1 AND deleted_at IS NOT NULL",
+        params![uuid],
+        |row| row.get(0),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Saves a note of a bucket over its local copy, after a snapshot of the local copy, restoring it if it is in the
+/// trash. Returns its new update time.
+fn overwrite_local_note(id: i64, note: &Note) -> Result<i64, String> {
+    let (encrypted_content, nonce_str) = crate::crypto::encrypt_content(&note.content)?;
+    let now = chrono::Utc::now();
+
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    version_operations::snapshot_note(&tx, id, SNAPSHOT_REASON)?;
+    tx.execute(
+        "UPDATE notes SET title = ?1, content = ?2, nonce = ?3, updated_at = ?4, timestamp = ?5, secure = ?6, preview = ?7,
+        status = ?8, icon = ?9, locked = ?10, deleted_at = NULL WHERE id = ?11",
+        params![
+            note.title, encrypted_content, nonce_str, now.timestamp(), now.to_rfc3339(), note.secure,
+            local_operations::stored_preview(&note.content, note.secure), note.status, note.icon, note.locked, id,
+        ],
+    ).map_err(|e| e.to_string())?;
+    alias_operations::store_aliases(&tx, id, &note.aliases)?;
+    tag_operations::store_tags(&tx, id, &note.tags)?;
+    note_events::note_saved(&tx, id, &note.content)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(now.timestamp())
+}
+
+
+/// Creates the local copy of a note of a bucket, keeping its UUID.
+fn create_local_note(note: &Note) -> Result<(), String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let note_id = local_operations::insert_note(&tx, note)?;
+    if note.locked {
+        tx.execute("UPDATE notes SET locked = 1 WHERE id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    }
+    note_events::note_saved(&tx, note_id, &note.content)?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+
+/// Deletes a local note deleted from the bucket, after a snapshot of it.
+fn delete_local_note(id: i64) -> Result<(), String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    local_operations::check_not_locked(&tx, id)?;
+    version_operations::snapshot_note(&tx, id, SNAPSHOT_REASON)?;
+    tx.execute("DELETE FROM notes WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+    note_events::note_deleted(&tx, id)?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+
+/// Deletes an object of a bucket.
+async fn delete_object(client: &s3::Client, bucket_name: &str, key: &str) -> Result<(), String> {
+    let result = client.delete_object()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await;
+    result.map(|_| ()).map_err(|e| e.to_string())
+}
+
+
+/// Reads the times of both sides of the notes of a bucket at their last sync, by UUID.
+fn read_states(bucket_name: &str) -> Result<HashMap<String, (i64, i64)>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT uuid, local_updated_at, remote_updated_at FROM sync_state WHERE bucket = ?1"
+    ).map_err(|e| e.to_string())?;
+    let states = stmt.query_map(params![bucket_name], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(states)
+}
+
+
+/// Records the times of both sides of a note once it is synced.
+fn save_state(bucket_name: &str, uuid: &str, local_updated_at: i64, remote_updated_at: i64) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO sync_state (bucket, uuid, local_updated_at, remote_updated_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (bucket, uuid) DO UPDATE SET local_updated_at = ?3, remote_updated_at = ?4",
+        params![bucket_name, uuid, local_updated_at, remote_updated_at],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Records a note changed on both sides since its last sync, keeping the time it was first found.
+fn record_conflict(bucket_name: &str, uuid: &str, local: &Note, remote: &RemoteNote) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO conflicts (bucket, uuid, local_title, remote_title, local_updated_at, remote_updated_at, detected_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (bucket, uuid) DO UPDATE SET local_title = ?3, remote_title = ?4, local_updated_at = ?5, remote_updated_at = ?6",
+        params![
+            bucket_name, uuid, local.title, remote_title(&remote.key, &remote.metadata), local_updated_at(local), remote.updated_at,
+            chrono::Utc::now().timestamp(),
+        ],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Forgets the conflict of a note once it is resolved, or both sides are in line again.
+fn forget_conflict(bucket_name: &str, uuid: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute("DELETE FROM conflicts WHERE bucket = ?1 AND uuid = ?2", params![bucket_name, uuid]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Lists the buckets a note conflicts with.
+fn conflict_buckets(uuid: &str) -> Result<Vec<String>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT bucket FROM conflicts WHERE uuid = ?1 ORDER BY bucket").map_err(|e| e.to_string())?;
+    let buckets = stmt.query_map(params![uuid], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(buckets)
+}
+
+
+/// Forgets a note once it is deleted on both sides.
+fn forget_state(bucket_name: &str, uuid: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute("DELETE FROM sync_state WHERE bucket = ?1 AND uuid = ?2", params![bucket_name, uuid]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(updated_at: i64, content: &str) -> Note {
+        Note {
+            id: Some(1),
+            uuid: Some("8d5572eb-b4a0-4697-b551-fff4de57f17e".to_string()),
+            title: "Title".to_string(),
+            content: content.to_string(),
+            nonce: None,
+            created_at: 100,
+            updated_at: Some(updated_at),
+            timestamp: None,
+            secure: false,
+            status: None,
+            icon: None,
+            cover_attachment_id: None,
+            locked: false,
+            pinned: false,
+            archived: false,
+            notebook_id: None,
+            aliases: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn remote(updated_at: i64, content: &str) -> RemoteNote {
+        RemoteNote {
+            key: "Title.txt".to_string(),
+            metadata: HashMap::new(),
+            content: content.to_string(),
+            updated_at,
+        }
+    }
+
+    #[test]
+    fn decide_without_state_keeps_the_latest_copy() {
+        assert!(matches!(decide(Some(&local(200, "a")), Some(&remote(200, "b")), None), Action::Unchanged));
+        assert!(matches!(decide(Some(&local(300, "a")), Some(&remote(200, "b")), None), Action::Upload));
+        assert!(matches!(decide(Some(&local(200, "a")), Some(&remote(300, "b")), None), Action::Download));
+    }
+
+    #[test]
+    fn decide_with_state_follows_the_side_that_changed() {
+        let state = Some((200, 200));
+        assert!(matches!(decide(Some(&local(200, "a")), Some(&remote(200, "a")), state), Action::Unchanged));
+        assert!(matches!(decide(Some(&local(300, "b")), Some(&remote(200, "a")), state), Action::Upload));
+        assert!(matches!(decide(Some(&local(200, "a")), Some(&remote(300, "b")), state), Action::Download));
+    }
+
+    #[test]
+    fn decide_reports_a_conflict_only_for_different_changes() {
+        let state = Some((200, 200));
+        assert!(matches!(decide(Some(&local(300, "b")), Some(&remote(400, "c")), state), Action::Conflict));
+        assert!(matches!(decide(Some(&local(300, "b")), Some(&remote(400, "b")), state), Action::Unchanged));
+    }
+
+    #[test]
+    fn decide_deletes_only_unchanged_copies() {
+        let state = Some((200, 200));
+        assert!(matches!(decide(Some(&local(200, "a")), None, state), Action::DeleteLocal));
+        assert!(matches!(decide(Some(&local(300, "b")), None, state), Action::Upload));
+        assert!(matches!(decide(None, Some(&remote(200, "a")), state), Action::DeleteRemote));
+        assert!(matches!(decide(None, Some(&remote(300, "b")), state), Action::Download));
+    }
+
+    #[test]
+    fn decide_copies_new_notes() {
+        assert!(matches!(decide(Some(&local(200, "a")), None, None), Action::Upload));
+        assert!(matches!(decide(None, Some(&remote(200, "a")), None), Action::Download));
+        assert!(matches!(decide(None, None, None), Action::Unchanged));
+    }
+}
@@ -0,0 +1,542 @@
+// tag_operations.rs
+
+use crate::{ collation, crypto, delta, local_operations, note_events, notifier, preview, search_index, vault, version_operations, local_operations::CONNECTION, models::AppError, models::Note, models::TagCount, models::TagRefactor };
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+
+/// The maximum number of tags of a note.
+const MAX_TAGS: usize = 20;
+
+/// The maximum length of a tag.
+const MAX_TAG_LENGTH: usize = 50;
+
+/// The maximum size of the tags of a note once encoded, so they fit in the metadata of its object in a bucket with
+/// the aliases and the other fields (see `alias_operations::check_aliases`).
+const MAX_TAGS_BYTES: usize = 384;
+
+
+/// Tags a local note.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `tag` - The tag, up to 50 characters. A leading "#" is dropped, so "#work" and "work" are the same tag.
+///
+/// # Operation
+///
+/// * Tags are shared by the notes and case is ignored: a tag that differs from an existing one only by its case
+///   takes the name of the existing one.
+/// * The note is reindexed so the `tag:` filter of a search finds it. The tags are uploaded with the note the next
+///   time it is uploaded to a bucket.
+///
+/// # Returns
+///
+/// Returns the tags of the note, sorted alphabetically.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if the tag is invalid, if the note has 20 tags
+/// already, or if there is an issue with the database connection.
+pub fn add_tag_to_note(note_id: i64, tag: &str) -> Result<Vec<String>, String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    check_note_exists(&conn, note_id)?;
+
+    let mut tags = note_tags(&conn, note_id)?;
+    tags.push(tag.to_string());
+    let tags = check_tags(&tags)?;
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    store_tags(&tx, note_id, &tags)?;
+    let tags = note_tags(&tx, note_id)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    search_index::schedule_reindex(note_id);
+    Ok(tags)
+}
+
+
+/// Removes a tag from a local note. A tag left without notes is removed.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+/// * `tag` - The tag, whatever its case, with or without a leading "#".
+///
+/// # Returns
+///
+/// Returns the tags left on the note, sorted alphabetically.
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if it does not have the tag, or if there is an
+/// issue with the database connection.
+pub fn remove_tag_from_note(note_id: i64, tag: &str) -> Result<Vec<String>, String> {
+    let tag = clean_tag(tag);
+    let mut conn = CONNECTION.lock().unwrap();
+    check_note_exists(&conn, note_id)?;
+
+    let tags = note_tags(&conn, note_id)?;
+    if !tags.iter().any(|existing| existing.to_lowercase() == tag.to_lowercase()) {
+        return Err(format!("The note has no tag '{}'", tag));
+    }
+    let tags: Vec<String> = tags.into_iter().filter(|existing| existing.to_lowercase() != tag.to_lowercase()).collect();
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    store_tags(&tx, note_id, &tags)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    search_index::schedule_reindex(note_id);
+    Ok(tags)
+}
+
+
+/// Retrieves the local notes with a tag.
+///
+/// # Arguments
+///
+/// * `tag` - The tag, whatever its case, with or without a leading "#".
+///
+/// # Returns
+///
+/// Returns the notes with their plaintext content, sorted by title like `local_operations::get_local_notes`.
+///
+/// # Errors
+///
+/// This function will return an error if the notes cannot be decrypted, e.g. while the vault is locked with a
+/// passphrase, or if there is an issue with the database connection.
+pub fn get_notes_by_tag(tag: &str) -> Result<Vec<Note>, String> {
+    let tag = clean_tag(tag);
+    let locale = collation::get_locale()?;
+    let mut notes = local_operations::get_local_notes_where(&tagged_condition("id", 1), &[&tag])?;
+    notes.sort_by(|a, b| collation::compare_titles(&a.title, &b.title, &locale));
+    Ok(notes)
+}
+
+
+/// Lists the tags of the local notes.
+///
+/// # Returns
+///
+/// Returns each tag with its number of notes, sorted alphabetically.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_tags() -> Result<Vec<TagCount>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT tags.name, COUNT(note_tags.note_id) FROM tags JOIN note_tags ON note_tags.tag_id = tags.id
+        JOIN notes ON notes.id = note_tags.note_id AND notes.deleted_at IS NULL
+        GROUP BY tags.id ORDER BY tags.name"
+    ).map_err(|e| e.to_string())?;
+    let tags = stmt.query_map([], |row| Ok(TagCount { tag: row.get(0)?, count: row.get(1)? }))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(tags)
+}
+
+
+/// Renames a tag on every local note, e.g. "todo" to "tasks".
+///
+/// # Arguments
+///
+/// * `old` - The tag, whatever its case, with or without a leading "#".
+/// * `new` - The new name of the tag, checked like the tags of a note (see `check_tags`). It can differ from the old
+///   name only by its case.
+///
+/// # Operation
+///
+/// See `retag`: the notes keep the tag under its new name, and the "#old" references in their content are rewritten.
+///
+/// # Returns
+///
+/// Returns a `TagRefactor` with the number of tagged notes, of rewritten notes, and of rewritten references.
+///
+/// # Errors
+///
+/// This function will return an error if the tag does not exist, if the new name is invalid or is already another
+/// tag, which should be merged instead, if a note would become too long, or if there is an issue with the database
+/// connection.
+pub fn rename_tag(old: &str, new: &str) -> Result<TagRefactor, AppError> {
+    let old = clean_tag(old);
+    let new = check_tags(&[new.to_string()]).map_err(AppError::Validation)?
+        .pop()
+        .ok_or(AppError::Validation("The new tag cannot be empty".to_string()))?;
+    if new == old {
+        return Err(AppError::Validation(format!("The tag is already named '{}'", new)));
+    }
+    retag(&old, &new, false)
+}
+
+
+/// Merges a tag into another on every local note, e.g. "meeting" into "meetings". The merged tag is removed.
+///
+/// # Arguments
+///
+/// * `source` - The tag merged, whatever its case, with or without a leading "#".
+/// * `target` - The tag it is merged into, which must exist.
+///
+/// # Operation
+///
+/// See `retag`: the notes with the merged tag get the other one, and the "#source" references in their content are
+/// rewritten.
+///
+/// # Returns
+///
+/// Returns a `TagRefactor` with the number of tagged notes, of rewritten notes, and of rewritten references.
+///
+/// # Errors
+///
+/// This function will return an error if either tag does not exist, if they are the same tag, if a note would become
+/// too long, or if there is an issue with the database connection.
+pub fn merge_tags(source: &str, target: &str) -> Result<TagRefactor, AppError> {
+    retag(&clean_tag(source), &clean_tag(target), true)
+}
+
+
+/// Builds the SQL condition selecting the notes with any of several tags, e.g. to scope an operation by tags.
+///
+/// # Arguments
+///
+/// * `column` - The column holding the ID of the note, e.g. "id" in the "notes" table.
+/// * `count` - The number of tags, bound to as many `?` placeholders, cleaned with `clean_tag`.
+///
+/// # Returns
+///
+/// Returns the condition. Case is ignored when the tags are compared.
+pub fn tagged_condition(column: &str, count: usize) -> String {
+    let placeholders = vec!["?"; count].join(", ");
+    format!(
+        "{} IN (SELECT note_tags.note_id FROM note_tags JOIN tags ON tags.id = note_tags.tag_id WHERE tags.name IN ({}))",
+        column, placeholders,
+    )
+}
+
+
+/// Cleans up a tag as it is stored: trimmed, without its leading "#".
+///
+/// # Arguments
+///
+/// * `tag` - The tag, e.g. "#work".
+pub fn clean_tag(tag: &str) -> String {
+    tag.trim().trim_start_matches('#').trim().to_string()
+}
+
+
+/// Checks the tags of a note and cleans them up.
+///
+/// # Arguments
+///
+/// * `tags` - The tags. They are cleaned with `clean_tag`, and the empty ones and the duplicates, whatever their
+///   case, are dropped.
+///
+/// # Returns
+///
+/// Returns the cleaned tags, in their order.
+///
+/// # Errors
+///
+/// This function will return an error if there are more than 20 tags, if one is longer than 50 characters or holds
+/// control characters, or if they are too long together to be stored in the metadata of an object.
+pub fn check_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|tag| clean_tag(tag)).filter(|tag| !tag.is_empty()) {
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!("Tag '{}' is too long", tag));
+        }
+        if tag.chars().any(char::is_control) {
+            return Err(format!("Tag '{}' contains invalid characters", tag.escape_debug()));
+        }
+        if !cleaned.iter().any(|existing| existing.to_lowercase() == tag.to_lowercase()) {
+            cleaned.push(tag);
+        }
+    }
+    if cleaned.len() > MAX_TAGS {
+        return Err(format!("A note can have at most {} tags", MAX_TAGS));
+    }
+    if serde_json::to_string(&cleaned).map_err(|e| e.to_string())?.len() > MAX_TAGS_BYTES {
+        return Err("The tags are too long together".to_string());
+    }
+    Ok(cleaned)
+}
+
+
+/// Replaces the tags of a note in the database, e.g. when it is created. The tags left without notes are removed.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+/// * `tags` - The tags, checked with `check_tags`.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn store_tags(conn: &Connection, note_id: i64, tags: &[String]) -> Result<(), String> {
+    conn.execute("DELETE FROM note_tags WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    for tag in tags {
+        // An existing tag keeps its case
+        conn.execute(
+            "INSERT OR IGNORE INTO tags (name, created_at) VALUES (?1, ?2)",
+            params![tag, chrono::Utc::now().timestamp()],
+        ).map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT OR IGNORE INTO note_tags (note_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
+            params![note_id, tag],
+        ).map_err(|e| e.to_string())?;
+    }
+    remove_unused_tags(conn)
+}
+
+
+/// Reads the tags of every local note, by note ID.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn read_tags(conn: &Connection) -> Result<HashMap<i64, Vec<String>>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT note_tags.note_id, tags.name FROM note_tags JOIN tags ON tags.id = note_tags.tag_id ORDER BY tags.name"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))).map_err(|e| e.to_string())?;
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in rows {
+        let (note_id, tag) = row.map_err(|e| e.to_string())?;
+        tags.entry(note_id).or_default().push(tag);
+    }
+    Ok(tags)
+}
+
+
+/// Fills in the tags of notes read from the database.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection.
+/// * `notes` - The notes, built with `local_operations::note_from_row`.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn fill_tags(conn: &Connection, notes: &mut [Note]) -> Result<(), String> {
+    // A single note is read often, e.g. when it is opened, so its tags are read alone
+    if let [note] = notes {
+        note.tags = note.id.map(|id| note_tags(conn, id)).transpose()?.unwrap_or_default();
+        return Ok(());
+    }
+
+    let mut tags = read_tags(conn)?;
+    for note in notes {
+        note.tags = note.id.and_then(|id| tags.remove(&id)).unwrap_or_default();
+    }
+    Ok(())
+}
+
+
+/// Removes the tags of a note after it was deleted. The tags left without notes are removed.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn forget_tags(conn: &Connection, note_id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM note_tags WHERE note_id = ?1", params![note_id]).map_err(|e| e.to_string())?;
+    remove_unused_tags(conn)
+}
+
+
+/// Reads the tags of a note, sorted alphabetically.
+fn note_tags(conn: &Connection, note_id: i64) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT tags.name FROM note_tags JOIN tags ON tags.id = note_tags.tag_id WHERE note_tags.note_id = ?1 ORDER BY tags.name"
+    ).map_err(|e| e.to_string())?;
+    let tags = stmt.query_map(params![note_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(tags)
+}
+
+
+/// Fails if a note does not exist.
+fn check_note_exists(conn: &Connection, note_id: i64) -> Result<(), AppError> {
+    let note_exists: Option<i64> = conn.query_row("SELECT id FROM notes WHERE id = ?1", params![note_id], |row| row.get(0))
+        .optional()?;
+    note_exists.map(|_| ()).ok_or(AppError::NotFound("Note not found".to_string()))
+}
+
+
+/// Removes the tags no note has anymore.
+fn remove_unused_tags(conn: &Connection) -> Result<(), String> {
+    conn.execute("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM note_tags)", []).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+
+/// Renames a tag, or merges it into another, on every local note, in a single transaction.
+///
+/// # Operation
+///
+/// * The notes with the tag get the new one, including the notes in the trash. A note that has both tags keeps one.
+/// * The hashtag references to the tag in the content of the notes, e.g. "#todo", are rewritten whatever their case,
+///   but not the longer hashtags starting with it, e.g. "#todo-later". A snapshot of each rewritten note is saved in
+///   the "note_versions" table so the change can be undone. Secure notes are skipped while the vault is locked, and
+///   locked notes are skipped (see `local_operations::set_note_locked`).
+/// * The notes are reindexed, and their tags and content are uploaded the next time they are uploaded to a bucket.
+fn retag(source: &str, target: &str, merge: bool) -> Result<TagRefactor, AppError> {
+    if source.is_empty() || target.is_empty() {
+        return Err(AppError::Validation("Tag cannot be empty".to_string()));
+    }
+
+    // A merged tag takes the case of the tag it is merged into
+    let target = match merge {
+        true => find_tag(&CONNECTION.lock().unwrap(), target)?.map(|(_, name)| name).unwrap_or(target.to_string()),
+        false => target.to_string(),
+    };
+    let target = target.as_str();
+
+    // The references are rewritten before the transaction, which holds the database connection
+    let reference = Regex::new(&format!(r"(?i)\B#{}", regex::escape(source))).map_err(|e| e.to_string())?;
+    let mut rewrites = Vec::new();
+    let mut skipped_notes = 0;
+    for note in local_operations::get_local_notes_where("1 = 1", &[])? {
+        let (content, count) = rewrite_references(&note.content, &reference, target);
+        if count == 0 {
+            continue;
+        }
+        if (note.secure && !vault::is_unlocked()) || note.locked {
+            skipped_notes += 1;
+            continue;
+        }
+        local_operations::validate_params(Note { content: content.clone(), ..note.clone() })?;
+        rewrites.push((note.id.ok_or("Note without id".to_string())?, content, count));
+    }
+
+    let mut changed_notes = BTreeSet::new();
+    let (tag, tagged_notes) = {
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let (source_id, source_name) = find_tag(&tx, source)?.ok_or(AppError::NotFound(format!("Tag '{}' not found", source)))?;
+        let target_tag = find_tag(&tx, target)?;
+        let mut stmt = tx.prepare("SELECT note_id FROM note_tags WHERE tag_id = ?1")?;
+        let tagged: Vec<i64> = stmt.query_map(params![source_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(stmt);
+
+        let tag = match (merge, target_tag) {
+            (false, Some((target_id, target_name))) if target_id != source_id => {
+                return Err(AppError::Validation(format!("Tag '{}' already exists, merge the tags instead", target_name)));
+            },
+            (false, _) => {
+                tx.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![target, source_id])?;
+                target.to_string()
+            },
+            (true, None) => return Err(AppError::NotFound(format!("Tag '{}' not found", target))),
+            (true, Some((target_id, _))) if target_id == source_id => {
+                return Err(AppError::Validation(format!("Tag '{}' cannot be merged into itself", source_name)));
+            },
+            (true, Some((target_id, target_name))) => {
+                tx.execute(
+                    "INSERT OR IGNORE INTO note_tags (note_id, tag_id) SELECT note_id, ?1 FROM note_tags WHERE tag_id = ?2",
+                    params![target_id, source_id],
+                )?;
+                tx.execute("DELETE FROM note_tags WHERE tag_id = ?1", params![source_id])?;
+                remove_unused_tags(&tx)?;
+                target_name
+            },
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        for (note_id, content, _) in &rewrites {
+            version_operations::snapshot_note(&tx, *note_id, "retag")?;
+            let (encrypted_content, nonce_str) = crypto::encrypt_content(content)?;
+            tx.execute(
+                "UPDATE notes SET content = ?1, nonce = ?2, updated_at = ?3, timestamp = ?4,
+                preview = CASE WHEN secure = 1 THEN NULL ELSE ?5 END WHERE id = ?6",
+                params![encrypted_content, nonce_str, now, timestamp, preview::build_preview(content), note_id],
+            )?;
+            note_events::note_saved(&tx, *note_id, content)?;
+        }
+
+        tx.commit()?;
+        changed_notes.extend(tagged.iter().copied());
+        (tag, tagged.len())
+    };
+
+    changed_notes.extend(rewrites.iter().map(|(note_id, _, _)| *note_id));
+    for note_id in &changed_notes {
+        search_index::schedule_reindex(*note_id);
+    }
+    if !changed_notes.is_empty() {
+        notifier::notify("Tags updated", &format!("Updated {} notes with the tag '{}'.", changed_notes.len(), tag));
+    }
+
+    Ok(TagRefactor {
+        tag,
+        tagged_notes,
+        rewritten_notes: rewrites.len(),
+        references: rewrites.iter().map(|(_, _, count)| count).sum(),
+        skipped_notes,
+    })
+}
+
+
+/// Reads the ID and the name of a tag, whatever its case.
+fn find_tag(conn: &Connection, tag: &str) -> Result<Option<(i64, String)>, String> {
+    conn.query_row("SELECT id, name FROM tags WHERE name = ?1", params![tag], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+        .map_err(|e| e.to_string())
+}
+
+
+/// Rewrites the hashtag references to a tag in the text of the content of a note.
+///
+/// # Returns
+///
+/// Returns the new content and the number of rewritten references.
+fn rewrite_references(content: &str, reference: &Regex, tag: &str) -> (String, usize) {
+    let mut ops = delta::parse_ops(content);
+    let mut count = 0;
+    for op in ops.iter_mut() {
+        let Some(text) = op.get("insert").and_then(Value::as_str) else { continue };
+
+        let mut rewritten = String::new();
+        let mut last = 0;
+        for found in reference.find_iter(text) {
+            // A longer hashtag, e.g. "#todo-later" for "#todo", is another tag
+            let longer = text[found.end()..].chars().next()
+                .is_some_and(|next| next.is_alphanumeric() || next == '_' || next == '-' || next == '/');
+            if longer {
+                continue;
+            }
+            rewritten.push_str(&text[last..found.start()]);
+            rewritten.push('#');
+            rewritten.push_str(tag);
+            last = found.end();
+            count += 1;
+        }
+        if last > 0 {
+            rewritten.push_str(&text[last..]);
+            op["insert"] = Value::String(rewritten);
+        }
+    }
+    if count == 0 {
+        return (content.to_string(), 0);
+    }
+    (delta::to_content(ops), count)
+}
@@ -0,0 +1,455 @@
+// tasks.rs
+
+use crate::{ email_operations, note_schedules, reencryption, search_index, stats_operations, text_extraction, trash, local_operations::CONNECTION, models::AppError, models::BackgroundTask, models::TaskPriority, models::TaskStatus };
+use lazy_static::lazy_static;
+use rusqlite::params;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+
+/// The kind of the task warming up the search index at startup.
+pub const WARM_UP: &str = "warm_up";
+
+/// The kind of the task applying the queued changes to the search index.
+pub const REINDEX: &str = "reindex";
+
+/// The kind of the task recording the daily statistics of the vault.
+pub const STATS_SNAPSHOT: &str = "stats_snapshot";
+
+/// The kind of the task turning the new emails of the configured mailbox into notes.
+pub const EMAIL_FETCH: &str = "email_fetch";
+
+/// The kind of the task extracting the text of the attachments.
+pub const EXTRACT_TEXT: &str = "extract_text";
+
+/// The kind of the task encrypting the local database again after a key change.
+pub const REENCRYPT: &str = "reencrypt";
+
+/// The kind of the task purging the notes kept in the trash for longer than the retention.
+pub const TRASH_PURGE: &str = "trash_purge";
+
+/// The kind of the task creating the scheduled notes that are due.
+pub const SCHEDULED_NOTES: &str = "scheduled_notes";
+
+/// The number of worker threads, i.e. the number of tasks running at the same time.
+const MAX_WORKERS: usize = 2;
+
+/// The number of finished tasks kept in memory to be listed.
+const MAX_FINISHED_TASKS: usize = 100;
+
+/// The number of days finished tasks are kept in the "background_tasks" table.
+const FINISHED_TASKS_RETENTION_DAYS: i64 = 7;
+
+
+/// The function running a task. It should return early when the task is cancelled.
+type TaskRun = fn(&TaskContext) -> Result<(), String>;
+
+
+/// What a running task can read: whether it was cancelled.
+pub struct TaskContext {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TaskContext {
+    /// Tells whether the task was cancelled while running.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+
+/// A task known to the manager, with what it needs to run.
+struct TaskEntry {
+    task: BackgroundTask,
+    cancelled: Arc<AtomicBool>,
+    /// Whether the task changed since it was last saved in the "background_tasks" table.
+    dirty: bool,
+}
+
+
+/// The tasks of the session and the number of running tasks by kind.
+#[derive(Default)]
+struct TaskQueue {
+    entries: BTreeMap<i64, TaskEntry>,
+    running_by_kind: HashMap<String, usize>,
+    last_id: i64,
+    /// Whether the application is shutting down, in which case no new task is started.
+    stopping: bool,
+}
+
+
+lazy_static! {
+    /// The tasks of the session, shared by the workers.
+    static ref TASKS: Mutex<TaskQueue> = Mutex::new(TaskQueue::default());
+
+    /// Wakes up the workers when a task is submitted, finishes, or needs to be saved.
+    static ref TASKS_CHANGED: Condvar = Condvar::new();
+}
+
+
+/// Returns how a kind of task is run: the maximum number of tasks of the kind running at the same time, and the
+/// function running them.
+fn handler(kind: &str) -> Option<(usize, TaskRun)> {
+    match kind {
+        WARM_UP => Some((1, search_index::run_warm_up)),
+        REINDEX => Some((1, search_index::run_reindex)),
+        STATS_SNAPSHOT => Some((1, stats_operations::run_snapshot)),
+        EMAIL_FETCH => Some((1, email_operations::run_fetch)),
+        EXTRACT_TEXT => Some((1, text_extraction::run_extraction)),
+        REENCRYPT => Some((1, reencryption::run_reencryption)),
+        TRASH_PURGE => Some((1, trash::run_purge)),
+        SCHEDULED_NOTES => Some((1, note_schedules::run_schedules)),
+        _ => None,
+    }
+}
+
+
+/// Starts the workers running the background tasks.
+///
+/// # Operation
+///
+/// * The tasks left queued or running when the application last exited are queued again, unless their kind is
+///   unknown, in which case they are marked as failed. Finished tasks older than 7 days are removed.
+/// * Worker threads are spawned. Each runs the queued task with the highest priority, the oldest first, whose kind
+///   has not reached its concurrency limit, and saves the changes of the tasks in the "background_tasks" table.
+///
+/// It must not be called while the database connection is locked.
+pub fn start_workers() {
+    if let Err(e) = resume_tasks() {
+        eprintln!("Failed to resume the background tasks: {}", e);
+    }
+
+    for _ in 0..MAX_WORKERS {
+        std::thread::spawn(run_worker);
+    }
+}
+
+
+/// Submits a task to be run in the background.
+///
+/// The task is only saved in the "background_tasks" table by a worker, so it can be submitted while the database
+/// connection is locked, e.g. from a transaction.
+///
+/// # Arguments
+///
+/// * `kind` - The kind of the task, e.g. `REINDEX`.
+/// * `label` - The description of the task shown to the user.
+/// * `priority` - The priority of the task.
+///
+/// # Returns
+///
+/// Returns the ID of the task.
+pub fn submit(kind: &str, label: &str, priority: TaskPriority) -> i64 {
+    let mut tasks = TASKS.lock().unwrap();
+    let id = next_id(&mut tasks);
+    tasks.entries.insert(id, TaskEntry {
+        task: BackgroundTask {
+            id,
+            kind: kind.to_string(),
+            label: label.to_string(),
+            priority,
+            status: TaskStatus::Queued,
+            created_at: chrono::Utc::now().timestamp(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+        },
+        cancelled: Arc::new(AtomicBool::new(false)),
+        dirty: true,
+    });
+    TASKS_CHANGED.notify_all();
+
+    id
+}
+
+
+/// Submits a task unless a task of the same kind is already queued, e.g. to batch the changes to the search index.
+///
+/// # Arguments
+///
+/// * `kind` - The kind of the task.
+/// * `label` - The description of the task shown to the user.
+/// * `priority` - The priority of the task.
+///
+/// # Returns
+///
+/// Returns the ID of the queued task, or of the new one.
+pub fn submit_unless_queued(kind: &str, label: &str, priority: TaskPriority) -> i64 {
+    let queued = TASKS.lock().unwrap().entries.values()
+        .find(|entry| entry.task.kind == kind && entry.task.status == TaskStatus::Queued)
+        .map(|entry| entry.task.id);
+
+    queued.unwrap_or_else(|| submit(kind, label, priority))
+}
+
+
+/// Lists the background tasks of the session.
+///
+/// # Returns
+///
+/// Returns the running and queued tasks, then up to the last 100 finished tasks, newest first.
+pub fn list_background_tasks() -> Vec<BackgroundTask> {
+    let tasks = TASKS.lock().unwrap();
+    let mut background_tasks: Vec<BackgroundTask> = tasks.entries.values().rev().map(|entry| entry.task.clone()).collect();
+    background_tasks.sort_by_key(|task| match task.status {
+        TaskStatus::Running => 0,
+        TaskStatus::Queued => 1,
+        _ => 2,
+    });
+
+    background_tasks
+}
+
+
+/// Cancels a background task.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the task.
+///
+/// # Operation
+///
+/// * A queued task is cancelled right away.
+/// * A running task is asked to stop, and is marked as cancelled once it does.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the task is cancelled, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the task does not exist or is already finished.
+pub fn cancel_task(id: i64) -> Result<(), AppError> {
+    let mut tasks = TASKS.lock().unwrap();
+    let entry = tasks.entries.get_mut(&id).ok_or(AppError::NotFound("Task not found".to_string()))?;
+
+    match entry.task.status {
+        TaskStatus::Queued => {
+            entry.task.status = TaskStatus::Cancelled;
+            entry.task.finished_at = Some(chrono::Utc::now().timestamp());
+            entry.dirty = true;
+            TASKS_CHANGED.notify_all();
+        },
+        TaskStatus::Running => entry.cancelled.store(true, Ordering::SeqCst),
+        _ => return Err(AppError::Validation(format!("Task {} is already finished", id))),
+    }
+
+    Ok(())
+}
+
+
+/// Stops starting new tasks and waits for the running ones to finish, when the application shuts down.
+///
+/// # Arguments
+///
+/// * `deadline` - The instant after which the running tasks are no longer waited for.
+///
+/// # Operation
+///
+/// The tasks are saved in the "background_tasks" table once the running ones finished or the deadline passed,
+/// so the tasks still queued or running are resumed on the next start.
+///
+/// It must not be called while the database connection is locked.
+pub fn shut_down(deadline: std::time::Instant) {
+    {
+        let mut tasks = TASKS.lock().unwrap();
+        tasks.stopping = true;
+        while tasks.running_by_kind.values().sum::<usize>() > 0 {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            if timeout.is_zero() {
+                eprintln!("Exiting with background tasks still running");
+                break;
+            }
+            tasks = TASKS_CHANGED.wait_timeout(tasks, timeout).unwrap().0;
+        }
+    }
+
+    save_tasks();
+}
+
+
+/// Returns a new task ID. IDs are increasing timestamps in microseconds, so they stay unique across restarts.
+fn next_id(tasks: &mut TaskQueue) -> i64 {
+    let id = chrono::Utc::now().timestamp_micros().max(tasks.last_id + 1);
+    tasks.last_id = id;
+    id
+}
+
+
+/// Runs the background tasks until the application exits.
+fn run_worker() {
+    loop {
+        save_tasks();
+
+        let picked = {
+            let mut tasks = TASKS.lock().unwrap();
+            loop {
+                if let Some(picked) = pick_task(&mut tasks) {
+                    break Some(picked);
+                }
+                // Nothing to run, but some tasks still need to be saved
+                if tasks.entries.values().any(|entry| entry.dirty) {
+                    break None;
+                }
+                tasks = TASKS_CHANGED.wait(tasks).unwrap();
+            }
+        };
+        let Some((id, Some((run, context)))) = picked else {
+            continue;
+        };
+
+        let result = run(&context);
+
+        let mut tasks = TASKS.lock().unwrap();
+        if let Some(entry) = tasks.entries.get_mut(&id) {
+            entry.task.status = match &result {
+                _ if context.is_cancelled() => TaskStatus::Cancelled,
+                Ok(_) => TaskStatus::Completed,
+                Err(_) => TaskStatus::Failed,
+            };
+            entry.task.error = result.err();
+            entry.task.finished_at = Some(chrono::Utc::now().timestamp());
+            entry.dirty = true;
+
+            let kind = entry.task.kind.clone();
+            if let Some(running) = tasks.running_by_kind.get_mut(&kind) {
+                *running = running.saturating_sub(1);
+            }
+        }
+        forget_finished_tasks(&mut tasks);
+        TASKS_CHANGED.notify_all();
+    }
+}
+
+
+/// Picks the next task to run and marks it as running.
+///
+/// Returns the ID of the task and how to run it, or no way to run it if its kind is unknown, in which case it is
+/// marked as failed.
+fn pick_task(tasks: &mut TaskQueue) -> Option<(i64, Option<(TaskRun, TaskContext)>)> {
+    if tasks.stopping {
+        return None;
+    }
+    let running_by_kind = &tasks.running_by_kind;
+    let next = tasks.entries.values()
+        .filter(|entry| entry.task.status == TaskStatus::Queued)
+        .filter(|entry| match handler(&entry.task.kind) {
+            Some((max_concurrent, _)) => running_by_kind.get(&entry.task.kind).copied().unwrap_or(0) < max_concurrent,
+            None => true,
+        })
+        // The entries are sorted by ID, so the oldest task wins among the ones with the highest priority
+        .max_by(|a, b| a.task.priority.cmp(&b.task.priority).then(b.task.id.cmp(&a.task.id)))
+        .map(|entry| entry.task.id)?;
+
+    let entry = tasks.entries.get_mut(&next)?;
+    entry.dirty = true;
+    let Some((_, run)) = handler(&entry.task.kind) else {
+        entry.task.status = TaskStatus::Failed;
+        entry.task.error = Some(format!("Unknown task kind '{}'", entry.task.kind));
+        entry.task.finished_at = Some(chrono::Utc::now().timestamp());
+        return Some((next, None));
+    };
+
+    entry.task.status = TaskStatus::Running;
+    entry.task.started_at = Some(chrono::Utc::now().timestamp());
+    let context = TaskContext {
+        cancelled: entry.cancelled.clone(),
+    };
+    let kind = entry.task.kind.clone();
+    *tasks.running_by_kind.entry(kind).or_insert(0) += 1;
+
+    Some((next, Some((run, context))))
+}
+
+
+/// Saves the tasks that changed since they were last saved in the "background_tasks" table.
+fn save_tasks() {
+    let changed: Vec<BackgroundTask> = {
+        let mut tasks = TASKS.lock().unwrap();
+        tasks.entries.values_mut()
+            .filter(|entry| entry.dirty)
+            .map(|entry| {
+                entry.dirty = false;
+                entry.task.clone()
+            })
+            .collect()
+    };
+    if changed.is_empty() {
+        return;
+    }
+
+    let conn = CONNECTION.lock().unwrap();
+    for task in changed {
+        let result = conn.execute(
+            "INSERT OR REPLACE INTO background_tasks (id, kind, label, priority, status, created_at, started_at, finished_at, error)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![task.id, task.kind, task.label, task.priority.as_str(), task.status.as_str(),
+                task.created_at, task.started_at, task.finished_at, task.error],
+        );
+        if let Err(e) = result {
+            eprintln!("Failed to save background task {}: {}", task.id, e);
+        }
+    }
+}
+
+
+/// Removes the oldest finished tasks from memory once they are saved, keeping the last 100.
+fn forget_finished_tasks(tasks: &mut TaskQueue) {
+    let finished: Vec<i64> = tasks.entries.values()
+        .filter(|entry| !entry.dirty && matches!(entry.task.status, TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled))
+        .map(|entry| entry.task.id)
+        .collect();
+
+    for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_TASKS)) {
+        tasks.entries.remove(id);
+    }
+}
+
+
+/// Queues the tasks left unfinished when the application last exited and removes the old finished tasks.
+fn resume_tasks() -> Result<(), String> {
+    let unfinished = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.execute(
+            "DELETE FROM background_tasks WHERE status IN ('completed', 'failed', 'cancelled') AND finished_at < ?1",
+            params![chrono::Utc::now().timestamp() - FINISHED_TASKS_RETENTION_DAYS * 24 * 60 * 60],
+        ).map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, kind, label, priority, created_at FROM background_tasks WHERE status IN ('queued', 'running') ORDER BY id"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        }).map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+    };
+
+    let mut tasks = TASKS.lock().unwrap();
+    for (id, kind, label, priority, created_at) in unfinished {
+        tasks.last_id = tasks.last_id.max(id);
+        tasks.entries.insert(id, TaskEntry {
+            task: BackgroundTask {
+                id,
+                kind,
+                label,
+                priority: TaskPriority::from_str(&priority),
+                status: TaskStatus::Queued,
+                created_at,
+                started_at: None,
+                finished_at: None,
+                error: None,
+            },
+            cancelled: Arc::new(AtomicBool::new(false)),
+            dirty: true,
+        });
+    }
+
+    Ok(())
+}
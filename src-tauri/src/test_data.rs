@@ -0,0 +1,237 @@
+// test_data.rs
+
+use crate::{ local_operations, note_events, search_index, status_operations, models::Note };
+use crate::local_operations::CONNECTION;
+use rusqlite::params;
+
+
+/// The maximum number of notes generated at once.
+const MAX_NOTES: usize = 100_000;
+
+/// The maximum average size of the generated notes, in bytes.
+const MAX_AVERAGE_SIZE: usize = 100_000;
+
+/// The maximum number of distinct tags.
+const MAX_TAGS: usize = 1_000;
+
+/// The number of notes inserted per transaction, so the database is not locked for the whole generation.
+const BATCH_SIZE: usize = 500;
+
+/// The number of seconds over which the creation dates are spread, back from now: about five years.
+const DATE_SPREAD_SECONDS: u64 = 5 * 365 * 24 * 60 * 60;
+
+/// The seed of the generator, so the same arguments always generate the same notes and measures can be compared.
+const SEED: u64 = 0x5EED_0FC0_FFEE;
+
+/// The words the notes are made of.
+const LOREM_WORDS: [&str; 64] = [
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do", "eiusmod", "tempor",
+    "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua", "enim", "ad", "minim", "veniam", "quis",
+    "nostrud", "exercitation", "ullamco", "laboris", "nisi", "aliquip", "ex", "ea", "commodo", "consequat", "duis",
+    "aute", "irure", "in", "reprehenderit", "voluptate", "velit", "esse", "cillum", "fugiat", "nulla", "pariatur",
+    "excepteur", "sint", "occaecat", "cupidatat", "non", "proident", "sunt", "culpa", "qui", "officia", "deserunt",
+    "mollit", "anim", "id", "est", "laborum", "vitae", "porta",
+];
+
+/// The languages of the generated code blocks.
+const CODE_LANGUAGES: [&str; 4] = ["rust", "python", "javascript", "sql"];
+
+
+/// Fills the vault with synthetic notes, to measure the listing, search, and sync of a large vault.
+///
+/// # Arguments
+///
+/// * `n_notes` - The number of notes to generate, up to 100,000.
+/// * `avg_size` - The average size of their content in bytes, up to 100,000. Each note is between half and one and
+///   a half times this size.
+/// * `n_tags` - The number of distinct tags the notes are tagged with, up to 1,000, e.g. "tag-12", one to three per
+///   note. With no tags, the notes have none.
+///
+/// # Operation
+///
+/// * Each note has a lorem ipsum title and markdown content made of headings, paragraphs, lists, and now and then
+///   a code block. About one note in twenty is secure, and some have a status of the workflow.
+/// * The creation dates are spread over the last five years, and some notes were updated after their creation.
+/// * The notes are generated from a fixed seed, so the same arguments generate the same notes.
+/// * They are inserted by batches of 500, each in its own transaction, with their tags, and their citations and
+///   code blocks are recorded. The search index is rebuilt once they are all inserted.
+///
+/// # Returns
+///
+/// Returns the number of notes generated, or `Err(String)` if an error occurs. The batches inserted before an error are kept.
+///
+/// # Errors
+///
+/// This function will return an error if an argument is out of range, if a note cannot be encrypted, or if there
+/// is an issue with the database connection.
+pub fn generate_test_data(n_notes: usize, avg_size: usize, n_tags: usize) -> Result<usize, String> {
+    if n_notes == 0 || n_notes > MAX_NOTES {
+        return Err(format!("The number of notes should be between 1 and {}", MAX_NOTES));
+    }
+    if avg_size == 0 || avg_size > MAX_AVERAGE_SIZE {
+        return Err(format!("The average size should be between 1 and {} bytes", MAX_AVERAGE_SIZE));
+    }
+    if n_tags > MAX_TAGS {
+        return Err(format!("The number of tags should be at most {}", MAX_TAGS));
+    }
+
+    let statuses = status_operations::get_status_workflow()?.statuses;
+    let now = chrono::Utc::now().timestamp();
+    let mut random = Random(SEED);
+    let mut generated = 0;
+
+    while generated < n_notes {
+        let batch_size = BATCH_SIZE.min(n_notes - generated);
+        let notes: Vec<Note> = (0..batch_size).map(|_| generate_note(&mut random, avg_size, n_tags, &statuses, now)).collect();
+
+        let mut conn = CONNECTION.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for note in &notes {
+            let note_id = local_operations::insert_note(&tx, note)?;
+            if let Some(updated_at) = note.updated_at {
+                tx.execute("UPDATE notes SET updated_at = ?1 WHERE id = ?2", params![updated_at, note_id])
+                    .map_err(|e| e.to_string())?;
+            }
+            note_events::note_saved(&tx, note_id, &note.content)?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+
+        generated += batch_size;
+    }
+
+    // A single rebuild is faster than reindexing each of the notes
+    search_index::schedule_rebuild();
+
+    Ok(generated)
+}
+
+
+/// Generates notes without inserting them, e.g. to measure the core operations on scratch data.
+///
+/// # Arguments
+///
+/// * `n_notes` - The number of notes to generate.
+/// * `avg_size` - The average size of their content in bytes.
+/// * `n_tags` - The number of distinct tags the notes are tagged with.
+///
+/// # Returns
+///
+/// Returns the notes, generated from the same seed as `generate_test_data`, without status and numbered from 1.
+#[cfg(feature = "benchmarks")]
+pub fn generate_notes(n_notes: usize, avg_size: usize, n_tags: usize) -> Vec<Note> {
+    let now = chrono::Utc::now().timestamp();
+    let mut random = Random(SEED);
+
+    (1..=n_notes as i64)
+        .map(|id| Note {
+            id: Some(id),
+            ..generate_note(&mut random, avg_size.max(1), n_tags, &[], now)
+        })
+        .collect()
+}
+
+
+/// Generates a note.
+fn generate_note(random: &mut Random, avg_size: usize, n_tags: usize, statuses: &[String], now: i64) -> Note {
+    let title_length = 3 + random.below(5);
+    let title = capitalize(&words(random, title_length));
+
+    let target_size = (avg_size / 2 + random.below(avg_size + 1)).max(1);
+    let tags: Vec<String> = match n_tags {
+        0 => Vec::new(),
+        _ => (0..1 + random.below(3)).map(|_| format!("tag-{}", 1 + random.below(n_tags))).collect(),
+    };
+    let mut content = String::new();
+    while content.len() < target_size {
+        match random.below(10) {
+            0 => {
+                let count = 2 + random.below(4);
+                content.push_str(&format!("## {}\n\n", capitalize(&words(random, count))));
+            },
+            1 => {
+                for _ in 0..2 + random.below(4) {
+                    let count = 3 + random.below(8);
+                    content.push_str(&format!("- {}\n", words(random, count)));
+                }
+                content.push('\n');
+            },
+            2 if random.below(3) == 0 => {
+                let language = CODE_LANGUAGES[random.below(CODE_LANGUAGES.len())];
+                let count = 4 + random.below(12);
+                content.push_str(&format!("```{}\n{}\n```\n\n", language, words(random, count)));
+            },
+            _ => {
+                let sentences: Vec<String> = (0..2 + random.below(5))
+                    .map(|_| {
+                        let count = 6 + random.below(11);
+                        format!("{}.", capitalize(&words(random, count)))
+                    })
+                    .collect();
+                content.push_str(&sentences.join(" "));
+                content.push_str("\n\n");
+            },
+        }
+    }
+    let mut end = target_size.min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    content.truncate(end);
+
+    let created_at = now - random.below(DATE_SPREAD_SECONDS as usize) as i64;
+    let updated_at = (random.below(3) == 0).then(|| created_at + random.below((now - created_at) as usize + 1) as i64);
+    let status = (!statuses.is_empty() && random.below(3) == 0).then(|| statuses[random.below(statuses.len())].clone());
+
+    Note {
+        id: None,
+        uuid: None,
+        title: local_operations::truncate_title(&title),
+        content,
+        nonce: None,
+        created_at,
+        updated_at,
+        timestamp: None,
+        secure: random.below(20) == 0,
+        status,
+        icon: None,
+        cover_attachment_id: None,
+        locked: false,
+        pinned: false,
+        archived: false,
+        notebook_id: None,
+        aliases: Vec::new(),
+        tags,
+    }
+}
+
+
+/// Returns a number of random lorem ipsum words separated by spaces.
+fn words(random: &mut Random, count: usize) -> String {
+    (0..count).map(|_| LOREM_WORDS[random.below(LOREM_WORDS.len())]).collect::<Vec<_>>().join(" ")
+}
+
+
+/// Returns a text with its first letter in uppercase.
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+
+/// A small pseudorandom generator (SplitMix64), good enough for test data and reproducible from its seed.
+struct Random(u64);
+
+impl Random {
+    /// Returns a number between 0 and `bound`, excluded. The bound should not be 0.
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z % bound as u64) as usize
+    }
+}
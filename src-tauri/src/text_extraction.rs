@@ -0,0 +1,234 @@
+// text_extraction.rs
+
+use crate::{ attachment_operations, crypto, search_index, tasks };
+use crate::local_operations::CONNECTION;
+use crate::models::{AppError, AttachmentText, TaskPriority};
+use crate::tasks::TaskContext;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{BTreeSet, HashMap};
+
+
+/// The status of an attachment whose text waits to be extracted.
+const PENDING: &str = "pending";
+
+/// The status of an attachment whose text is extracted and indexed.
+const EXTRACTED: &str = "extracted";
+
+/// The status of an attachment whose text could not be extracted.
+const FAILED: &str = "failed";
+
+/// The MIME types whose text can be extracted.
+const EXTRACTABLE_TYPES: [&str; 1] = ["application/pdf"];
+
+
+/// Queues the extraction of the text of a new attachment, if its type has text to extract.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `attachment_id` - The ID of the attachment.
+/// * `mime_type` - The MIME type of the attachment.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn queue_extraction(conn: &Connection, attachment_id: i64, mime_type: &str) -> Result<(), String> {
+    if !EXTRACTABLE_TYPES.contains(&mime_type) {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT OR REPLACE INTO attachment_texts (attachment_id, status) VALUES (?1, ?2)",
+        params![attachment_id, PENDING],
+    ).map_err(|e| e.to_string())?;
+    tasks::submit_unless_queued(tasks::EXTRACT_TEXT, "Extract the text of attachments", TaskPriority::Low);
+
+    Ok(())
+}
+
+
+/// Queues the text of the attachments added before text extraction existed, and submits the extraction task if any
+/// text waits to be extracted. It is called once at startup.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn resume_extractions() -> Result<(), String> {
+    let pending: i64 = {
+        let conn = CONNECTION.lock().unwrap();
+        let placeholders = vec!["?"; EXTRACTABLE_TYPES.len()].join(", ");
+        conn.execute(
+            &format!("INSERT OR IGNORE INTO attachment_texts (attachment_id, status) SELECT id, '{}' FROM attachments WHERE mime_type IN ({})", PENDING, placeholders),
+            rusqlite::params_from_iter(EXTRACTABLE_TYPES),
+        ).map_err(|e| e.to_string())?;
+        conn.query_row("SELECT COUNT(*) FROM attachment_texts WHERE status = ?1", params![PENDING], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+    };
+
+    if pending > 0 {
+        tasks::submit_unless_queued(tasks::EXTRACT_TEXT, "Extract the text of attachments", TaskPriority::Low);
+    }
+    Ok(())
+}
+
+
+/// Runs the extraction task, which extracts the text of every pending attachment, one at a time.
+///
+/// The text is stored encrypted in the "attachment_texts" table and the note of the attachment is reindexed,
+/// so searches match the text of its attachments. A file that cannot be read is marked as failed with the error.
+pub fn run_extraction(context: &TaskContext) -> Result<(), String> {
+    while !context.is_cancelled() {
+        let next: Option<(i64, i64)> = {
+            let conn = CONNECTION.lock().unwrap();
+            conn.query_row(
+                "SELECT attachment_texts.attachment_id, attachments.note_id FROM attachment_texts
+                JOIN attachments ON attachments.id = attachment_texts.attachment_id
+                WHERE attachment_texts.status = ?1 ORDER BY attachment_texts.attachment_id LIMIT 1",
+                params![PENDING],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).optional().map_err(|e| e.to_string())?
+        };
+        let Some((attachment_id, note_id)) = next else {
+            break;
+        };
+
+        // Extract outside of the lock, since large files take a while
+        let result = attachment_operations::read_attachment(attachment_id).map_err(String::from).and_then(|(_, data)| extract_pdf_text(&data));
+        let conn = CONNECTION.lock().unwrap();
+        match result {
+            Ok(text) => {
+                let (encrypted_text, nonce_str) = crypto::encrypt_content(&text)?;
+                conn.execute(
+                    "UPDATE attachment_texts SET status = ?1, text = ?2, nonce = ?3, characters = ?4, error = NULL, extracted_at = ?5
+                    WHERE attachment_id = ?6",
+                    params![EXTRACTED, encrypted_text, nonce_str, text.chars().count() as i64, chrono::Utc::now().timestamp(), attachment_id],
+                ).map_err(|e| e.to_string())?;
+            },
+            Err(e) => {
+                conn.execute(
+                    "UPDATE attachment_texts SET status = ?1, text = NULL, nonce = NULL, characters = NULL, error = ?2, extracted_at = ?3
+                    WHERE attachment_id = ?4",
+                    params![FAILED, e, chrono::Utc::now().timestamp(), attachment_id],
+                ).map_err(|e| e.to_string())?;
+            },
+        }
+        search_index::schedule_reindex(note_id);
+    }
+
+    Ok(())
+}
+
+
+/// Extracts the text of an attachment again, e.g. after its extraction failed.
+///
+/// # Arguments
+///
+/// * `attachment_id` - The ID of the attachment.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the extraction is queued successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the attachment does not exist, if its type has no text to extract,
+/// or if there is an issue with the database connection.
+pub fn reextract_attachment_text(attachment_id: i64) -> Result<(), AppError> {
+    let conn = CONNECTION.lock().unwrap();
+    let (file_name, mime_type): (String, String) = conn.query_row(
+        "SELECT file_name, mime_type FROM attachments WHERE id = ?1",
+        params![attachment_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(AppError::NotFound("Attachment not found".to_string()))?;
+    if !EXTRACTABLE_TYPES.contains(&mime_type.as_str()) {
+        return Err(AppError::Validation(format!("{} has no text to extract", file_name)));
+    }
+
+    Ok(queue_extraction(&conn, attachment_id, &mime_type)?)
+}
+
+
+/// Retrieves the extraction status of the text of an attachment.
+///
+/// # Arguments
+///
+/// * `attachment_id` - The ID of the attachment.
+///
+/// # Returns
+///
+/// Returns an `AttachmentText` with the status, "pending", "extracted", or "failed", the error of a failed
+/// extraction, and the number of characters extracted, or `None` if the attachment has no text to extract.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_attachment_text_status(attachment_id: i64) -> Result<Option<AttachmentText>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    conn.query_row(
+        "SELECT attachment_id, status, error, characters, extracted_at FROM attachment_texts WHERE attachment_id = ?1",
+        params![attachment_id],
+        |row| Ok(AttachmentText {
+            attachment_id: row.get(0)?,
+            status: row.get(1)?,
+            error: row.get(2)?,
+            characters: row.get(3)?,
+            extracted_at: row.get(4)?,
+        }),
+    ).optional().map_err(|e| e.to_string())
+}
+
+
+/// Retrieves the extracted text of the attachments of local notes, to index it with the notes.
+///
+/// # Arguments
+///
+/// * `note_ids` - The IDs of the notes, or `None` for every note.
+///
+/// # Returns
+///
+/// Returns the text of the attachments of each note with any, decrypted and joined by empty lines.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_extracted_texts(note_ids: Option<&BTreeSet<i64>>) -> Result<HashMap<i64, String>, String> {
+    let mut query = "SELECT attachments.note_id, attachment_texts.text, attachment_texts.nonce FROM attachment_texts
+        JOIN attachments ON attachments.id = attachment_texts.attachment_id WHERE attachment_texts.status = ?1".to_string();
+    let mut values: Vec<&dyn rusqlite::ToSql> = vec![&EXTRACTED as &dyn rusqlite::ToSql];
+    if let Some(note_ids) = note_ids {
+        query.push_str(&format!(" AND attachments.note_id IN ({})", vec!["?"; note_ids.len()].join(", ")));
+        values.extend(note_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    query.push_str(" ORDER BY attachments.id");
+
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(values.as_slice(), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut texts: HashMap<i64, String> = HashMap::new();
+    for (note_id, encrypted_text, nonce_str) in rows {
+        // A text that cannot be decrypted is left out of the index rather than failing it
+        let Ok(text) = crypto::decrypt_content(&encrypted_text, &nonce_str) else {
+            continue;
+        };
+        let note_text = texts.entry(note_id).or_default();
+        if !note_text.is_empty() {
+            note_text.push_str("\n\n");
+        }
+        note_text.push_str(&text);
+    }
+
+    Ok(texts)
+}
+
+
+/// Extracts the text of a PDF file.
+fn extract_pdf_text(data: &[u8]) -> Result<String, String> {
+    // The parser panics on some malformed files, which must not bring down the worker
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data))
+        .map_err(|_| "Malformed PDF".to_string())?
+        .map_err(|e| format!("Failed to extract the text: {}", e))
+}
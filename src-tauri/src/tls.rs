@@ -0,0 +1,223 @@
+// tls.rs
+
+use crate::{ proxy, s3_operations, settings, models::TlsConnectionTest, models::TlsRemote };
+use reqwest::{Certificate, ClientBuilder, Url};
+
+
+/// The settings key under which the certificates trusted for the self-hosted remotes are stored, as JSON.
+const REMOTES_SETTING: &str = "tls_remotes";
+
+/// The words by which the errors of the TLS handshake are told apart from the other connection errors.
+const TLS_ERROR_WORDS: &[&str] = &["certificate", "tls", "ssl", "handshake", "x509", "trust"];
+
+
+/// Lists the remotes trusted with their own certificates.
+///
+/// # Returns
+///
+/// Returns a vector of `TlsRemote`, sorted by host, with the PEM of their CA bundle or pinned certificate.
+///
+/// # Errors
+///
+/// This function will return an error if the stored remotes are malformed or if there is an issue with the database connection.
+pub fn get_tls_remotes() -> Result<Vec<TlsRemote>, String> {
+    match settings::get_setting(REMOTES_SETTING)? {
+        Some(remotes) => serde_json::from_str(&remotes).map_err(|e| format!("Invalid TLS settings: {}", e)),
+        None => Ok(Vec::new()),
+    }
+}
+
+
+/// Trusts a custom CA bundle or a pinned certificate for a remote, e.g. a MinIO server signed by a private CA.
+///
+/// # Arguments
+///
+/// * `host` - The host of the remote, e.g. "minio.example.com", which applies to its subdomains too. An address
+///   such as "https://minio.example.com:9000" is accepted, of which only the host is kept.
+/// * `ca_bundle_path` - The path of a PEM file with the certificates of the CAs to trust for the remote, on top of
+///   the CAs of the system.
+/// * `pinned_certificate_path` - The path of a PEM file with the only certificate to trust for the remote, e.g. its
+///   self-signed certificate. The CAs of the system are then no longer trusted for it.
+///
+/// # Operation
+///
+/// The PEM files are copied in the settings, so they can be moved or deleted afterwards. The certificates apply to
+/// the S3 requests sent from then on, and replace the ones trusted for the remote before.
+///
+/// # Returns
+///
+/// Returns the stored `TlsRemote`.
+///
+/// # Errors
+///
+/// This function will return an error if the host is invalid, if neither file is given, if a file cannot be read or
+/// holds no valid certificate, or if there is an issue with the database connection.
+pub fn set_tls_remote(host: &str, ca_bundle_path: Option<&str>, pinned_certificate_path: Option<&str>) -> Result<TlsRemote, String> {
+    let remote = TlsRemote {
+        host: parse_address(host)?.host_str().unwrap_or_default().to_string(),
+        ca_bundle: ca_bundle_path.map(read_pem).transpose()?,
+        pinned_certificate: pinned_certificate_path.map(read_pem).transpose()?,
+    };
+    if remote.ca_bundle.is_none() && remote.pinned_certificate.is_none() {
+        return Err("Give a CA bundle or a certificate to pin for the remote".to_string());
+    }
+    // The certificates are checked before they are stored
+    trust(reqwest::Client::builder(), &remote)?.build().map_err(|e| e.to_string())?;
+
+    let mut remotes = get_tls_remotes()?;
+    remotes.retain(|other| other.host != remote.host);
+    remotes.push(remote.clone());
+    remotes.sort_by(|a, b| a.host.cmp(&b.host));
+    let json = serde_json::to_string(&remotes).map_err(|e| e.to_string())?;
+    settings::set_setting(REMOTES_SETTING, &json)?;
+    s3_operations::invalidate_s3_client();
+
+    Ok(remote)
+}
+
+
+/// Stops trusting the certificates of a remote, which is then checked against the CAs of the system again.
+///
+/// # Arguments
+///
+/// * `host` - The host of the remote.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the remote is removed, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if no certificate is trusted for the host or if there is an issue with the database connection.
+pub fn delete_tls_remote(host: &str) -> Result<(), String> {
+    let host = parse_address(host)?.host_str().unwrap_or_default().to_string();
+    let mut remotes = get_tls_remotes()?;
+    let count = remotes.len();
+    remotes.retain(|remote| remote.host != host);
+    if remotes.len() == count {
+        return Err(format!("No certificate is trusted for {}", host));
+    }
+    let json = serde_json::to_string(&remotes).map_err(|e| e.to_string())?;
+    settings::set_setting(REMOTES_SETTING, &json)?;
+    s3_operations::invalidate_s3_client();
+    Ok(())
+}
+
+
+/// Tests the TLS connection to a remote, with the proxy and the certificates the S3 requests would use.
+///
+/// # Arguments
+///
+/// * `address` - The address of the remote, e.g. "https://minio.example.com:9000" or "minio.example.com".
+///
+/// # Returns
+///
+/// Returns a `TlsConnectionTest`, telling whether the connection succeeded and, if it failed, whether the
+/// certificate of the remote was rejected, with the reason given by the TLS library. Any HTTP response, e.g. 403,
+/// means the TLS handshake succeeded.
+///
+/// # Errors
+///
+/// This function will return an error if the address is invalid or if the proxy or the certificates cannot be read.
+pub async fn test_tls_connection(address: &str) -> Result<TlsConnectionTest, String> {
+    let url = parse_address(address)?;
+    let host = url.host_str().unwrap_or_default().to_string();
+    let client = proxy::client_for_host(&host)?;
+
+    Ok(match client.head(url.clone()).send().await {
+        Ok(response) => TlsConnectionTest {
+            host,
+            success: true,
+            tls_error: false,
+            status: Some(response.status().as_u16()),
+            message: format!("Connected to {} (HTTP {})", url, response.status().as_u16()),
+        },
+        Err(e) => {
+            let reason = error_chain(&e);
+            let tls_error = TLS_ERROR_WORDS.iter().any(|word| reason.to_lowercase().contains(word));
+            let message = match tls_error {
+                true => format!("The certificate of {} was rejected: {}", host, reason),
+                false => format!("Failed to connect to {}: {}", url, reason),
+            };
+            TlsConnectionTest { host, success: false, tls_error, status: None, message }
+        },
+    })
+}
+
+
+/// Returns the remote whose certificates are trusted for a host, the most specific one if several match.
+pub fn remote_for_host<'a>(remotes: &'a [TlsRemote], host: &str) -> Option<&'a TlsRemote> {
+    remotes.iter()
+        .filter(|remote| host_matches(&remote.host, host))
+        .max_by_key(|remote| remote.host.len())
+}
+
+
+/// Tells whether the certificates of a remote apply to a host: the host of the remote itself or one of its subdomains.
+pub fn host_matches(remote_host: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    host == remote_host || host.ends_with(&format!(".{}", remote_host))
+}
+
+
+/// Makes a client trust the certificates of a remote.
+///
+/// # Errors
+///
+/// This function will return an error if a stored certificate is not valid PEM.
+pub fn trust(builder: ClientBuilder, remote: &TlsRemote) -> Result<ClientBuilder, String> {
+    let mut builder = builder;
+    if let Some(pinned_certificate) = &remote.pinned_certificate {
+        let certificate = Certificate::from_pem(pinned_certificate.as_bytes())
+            .map_err(|e| format!("Invalid pinned certificate for {}: {}", remote.host, e))?;
+        builder = builder.tls_built_in_root_certs(false).add_root_certificate(certificate);
+    }
+    if let Some(ca_bundle) = &remote.ca_bundle {
+        let certificates = Certificate::from_pem_bundle(ca_bundle.as_bytes())
+            .map_err(|e| format!("Invalid CA bundle for {}: {}", remote.host, e))?;
+        if certificates.is_empty() {
+            return Err(format!("The CA bundle for {} holds no certificate", remote.host));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder)
+}
+
+
+/// Parses the address of a remote, with or without its scheme.
+fn parse_address(address: &str) -> Result<Url, String> {
+    let address = address.trim();
+    let url = match address.contains("://") {
+        true => Url::parse(address),
+        false => Url::parse(&format!("https://{}", address)),
+    }.map_err(|e| format!("Invalid address {}: {}", address, e))?;
+    if url.scheme() != "https" || url.host_str().is_none() {
+        return Err(format!("Invalid address {}: it should be an HTTPS address", address));
+    }
+    Ok(url)
+}
+
+
+/// Reads a PEM file.
+fn read_pem(path: &str) -> Result<String, String> {
+    let pem = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    if !pem.contains("-----BEGIN CERTIFICATE-----") {
+        return Err(format!("{} holds no PEM certificate", path));
+    }
+    Ok(pem)
+}
+
+
+/// Joins the messages of an error and of its sources, where the TLS libraries give the reason of a failed handshake.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut messages = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(error) = source {
+        messages.push(error.to_string());
+        source = error.source();
+    }
+    messages.dedup();
+    messages.join(": ")
+}
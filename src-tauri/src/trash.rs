@@ -0,0 +1,202 @@
+// trash.rs
+
+use crate::{ crypto, note_events, notifier, settings, tasks, local_operations::CONNECTION, models::TaskPriority, models::TrashedNote, tasks::TaskContext };
+use rusqlite::{params, Connection, OptionalExtension};
+use std::time::Duration;
+
+
+/// The settings key under which the number of days notes are kept in the trash is stored.
+const RETENTION_SETTING: &str = "trash_retention_days";
+
+/// The number of days notes are kept in the trash when it is not configured.
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+
+/// Lists the notes in the trash (see `local_operations::delete_local_note`).
+///
+/// # Returns
+///
+/// Returns a vector of `TrashedNote`, the most recently deleted first, with the date each one is purged.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn list_trashed_notes() -> Result<Vec<TrashedNote>, String> {
+    let retention = get_trash_retention()?;
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, uuid, title, preview, secure, icon, deleted_at FROM notes
+        WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC"
+    ).map_err(|e| e.to_string())?;
+    let notes = stmt.query_map([], |row| {
+        let deleted_at: i64 = row.get(6)?;
+        Ok(TrashedNote {
+            id: row.get(0)?,
+            uuid: row.get(1)?,
+            title: row.get(2)?,
+            preview: row.get(3)?,
+            secure: row.get(4)?,
+            icon: row.get(5)?,
+            deleted_at,
+            purge_at: deleted_at + retention * 24 * 60 * 60,
+        })
+    }).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(notes)
+}
+
+
+/// Restores a note from the trash.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+///
+/// # Operation
+///
+/// The data derived from the content of the note is recorded again and the note is reindexed for search. Its
+/// aliases, tags, attachments, and flashcards were kept while it was in the trash. If the note was synced, it is
+/// uploaded again with the next sync.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is restored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note is not in the trash, if its content cannot be decrypted, or if
+/// there is an issue with the database connection.
+pub fn restore_note(id: i64) -> Result<(), String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let (title, encrypted_content, nonce_str) = trashed_note(&conn, id)?;
+    let content = crypto::decrypt_content(&encrypted_content, &nonce_str)?;
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("UPDATE notes SET deleted_at = NULL WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+    note_events::note_saved(&tx, id, &content)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    notifier::notify_grouped(
+        "restore",
+        "Local note restored",
+        &format!("Note '{}' was restored from the trash.", title),
+        "Local notes restored",
+        "{count} notes were restored from the trash.",
+    );
+    Ok(())
+}
+
+
+/// Deletes a note in the trash for good, with its attachments, aliases, tags, and the other data kept with it.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is purged successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note is not in the trash or if there is an issue with the database connection.
+pub fn purge_note(id: i64) -> Result<(), String> {
+    let mut conn = CONNECTION.lock().unwrap();
+    trashed_note(&conn, id)?;
+    purge(&mut conn, id)
+}
+
+
+/// Retrieves the number of days notes are kept in the trash before they are purged.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_trash_retention() -> Result<i64, String> {
+    Ok(settings::get_setting(RETENTION_SETTING)?
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS))
+}
+
+
+/// Sets the number of days notes are kept in the trash before they are purged.
+///
+/// # Arguments
+///
+/// * `days` - The number of days, at least 1. The notes already in the trash for longer are purged with the next purge.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setting is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the number of days is lower than 1 or if there is an issue with the database connection.
+pub fn set_trash_retention(days: i64) -> Result<(), String> {
+    if days < 1 {
+        return Err("Notes should be kept in the trash for at least 1 day".to_string());
+    }
+    settings::set_setting(RETENTION_SETTING, &days.to_string())
+}
+
+
+/// Starts the thread purging the trash, once at startup and then every day at midnight (UTC).
+pub fn start_daily_purge() {
+    std::thread::spawn(|| loop {
+        tasks::submit_unless_queued(tasks::TRASH_PURGE, "Empty the old notes from the trash", TaskPriority::Low);
+
+        let now = chrono::Utc::now();
+        let next_midnight = (now.date_naive() + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc();
+        std::thread::sleep((next_midnight - now).to_std().unwrap_or(Duration::from_secs(60 * 60)));
+    });
+}
+
+
+/// Runs the purge task, which deletes for good the notes kept in the trash for longer than the retention.
+///
+/// Each note is purged in a transaction of its own, so a cancelled purge keeps the notes already purged.
+pub fn run_purge(context: &TaskContext) -> Result<(), String> {
+    let cutoff = chrono::Utc::now().timestamp() - get_trash_retention()? * 24 * 60 * 60;
+    let ids = {
+        let conn = CONNECTION.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id FROM notes WHERE deleted_at IS NOT NULL AND deleted_at <= ?1")
+            .map_err(|e| e.to_string())?;
+        let ids = stmt.query_map(params![cutoff], |row| row.get::<_, i64>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+
+    for id in ids {
+        if context.is_cancelled() {
+            break;
+        }
+        let mut conn = CONNECTION.lock().unwrap();
+        purge(&mut conn, id)?;
+    }
+
+    Ok(())
+}
+
+
+/// Reads the title and the encrypted content of a note in the trash.
+fn trashed_note(conn: &Connection, id: i64) -> Result<(String, String, String), String> {
+    conn.query_row(
+        "SELECT title, content, nonce FROM notes WHERE id = ?1 AND deleted_at IS NOT NULL",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).optional().map_err(|e| e.to_string())?
+        .ok_or(format!("Note {} is not in the trash", id))
+}
+
+
+/// Deletes a note in the trash with the data kept with it, in a transaction.
+fn purge(conn: &mut Connection, id: i64) -> Result<(), String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM notes WHERE id = ?1 AND deleted_at IS NOT NULL", params![id]).map_err(|e| e.to_string())?;
+    note_events::note_deleted(&tx, id)?;
+    tx.commit().map_err(|e| e.to_string())
+}
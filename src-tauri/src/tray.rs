@@ -0,0 +1,235 @@
+// tray.rs
+
+use crate::{ file_associations, settings, shutdown, local_operations::CONNECTION, models::AppError, models::TrayMenuItem };
+use rusqlite::OptionalExtension;
+use std::sync::OnceLock;
+use tauri::{AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
+
+
+/// The settings key under which the IDs of the pinned notes are stored, as JSON, in the order of the menu.
+const PINS_SETTING: &str = "tray_pins";
+
+/// The number of notes that can be pinned to the tray, so the menu fits on the screen.
+const MAX_PINNED_NOTES: usize = 10;
+
+/// The event telling the window that the pinned notes changed, with the items of the menu.
+const TRAY_UPDATED_EVENT: &str = "tray-updated";
+
+/// The prefix of the IDs of the menu items of the pinned notes, followed by the ID of the note.
+const NOTE_ITEM_PREFIX: &str = "note-";
+
+/// The ID of the menu item showing the window.
+const SHOW_ITEM: &str = "show";
+
+/// The ID of the menu item exiting the application.
+const QUIT_ITEM: &str = "quit";
+
+/// The label of the main window.
+const MAIN_WINDOW: &str = "main";
+
+
+/// The handle of the application, which rebuilds the menu of the tray.
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+
+
+/// Returns the tray of the application, with a menu without pinned notes, since the database is opened after the
+/// application is set up. The pinned notes are added by `refresh` once it is open.
+pub fn system_tray() -> SystemTray {
+    SystemTray::new().with_menu(build_menu(&[]))
+}
+
+
+/// Gives the handle of the application, to rebuild the menu of the tray. It is called once the application is set up.
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+pub fn init(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+}
+
+
+/// Retrieves the items of the menu of the tray: the pinned notes, in the order they were pinned.
+///
+/// # Returns
+///
+/// Returns a vector of `TrayMenuItem`, without the pinned notes that were deleted since.
+///
+/// # Errors
+///
+/// This function will return an error if the stored pins are malformed or if there is an issue with the database connection.
+pub fn get_tray_menu_items() -> Result<Vec<TrayMenuItem>, String> {
+    let pins = get_pins()?;
+    let conn = CONNECTION.lock().unwrap();
+    let mut items = Vec::new();
+    for note_id in pins {
+        // The titles are not encrypted, so the menu is filled even while the vault is locked
+        let title: Option<String> = conn.query_row(
+            "SELECT title FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+            rusqlite::params![note_id],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+        if let Some(title) = title {
+            items.push(TrayMenuItem { note_id, title });
+        }
+    }
+    Ok(items)
+}
+
+
+/// Pins a note to the menu of the tray, to open it in one click.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note. A note already pinned keeps its place.
+///
+/// # Operation
+///
+/// The menu of the tray is rebuilt, and the window is told with the "tray-updated" event.
+///
+/// # Returns
+///
+/// Returns the items of the menu (see `get_tray_menu_items`).
+///
+/// # Errors
+///
+/// This function will return an error if the note does not exist, if `MAX_PINNED_NOTES` notes are already pinned,
+/// or if there is an issue with the database connection.
+pub fn pin_note_to_tray(note_id: i64) -> Result<Vec<TrayMenuItem>, AppError> {
+    let exists: bool = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM notes WHERE id = ?1 AND deleted_at IS NULL)",
+            rusqlite::params![note_id],
+            |row| row.get(0),
+        )?
+    };
+    if !exists {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+
+    // The pins of the deleted notes are dropped, so they do not count towards the limit
+    let mut pins: Vec<i64> = get_tray_menu_items()?.iter().map(|item| item.note_id).collect();
+    if !pins.contains(&note_id) {
+        if pins.len() >= MAX_PINNED_NOTES {
+            return Err(AppError::Validation(format!("At most {} notes can be pinned to the tray", MAX_PINNED_NOTES)));
+        }
+        pins.push(note_id);
+    }
+    set_pins(&pins)?;
+    Ok(refresh()?)
+}
+
+
+/// Unpins a note from the menu of the tray.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note. Unpinning a note that is not pinned does nothing.
+///
+/// # Operation
+///
+/// The menu of the tray is rebuilt, and the window is told with the "tray-updated" event.
+///
+/// # Returns
+///
+/// Returns the items of the menu (see `get_tray_menu_items`).
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn unpin_note_from_tray(note_id: i64) -> Result<Vec<TrayMenuItem>, String> {
+    let mut pins = get_pins()?;
+    pins.retain(|pin| *pin != note_id);
+    set_pins(&pins)?;
+    refresh()
+}
+
+
+/// Rebuilds the menu of the tray from the pinned notes, and tells the window with the "tray-updated" event.
+///
+/// # Returns
+///
+/// Returns the items of the menu (see `get_tray_menu_items`).
+///
+/// # Errors
+///
+/// This function will return an error if the pinned notes cannot be read. The menu is left as it is if it cannot be
+/// rebuilt, which is printed.
+pub fn refresh() -> Result<Vec<TrayMenuItem>, String> {
+    let items = get_tray_menu_items()?;
+    if let Some(app) = APP_HANDLE.get() {
+        if let Err(e) = app.tray_handle().set_menu(build_menu(&items)) {
+            eprintln!("Failed to rebuild the menu of the tray: {}", e);
+        }
+        if let Err(e) = app.emit_all(TRAY_UPDATED_EVENT, &items) {
+            eprintln!("Failed to tell the window about the pinned notes: {}", e);
+        }
+    }
+    Ok(items)
+}
+
+
+/// Handles the events of the tray: a left click shows the window, and the items of the menu open a pinned note,
+/// show the window, or exit the application after flushing the pending writes (see `shutdown::begin`).
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+/// * `event` - The event of the tray.
+pub fn handle_event(app: &AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => show_window(app),
+        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            SHOW_ITEM => show_window(app),
+            QUIT_ITEM => shutdown::begin(app.clone()),
+            id => {
+                if let Some(note_id) = id.strip_prefix(NOTE_ITEM_PREFIX).and_then(|note_id| note_id.parse().ok()) {
+                    show_window(app);
+                    file_associations::show_notes(&[note_id]);
+                }
+            },
+        },
+        _ => {},
+    }
+}
+
+
+/// Builds the menu of the tray: the pinned notes, then the items showing the window and exiting the application.
+fn build_menu(items: &[TrayMenuItem]) -> SystemTrayMenu {
+    let mut menu = SystemTrayMenu::new();
+    for item in items {
+        menu = menu.add_item(CustomMenuItem::new(format!("{}{}", NOTE_ITEM_PREFIX, item.note_id), &item.title));
+    }
+    if !items.is_empty() {
+        menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+    }
+    menu.add_item(CustomMenuItem::new(SHOW_ITEM, "Show Custom Notes"))
+        .add_item(CustomMenuItem::new(QUIT_ITEM, "Quit"))
+}
+
+
+/// Shows the main window in front of the others, e.g. when it was minimized or hidden.
+fn show_window(app: &AppHandle) {
+    if let Some(window) = app.get_window(MAIN_WINDOW) {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+
+/// Reads the IDs of the pinned notes.
+fn get_pins() -> Result<Vec<i64>, String> {
+    match settings::get_setting(PINS_SETTING)? {
+        Some(pins) => serde_json::from_str(&pins).map_err(|e| format!("Invalid tray pins: {}", e)),
+        None => Ok(Vec::new()),
+    }
+}
+
+
+/// Stores the IDs of the pinned notes.
+fn set_pins(pins: &[i64]) -> Result<(), String> {
+    let json = serde_json::to_string(pins).map_err(|e| e.to_string())?;
+    settings::set_setting(PINS_SETTING, &json)
+}
@@ -0,0 +1,157 @@
+// update_operations.rs
+
+use crate::{ settings, models::UpdateInfo };
+use std::sync::OnceLock;
+use std::time::Duration;
+use tauri::AppHandle;
+
+
+/// The settings key under which the address of the update manifest is stored.
+const MANIFEST_URL_SETTING: &str = "update_manifest_url";
+
+/// The time given to the update server to answer.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// The handle of the application, which the updater needs to read the configuration and to restart it.
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+
+
+/// Gives the updater the handle of the application. It is called once the application is set up.
+///
+/// # Arguments
+///
+/// * `app` - The handle of the application.
+pub fn init(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+}
+
+
+/// Retrieves the address of the update manifest.
+///
+/// # Returns
+///
+/// Returns the address, or `None` if it was not configured.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_update_manifest_url() -> Result<Option<String>, String> {
+    settings::get_setting(MANIFEST_URL_SETTING)
+}
+
+
+/// Stores the address of the update manifest.
+///
+/// # Arguments
+///
+/// * `url` - The address of the manifest, served over HTTPS. It can hold the variables of the Tauri updater:
+///   `{{target}}`, `{{arch}}`, and `{{current_version}}`.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the address is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the address does not use HTTPS or if there is an issue with the database connection.
+pub fn set_update_manifest_url(url: &str) -> Result<(), String> {
+    let url = url.trim();
+    if !url.starts_with("https://") || url.len() <= "https://".len() {
+        return Err(format!("Invalid URL {}: the update manifest must be served over HTTPS", url));
+    }
+
+    settings::set_setting(MANIFEST_URL_SETTING, url)
+}
+
+
+/// Checks whether a newer version of the application is available.
+///
+/// # Operation
+///
+/// The update manifest is downloaded from its configured address and compared with the running version.
+///
+/// # Returns
+///
+/// Returns an `UpdateInfo` with the running version and, if an update is available, its version, date, and notes.
+///
+/// # Errors
+///
+/// This function will return an error if updates are not configured or if the manifest cannot be downloaded or read.
+pub async fn check_for_updates() -> Result<UpdateInfo, String> {
+    let app = app_handle()?;
+    let current_version = app.package_info().version.to_string();
+
+    match updater(&app)?.check().await {
+        Ok(update) if update.is_update_available() => Ok(UpdateInfo {
+            available: true,
+            current_version,
+            latest_version: Some(update.latest_version().to_string()),
+            date: update.date().map(|date| date.to_string()),
+            notes: update.body().cloned(),
+        }),
+        Ok(_) | Err(tauri::updater::Error::UpToDate) => Ok(UpdateInfo {
+            available: false,
+            current_version,
+            latest_version: None,
+            date: None,
+            notes: None,
+        }),
+        Err(e) => Err(format!("Failed to check for updates: {}", e)),
+    }
+}
+
+
+/// Downloads and installs the latest version of the application.
+///
+/// # Operation
+///
+/// * The manifest is checked again, so the version installed is the latest one.
+/// * The update is downloaded and its signature is verified with the public key of the "updater" section of
+///   "tauri.conf.json". An update whose signature does not match is not installed.
+/// * The progress is sent to the window with the "tauri://update-download-progress" event, holding the length of
+///   each chunk and the total length, and the status with the "tauri://update-status" event.
+/// * Once installed, the application restarts on Windows and Linux. On macOS, it runs the new version on its next start.
+///
+/// # Returns
+///
+/// Returns the version installed, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if updates are not configured, if no update is available, if the download
+/// fails, or if the signature of the update is invalid.
+pub async fn install_update() -> Result<String, String> {
+    let app = app_handle()?;
+
+    let update = match updater(&app)?.check().await {
+        Ok(update) if update.is_update_available() => update,
+        Ok(_) | Err(tauri::updater::Error::UpToDate) => return Err("The application is up to date".to_string()),
+        Err(e) => return Err(format!("Failed to check for updates: {}", e)),
+    };
+    let version = update.latest_version().to_string();
+
+    update.download_and_install().await.map_err(|e| format!("Failed to install version {}: {}", version, e))?;
+    Ok(version)
+}
+
+
+/// Returns the handle of the application given to `init`.
+fn app_handle() -> Result<AppHandle, String> {
+    APP_HANDLE.get().cloned().ok_or("The application is still starting".to_string())
+}
+
+
+/// Builds the updater querying the configured manifest.
+fn updater(app: &AppHandle) -> Result<tauri::updater::UpdateBuilder<tauri::Wry>, String> {
+    // Without a public key, the signature of an update could not be verified
+    if app.config().tauri.updater.pubkey.trim().is_empty() {
+        return Err("Updates are not configured: the public key of the updater is missing".to_string());
+    }
+    let url = get_update_manifest_url()?.ok_or("Updates are not configured: the address of the update manifest is missing".to_string())?;
+
+    Ok(tauri::updater::builder(app.clone())
+        .endpoints(&[url])
+        .timeout(CHECK_TIMEOUT)
+        .skip_events())
+}
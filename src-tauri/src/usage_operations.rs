@@ -0,0 +1,400 @@
+// usage_operations.rs
+
+use crate::local_operations::CONNECTION;
+use crate::models::{CloudBudget, CloudUsageReport, RequestUsage};
+use crate::notifier;
+use crate::settings;
+use aws_smithy_runtime_api::client::http::{http_client_fn, HttpClient, HttpConnector, HttpConnectorFuture, SharedHttpConnector};
+use aws_smithy_runtime_api::client::orchestrator::HttpRequest;
+use lazy_static::lazy_static;
+use rusqlite::params;
+use std::sync::Mutex;
+
+
+/// The price in USD of 1,000 PUT, COPY, POST or LIST requests (S3 Standard, eu-west-3).
+const PRICE_PER_1000_WRITE_REQUESTS: f64 = 0.0053;
+
+/// The price in USD of 1,000 GET or HEAD requests (S3 Standard, eu-west-3).
+const PRICE_PER_1000_READ_REQUESTS: f64 = 0.00042;
+
+/// The price in USD of one GB transferred out of S3 to the internet.
+const PRICE_PER_GB_TRANSFERRED_OUT: f64 = 0.09;
+
+/// The settings key under which the monthly budget is stored as JSON.
+const BUDGET_SETTING: &str = "cloud_budget";
+
+/// The settings key under which the month of the last budget warning is stored.
+const BUDGET_WARNED_SETTING: &str = "cloud_budget_warned_month";
+
+
+/// The budget and the running totals of the current month, so recording a request does not rebuild the report.
+struct BudgetState {
+    month: String,
+    budget: CloudBudget,
+    total_requests: i64,
+    total_bytes: i64,
+    warned: bool,
+}
+
+
+lazy_static! {
+    /// The budget state of the current month, loaded by the first request of the month or after the budget changes.
+    static ref BUDGET_STATE: Mutex<Option<BudgetState>> = Mutex::new(None);
+}
+
+
+/// Records the S3 requests sent by the S3 clients created from an AWS configuration, so every request counts towards
+/// the usage and the budget, whichever operation sends it.
+///
+/// # Arguments
+///
+/// * `config` - The AWS configuration.
+///
+/// # Operation
+///
+/// The HTTP client of the configuration is wrapped, so each request S3 answers is recorded once its response arrives
+/// (see `record_request`), retries included since S3 bills them. Its type is told from its method and its address
+/// (see `request_type`), and its bytes are the length of the body it uploads, or of the body it downloads for a GET.
+///
+/// # Returns
+///
+/// Returns the configuration, with its HTTP client wrapped.
+pub fn record_s3_requests(config: aws_config::SdkConfig) -> aws_config::SdkConfig {
+    let Some(http_client) = config.http_client().or_else(aws_smithy_runtime::client::http::hyper_014::default_client) else {
+        eprintln!("Failed to record the S3 requests: no HTTP client is available");
+        return config;
+    };
+
+    config.to_builder()
+        .http_client(http_client_fn(move |settings, components| {
+            SharedHttpConnector::new(RecordingConnector(http_client.http_connector(settings, components)))
+        }))
+        .build()
+}
+
+
+/// An HTTP connector recording the S3 requests it sends in the usage counters.
+#[derive(Debug)]
+struct RecordingConnector(SharedHttpConnector);
+
+impl HttpConnector for RecordingConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let request_type = request_type(request.method(), request.uri());
+        let uploaded_bytes = content_length(request.headers().get("content-length"));
+        let connector = self.0.clone();
+
+        HttpConnectorFuture::new(async move {
+            let response = connector.call(request).await?;
+            let downloaded_bytes = match request_type {
+                "GET" => content_length(response.headers().get("content-length")),
+                _ => 0,
+            };
+            record_request(request_type, uploaded_bytes + downloaded_bytes);
+            Ok(response)
+        })
+    }
+}
+
+
+/// Records an S3 request in the usage counters of the current month.
+///
+/// # Arguments
+///
+/// * `request_type` - The type of the request: "PUT", "GET", "LIST", "HEAD" or "DELETE".
+/// * `bytes` - The number of bytes uploaded or downloaded by the request.
+///
+/// # Operation
+///
+/// * The request and byte counters of the current month are incremented in the "cloud_usage" table.
+/// * The running totals of the month are updated in memory, and read from the table only on the first request of the
+///   month or after the budget changes.
+/// * If a monthly budget is configured and the request makes it exceeded for the first time this month, a desktop
+///   notification is sent.
+///
+/// Failing to record a request never fails the S3 operation itself, so errors are only printed.
+fn record_request(request_type: &str, bytes: u64) {
+    let month = current_month();
+
+    {
+        let conn = CONNECTION.lock().unwrap();
+        if let Err(e) = conn.execute(
+            "INSERT INTO cloud_usage (month, request_type, request_count, bytes) VALUES (?1, ?2, 1, ?3)
+            ON CONFLICT (month, request_type) DO UPDATE SET request_count = request_count + 1, bytes = bytes + ?3",
+            params![month, request_type, bytes as i64],
+        ) {
+            eprintln!("Failed to record S3 request: {}", e);
+            return;
+        }
+    }
+
+    if let Err(e) = warn_if_budget_exceeded(&month, bytes) {
+        eprintln!("Failed to check cloud budget: {}", e);
+    }
+}
+
+
+/// Builds the usage report of a month.
+///
+/// # Arguments
+///
+/// * `month` - The month of the report in "YYYY-MM" format. Defaults to the current month.
+///
+/// # Returns
+///
+/// Returns a `CloudUsageReport` with the requests per type, the totals, the estimated cost, and the budget status,
+/// or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn get_cloud_usage_report(month: Option<String>) -> Result<CloudUsageReport, String> {
+    let month = month.unwrap_or_else(current_month);
+
+    let requests = {
+        let conn = CONNECTION.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT request_type, request_count, bytes FROM cloud_usage WHERE month = ?1 ORDER BY request_type"
+        ).map_err(|e| e.to_string())?;
+        let usage_iter = stmt.query_map(params![month], |row| {
+            Ok(RequestUsage {
+                request_type: row.get(0)?,
+                request_count: row.get(1)?,
+                bytes: row.get(2)?,
+            })
+        }).map_err(|e| e.to_string())?;
+        usage_iter.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+    };
+
+    let total_requests = requests.iter().map(|usage| usage.request_count).sum();
+    let total_bytes = requests.iter().map(|usage| usage.bytes).sum();
+    let estimated_cost_usd = estimate_cost(&requests);
+    let budget = get_cloud_budget()?;
+    let budget_exceeded = exceeds(&budget, total_requests, total_bytes);
+
+    Ok(CloudUsageReport {
+        month,
+        requests,
+        total_requests,
+        total_bytes,
+        estimated_cost_usd,
+        budget,
+        budget_exceeded,
+    })
+}
+
+
+/// Retrieves the configured monthly budget.
+///
+/// # Returns
+///
+/// Returns the `CloudBudget`, which has no limits if none was configured, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or the stored budget is invalid.
+pub fn get_cloud_budget() -> Result<CloudBudget, String> {
+    match settings::get_setting(BUDGET_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| e.to_string()),
+        None => Ok(CloudBudget::default()),
+    }
+}
+
+
+/// Stores the monthly budget.
+///
+/// # Arguments
+///
+/// * `budget` - The maximum number of requests and bytes per month, and whether auto-sync should be paused
+///   once the budget is exceeded. A `None` limit means unlimited.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the budget is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn set_cloud_budget(budget: CloudBudget) -> Result<(), String> {
+    let value = serde_json::to_string(&budget).map_err(|e| e.to_string())?;
+    settings::set_setting(BUDGET_SETTING, &value)?;
+
+    // Warn again if the new budget is already exceeded, and reload the budget on the next request
+    settings::delete_setting(BUDGET_WARNED_SETTING)?;
+    BUDGET_STATE.lock().unwrap().take();
+
+    Ok(())
+}
+
+
+/// Tells whether the automatic sync is paused by the budget (see `sync::start_auto_sync`).
+///
+/// # Returns
+///
+/// Returns `true` if the budget asks to pause the automatic sync once it is exceeded, and it is exceeded this month.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or the stored budget is invalid.
+pub fn is_sync_paused() -> Result<bool, String> {
+    let month = current_month();
+    let mut state = BUDGET_STATE.lock().unwrap();
+    if state.as_ref().is_none_or(|current| current.month != month) {
+        *state = Some(load_budget_state(&month)?);
+    }
+
+    let current = state.as_ref().unwrap();
+    Ok(current.budget.pause_sync && exceeds(&current.budget, current.total_requests, current.total_bytes))
+}
+
+
+/// Adds a request to the running totals of the month, and sends a desktop notification when they cross the budget.
+///
+/// # Arguments
+///
+/// * `month` - The month of the request in "YYYY-MM" format.
+/// * `bytes` - The number of bytes uploaded or downloaded by the request.
+fn warn_if_budget_exceeded(month: &str, bytes: u64) -> Result<(), String> {
+    let mut state = BUDGET_STATE.lock().unwrap();
+    match state.as_mut() {
+        Some(current) if current.month == month => {
+            current.total_requests += 1;
+            current.total_bytes += bytes as i64;
+        }
+        // The totals read from the table already count this request
+        _ => *state = Some(load_budget_state(month)?),
+    }
+
+    let current = state.as_mut().unwrap();
+    if current.warned || !exceeds(&current.budget, current.total_requests, current.total_bytes) {
+        return Ok(());
+    }
+    settings::set_setting(BUDGET_WARNED_SETTING, month)?;
+    current.warned = true;
+
+    // Send a desktop notification
+    notifier::notify("Cloud budget exceeded", &format!("The cloud budget for {} is exceeded.", month));
+
+    Ok(())
+}
+
+
+/// Reads the budget, the totals of a month, and whether it was already warned about.
+fn load_budget_state(month: &str) -> Result<BudgetState, String> {
+    let (total_requests, total_bytes) = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            "SELECT COALESCE(SUM(request_count), 0), COALESCE(SUM(bytes), 0) FROM cloud_usage WHERE month = ?1",
+            params![month],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).map_err(|e| e.to_string())?
+    };
+
+    Ok(BudgetState {
+        month: month.to_string(),
+        budget: get_cloud_budget()?,
+        total_requests,
+        total_bytes,
+        warned: settings::get_setting(BUDGET_WARNED_SETTING)?.as_deref() == Some(month),
+    })
+}
+
+
+/// Estimates the cost in USD of the requests and transfers of a month.
+///
+/// Uploads are free, so only the bytes of GET requests are charged as data transfer out.
+fn estimate_cost(requests: &[RequestUsage]) -> f64 {
+    requests.iter().map(|usage| {
+        let request_cost = match usage.request_type.as_str() {
+            "PUT" | "LIST" => usage.request_count as f64 / 1000.0 * PRICE_PER_1000_WRITE_REQUESTS,
+            "GET" | "HEAD" => usage.request_count as f64 / 1000.0 * PRICE_PER_1000_READ_REQUESTS,
+            _ => 0.0,
+        };
+        let transfer_cost = match usage.request_type.as_str() {
+            "GET" => usage.bytes as f64 / 1_000_000_000.0 * PRICE_PER_GB_TRANSFERRED_OUT,
+            _ => 0.0,
+        };
+        request_cost + transfer_cost
+    }).sum()
+}
+
+
+/// Tells whether the totals of a month exceed the budget.
+fn exceeds(budget: &CloudBudget, total_requests: i64, total_bytes: i64) -> bool {
+    budget.max_requests.is_some_and(|max| total_requests > max)
+        || budget.max_bytes.is_some_and(|max| total_bytes > max)
+}
+
+
+/// Returns the type of an S3 request, as recorded in the "cloud_usage" table, from its method and its address.
+///
+/// The address names the bucket in its host or at the start of its path, so a listing is told by its "list-type"
+/// parameter, or by a GET of the root without parameters for the list of the buckets.
+fn request_type(method: &str, uri: &str) -> &'static str {
+    let path = uri.split_once("://").map(|(_, rest)| rest).unwrap_or(uri);
+    let path = path.find('/').map(|start| &path[start..]).unwrap_or("");
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    match method {
+        "GET" if query.split('&').any(|parameter| parameter.starts_with("list-type=")) => "LIST",
+        "GET" if (path.is_empty() || path == "/") && query.is_empty() => "LIST",
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "DELETE" => "DELETE",
+        "POST" if query.split('&').any(|parameter| parameter == "delete" || parameter.starts_with("delete=")) => "DELETE",
+        _ => "PUT",
+    }
+}
+
+
+/// Reads the length of a body from its "Content-Length" header, 0 if it has none.
+fn content_length(header: Option<&str>) -> u64 {
+    header.and_then(|length| length.parse().ok()).unwrap_or(0)
+}
+
+
+/// Returns the current month in "YYYY-MM" format.
+fn current_month() -> String {
+    chrono::Utc::now().format("%Y-%m").to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_type_reads_the_method_and_the_address() {
+        assert_eq!(request_type("GET", "https://notes.s3.eu-west-3.amazonaws.com/?list-type=2&max-keys=10"), "LIST");
+        assert_eq!(request_type("GET", "http://localhost:9000/notes?list-type=2"), "LIST");
+        assert_eq!(request_type("GET", "https://s3.eu-west-3.amazonaws.com/"), "LIST");
+        assert_eq!(request_type("GET", "https://notes.s3.eu-west-3.amazonaws.com/?tagging"), "GET");
+        assert_eq!(request_type("GET", "https://notes.s3.eu-west-3.amazonaws.com/notes/1234?x-id=GetObject"), "GET");
+        assert_eq!(request_type("HEAD", "https://notes.s3.eu-west-3.amazonaws.com/notes/1234"), "HEAD");
+        assert_eq!(request_type("PUT", "https://notes.s3.eu-west-3.amazonaws.com/notes/1234?x-id=PutObject"), "PUT");
+        assert_eq!(request_type("DELETE", "https://notes.s3.eu-west-3.amazonaws.com/notes/1234"), "DELETE");
+        assert_eq!(request_type("POST", "https://notes.s3.eu-west-3.amazonaws.com/?delete"), "DELETE");
+    }
+
+    #[test]
+    fn content_length_defaults_to_zero() {
+        assert_eq!(content_length(Some("1024")), 1024);
+        assert_eq!(content_length(Some("chunked")), 0);
+        assert_eq!(content_length(None), 0);
+    }
+
+    #[test]
+    fn sync_is_paused_from_the_running_totals() {
+        let budget = CloudBudget { max_requests: Some(10), max_bytes: None, pause_sync: true };
+        *BUDGET_STATE.lock().unwrap() = Some(BudgetState {
+            month: current_month(),
+            budget,
+            total_requests: 11,
+            total_bytes: 0,
+            warned: true,
+        });
+        assert!(is_sync_paused().unwrap());
+
+        BUDGET_STATE.lock().unwrap().as_mut().unwrap().total_requests = 10;
+        assert!(!is_sync_paused().unwrap());
+    }
+}
@@ -0,0 +1,58 @@
+// vault.rs
+
+use crate::{ key_management, search_index };
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+/// Whether the vault is unlocked for the current session.
+///
+/// The vault starts locked every time the application is launched.
+static VAULT_UNLOCKED: AtomicBool = AtomicBool::new(false);
+
+
+/// Tells whether the vault is unlocked.
+///
+/// # Returns
+///
+/// Returns `true` if secure notes can be revealed in the current session.
+pub fn is_unlocked() -> bool {
+    VAULT_UNLOCKED.load(Ordering::SeqCst)
+}
+
+
+/// Unlocks the vault for the current session so secure notes are revealed.
+///
+/// Secure notes left out of the search index while the vault was locked are indexed again (see `search_index`).
+/// When a passphrase is set, the notes are decrypted with the key derived from it (see `key_management::set_passphrase`).
+/// Otherwise, the vault is unlocked with the vault password (see `key_management::set_vault_password`).
+///
+/// # Arguments
+///
+/// * `passphrase` - The passphrase when one is set, or else the vault password. It is always required.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the vault is unlocked, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the passphrase or the password is missing or wrong, or if no vault password
+/// is set while there is no passphrase.
+pub fn unlock_vault(passphrase: Option<&str>) -> Result<(), String> {
+    key_management::unlock_content_key(passphrase)?;
+    VAULT_UNLOCKED.store(true, Ordering::SeqCst);
+    search_index::vault_unlocked();
+
+    Ok(())
+}
+
+
+/// Locks the vault so secure notes are redacted again.
+///
+/// Secure notes are also wiped from the search index if it only keeps them while the vault is unlocked. When a
+/// passphrase is set, the key derived from it is forgotten, so no note can be decrypted until the vault is unlocked.
+pub fn lock_vault() {
+    VAULT_UNLOCKED.store(false, Ordering::SeqCst);
+    key_management::forget_content_key();
+    search_index::vault_locked();
+}
@@ -0,0 +1,313 @@
+// vault_lock.rs
+
+use crate::{ app_paths, key_management, notifier, local_operations::CONNECTION, models::VaultLockOwner, models::VaultLockStatus };
+use lazy_static::lazy_static;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+
+/// The suffix of the lock file, next to the database.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// The name of the file holding the identifier of this device, in the configuration directory.
+const DEVICE_ID_FILE: &str = "device_id";
+
+/// The time between two refreshes of the lock by the instance holding it.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The time after which a lock that was not refreshed is stale, e.g. because its instance crashed on another device.
+const STALE_AFTER_SECONDS: i64 = 120;
+
+
+/// Whether this instance holds the lock, since when, and whether it let it go on shutdown.
+#[derive(Default)]
+struct LockState {
+    path: PathBuf,
+    held: bool,
+    acquired_at: i64,
+    released: bool,
+}
+
+
+lazy_static! {
+    /// The state of the lock of the vault. When the connection is needed too, it is locked first.
+    static ref LOCK_STATE: Mutex<LockState> = Mutex::new(LockState::default());
+
+    /// The identifier of this device, generated on first use and kept in the configuration directory, which belongs to
+    /// the user of the operating system, so two users pointed at the same vault are told apart.
+    static ref DEVICE_ID: String = read_device_id().unwrap_or_else(|e| {
+        eprintln!("Failed to read the identifier of the device: {}", e);
+        Uuid::new_v4().to_string()
+    });
+}
+
+
+/// Locks the vault for this instance, so two instances never write to the same database. It is called once the
+/// database is opened, before the connection is shared.
+///
+/// # Arguments
+///
+/// * `conn` - The connection to the database.
+/// * `db_path` - The path of the database, next to which the lock file is written.
+///
+/// # Operation
+///
+/// * The lock file holds the process ID and the device of the instance holding it, with the time it was last refreshed.
+/// * If the vault is locked by another instance that is still running, the connection is made read-only, so the notes
+///   can be read but not changed, until the lock is taken over (see `take_over_vault_lock`) or released.
+/// * A lock whose instance is no longer running on this device, or that was not refreshed for 2 minutes, is stale and
+///   taken over right away.
+/// * If the lock file cannot be written, e.g. on a read-only drive, the vault is opened without it.
+pub fn acquire(conn: &Connection, db_path: &Path) {
+    let mut state = LOCK_STATE.lock().unwrap();
+    state.path = PathBuf::from(format!("{}{}", db_path.display(), LOCK_SUFFIX));
+
+    match try_acquire(&mut state, false) {
+        Ok(true) => {},
+        Ok(false) => {
+            eprintln!("The vault is open in another instance, so it is opened read-only");
+            if let Err(e) = set_read_only(conn, true) {
+                eprintln!("Failed to open the vault read-only: {}", e);
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to lock the vault, so it is opened without a lock: {}", e);
+            state.held = true;
+        },
+    }
+}
+
+
+/// Refreshes the lock in the background while the application runs.
+///
+/// # Operation
+///
+/// * Every 30 seconds, the instance holding the lock refreshes it. If another instance took it over, the connection
+///   is made read-only and the user is notified.
+/// * An instance opened read-only takes the lock as soon as it is released or stale, and can write again.
+pub fn start_heartbeat() {
+    std::thread::spawn(|| loop {
+        std::thread::sleep(HEARTBEAT_INTERVAL);
+        if let Err(e) = heartbeat() {
+            eprintln!("Failed to refresh the vault lock: {}", e);
+        }
+    });
+}
+
+
+/// Retrieves the state of the lock of the vault.
+///
+/// # Returns
+///
+/// Returns whether this instance holds the lock, in which case the vault is writable, the instance holding it, and
+/// whether its lock is stale.
+pub fn get_vault_lock_status() -> VaultLockStatus {
+    let state = LOCK_STATE.lock().unwrap();
+    let owner = read_owner(&state.path);
+    VaultLockStatus {
+        held: state.held,
+        stale: owner.as_ref().is_some_and(|owner| !is_ours(owner) && is_stale(owner)),
+        owner,
+    }
+}
+
+
+/// Takes the lock of the vault over from the instance holding it, so this instance can write to the vault.
+///
+/// The other instance becomes read-only when it next refreshes the lock, within 30 seconds. Changes it makes until
+/// then are still saved, so the user should close it first when it is running.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if this instance holds the lock, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the lock file cannot be written, if another instance took the lock at the
+/// same time, or if there is an issue with the database connection.
+pub fn take_over_vault_lock() -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut state = LOCK_STATE.lock().unwrap();
+    if state.held {
+        return Ok(());
+    }
+
+    if !try_acquire(&mut state, true)? {
+        return Err("Another instance took the vault over at the same time".to_string());
+    }
+    set_read_only(&conn, false)
+}
+
+
+/// Makes the connection writable again if this instance holds the lock, e.g. once the master key is restored after
+/// it was missing on startup (see `key_management::disable_content_key`).
+///
+/// # Arguments
+///
+/// * `conn` - The database connection, locked by the caller.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection.
+pub fn refresh_read_only(conn: &Connection) -> Result<(), String> {
+    let state = LOCK_STATE.lock().unwrap();
+    set_read_only(conn, !state.held)
+}
+
+
+/// Releases the lock of the vault when the application exits, so the next instance does not wait for it to go stale.
+/// It is called while the database connection is locked, after the last write.
+pub fn release() {
+    let mut state = LOCK_STATE.lock().unwrap();
+    state.released = true;
+    if !state.held {
+        return;
+    }
+
+    state.held = false;
+    if read_owner(&state.path).is_some_and(|owner| is_ours(&owner)) {
+        if let Err(e) = std::fs::remove_file(&state.path) {
+            eprintln!("Failed to release the vault lock: {}", e);
+        }
+    }
+}
+
+
+/// Refreshes the lock if this instance holds it, or takes it if it is free or stale.
+fn heartbeat() -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut state = LOCK_STATE.lock().unwrap();
+    if state.released {
+        return Ok(());
+    }
+
+    match read_owner(&state.path) {
+        Some(owner) if !is_ours(&owner) && !is_stale(&owner) => {
+            if state.held {
+                state.held = false;
+                set_read_only(&conn, true)?;
+                notifier::notify(
+                    "Vault opened elsewhere",
+                    &format!("The vault was taken over by {}, so the changes made here are no longer saved.", describe_owner(&owner)),
+                );
+            }
+        },
+        _ => {
+            let was_held = state.held;
+            if try_acquire(&mut state, true)? && !was_held {
+                set_read_only(&conn, false)?;
+                eprintln!("The vault lock was released, so the vault is writable again");
+            }
+        },
+    }
+
+    Ok(())
+}
+
+
+/// Writes the lock of this instance unless another running instance holds it, or in any case if `force` is set.
+///
+/// Returns whether this instance holds the lock, which it does not if another instance wrote its own at the same time.
+fn try_acquire(state: &mut LockState, force: bool) -> Result<bool, String> {
+    if !force && read_owner(&state.path).is_some_and(|owner| !is_ours(&owner) && !is_stale(&owner)) {
+        return Ok(false);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    if !state.held {
+        state.acquired_at = now;
+    }
+    let owner = VaultLockOwner {
+        pid: std::process::id(),
+        device_id: DEVICE_ID.clone(),
+        host_name: std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")).ok(),
+        user_name: std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok(),
+        acquired_at: state.acquired_at,
+        heartbeat_at: now,
+    };
+
+    // The lock is written to a temporary file first, so it is never read half written
+    let json = serde_json::to_string(&owner).map_err(|e| e.to_string())?;
+    let temp_path = PathBuf::from(format!("{}.{}.tmp", state.path.display(), owner.pid));
+    std::fs::write(&temp_path, json).map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    if let Err(e) = std::fs::rename(&temp_path, &state.path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(format!("Failed to write {}: {}", state.path.display(), e));
+    }
+
+    state.held = read_owner(&state.path).is_some_and(|owner| is_ours(&owner));
+    Ok(state.held)
+}
+
+
+/// Reads the instance holding the lock, if any. A malformed lock file counts as no lock.
+fn read_owner(path: &Path) -> Option<VaultLockOwner> {
+    let json = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+
+/// Tells whether a lock is held by this instance.
+fn is_ours(owner: &VaultLockOwner) -> bool {
+    owner.pid == std::process::id() && owner.device_id == *DEVICE_ID
+}
+
+
+/// Tells whether a lock was not refreshed for 2 minutes, or was taken on this device by a process that is no longer running.
+fn is_stale(owner: &VaultLockOwner) -> bool {
+    chrono::Utc::now().timestamp() - owner.heartbeat_at > STALE_AFTER_SECONDS
+        || (owner.device_id == *DEVICE_ID && owner.pid != std::process::id() && !process_running(owner.pid))
+}
+
+
+/// Tells whether a process is running on this device. When it cannot be told, the process is deemed running and
+/// only the heartbeat tells whether its lock is stale.
+fn process_running(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        match Command::new("tasklist").args(["/FI", &format!("PID eq {}", pid), "/NH"]).output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).split_whitespace().any(|word| word == pid.to_string()),
+            Err(_) => true,
+        }
+    } else {
+        match Command::new("ps").args(["-p", &pid.to_string()]).output() {
+            Ok(output) => output.status.success(),
+            Err(_) => true,
+        }
+    }
+}
+
+
+/// Makes the connection read-only, or writable again unless the content key cannot be used.
+fn set_read_only(conn: &Connection, read_only: bool) -> Result<(), String> {
+    let read_only = read_only || key_management::get_encryption_key_error().is_some();
+    conn.pragma_update(None, "query_only", read_only).map_err(|e| e.to_string())
+}
+
+
+/// Describes the instance holding a lock to the user, e.g. "alice on laptop (process 1234)".
+fn describe_owner(owner: &VaultLockOwner) -> String {
+    format!(
+        "{} on {} (process {})",
+        owner.user_name.as_deref().unwrap_or("another user"),
+        owner.host_name.as_deref().unwrap_or("another device"),
+        owner.pid,
+    )
+}
+
+
+/// Reads the identifier of this device, generating it on first use.
+fn read_device_id() -> Result<String, String> {
+    let path = app_paths::config_dir()?.join(DEVICE_ID_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(device_id) if !device_id.trim().is_empty() => Ok(device_id.trim().to_string()),
+        _ => {
+            let device_id = Uuid::new_v4().to_string();
+            std::fs::write(&path, &device_id).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            Ok(device_id)
+        },
+    }
+}
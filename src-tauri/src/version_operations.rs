@@ -0,0 +1,160 @@
+// version_operations.rs
+
+use crate::{ crypto, local_operations, note_events, notifier, preview, local_operations::CONNECTION, models::AppError, models::NoteVersion };
+use rusqlite::{params, Connection, OptionalExtension};
+
+
+/// Saves a snapshot of a note in the "note_versions" table.
+///
+/// The snapshot copies the encrypted content as is, so it is never decrypted. It is meant to be taken in the
+/// same transaction as the change it protects, so both are committed or rolled back together.
+///
+/// # Arguments
+///
+/// * `conn` - The database connection or transaction.
+/// * `note_id` - The ID of the note to snapshot.
+/// * `reason` - The operation the snapshot was taken for, e.g. "replace".
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the snapshot is saved successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or if the note with the specified ID does not exist.
+pub fn snapshot_note(conn: &Connection, note_id: i64, reason: &str) -> Result<(), AppError> {
+    let inserted = conn.execute(
+        "INSERT INTO note_versions (note_id, uuid, title, content, nonce, note_created_at, created_at, reason)
+        SELECT id, uuid, title, content, nonce, created_at, ?2, ?3 FROM notes WHERE id = ?1",
+        params![note_id, chrono::Utc::now().timestamp(), reason],
+    )?;
+
+    if inserted == 0 {
+        return Err(AppError::NotFound("Note not found".to_string()));
+    }
+
+    Ok(())
+}
+
+
+/// Retrieves the snapshots of a note, most recent first.
+///
+/// The IDs of deleted notes can be reused by new notes, so only the snapshots with the UUID of the current note are returned.
+///
+/// # Arguments
+///
+/// * `note_id` - The ID of the note.
+///
+/// # Returns
+///
+/// Returns a vector of the snapshots of the note with their content decrypted, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue with the database connection or if a snapshot cannot be decrypted.
+pub fn list_note_versions(note_id: i64) -> Result<Vec<NoteVersion>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, note_id, uuid, title, content, nonce, created_at, reason FROM note_versions WHERE note_id = ?1
+        AND (NOT EXISTS (SELECT 1 FROM notes WHERE id = ?1) OR uuid IS (SELECT uuid FROM notes WHERE id = ?1))
+        ORDER BY created_at DESC, id DESC"
+    ).map_err(|e| e.to_string())?;
+    let version_iter = stmt.query_map(params![note_id], |row| {
+        Ok((
+            NoteVersion {
+                id: row.get(0)?,
+                note_id: row.get(1)?,
+                uuid: row.get(2)?,
+                title: row.get(3)?,
+                content: String::new(),
+                created_at: row.get(6)?,
+                reason: row.get(7)?,
+            },
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+        ))
+    }).map_err(|e| e.to_string())?;
+
+    let mut versions = Vec::new();
+    for version in version_iter {
+        let (mut version, encrypted_content, nonce_str) = version.map_err(|e| e.to_string())?;
+        version.content = crypto::decrypt_content(&encrypted_content, &nonce_str)?;
+        versions.push(version);
+    }
+
+    Ok(versions)
+}
+
+
+/// Restores a note to one of its snapshots.
+///
+/// # Arguments
+///
+/// * `version_id` - The ID of the snapshot to restore.
+///
+/// # Operation
+///
+/// * If the note still exists, its current state is saved as a new snapshot and it is overwritten with the snapshot.
+/// * If the note was deleted, it is recreated with its original UUID and creation date, and its original ID if it is free.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the note is restored successfully, or `Err(AppError)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the note exists and is locked (see `local_operations::set_note_locked`),
+/// if there is an issue with the database connection, or if the snapshot does not exist.
+pub fn restore_note_version(version_id: i64) -> Result<(), AppError> {
+    let mut conn = CONNECTION.lock().unwrap();
+    let tx = conn.transaction()?;
+
+    let (note_id, uuid, title, content, nonce, note_created_at): (i64, Option<String>, String, String, Option<String>, i64) = tx.query_row(
+        "SELECT note_id, uuid, title, content, nonce, note_created_at FROM note_versions WHERE id = ?1",
+        params![version_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)),
+    ).optional()?.ok_or(AppError::NotFound("Version not found".to_string()))?;
+
+    let nonce_str = nonce.as_deref().ok_or("Version without nonce".to_string())?;
+    let plain_content = crypto::decrypt_content(&content, nonce_str)?;
+    let note_preview = preview::build_preview(&plain_content);
+    let now = chrono::Utc::now().timestamp();
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    // The note is found by UUID since the ID of a deleted note can be reused by another note
+    let existing_id: Option<i64> = tx.query_row(
+        "SELECT id FROM notes WHERE uuid IS ?1 AND (?1 IS NOT NULL OR id = ?2)",
+        params![uuid, note_id],
+        |row| row.get(0),
+    ).optional()?;
+    let id_taken = tx.query_row(
+        "SELECT 1 FROM notes WHERE id = ?1",
+        params![note_id],
+        |_| Ok(()),
+    ).optional()?.is_some();
+
+    let restored_id = if let Some(note_id) = existing_id {
+        local_operations::check_not_locked(&tx, note_id)?;
+        snapshot_note(&tx, note_id, "restore")?;
+        tx.execute(
+            "UPDATE notes SET title = ?1, content = ?2, nonce = ?3, updated_at = ?4, timestamp = ?5,
+            preview = CASE WHEN secure = 1 THEN NULL ELSE ?6 END WHERE id = ?7",
+            params![title, content, nonce, now, timestamp, note_preview, note_id],
+        )?;
+        note_id
+    } else {
+        // Recreate the note with its original ID unless another note uses it now
+        tx.execute(
+            "INSERT INTO notes (id, uuid, title, content, nonce, created_at, updated_at, timestamp, preview) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![(!id_taken).then_some(note_id), uuid, title, content, nonce, note_created_at, now, timestamp, note_preview],
+        )?;
+        tx.last_insert_rowid()
+    };
+    note_events::note_saved(&tx, restored_id, &plain_content)?;
+
+    tx.commit()?;
+
+    // Send a desktop notification
+    notifier::notify("Note restored", &format!("Note with title '{}' was restored.", title));
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+// workspace.rs
+
+use crate::{ collation, settings, local_operations::CONNECTION, models::WorkspaceSnapshot, models::WorkspaceState };
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashSet;
+
+
+/// The settings key under which the current workspace is stored, as JSON.
+const WORKSPACE_SETTING: &str = "workspace";
+
+
+/// Retrieves the current workspace, as last stored by the window.
+///
+/// # Returns
+///
+/// Returns the current `WorkspaceState`, or an empty one if none is stored. Its locale is the locale used to sort notes.
+///
+/// # Errors
+///
+/// This function will return an error if the stored workspace is malformed or if there is an issue with the database connection.
+pub fn get_workspace_state() -> Result<WorkspaceState, String> {
+    let state: WorkspaceState = match settings::get_setting(WORKSPACE_SETTING)? {
+        Some(state) => serde_json::from_str(&state).map_err(|e| format!("Invalid workspace: {}", e))?,
+        None => WorkspaceState::default(),
+    };
+    Ok(WorkspaceState { locale: Some(collation::get_locale()?), ..state })
+}
+
+
+/// Stores the current workspace, so it can be saved as a snapshot. The window calls it whenever the open notes,
+/// the filters, or the sort change.
+///
+/// # Arguments
+///
+/// * `state` - The open notes, the active one, the search query with its filters, and the sort. A locale, if any,
+///   becomes the locale used to sort notes (see `collation::set_locale`).
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the workspace is stored successfully, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if the active note is not one of the open notes, if the locale is invalid,
+/// or if there is an issue with the database connection.
+pub fn set_workspace_state(state: WorkspaceState) -> Result<(), String> {
+    if state.active_note_id.is_some_and(|id| !state.open_note_ids.contains(&id)) {
+        return Err("The active note should be one of the open notes".to_string());
+    }
+    if let Some(locale) = &state.locale {
+        collation::set_locale(locale)?;
+    }
+
+    // The locale is a setting of its own, read again with the workspace
+    let json = serde_json::to_string(&WorkspaceState { locale: None, ..state }).map_err(|e| e.to_string())?;
+    settings::set_setting(WORKSPACE_SETTING, &json)
+}
+
+
+/// Saves the current workspace under a name, to switch back to it later with `restore_workspace_snapshot`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the snapshot, e.g. "Project X". A snapshot with the same name, ignoring case, is replaced.
+///
+/// # Returns
+///
+/// Returns the saved `WorkspaceSnapshot`.
+///
+/// # Errors
+///
+/// This function will return an error if the name is blank or longer than 100 characters, if the stored workspace is
+/// malformed, or if there is an issue with the database connection.
+pub fn save_workspace_snapshot(name: &str) -> Result<WorkspaceSnapshot, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err("The name of a snapshot should be between 1 and 100 characters".to_string());
+    }
+
+    let snapshot = WorkspaceSnapshot {
+        name: name.to_string(),
+        state: get_workspace_state()?,
+        saved_at: chrono::Utc::now().timestamp(),
+    };
+    let json = serde_json::to_string(&snapshot.state).map_err(|e| e.to_string())?;
+    let conn = CONNECTION.lock().unwrap();
+    conn.execute(
+        "INSERT INTO workspace_snapshots (name, state, saved_at) VALUES (?1, ?2, ?3)
+        ON CONFLICT (name) DO UPDATE SET name = ?1, state = ?2, saved_at = ?3",
+        params![snapshot.name, json, snapshot.saved_at],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(snapshot)
+}
+
+
+/// Restores a workspace saved with `save_workspace_snapshot`, making it the current workspace.
+///
+/// # Arguments
+///
+/// * `name` - The name of the snapshot, ignoring case.
+///
+/// # Operation
+///
+/// The notes deleted since the snapshot was saved are left out of the open notes, and the locale of the snapshot
+/// becomes the locale used to sort notes.
+///
+/// # Returns
+///
+/// Returns the restored `WorkspaceState`, for the window to open its notes and apply its filters and sort.
+///
+/// # Errors
+///
+/// This function will return an error if there is no snapshot with this name, if it is malformed, or if there is an
+/// issue with the database connection.
+pub fn restore_workspace_snapshot(name: &str) -> Result<WorkspaceState, String> {
+    let (name, state) = {
+        let conn = CONNECTION.lock().unwrap();
+        conn.query_row(
+            "SELECT name, state FROM workspace_snapshots WHERE name = ?1",
+            params![name.trim()],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        ).optional().map_err(|e| e.to_string())?
+    }.ok_or(format!("No workspace snapshot named '{}'", name.trim()))?;
+    let mut state: WorkspaceState = serde_json::from_str(&state)
+        .map_err(|e| format!("Invalid workspace snapshot '{}': {}", name, e))?;
+
+    let existing = existing_note_ids(&state.open_note_ids)?;
+    state.open_note_ids.retain(|id| existing.contains(id));
+    state.active_note_id = state.active_note_id.filter(|id| existing.contains(id));
+
+    set_workspace_state(state)?;
+    get_workspace_state()
+}
+
+
+/// Lists the saved workspace snapshots.
+///
+/// # Returns
+///
+/// Returns a vector of `WorkspaceSnapshot`, sorted by name.
+///
+/// # Errors
+///
+/// This function will return an error if a snapshot is malformed or if there is an issue with the database connection.
+pub fn list_workspace_snapshots() -> Result<Vec<WorkspaceSnapshot>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT name, state, saved_at FROM workspace_snapshots ORDER BY name COLLATE NOCASE"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    rows.into_iter().map(|(name, state, saved_at)| {
+        let state = serde_json::from_str(&state).map_err(|e| format!("Invalid workspace snapshot '{}': {}", name, e))?;
+        Ok(WorkspaceSnapshot { name, state, saved_at })
+    }).collect()
+}
+
+
+/// Deletes a workspace snapshot.
+///
+/// # Arguments
+///
+/// * `name` - The name of the snapshot, ignoring case.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the snapshot is deleted, or `Err(String)` if an error occurs.
+///
+/// # Errors
+///
+/// This function will return an error if there is no snapshot with this name or if there is an issue with the database connection.
+pub fn delete_workspace_snapshot(name: &str) -> Result<(), String> {
+    let conn = CONNECTION.lock().unwrap();
+    let deleted = conn.execute("DELETE FROM workspace_snapshots WHERE name = ?1", params![name.trim()])
+        .map_err(|e| e.to_string())?;
+    if deleted == 0 {
+        return Err(format!("No workspace snapshot named '{}'", name.trim()));
+    }
+    Ok(())
+}
+
+
+/// Returns which of the given notes still exist.
+fn existing_note_ids(ids: &[i64]) -> Result<HashSet<i64>, String> {
+    let conn = CONNECTION.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT 1 FROM notes WHERE id = ?1 AND deleted_at IS NULL").map_err(|e| e.to_string())?;
+    let mut existing = HashSet::new();
+    for id in ids {
+        if stmt.exists(params![id]).map_err(|e| e.to_string())? {
+            existing.insert(*id);
+        }
+    }
+    Ok(existing)
+}
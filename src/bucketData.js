@@ -1,7 +1,8 @@
 // bucketData.js
 
-const { invoke } = window.__TAURI__.tauri;
 import {
+  invoke,
+  errorMessage,
   quill,
   noteForm,
   noteId,
@@ -30,24 +31,37 @@ export async function createBucket() {
     return;
   }
   try {
-    await invoke("execute_command", {
-      command: "create_bucket",
-      args: {
-        bucket_name: bucketName,
-      },
+    await invoke("create_bucket", {
+      bucket_name: bucketName,
     });
     await updateBucketList();
     alert("Bucket created successfully");
   } catch (error) {
     console.error("Error creating bucket:", error);
-    if (error.toString().includes("Bucket already exists")) {
+    if (error.message && error.message.includes("Bucket already exists")) {
       alert("The bucket already exists. Please choose a different name.");
     } else {
-      alert("An error occurred while trying to create the bucket.");
+      alert(errorMessage(error, "An error occurred while trying to create the bucket."));
     }
   }
 }
 
+/**
+ * Returns the title of a bucket note from the metadata of its object, where it is stored in base64.
+ * The notes uploaded by the previous versions are named after their title instead.
+ *
+ * @param {string} key - The key of the object.
+ * @param {Object} metadata - The metadata of the object.
+ * @returns {string} The title of the note.
+ */
+function noteTitleFromObject(key, metadata) {
+  if (metadata && metadata.title) {
+    const bytes = Uint8Array.from(atob(metadata.title), (c) => c.charCodeAt(0));
+    return new TextDecoder().decode(bytes);
+  }
+  return key.replace(/\.txt$/, "");
+}
+
 /**
  * Checks if the provided bucket name is valid.
  *
@@ -89,12 +103,7 @@ function isValidBucketName(bucketName) {
  * @throws {Error} If an error occurs while updating the bucket list.
  */
 export async function updateBucketList() {
-  const bucketsJson = await invoke("execute_command", {
-    command: "fetch_buckets",
-    args: {},
-  });
-
-  const buckets = JSON.parse(bucketsJson);
+  const buckets = await invoke("fetch_buckets");
 
   // Clear existing options in the bucket list
   while (bucketList.firstChild) {
@@ -131,17 +140,14 @@ export async function deleteBucket() {
     return;
   }
   try {
-    await invoke("execute_command", {
-      command: "delete_bucket",
-      args: {
-        bucket_name: bucketName,
-      },
+    await invoke("delete_bucket", {
+      bucket_name: bucketName,
     });
     await updateBucketList();
     alert("Bucket deleted successfully");
   } catch (error) {
     console.error("Error deleting bucket:", error);
-    alert("An error occurred while trying to delete the bucket.");
+    alert(errorMessage(error, "An error occurred while trying to delete the bucket."));
   }
 }
 
@@ -159,28 +165,23 @@ export async function uploadNoteToBucket() {
     return;
   }
 
-  let notes = JSON.parse(
-    await invoke("execute_command", { command: "get_local_notes", args: "" })
-  );
+  let notes = await invoke("get_local_notes");
   const id = noteForm.dataset.noteId;
   const note = notes.find((note) => note.id === Number(id));
 
   try {
     if (note) {
-      const result = await invoke("execute_command", {
-        command: "upload_note_to_bucket",
-        args: {
-          bucket_name: bucketList.value,
-          note: {
-            id: Number(note.id),
-            uuid: note.uuid,
-            title: note.title,
-            content: note.content,
-            nonce: null,
-            created_at: Number(note.created_at),
-            updated_at: Number(note.updated_at),
-            timestamp: note.timestamp,
-          },
+      const result = await invoke("upload_note_to_bucket", {
+        bucket_name: bucketList.value,
+        note: {
+          id: Number(note.id),
+          uuid: note.uuid,
+          title: note.title,
+          content: note.content,
+          nonce: null,
+          created_at: Number(note.created_at),
+          updated_at: Number(note.updated_at),
+          timestamp: note.timestamp,
         },
       });
       await loadBucketNotes();
@@ -190,7 +191,7 @@ export async function uploadNoteToBucket() {
     }
   } catch (error) {
     console.error("Error uploading note:", error);
-    alert("An error occurred while trying to upload the note.");
+    alert(errorMessage(error, "An error occurred while trying to upload the note."));
   }
 }
 
@@ -214,14 +215,10 @@ export async function showBucketNote(noteUuid) {
     return;
   }
   try {
-    let noteJson = await invoke("execute_command", {
-      command: "fetch_bucket_note",
-      args: {
-        bucket_name: bucketList.value,
-        uuid: noteUuid,
-      },
+    let note = await invoke("fetch_bucket_note", {
+      bucket_name: bucketList.value,
+      uuid: noteUuid,
     });
-    let note = JSON.parse(noteJson);
 
     if (!note) {
       console.error("No note found with UUID:", noteUuid);
@@ -242,7 +239,7 @@ export async function showBucketNote(noteUuid) {
           noteContent = JSON.parse(jsonPart);
         } catch (error) {
           console.error("Error parsing note content:", error);
-          alert("An error occurred while trying to parse the note content.");
+          alert(errorMessage(error, "An error occurred while trying to parse the note content."));
           return;
         }
       } else {
@@ -254,7 +251,7 @@ export async function showBucketNote(noteUuid) {
     quill.setContents(noteContent);
   } catch (error) {
     console.error("Error showing note:", error);
-    alert("An error occurred while trying to show the note.");
+    alert(errorMessage(error, "An error occurred while trying to show the note."));
   }
 }
 
@@ -269,14 +266,9 @@ export async function showBucketNote(noteUuid) {
 export async function loadBucketNotes() {
   try {
     // Fetch the notes from the server
-    let notes = JSON.parse(
-      await invoke("execute_command", {
-        command: "fetch_bucket_notes",
-        args: {
-          bucket_name: bucketList.value,
-        },
-      })
-    );
+    let notes = await invoke("fetch_bucket_notes", {
+        bucket_name: bucketList.value,
+      });
 
     if (!notes) {
       alert("No notes found in the bucket.");
@@ -292,7 +284,7 @@ export async function loadBucketNotes() {
       row.className = index % 2 === 0 ? "even-row" : "odd-row";
       const metadata = note[2];
       row.innerHTML = `
-          <td>${note[0]}</td>
+          <td>${noteTitleFromObject(note[0], metadata)}</td>
           <td>${formatDateTime(note[1])}</td>
           <td>
             <button class="btn btn-primary" onclick="showBucketNote('${
@@ -309,7 +301,7 @@ export async function loadBucketNotes() {
     });
   } catch (error) {
     console.error("Error loading bucket notes:", error);
-    alert("An error occurred while trying to load the bucket notes.");
+    alert(errorMessage(error, "An error occurred while trying to load the bucket notes."));
   }
 }
 
@@ -337,31 +329,24 @@ export async function updateBucketNote(noteUuid) {
   }
 
   try {
-    let noteJson = await invoke("execute_command", {
-      command: "fetch_bucket_note",
-      args: {
-        bucket_name: bucketList.value,
-        uuid: noteUuid,
-      },
+    let note = await invoke("fetch_bucket_note", {
+      bucket_name: bucketList.value,
+      uuid: noteUuid,
     });
-    let note = JSON.parse(noteJson);
     if (!note) {
       console.error("No note found with UUID:", noteUuid);
       alert("Note not found. Please select a note to update.");
       return;
     }
 
-    await invoke("execute_command", {
-      command: "update_bucket_note",
-      args: {
-        bucket_name: bucketList.value,
-        note: {
-          uuid: noteUuid,
-          title: noteTitle.value,
-          content: content,
-          created_at: note.created_at,
-          updated_at: Math.floor(Date.now() / 1000),
-        },
+    await invoke("update_bucket_note", {
+      bucket_name: bucketList.value,
+      note: {
+        uuid: noteUuid,
+        title: noteTitle.value,
+        content: content,
+        created_at: note.created_at,
+        updated_at: Math.floor(Date.now() / 1000),
       },
     });
 
@@ -374,7 +359,7 @@ export async function updateBucketNote(noteUuid) {
     alert("Note updated successfully");
   } catch (error) {
     console.error("Error updating note:", error);
-    alert("An error occurred while trying to update the note.");
+    alert(errorMessage(error, "An error occurred while trying to update the note."));
   }
 }
 
@@ -389,18 +374,15 @@ export async function updateBucketNote(noteUuid) {
  */
 export async function deleteBucketNote(noteUuid) {
   try {
-    await invoke("execute_command", {
-      command: "delete_bucket_note",
-      args: {
-        bucket_name: bucketList.value,
-        uuid: noteUuid,
-      },
+    await invoke("delete_bucket_note", {
+      bucket_name: bucketList.value,
+      uuid: noteUuid,
     });
     await loadBucketNotes();
     alert("Note removed successfully from the bucket");
   } catch (error) {
     console.error("Error deleting note:", error);
-    alert("An error occurred while trying to delete the note.");
+    alert(errorMessage(error, "An error occurred while trying to delete the note."));
   }
 }
 
@@ -419,16 +401,13 @@ export async function deleteBucketNotes() {
   }
 
   try {
-    await invoke("execute_command", {
-      command: "delete_bucket_notes",
-      args: {
-        bucket_name: bucketList.value,
-      },
+    await invoke("delete_bucket_notes", {
+      bucket_name: bucketList.value,
     });
     await loadBucketNotes();
     alert("All notes removed successfully from the bucket");
   } catch (error) {
     console.error("Error deleting notes:", error);
-    alert("An error occurred while trying to delete the notes.");
+    alert(errorMessage(error, "An error occurred while trying to delete the notes."));
   }
 }
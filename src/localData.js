@@ -1,7 +1,8 @@
 // localData.js
 
-const { invoke } = window.__TAURI__.tauri;
 import {
+  invoke,
+  errorMessage,
   quill,
   noteForm,
   noteId,
@@ -24,15 +25,12 @@ const notesTableBody = document.querySelector("#notes-table-body");
 export async function createLocalNote() {
   let content = JSON.stringify(quill.getContents());
   try {
-    await invoke("execute_command", {
-      command: "create_local_note",
-      args: {
-        note: {
-          title: noteTitle.value,
-          content: content,
-          created_at: Math.floor(Date.now() / 1000),
-          updated_at: Math.floor(Date.now() / 1000),
-        },
+    await invoke("create_local_note", {
+      note: {
+        title: noteTitle.value,
+        content: content,
+        created_at: Math.floor(Date.now() / 1000),
+        updated_at: Math.floor(Date.now() / 1000),
       },
     });
     noteTitle.value = "";
@@ -40,7 +38,7 @@ export async function createLocalNote() {
     await loadLocalNotes();
   } catch (error) {
     console.error("Error creating note:", error);
-    alert("An error occurred while trying to create the note.");
+    alert(errorMessage(error, "An error occurred while trying to create the note."));
   }
 }
 
@@ -55,11 +53,7 @@ export async function createLocalNote() {
 export async function showLocalNote(id) {
   try {
     // Retrieve the note from the server
-    let noteJson = await invoke("execute_command", {
-      command: "get_local_note",
-      args: { id: id },
-    });
-    let note = JSON.parse(noteJson);
+    let note = await invoke("get_local_note", { id: id });
 
     if (note) {
       // Update the form fields with the note data
@@ -84,7 +78,7 @@ export async function showLocalNote(id) {
     }
   } catch (error) {
     console.error("Error showing note:", error);
-    alert("An error occurred while trying to show the note.");
+    alert(errorMessage(error, "An error occurred while trying to show the note."));
   }
 }
 
@@ -111,26 +105,19 @@ export async function updateLocalNote(id) {
   }
 
   try {
-    let noteJson = await invoke("execute_command", {
-      command: "get_local_note",
-      args: { id },
-    });
-    let note = JSON.parse(noteJson);
+    let note = await invoke("get_local_note", { id });
     if (!note) {
       console.error("No note found with id:", id);
       alert("Note not found. Please select a note to update.");
       return;
     }
-    await invoke("execute_command", {
-      command: "update_local_note",
-      args: {
-        note: {
-          id: Number(note.id),
-          title: noteTitle.value,
-          content: content,
-          created_at: note.created_at,
-          updated_at: Math.floor(Date.now() / 1000),
-        },
+    await invoke("update_local_note", {
+      note: {
+        id: Number(note.id),
+        title: noteTitle.value,
+        content: content,
+        created_at: note.created_at,
+        updated_at: Math.floor(Date.now() / 1000),
       },
     });
 
@@ -143,8 +130,40 @@ export async function updateLocalNote(id) {
     alert("Note updated successfully");
   } catch (error) {
     console.error("Error updating note:", error);
-    alert("An error occurred while trying to update the note.");
+    alert(errorMessage(error, "An error occurred while trying to update the note."));
+  }
+}
+
+/**
+ * Saves the local note being edited if it has unsaved changes, without any prompt,
+ * e.g. before the application closes.
+ *
+ * @async
+ * @function flushDraft
+ * @returns {Promise<void>} A promise that resolves when the note is saved, or right away if there is nothing to save.
+ * @throws {Error} If an error occurs while saving the note.
+ */
+export async function flushDraft() {
+  let id = noteForm.dataset.noteId;
+  if (!id || !noteTitle.value) {
+    return;
+  }
+
+  let note = await invoke("get_local_note", { id: Number(id) });
+  let content = JSON.stringify(quill.getContents());
+  if (!note || (note.title === noteTitle.value && note.content === content)) {
+    return;
   }
+
+  await invoke("update_local_note", {
+    note: {
+      id: Number(note.id),
+      title: noteTitle.value,
+      content: content,
+      created_at: note.created_at,
+      updated_at: Math.floor(Date.now() / 1000),
+    },
+  });
 }
 
 /**
@@ -158,9 +177,7 @@ export async function updateLocalNote(id) {
 export async function loadLocalNotes() {
   try {
     // Retrieve the notes from the server
-    let notes = JSON.parse(
-      await invoke("execute_command", { command: "get_local_notes", args: "" })
-    );
+    let notes = await invoke("get_local_notes");
 
     // Clear the existing table rows
     notesTableBody.innerHTML = "";
@@ -188,7 +205,7 @@ export async function loadLocalNotes() {
     });
   } catch (error) {
     console.error("Error loading local notes:", error);
-    alert("An error occurred while trying to load the local notes.");
+    alert(errorMessage(error, "An error occurred while trying to load the local notes."));
   }
 }
 
@@ -203,11 +220,8 @@ export async function loadLocalNotes() {
 export async function deleteLocalNote(id) {
   try {
     // Invoke the "delete_local_note" command to delete the note
-    await invoke("execute_command", {
-      command: "delete_local_note",
-      args: {
-        id: Number(id),
-      },
+    await invoke("delete_local_note", {
+      id: Number(id),
     });
 
     // Clear the selected note ID and form fields
@@ -224,7 +238,7 @@ export async function deleteLocalNote(id) {
     alert("Note deleted successfully");
   } catch (error) {
     console.error("Error deleting note:", error);
-    alert("An error occurred while trying to delete the note.");
+    alert(errorMessage(error, "An error occurred while trying to delete the note."));
   }
 }
 
@@ -239,10 +253,7 @@ export async function deleteLocalNote(id) {
 export async function deleteAllLocalNotes() {
   try {
     // Invoke the "delete_all_local_notes" command to delete all notes
-    await invoke("execute_command", {
-      command: "delete_all_local_notes",
-      args: "",
-    });
+    await invoke("delete_all_local_notes");
     // Reload the local notes and update the table
     await loadLocalNotes();
     // Clear the form fields and editor content
@@ -252,7 +263,7 @@ export async function deleteAllLocalNotes() {
     quill.removeFormat(0, quill.getLength());
   } catch (error) {
     console.error("Error deleting all notes:", error);
-    alert("An error occurred while trying to delete all notes.");
+    alert(errorMessage(error, "An error occurred while trying to delete all notes."));
   }
 }
 
@@ -356,8 +367,7 @@ function exportNotesAsPDF(notes) {
  * @returns {void}
  */
 export async function exportNotes(format) {
-  const response = await invoke("execute_command", { command: "get_local_notes", args: "" });
-  const notes = JSON.parse(response);
+  const notes = await invoke("get_local_notes");
 
   switch (format) {
     case 'pdf':
@@ -395,22 +405,19 @@ async function importNotes(notes) {
     note.content = quill.getContents();
 
     try {
-      await invoke("execute_command", {
-        command: "create_local_note",
-        args: {
-          note: {
-            title: note.title,
-            content: JSON.stringify(note.content),
-            created_at: Math.floor(Date.now() / 1000),
-            updated_at: Math.floor(Date.now() / 1000),
-          },
+      await invoke("create_local_note", {
+        note: {
+          title: note.title,
+          content: JSON.stringify(note.content),
+          created_at: Math.floor(Date.now() / 1000),
+          updated_at: Math.floor(Date.now() / 1000),
         },
       });
 
       await loadLocalNotes();
     } catch (error) {
       console.error("Error creating note:", error);
-      alert("An error occurred while trying to create the note.");
+      alert(errorMessage(error, "An error occurred while trying to create the note."));
     }
   }
 }
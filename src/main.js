@@ -4,6 +4,7 @@ import {
   createLocalNote,
   showLocalNote,
   updateLocalNote,
+  flushDraft,
   deleteLocalNote,
   loadLocalNotes,
   deleteAllLocalNotes,
@@ -22,7 +23,63 @@ import {
   deleteBucketNotes,
 } from "./bucketData.js";
 
-export const { invoke } = window.__TAURI__.tauri;
+const tauri = window.__TAURI__.tauri;
+
+/**
+ * What to do about each kind of error of the backend.
+ */
+const ERROR_HINTS = {
+  validation: "Check the values you entered and try again.",
+  not_found: "It may have been deleted. Refresh the list and try again.",
+  database: "The local database could not be read or written. Restart the application and try again.",
+  s3: "Check your AWS credentials, region, and bucket permissions.",
+  encryption: "Unlock the vault, or check the encryption key.",
+  network: "Check your internet connection and try again.",
+  unavailable: "The application is shutting down.",
+};
+
+/**
+ * Invokes a command of the backend and unwraps the envelope it responds with.
+ *
+ * The ID of each call is logged with its outcome, so a failure can be matched with the logs of the backend.
+ *
+ * @async
+ * @function invoke
+ * @param {string} command - The name of the command.
+ * @param {Object} [args] - The arguments of the command.
+ * @returns {Promise<*>} A promise that resolves with the data of the command.
+ * @throws {Error} If the command fails, with the kind of the error, what to do about it, and the ID of the call.
+ */
+export async function invoke(command, args) {
+  const response = await tauri.invoke(command, args);
+  if (!response.ok) {
+    console.error(
+      `[${response.request_id}] ${command} failed after ${response.duration_ms} ms: ${response.error.message}`
+    );
+    const error = new Error(response.error.message);
+    error.kind = response.error.kind;
+    error.hint = ERROR_HINTS[response.error.kind];
+    error.requestId = response.request_id;
+    throw error;
+  }
+  console.debug(`[${response.request_id}] ${command} succeeded in ${response.duration_ms} ms`);
+  return response.data;
+}
+
+/**
+ * Builds the message shown when an action fails, with what failed and what to do about it when the backend tells.
+ *
+ * @function errorMessage
+ * @param {Error} error - The error, as thrown by `invoke`.
+ * @param {string} fallback - The message telling which action failed.
+ * @returns {string} The message to show.
+ */
+export function errorMessage(error, fallback) {
+  if (!error || !error.kind) {
+    return fallback;
+  }
+  return [fallback, error.message, error.hint].filter(Boolean).join("\n\n");
+}
 export const noteForm = document.querySelector("#note-form");
 export const noteId = document.querySelector("#note-id");
 export const noteTitle = document.querySelector("#note-title");
@@ -182,6 +239,36 @@ window.addEventListener("DOMContentLoaded", () => {
       return;
     }
 
+    // Search the local notes and every bucket at once, each result telling where it was found
+    if (searchLocation === "all") {
+      try {
+        const searchResults = await invoke("search_all_notes", { query: searchQuery });
+        if (searchResults.length === 0) {
+          alert("No result found");
+          return;
+        }
+        const notesTableBody = document.querySelector("#notes-table-body");
+        notesTableBody.innerHTML = "";
+        searchResults.forEach((note, index) => {
+          const local = note.source.type === "local";
+          const row = notesTableBody.insertRow();
+          row.className = index % 2 === 0 ? "even-row" : "odd-row";
+          row.innerHTML = `
+            <td>${note.title} <small>(${local ? "Local" : note.source.bucket_name})</small></td>
+            <td>${formatTimestamp(note.created_at)}</td>
+            <td>${formatTimestamp(note.updated_at)}</td>
+            <td>
+              ${local ? `<button class="btn btn-primary" onclick="showLocalNote('${note.id}')">Show</button>` : ""}
+            </td>
+          `;
+        });
+      } catch (error) {
+        console.error("Error searching in notes:", error);
+        alert(errorMessage(error, "An error occurred while trying to search in the notes."));
+      }
+      return;
+    }
+
     // Prepare the arguments for the search command
     let args = {
       query: searchQuery,
@@ -194,14 +281,8 @@ window.addEventListener("DOMContentLoaded", () => {
     }
 
     try {
-      // Invoke the "execute_command" function with the search command and arguments
-      const searchResultsJson = await invoke("execute_command", {
-        command: "search_in_notes",
-        args: args,
-      });
-
-      // Parse the search results from the JSON response
-      const searchResults = JSON.parse(searchResultsJson);
+      // Invoke the "search_in_notes" command with the search arguments
+      const searchResults = await invoke("search_in_notes", args);
 
       // Handle the case when no results are found
       if (searchResults.length === 0) {
@@ -252,7 +333,7 @@ window.addEventListener("DOMContentLoaded", () => {
       }
     } catch (error) {
       console.error("Error searching in notes:", error);
-      alert("An error occurred while trying to search in the notes.");
+      alert(errorMessage(error, "An error occurred while trying to search in the notes."));
     }
   }
 
@@ -269,6 +350,25 @@ window.addEventListener("DOMContentLoaded", () => {
   window.handleFile = handleFile;
 
 
+  // Save the note being edited before the application closes, then let the shutdown go on
+  window.__TAURI__.event.listen("flush-drafts", async () => {
+    try {
+      await flushDraft();
+    } catch (error) {
+      console.error("Error saving the draft:", error);
+    }
+    await invoke("drafts_flushed");
+  });
+
+  // Tell the user when the notes cannot be decrypted, since the vault stays read-only until the key is imported
+  invoke("get_encryption_key_error")
+    .then((keyError) => {
+      if (keyError) {
+        alert(`${keyError}\n\nThe vault is read-only until the master key is imported.`);
+      }
+    })
+    .catch((error) => console.error("Error checking the encryption key:", error));
+
   loadLocalNotes();
   updateBucketList();
 });